@@ -0,0 +1,70 @@
+//! Times the tree-walking interpreter against the stack and register VMs on
+//! a few scripts, and the stack VM again with loops optimized. Not a
+//! statistical harness: each script runs a fixed number of times per engine
+//! and the best run is reported.
+
+use std::time::{Duration, Instant};
+
+use tbasic_rsc::compiler::{compile_with, Backend, CompileOptions};
+use tbasic_rsc::interpreter::{Interpreter, SharedBuffer};
+use tbasic_rsc::parser::parse;
+
+const RUNS: usize = 5;
+
+const SCRIPTS: [(&str, &str); 4] = [
+    (
+        "arithmetic",
+        "i = 0\ntotal = 0\nwhile i < 200000 {\n  total = total + i * 3 - (i / 7) * 2 + 1\n  i = i + 1\n}\ntotal",
+    ),
+    (
+        "invariants",
+        "n = 500\nscale = 3\ni = 0\ntotal = 0\nwhile i < 200000 {\n  total = total + (n * scale - n / 7) * 2 + i * 5\n  i = i + 1\n}\ntotal",
+    ),
+    ("calls", "fn fib(n) {\n  if n < 2 { return n }\n  return fib(n - 1) + fib(n - 2)\n}\nfib(22)"),
+    (
+        "arrays",
+        "a = []\ni = 0\nwhile i < 20000 {\n  push(a, i * i)\n  i = i + 1\n}\ns = 0\ni = 0\nwhile i < len(a) {\n  s = s + a[i] / 3\n  i = i + 1\n}\ns",
+    ),
+];
+
+fn best_of(mut run: impl FnMut()) -> Duration {
+    (0..RUNS)
+        .map(|_| {
+            let start = Instant::now();
+            run();
+            start.elapsed()
+        })
+        .min()
+        .unwrap()
+}
+
+fn main() {
+    println!("{:<12} {:>12} {:>12} {:>12} {:>12}", "script", "tree-walker", "stack vm", "register vm", "+ loops");
+    for (name, source) in SCRIPTS {
+        let program = parse(source).unwrap();
+        let stack = compile_with(&program, &CompileOptions { backend: Backend::Stack, optimize: false, tail_calls: false, inline_size: 0, optimize_loops: false });
+        let registers = compile_with(&program, &CompileOptions { backend: Backend::Register, optimize: false, tail_calls: false, inline_size: 0, optimize_loops: false });
+        let loops = compile_with(&program, &CompileOptions { backend: Backend::Stack, optimize: false, tail_calls: false, inline_size: 0, optimize_loops: true });
+        let mut interpreter = Interpreter::with_output(Box::new(SharedBuffer::new()));
+        interpreter.set_max_call_depth(1000);
+        let expected = interpreter.run(&program).unwrap();
+        assert_eq!(stack.run(&mut interpreter).unwrap(), expected);
+        assert_eq!(registers.run(&mut interpreter).unwrap(), expected);
+        assert_eq!(loops.run(&mut interpreter).unwrap(), expected);
+
+        let walker = best_of(|| {
+            interpreter.run(&program).unwrap();
+        });
+        let stack = best_of(|| {
+            stack.run(&mut interpreter).unwrap();
+        });
+        let registers = best_of(|| {
+            registers.run(&mut interpreter).unwrap();
+        });
+        let loops = best_of(|| {
+            loops.run(&mut interpreter).unwrap();
+        });
+        let ms = |duration: Duration| format!("{:.2} ms", duration.as_secs_f64() * 1000.0);
+        println!("{:<12} {:>12} {:>12} {:>12} {:>12}", name, ms(walker), ms(stack), ms(registers), ms(loops));
+    }
+}
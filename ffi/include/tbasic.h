@@ -0,0 +1,64 @@
+/* The C API of tbasic, in the libraries `cargo build --release -p tbasic-ffi`
+ * builds. See src/ffi.rs for the details of each function. */
+
+#ifndef TBASIC_H
+#define TBASIC_H
+
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef struct tb_interp tb_interp;
+
+typedef enum tb_type {
+    TB_NIL,
+    TB_BOOL,
+    TB_INT,
+    TB_FLOAT,
+    TB_STRING,
+    /* Arrays, maps, functions and ints too big for int64_t; `string` holds
+     * the value as `print` shows it. */
+    TB_OTHER,
+} tb_type;
+
+/* Only the field of `kind` is meaningful. */
+typedef struct tb_value {
+    tb_type kind;
+    bool boolean;
+    int64_t integer;
+    double number;
+    const char *string;
+} tb_value;
+
+/* Returns false to fail the call, with the message in `result->string` if
+ * that isn't NULL. */
+typedef bool (*tb_fn)(const tb_value *args, size_t count, tb_value *result, void *data);
+
+/* An interpreter printing to stdout. */
+tb_interp *tb_new(void);
+
+void tb_free(tb_interp *tb);
+
+/* Runs `source`, returning 0 and its value in `result` (which may be NULL),
+ * or -1 with the message in tb_error. Strings in `result` live until the
+ * next call with `tb`. */
+int tb_eval(tb_interp *tb, const char *source, tb_value *result);
+
+/* The message of the last tb_eval if it failed, NULL otherwise. */
+const char *tb_error(const tb_interp *tb);
+
+/* Stores the global `name` in `result`, returning whether it exists. */
+bool tb_get_var(tb_interp *tb, const char *name, tb_value *result);
+
+/* Exposes `func` to scripts as the global function `name`. */
+bool tb_register_fn(tb_interp *tb, const char *name, tb_fn func, void *data);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
@@ -0,0 +1,3 @@
+// The functions are defined in `tbasic_rsc::ffi`; linking them in is
+// enough for the library to export them.
+pub use tbasic_rsc::ffi::*;
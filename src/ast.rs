@@ -0,0 +1,139 @@
+use alloc::boxed::Box;
+use alloc::rc::Rc;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use crate::collections::HashMap;
+use crate::lexer::Span;
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Program {
+    pub statements: Vec<Stmt>,
+    /// Classic dialect only: maps each line number to the index of the
+    /// statement it labels.
+    pub lines: HashMap<i64, usize>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Stmt {
+    pub kind: StmtKind,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum StmtKind {
+    /// `name = value`, or `name: type = value` with an annotation. Both may
+    /// start with `let`, which `declaration` records: it starts a new
+    /// variable, shadowing any of the same name from enclosing functions, the
+    /// globals if the function declared the name `global`, or an earlier one
+    /// in the same function.
+    Assign { name: String, ty: Option<TypeAnnotation>, value: Expr, declaration: bool },
+    SetIndex { target: Expr, index: Expr, value: Expr },
+    SetField { target: Expr, field: String, value: Expr },
+    If { condition: Expr, then_branch: Vec<Stmt>, else_branch: Option<Vec<Stmt>> },
+    While { condition: Expr, body: Vec<Stmt> },
+    Break,
+    Continue,
+    /// Prints its arguments separated by spaces, then a newline.
+    Print(Vec<Expr>),
+    Function(Rc<FunctionDecl>),
+    /// Defines the struct's name as its constructor.
+    Struct(Rc<StructDecl>),
+    Return(Option<Expr>),
+    /// Makes the listed names refer to globals for the rest of the enclosing
+    /// function, so assigning to them changes the global instead of creating
+    /// a local. Has no effect at the top level.
+    Global(Vec<String>),
+    /// `import name`: runs the module `name` the first time it is imported
+    /// and binds its namespace to `name`.
+    Import(String),
+    /// Classic dialect: continue at a line number.
+    Goto(i64),
+    /// Classic dialect: call the subroutine starting at a line number.
+    Gosub(i64),
+    /// Classic dialect: `return` outside of a function, resuming after the
+    /// most recent `gosub`.
+    SubReturn,
+    Expr(Expr),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FunctionDecl {
+    pub name: String,
+    pub params: Vec<String>,
+    /// One entry per parameter.
+    pub param_types: Vec<Option<TypeAnnotation>>,
+    /// Written after the parameter list: `fn f(x: int): int { ... }`.
+    pub return_type: Option<TypeAnnotation>,
+    pub body: Vec<Stmt>,
+    pub span: Span,
+    /// The `##` comment before the declaration, its lines joined with
+    /// newlines.
+    pub doc: Option<String>,
+}
+
+/// A type written after a `:`. The interpreter ignores annotations; only
+/// [`typecheck`](crate::typecheck) reads them.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TypeAnnotation {
+    pub name: String,
+    pub span: Span,
+}
+
+/// `struct Name { field, ... }`. Instances are built by calling `Name` with
+/// one argument per field, in declaration order.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StructDecl {
+    pub name: String,
+    pub fields: Vec<String>,
+    pub span: Span,
+    /// As for [`FunctionDecl::doc`].
+    pub doc: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Expr {
+    pub kind: ExprKind,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ExprKind {
+    Number(i64),
+    Float(f64),
+    Str(String),
+    Variable(String),
+    Array(Vec<Expr>),
+    /// `{key: value, ...}`; keys are expressions evaluating to strings.
+    Map(Vec<(Expr, Expr)>),
+    Index { target: Box<Expr>, index: Box<Expr> },
+    Field { target: Box<Expr>, field: String },
+    Unary { op: UnaryOp, operand: Box<Expr> },
+    Binary { op: BinaryOp, left: Box<Expr>, right: Box<Expr> },
+    Call { callee: Box<Expr>, args: Vec<Expr> },
+    Function(Rc<FunctionDecl>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnaryOp {
+    Neg,
+    Not,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinaryOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Equals,
+    NotEquals,
+    SmallerThan,
+    GreaterThan,
+    SmallerEquals,
+    GreaterEquals,
+    /// Only evaluates its right operand if the left one is truthy.
+    And,
+    /// Only evaluates its right operand if the left one is falsy.
+    Or,
+}
@@ -0,0 +1,283 @@
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::ast::{ExprKind, Program, Stmt, StmtKind};
+use crate::check::{self, scope_definitions, shallow_statement_exprs, visit_statement};
+use crate::diagnostic::{Diagnostic, Diagnostics, Level};
+use crate::lexer::Span;
+use crate::lint::Linter;
+use crate::modules::module_path;
+use crate::parser::{parse_into_with, Dialect, ParseOptions};
+use crate::typecheck::typecheck;
+
+/// A script and every module it imports, directly or through other
+/// modules, found as `import` finds them: `name.tb`, or the older
+/// `name.bas`, in the project's root.
+#[derive(Debug, Clone)]
+pub struct Project {
+    pub root: PathBuf,
+    /// The entry first, then the modules in the order they are first
+    /// imported.
+    pub files: Vec<SourceFile>,
+}
+
+#[derive(Debug, Clone)]
+pub struct SourceFile {
+    pub path: PathBuf,
+    /// The module's name, `None` for the entry.
+    pub module: Option<String>,
+    pub source: String,
+    /// `None` if the file doesn't parse.
+    pub program: Option<Program>,
+    pub diagnostics: Diagnostics,
+}
+
+/// An `import` statement, and whether it is in a function, which only
+/// imports when it is called.
+struct Import<'a> {
+    module: &'a str,
+    span: Span,
+    in_function: bool,
+}
+
+impl Project {
+    /// Reads and parses `entry` with `options`, then the modules it imports
+    /// from `root`. Modules are parsed in the modern dialect, as `import`
+    /// parses them. An import of a module that doesn't exist is an error
+    /// of the file importing it; only failing to read `entry` fails.
+    pub fn load(entry: &Path, root: &Path, options: ParseOptions) -> io::Result<Project> {
+        let source = fs::read_to_string(entry)?;
+        let mut project = Project { root: root.to_path_buf(), files: Vec::new() };
+        project.add(entry.to_path_buf(), None, source, options);
+        let options = ParseOptions { dialect: Dialect::Modern, ..options };
+        let mut i = 0;
+        while i < project.files.len() {
+            let modules: Vec<(String, Span)> = match &project.files[i].program {
+                Some(program) => imports(program).iter().map(|import| (import.module.to_string(), import.span)).collect(),
+                None => Vec::new(),
+            };
+            for (module, span) in modules {
+                if project.file(&module).is_some() {
+                    continue;
+                }
+                let path = module_path(root, &module);
+                match fs::read_to_string(&path) {
+                    Ok(source) => project.add(path, Some(module), source, options),
+                    Err(error) => {
+                        let reason = match error.kind() {
+                            io::ErrorKind::NotFound => "no such module".to_string(),
+                            _ => error.to_string(),
+                        };
+                        let message = format!("cannot import `{}`: {}", module, reason);
+                        project.files[i].diagnostics.push(import_error("E0320", message, span));
+                    },
+                }
+            }
+            i += 1;
+        }
+        Ok(project)
+    }
+
+    fn add(&mut self, path: PathBuf, module: Option<String>, source: String, options: ParseOptions) {
+        let mut diagnostics = Diagnostics::new();
+        let program = parse_into_with(&source, options, &mut diagnostics);
+        self.files.push(SourceFile { path, module, source, program, diagnostics });
+    }
+
+    /// The index of the file of `module`.
+    fn file(&self, module: &str) -> Option<usize> {
+        self.files.iter().position(|file| file.module.as_deref() == Some(module))
+    }
+
+    /// Runs [`check`](check::check), [`typecheck`] and `linter` over every
+    /// file that parsed, then looks across files: for uses of `module.name`
+    /// the module doesn't define, and for modules importing each other as
+    /// they run, which fails. What a module defines counts as used if a file
+    /// importing it uses it.
+    pub fn analyze(&mut self, linter: &Linter) {
+        let mut uses = Vec::new();
+        for (i, file) in self.files.iter().enumerate() {
+            if let Some(program) = &file.program {
+                uses.extend(module_uses(program).into_iter().map(|(module, name, span)| (i, module, name, span)));
+            }
+        }
+        let used: HashSet<(&str, &str)> = uses.iter().map(|(_, module, name, _)| (module.as_str(), name.as_str())).collect();
+        let mut found = Vec::new();
+        for (i, module, name, span) in &uses {
+            let defined = self.file(module).and_then(|m| self.files[m].program.as_ref()).map(|program| {
+                let mut names = HashSet::new();
+                check::assigned_names(&program.statements, &mut names);
+                names.contains(name)
+            });
+            if defined == Some(false) {
+                let message = format!("module `{}` doesn't define `{}`", module, name);
+                found.push((*i, import_error("E0301", message, *span)));
+            }
+        }
+        for file in &mut self.files {
+            let Some(program) = &file.program else {
+                continue;
+            };
+            let exported = match &file.module {
+                Some(module) => exported_spans(program, |name| used.contains(&(module.as_str(), name))),
+                None => Vec::new(),
+            };
+            let warnings = check::check(program).into_iter().filter(|warning| {
+                !(matches!(warning.code, "unused-variable" | "unused-function") && exported.contains(&warning.span))
+            });
+            file.diagnostics.extend(warnings);
+            file.diagnostics.extend(typecheck(program));
+            file.diagnostics.extend(linter.lint(program));
+        }
+        let mut done = HashSet::new();
+        for i in 0..self.files.len() {
+            self.find_cycles(i, &mut Vec::new(), &mut done, &mut found);
+        }
+        for (i, diagnostic) in found {
+            self.files[i].diagnostics.push(diagnostic);
+        }
+        for file in &mut self.files {
+            file.diagnostics.sort();
+        }
+    }
+
+    /// Follows the imports `file` makes as it runs, reporting those that
+    /// import a module still running further up `running`.
+    fn find_cycles(&self, file: usize, running: &mut Vec<usize>, done: &mut HashSet<usize>, found: &mut Vec<(usize, Diagnostic)>) {
+        let Some(program) = &self.files[file].program else {
+            return;
+        };
+        if !done.insert(file) {
+            return;
+        }
+        running.push(file);
+        for import in imports(program).iter().filter(|import| !import.in_function) {
+            let Some(next) = self.file(import.module) else {
+                continue;
+            };
+            match running.iter().position(|&running| running == next) {
+                Some(start) => {
+                    let mut cycle: Vec<&str> = running[start..].iter().filter_map(|&i| self.files[i].module.as_deref()).collect();
+                    cycle.push(import.module);
+                    let message = format!("cyclic import: {}", cycle.join(" -> "));
+                    found.push((file, import_error("E0321", message, import.span)));
+                },
+                None => self.find_cycles(next, running, done, found),
+            }
+        }
+        running.pop();
+    }
+
+    pub fn has_errors(&self) -> bool {
+        self.files.iter().any(|file| file.diagnostics.has_errors())
+    }
+
+    /// The name and source of every module, for
+    /// [`save_with_modules`](crate::bytecode::save_with_modules).
+    pub fn modules(&self) -> Vec<(String, String)> {
+        self.files.iter().filter_map(|file| Some((file.module.clone()?, file.source.clone()))).collect()
+    }
+}
+
+fn import_error(id: &'static str, message: String, span: Span) -> Diagnostic {
+    Diagnostic { id: Some(id), ..Diagnostic::new(Level::Error, "import-error", message, span) }
+}
+
+fn imports(program: &Program) -> Vec<Import<'_>> {
+    let mut found = Vec::new();
+    find_imports(&program.statements, false, &mut found);
+    found
+}
+
+/// Adds the imports in `statements` and the blocks and functions nested in
+/// them to `found`.
+fn find_imports<'a>(statements: &'a [Stmt], in_function: bool, found: &mut Vec<Import<'a>>) {
+    for statement in statements {
+        match &statement.kind {
+            StmtKind::Import(module) => found.push(Import { module, span: statement.span, in_function }),
+            StmtKind::If { then_branch, else_branch, .. } => {
+                find_imports(then_branch, in_function, found);
+                find_imports(else_branch.as_deref().unwrap_or_default(), in_function, found);
+            },
+            StmtKind::While { body, .. } => find_imports(body, in_function, found),
+            StmtKind::Function(decl) => find_imports(&decl.body, true, found),
+            _ => {},
+        }
+        shallow_statement_exprs(statement, &mut |expr| {
+            if let ExprKind::Function(decl) = &expr.kind {
+                find_imports(&decl.body, true, found);
+            }
+        });
+    }
+}
+
+/// Every `module.name` in `program` where `module` is a module it imports,
+/// with the span of the expression.
+fn module_uses(program: &Program) -> Vec<(String, String, Span)> {
+    let modules: HashSet<&str> = imports(program).iter().map(|import| import.module).collect();
+    let mut uses = Vec::new();
+    for statement in &program.statements {
+        visit_statement(statement, &mut |expr| {
+            if let ExprKind::Field { target, field } = &expr.kind {
+                if let ExprKind::Variable(module) = &target.kind {
+                    if modules.contains(module.as_str()) {
+                        uses.push((module.clone(), field.clone(), expr.span));
+                    }
+                }
+            }
+        });
+    }
+    uses
+}
+
+/// Where the module `program` defines the names `used` by files importing
+/// it, as variables or named functions.
+fn exported_spans(program: &Program, used: impl Fn(&str) -> bool) -> Vec<Span> {
+    let (mut declared_global, mut assignments, mut functions) = (HashSet::new(), Vec::new(), Vec::new());
+    scope_definitions(&program.statements, &mut declared_global, &mut assignments, &mut functions);
+    let functions = functions.into_iter().filter(|(_, named)| *named).map(|(decl, _)| (decl.name.clone(), decl.span));
+    assignments.into_iter().chain(functions).filter(|(name, _)| used(name)).map(|(_, span)| span).collect()
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+
+    use crate::build::Project;
+    use crate::lint::Linter;
+    use crate::parser::ParseOptions;
+
+    #[test]
+    fn test_project() {
+        let root = std::env::temp_dir().join(format!("tbasic-build-{}", std::process::id()));
+        fs::create_dir_all(&root).unwrap();
+        fs::write(root.join("main.tb"), "import util\nprint util.twice(2), util.thrice(1)\nimport missing").unwrap();
+        fs::write(root.join("util.tb"), "import helper\nfn twice(x) {\n  return 2 * x\n}\nlimit = 3").unwrap();
+        fs::write(root.join("helper.tb"), "fn _later() {\n  import helper\n}\nimport util").unwrap();
+
+        let mut project = Project::load(&root.join("main.tb"), &root, ParseOptions::default()).unwrap();
+        let modules: Vec<_> = project.files.iter().map(|file| file.module.as_deref()).collect();
+        assert_eq!(modules, vec![None, Some("util"), Some("helper")]);
+        assert_eq!(project.modules()[0], ("util".to_string(), fs::read_to_string(root.join("util.tb")).unwrap()));
+
+        project.analyze(&Linter::new());
+        let messages: Vec<Vec<String>> = project
+            .files
+            .iter()
+            .map(|file| file.diagnostics.iter().map(|diagnostic| format!("{}", diagnostic)).collect())
+            .collect();
+        assert_eq!(messages, vec![
+            vec![
+                "2:22: error[E0301]: module `util` doesn't define `thrice`".to_string(),
+                "3:1: error[E0320]: cannot import `missing`: no such module".to_string(),
+            ],
+            // `twice` is used by main.tb, `limit` by nothing
+            vec!["5:1: warning[W0001]: `limit` is assigned but never read".to_string()],
+            // Only the import as the module runs fails
+            vec!["4:1: error[E0321]: cyclic import: util -> helper -> util".to_string()],
+        ]);
+        assert!(project.has_errors());
+    }
+}
@@ -0,0 +1,989 @@
+use alloc::boxed::Box;
+use alloc::format;
+use alloc::rc::Rc;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::cell::RefCell;
+use core::cmp::Ordering;
+use core::fmt;
+#[cfg(feature = "std")]
+use std::io::{self, Write};
+#[cfg(feature = "std")]
+use std::thread;
+#[cfg(feature = "std")]
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use num_bigint::BigInt;
+use num_traits::{Signed, ToPrimitive};
+
+use crate::collections::{HashMap, IndexMap};
+use crate::interpreter::{map_key, overflowed, Interpreter, RuntimeError};
+use crate::lexer::Span;
+use crate::value::Value;
+
+pub type BuiltinFn = fn(&mut Interpreter, &[Value], Span) -> Result<Value, RuntimeError>;
+
+/// A function implemented in Rust and callable from scripts.
+#[derive(Clone, Copy)]
+pub struct Builtin {
+    pub name: &'static str,
+    pub arity: Arity,
+    pub func: BuiltinFn,
+}
+
+/// How many arguments a builtin accepts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Arity {
+    pub min: usize,
+    /// `None` if it accepts any number of further arguments.
+    pub max: Option<usize>,
+}
+
+impl Arity {
+    pub const fn exactly(count: usize) -> Arity {
+        Arity { min: count, max: Some(count) }
+    }
+
+    pub const fn between(min: usize, max: usize) -> Arity {
+        Arity { min, max: Some(max) }
+    }
+
+    pub const fn at_least(min: usize) -> Arity {
+        Arity { min, max: None }
+    }
+
+    pub fn accepts(&self, count: usize) -> bool {
+        count >= self.min && self.max.is_none_or(|max| count <= max)
+    }
+}
+
+impl fmt::Display for Arity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.max {
+            Some(max) if max == self.min => write!(f, "{}", max),
+            Some(max) => write!(f, "{} to {}", self.min, max),
+            None => write!(f, "at least {}", self.min),
+        }
+    }
+}
+
+impl fmt::Debug for Builtin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Builtin({})", self.name)
+    }
+}
+
+impl PartialEq for Builtin {
+    fn eq(&self, other: &Self) -> bool {
+        self.name == other.name
+    }
+}
+
+const BUILTINS: &[Builtin] = &[
+    Builtin { name: "abs", arity: Arity::exactly(1), func: abs },
+    Builtin { name: "min", arity: Arity::at_least(2), func: min },
+    Builtin { name: "max", arity: Arity::at_least(2), func: max },
+    Builtin { name: "len", arity: Arity::exactly(1), func: len },
+    Builtin { name: "substr", arity: Arity::between(2, 3), func: substr },
+    Builtin { name: "upper", arity: Arity::exactly(1), func: upper },
+    Builtin { name: "lower", arity: Arity::exactly(1), func: lower },
+    Builtin { name: "trim", arity: Arity::exactly(1), func: trim },
+    Builtin { name: "split", arity: Arity::exactly(2), func: split },
+    Builtin { name: "contains", arity: Arity::exactly(2), func: contains },
+    Builtin { name: "replace", arity: Arity::exactly(3), func: replace },
+    Builtin { name: "format", arity: Arity::at_least(1), func: format },
+    Builtin { name: "push", arity: Arity::exactly(2), func: push },
+    Builtin { name: "pop", arity: Arity::exactly(1), func: pop },
+    Builtin { name: "insert", arity: Arity::exactly(3), func: insert },
+    Builtin { name: "remove", arity: Arity::exactly(2), func: remove },
+    Builtin { name: "sort", arity: Arity::exactly(1), func: sort },
+    Builtin { name: "reverse", arity: Arity::exactly(1), func: reverse },
+    Builtin { name: "keys", arity: Arity::exactly(1), func: keys },
+    Builtin { name: "values", arity: Arity::exactly(1), func: values },
+    Builtin { name: "has", arity: Arity::exactly(2), func: has },
+    Builtin { name: "rnd", arity: Arity::exactly(0), func: rnd },
+    Builtin { name: "random", arity: Arity::exactly(2), func: random },
+    Builtin { name: "seed", arity: Arity::exactly(1), func: seed },
+    Builtin { name: "args", arity: Arity::exactly(0), func: args },
+    Builtin { name: "exit", arity: Arity::between(0, 1), func: exit },
+    Builtin { name: "assert", arity: Arity::between(1, 2), func: assert },
+];
+
+/// Builtins that need `std`: for stdin, float math, the clock and the
+/// environment.
+#[cfg(feature = "std")]
+const STD_BUILTINS: &[Builtin] = &[
+    Builtin { name: "input", arity: Arity::between(0, 1), func: input },
+    Builtin { name: "sqrt", arity: Arity::exactly(1), func: sqrt },
+    Builtin { name: "pow", arity: Arity::exactly(2), func: pow },
+    Builtin { name: "floor", arity: Arity::exactly(1), func: floor },
+    Builtin { name: "ceil", arity: Arity::exactly(1), func: ceil },
+    Builtin { name: "round", arity: Arity::exactly(1), func: round },
+    Builtin { name: "now", arity: Arity::exactly(0), func: now },
+    Builtin { name: "clock_ms", arity: Arity::exactly(0), func: clock_ms },
+    Builtin { name: "sleep", arity: Arity::exactly(1), func: sleep },
+    Builtin { name: "getenv", arity: Arity::exactly(1), func: getenv },
+];
+
+/// Builtins touching the file system, compiled in with the `io` feature and
+/// allowed by [`Sandbox::allow_file_io`](crate::sandbox::Sandbox::allow_file_io).
+#[cfg(feature = "io")]
+const FILE_BUILTINS: &[Builtin] = &[
+    Builtin { name: "read_file", arity: Arity::exactly(1), func: read_file },
+    Builtin { name: "write_file", arity: Arity::exactly(2), func: write_file },
+    Builtin { name: "append_file", arity: Arity::exactly(2), func: append_file },
+    Builtin { name: "file_exists", arity: Arity::exactly(1), func: file_exists },
+];
+
+/// A Rust closure registered by the host with
+/// [`Interpreter::register_fn`].
+pub struct HostFunction {
+    pub name: String,
+    func: RefCell<Box<HostFn>>,
+}
+
+pub type HostFn = dyn FnMut(&[Value]) -> Result<Value, RuntimeError>;
+
+impl HostFunction {
+    pub fn new<F>(name: &str, func: F) -> HostFunction
+    where
+        F: FnMut(&[Value]) -> Result<Value, RuntimeError> + 'static,
+    {
+        HostFunction { name: name.to_string(), func: RefCell::new(Box::new(func)) }
+    }
+
+    pub fn call(&self, args: &[Value]) -> Result<Value, RuntimeError> {
+        // Host functions can't call back into the interpreter, so the borrow
+        // is never held twice
+        (self.func.borrow_mut())(args)
+    }
+}
+
+impl fmt::Debug for HostFunction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "HostFunction({})", self.name)
+    }
+}
+
+impl PartialEq for HostFunction {
+    fn eq(&self, other: &Self) -> bool {
+        core::ptr::eq(self, other)
+    }
+}
+
+/// The builtin called `name`, if there is one.
+pub fn lookup(name: &str) -> Option<Builtin> {
+    let found = BUILTINS.iter().find(|builtin| builtin.name == name);
+    #[cfg(feature = "std")]
+    let found = found.or_else(|| STD_BUILTINS.iter().find(|builtin| builtin.name == name));
+    #[cfg(feature = "io")]
+    let found = found.or_else(|| FILE_BUILTINS.iter().find(|builtin| builtin.name == name));
+    found.copied()
+}
+
+/// Defines every builtin as a global variable.
+pub fn register(globals: &mut HashMap<String, Value>) {
+    for builtin in BUILTINS {
+        globals.insert(builtin.name.to_string(), Value::Builtin(*builtin));
+    }
+    #[cfg(feature = "std")]
+    for builtin in STD_BUILTINS {
+        globals.insert(builtin.name.to_string(), Value::Builtin(*builtin));
+    }
+    #[cfg(feature = "io")]
+    for builtin in FILE_BUILTINS {
+        globals.insert(builtin.name.to_string(), Value::Builtin(*builtin));
+    }
+}
+
+fn expect_args(name: &str, args: &[Value], expected: usize, span: Span) -> Result<(), RuntimeError> {
+    if args.len() == expected {
+        Ok(())
+    } else {
+        Err(RuntimeError::WrongArgumentCount { name: name.to_string(), expected, found: args.len(), span })
+    }
+}
+
+fn number(name: &str, value: &Value, span: Span) -> Result<f64, RuntimeError> {
+    match value {
+        Value::Int(n) => Ok(*n as f64),
+        Value::BigInt(n) => Ok(n.to_f64().unwrap_or(f64::NAN)),
+        Value::Float(n) => Ok(*n),
+        other => Err(RuntimeError::TypeMismatch {
+            message: format!("`{}` expects a number, found {}", name, other.type_name()),
+            span,
+        }),
+    }
+}
+
+fn string<'a>(name: &str, value: &'a Value, span: Span) -> Result<&'a str, RuntimeError> {
+    match value {
+        Value::Str(s) => Ok(s),
+        other => Err(RuntimeError::TypeMismatch {
+            message: format!("`{}` expects a string, found {}", name, other.type_name()),
+            span,
+        }),
+    }
+}
+
+fn array(name: &str, value: &Value, span: Span) -> Result<Rc<RefCell<Vec<Value>>>, RuntimeError> {
+    match value {
+        Value::Array(values) => Ok(values.clone()),
+        other => Err(RuntimeError::TypeMismatch {
+            message: format!("`{}` expects an array, found {}", name, other.type_name()),
+            span,
+        }),
+    }
+}
+
+fn map(name: &str, value: &Value, span: Span) -> Result<Rc<RefCell<IndexMap<String, Value>>>, RuntimeError> {
+    match value {
+        Value::Map(entries) => Ok(entries.clone()),
+        other => Err(RuntimeError::TypeMismatch {
+            message: format!("`{}` expects a map, found {}", name, other.type_name()),
+            span,
+        }),
+    }
+}
+
+fn int(name: &str, value: &Value, span: Span) -> Result<i64, RuntimeError> {
+    match value {
+        Value::Int(n) => Ok(*n),
+        other => Err(RuntimeError::TypeMismatch {
+            message: format!("`{}` expects an int, found {}", name, other.type_name()),
+            span,
+        }),
+    }
+}
+
+/// Checks `value`, which a builtin just built, against the sandbox's limits
+/// before handing it to the script.
+fn sized(interpreter: &Interpreter, value: Value, span: Span) -> Result<Value, RuntimeError> {
+    interpreter.check_size(&value, span)?;
+    Ok(value)
+}
+
+#[cfg(feature = "std")]
+fn float_to_int(n: f64, span: Span) -> Result<Value, RuntimeError> {
+    // `as` saturates, so reject anything that wouldn't round-trip
+    if !n.is_finite() {
+        Err(RuntimeError::InvalidArgument { message: format!("cannot convert {} to integer", n), span })
+    } else if n >= i64::MIN as f64 && n < i64::MAX as f64 {
+        Ok(Value::Int(n as i64))
+    } else {
+        Err(RuntimeError::IntegerOverflow { span })
+    }
+}
+
+#[cfg(feature = "std")]
+/// `input([prompt])` reads a line and returns it as an int if it looks like
+/// one and as a string otherwise. Returns nil at end of input.
+fn input(interpreter: &mut Interpreter, args: &[Value], span: Span) -> Result<Value, RuntimeError> {
+    if !interpreter.sandbox.allow_input {
+        return Err(RuntimeError::NotPermitted { what: "`input`".to_string(), span });
+    }
+    let io_error = |e: io::Error| RuntimeError::Io { message: e.to_string(), span };
+    match args {
+        [] => {},
+        [prompt] => {
+            write!(interpreter.output, "{}", prompt).map_err(io_error)?;
+            interpreter.output.flush().map_err(io_error)?;
+        },
+        _ => expect_args("input", args, 1, span)?,
+    }
+    Ok(match interpreter.input.read_line().map_err(io_error)? {
+        Some(line) => match line.trim().parse::<i64>() {
+            Ok(n) => Value::Int(n),
+            Err(_) => Value::from(line),
+        },
+        None => Value::Nil,
+    })
+}
+
+fn abs(interpreter: &mut Interpreter, args: &[Value], span: Span) -> Result<Value, RuntimeError> {
+    expect_args("abs", args, 1, span)?;
+    match &args[0] {
+        Value::Int(n) => match n.checked_abs() {
+            Some(n) => Ok(Value::Int(n)),
+            None => overflowed(interpreter.arithmetic.overflow, n.wrapping_abs(), i64::MAX, || BigInt::from(*n).abs(), span),
+        },
+        Value::BigInt(n) => Ok(Value::from_big(n.abs())),
+        other => Ok(Value::Float(number("abs", other, span)?.abs())),
+    }
+}
+
+fn min(_: &mut Interpreter, args: &[Value], span: Span) -> Result<Value, RuntimeError> {
+    extremum("min", args, span, |candidate, best| candidate < best)
+}
+
+fn max(_: &mut Interpreter, args: &[Value], span: Span) -> Result<Value, RuntimeError> {
+    extremum("max", args, span, |candidate, best| candidate > best)
+}
+
+/// Shared implementation of `min` and `max`, which accept two or more numbers
+/// and return the winning argument unchanged.
+fn extremum(name: &str, args: &[Value], span: Span, better: fn(f64, f64) -> bool) -> Result<Value, RuntimeError> {
+    if args.len() < 2 {
+        expect_args(name, args, 2, span)?;
+    }
+    let mut best = &args[0];
+    let mut best_number = number(name, best, span)?;
+    for arg in &args[1..] {
+        let n = number(name, arg, span)?;
+        if better(n, best_number) {
+            best = arg;
+            best_number = n;
+        }
+    }
+    Ok(best.clone())
+}
+
+#[cfg(feature = "std")]
+fn sqrt(_: &mut Interpreter, args: &[Value], span: Span) -> Result<Value, RuntimeError> {
+    expect_args("sqrt", args, 1, span)?;
+    let n = number("sqrt", &args[0], span)?;
+    if n < 0.0 {
+        return Err(RuntimeError::InvalidArgument {
+            message: "cannot take the square root of a negative number".to_string(),
+            span,
+        });
+    }
+    Ok(Value::Float(n.sqrt()))
+}
+
+#[cfg(feature = "std")]
+/// `pow(base, exp)` stays an int when both operands are ints and `exp` is
+/// non-negative, and is a float otherwise.
+fn pow(interpreter: &mut Interpreter, args: &[Value], span: Span) -> Result<Value, RuntimeError> {
+    expect_args("pow", args, 2, span)?;
+    match (&args[0], &args[1]) {
+        (Value::Int(base), Value::Int(exp)) if *exp >= 0 => {
+            let exp = u32::try_from(*exp).map_err(|_| RuntimeError::IntegerOverflow { span })?;
+            match base.checked_pow(exp) {
+                Some(n) => Ok(Value::Int(n)),
+                None => {
+                    let saturated = if *base < 0 && exp % 2 == 1 { i64::MIN } else { i64::MAX };
+                    let exact = || BigInt::from(*base).pow(exp);
+                    overflowed(interpreter.arithmetic.overflow, base.wrapping_pow(exp), saturated, exact, span)
+                },
+            }
+        },
+        (Value::BigInt(base), Value::Int(exp)) if *exp >= 0 => {
+            let exp = u32::try_from(*exp).map_err(|_| RuntimeError::IntegerOverflow { span })?;
+            Ok(Value::from_big(base.pow(exp)))
+        },
+        (base, exp) => Ok(Value::Float(number("pow", base, span)?.powf(number("pow", exp, span)?))),
+    }
+}
+
+#[cfg(feature = "std")]
+fn floor(_: &mut Interpreter, args: &[Value], span: Span) -> Result<Value, RuntimeError> {
+    rounding("floor", args, span, f64::floor)
+}
+
+#[cfg(feature = "std")]
+fn ceil(_: &mut Interpreter, args: &[Value], span: Span) -> Result<Value, RuntimeError> {
+    rounding("ceil", args, span, f64::ceil)
+}
+
+#[cfg(feature = "std")]
+fn round(_: &mut Interpreter, args: &[Value], span: Span) -> Result<Value, RuntimeError> {
+    rounding("round", args, span, f64::round)
+}
+
+#[cfg(feature = "std")]
+/// Shared implementation of `floor`, `ceil` and `round`, which always return
+/// an int.
+fn rounding(name: &str, args: &[Value], span: Span, op: fn(f64) -> f64) -> Result<Value, RuntimeError> {
+    expect_args(name, args, 1, span)?;
+    match &args[0] {
+        Value::Int(n) => Ok(Value::Int(*n)),
+        other => float_to_int(op(number(name, other, span)?), span),
+    }
+}
+
+/// `len(x)` is the number of elements of an array or characters (not bytes)
+/// of a string.
+fn len(_: &mut Interpreter, args: &[Value], span: Span) -> Result<Value, RuntimeError> {
+    expect_args("len", args, 1, span)?;
+    match &args[0] {
+        Value::Array(values) => Ok(Value::Int(values.borrow().len() as i64)),
+        Value::Map(entries) => Ok(Value::Int(entries.borrow().len() as i64)),
+        other => Ok(Value::Int(string("len", other, span)?.chars().count() as i64)),
+    }
+}
+
+/// `substr(s, start[, count])` takes up to `count` characters starting at the
+/// zero-based character index `start`, or the rest of the string if `count`
+/// is omitted.
+fn substr(interpreter: &mut Interpreter, args: &[Value], span: Span) -> Result<Value, RuntimeError> {
+    if args.len() != 2 {
+        expect_args("substr", args, 3, span)?;
+    }
+    let s = string("substr", &args[0], span)?;
+    let length = s.chars().count();
+    let start = int("substr", &args[1], span)?;
+    let start = usize::try_from(start).ok().filter(|start| *start <= length).ok_or_else(|| {
+        RuntimeError::InvalidArgument {
+            message: format!("substring start {} is out of range for a string of length {}", start, length),
+            span,
+        }
+    })?;
+    let count = match args.get(2) {
+        Some(count) => {
+            let count = int("substr", count, span)?;
+            usize::try_from(count).map_err(|_| RuntimeError::InvalidArgument {
+                message: format!("substring length {} is negative", count),
+                span,
+            })?
+        },
+        None => length - start,
+    };
+    sized(interpreter, Value::from(s.chars().skip(start).take(count).collect::<String>()), span)
+}
+
+fn upper(interpreter: &mut Interpreter, args: &[Value], span: Span) -> Result<Value, RuntimeError> {
+    expect_args("upper", args, 1, span)?;
+    sized(interpreter, Value::from(string("upper", &args[0], span)?.to_uppercase()), span)
+}
+
+fn lower(interpreter: &mut Interpreter, args: &[Value], span: Span) -> Result<Value, RuntimeError> {
+    expect_args("lower", args, 1, span)?;
+    sized(interpreter, Value::from(string("lower", &args[0], span)?.to_lowercase()), span)
+}
+
+fn trim(interpreter: &mut Interpreter, args: &[Value], span: Span) -> Result<Value, RuntimeError> {
+    expect_args("trim", args, 1, span)?;
+    sized(interpreter, Value::from(string("trim", &args[0], span)?.trim()), span)
+}
+
+/// `split(s, separator)` returns an array of the pieces between separators.
+/// An empty separator splits `s` into its characters.
+fn split(interpreter: &mut Interpreter, args: &[Value], span: Span) -> Result<Value, RuntimeError> {
+    expect_args("split", args, 2, span)?;
+    let s = string("split", &args[0], span)?;
+    let separator = string("split", &args[1], span)?;
+    let pieces = if separator.is_empty() {
+        s.chars().map(|c| sized(interpreter, Value::from(c.to_string()), span)).collect::<Result<_, _>>()?
+    } else {
+        s.split(separator).map(|piece| sized(interpreter, Value::from(piece), span)).collect::<Result<_, _>>()?
+    };
+    sized(interpreter, Value::array(pieces), span)
+}
+
+fn contains(_: &mut Interpreter, args: &[Value], span: Span) -> Result<Value, RuntimeError> {
+    expect_args("contains", args, 2, span)?;
+    let s = string("contains", &args[0], span)?;
+    Ok(Value::Bool(s.contains(string("contains", &args[1], span)?)))
+}
+
+fn replace(interpreter: &mut Interpreter, args: &[Value], span: Span) -> Result<Value, RuntimeError> {
+    expect_args("replace", args, 3, span)?;
+    let s = string("replace", &args[0], span)?;
+    let from = string("replace", &args[1], span)?;
+    let to = string("replace", &args[2], span)?;
+    sized(interpreter, Value::from(s.replace(from, to)), span)
+}
+
+/// `format(template, args...)` replaces each `{}` in the template with the
+/// next argument. `{{` and `}}` stand for literal braces.
+fn format(interpreter: &mut Interpreter, args: &[Value], span: Span) -> Result<Value, RuntimeError> {
+    if args.is_empty() {
+        expect_args("format", args, 1, span)?;
+    }
+    let (template, mut rest) = (&args[0], &args[1..]);
+    let template = string("format", template, span)?;
+    let invalid = |message: &str| RuntimeError::InvalidArgument { message: message.to_string(), span };
+    let mut formatted = String::new();
+    let mut chars = template.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (c, chars.peek()) {
+            ('{', Some('{')) | ('}', Some('}')) => {
+                chars.next();
+                formatted.push(c);
+            },
+            ('{', Some('}')) => {
+                chars.next();
+                let (value, remaining) = rest.split_first().ok_or_else(|| invalid("more `{}` than arguments in format string"))?;
+                formatted.push_str(&value.to_string());
+                rest = remaining;
+            },
+            ('{', _) | ('}', _) => return Err(invalid("unmatched brace in format string, use `{{` or `}}` for a literal one")),
+            _ => formatted.push(c),
+        }
+    }
+    if !rest.is_empty() {
+        return Err(invalid("more arguments than `{}` in format string"));
+    }
+    sized(interpreter, Value::from(formatted), span)
+}
+
+/// `push(array, value)`. Pushing an array into itself makes a cycle, which
+/// is never freed; see [`Value`].
+fn push(interpreter: &mut Interpreter, args: &[Value], span: Span) -> Result<Value, RuntimeError> {
+    expect_args("push", args, 2, span)?;
+    array("push", &args[0], span)?.borrow_mut().push(args[1].clone());
+    interpreter.check_growth(&args[0], size_of::<Value>(), span)?;
+    Ok(Value::Nil)
+}
+
+fn pop(_: &mut Interpreter, args: &[Value], span: Span) -> Result<Value, RuntimeError> {
+    expect_args("pop", args, 1, span)?;
+    array("pop", &args[0], span)?.borrow_mut().pop().ok_or_else(|| RuntimeError::InvalidArgument {
+        message: "cannot pop from an empty array".to_string(),
+        span,
+    })
+}
+
+/// `insert(array, index, value)` accepts any index from 0 up to and including
+/// the array's length. Like `push`, it may make a cycle that is never freed.
+fn insert(interpreter: &mut Interpreter, args: &[Value], span: Span) -> Result<Value, RuntimeError> {
+    expect_args("insert", args, 3, span)?;
+    let values = array("insert", &args[0], span)?;
+    let len = values.borrow().len();
+    let index = if args[1] == Value::Int(len as i64) { len } else { interpreter.array_index(&args[1], len, span)? };
+    values.borrow_mut().insert(index, args[2].clone());
+    interpreter.check_growth(&args[0], size_of::<Value>(), span)?;
+    Ok(Value::Nil)
+}
+
+/// `remove(array, index)` removes and returns the element at `index`;
+/// `remove(map, key)` does the same for the entry under `key`.
+fn remove(interpreter: &mut Interpreter, args: &[Value], span: Span) -> Result<Value, RuntimeError> {
+    expect_args("remove", args, 2, span)?;
+    if let Value::Map(entries) = &args[0] {
+        let key = map_key(args[1].clone(), span)?;
+        let removed = entries.borrow_mut().shift_remove(&key);
+        return removed.ok_or(RuntimeError::MissingKey { key, span });
+    }
+    let values = array("remove", &args[0], span)?;
+    let mut values = values.borrow_mut();
+    let len = values.len();
+    let index = interpreter.array_index(&args[1], len, span)?;
+    Ok(values.remove(index))
+}
+
+/// `sort(array)` sorts in place. The elements must be all numbers or all
+/// strings.
+fn sort(_: &mut Interpreter, args: &[Value], span: Span) -> Result<Value, RuntimeError> {
+    expect_args("sort", args, 1, span)?;
+    let values = array("sort", &args[0], span)?;
+    let mut values = values.borrow_mut();
+    let mut error = None;
+    values.sort_by(|a, b| match (a, b) {
+        (Value::Str(a), Value::Str(b)) => a.cmp(b),
+        (Value::Int(a), Value::Int(b)) => a.cmp(b),
+        (Value::Int(_) | Value::Float(_), Value::Int(_) | Value::Float(_)) => {
+            let (a, b) = (number("sort", a, span).unwrap(), number("sort", b, span).unwrap());
+            a.partial_cmp(&b).unwrap_or(Ordering::Equal)
+        },
+        _ => {
+            error.get_or_insert_with(|| RuntimeError::TypeMismatch {
+                message: format!("cannot compare {} with {}", a.type_name(), b.type_name()),
+                span,
+            });
+            Ordering::Equal
+        },
+    });
+    match error {
+        Some(error) => Err(error),
+        None => Ok(Value::Nil),
+    }
+}
+
+fn reverse(_: &mut Interpreter, args: &[Value], span: Span) -> Result<Value, RuntimeError> {
+    expect_args("reverse", args, 1, span)?;
+    array("reverse", &args[0], span)?.borrow_mut().reverse();
+    Ok(Value::Nil)
+}
+
+/// `rnd()` returns a float in `[0, 1)`.
+/// The keys of a map, in insertion order.
+fn keys(interpreter: &mut Interpreter, args: &[Value], span: Span) -> Result<Value, RuntimeError> {
+    expect_args("keys", args, 1, span)?;
+    let keys = map("keys", &args[0], span)?.borrow().keys().map(|key| sized(interpreter, Value::from(key.as_str()), span)).collect::<Result<_, _>>()?;
+    sized(interpreter, Value::array(keys), span)
+}
+
+/// The values of a map, in the order of their keys.
+fn values(interpreter: &mut Interpreter, args: &[Value], span: Span) -> Result<Value, RuntimeError> {
+    expect_args("values", args, 1, span)?;
+    sized(interpreter, Value::array(map("values", &args[0], span)?.borrow().values().cloned().collect()), span)
+}
+
+fn has(_: &mut Interpreter, args: &[Value], span: Span) -> Result<Value, RuntimeError> {
+    expect_args("has", args, 2, span)?;
+    let entries = map("has", &args[0], span)?;
+    let key = map_key(args[1].clone(), span)?;
+    let found = entries.borrow().contains_key(&key);
+    Ok(Value::Bool(found))
+}
+
+fn rnd(interpreter: &mut Interpreter, args: &[Value], span: Span) -> Result<Value, RuntimeError> {
+    expect_args("rnd", args, 0, span)?;
+    Ok(Value::Float(interpreter.rng.next_float()))
+}
+
+/// `random(min, max)` returns an int between `min` and `max`, inclusive.
+fn random(interpreter: &mut Interpreter, args: &[Value], span: Span) -> Result<Value, RuntimeError> {
+    expect_args("random", args, 2, span)?;
+    let min = int("random", &args[0], span)?;
+    let max = int("random", &args[1], span)?;
+    if min > max {
+        return Err(RuntimeError::InvalidArgument {
+            message: format!("random range is empty: {} is greater than {}", min, max),
+            span,
+        });
+    }
+    Ok(Value::Int(interpreter.rng.range(min, max)))
+}
+
+fn seed(interpreter: &mut Interpreter, args: &[Value], span: Span) -> Result<Value, RuntimeError> {
+    expect_args("seed", args, 1, span)?;
+    interpreter.set_seed(int("seed", &args[0], span)? as u64);
+    Ok(Value::Nil)
+}
+
+#[cfg(feature = "std")]
+/// `now()` returns the seconds since the Unix epoch as a float.
+fn now(_: &mut Interpreter, args: &[Value], span: Span) -> Result<Value, RuntimeError> {
+    expect_args("now", args, 0, span)?;
+    let elapsed = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+    Ok(Value::Float(elapsed.as_secs_f64()))
+}
+
+#[cfg(feature = "std")]
+/// `clock_ms()` returns the milliseconds since the interpreter was created,
+/// from a clock that never goes backwards.
+fn clock_ms(interpreter: &mut Interpreter, args: &[Value], span: Span) -> Result<Value, RuntimeError> {
+    expect_args("clock_ms", args, 0, span)?;
+    Ok(Value::Int(interpreter.started.elapsed().as_millis() as i64))
+}
+
+#[cfg(feature = "std")]
+/// How often `sleep` wakes up to check for cancellation.
+const SLEEP_SLICE: Duration = Duration::from_millis(10);
+
+#[cfg(feature = "std")]
+/// The longest `sleep`, a day.
+const MAX_SLEEP_MS: u64 = 24 * 60 * 60 * 1000;
+
+#[cfg(feature = "std")]
+/// `sleep(ms)` pauses the script, stopping early with
+/// [`RuntimeError::Cancelled`] if the script is cancelled meanwhile.
+fn sleep(interpreter: &mut Interpreter, args: &[Value], span: Span) -> Result<Value, RuntimeError> {
+    expect_args("sleep", args, 1, span)?;
+    if !interpreter.sandbox.allow_sleep {
+        return Err(RuntimeError::NotPermitted { what: "`sleep`".to_string(), span });
+    }
+    let ms = int("sleep", &args[0], span)?;
+    let ms = u64::try_from(ms).map_err(|_| RuntimeError::InvalidArgument {
+        message: format!("cannot sleep for a negative time: {}", ms),
+        span,
+    })?;
+    if ms > MAX_SLEEP_MS {
+        let message = format!("cannot sleep for {} ms, more than the limit of {}", ms, MAX_SLEEP_MS);
+        return Err(RuntimeError::InvalidArgument { message, span });
+    }
+    let deadline = Instant::now() + Duration::from_millis(ms);
+    loop {
+        if interpreter.is_cancelled() {
+            return Err(RuntimeError::Cancelled { span });
+        }
+        let left = deadline.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            return Ok(Value::Nil);
+        }
+        thread::sleep(left.min(SLEEP_SLICE));
+    }
+}
+
+fn check_process(interpreter: &Interpreter, name: &str, span: Span) -> Result<(), RuntimeError> {
+    if interpreter.sandbox.allow_process {
+        Ok(())
+    } else {
+        Err(RuntimeError::NotPermitted { what: format!("`{}`", name), span })
+    }
+}
+
+#[cfg(feature = "std")]
+/// `getenv(name)` returns the environment variable `name`, or nil if it isn't
+/// set.
+fn getenv(interpreter: &mut Interpreter, args: &[Value], span: Span) -> Result<Value, RuntimeError> {
+    expect_args("getenv", args, 1, span)?;
+    check_process(interpreter, "getenv", span)?;
+    let name = string("getenv", &args[0], span)?;
+    Ok(std::env::var(name).map_or(Value::Nil, Value::from))
+}
+
+/// `args()` returns the arguments the host passed with
+/// [`Interpreter::set_args`].
+fn args(interpreter: &mut Interpreter, args: &[Value], span: Span) -> Result<Value, RuntimeError> {
+    expect_args("args", args, 0, span)?;
+    check_process(interpreter, "args", span)?;
+    Ok(Value::array(interpreter.args.iter().map(|arg| Value::from(arg.as_str())).collect()))
+}
+
+/// `exit([code])` stops the script with [`RuntimeError::Exit`]; the code
+/// defaults to 0.
+fn exit(interpreter: &mut Interpreter, args: &[Value], span: Span) -> Result<Value, RuntimeError> {
+    check_process(interpreter, "exit", span)?;
+    let code = match args {
+        [] => 0,
+        [code] => int("exit", code, span)?,
+        _ => {
+            return Err(RuntimeError::WrongArgumentCount { name: "exit".to_string(), expected: 1, found: args.len(), span })
+        },
+    };
+    let code = i32::try_from(code).map_err(|_| RuntimeError::InvalidArgument {
+        message: format!("exit code {} is out of range", code),
+        span,
+    })?;
+    Err(RuntimeError::Exit { code, span })
+}
+
+/// `assert(cond)` or `assert(cond, message)` fails with
+/// [`RuntimeError::AssertionFailed`] unless `cond` is truthy.
+fn assert(_: &mut Interpreter, args: &[Value], span: Span) -> Result<Value, RuntimeError> {
+    match args {
+        [cond, ..] if cond.is_truthy() => Ok(Value::Nil),
+        [_] => Err(RuntimeError::AssertionFailed { message: None, span }),
+        [_, message] => Err(RuntimeError::AssertionFailed { message: Some(message.to_string()), span }),
+        _ => Err(RuntimeError::WrongArgumentCount { name: "assert".to_string(), expected: 2, found: args.len(), span }),
+    }
+}
+
+/// Checks that the sandbox lets `name` touch the file system and returns
+/// the path it was given.
+#[cfg(feature = "io")]
+fn file_path<'a>(interpreter: &Interpreter, name: &str, args: &'a [Value], span: Span) -> Result<&'a str, RuntimeError> {
+    if !interpreter.sandbox.allow_file_io {
+        return Err(RuntimeError::NotPermitted { what: format!("`{}`", name), span });
+    }
+    string(name, &args[0], span)
+}
+
+#[cfg(feature = "io")]
+fn io_error(span: Span) -> impl Fn(io::Error) -> RuntimeError {
+    move |e| RuntimeError::Io { message: e.to_string(), span }
+}
+
+#[cfg(feature = "io")]
+fn read_file(interpreter: &mut Interpreter, args: &[Value], span: Span) -> Result<Value, RuntimeError> {
+    expect_args("read_file", args, 1, span)?;
+    let path = file_path(interpreter, "read_file", args, span)?;
+    let contents = std::fs::read_to_string(path).map_err(io_error(span))?;
+    sized(interpreter, Value::from(contents), span)
+}
+
+/// `write_file(path, text)` replaces the file's contents, creating it if
+/// needed.
+#[cfg(feature = "io")]
+fn write_file(interpreter: &mut Interpreter, args: &[Value], span: Span) -> Result<Value, RuntimeError> {
+    expect_args("write_file", args, 2, span)?;
+    let path = file_path(interpreter, "write_file", args, span)?;
+    std::fs::write(path, string("write_file", &args[1], span)?).map_err(io_error(span))?;
+    Ok(Value::Nil)
+}
+
+#[cfg(feature = "io")]
+fn append_file(interpreter: &mut Interpreter, args: &[Value], span: Span) -> Result<Value, RuntimeError> {
+    expect_args("append_file", args, 2, span)?;
+    let path = file_path(interpreter, "append_file", args, span)?;
+    let text = string("append_file", &args[1], span)?;
+    let mut file = std::fs::OpenOptions::new().append(true).create(true).open(path).map_err(io_error(span))?;
+    file.write_all(text.as_bytes()).map_err(io_error(span))?;
+    Ok(Value::Nil)
+}
+
+#[cfg(feature = "io")]
+fn file_exists(interpreter: &mut Interpreter, args: &[Value], span: Span) -> Result<Value, RuntimeError> {
+    expect_args("file_exists", args, 1, span)?;
+    let path = file_path(interpreter, "file_exists", args, span)?;
+    Ok(Value::Bool(std::path::Path::new(path).exists()))
+}
+
+#[cfg(all(test, feature = "std"))]
+mod test {
+    use crate::interpreter::{CancellationToken, Interpreter, RuntimeError};
+    use crate::parser::parse;
+    use crate::value::Value;
+
+    fn eval(source: &str) -> Result<Value, RuntimeError> {
+        Interpreter::new().run(&parse(source).unwrap())
+    }
+
+    #[test]
+    fn test_math() {
+        assert_eq!(eval("abs(-3)"), Ok(Value::Int(3)));
+        assert_eq!(eval("abs(-2.5)"), Ok(Value::Float(2.5)));
+        assert_eq!(eval("min(4, 2, 9)"), Ok(Value::Int(2)));
+        assert_eq!(eval("max(4, 2.5)"), Ok(Value::Int(4)));
+        assert_eq!(eval("sqrt(16)"), Ok(Value::Float(4.0)));
+        assert_eq!(eval("pow(2, 10)"), Ok(Value::Int(1024)));
+        assert_eq!(eval("pow(2, -1)"), Ok(Value::Float(0.5)));
+        assert_eq!(eval("floor(2.7)"), Ok(Value::Int(2)));
+        assert_eq!(eval("ceil(2.1)"), Ok(Value::Int(3)));
+        assert_eq!(eval("round(2.5)"), Ok(Value::Int(3)));
+    }
+
+    #[test]
+    fn test_math_errors() {
+        assert!(matches!(eval("sqrt(-1)"), Err(RuntimeError::InvalidArgument { .. })));
+        assert!(matches!(eval("abs(\"x\")"), Err(RuntimeError::TypeMismatch { .. })));
+        assert!(matches!(eval("min(1)"), Err(RuntimeError::WrongArgumentCount { expected: 2, found: 1, .. })));
+        assert!(matches!(eval("pow(10, 30)"), Err(RuntimeError::IntegerOverflow { .. })));
+        assert!(matches!(eval("floor(pow(10.0, 300))"), Err(RuntimeError::IntegerOverflow { .. })));
+        let error = eval("ceil(-pow(10.0, 400))").unwrap_err();
+        assert_eq!(error.to_string(), "1:1: invalid argument: cannot convert -inf to integer");
+        let error = eval("inf = pow(10.0, 400)\nround(inf - inf)").unwrap_err();
+        assert_eq!(error.to_string(), "2:1: invalid argument: cannot convert NaN to integer");
+    }
+
+    #[test]
+    fn test_strings() {
+        let s = |v: &str| Value::Str(v.into());
+        assert_eq!(eval("len(\"héllo\")"), Ok(Value::Int(5)));
+        assert_eq!(eval("substr(\"hello\", 1, 3)"), Ok(s("ell")));
+        assert_eq!(eval("substr(\"hello\", 2)"), Ok(s("llo")));
+        assert_eq!(eval("substr(\"hello\", 3, 10)"), Ok(s("lo")));
+        assert_eq!(eval("upper(\"abc\") + lower(\"DEF\")"), Ok(s("ABCdef")));
+        assert_eq!(eval("trim(\"  x \")"), Ok(s("x")));
+        assert_eq!(eval("split(\"a,b,,c\", \",\")"), Ok(Value::array(vec![s("a"), s("b"), s(""), s("c")])));
+        assert_eq!(eval("split(\"ab\", \"\")"), Ok(Value::array(vec![s("a"), s("b")])));
+        assert_eq!(eval("contains(\"hello\", \"ell\")"), Ok(Value::Bool(true)));
+        assert_eq!(eval("replace(\"a-b-c\", \"-\", \"+\")"), Ok(s("a+b+c")));
+        assert_eq!(eval("format(\"x={} y={}\", 1, [\"a\"])"), Ok(s("x=1 y=[\"a\"]")));
+        assert_eq!(eval("format(\"{{{}}}\", \"b\")"), Ok(s("{b}")));
+    }
+
+    #[test]
+    fn test_string_errors() {
+        assert!(matches!(eval("substr(\"abc\", 4)"), Err(RuntimeError::InvalidArgument { .. })));
+        assert!(matches!(eval("substr(\"abc\", 0, -1)"), Err(RuntimeError::InvalidArgument { .. })));
+        assert!(matches!(eval("upper(1)"), Err(RuntimeError::TypeMismatch { .. })));
+        assert!(matches!(eval("substr(\"abc\")"), Err(RuntimeError::WrongArgumentCount { .. })));
+        assert!(matches!(eval("format(\"{}\")"), Err(RuntimeError::InvalidArgument { .. })));
+        assert!(matches!(eval("format(\"{}\", 1, 2)"), Err(RuntimeError::InvalidArgument { .. })));
+        assert!(matches!(eval("format(\"{\", 1)"), Err(RuntimeError::InvalidArgument { .. })));
+        assert!(matches!(eval("format()"), Err(RuntimeError::WrongArgumentCount { .. })));
+    }
+
+    #[test]
+    fn test_arrays() {
+        let ints = |v: &[i64]| Value::array(v.iter().map(|n| Value::Int(*n)).collect());
+        assert_eq!(eval("a = [1, 2]\npush(a, 3)\na"), Ok(ints(&[1, 2, 3])));
+        assert_eq!(eval("a = [1, 2]\npop(a) + len(a)"), Ok(Value::Int(3)));
+        assert_eq!(eval("a = [1, 3]\ninsert(a, 1, 2)\ninsert(a, 3, 4)\na"), Ok(ints(&[1, 2, 3, 4])));
+        assert_eq!(eval("a = [1, 2, 3]\nremove(a, 0) * 10 + len(a)"), Ok(Value::Int(12)));
+        assert_eq!(eval("a = [3, 1, 2]\nsort(a)\na"), Ok(ints(&[1, 2, 3])));
+        assert_eq!(eval("a = [1, 2, 3]\nreverse(a)\na"), Ok(ints(&[3, 2, 1])));
+    }
+
+    #[test]
+    fn test_maps() {
+        let source = "m = {\"b\": 1, \"a\": 2}\nm[\"c\"] = 3\nr = remove(m, \"b\")\n[keys(m), values(m), has(m, \"a\"), has(m, \"b\"), len(m), r]";
+        assert_eq!(eval(source).unwrap().to_string(), "[[\"a\", \"c\"], [2, 3], true, false, 2, 1]");
+        assert!(matches!(eval("remove({}, \"x\")"), Err(RuntimeError::MissingKey { .. })));
+        assert!(matches!(eval("keys([1])"), Err(RuntimeError::TypeMismatch { .. })));
+        assert!(matches!(eval("has({}, 1)"), Err(RuntimeError::TypeMismatch { .. })));
+    }
+
+    #[test]
+    fn test_array_errors() {
+        assert!(matches!(eval("pop([])"), Err(RuntimeError::InvalidArgument { .. })));
+        assert!(matches!(eval("insert([1], 2, 0)"), Err(RuntimeError::IndexOutOfBounds { index: 2, len: 1, .. })));
+        assert!(matches!(eval("remove([1], -1)"), Err(RuntimeError::IndexOutOfBounds { index: -1, len: 1, .. })));
+        assert!(matches!(eval("sort([1, \"a\"])"), Err(RuntimeError::TypeMismatch { .. })));
+        assert!(matches!(eval("push(1, 2)"), Err(RuntimeError::TypeMismatch { .. })));
+    }
+
+    #[test]
+    fn test_random_is_reproducible() {
+        let program = parse("[random(1, 100), random(1, 100), rnd()]").unwrap();
+        let mut first = Interpreter::new();
+        first.set_seed(99);
+        let mut second = Interpreter::new();
+        second.set_seed(99);
+        assert_eq!(first.run(&program), second.run(&program));
+
+        assert_eq!(eval("seed(5)\na = random(0, 1000)\nseed(5)\na == random(0, 1000)"), Ok(Value::Bool(true)));
+        assert!(matches!(eval("random(2, 1)"), Err(RuntimeError::InvalidArgument { .. })));
+    }
+
+    #[test]
+    fn test_builtins_are_values() {
+        assert_eq!(eval("f = sqrt\nf(9)"), Ok(Value::Float(3.0)));
+        assert!(matches!(eval("abs = 1\nabs(1)"), Err(RuntimeError::NotCallable { .. })));
+    }
+
+    #[cfg(feature = "io")]
+    #[test]
+    fn test_files() {
+        use crate::sandbox::Sandbox;
+
+        let dir = std::env::temp_dir().join(format!("tbasic-test-files-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("notes.txt");
+        let source = format!(
+            "path = {:?}\nwrite_file(path, \"a\")\nappend_file(path, \"b\")\n[file_exists(path), read_file(path), file_exists(path + \".x\")]",
+            path.to_str().unwrap()
+        );
+        let result = eval(&source).unwrap();
+        assert_eq!(result.to_string(), "[true, \"ab\", false]");
+        assert!(matches!(eval(&format!("read_file({:?})", dir.join("nope").to_str().unwrap())), Err(RuntimeError::Io { .. })));
+
+        let mut interpreter = Interpreter::new();
+        interpreter.set_sandbox(Sandbox { allow_file_io: false, ..Sandbox::default() });
+        let result = interpreter.run(&parse("read_file(\"x\")").unwrap());
+        assert_eq!(result.unwrap_err().to_string(), "1:1: `read_file` is not permitted in this sandbox");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_time() {
+        assert!(matches!(eval("now() > 1600000000.0"), Ok(Value::Bool(true))));
+        assert_eq!(eval("start = clock_ms()\nsleep(15)\nclock_ms() - start >= 15"), Ok(Value::Bool(true)));
+        assert!(matches!(eval("sleep(-1)"), Err(RuntimeError::InvalidArgument { .. })));
+        let error = eval("sleep(9223372036854775807)").unwrap_err();
+        assert_eq!(error.to_string(), "1:1: invalid argument: cannot sleep for 9223372036854775807 ms, more than the limit of 86400000");
+
+        let token = CancellationToken::new();
+        let mut interpreter = Interpreter::new();
+        interpreter.set_cancellation_token(token.clone());
+        let canceller = std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(20));
+            token.cancel();
+        });
+        let started = std::time::Instant::now();
+        let result = interpreter.run(&parse("sleep(60000)").unwrap());
+        canceller.join().unwrap();
+        assert!(matches!(result, Err(RuntimeError::Cancelled { .. })));
+        assert!(started.elapsed().as_secs() < 10);
+    }
+
+    #[test]
+    fn test_process() {
+        std::env::set_var("TBASIC_TEST_GETENV", "yes");
+        assert_eq!(eval("getenv(\"TBASIC_TEST_GETENV\")"), Ok(Value::Str("yes".into())));
+        assert_eq!(eval("getenv(\"TBASIC_TEST_UNSET\")"), Ok(Value::Nil));
+
+        let mut interpreter = Interpreter::new();
+        interpreter.set_args(vec!["a".to_string(), "b".to_string()]);
+        let result = interpreter.run(&parse("args()").unwrap());
+        assert_eq!(result.unwrap().to_string(), "[\"a\", \"b\"]");
+
+        let error = eval("fn quit() {\n  exit(3)\n}\nquit()\nprint 1").unwrap_err();
+        assert!(matches!(error, RuntimeError::Exit { code: 3, .. }));
+        assert!(matches!(eval("exit()"), Err(RuntimeError::Exit { code: 0, .. })));
+    }
+
+    #[test]
+    fn test_assert() {
+        assert_eq!(eval("assert(1 < 2, \"fine\")"), Ok(Value::Nil));
+        let error = eval("x = 3\nassert(x == 2, format(\"x is {}\", x))").unwrap_err();
+        assert_eq!(error.message(), "assertion failed: x is 3");
+        assert_eq!((error.span().line, error.span().column), (2, 1));
+        assert_eq!(eval("assert([])").unwrap_err().message(), "assertion failed");
+    }
+}
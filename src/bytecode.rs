@@ -0,0 +1,645 @@
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::rc::Rc;
+
+use num_bigint::BigInt;
+
+use crate::ast::{BinaryOp, FunctionDecl, StructDecl, UnaryOp};
+use crate::compiler::{Chunk, CompiledFunction, Op};
+use crate::lexer::Span;
+use crate::value::Value;
+
+/// The first bytes of every `.tbc` file.
+pub const MAGIC: &[u8; 4] = b"TBC\0";
+
+/// The encoding [`save`] writes. [`load`] rejects files of other versions,
+/// so programs compiled by an older build have to be compiled again.
+/// Version 2 added the modules of [`save_with_modules`].
+pub const VERSION: u16 = 2;
+
+/// Why [`load`] couldn't read a compiled program.
+#[derive(Debug)]
+pub enum LoadError {
+    Io(io::Error),
+    /// The data doesn't start with [`MAGIC`], or
+    /// [`snapshot::MAGIC`](crate::snapshot::MAGIC) for a snapshot.
+    NotBytecode,
+    /// The data was written in an encoding this build doesn't read.
+    UnsupportedVersion(u16),
+    /// The data is cut short or inconsistent.
+    Malformed(String),
+    /// A snapshot refers to a host function that isn't registered with the
+    /// interpreter loading it.
+    UnknownHostFunction(String),
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LoadError::Io(error) => write!(f, "io error: {}", error),
+            LoadError::NotBytecode => write!(f, "not a compiled tbasic program"),
+            LoadError::UnsupportedVersion(version) => {
+                write!(f, "bytecode version {} is not supported, expected version {}", version, VERSION)
+            },
+            LoadError::Malformed(message) => write!(f, "malformed bytecode: {}", message),
+            LoadError::UnknownHostFunction(name) => write!(f, "host function `{}` is not registered", name),
+        }
+    }
+}
+
+impl std::error::Error for LoadError {}
+
+impl From<io::Error> for LoadError {
+    fn from(error: io::Error) -> Self {
+        LoadError::Io(error)
+    }
+}
+
+/// Writes `chunk` in the `.tbc` format: [`MAGIC`], [`VERSION`] as a
+/// little-endian `u16`, the chunk, then the modules it embeds, here none.
+/// Numbers are LEB128 varints, signed ones zigzag-encoded, and strings are
+/// their length followed by UTF-8.
+///
+/// Fails with [`io::ErrorKind::InvalidInput`] if the constant pool holds a
+/// value that only exists at run time, such as an array; [`compile`]
+/// never produces those.
+///
+/// [`compile`]: crate::compiler::compile
+pub fn save(chunk: &Chunk, writer: impl Write) -> io::Result<()> {
+    save_with_modules(chunk, &[], writer)
+}
+
+/// Like [`save`], embedding the sources of the modules the program
+/// imports as `(name, source)` pairs, so it runs without their files.
+pub fn save_with_modules(chunk: &Chunk, modules: &[(String, String)], mut writer: impl Write) -> io::Result<()> {
+    let mut out = Vec::from(&MAGIC[..]);
+    out.extend_from_slice(&VERSION.to_le_bytes());
+    encode_chunk(&mut out, chunk)?;
+    encode_len(&mut out, modules.len());
+    for (name, source) in modules {
+        encode_bytes(&mut out, name.as_bytes());
+        encode_bytes(&mut out, source.as_bytes());
+    }
+    writer.write_all(&out)
+}
+
+/// Reads a program written by [`save`], checking that every instruction
+/// refers to existing pool entries and code offsets, and that the code uses
+/// the operand stack consistently: no instruction pops more than the code
+/// before it pushed, and the stack has the same height whichever way an
+/// instruction is reached.
+///
+/// Functions of a loaded program keep their names and parameters, but
+/// their [`decl`](CompiledFunction::decl) has an empty body. Modules the
+/// file embeds are skipped; [`load_with_modules`] returns them too.
+pub fn load(reader: impl Read) -> Result<Chunk, LoadError> {
+    load_with_modules(reader).map(|(chunk, _)| chunk)
+}
+
+/// Reads a program written by [`save_with_modules`] and the modules it
+/// embeds.
+pub fn load_with_modules(mut reader: impl Read) -> Result<(Chunk, Vec<(String, String)>), LoadError> {
+    let mut data = Vec::new();
+    reader.read_to_end(&mut data)?;
+    let rest = data.strip_prefix(&MAGIC[..]).ok_or(LoadError::NotBytecode)?;
+    let mut decoder = Decoder::new(rest);
+    let version = u16::from_le_bytes([decoder.byte()?, decoder.byte()?]);
+    if version != VERSION {
+        return Err(LoadError::UnsupportedVersion(version));
+    }
+    let chunk = decoder.chunk()?;
+    let mut modules = Vec::new();
+    for _ in 0..decoder.len()? {
+        modules.push((decoder.string()?, decoder.string()?));
+    }
+    if !decoder.data.is_empty() {
+        return Err(malformed("trailing data after the program"));
+    }
+    Ok((chunk, modules))
+}
+
+fn malformed(message: &str) -> LoadError {
+    LoadError::Malformed(message.to_string())
+}
+
+fn unsupported(what: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, format!("{} can't be saved as bytecode", what))
+}
+
+const UNARY_OPS: [UnaryOp; 2] = [UnaryOp::Neg, UnaryOp::Not];
+
+const BINARY_OPS: [BinaryOp; 12] = [
+    BinaryOp::Add,
+    BinaryOp::Sub,
+    BinaryOp::Mul,
+    BinaryOp::Div,
+    BinaryOp::Equals,
+    BinaryOp::NotEquals,
+    BinaryOp::SmallerThan,
+    BinaryOp::GreaterThan,
+    BinaryOp::SmallerEquals,
+    BinaryOp::GreaterEquals,
+    BinaryOp::And,
+    BinaryOp::Or,
+];
+
+/// How deeply functions may be nested in a loaded program, so hostile data
+/// can't exhaust the host stack.
+const MAX_NESTING: usize = 64;
+
+pub(crate) fn encode_uint(out: &mut Vec<u8>, mut n: u64) {
+    loop {
+        let byte = (n & 0x7f) as u8;
+        n >>= 7;
+        if n == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+pub(crate) fn encode_int(out: &mut Vec<u8>, n: i64) {
+    encode_uint(out, ((n << 1) ^ (n >> 63)) as u64);
+}
+
+pub(crate) fn encode_len(out: &mut Vec<u8>, len: usize) {
+    encode_uint(out, len as u64);
+}
+
+pub(crate) fn encode_bytes(out: &mut Vec<u8>, bytes: &[u8]) {
+    encode_len(out, bytes.len());
+    out.extend_from_slice(bytes);
+}
+
+pub(crate) fn encode_span(out: &mut Vec<u8>, span: Span) {
+    for n in [span.start, span.end, span.line, span.column] {
+        encode_len(out, n);
+    }
+}
+
+pub(crate) fn encode_chunk(out: &mut Vec<u8>, chunk: &Chunk) -> io::Result<()> {
+    encode_len(out, chunk.code.len());
+    for (op, span) in chunk.code.iter().zip(&chunk.spans) {
+        encode_op(out, *op);
+        encode_span(out, *span);
+    }
+    encode_len(out, chunk.constants.len());
+    for constant in &chunk.constants {
+        encode_value(out, constant)?;
+    }
+    encode_len(out, chunk.names.len());
+    for name in &chunk.names {
+        encode_bytes(out, name.as_bytes());
+    }
+    encode_len(out, chunk.operand_spans.len());
+    for span in &chunk.operand_spans {
+        encode_span(out, *span);
+    }
+    encode_len(out, chunk.functions.len());
+    for function in &chunk.functions {
+        let decl = &function.decl;
+        encode_bytes(out, decl.name.as_bytes());
+        encode_len(out, decl.params.len());
+        for param in &decl.params {
+            encode_bytes(out, param.as_bytes());
+        }
+        encode_span(out, decl.span);
+        encode_chunk(out, &function.chunk)?;
+    }
+    Ok(())
+}
+
+pub(crate) fn encode_value(out: &mut Vec<u8>, value: &Value) -> io::Result<()> {
+    match value {
+        Value::Nil => out.push(0),
+        Value::Bool(b) => out.extend_from_slice(&[1, u8::from(*b)]),
+        Value::Int(n) => {
+            out.push(2);
+            encode_int(out, *n);
+        },
+        Value::BigInt(n) => {
+            out.push(3);
+            encode_bytes(out, &n.to_signed_bytes_le());
+        },
+        Value::Float(n) => {
+            out.push(4);
+            out.extend_from_slice(&n.to_le_bytes());
+        },
+        Value::Str(s) => {
+            out.push(5);
+            encode_bytes(out, s.as_bytes());
+        },
+        Value::StructType(decl) => {
+            out.push(6);
+            encode_bytes(out, decl.name.as_bytes());
+            encode_len(out, decl.fields.len());
+            for field in &decl.fields {
+                encode_bytes(out, field.as_bytes());
+            }
+            encode_span(out, decl.span);
+        },
+        other => return Err(unsupported(other.type_name())),
+    }
+    Ok(())
+}
+
+/// Writes an opcode byte followed by the instruction's operands.
+fn encode_op(out: &mut Vec<u8>, op: Op) {
+    let (opcode, operands): (u8, &[u32]) = match op {
+        Op::Constant(i) => (0, &[i]),
+        Op::Nil => (1, &[]),
+        Op::True => (2, &[]),
+        Op::False => (3, &[]),
+        Op::Pop => (4, &[]),
+        Op::Load(i) => (5, &[i]),
+        Op::LoadFunction(i) => (6, &[i]),
+        Op::Store(i) => (7, &[i]),
+        Op::Declare(i) => (8, &[i]),
+        Op::Global(i) => (9, &[i]),
+        Op::Closure(i) => (10, &[i]),
+        Op::Array(n) => (11, &[n]),
+        Op::Map(n) => (12, &[n]),
+        Op::CheckKey => (13, &[]),
+        Op::Index { target } => (14, &[target]),
+        Op::SetIndex { target } => (15, &[target]),
+        Op::Field { name, target } => (16, &[name, target]),
+        Op::SetField(i) => (17, &[i]),
+        Op::Unary(op) => (18, &[UNARY_OPS.iter().position(|o| *o == op).unwrap() as u32]),
+        Op::Binary(op) => (19, &[BINARY_OPS.iter().position(|o| *o == op).unwrap() as u32]),
+        Op::Truthy => (20, &[]),
+        Op::Jump(target) => (21, &[target]),
+        Op::JumpIfFalse(target) => (22, &[target]),
+        Op::JumpIfTrue(target) => (23, &[target]),
+        Op::Call { args, callee } => (24, &[args, callee]),
+        Op::Return => (25, &[]),
+        Op::Print(n) => (26, &[n]),
+        Op::Import(i) => (27, &[i]),
+        Op::Statement => (28, &[]),
+        Op::Iteration => (29, &[]),
+        Op::SetResult => (30, &[]),
+        Op::ClearResult => (31, &[]),
+        Op::Gosub { target, resume } => (32, &[target, resume]),
+        Op::SubReturn => (33, &[]),
+        Op::UndefinedLine(i) => (34, &[i]),
+        Op::TailCall { args, callee } => (35, &[args, callee]),
+    };
+    out.push(opcode);
+    for operand in operands {
+        encode_uint(out, u64::from(*operand));
+    }
+}
+
+pub(crate) struct Decoder<'a> {
+    pub(crate) data: &'a [u8],
+    /// How many functions enclose the chunk being read.
+    depth: usize,
+}
+
+impl Decoder<'_> {
+    pub(crate) fn new(data: &[u8]) -> Decoder<'_> {
+        Decoder { data, depth: 0 }
+    }
+
+    pub(crate) fn byte(&mut self) -> Result<u8, LoadError> {
+        let (&byte, rest) = self.data.split_first().ok_or_else(|| malformed("unexpected end of data"))?;
+        self.data = rest;
+        Ok(byte)
+    }
+
+    fn bytes(&mut self, len: usize) -> Result<&[u8], LoadError> {
+        if self.data.len() < len {
+            return Err(malformed("unexpected end of data"));
+        }
+        let (bytes, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(bytes)
+    }
+
+    pub(crate) fn uint(&mut self) -> Result<u64, LoadError> {
+        let mut n = 0u64;
+        for shift in (0..64).step_by(7) {
+            let byte = self.byte()?;
+            n |= u64::from(byte & 0x7f) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(n);
+            }
+        }
+        Err(malformed("varint is too long"))
+    }
+
+    pub(crate) fn int(&mut self) -> Result<i64, LoadError> {
+        let n = self.uint()?;
+        Ok((n >> 1) as i64 ^ -((n & 1) as i64))
+    }
+
+    pub(crate) fn u32(&mut self) -> Result<u32, LoadError> {
+        u32::try_from(self.uint()?).map_err(|_| malformed("operand is out of range"))
+    }
+
+    /// A count of items that follow, each taking at least a byte, so
+    /// corrupt counts can't make `load` allocate more than the data's size.
+    pub(crate) fn len(&mut self) -> Result<usize, LoadError> {
+        let len = usize::try_from(self.uint()?).map_err(|_| malformed("length is out of range"))?;
+        if len > self.data.len() {
+            return Err(malformed("length exceeds the data"));
+        }
+        Ok(len)
+    }
+
+    pub(crate) fn string(&mut self) -> Result<String, LoadError> {
+        let len = self.len()?;
+        let bytes = self.bytes(len)?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| malformed("string is not UTF-8"))
+    }
+
+    pub(crate) fn span(&mut self) -> Result<Span, LoadError> {
+        let mut next = || usize::try_from(self.uint()?).map_err(|_| malformed("span is out of range"));
+        Ok(Span { start: next()?, end: next()?, line: next()?, column: next()? })
+    }
+
+    pub(crate) fn chunk(&mut self) -> Result<Chunk, LoadError> {
+        let mut chunk = Chunk::default();
+        for _ in 0..self.len()? {
+            chunk.code.push(self.op()?);
+            chunk.spans.push(self.span()?);
+        }
+        for _ in 0..self.len()? {
+            let value = self.value()?;
+            chunk.constants.push(value);
+        }
+        for _ in 0..self.len()? {
+            let name = self.string()?;
+            chunk.names.push(name);
+        }
+        for _ in 0..self.len()? {
+            let span = self.span()?;
+            chunk.operand_spans.push(span);
+        }
+        for _ in 0..self.len()? {
+            let function = self.function()?;
+            chunk.functions.push(Rc::new(function));
+        }
+        check_operands(&chunk)?;
+        stack_heights(&chunk)?;
+        Ok(chunk)
+    }
+
+    fn function(&mut self) -> Result<CompiledFunction, LoadError> {
+        if self.depth == MAX_NESTING {
+            return Err(malformed("functions are nested too deeply"));
+        }
+        let name = self.string()?;
+        let mut params = Vec::new();
+        for _ in 0..self.len()? {
+            params.push(self.string()?);
+        }
+        let span = self.span()?;
+        self.depth += 1;
+        let chunk = self.chunk()?;
+        self.depth -= 1;
+        let param_types = vec![None; params.len()];
+        let decl = FunctionDecl { name, params, param_types, return_type: None, body: Vec::new(), span, doc: None };
+        Ok(CompiledFunction { decl: Rc::new(decl), chunk })
+    }
+
+    fn value(&mut self) -> Result<Value, LoadError> {
+        let tag = self.byte()?;
+        self.constant(tag)
+    }
+
+    /// The rest of a constant whose tag byte has been read.
+    pub(crate) fn constant(&mut self, tag: u8) -> Result<Value, LoadError> {
+        Ok(match tag {
+            0 => Value::Nil,
+            1 => Value::Bool(self.byte()? != 0),
+            2 => Value::Int(self.int()?),
+            3 => {
+                let len = self.len()?;
+                Value::from_big(BigInt::from_signed_bytes_le(self.bytes(len)?))
+            },
+            4 => {
+                let bytes = self.bytes(8)?;
+                Value::Float(f64::from_le_bytes(bytes.try_into().unwrap()))
+            },
+            5 => Value::from(self.string()?),
+            6 => {
+                let name = self.string()?;
+                let mut fields = Vec::new();
+                for _ in 0..self.len()? {
+                    fields.push(self.string()?);
+                }
+                let span = self.span()?;
+                Value::StructType(Rc::new(StructDecl { name, fields, span, doc: None }))
+            },
+            tag => return Err(LoadError::Malformed(format!("unknown constant tag {}", tag))),
+        })
+    }
+
+    fn op(&mut self) -> Result<Op, LoadError> {
+        Ok(match self.byte()? {
+            0 => Op::Constant(self.u32()?),
+            1 => Op::Nil,
+            2 => Op::True,
+            3 => Op::False,
+            4 => Op::Pop,
+            5 => Op::Load(self.u32()?),
+            6 => Op::LoadFunction(self.u32()?),
+            7 => Op::Store(self.u32()?),
+            8 => Op::Declare(self.u32()?),
+            9 => Op::Global(self.u32()?),
+            10 => Op::Closure(self.u32()?),
+            11 => Op::Array(self.u32()?),
+            12 => Op::Map(self.u32()?),
+            13 => Op::CheckKey,
+            14 => Op::Index { target: self.u32()? },
+            15 => Op::SetIndex { target: self.u32()? },
+            16 => Op::Field { name: self.u32()?, target: self.u32()? },
+            17 => Op::SetField(self.u32()?),
+            18 => {
+                let op = UNARY_OPS.get(self.u32()? as usize).ok_or_else(|| malformed("unknown unary operator"))?;
+                Op::Unary(*op)
+            },
+            19 => {
+                let op = BINARY_OPS.get(self.u32()? as usize).ok_or_else(|| malformed("unknown binary operator"))?;
+                Op::Binary(*op)
+            },
+            20 => Op::Truthy,
+            21 => Op::Jump(self.u32()?),
+            22 => Op::JumpIfFalse(self.u32()?),
+            23 => Op::JumpIfTrue(self.u32()?),
+            24 => Op::Call { args: self.u32()?, callee: self.u32()? },
+            25 => Op::Return,
+            26 => Op::Print(self.u32()?),
+            27 => Op::Import(self.u32()?),
+            28 => Op::Statement,
+            29 => Op::Iteration,
+            30 => Op::SetResult,
+            31 => Op::ClearResult,
+            32 => Op::Gosub { target: self.u32()?, resume: self.u32()? },
+            33 => Op::SubReturn,
+            34 => Op::UndefinedLine(self.u32()?),
+            35 => Op::TailCall { args: self.u32()?, callee: self.u32()? },
+            opcode => return Err(LoadError::Malformed(format!("unknown opcode {}", opcode))),
+        })
+    }
+}
+
+/// Checks that the instructions of `chunk` only refer to what exists.
+fn check_operands(chunk: &Chunk) -> Result<(), LoadError> {
+    let in_range = |i: u32, len: usize| (i as usize) < len;
+    for op in &chunk.code {
+        let valid = match *op {
+            Op::Constant(i) => in_range(i, chunk.constants.len()),
+            Op::UndefinedLine(i) => matches!(chunk.constants.get(i as usize), Some(Value::Int(_))),
+            Op::Load(i) | Op::LoadFunction(i) | Op::Store(i) | Op::Declare(i) | Op::Global(i) | Op::SetField(i) | Op::Import(i) => {
+                in_range(i, chunk.names.len())
+            },
+            Op::Field { name, target } => in_range(name, chunk.names.len()) && in_range(target, chunk.operand_spans.len()),
+            Op::Index { target } | Op::SetIndex { target } | Op::Call { callee: target, .. } | Op::TailCall { callee: target, .. } => {
+                in_range(target, chunk.operand_spans.len())
+            },
+            Op::Closure(i) => in_range(i, chunk.functions.len()),
+            // Jumping to the end finishes the program
+            Op::Jump(target) | Op::JumpIfFalse(target) | Op::JumpIfTrue(target) => target as usize <= chunk.code.len(),
+            Op::Gosub { target, resume } => target as usize <= chunk.code.len() && resume as usize <= chunk.code.len(),
+            _ => true,
+        };
+        if !valid {
+            return Err(LoadError::Malformed(format!("`{:?}` refers to a missing entry", op)));
+        }
+    }
+    Ok(())
+}
+
+/// How many values the operand stack holds at each instruction of `chunk`,
+/// `None` for those that are never reached, checking that every instruction
+/// finds enough and always the same number. The stack starts empty, and
+/// jumps must have been checked to stay within the code.
+pub(crate) fn stack_heights(chunk: &Chunk) -> Result<Vec<Option<usize>>, LoadError> {
+    let mut heights: Vec<Option<usize>> = vec![None; chunk.code.len()];
+    let mut pending = vec![(0, 0)];
+    while let Some((pc, height)) = pending.pop() {
+        // Running off the end finishes the program or call
+        let Some(&op) = chunk.code.get(pc) else {
+            continue;
+        };
+        match heights[pc] {
+            Some(known) if known == height => continue,
+            Some(_) => return Err(LoadError::Malformed(format!("the stack height at instruction {} depends on the path taken", pc))),
+            None => heights[pc] = Some(height),
+        }
+        let (pops, pushes) = op.stack_effect();
+        let height = height
+            .checked_sub(pops)
+            .ok_or_else(|| LoadError::Malformed(format!("instruction {} pops more values than the stack holds", pc)))?;
+        let height = height + pushes;
+        match op {
+            Op::Jump(target) => pending.push((target as usize, height)),
+            Op::JumpIfFalse(target) | Op::JumpIfTrue(target) => pending.extend([(target as usize, height), (pc + 1, height)]),
+            // `sub-return` continues at the `resume` of the `gosub`
+            Op::Gosub { target, resume } => pending.extend([(target as usize, height), (resume as usize, height)]),
+            Op::Return | Op::SubReturn | Op::UndefinedLine(_) => {},
+            _ => pending.push((pc + 1, height)),
+        }
+    }
+    Ok(heights)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::bytecode::{load, load_with_modules, save, save_with_modules, LoadError, VERSION};
+    use crate::compiler::{compile, Chunk, Op};
+    use crate::interpreter::{Interpreter, RuntimeError, SharedBuffer};
+    use crate::lexer::Span;
+    use crate::parser::parse;
+    use crate::sandbox::Sandbox;
+    use crate::value::Value;
+
+    #[test]
+    fn test_round_trip() {
+        let source = "struct P { x, y }\nfn f(a, b) {\n  return fn(c) {\n    return P(a, b + c)\n  }\n}\np = f(1, 2.5)(-3)\nprint p.x, p.y, \"done\"\n[p.x < 2 && 1, {\"k\": 9223372036854775807}]";
+        let chunk = compile(&parse(source).unwrap());
+        let mut bytes = Vec::new();
+        save(&chunk, &mut bytes).unwrap();
+        assert!(bytes.starts_with(b"TBC\0\x02\x00"));
+        let loaded = load(&bytes[..]).unwrap();
+        assert_eq!(loaded.to_string(), chunk.to_string());
+        assert_eq!(loaded.functions[0].decl.params, vec!["a", "b"]);
+
+        let output = SharedBuffer::new();
+        let mut interpreter = Interpreter::with_output(Box::new(output.clone()));
+        let result = interpreter.run_compiled(&loaded).unwrap();
+        assert_eq!(result.to_string(), "[true, {\"k\": 9223372036854775807}]");
+        assert_eq!(output.contents(), "1 -0.5 done\n");
+
+        let modules = vec![("util".to_string(), "fn twice(x) {\n  return 2 * x\n}".to_string())];
+        let mut bytes = Vec::new();
+        save_with_modules(&chunk, &modules, &mut bytes).unwrap();
+        let (loaded, loaded_modules) = load_with_modules(&bytes[..]).unwrap();
+        assert_eq!((loaded.to_string(), loaded_modules), (chunk.to_string(), modules));
+        assert_eq!(load(&bytes[..]).unwrap().to_string(), chunk.to_string());
+    }
+
+    #[test]
+    fn test_load_errors() {
+        assert!(matches!(load(&b"BAS\0"[..]), Err(LoadError::NotBytecode)));
+        assert!(matches!(load(&b"TBC\0\x09\x00"[..]), Err(LoadError::UnsupportedVersion(9))));
+
+        let mut bytes = Vec::new();
+        save(&compile(&parse("x = [1, 2]\nprint x[0]").unwrap()), &mut bytes).unwrap();
+        for len in 6..bytes.len() {
+            assert!(matches!(load(&bytes[..len]), Err(LoadError::Malformed(_))), "loaded {} bytes", len);
+        }
+        // `constant 0` pointing past an empty pool
+        let header = [&b"TBC\0"[..], &VERSION.to_le_bytes()].concat();
+        let error = load(&[&header[..], &[1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]].concat()[..]).unwrap_err();
+        assert_eq!(error.to_string(), "malformed bytecode: `Constant(0)` refers to a missing entry");
+
+        let mut chunk = Chunk::default();
+        chunk.constants.push(Value::array(vec![]));
+        assert!(save(&chunk, &mut Vec::new()).is_err());
+        chunk.constants[0] = Value::Str("ok".into());
+        assert!(save(&chunk, &mut Vec::new()).is_ok());
+    }
+
+    #[test]
+    fn test_stack_checks() {
+        let check = |code: Vec<Op>| {
+            let chunk = Chunk { spans: vec![Span::default(); code.len()], code, ..Chunk::default() };
+            let mut bytes = Vec::new();
+            save(&chunk, &mut bytes).unwrap();
+            load(&bytes[..]).map(|_| ()).map_err(|error| error.to_string())
+        };
+        assert_eq!(check(vec![Op::Nil, Op::Pop, Op::Nil, Op::Return]), Ok(()));
+        assert_eq!(check(vec![Op::Nil, Op::Pop, Op::Pop]), Err("malformed bytecode: instruction 2 pops more values than the stack holds".to_string()));
+        assert_eq!(check(vec![Op::Print(1)]), Err("malformed bytecode: instruction 0 pops more values than the stack holds".to_string()));
+        // Only the path skipping `nil` leaves the stack as it was
+        let error = check(vec![Op::True, Op::JumpIfFalse(3), Op::Nil, Op::Statement]).unwrap_err();
+        assert_eq!(error, "malformed bytecode: the stack height at instruction 3 depends on the path taken");
+        // Code after a `return` is never reached
+        assert_eq!(check(vec![Op::Nil, Op::Return, Op::Pop]), Ok(()));
+    }
+
+    #[test]
+    fn test_mutated() {
+        let source = "fn f(a, b) {\n  return [a, b][0] + b\n}\nm = {\"k\": f(1, 2)}\nwhile m[\"k\"] > 0 {\n  m[\"k\"] = m[\"k\"] - 1\n}\nprint m, len(\"ab\")";
+        let mut bytes = Vec::new();
+        save(&compile(&parse(source).unwrap()), &mut bytes).unwrap();
+        // Loading or running a mutated program fails or not, but never
+        // panics, and loaded code never underflows the operand stack
+        for i in 6..bytes.len() {
+            for byte in [0, 1, 2, 0x7f, 0x80, 0xff, bytes[i] ^ 1, bytes[i].wrapping_add(1)] {
+                let mut mutated = bytes.clone();
+                mutated[i] = byte;
+                if let Ok(chunk) = load(&mutated[..]) {
+                    let mut interpreter = Interpreter::with_output(Box::new(SharedBuffer::new()));
+                    interpreter.set_sandbox(Sandbox { fuel: Some(10_000), ..Sandbox::strict() });
+                    if let Err(error) = interpreter.run_compiled(&chunk) {
+                        assert!(!matches!(error.root(), RuntimeError::InvalidBytecode { .. }), "{}", error);
+                    }
+                }
+            }
+        }
+    }
+}
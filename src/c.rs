@@ -0,0 +1,749 @@
+use std::collections::BTreeSet;
+use std::fmt::Write;
+use std::rc::Rc;
+
+use indexmap::IndexSet;
+
+use crate::ast::{BinaryOp, Expr, ExprKind, Program, Stmt, StmtKind, StructDecl, UnaryOp};
+use crate::collections::{HashMap, HashSet, IndexMap};
+use crate::lexer::Span;
+use crate::transpile::{mangle, walk_blocks, Scopes, TranspileError};
+
+const RUNTIME: &str = include_str!("c_runtime.c");
+
+/// The builtins the C runtime implements.
+const BUILTINS: &[&str] = &[
+    "input", "abs", "min", "max", "sqrt", "pow", "floor", "ceil", "round", "len", "substr", "trim", "split", "contains",
+    "replace", "format", "push", "pop", "insert", "remove", "sort", "reverse", "keys", "values", "has", "rnd", "random",
+    "seed", "getenv", "args", "exit",
+];
+
+/// Translates `program` into a standalone C99 source file, which only needs
+/// the C standard library and `-lm` to build.
+///
+/// The executable behaves like the interpreter with its default settings:
+/// it prints to stdout, reads `input` from stdin and passes its command
+/// line to `args`. A runtime error prints `error: ` and the error's location
+/// and message to stderr and exits with status 1; unlike the interpreter,
+/// it doesn't list the calls that led there. Imports and the builtins
+/// without a C version (`upper`, `lower`, the clock and the file builtins)
+/// are rejected.
+///
+/// Its values are only freed, all at once, when it exits, so it suits
+/// short-lived programs; one embedded elsewhere can call `tb_reset` to free
+/// them between runs.
+pub fn transpile(program: &Program) -> Result<String, TranspileError> {
+    let scopes = Scopes::new(program, BUILTINS)?;
+    let mut generator = Generator::new(&scopes);
+    for function in 0..scopes.functions.len() {
+        generator.function(function);
+    }
+    generator.main(program);
+    Ok(generator.finish())
+}
+
+struct Generator<'a> {
+    scopes: &'a Scopes,
+    /// String constants, numbered in order of appearance.
+    strings: IndexMap<String, usize>,
+    types: HashMap<*const StructDecl, usize>,
+    type_definitions: String,
+    /// The globals the program reads or writes.
+    globals: IndexSet<String>,
+    bodies: String,
+    /// The function being generated, `None` for the top level.
+    function: Option<usize>,
+    out: String,
+    indent: usize,
+    temps: usize,
+    /// Classic dialect: the line labels `goto` and `gosub` jump to.
+    lines: HashMap<i64, usize>,
+    targets: HashSet<i64>,
+    sub_returns: bool,
+}
+
+impl<'a> Generator<'a> {
+    fn new(scopes: &'a Scopes) -> Generator<'a> {
+        Generator {
+            scopes,
+            strings: IndexMap::default(),
+            types: HashMap::default(),
+            type_definitions: String::new(),
+            globals: IndexSet::new(),
+            bodies: String::new(),
+            function: None,
+            out: String::new(),
+            indent: 1,
+            temps: 0,
+            lines: HashMap::default(),
+            targets: HashSet::default(),
+            sub_returns: false,
+        }
+    }
+
+    fn finish(self) -> String {
+        let mut c = String::from(RUNTIME);
+        c.push_str("\n/* Program */\n\n");
+        for (i, scope) in self.scopes.functions.iter().enumerate() {
+            if scope.captured.is_empty() {
+                continue;
+            }
+            let _ = writeln!(c, "struct tb_env_{} {{", i);
+            match self.env_parent(i) {
+                Some(parent) => {
+                    let _ = writeln!(c, "    struct tb_env_{} *parent;", parent);
+                },
+                None => c.push_str("    void *parent;\n"),
+            }
+            for name in scope.locals.iter().filter(|name| scope.captured.contains(*name)) {
+                let _ = writeln!(c, "    tb_value v_{};", mangle(name));
+            }
+            c.push_str("};\n\n");
+        }
+        for (i, scope) in self.scopes.functions.iter().enumerate() {
+            let _ = writeln!(c, "static tb_value tb_fn_{}(void *up_, tb_value *args);", i);
+            let _ = writeln!(
+                c,
+                "static const tb_function tb_function_{} = {{ {}, {}, tb_fn_{} }};",
+                i,
+                c_string(&scope.decl.name),
+                scope.decl.params.len(),
+                i
+            );
+        }
+        for (s, i) in &self.strings {
+            let _ = writeln!(c, "static const tb_str tb_s{} = {{ {}, {} }};", i, s.len(), c_string(s));
+        }
+        c.push_str(&self.type_definitions);
+        let builtins: Vec<&String> = self.globals.iter().filter(|name| BUILTINS.contains(&name.as_str())).collect();
+        for name in &builtins {
+            let _ = writeln!(c, "static const tb_builtin tb_builtin_{0}_info = {{ \"{0}\", tb_builtin_{0} }};", name);
+        }
+        for name in &self.globals {
+            let _ = writeln!(c, "static tb_value tb_g_{} = TB_UNSET_INIT;", mangle(name));
+        }
+        c.push('\n');
+        c.push_str(&self.bodies);
+        c.push_str("int main(int argc, char **argv) {\n");
+        if self.sub_returns {
+            c.push_str("    int tb_resume;\n");
+        }
+        c.push_str("    atexit(tb_reset);\n    tb_argc = argc;\n    tb_argv = argv;\n");
+        for name in &builtins {
+            let _ = writeln!(c, "    tb_g_{0} = tb_builtin_value(&tb_builtin_{0}_info);", name);
+        }
+        c.push_str(&self.out);
+        c.push_str("}\n");
+        c
+    }
+
+    /// The closest function `function` is defined in whose calls keep
+    /// variables for functions defined inside them; what its `up` points to.
+    fn env_parent(&self, function: usize) -> Option<usize> {
+        let mut parent = self.scopes.functions[function].parent;
+        while let Some(i) = parent {
+            if !self.scopes.functions[i].captured.is_empty() {
+                return Some(i);
+            }
+            parent = self.scopes.functions[i].parent;
+        }
+        None
+    }
+
+    fn function(&mut self, i: usize) {
+        let scope = &self.scopes.functions[i];
+        self.function = Some(i);
+        self.out.clear();
+        self.indent = 1;
+        self.temps = 0;
+        match self.env_parent(i) {
+            Some(parent) => {
+                self.emit(format!("struct tb_env_{} *up = up_;", parent));
+                self.emit("(void)up;");
+            },
+            None => self.emit("(void)up_;"),
+        }
+        self.emit("(void)args;");
+        if !scope.captured.is_empty() {
+            self.emit(format!("struct tb_env_{0} *env = tb_alloc(sizeof(struct tb_env_{0}));", i));
+            if self.env_parent(i).is_some() {
+                self.emit("env->parent = up;");
+            } else {
+                self.emit("env->parent = NULL;");
+            }
+        }
+        for (n, name) in scope.locals.iter().enumerate() {
+            let param = n < scope.decl.params.len();
+            match (scope.captured.contains(name), param) {
+                (true, true) => self.emit(format!("env->v_{} = args[{}];", mangle(name), n)),
+                (true, false) => self.emit(format!("env->v_{}.tag = TB_UNSET;", mangle(name))),
+                (false, true) => self.emit(format!("tb_value l_{} = args[{}];", mangle(name), n)),
+                (false, false) => self.emit(format!("tb_value l_{} = TB_UNSET_INIT;", mangle(name))),
+            }
+            if !scope.captured.contains(name) {
+                self.emit(format!("(void)l_{};", mangle(name)));
+            }
+        }
+        let mut globals: Vec<&String> = scope.globals.iter().collect();
+        globals.sort();
+        for name in globals {
+            self.emit(format!("int gl_{} = 0;", mangle(name)));
+            self.emit(format!("(void)gl_{};", mangle(name)));
+        }
+        let body = scope.decl.body.clone();
+        self.block(&body, 0);
+        self.emit("return tb_nil();");
+        let _ = writeln!(self.bodies, "static tb_value tb_fn_{}(void *up_, tb_value *args) {{", i);
+        self.bodies.push_str(&self.out);
+        self.bodies.push_str("}\n\n");
+    }
+
+    fn main(&mut self, program: &Program) {
+        self.function = None;
+        self.out.clear();
+        self.indent = 1;
+        self.temps = 0;
+        self.lines = program.lines.clone();
+        let mut resumes = BTreeSet::new();
+        for (i, statement) in program.statements.iter().enumerate() {
+            walk_blocks(statement, &mut |kind| match kind {
+                StmtKind::Goto(line) => {
+                    self.targets.insert(*line);
+                },
+                StmtKind::Gosub(line) => {
+                    self.targets.insert(*line);
+                    resumes.insert(i + 1);
+                },
+                StmtKind::SubReturn => self.sub_returns = true,
+                _ => {},
+            });
+        }
+        if !self.sub_returns {
+            resumes.clear();
+        }
+        let mut labels: HashMap<usize, Vec<i64>> = HashMap::default();
+        for (&line, &i) in &program.lines {
+            if self.targets.contains(&line) {
+                labels.entry(i).or_default().push(line);
+            }
+        }
+        for i in 0..=program.statements.len() {
+            if resumes.contains(&i) {
+                self.emit(format!("tb_resume_{}:;", i));
+            }
+            let mut lines = labels.remove(&i).unwrap_or_default();
+            lines.sort();
+            for line in lines {
+                self.emit(format!("tb_line_{}:;", line));
+            }
+            if let Some(statement) = program.statements.get(i) {
+                self.statement(statement, i + 1);
+            }
+        }
+        self.emit("return 0;");
+        if self.sub_returns {
+            self.out.push_str("tb_dispatch:\n");
+            self.emit("switch (tb_resume) {");
+            for i in &resumes {
+                self.emit(format!("case {0}: goto tb_resume_{0};", i));
+            }
+            self.emit("default: return 0;");
+            self.emit("}");
+        }
+    }
+
+    fn emit(&mut self, line: impl AsRef<str>) {
+        for _ in 0..self.indent {
+            self.out.push_str("    ");
+        }
+        self.out.push_str(line.as_ref());
+        self.out.push('\n');
+    }
+
+    fn temp(&mut self, value: String) -> String {
+        let name = format!("t{}", self.temps);
+        self.temps += 1;
+        self.emit(format!("tb_value {} = {};", name, value));
+        name
+    }
+
+    /// A `gosub` nested in `statements` continues at `resume`, after the
+    /// top-level statement it is in.
+    fn block(&mut self, statements: &[Stmt], resume: usize) {
+        for statement in statements {
+            self.statement(statement, resume);
+        }
+    }
+
+    fn nested(&mut self, statements: &[Stmt], resume: usize) {
+        self.indent += 1;
+        self.block(statements, resume);
+        self.indent -= 1;
+    }
+
+    /// `resume` is where a `gosub` in `statement` continues.
+    fn statement(&mut self, statement: &Stmt, resume: usize) {
+        let at = c_span(statement.span);
+        match &statement.kind {
+            StmtKind::Assign { name, value, declaration, .. } => {
+                let value = self.expr(value);
+                if *declaration {
+                    if let Some(flag) = self.flag(name) {
+                        self.emit(format!("{} = 0;", flag));
+                    }
+                }
+                self.assign(name, &value);
+            },
+            StmtKind::SetIndex { target, index, value } => {
+                let target_value = self.expr(target);
+                let index_value = self.expr(index);
+                let value = self.expr(value);
+                self.emit(format!(
+                    "tb_set_index({}, {}, {}, {}, {});",
+                    target_value,
+                    index_value,
+                    value,
+                    c_span(target.span),
+                    c_span(index.span)
+                ));
+            },
+            StmtKind::SetField { target, field, value } => {
+                let target_value = self.expr(target);
+                let value = self.expr(value);
+                self.emit(format!("tb_set_field({}, {}, {}, {});", target_value, c_string(field), value, c_span(target.span)));
+            },
+            StmtKind::If { condition, then_branch, else_branch } => {
+                let condition = self.expr(condition);
+                self.emit(format!("if (tb_truthy({})) {{", condition));
+                self.nested(then_branch, resume);
+                if let Some(else_branch) = else_branch {
+                    self.emit("} else {");
+                    self.nested(else_branch, resume);
+                }
+                self.emit("}");
+            },
+            StmtKind::While { condition, body } => {
+                self.emit("while (1) {");
+                self.indent += 1;
+                let condition = self.expr(condition);
+                self.emit(format!("if (!tb_truthy({})) break;", condition));
+                self.block(body, resume);
+                self.indent -= 1;
+                self.emit("}");
+            },
+            StmtKind::Break => self.emit("break;"),
+            StmtKind::Continue => self.emit("continue;"),
+            StmtKind::Print(args) => {
+                let values = self.values(args);
+                self.emit(format!("tb_print({}, {});", args.len(), values));
+            },
+            StmtKind::Function(decl) => {
+                let function = self.make_function(self.scopes.of(decl));
+                self.assign(&decl.name, &function);
+            },
+            StmtKind::Struct(decl) => {
+                let i = self.struct_type(decl);
+                self.assign(&decl.name, &format!("tb_struct_type(&tb_type_{})", i));
+            },
+            StmtKind::Return(value) => {
+                let value = match value {
+                    Some(value) => self.expr(value),
+                    None => "tb_nil()".to_string(),
+                };
+                if self.function.is_some() {
+                    self.emit(format!("return {};", value));
+                } else {
+                    self.emit(format!("(void){};", value));
+                    self.emit("return 0;");
+                }
+            },
+            StmtKind::Global(names) => {
+                for name in names {
+                    if let Some(flag) = self.flag(name) {
+                        self.emit(format!("{} = 1;", flag));
+                    }
+                }
+            },
+            // Rejected by `Scopes::new`
+            StmtKind::Import(_) => {},
+            StmtKind::Goto(line) => self.jump(*line, &at),
+            StmtKind::Gosub(line) => {
+                self.emit(format!("tb_gosub({}, {});", resume, at));
+                self.jump(*line, &at);
+            },
+            StmtKind::SubReturn => {
+                self.emit(format!("tb_resume = tb_sub_return({});", at));
+                self.emit("goto tb_dispatch;");
+            },
+            StmtKind::Expr(expr) => {
+                let value = self.expr(expr);
+                self.emit(format!("(void){};", value));
+            },
+        }
+    }
+
+    fn jump(&mut self, line: i64, at: &str) {
+        if self.lines.contains_key(&line) {
+            self.emit(format!("goto tb_line_{};", line));
+        } else {
+            self.emit(format!("tb_fail({}, \"there is no line {}\");", at, line));
+        }
+    }
+
+    /// Evaluates `exprs` in order into an array, returning a pointer to it.
+    fn values(&mut self, exprs: &[Expr]) -> String {
+        if exprs.is_empty() {
+            return "NULL".to_string();
+        }
+        let values: Vec<String> = exprs.iter().map(|expr| self.expr(expr)).collect();
+        let name = format!("t{}", self.temps);
+        self.temps += 1;
+        self.emit(format!("tb_value {}[] = {{ {} }};", name, values.join(", ")));
+        name
+    }
+
+    /// Emits the code evaluating `expr`, returning a C expression for its
+    /// value without side effects.
+    fn expr(&mut self, expr: &Expr) -> String {
+        let at = c_span(expr.span);
+        match &expr.kind {
+            ExprKind::Number(n) => format!("tb_int({})", n),
+            ExprKind::Float(f) if f.is_infinite() => "tb_float(HUGE_VAL)".to_string(),
+            ExprKind::Float(f) => format!("tb_float({:e})", f),
+            ExprKind::Str(s) => {
+                let next = self.strings.len();
+                let i = *self.strings.entry(s.clone()).or_insert(next);
+                format!("tb_string(&tb_s{})", i)
+            },
+            ExprKind::Variable(name) => {
+                let value = self.read(name, expr.span, "tb_global");
+                self.temp(value)
+            },
+            ExprKind::Function(decl) => {
+                let function = self.make_function(self.scopes.of(decl));
+                self.temp(function)
+            },
+            ExprKind::Array(items) => {
+                let values = self.values(items);
+                self.temp(format!("tb_array_of({}, {})", items.len(), values))
+            },
+            ExprKind::Map(entries) => {
+                let map = self.temp("tb_map_new()".to_string());
+                for (key, value) in entries {
+                    let key_value = self.expr(key);
+                    let key_name = format!("t{}", self.temps);
+                    self.temps += 1;
+                    self.emit(format!("const tb_str *{} = tb_key({}, {});", key_name, key_value, c_span(key.span)));
+                    let value = self.expr(value);
+                    self.emit(format!("tb_map_put({}.as.m, {}, {});", map, key_name, value));
+                }
+                map
+            },
+            ExprKind::Index { target, index } => {
+                let target_value = self.expr(target);
+                let index_value = self.expr(index);
+                self.temp(format!(
+                    "tb_index({}, {}, {}, {})",
+                    target_value,
+                    index_value,
+                    c_span(target.span),
+                    c_span(index.span)
+                ))
+            },
+            ExprKind::Field { target, field } => {
+                let target_value = self.expr(target);
+                self.temp(format!("tb_field({}, {}, {}, {})", target_value, c_string(field), c_span(target.span), at))
+            },
+            ExprKind::Unary { op, operand } => {
+                let value = self.expr(operand);
+                match op {
+                    UnaryOp::Neg => self.temp(format!("tb_neg({}, {})", value, at)),
+                    UnaryOp::Not => self.temp(format!("tb_not({})", value)),
+                }
+            },
+            ExprKind::Binary { op: op @ (BinaryOp::And | BinaryOp::Or), left, right } => {
+                let result = format!("t{}", self.temps);
+                self.temps += 1;
+                self.emit(format!("tb_value {};", result));
+                let left = self.expr(left);
+                let (test, skipped) = if *op == BinaryOp::And { ("", 0) } else { ("!", 1) };
+                self.emit(format!("if ({}tb_truthy({})) {{", test, left));
+                self.indent += 1;
+                let right = self.expr(right);
+                self.emit(format!("{} = tb_bool(tb_truthy({}));", result, right));
+                self.indent -= 1;
+                self.emit("} else {");
+                self.emit(format!("    {} = tb_bool({});", result, skipped));
+                self.emit("}");
+                result
+            },
+            ExprKind::Binary { op, left, right } => {
+                let left = self.expr(left);
+                let right = self.expr(right);
+                self.temp(format!("tb_binary({}, {}, {}, {})", c_operator(*op), left, right, at))
+            },
+            ExprKind::Call { callee, args } => {
+                let function = match &callee.kind {
+                    ExprKind::Variable(name) => {
+                        let value = self.read(name, callee.span, "tb_global_function");
+                        self.temp(value)
+                    },
+                    _ => self.expr(callee),
+                };
+                let values = self.values(args);
+                self.temp(format!("tb_call({}, {}, {}, {}, {})", function, args.len(), values, at, c_span(callee.span)))
+            },
+        }
+    }
+
+    /// A C expression reading the variable `name`, using `global` to read a
+    /// global.
+    fn read(&mut self, name: &str, span: Span, global: &str) -> String {
+        let global = format!("{}(&{}, {}, {})", global, self.global(name), c_string(name), c_span(span));
+        let Some(function) = self.function else {
+            return global;
+        };
+        let mut value = global.clone();
+        for holder in self.scopes.holders(function, name).into_iter().rev() {
+            let slot = self.slot(function, holder, name);
+            value = format!("{0}.tag != TB_UNSET ? {0} : {1}", slot, value);
+        }
+        match self.flag(name) {
+            Some(flag) => format!("{} ? {} : ({})", flag, global, value),
+            None => value,
+        }
+    }
+
+    fn assign(&mut self, name: &str, value: &str) {
+        let global = self.global(name);
+        let Some(function) = self.function else {
+            self.emit(format!("{} = {};", global, value));
+            return;
+        };
+        let slot = self.slot(function, function, name);
+        match self.flag(name) {
+            Some(flag) => self.emit(format!("if ({}) {} = {}; else {} = {};", flag, global, value, slot, value)),
+            None => self.emit(format!("{} = {};", slot, value)),
+        }
+    }
+
+    /// Where the variables of a call of `holder` keep `name`, from inside
+    /// `function`.
+    fn slot(&self, function: usize, holder: usize, name: &str) -> String {
+        if holder == function {
+            return match self.scopes.functions[function].captured.contains(name) {
+                true => format!("env->v_{}", mangle(name)),
+                false => format!("l_{}", mangle(name)),
+            };
+        }
+        let mut path = "up".to_string();
+        let mut env = self.env_parent(function);
+        while let Some(i) = env.filter(|&i| i != holder) {
+            path.push_str("->parent");
+            env = self.env_parent(i);
+        }
+        format!("{}->v_{}", path, mangle(name))
+    }
+
+    /// The flag telling whether the current function declared `name`
+    /// global.
+    fn flag(&self, name: &str) -> Option<String> {
+        let function = self.function?;
+        self.scopes.functions[function].globals.contains(name).then(|| format!("gl_{}", mangle(name)))
+    }
+
+    fn global(&mut self, name: &str) -> String {
+        self.globals.insert(name.to_string());
+        format!("tb_g_{}", mangle(name))
+    }
+
+    /// A value for `function` defined in the current call.
+    fn make_function(&self, function: usize) -> String {
+        let env = match self.function {
+            Some(current) if !self.scopes.functions[current].captured.is_empty() => "env",
+            Some(current) if self.env_parent(current).is_some() => "up",
+            _ => "NULL",
+        };
+        format!("tb_make_function(&tb_function_{}, {})", function, env)
+    }
+
+    fn struct_type(&mut self, decl: &Rc<StructDecl>) -> usize {
+        let next = self.types.len();
+        let i = *self.types.entry(Rc::as_ptr(decl)).or_insert(next);
+        if i == next {
+            let fields = if decl.fields.is_empty() {
+                "NULL".to_string()
+            } else {
+                let names: Vec<String> = decl.fields.iter().map(|field| c_string(field)).collect();
+                let _ = writeln!(self.type_definitions, "static const char *const tb_fields_{}[] = {{ {} }};", i, names.join(", "));
+                format!("tb_fields_{}", i)
+            };
+            let _ = writeln!(
+                self.type_definitions,
+                "static const tb_type tb_type_{} = {{ {}, {}, {} }};",
+                i,
+                c_string(&decl.name),
+                decl.fields.len(),
+                fields
+            );
+        }
+        i
+    }
+}
+
+fn c_span(span: Span) -> String {
+    format!("TB_AT({}, {})", span.line, span.column)
+}
+
+fn c_operator(op: BinaryOp) -> &'static str {
+    match op {
+        BinaryOp::Add => "TB_ADD",
+        BinaryOp::Sub => "TB_SUB",
+        BinaryOp::Mul => "TB_MUL",
+        BinaryOp::Div => "TB_DIV",
+        BinaryOp::Equals => "TB_EQ",
+        BinaryOp::NotEquals => "TB_NE",
+        BinaryOp::SmallerThan => "TB_LT",
+        BinaryOp::GreaterThan => "TB_GT",
+        BinaryOp::SmallerEquals => "TB_LE",
+        BinaryOp::GreaterEquals => "TB_GE",
+        BinaryOp::And | BinaryOp::Or => unreachable!("`&&` and `||` short-circuit"),
+    }
+}
+
+/// A C string literal holding the UTF-8 bytes of `s`.
+fn c_string(s: &str) -> String {
+    let mut literal = String::from("\"");
+    for &byte in s.as_bytes() {
+        match byte {
+            b'"' | b'\\' => {
+                literal.push('\\');
+                literal.push(byte as char);
+            },
+            // `?` could start a trigraph
+            b' '..=b'~' if byte != b'?' => literal.push(byte as char),
+            _ => {
+                let _ = write!(literal, "\\{:03o}", byte);
+            },
+        }
+    }
+    literal.push('"');
+    literal
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::PathBuf;
+    use std::process::Command;
+
+    use crate::c::{c_string, transpile};
+    use crate::interpreter::{Interpreter, SharedBuffer};
+    use crate::parser::{parse, parse_with_dialect, Dialect};
+
+    /// Builds and runs the C version of `source`, returning its exit status,
+    /// stdout and stderr, or `None` without a C compiler.
+    fn run_c(name: &str, source: &str, dialect: Dialect) -> Option<(i32, String, String)> {
+        let c = transpile(&parse_with_dialect(source, dialect).unwrap()).unwrap();
+        let dir = std::env::temp_dir().join(format!("tbasic-c-{}-{}", std::process::id(), name));
+        std::fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("program.c");
+        let executable: PathBuf = dir.join("program");
+        std::fs::write(&file, c).unwrap();
+        let build = Command::new("cc")
+            .args(["-std=c99", "-Wall", "-Wextra", "-pedantic", "-Werror", "-o"])
+            .arg(&executable)
+            .arg(&file)
+            .arg("-lm")
+            .output()
+            .ok()?;
+        assert!(build.status.success(), "{}", String::from_utf8_lossy(&build.stderr));
+        let run = Command::new(&executable).output().unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        let text = |bytes: Vec<u8>| String::from_utf8(bytes).unwrap();
+        Some((run.status.code().unwrap(), text(run.stdout), text(run.stderr)))
+    }
+
+    /// Checks that the C version of `source` prints what the interpreter
+    /// prints.
+    fn check(name: &str, source: &str, dialect: Dialect) {
+        let output = SharedBuffer::new();
+        let result = Interpreter::with_output(Box::new(output.clone())).run(&parse_with_dialect(source, dialect).unwrap());
+        assert!(result.is_ok(), "{:?}", result);
+        if let Some((status, stdout, stderr)) = run_c(name, source, dialect) {
+            assert_eq!((status, stdout.as_str(), stderr.as_str()), (0, output.contents().as_str(), ""), "{:?}", source);
+        }
+    }
+
+    #[test]
+    fn test_matches_interpreter() {
+        check(
+            "values",
+            "fn f() {}\nprint 1, 2.5, -0.0, 10000000000000000.0, 0.00001, 0.0001, 1.0 / 0.0, \"a\\tb\", f() == f(), f()\n\
+             print [1, \"x\", [2.0]], {\"k\": \"v\", \"n\": {}}, [] == [], 7 / 2, 7.0 / 2\n\
+             print \"ab\" + \"c\", \"b\" < \"ab\", 1 == 1.0, !0, 1 && 0, 0 || \"\", len, fn() {}",
+            Dialect::Modern,
+        );
+        check(
+            "closures",
+            "fn counter() {\n  n = 0\n  return fn() {\n    n = n + 1\n    return n\n  }\n}\n\
+             c = counter()\nc()\nprint c(), c(), counter()()\n\
+             fn adder(a) {\n  return fn(b) {\n    return fn(c) { return a + b + c }\n  }\n}\nprint adder(1)(2)(3)\n\
+             fn outer() {\n  x = 1\n  f = fn() { return x }\n  x = 2\n  return f\n}\nprint outer()()\n\
+             fn fib(n) {\n  if n < 2 { return n }\n  return fib(n - 1) + fib(n - 2)\n}\nprint fib(15)",
+            Dialect::Modern,
+        );
+        check(
+            "scopes",
+            "g = 1\nfn set() {\n  global g\n  g = 2\n  let g = 3\n  print g\n}\nset()\nprint g\n\
+             fn shadow() {\n  print g\n  g = 5\n  print g\n}\nshadow()\nprint g",
+            Dialect::Modern,
+        );
+        check(
+            "data",
+            "struct P { x, y }\np = P(1, 2)\np.x = p.x + 10\nprint p, p.x, P\n\
+             m = {\"b\": 1, \"a\": 2}\nm[\"c\"] = 3\nm[\"b\"] = 4\nprint m, keys(m), values(m), has(m, \"a\")\n\
+             a = [3, 1, 2]\npush(a, 0)\nsort(a)\nprint a, pop(a), reverse(a), len(\"héllo\")\n\
+             i = 0\nwhile 1 {\n  i = i + 1\n  if i == 2 { continue }\n  if i > 4 { break }\n  print i\n}\n\
+             print split(\"a,b\", \",\"), substr(\"hello\", 1, 3), trim(\"  x \"), replace(\"aaa\", \"a\", \"b\")\n\
+             print format(\"{} and {}\", 1, [2]), min(3, 1.5), max(2, 4), abs(-3), pow(2, 10), sqrt(16.0), floor(2.5)",
+            Dialect::Modern,
+        );
+        check(
+            "classic",
+            "10 n = 0\n20 gosub 100\n30 if n < 3 { goto 20 }\n40 print \"done\", n\n45 if n > 0 { gosub 100 }\n\
+             50 goto 200\n100 n = n + 1\n110 print \"sub\", n\n120 return\n200 print \"end\"",
+            Dialect::Classic,
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        let programs = [
+            ("undefined", "x = 1\nprint y", "error: 2:7: undefined variable `y`"),
+            ("call", "f(1)", "error: 1:1: call to undefined function `f`"),
+            ("arity", "fn f(a) {}\nf()", "error: 2:1: `f` expects 1 argument, but 0 were given"),
+            ("overflow", "print 9223372036854775807 + 1", "error: 1:7: integer overflow"),
+            ("index", "a = [1]\nprint a[1]", "error: 2:9: index 1 is out of bounds for an array of length 1"),
+            ("recursion", "fn f() { return f() }\nf()", "error: 1:17: stack overflow in script: more than 100 nested calls"),
+        ];
+        for (name, source, message) in programs {
+            let Some((status, stdout, stderr)) = run_c(name, source, Dialect::Modern) else { return };
+            let expected = Interpreter::with_output(Box::new(SharedBuffer::new())).run(&parse(source).unwrap()).unwrap_err();
+            assert!(expected.to_string().starts_with(&message["error: ".len()..]), "{}", expected);
+            assert_eq!((status, stdout.as_str(), stderr.trim_end()), (1, "", message));
+        }
+        let Some((status, stdout, stderr)) = run_c("line", "10 print 1\n20 goto 5", Dialect::Classic) else { return };
+        assert_eq!((status, stdout.as_str(), stderr.as_str()), (1, "1\n", "error: 2:4: there is no line 5\n"));
+    }
+
+    #[test]
+    fn test_rejected() {
+        assert_eq!(transpile(&parse("import m").unwrap()).unwrap_err().to_string(), "1:1: modules can't be transpiled");
+        assert_eq!(transpile(&parse("print upper(\"a\")").unwrap()).unwrap_err().to_string(), "1:7: the builtin `upper` isn't available");
+    }
+
+    #[test]
+    fn test_c_string() {
+        assert_eq!(c_string("\"é?\"\n"), "\"\\\"\\303\\251\\077\\\"\\012\"");
+    }
+}
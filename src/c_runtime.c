@@ -0,0 +1,1560 @@
+/* Runtime for C transpiled from tbasic: values, operators and builtins,
+ * behaving like the interpreter with its default settings.
+ *
+ * Heap values are never freed one by one: everything `tb_alloc` and
+ * `tb_grow` hand out stays in one arena until `tb_reset` frees it all, which
+ * `main` arranges at exit. A program's memory therefore only grows while it
+ * runs, so the output suits short-lived programs, not ones that loop
+ * building values for long. */
+
+#include <inttypes.h>
+#include <math.h>
+#include <stdarg.h>
+#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+#include <time.h>
+
+#ifdef __GNUC__
+#define TB_UNUSED __attribute__((unused))
+#else
+#define TB_UNUSED
+#endif
+#define TB_FN static TB_UNUSED
+
+/* Same as the interpreter's default limit on nested calls. */
+#define TB_MAX_DEPTH 100
+
+typedef struct {
+    int line, column;
+} tb_span;
+
+#define TB_AT(line, column) ((tb_span){ (line), (column) })
+
+typedef enum {
+    /* A variable that hasn't been assigned. */
+    TB_UNSET,
+    TB_NIL,
+    TB_INT,
+    TB_FLOAT,
+    TB_BOOL,
+    TB_STR,
+    TB_ARRAY,
+    TB_MAP,
+    TB_STRUCT,
+    TB_STRUCT_TYPE,
+    TB_FUNCTION,
+    TB_BUILTIN
+} tb_tag;
+
+typedef struct {
+    size_t len;
+    const char *data;
+} tb_str;
+
+typedef struct tb_array tb_array;
+typedef struct tb_map tb_map;
+typedef struct tb_instance tb_instance;
+typedef struct tb_closure tb_closure;
+typedef struct tb_builtin tb_builtin;
+
+typedef struct {
+    const char *name;
+    size_t count;
+    const char *const *fields;
+} tb_type;
+
+typedef struct {
+    tb_tag tag;
+    union {
+        int64_t i;
+        double f;
+        int b;
+        const tb_str *s;
+        tb_array *a;
+        tb_map *m;
+        tb_instance *o;
+        const tb_type *t;
+        const tb_closure *fn;
+        const tb_builtin *builtin;
+    } as;
+} tb_value;
+
+#define TB_UNSET_INIT { TB_UNSET, { 0 } }
+
+struct tb_array {
+    size_t len, cap;
+    tb_value *items;
+};
+
+/* Keys keep their insertion order. */
+struct tb_map {
+    size_t len, cap;
+    const tb_str **keys;
+    tb_value *values;
+};
+
+struct tb_instance {
+    const tb_type *type;
+    tb_value *fields;
+};
+
+typedef struct {
+    const char *name;
+    size_t arity;
+    /* `env` holds the variables of the call the function was defined in. */
+    tb_value (*code)(void *env, tb_value *args);
+} tb_function;
+
+struct tb_closure {
+    const tb_function *function;
+    void *env;
+};
+
+struct tb_builtin {
+    const char *name;
+    tb_value (*code)(size_t argc, tb_value *args, tb_span at);
+};
+
+enum { TB_ADD, TB_SUB, TB_MUL, TB_DIV, TB_EQ, TB_NE, TB_LT, TB_GT, TB_LE, TB_GE };
+
+static const char *const tb_op_names[] = {
+    "Add", "Sub", "Mul", "Div", "Equals", "NotEquals", "SmallerThan", "GreaterThan", "SmallerEquals", "GreaterEquals"
+};
+
+static int tb_depth;
+static int tb_argc;
+static char **tb_argv;
+
+/* Text being built up, e.g. the line `print` writes. */
+typedef struct {
+    char *data;
+    size_t len, cap;
+} tb_buf;
+
+TB_FN void tb_fail(tb_span at, const char *format, ...) {
+    va_list args;
+    fflush(stdout);
+    fprintf(stderr, "error: %d:%d: ", at.line, at.column);
+    va_start(args, format);
+    vfprintf(stderr, format, args);
+    va_end(args);
+    fputc('\n', stderr);
+    exit(1);
+}
+
+/* The header of each block in the arena, which links it to the others. The
+ * other members only align what follows it for any value. */
+typedef union tb_block {
+    struct {
+        union tb_block *prev, *next;
+    } link;
+    long double ld;
+    int64_t i;
+    void *p;
+} tb_block;
+
+/* The arena, a circular list of every block not yet freed. */
+static tb_block tb_heap = { { &tb_heap, &tb_heap } };
+
+TB_FN void *tb_adopt(tb_block *b) {
+    if (!b) {
+        fputs("error: out of memory\n", stderr);
+        exit(1);
+    }
+    b->link.prev = &tb_heap;
+    b->link.next = tb_heap.link.next;
+    tb_heap.link.next->link.prev = b;
+    tb_heap.link.next = b;
+    return b + 1;
+}
+
+TB_FN tb_block *tb_unlink(void *p) {
+    tb_block *b = (tb_block *)p - 1;
+    b->link.prev->link.next = b->link.next;
+    b->link.next->link.prev = b->link.prev;
+    return b;
+}
+
+TB_FN void *tb_alloc(size_t size) {
+    return tb_adopt(malloc(sizeof(tb_block) + size));
+}
+
+TB_FN void *tb_grow(void *p, size_t *cap, size_t needed, size_t item) {
+    tb_block *b;
+    if (needed <= *cap) {
+        return p;
+    }
+    *cap = needed > 2 * *cap ? needed : 2 * *cap;
+    if (!p) {
+        return tb_alloc(*cap * item);
+    }
+    b = tb_unlink(p);
+    return tb_adopt(realloc(b, sizeof *b + *cap * item));
+}
+
+/* Frees a scratch block before `tb_reset` would. */
+TB_FN void tb_free(void *p) {
+    if (p) {
+        free(tb_unlink(p));
+    }
+}
+
+TB_FN void tb_put(tb_buf *buf, const char *data, size_t len) {
+    buf->data = tb_grow(buf->data, &buf->cap, buf->len + len + 1, 1);
+    memcpy(buf->data + buf->len, data, len);
+    buf->len += len;
+    buf->data[buf->len] = '\0';
+}
+
+TB_FN void tb_puts(tb_buf *buf, const char *s) {
+    tb_put(buf, s, strlen(s));
+}
+
+TB_FN void tb_printf(tb_buf *buf, const char *format, ...) {
+    char small[64];
+    va_list args;
+    int n;
+    va_start(args, format);
+    n = vsnprintf(small, sizeof small, format, args);
+    va_end(args);
+    if (n < (int)sizeof small) {
+        tb_put(buf, small, (size_t)n);
+        return;
+    }
+    buf->data = tb_grow(buf->data, &buf->cap, buf->len + (size_t)n + 1, 1);
+    va_start(args, format);
+    vsnprintf(buf->data + buf->len, (size_t)n + 1, format, args);
+    va_end(args);
+    buf->len += (size_t)n;
+}
+
+/* Values */
+
+TB_FN tb_value tb_nil(void) {
+    tb_value v;
+    v.tag = TB_NIL;
+    v.as.i = 0;
+    return v;
+}
+
+TB_FN tb_value tb_int(int64_t i) {
+    tb_value v;
+    v.tag = TB_INT;
+    v.as.i = i;
+    return v;
+}
+
+TB_FN tb_value tb_float(double f) {
+    tb_value v;
+    v.tag = TB_FLOAT;
+    v.as.f = f;
+    return v;
+}
+
+TB_FN tb_value tb_bool(int b) {
+    tb_value v;
+    v.tag = TB_BOOL;
+    v.as.b = b != 0;
+    return v;
+}
+
+TB_FN tb_value tb_string(const tb_str *s) {
+    tb_value v;
+    v.tag = TB_STR;
+    v.as.s = s;
+    return v;
+}
+
+TB_FN tb_value tb_new_string(const char *data, size_t len) {
+    tb_str *s = tb_alloc(sizeof *s);
+    char *copy = tb_alloc(len + 1);
+    memcpy(copy, data, len);
+    copy[len] = '\0';
+    s->len = len;
+    s->data = copy;
+    return tb_string(s);
+}
+
+/* Takes over the text of `buf`. */
+TB_FN tb_value tb_buf_string(tb_buf *buf) {
+    tb_str *s = tb_alloc(sizeof *s);
+    s->len = buf->len;
+    s->data = buf->data ? buf->data : "";
+    return tb_string(s);
+}
+
+TB_FN tb_value tb_array_value(tb_array *a) {
+    tb_value v;
+    v.tag = TB_ARRAY;
+    v.as.a = a;
+    return v;
+}
+
+TB_FN tb_value tb_array_of(size_t len, const tb_value *items) {
+    tb_array *a = tb_alloc(sizeof *a);
+    a->len = a->cap = len;
+    a->items = tb_alloc(len * sizeof *a->items);
+    if (len) {
+        memcpy(a->items, items, len * sizeof *items);
+    }
+    return tb_array_value(a);
+}
+
+TB_FN tb_value tb_map_new(void) {
+    tb_value v;
+    v.tag = TB_MAP;
+    v.as.m = tb_alloc(sizeof *v.as.m);
+    v.as.m->len = v.as.m->cap = 0;
+    v.as.m->keys = NULL;
+    v.as.m->values = NULL;
+    return v;
+}
+
+TB_FN tb_value tb_struct_type(const tb_type *t) {
+    tb_value v;
+    v.tag = TB_STRUCT_TYPE;
+    v.as.t = t;
+    return v;
+}
+
+TB_FN tb_value tb_make_function(const tb_function *function, void *env) {
+    tb_closure *closure = tb_alloc(sizeof *closure);
+    tb_value v;
+    closure->function = function;
+    closure->env = env;
+    v.tag = TB_FUNCTION;
+    v.as.fn = closure;
+    return v;
+}
+
+TB_FN tb_value tb_builtin_value(const tb_builtin *builtin) {
+    tb_value v;
+    v.tag = TB_BUILTIN;
+    v.as.builtin = builtin;
+    return v;
+}
+
+TB_FN const char *tb_type_name(tb_value v) {
+    switch (v.tag) {
+    case TB_UNSET:
+    case TB_NIL: return "nil";
+    case TB_INT: return "int";
+    case TB_FLOAT: return "float";
+    case TB_BOOL: return "bool";
+    case TB_STR: return "string";
+    case TB_ARRAY: return "array";
+    case TB_MAP: return "map";
+    case TB_STRUCT: return "struct";
+    default: return "function";
+    }
+}
+
+TB_FN int tb_truthy(tb_value v) {
+    switch (v.tag) {
+    case TB_UNSET:
+    case TB_NIL: return 0;
+    case TB_INT: return v.as.i != 0;
+    case TB_FLOAT: return v.as.f != 0.0;
+    case TB_BOOL: return v.as.b;
+    case TB_STR: return v.as.s->len != 0;
+    case TB_ARRAY: return v.as.a->len != 0;
+    case TB_MAP: return v.as.m->len != 0;
+    default: return 1;
+    }
+}
+
+/* Variables */
+
+TB_FN tb_value tb_global(const tb_value *slot, const char *name, tb_span at) {
+    if (slot->tag == TB_UNSET) {
+        tb_fail(at, "undefined variable `%s`", name);
+    }
+    return *slot;
+}
+
+/* A global being called. */
+TB_FN tb_value tb_global_function(const tb_value *slot, const char *name, tb_span at) {
+    if (slot->tag == TB_UNSET) {
+        tb_fail(at, "call to undefined function `%s`", name);
+    }
+    return *slot;
+}
+
+/* Printing */
+
+TB_FN void tb_show_float(tb_buf *buf, double f) {
+    char digits[32];
+    char scientific[40];
+    int precision, exponent, count, i;
+    const char *p;
+    if (f != f) {
+        tb_puts(buf, "NaN");
+        return;
+    }
+    if (f == HUGE_VAL || f == -HUGE_VAL) {
+        tb_puts(buf, f < 0 ? "-inf" : "inf");
+        return;
+    }
+    if (signbit(f)) {
+        tb_puts(buf, "-");
+        f = -f;
+    }
+    if (f == 0.0) {
+        tb_puts(buf, "0.0");
+        return;
+    }
+    /* The fewest digits reading back as `f` */
+    for (precision = 1; precision < 17; precision++) {
+        sprintf(scientific, "%.*e", precision - 1, f);
+        if (strtod(scientific, NULL) == f) {
+            break;
+        }
+    }
+    sprintf(scientific, "%.*e", precision - 1, f);
+    count = 0;
+    for (p = scientific; *p != 'e'; p++) {
+        if (*p >= '0' && *p <= '9') {
+            digits[count++] = *p;
+        }
+    }
+    while (count > 1 && digits[count - 1] == '0') {
+        count--;
+    }
+    exponent = atoi(p + 1);
+    if (exponent < -4 || exponent >= 16) {
+        tb_put(buf, digits, 1);
+        if (count > 1) {
+            tb_puts(buf, ".");
+            tb_put(buf, digits + 1, (size_t)count - 1);
+        }
+        tb_printf(buf, "e%d", exponent);
+    } else if (exponent < 0) {
+        tb_puts(buf, "0.");
+        for (i = -1; i > exponent; i--) {
+            tb_puts(buf, "0");
+        }
+        tb_put(buf, digits, (size_t)count);
+    } else if (count > exponent + 1) {
+        tb_put(buf, digits, (size_t)exponent + 1);
+        tb_puts(buf, ".");
+        tb_put(buf, digits + exponent + 1, (size_t)(count - exponent - 1));
+    } else {
+        tb_put(buf, digits, (size_t)count);
+        for (i = count; i <= exponent; i++) {
+            tb_puts(buf, "0");
+        }
+        tb_puts(buf, ".0");
+    }
+}
+
+/* A string in double quotes with escapes, as nested values print. */
+TB_FN void tb_show_quoted(tb_buf *buf, const tb_str *s) {
+    size_t i;
+    tb_puts(buf, "\"");
+    for (i = 0; i < s->len; i++) {
+        unsigned char c = (unsigned char)s->data[i];
+        switch (c) {
+        case '"': tb_puts(buf, "\\\""); break;
+        case '\\': tb_puts(buf, "\\\\"); break;
+        case '\n': tb_puts(buf, "\\n"); break;
+        case '\r': tb_puts(buf, "\\r"); break;
+        case '\t': tb_puts(buf, "\\t"); break;
+        case '\0': tb_puts(buf, "\\0"); break;
+        default:
+            if (c < 0x20 || c == 0x7f) {
+                tb_printf(buf, "\\u{%x}", c);
+            } else {
+                tb_put(buf, (const char *)&c, 1);
+            }
+        }
+    }
+    tb_puts(buf, "\"");
+}
+
+TB_FN void tb_show(tb_buf *buf, tb_value v, int nested) {
+    size_t i;
+    switch (v.tag) {
+    case TB_UNSET:
+    case TB_NIL: tb_puts(buf, "nil"); break;
+    case TB_INT: tb_printf(buf, "%" PRId64, v.as.i); break;
+    case TB_FLOAT: tb_show_float(buf, v.as.f); break;
+    case TB_BOOL: tb_puts(buf, v.as.b ? "true" : "false"); break;
+    case TB_STR:
+        if (nested) {
+            tb_show_quoted(buf, v.as.s);
+        } else {
+            tb_put(buf, v.as.s->data, v.as.s->len);
+        }
+        break;
+    case TB_ARRAY:
+        tb_puts(buf, "[");
+        for (i = 0; i < v.as.a->len; i++) {
+            if (i > 0) {
+                tb_puts(buf, ", ");
+            }
+            tb_show(buf, v.as.a->items[i], 1);
+        }
+        tb_puts(buf, "]");
+        break;
+    case TB_MAP:
+        tb_puts(buf, "{");
+        for (i = 0; i < v.as.m->len; i++) {
+            if (i > 0) {
+                tb_puts(buf, ", ");
+            }
+            tb_show_quoted(buf, v.as.m->keys[i]);
+            tb_puts(buf, ": ");
+            tb_show(buf, v.as.m->values[i], 1);
+        }
+        tb_puts(buf, "}");
+        break;
+    case TB_STRUCT:
+        tb_printf(buf, "%s {", v.as.o->type->name);
+        for (i = 0; i < v.as.o->type->count; i++) {
+            tb_printf(buf, "%s%s: ", i > 0 ? ", " : " ", v.as.o->type->fields[i]);
+            tb_show(buf, v.as.o->fields[i], 1);
+        }
+        tb_puts(buf, v.as.o->type->count ? " }" : "}");
+        break;
+    case TB_STRUCT_TYPE: tb_printf(buf, "<struct %s>", v.as.t->name); break;
+    case TB_FUNCTION: tb_printf(buf, "<fn %s>", v.as.fn->function->name); break;
+    case TB_BUILTIN: tb_printf(buf, "<builtin %s>", v.as.builtin->name); break;
+    }
+}
+
+TB_FN void tb_print(size_t count, const tb_value *values) {
+    tb_buf line = { NULL, 0, 0 };
+    size_t i;
+    for (i = 0; i < count; i++) {
+        if (i > 0) {
+            tb_puts(&line, " ");
+        }
+        tb_show(&line, values[i], 0);
+    }
+    tb_puts(&line, "\n");
+    fwrite(line.data, 1, line.len, stdout);
+    tb_free(line.data);
+}
+
+/* Operators */
+
+TB_FN int tb_str_equal(const tb_str *a, const tb_str *b) {
+    return a->len == b->len && memcmp(a->data, b->data, a->len) == 0;
+}
+
+TB_FN int tb_str_compare(const tb_str *a, const tb_str *b) {
+    size_t n = a->len < b->len ? a->len : b->len;
+    int c = memcmp(a->data, b->data, n);
+    if (c != 0) {
+        return c;
+    }
+    return a->len < b->len ? -1 : a->len > b->len;
+}
+
+TB_FN long tb_map_find(const tb_map *m, const tb_str *key) {
+    size_t i;
+    for (i = 0; i < m->len; i++) {
+        if (tb_str_equal(m->keys[i], key)) {
+            return (long)i;
+        }
+    }
+    return -1;
+}
+
+TB_FN int tb_equal(tb_value a, tb_value b) {
+    size_t i;
+    if (a.tag != b.tag) {
+        return 0;
+    }
+    switch (a.tag) {
+    case TB_UNSET:
+    case TB_NIL: return 1;
+    case TB_INT: return a.as.i == b.as.i;
+    case TB_FLOAT: return a.as.f == b.as.f;
+    case TB_BOOL: return a.as.b == b.as.b;
+    case TB_STR: return tb_str_equal(a.as.s, b.as.s);
+    case TB_ARRAY:
+        if (a.as.a->len != b.as.a->len) {
+            return 0;
+        }
+        for (i = 0; i < a.as.a->len; i++) {
+            if (!tb_equal(a.as.a->items[i], b.as.a->items[i])) {
+                return 0;
+            }
+        }
+        return 1;
+    case TB_MAP:
+        if (a.as.m->len != b.as.m->len) {
+            return 0;
+        }
+        for (i = 0; i < a.as.m->len; i++) {
+            long j = tb_map_find(b.as.m, a.as.m->keys[i]);
+            if (j < 0 || !tb_equal(a.as.m->values[i], b.as.m->values[j])) {
+                return 0;
+            }
+        }
+        return 1;
+    case TB_STRUCT:
+        if (a.as.o->type != b.as.o->type) {
+            return 0;
+        }
+        for (i = 0; i < a.as.o->type->count; i++) {
+            if (!tb_equal(a.as.o->fields[i], b.as.o->fields[i])) {
+                return 0;
+            }
+        }
+        return 1;
+    case TB_STRUCT_TYPE: return a.as.t == b.as.t;
+    case TB_FUNCTION: return a.as.fn == b.as.fn;
+    case TB_BUILTIN: return a.as.builtin == b.as.builtin;
+    }
+    return 0;
+}
+
+TB_FN tb_value tb_int_op(int op, int64_t l, int64_t r, tb_span at) {
+    switch (op) {
+    case TB_ADD:
+        if ((r > 0 && l > INT64_MAX - r) || (r < 0 && l < INT64_MIN - r)) {
+            tb_fail(at, "integer overflow");
+        }
+        return tb_int(l + r);
+    case TB_SUB:
+        if ((r < 0 && l > INT64_MAX + r) || (r > 0 && l < INT64_MIN + r)) {
+            tb_fail(at, "integer overflow");
+        }
+        return tb_int(l - r);
+    case TB_MUL:
+        if (l != 0 && r != 0) {
+            int overflows = l > 0 ? (r > 0 ? l > INT64_MAX / r : r < INT64_MIN / l)
+                                  : (r > 0 ? l < INT64_MIN / r : l < INT64_MAX / r);
+            if (overflows) {
+                tb_fail(at, "integer overflow");
+            }
+        }
+        return tb_int(l * r);
+    default:
+        if (r == 0) {
+            tb_fail(at, "division by zero");
+        }
+        if (l == INT64_MIN && r == -1) {
+            tb_fail(at, "integer overflow");
+        }
+        return tb_int(l / r);
+    }
+}
+
+TB_FN tb_value tb_binary(int op, tb_value l, tb_value r, tb_span at) {
+    if (l.tag == TB_INT && r.tag == TB_FLOAT) {
+        l = tb_float((double)l.as.i);
+    } else if (l.tag == TB_FLOAT && r.tag == TB_INT) {
+        r = tb_float((double)r.as.i);
+    }
+    if (op == TB_EQ) {
+        return tb_bool(tb_equal(l, r));
+    }
+    if (op == TB_NE) {
+        return tb_bool(!tb_equal(l, r));
+    }
+    if (l.tag == TB_INT && r.tag == TB_INT) {
+        switch (op) {
+        case TB_LT: return tb_bool(l.as.i < r.as.i);
+        case TB_GT: return tb_bool(l.as.i > r.as.i);
+        case TB_LE: return tb_bool(l.as.i <= r.as.i);
+        case TB_GE: return tb_bool(l.as.i >= r.as.i);
+        default: return tb_int_op(op, l.as.i, r.as.i, at);
+        }
+    }
+    if (l.tag == TB_FLOAT && r.tag == TB_FLOAT) {
+        switch (op) {
+        case TB_ADD: return tb_float(l.as.f + r.as.f);
+        case TB_SUB: return tb_float(l.as.f - r.as.f);
+        case TB_MUL: return tb_float(l.as.f * r.as.f);
+        case TB_DIV: return tb_float(l.as.f / r.as.f);
+        case TB_LT: return tb_bool(l.as.f < r.as.f);
+        case TB_GT: return tb_bool(l.as.f > r.as.f);
+        case TB_LE: return tb_bool(l.as.f <= r.as.f);
+        case TB_GE: return tb_bool(l.as.f >= r.as.f);
+        }
+    }
+    if (l.tag == TB_STR && r.tag == TB_STR) {
+        switch (op) {
+        case TB_ADD: {
+            tb_buf buf = { NULL, 0, 0 };
+            tb_put(&buf, l.as.s->data, l.as.s->len);
+            tb_put(&buf, r.as.s->data, r.as.s->len);
+            return tb_buf_string(&buf);
+        }
+        case TB_LT: return tb_bool(tb_str_compare(l.as.s, r.as.s) < 0);
+        case TB_GT: return tb_bool(tb_str_compare(l.as.s, r.as.s) > 0);
+        case TB_LE: return tb_bool(tb_str_compare(l.as.s, r.as.s) <= 0);
+        case TB_GE: return tb_bool(tb_str_compare(l.as.s, r.as.s) >= 0);
+        }
+    }
+    tb_fail(at, "type mismatch: unsupported operands for %s: %s and %s", tb_op_names[op], tb_type_name(l), tb_type_name(r));
+    return tb_nil();
+}
+
+TB_FN tb_value tb_neg(tb_value v, tb_span at) {
+    switch (v.tag) {
+    case TB_INT:
+        if (v.as.i == INT64_MIN) {
+            tb_fail(at, "integer overflow");
+        }
+        return tb_int(-v.as.i);
+    case TB_FLOAT: return tb_float(-v.as.f);
+    default:
+        tb_fail(at, "type mismatch: cannot negate %s", tb_type_name(v));
+        return tb_nil();
+    }
+}
+
+TB_FN tb_value tb_not(tb_value v) {
+    return tb_bool(!tb_truthy(v));
+}
+
+/* Arrays, maps and structs */
+
+TB_FN size_t tb_array_index(tb_value index, size_t len, tb_span at) {
+    if (index.tag != TB_INT) {
+        tb_fail(at, "type mismatch: array index must be an int, found %s", tb_type_name(index));
+    }
+    if (index.as.i < 0 || (uint64_t)index.as.i >= len) {
+        tb_fail(at, "index %" PRId64 " is out of bounds for an array of length %lu", index.as.i, (unsigned long)len);
+    }
+    return (size_t)index.as.i;
+}
+
+TB_FN const tb_str *tb_key(tb_value key, tb_span at) {
+    if (key.tag != TB_STR) {
+        tb_fail(at, "type mismatch: map keys must be strings, found %s", tb_type_name(key));
+    }
+    return key.as.s;
+}
+
+TB_FN void tb_missing_key(const tb_str *key, tb_span at) {
+    tb_buf quoted = { NULL, 0, 0 };
+    tb_show_quoted(&quoted, key);
+    tb_fail(at, "map has no key %s", quoted.data);
+}
+
+TB_FN void tb_map_put(tb_map *m, const tb_str *key, tb_value value) {
+    long i = tb_map_find(m, key);
+    size_t cap;
+    if (i >= 0) {
+        m->values[i] = value;
+        return;
+    }
+    cap = m->cap;
+    m->keys = tb_grow(m->keys, &cap, m->len + 1, sizeof *m->keys);
+    m->values = tb_grow(m->values, &m->cap, m->len + 1, sizeof *m->values);
+    m->keys[m->len] = key;
+    m->values[m->len] = value;
+    m->len++;
+}
+
+TB_FN tb_value tb_index(tb_value target, tb_value index, tb_span target_at, tb_span index_at) {
+    if (target.tag == TB_ARRAY) {
+        return target.as.a->items[tb_array_index(index, target.as.a->len, index_at)];
+    }
+    if (target.tag == TB_MAP) {
+        const tb_str *key = tb_key(index, index_at);
+        long i = tb_map_find(target.as.m, key);
+        if (i < 0) {
+            tb_missing_key(key, index_at);
+        }
+        return target.as.m->values[i];
+    }
+    tb_fail(target_at, "type mismatch: cannot index into %s", tb_type_name(target));
+    return tb_nil();
+}
+
+TB_FN void tb_set_index(tb_value target, tb_value index, tb_value value, tb_span target_at, tb_span index_at) {
+    if (target.tag == TB_ARRAY) {
+        target.as.a->items[tb_array_index(index, target.as.a->len, index_at)] = value;
+    } else if (target.tag == TB_MAP) {
+        tb_map_put(target.as.m, tb_key(index, index_at), value);
+    } else {
+        tb_fail(target_at, "type mismatch: cannot index into %s", tb_type_name(target));
+    }
+}
+
+TB_FN size_t tb_field_index(tb_value target, const char *field, tb_span target_at, tb_span at) {
+    size_t i;
+    if (target.tag != TB_STRUCT) {
+        tb_fail(target_at, "type mismatch: %s has no fields", tb_type_name(target));
+    }
+    for (i = 0; i < target.as.o->type->count; i++) {
+        if (strcmp(target.as.o->type->fields[i], field) == 0) {
+            return i;
+        }
+    }
+    tb_fail(at, "struct `%s` has no field `%s`", target.as.o->type->name, field);
+    return 0;
+}
+
+TB_FN tb_value tb_field(tb_value target, const char *field, tb_span target_at, tb_span at) {
+    size_t i = tb_field_index(target, field, target_at, at);
+    return target.as.o->fields[i];
+}
+
+TB_FN void tb_set_field(tb_value target, const char *field, tb_value value, tb_span target_at) {
+    size_t i = tb_field_index(target, field, target_at, target_at);
+    target.as.o->fields[i] = value;
+}
+
+/* Calls */
+
+TB_FN void tb_expect_args(const char *name, size_t expected, size_t found, tb_span at) {
+    if (expected != found) {
+        tb_fail(at, "`%s` expects %lu argument%s, but %lu %s given", name, (unsigned long)expected,
+                expected == 1 ? "" : "s", (unsigned long)found, found == 1 ? "was" : "were");
+    }
+}
+
+TB_FN tb_value tb_call(tb_value callee, size_t argc, tb_value *args, tb_span at, tb_span callee_at) {
+    switch (callee.tag) {
+    case TB_FUNCTION: {
+        const tb_function *function = callee.as.fn->function;
+        tb_value result;
+        tb_expect_args(function->name, function->arity, argc, at);
+        if (tb_depth >= TB_MAX_DEPTH) {
+            tb_fail(at, "stack overflow in script: more than %d nested calls", TB_MAX_DEPTH);
+        }
+        tb_depth++;
+        result = function->code(callee.as.fn->env, args);
+        tb_depth--;
+        return result;
+    }
+    case TB_BUILTIN: return callee.as.builtin->code(argc, args, at);
+    case TB_STRUCT_TYPE: {
+        tb_value v;
+        tb_expect_args(callee.as.t->name, callee.as.t->count, argc, at);
+        v.tag = TB_STRUCT;
+        v.as.o = tb_alloc(sizeof *v.as.o);
+        v.as.o->type = callee.as.t;
+        v.as.o->fields = tb_alloc(argc * sizeof *args);
+        if (argc) {
+            memcpy(v.as.o->fields, args, argc * sizeof *args);
+        }
+        return v;
+    }
+    default:
+        tb_fail(callee_at, "value of type %s is not callable", tb_type_name(callee));
+        return tb_nil();
+    }
+}
+
+/* Classic `gosub` */
+
+static int tb_returns[TB_MAX_DEPTH];
+static int tb_pending_returns;
+
+TB_FN void tb_gosub(int resume, tb_span at) {
+    if (tb_pending_returns >= TB_MAX_DEPTH) {
+        tb_fail(at, "stack overflow in script: more than %d nested calls", TB_MAX_DEPTH);
+    }
+    tb_returns[tb_pending_returns++] = resume;
+}
+
+TB_FN int tb_sub_return(tb_span at) {
+    if (tb_pending_returns == 0) {
+        tb_fail(at, "`return` without a pending `gosub`");
+    }
+    return tb_returns[--tb_pending_returns];
+}
+
+/* Builtins */
+
+TB_FN double tb_number(const char *name, tb_value v, tb_span at) {
+    if (v.tag == TB_INT) {
+        return (double)v.as.i;
+    }
+    if (v.tag != TB_FLOAT) {
+        tb_fail(at, "type mismatch: `%s` expects a number, found %s", name, tb_type_name(v));
+    }
+    return v.as.f;
+}
+
+TB_FN const tb_str *tb_expect_string(const char *name, tb_value v, tb_span at) {
+    if (v.tag != TB_STR) {
+        tb_fail(at, "type mismatch: `%s` expects a string, found %s", name, tb_type_name(v));
+    }
+    return v.as.s;
+}
+
+TB_FN tb_array *tb_expect_array(const char *name, tb_value v, tb_span at) {
+    if (v.tag != TB_ARRAY) {
+        tb_fail(at, "type mismatch: `%s` expects an array, found %s", name, tb_type_name(v));
+    }
+    return v.as.a;
+}
+
+TB_FN tb_map *tb_expect_map(const char *name, tb_value v, tb_span at) {
+    if (v.tag != TB_MAP) {
+        tb_fail(at, "type mismatch: `%s` expects a map, found %s", name, tb_type_name(v));
+    }
+    return v.as.m;
+}
+
+TB_FN int64_t tb_expect_int(const char *name, tb_value v, tb_span at) {
+    if (v.tag != TB_INT) {
+        tb_fail(at, "type mismatch: `%s` expects an int, found %s", name, tb_type_name(v));
+    }
+    return v.as.i;
+}
+
+TB_FN void tb_invalid(tb_span at, const char *message) {
+    tb_fail(at, "invalid argument: %s", message);
+}
+
+/* Parses an int the way Rust's `str::parse::<i64>` does. */
+TB_FN int tb_parse_int(const char *s, size_t len, int64_t *out) {
+    size_t i = 0;
+    int negative = 0;
+    uint64_t n = 0, limit;
+    if (i < len && (s[i] == '+' || s[i] == '-')) {
+        negative = s[i] == '-';
+        i++;
+    }
+    if (i == len) {
+        return 0;
+    }
+    limit = negative ? (uint64_t)INT64_MAX + 1 : (uint64_t)INT64_MAX;
+    for (; i < len; i++) {
+        if (s[i] < '0' || s[i] > '9') {
+            return 0;
+        }
+        if (n > (limit - (uint64_t)(s[i] - '0')) / 10) {
+            return 0;
+        }
+        n = n * 10 + (uint64_t)(s[i] - '0');
+    }
+    *out = negative ? (int64_t)(0 - n) : (int64_t)n;
+    return 1;
+}
+
+/* The length of the UTF-8 sequence starting with `c`. */
+TB_FN size_t tb_utf8_width(unsigned char c) {
+    return c < 0x80 ? 1 : c < 0xe0 ? 2 : c < 0xf0 ? 3 : 4;
+}
+
+TB_FN size_t tb_char_count(const tb_str *s) {
+    size_t i, count = 0;
+    for (i = 0; i < s->len; i++) {
+        count += ((unsigned char)s->data[i] & 0xc0) != 0x80;
+    }
+    return count;
+}
+
+/* Whether the character at `s` is Unicode white space; sets its width. */
+TB_FN int tb_space_at(const unsigned char *s, size_t left, size_t *width) {
+    uint32_t c = s[0];
+    size_t n = tb_utf8_width(s[0]);
+    if (n > left) {
+        n = left;
+    }
+    *width = n;
+    if (n == 2) {
+        c = ((uint32_t)(s[0] & 0x1f) << 6) | (s[1] & 0x3f);
+    } else if (n == 3) {
+        c = ((uint32_t)(s[0] & 0x0f) << 12) | ((uint32_t)(s[1] & 0x3f) << 6) | (s[2] & 0x3f);
+    } else if (n == 4) {
+        return 0;
+    }
+    return (c >= 0x09 && c <= 0x0d) || c == 0x20 || c == 0x85 || c == 0xa0 || c == 0x1680 ||
+           (c >= 0x2000 && c <= 0x200a) || c == 0x2028 || c == 0x2029 || c == 0x202f || c == 0x205f || c == 0x3000;
+}
+
+/* Narrows `[*start, *end)` to leave out white space at either end. */
+TB_FN void tb_trim_range(const char *data, size_t *start, size_t *end) {
+    size_t i, width, last;
+    while (*start < *end && tb_space_at((const unsigned char *)data + *start, *end - *start, &width)) {
+        *start += width;
+    }
+    /* UTF-8 is easier to read forward, so find the end of the last other
+     * character that way */
+    last = *start;
+    for (i = *start; i < *end; i += width) {
+        if (!tb_space_at((const unsigned char *)data + i, *end - i, &width)) {
+            last = i + width;
+        }
+    }
+    *end = last;
+}
+
+/* Where `needle` first occurs in `s` at or after `from`, or -1. */
+TB_FN long tb_find(const tb_str *s, const tb_str *needle, size_t from) {
+    size_t i;
+    if (needle->len > s->len) {
+        return -1;
+    }
+    for (i = from; i + needle->len <= s->len; i++) {
+        if (memcmp(s->data + i, needle->data, needle->len) == 0) {
+            return (long)i;
+        }
+    }
+    return -1;
+}
+
+static tb_buf tb_input_line;
+
+TB_FN tb_value tb_builtin_input(size_t argc, tb_value *args, tb_span at) {
+    int c;
+    int64_t n;
+    size_t start, end;
+    if (argc > 1) {
+        tb_expect_args("input", 1, argc, at);
+    }
+    if (argc == 1) {
+        tb_buf prompt = { NULL, 0, 0 };
+        tb_show(&prompt, args[0], 0);
+        fwrite(prompt.data, 1, prompt.len, stdout);
+        tb_free(prompt.data);
+    }
+    fflush(stdout);
+    tb_input_line.len = 0;
+    while ((c = getchar()) != EOF && c != '\n') {
+        char ch = (char)c;
+        tb_put(&tb_input_line, &ch, 1);
+    }
+    if (c == EOF && tb_input_line.len == 0) {
+        return tb_nil();
+    }
+    while (tb_input_line.len > 0 && tb_input_line.data[tb_input_line.len - 1] == '\r') {
+        tb_input_line.len--;
+    }
+    start = 0;
+    end = tb_input_line.len;
+    tb_trim_range(tb_input_line.data, &start, &end);
+    if (tb_parse_int(tb_input_line.data + start, end - start, &n)) {
+        return tb_int(n);
+    }
+    return tb_new_string(tb_input_line.len ? tb_input_line.data : "", tb_input_line.len);
+}
+
+TB_FN tb_value tb_builtin_abs(size_t argc, tb_value *args, tb_span at) {
+    tb_expect_args("abs", 1, argc, at);
+    if (args[0].tag == TB_INT) {
+        if (args[0].as.i == INT64_MIN) {
+            tb_fail(at, "integer overflow");
+        }
+        return tb_int(args[0].as.i < 0 ? -args[0].as.i : args[0].as.i);
+    }
+    return tb_float(fabs(tb_number("abs", args[0], at)));
+}
+
+TB_FN tb_value tb_extremum(const char *name, size_t argc, tb_value *args, tb_span at, int smallest) {
+    size_t i, best = 0;
+    double best_number;
+    if (argc < 2) {
+        tb_expect_args(name, 2, argc, at);
+    }
+    best_number = tb_number(name, args[0], at);
+    for (i = 1; i < argc; i++) {
+        double n = tb_number(name, args[i], at);
+        if (smallest ? n < best_number : n > best_number) {
+            best = i;
+            best_number = n;
+        }
+    }
+    return args[best];
+}
+
+TB_FN tb_value tb_builtin_min(size_t argc, tb_value *args, tb_span at) {
+    return tb_extremum("min", argc, args, at, 1);
+}
+
+TB_FN tb_value tb_builtin_max(size_t argc, tb_value *args, tb_span at) {
+    return tb_extremum("max", argc, args, at, 0);
+}
+
+TB_FN tb_value tb_builtin_sqrt(size_t argc, tb_value *args, tb_span at) {
+    double n;
+    tb_expect_args("sqrt", 1, argc, at);
+    n = tb_number("sqrt", args[0], at);
+    if (n < 0.0) {
+        tb_invalid(at, "cannot take the square root of a negative number");
+    }
+    return tb_float(sqrt(n));
+}
+
+TB_FN tb_value tb_builtin_pow(size_t argc, tb_value *args, tb_span at) {
+    tb_expect_args("pow", 2, argc, at);
+    if (args[0].tag == TB_INT && args[1].tag == TB_INT && args[1].as.i >= 0) {
+        int64_t base = args[0].as.i, exp = args[1].as.i, result = 1;
+        if (exp > UINT32_MAX) {
+            tb_fail(at, "integer overflow");
+        }
+        /* Squaring only overflows if the result would */
+        while (exp > 0) {
+            if (exp & 1) {
+                result = tb_int_op(TB_MUL, result, base, at).as.i;
+            }
+            exp >>= 1;
+            if (exp > 0) {
+                base = tb_int_op(TB_MUL, base, base, at).as.i;
+            }
+        }
+        return tb_int(result);
+    }
+    return tb_float(pow(tb_number("pow", args[0], at), tb_number("pow", args[1], at)));
+}
+
+TB_FN tb_value tb_rounding(const char *name, size_t argc, tb_value *args, tb_span at, double (*op)(double)) {
+    double n;
+    tb_expect_args(name, 1, argc, at);
+    if (args[0].tag == TB_INT) {
+        return args[0];
+    }
+    n = op(tb_number(name, args[0], at));
+    if (!(n >= -9223372036854775808.0 && n < 9223372036854775808.0)) {
+        tb_fail(at, "integer overflow");
+    }
+    return tb_int((int64_t)n);
+}
+
+TB_FN tb_value tb_builtin_floor(size_t argc, tb_value *args, tb_span at) {
+    return tb_rounding("floor", argc, args, at, floor);
+}
+
+TB_FN tb_value tb_builtin_ceil(size_t argc, tb_value *args, tb_span at) {
+    return tb_rounding("ceil", argc, args, at, ceil);
+}
+
+TB_FN tb_value tb_builtin_round(size_t argc, tb_value *args, tb_span at) {
+    return tb_rounding("round", argc, args, at, round);
+}
+
+TB_FN tb_value tb_builtin_len(size_t argc, tb_value *args, tb_span at) {
+    tb_expect_args("len", 1, argc, at);
+    if (args[0].tag == TB_ARRAY) {
+        return tb_int((int64_t)args[0].as.a->len);
+    }
+    if (args[0].tag == TB_MAP) {
+        return tb_int((int64_t)args[0].as.m->len);
+    }
+    return tb_int((int64_t)tb_char_count(tb_expect_string("len", args[0], at)));
+}
+
+/* The byte offset of character `n` of `s`. */
+TB_FN size_t tb_char_offset(const tb_str *s, size_t n) {
+    size_t i = 0;
+    for (; n > 0 && i < s->len; n--) {
+        i += tb_utf8_width((unsigned char)s->data[i]);
+    }
+    return i < s->len ? i : s->len;
+}
+
+TB_FN tb_value tb_builtin_substr(size_t argc, tb_value *args, tb_span at) {
+    const tb_str *s;
+    size_t length, start, end;
+    int64_t first;
+    if (argc != 2) {
+        tb_expect_args("substr", 3, argc, at);
+    }
+    s = tb_expect_string("substr", args[0], at);
+    length = tb_char_count(s);
+    first = tb_expect_int("substr", args[1], at);
+    if (first < 0 || (uint64_t)first > length) {
+        tb_fail(at, "invalid argument: substring start %" PRId64 " is out of range for a string of length %lu", first,
+                (unsigned long)length);
+    }
+    start = tb_char_offset(s, (size_t)first);
+    end = s->len;
+    if (argc == 3) {
+        int64_t count = tb_expect_int("substr", args[2], at);
+        if (count < 0) {
+            tb_fail(at, "invalid argument: substring length %" PRId64 " is negative", count);
+        }
+        if ((uint64_t)count < length - (size_t)first) {
+            end = tb_char_offset(s, (size_t)first + (size_t)count);
+        }
+    }
+    return tb_new_string(s->data + start, end - start);
+}
+
+TB_FN tb_value tb_builtin_trim(size_t argc, tb_value *args, tb_span at) {
+    const tb_str *s;
+    size_t start = 0, end;
+    tb_expect_args("trim", 1, argc, at);
+    s = tb_expect_string("trim", args[0], at);
+    end = s->len;
+    tb_trim_range(s->data, &start, &end);
+    return tb_new_string(s->data + start, end - start);
+}
+
+TB_FN tb_value tb_builtin_split(size_t argc, tb_value *args, tb_span at) {
+    const tb_str *s, *separator;
+    tb_array *pieces;
+    size_t cap = 0, from = 0;
+    tb_expect_args("split", 2, argc, at);
+    s = tb_expect_string("split", args[0], at);
+    separator = tb_expect_string("split", args[1], at);
+    pieces = tb_alloc(sizeof *pieces);
+    pieces->len = 0;
+    pieces->items = NULL;
+    if (separator->len == 0) {
+        while (from < s->len) {
+            size_t width = tb_utf8_width((unsigned char)s->data[from]);
+            pieces->items = tb_grow(pieces->items, &cap, pieces->len + 1, sizeof *pieces->items);
+            pieces->items[pieces->len++] = tb_new_string(s->data + from, width);
+            from += width;
+        }
+    } else {
+        for (;;) {
+            long found = tb_find(s, separator, from);
+            size_t end = found < 0 ? s->len : (size_t)found;
+            pieces->items = tb_grow(pieces->items, &cap, pieces->len + 1, sizeof *pieces->items);
+            pieces->items[pieces->len++] = tb_new_string(s->data + from, end - from);
+            if (found < 0) {
+                break;
+            }
+            from = end + separator->len;
+        }
+    }
+    pieces->cap = cap;
+    return tb_array_value(pieces);
+}
+
+TB_FN tb_value tb_builtin_contains(size_t argc, tb_value *args, tb_span at) {
+    const tb_str *s;
+    tb_expect_args("contains", 2, argc, at);
+    s = tb_expect_string("contains", args[0], at);
+    return tb_bool(tb_find(s, tb_expect_string("contains", args[1], at), 0) >= 0);
+}
+
+TB_FN tb_value tb_builtin_replace(size_t argc, tb_value *args, tb_span at) {
+    const tb_str *s, *from, *to;
+    tb_buf buf = { NULL, 0, 0 };
+    size_t i = 0;
+    tb_expect_args("replace", 3, argc, at);
+    s = tb_expect_string("replace", args[0], at);
+    from = tb_expect_string("replace", args[1], at);
+    to = tb_expect_string("replace", args[2], at);
+    if (from->len == 0) {
+        /* An empty pattern matches around every character */
+        tb_put(&buf, to->data, to->len);
+        while (i < s->len) {
+            size_t width = tb_utf8_width((unsigned char)s->data[i]);
+            tb_put(&buf, s->data + i, width);
+            tb_put(&buf, to->data, to->len);
+            i += width;
+        }
+        return tb_buf_string(&buf);
+    }
+    for (;;) {
+        long found = tb_find(s, from, i);
+        size_t end = found < 0 ? s->len : (size_t)found;
+        tb_put(&buf, s->data + i, end - i);
+        if (found < 0) {
+            break;
+        }
+        tb_put(&buf, to->data, to->len);
+        i = end + from->len;
+    }
+    return tb_buf_string(&buf);
+}
+
+TB_FN tb_value tb_builtin_format(size_t argc, tb_value *args, tb_span at) {
+    const tb_str *pattern;
+    tb_buf buf = { NULL, 0, 0 };
+    size_t i, next = 1;
+    if (argc == 0) {
+        tb_expect_args("format", 1, argc, at);
+    }
+    pattern = tb_expect_string("format", args[0], at);
+    for (i = 0; i < pattern->len; i++) {
+        char c = pattern->data[i];
+        char following = i + 1 < pattern->len ? pattern->data[i + 1] : '\0';
+        if ((c == '{' && following == '{') || (c == '}' && following == '}')) {
+            tb_put(&buf, &c, 1);
+            i++;
+        } else if (c == '{' && following == '}') {
+            if (next == argc) {
+                tb_invalid(at, "more `{}` than arguments in format string");
+            }
+            tb_show(&buf, args[next++], 0);
+            i++;
+        } else if (c == '{' || c == '}') {
+            tb_invalid(at, "unmatched brace in format string, use `{{` or `}}` for a literal one");
+        } else {
+            tb_put(&buf, &c, 1);
+        }
+    }
+    if (next != argc) {
+        tb_invalid(at, "more arguments than `{}` in format string");
+    }
+    return tb_buf_string(&buf);
+}
+
+TB_FN tb_value tb_builtin_push(size_t argc, tb_value *args, tb_span at) {
+    tb_array *a;
+    tb_expect_args("push", 2, argc, at);
+    a = tb_expect_array("push", args[0], at);
+    a->items = tb_grow(a->items, &a->cap, a->len + 1, sizeof *a->items);
+    a->items[a->len++] = args[1];
+    return tb_nil();
+}
+
+TB_FN tb_value tb_builtin_pop(size_t argc, tb_value *args, tb_span at) {
+    tb_array *a;
+    tb_expect_args("pop", 1, argc, at);
+    a = tb_expect_array("pop", args[0], at);
+    if (a->len == 0) {
+        tb_invalid(at, "cannot pop from an empty array");
+    }
+    return a->items[--a->len];
+}
+
+TB_FN tb_value tb_builtin_insert(size_t argc, tb_value *args, tb_span at) {
+    tb_array *a;
+    size_t index;
+    tb_expect_args("insert", 3, argc, at);
+    a = tb_expect_array("insert", args[0], at);
+    if (args[1].tag == TB_INT && args[1].as.i >= 0 && (uint64_t)args[1].as.i == a->len) {
+        index = a->len;
+    } else {
+        index = tb_array_index(args[1], a->len, at);
+    }
+    a->items = tb_grow(a->items, &a->cap, a->len + 1, sizeof *a->items);
+    memmove(a->items + index + 1, a->items + index, (a->len - index) * sizeof *a->items);
+    a->items[index] = args[2];
+    a->len++;
+    return tb_nil();
+}
+
+TB_FN tb_value tb_builtin_remove(size_t argc, tb_value *args, tb_span at) {
+    tb_array *a;
+    size_t index;
+    tb_value removed;
+    tb_expect_args("remove", 2, argc, at);
+    if (args[0].tag == TB_MAP) {
+        tb_map *m = args[0].as.m;
+        const tb_str *key = tb_key(args[1], at);
+        long i = tb_map_find(m, key);
+        if (i < 0) {
+            tb_missing_key(key, at);
+        }
+        removed = m->values[i];
+        memmove(m->keys + i, m->keys + i + 1, (m->len - (size_t)i - 1) * sizeof *m->keys);
+        memmove(m->values + i, m->values + i + 1, (m->len - (size_t)i - 1) * sizeof *m->values);
+        m->len--;
+        return removed;
+    }
+    a = tb_expect_array("remove", args[0], at);
+    index = tb_array_index(args[1], a->len, at);
+    removed = a->items[index];
+    memmove(a->items + index, a->items + index + 1, (a->len - index - 1) * sizeof *a->items);
+    a->len--;
+    return removed;
+}
+
+/* The first pair of elements `sort` couldn't compare. */
+static const char *tb_sort_mismatch[2];
+static tb_span tb_sort_at;
+
+/* Orders two elements for `sort`, failing like the interpreter does for
+ * ones that can't be compared. */
+TB_FN int tb_sort_less(tb_value a, tb_value b) {
+    if (a.tag == TB_STR && b.tag == TB_STR) {
+        return tb_str_compare(a.as.s, b.as.s) < 0;
+    }
+    if (a.tag == TB_INT && b.tag == TB_INT) {
+        return a.as.i < b.as.i;
+    }
+    if ((a.tag == TB_INT || a.tag == TB_FLOAT) && (b.tag == TB_INT || b.tag == TB_FLOAT)) {
+        return tb_number("sort", a, tb_sort_at) < tb_number("sort", b, tb_sort_at);
+    }
+    if (!tb_sort_mismatch[0]) {
+        tb_sort_mismatch[0] = tb_type_name(a);
+        tb_sort_mismatch[1] = tb_type_name(b);
+    }
+    return 0;
+}
+
+TB_FN void tb_merge_sort(tb_value *items, tb_value *scratch, size_t len) {
+    size_t i, j, k, half;
+    if (len <= 20) {
+        for (i = 1; i < len; i++) {
+            tb_value item = items[i];
+            for (j = i; j > 0 && tb_sort_less(item, items[j - 1]); j--) {
+                items[j] = items[j - 1];
+            }
+            items[j] = item;
+        }
+        return;
+    }
+    half = len / 2;
+    tb_merge_sort(items, scratch, half);
+    tb_merge_sort(items + half, scratch, len - half);
+    memcpy(scratch, items, half * sizeof *items);
+    for (i = 0, j = half, k = 0; i < half; k++) {
+        if (j < len && tb_sort_less(items[j], scratch[i])) {
+            items[k] = items[j++];
+        } else {
+            items[k] = scratch[i++];
+        }
+    }
+}
+
+TB_FN tb_value tb_builtin_sort(size_t argc, tb_value *args, tb_span at) {
+    tb_array *a;
+    tb_value *scratch;
+    tb_expect_args("sort", 1, argc, at);
+    a = tb_expect_array("sort", args[0], at);
+    tb_sort_mismatch[0] = NULL;
+    tb_sort_at = at;
+    scratch = tb_alloc(a->len * sizeof *scratch);
+    tb_merge_sort(a->items, scratch, a->len);
+    tb_free(scratch);
+    if (tb_sort_mismatch[0]) {
+        tb_fail(at, "type mismatch: cannot compare %s with %s", tb_sort_mismatch[0], tb_sort_mismatch[1]);
+    }
+    return tb_nil();
+}
+
+TB_FN tb_value tb_builtin_reverse(size_t argc, tb_value *args, tb_span at) {
+    tb_array *a;
+    size_t i;
+    tb_expect_args("reverse", 1, argc, at);
+    a = tb_expect_array("reverse", args[0], at);
+    for (i = 0; i < a->len / 2; i++) {
+        tb_value item = a->items[i];
+        a->items[i] = a->items[a->len - 1 - i];
+        a->items[a->len - 1 - i] = item;
+    }
+    return tb_nil();
+}
+
+TB_FN tb_value tb_builtin_keys(size_t argc, tb_value *args, tb_span at) {
+    tb_map *m;
+    tb_value keys;
+    size_t i;
+    tb_expect_args("keys", 1, argc, at);
+    m = tb_expect_map("keys", args[0], at);
+    keys = tb_array_of(m->len, m->values);
+    for (i = 0; i < m->len; i++) {
+        keys.as.a->items[i] = tb_string(m->keys[i]);
+    }
+    return keys;
+}
+
+TB_FN tb_value tb_builtin_values(size_t argc, tb_value *args, tb_span at) {
+    tb_map *m;
+    tb_expect_args("values", 1, argc, at);
+    m = tb_expect_map("values", args[0], at);
+    return tb_array_of(m->len, m->values);
+}
+
+TB_FN tb_value tb_builtin_has(size_t argc, tb_value *args, tb_span at) {
+    tb_map *m;
+    tb_expect_args("has", 2, argc, at);
+    m = tb_expect_map("has", args[0], at);
+    return tb_bool(tb_map_find(m, tb_key(args[1], at)) >= 0);
+}
+
+/* SplitMix64, as in the interpreter, so seeded programs draw the same
+ * numbers. */
+static uint64_t tb_rng_state;
+static int tb_rng_seeded;
+
+TB_FN uint64_t tb_next_u64(void) {
+    uint64_t z;
+    if (!tb_rng_seeded) {
+        tb_rng_state = (uint64_t)time(NULL) * 1000000007u;
+        tb_rng_seeded = 1;
+    }
+    tb_rng_state += 0x9e3779b97f4a7c15u;
+    z = tb_rng_state;
+    z = (z ^ (z >> 30)) * 0xbf58476d1ce4e5b9u;
+    z = (z ^ (z >> 27)) * 0x94d049bb133111ebu;
+    return z ^ (z >> 31);
+}
+
+TB_FN tb_value tb_builtin_rnd(size_t argc, tb_value *args, tb_span at) {
+    (void)args;
+    tb_expect_args("rnd", 0, argc, at);
+    return tb_float((double)(tb_next_u64() >> 11) / 9007199254740992.0);
+}
+
+TB_FN tb_value tb_builtin_random(size_t argc, tb_value *args, tb_span at) {
+    int64_t min, max;
+    uint64_t span, bound, zone, n;
+    tb_expect_args("random", 2, argc, at);
+    min = tb_expect_int("random", args[0], at);
+    max = tb_expect_int("random", args[1], at);
+    if (min > max) {
+        tb_fail(at, "invalid argument: random range is empty: %" PRId64 " is greater than %" PRId64, min, max);
+    }
+    span = (uint64_t)max - (uint64_t)min;
+    if (span == UINT64_MAX) {
+        return tb_int((int64_t)tb_next_u64());
+    }
+    bound = span + 1;
+    zone = UINT64_MAX - (UINT64_MAX % bound) - 1;
+    do {
+        n = tb_next_u64();
+    } while (n > zone);
+    return tb_int((int64_t)((uint64_t)min + n % bound));
+}
+
+TB_FN tb_value tb_builtin_seed(size_t argc, tb_value *args, tb_span at) {
+    tb_expect_args("seed", 1, argc, at);
+    tb_rng_state = (uint64_t)tb_expect_int("seed", args[0], at);
+    tb_rng_seeded = 1;
+    return tb_nil();
+}
+
+TB_FN tb_value tb_builtin_getenv(size_t argc, tb_value *args, tb_span at) {
+    const char *value;
+    tb_expect_args("getenv", 1, argc, at);
+    value = getenv(tb_expect_string("getenv", args[0], at)->data);
+    return value ? tb_new_string(value, strlen(value)) : tb_nil();
+}
+
+/* The program's command line arguments, without its name. */
+TB_FN tb_value tb_builtin_args(size_t argc, tb_value *args, tb_span at) {
+    tb_value values = tb_array_of(0, NULL);
+    int i;
+    (void)args;
+    tb_expect_args("args", 0, argc, at);
+    for (i = 1; i < tb_argc; i++) {
+        tb_value arg = tb_new_string(tb_argv[i], strlen(tb_argv[i]));
+        values.as.a->items = tb_grow(values.as.a->items, &values.as.a->cap, values.as.a->len + 1, sizeof arg);
+        values.as.a->items[values.as.a->len++] = arg;
+    }
+    return values;
+}
+
+TB_FN tb_value tb_builtin_exit(size_t argc, tb_value *args, tb_span at) {
+    int64_t code = 0;
+    if (argc > 1) {
+        tb_expect_args("exit", 1, argc, at);
+    }
+    if (argc == 1) {
+        code = tb_expect_int("exit", args[0], at);
+    }
+    if (code < INT32_MIN || code > INT32_MAX) {
+        tb_fail(at, "invalid argument: exit code %" PRId64 " is out of range", code);
+    }
+    fflush(stdout);
+    exit((int)code);
+    return tb_nil();
+}
+
+/* Frees every heap value at once, for a program that outlives its values,
+ * e.g. one embedded as a function called again and again. Nothing made
+ * before, including what globals hold, may be used afterwards. */
+TB_FN void tb_reset(void) {
+    tb_block *b = tb_heap.link.next;
+    while (b != &tb_heap) {
+        tb_block *next = b->link.next;
+        free(b);
+        b = next;
+    }
+    tb_heap.link.prev = tb_heap.link.next = &tb_heap;
+    tb_input_line.data = NULL;
+    tb_input_line.len = tb_input_line.cap = 0;
+}
@@ -0,0 +1,838 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+use crate::ast::{Expr, ExprKind, FunctionDecl, Program, Stmt, StmtKind};
+use crate::builtins;
+use crate::const_eval::const_eval;
+use crate::diagnostic::Label;
+use crate::interpreter::RuntimeError;
+use crate::lexer::Span;
+
+/// Something in a program that is legal but probably not what its author
+/// meant.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Warning {
+    /// Identifies the kind of warning for tools, e.g. `unused-variable`.
+    pub code: &'static str,
+    pub message: String,
+    pub span: Span,
+    /// Other places that explain the warning.
+    pub labels: Vec<Label>,
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}: warning: {}", self.span.line, self.span.column, self.message)
+    }
+}
+
+/// Looks for likely mistakes without running the program.
+///
+/// This flags functions assigning to a name that is also a global without
+/// declaring it `global`, which creates a local and leaves the global
+/// unchanged, and reads of variables that can't have been assigned yet on
+/// any path to them, which would fail with an undefined variable error, or
+/// only on some paths, e.g. by one branch of an `if`. It also reports
+/// variables that are assigned but never read and named functions that are
+/// never used, where names starting with `_` are exempt, code that can never
+/// run, and constant arithmetic that overflows.
+pub fn check(program: &Program) -> Vec<Warning> {
+    check_with_globals(program, &[])
+}
+
+/// Like [`check`], for programs run by a host that defines the globals
+/// `host_globals`, e.g. with
+/// [`Interpreter::register_fn`](crate::interpreter::Interpreter::register_fn).
+pub fn check_with_globals(program: &Program, host_globals: &[&str]) -> Vec<Warning> {
+    let mut checker = Checker { globals: HashSet::new(), warnings: Vec::new() };
+    assigned_names(&program.statements, &mut checker.globals);
+    for statement in &program.statements {
+        checker.statement(statement, None);
+    }
+
+    let mut builtins = HashMap::new();
+    builtins::register(&mut builtins);
+    let defined: HashSet<String> = builtins.into_keys().chain(host_globals.iter().map(|name| name.to_string())).collect();
+    let mut visible = defined.clone();
+    visible.extend(checker.globals.iter().cloned());
+    declared_globals(&program.statements, &mut visible);
+    let mut assigned = Assigned::default();
+    // `goto` can reach the statements of classic programs in any order
+    for name in if program.lines.is_empty() { &defined } else { &visible } {
+        assigned.insert(name);
+    }
+    let mut definitions = Definitions { visible, breaks: Vec::new(), warnings: Vec::new() };
+    definitions.block(&program.statements, &mut assigned);
+
+    let mut warnings = checker.warnings;
+    warnings.extend(definitions.warnings);
+    let mut reads = HashSet::new();
+    read_names(&program.statements, &mut reads);
+    unused(&program.statements, &[], &reads, &mut warnings);
+    for statement in &program.statements {
+        visit_statement(statement, &mut |expr| constant_overflow(expr, &mut warnings));
+    }
+    if program.lines.is_empty() {
+        reachable_block(&program.statements, &mut warnings);
+    } else {
+        // Every top-level statement has a line number `goto` can jump to
+        for statement in &program.statements {
+            reachable_statement(statement, &mut warnings);
+        }
+    }
+    warnings.sort_by_key(|warning| (warning.span.line, warning.span.column));
+    warnings
+}
+
+/// Adds the names `statements` may assign to `names`, looking into nested
+/// blocks but not into functions.
+pub(crate) fn assigned_names(statements: &[Stmt], names: &mut HashSet<String>) {
+    for statement in statements {
+        match &statement.kind {
+            StmtKind::Assign { name, .. } | StmtKind::Import(name) => {
+                names.insert(name.clone());
+            },
+            StmtKind::Function(decl) => {
+                names.insert(decl.name.clone());
+            },
+            StmtKind::Struct(decl) => {
+                names.insert(decl.name.clone());
+            },
+            StmtKind::If { then_branch, else_branch, .. } => {
+                assigned_names(then_branch, names);
+                assigned_names(else_branch.as_deref().unwrap_or_default(), names);
+            },
+            StmtKind::While { body, .. } => assigned_names(body, names),
+            _ => {},
+        }
+    }
+}
+
+/// Adds the names functions in `statements` declare `global` to `names`.
+fn declared_globals(statements: &[Stmt], names: &mut HashSet<String>) {
+    for statement in statements {
+        match &statement.kind {
+            StmtKind::Global(declared) => names.extend(declared.iter().cloned()),
+            StmtKind::If { then_branch, else_branch, .. } => {
+                declared_globals(then_branch, names);
+                declared_globals(else_branch.as_deref().unwrap_or_default(), names);
+            },
+            StmtKind::While { body, .. } => declared_globals(body, names),
+            StmtKind::Function(decl) => declared_globals(&decl.body, names),
+            _ => {},
+        }
+    }
+}
+
+/// Names the current function may assign without shadowing a global.
+struct Scope {
+    locals: HashSet<String>,
+    declared_global: HashSet<String>,
+}
+
+struct Checker {
+    globals: HashSet<String>,
+    warnings: Vec<Warning>,
+}
+
+impl Checker {
+    fn statement(&mut self, statement: &Stmt, mut scope: Option<&mut Scope>) {
+        match &statement.kind {
+            StmtKind::Assign { name, value, declaration, .. } => {
+                self.expression(value, scope.as_deref_mut());
+                // `let` shadows the global on purpose
+                if let Some(scope) = scope.as_deref_mut().filter(|_| *declaration) {
+                    scope.declared_global.remove(name);
+                    scope.locals.insert(name.clone());
+                }
+                self.assignment(name, statement.span, scope);
+            },
+            StmtKind::SetField { target, value, .. } => {
+                self.expression(target, scope.as_deref_mut());
+                self.expression(value, scope);
+            },
+            StmtKind::Struct(decl) => self.assignment(&decl.name, statement.span, scope),
+            StmtKind::Import(name) => self.assignment(name, statement.span, scope),
+            StmtKind::SetIndex { target, index, value } => {
+                self.expression(target, scope.as_deref_mut());
+                self.expression(index, scope.as_deref_mut());
+                self.expression(value, scope);
+            },
+            StmtKind::If { condition, then_branch, else_branch } => {
+                self.expression(condition, scope.as_deref_mut());
+                for statement in then_branch.iter().chain(else_branch.iter().flatten()) {
+                    self.statement(statement, scope.as_deref_mut());
+                }
+            },
+            StmtKind::While { condition, body } => {
+                self.expression(condition, scope.as_deref_mut());
+                for statement in body {
+                    self.statement(statement, scope.as_deref_mut());
+                }
+            },
+            StmtKind::Function(decl) => {
+                self.assignment(&decl.name, statement.span, scope);
+                self.function(decl);
+            },
+            StmtKind::Global(names) => {
+                if let Some(scope) = scope {
+                    scope.declared_global.extend(names.iter().cloned());
+                }
+            },
+            StmtKind::Print(args) => {
+                for arg in args {
+                    self.expression(arg, scope.as_deref_mut());
+                }
+            },
+            StmtKind::Expr(expr) | StmtKind::Return(Some(expr)) => self.expression(expr, scope),
+            StmtKind::Return(None)
+            | StmtKind::Break
+            | StmtKind::Continue
+            | StmtKind::Goto(_)
+            | StmtKind::Gosub(_)
+            | StmtKind::SubReturn => {},
+        }
+    }
+
+    fn assignment(&mut self, name: &str, span: Span, scope: Option<&mut Scope>) {
+        let Some(scope) = scope else {
+            return;
+        };
+        if self.globals.contains(name) && !scope.locals.contains(name) && !scope.declared_global.contains(name) {
+            self.warnings.push(Warning {
+                code: "shadowed-global",
+                message: format!(
+                    "assigning to `{}` creates a local that shadows the global `{}`; add `global {}` to change the global",
+                    name, name, name
+                ),
+                span,
+                labels: Vec::new(),
+            });
+        }
+        // Only warn about the first assignment
+        scope.locals.insert(name.to_string());
+    }
+
+    fn function(&mut self, decl: &FunctionDecl) {
+        let mut scope = Scope { locals: decl.params.iter().cloned().collect(), declared_global: HashSet::new() };
+        for statement in &decl.body {
+            self.statement(statement, Some(&mut scope));
+        }
+    }
+
+    fn expression(&mut self, expr: &Expr, mut scope: Option<&mut Scope>) {
+        match &expr.kind {
+            ExprKind::Function(decl) => self.function(decl),
+            ExprKind::Array(items) => {
+                for item in items {
+                    self.expression(item, scope.as_deref_mut());
+                }
+            },
+            ExprKind::Map(entries) => {
+                for (key, value) in entries {
+                    self.expression(key, scope.as_deref_mut());
+                    self.expression(value, scope.as_deref_mut());
+                }
+            },
+            ExprKind::Index { target: left, index: right } | ExprKind::Binary { left, right, .. } => {
+                self.expression(left, scope.as_deref_mut());
+                self.expression(right, scope);
+            },
+            ExprKind::Unary { operand, .. } | ExprKind::Field { target: operand, .. } => self.expression(operand, scope),
+            ExprKind::Call { callee, args } => {
+                self.expression(callee, scope.as_deref_mut());
+                for arg in args {
+                    self.expression(arg, scope.as_deref_mut());
+                }
+            },
+            ExprKind::Number(_) | ExprKind::Float(_) | ExprKind::Str(_) | ExprKind::Variable(_) => {},
+        }
+    }
+}
+
+/// Adds every variable `statements` read to `names`, including in nested
+/// functions.
+fn read_names(statements: &[Stmt], names: &mut HashSet<String>) {
+    for statement in statements {
+        visit_statement(statement, &mut |expr| {
+            if let ExprKind::Variable(name) = &expr.kind {
+                names.insert(name.clone());
+            }
+        });
+    }
+}
+
+/// Calls `visit` on every expression in `statement`, including those in
+/// nested blocks and function bodies.
+pub(crate) fn visit_statement<'a>(statement: &'a Stmt, visit: &mut impl FnMut(&'a Expr)) {
+    match &statement.kind {
+        StmtKind::Assign { value, .. } => visit_expression(value, true, visit),
+        StmtKind::SetIndex { target, index, value } => {
+            visit_expression(target, true, visit);
+            visit_expression(index, true, visit);
+            visit_expression(value, true, visit);
+        },
+        StmtKind::SetField { target, value, .. } => {
+            visit_expression(target, true, visit);
+            visit_expression(value, true, visit);
+        },
+        StmtKind::If { condition, then_branch, else_branch } => {
+            visit_expression(condition, true, visit);
+            for statement in then_branch.iter().chain(else_branch.iter().flatten()) {
+                visit_statement(statement, visit);
+            }
+        },
+        StmtKind::While { condition, body } => {
+            visit_expression(condition, true, visit);
+            for statement in body {
+                visit_statement(statement, visit);
+            }
+        },
+        StmtKind::Function(decl) => {
+            for statement in &decl.body {
+                visit_statement(statement, visit);
+            }
+        },
+        StmtKind::Print(args) => {
+            for arg in args {
+                visit_expression(arg, true, visit);
+            }
+        },
+        StmtKind::Expr(expr) | StmtKind::Return(Some(expr)) => visit_expression(expr, true, visit),
+        StmtKind::Struct(_)
+        | StmtKind::Global(_)
+        | StmtKind::Import(_)
+        | StmtKind::Return(None)
+        | StmtKind::Break
+        | StmtKind::Continue
+        | StmtKind::Goto(_)
+        | StmtKind::Gosub(_)
+        | StmtKind::SubReturn => {},
+    }
+}
+
+/// Calls `visit` on `expr` and everything nested in it, looking into the
+/// bodies of anonymous functions if `into_functions` is set.
+pub(crate) fn visit_expression<'a>(expr: &'a Expr, into_functions: bool, visit: &mut impl FnMut(&'a Expr)) {
+    visit(expr);
+    match &expr.kind {
+        ExprKind::Array(items) => {
+            for item in items {
+                visit_expression(item, into_functions, visit);
+            }
+        },
+        ExprKind::Map(entries) => {
+            for (key, value) in entries {
+                visit_expression(key, into_functions, visit);
+                visit_expression(value, into_functions, visit);
+            }
+        },
+        ExprKind::Index { target: left, index: right } | ExprKind::Binary { left, right, .. } => {
+            visit_expression(left, into_functions, visit);
+            visit_expression(right, into_functions, visit);
+        },
+        ExprKind::Unary { operand, .. } | ExprKind::Field { target: operand, .. } => visit_expression(operand, into_functions, visit),
+        ExprKind::Call { callee, args } => {
+            visit_expression(callee, into_functions, visit);
+            for arg in args {
+                visit_expression(arg, into_functions, visit);
+            }
+        },
+        ExprKind::Function(decl) if into_functions => {
+            for statement in &decl.body {
+                visit_statement(statement, visit);
+            }
+        },
+        ExprKind::Function(_) | ExprKind::Number(_) | ExprKind::Float(_) | ExprKind::Str(_) | ExprKind::Variable(_) => {},
+    }
+}
+
+/// Warns about what one scope defines but never uses: the program's top
+/// level, whose globals count as used if anything reads them, or a function
+/// body, whose locals count as used if it or its closures read them. Then
+/// does the same for the functions nested in it.
+fn unused(body: &[Stmt], params: &[String], reads: &HashSet<String>, warnings: &mut Vec<Warning>) {
+    let mut declared_global = HashSet::new();
+    let mut assignments = Vec::new();
+    let mut functions = Vec::new();
+    scope_definitions(body, &mut declared_global, &mut assignments, &mut functions);
+
+    let mut reported = HashSet::new();
+    for (name, span) in assignments {
+        let exempt = name.starts_with('_') || params.contains(&name) || declared_global.contains(&name);
+        if !exempt && !reads.contains(&name) && reported.insert(name.clone()) {
+            warnings.push(Warning {
+                code: "unused-variable",
+                message: format!("`{}` is assigned but never read", name),
+                span,
+                labels: Vec::new(),
+            });
+        }
+    }
+    for (decl, named) in functions {
+        if named && !decl.name.starts_with('_') && !reads.contains(&decl.name) {
+            warnings.push(Warning {
+                code: "unused-function",
+                message: format!("function `{}` is never used", decl.name),
+                span: decl.span,
+                labels: Vec::new(),
+            });
+        }
+        let mut inner_reads = HashSet::new();
+        read_names(&decl.body, &mut inner_reads);
+        unused(&decl.body, &decl.params, &inner_reads, warnings);
+    }
+}
+
+/// Collects what `statements` define in their own scope: names declared
+/// `global`, assigned variables with where they are first assigned, and
+/// functions, flagged whether they are named `fn` statements.
+pub(crate) fn scope_definitions<'a>(
+    statements: &'a [Stmt],
+    declared_global: &mut HashSet<String>,
+    assignments: &mut Vec<(String, Span)>,
+    functions: &mut Vec<(&'a FunctionDecl, bool)>,
+) {
+    for statement in statements {
+        match &statement.kind {
+            StmtKind::Assign { name, .. } => assignments.push((name.clone(), statement.span)),
+            StmtKind::Global(names) => declared_global.extend(names.iter().cloned()),
+            StmtKind::Function(decl) => functions.push((decl, true)),
+            StmtKind::If { then_branch, else_branch, .. } => {
+                scope_definitions(then_branch, declared_global, assignments, functions);
+                let else_branch = else_branch.as_deref().unwrap_or_default();
+                scope_definitions(else_branch, declared_global, assignments, functions);
+            },
+            StmtKind::While { body, .. } => scope_definitions(body, declared_global, assignments, functions),
+            _ => {},
+        }
+        // Anonymous functions are scopes of their own
+        if !matches!(statement.kind, StmtKind::Function(_)) {
+            shallow_statement_exprs(statement, &mut |expr| {
+                if let ExprKind::Function(decl) = &expr.kind {
+                    functions.push((decl, false));
+                }
+            });
+        }
+    }
+}
+
+/// Calls `visit` on the expressions directly in `statement`, without
+/// looking into nested blocks or function bodies.
+pub(crate) fn shallow_statement_exprs<'a>(statement: &'a Stmt, visit: &mut impl FnMut(&'a Expr)) {
+    let exprs: Vec<&Expr> = match &statement.kind {
+        StmtKind::Assign { value, .. } => vec![value],
+        StmtKind::SetIndex { target, index, value } => vec![target, index, value],
+        StmtKind::SetField { target, value, .. } => vec![target, value],
+        StmtKind::If { condition, .. } | StmtKind::While { condition, .. } => vec![condition],
+        StmtKind::Print(args) => args.iter().collect(),
+        StmtKind::Expr(expr) | StmtKind::Return(Some(expr)) => vec![expr],
+        _ => Vec::new(),
+    };
+    for expr in exprs {
+        visit_expression(expr, false, visit);
+    }
+}
+
+/// Warns about the first statement in `statements` that follows one always
+/// jumping away, and returns whether the block always jumps away.
+fn reachable_block(statements: &[Stmt], warnings: &mut Vec<Warning>) -> bool {
+    for (i, statement) in statements.iter().enumerate() {
+        if reachable_statement(statement, warnings) {
+            if let Some(next) = statements.get(i + 1) {
+                warnings.push(Warning {
+                    code: "unreachable-code",
+                    message: format!("unreachable statement after `{}`", jump_keyword(statement)),
+                    span: next.span,
+                    labels: vec![Label::new(statement.span, "any code after this is unreachable")],
+                });
+            }
+            return true;
+        }
+    }
+    false
+}
+
+/// Checks the blocks nested in `statement` and returns whether it always
+/// jumps away: returns, breaks, continues or goes to another line.
+fn reachable_statement(statement: &Stmt, warnings: &mut Vec<Warning>) -> bool {
+    shallow_statement_exprs(statement, &mut |expr| {
+        if let ExprKind::Function(decl) = &expr.kind {
+            reachable_block(&decl.body, warnings);
+        }
+    });
+    let never_runs =
+        |message: &str, span: Span| Warning { code: "unreachable-code", message: message.to_string(), span, labels: Vec::new() };
+    match &statement.kind {
+        StmtKind::Return(_) | StmtKind::Break | StmtKind::Continue | StmtKind::Goto(_) | StmtKind::SubReturn => true,
+        StmtKind::If { condition, then_branch, else_branch } => match constant_truth(condition) {
+            Some(false) => {
+                warnings.push(never_runs("condition is always false, so this branch never runs", condition.span));
+                else_branch.as_ref().is_some_and(|branch| reachable_block(branch, warnings))
+            },
+            Some(true) => {
+                if else_branch.is_some() {
+                    warnings.push(never_runs("condition is always true, so the `else` branch never runs", condition.span));
+                }
+                reachable_block(then_branch, warnings)
+            },
+            None => {
+                let then_jumps = reachable_block(then_branch, warnings);
+                let else_jumps = else_branch.as_ref().is_some_and(|branch| reachable_block(branch, warnings));
+                then_jumps && else_jumps
+            },
+        },
+        StmtKind::While { condition, body } => {
+            if constant_truth(condition) == Some(false) {
+                warnings.push(never_runs("loop condition is always false, so the body never runs", condition.span));
+            } else {
+                reachable_block(body, warnings);
+            }
+            false
+        },
+        StmtKind::Function(decl) => {
+            reachable_block(&decl.body, warnings);
+            false
+        },
+        _ => false,
+    }
+}
+
+/// How to refer to the statement a block ends with in warnings.
+fn jump_keyword(statement: &Stmt) -> &'static str {
+    match statement.kind {
+        StmtKind::Return(_) | StmtKind::SubReturn => "return",
+        StmtKind::Break => "break",
+        StmtKind::Continue => "continue",
+        StmtKind::Goto(_) => "goto",
+        // An `if` whose branches all jump away
+        _ => "if",
+    }
+}
+
+/// The truthiness of `condition` if it is constant and evaluates without
+/// errors.
+fn constant_truth(condition: &Expr) -> Option<bool> {
+    const_eval(condition)?.ok().map(|value| value.is_truthy())
+}
+
+/// What has been assigned at a point in the program.
+#[derive(Debug, Clone, Default)]
+struct Assigned {
+    /// Names some path to this point assigns.
+    some: HashSet<String>,
+    /// Names every path to this point assigns.
+    all: HashSet<String>,
+    /// Whether every path has jumped away before this point, so it can't run.
+    jumped: bool,
+}
+
+impl Assigned {
+    fn insert(&mut self, name: &str) {
+        self.some.insert(name.to_string());
+        self.all.insert(name.to_string());
+    }
+
+    /// Joins the paths of `other` into `self`.
+    fn merge(&mut self, other: Assigned) {
+        self.some.extend(other.some);
+        match (self.jumped, other.jumped) {
+            (false, false) => self.all.retain(|name| other.all.contains(name)),
+            (true, false) => self.all = other.all,
+            _ => {},
+        }
+        self.jumped &= other.jumped;
+    }
+}
+
+/// Warns if `expr` is an operator whose operands are constant but whose
+/// result doesn't fit an int, which fails or wraps around at run time
+/// depending on the [`OverflowMode`](crate::interpreter::OverflowMode).
+fn constant_overflow(expr: &Expr, warnings: &mut Vec<Warning>) {
+    if !matches!(expr.kind, ExprKind::Binary { .. } | ExprKind::Unary { .. }) {
+        return;
+    }
+    // Only report the operator that overflows, not the ones containing it
+    if let Some(Err(RuntimeError::IntegerOverflow { span })) = const_eval(expr) {
+        if span == expr.span {
+            warnings.push(Warning {
+                code: "constant-overflow",
+                message: "constant expression overflows a 64-bit int".to_string(),
+                span,
+                labels: Vec::new(),
+            });
+        }
+    }
+}
+
+/// Finds reads of variables no path assigns before them, and of variables
+/// only some paths assign.
+struct Definitions {
+    /// What function bodies defined at the current position may read: the
+    /// globals and everything the enclosing functions assign.
+    visible: HashSet<String>,
+    /// For each loop around the current position, what the `break`s leaving
+    /// it have assigned.
+    breaks: Vec<Option<Assigned>>,
+    warnings: Vec<Warning>,
+}
+
+impl Definitions {
+    /// Walks `statements` in order, adding what they assign to `assigned`.
+    fn block(&mut self, statements: &[Stmt], assigned: &mut Assigned) {
+        for statement in statements {
+            self.statement(statement, assigned);
+        }
+    }
+
+    fn statement(&mut self, statement: &Stmt, assigned: &mut Assigned) {
+        match &statement.kind {
+            StmtKind::Assign { name, value, .. } => {
+                self.expression(value, assigned);
+                assigned.insert(name);
+            },
+            StmtKind::SetIndex { target, index, value } => {
+                self.expression(target, assigned);
+                self.expression(index, assigned);
+                self.expression(value, assigned);
+            },
+            StmtKind::SetField { target, value, .. } => {
+                self.expression(target, assigned);
+                self.expression(value, assigned);
+            },
+            StmtKind::If { condition, then_branch, else_branch } => {
+                self.expression(condition, assigned);
+                let mut then_assigned = assigned.clone();
+                self.block(then_branch, &mut then_assigned);
+                self.block(else_branch.as_deref().unwrap_or_default(), assigned);
+                assigned.merge(then_assigned);
+            },
+            StmtKind::While { condition, body } => {
+                // Later iterations see what earlier ones assigned, but the
+                // first may not run at all
+                assigned_names(body, &mut assigned.some);
+                self.expression(condition, assigned);
+                self.breaks.push(None);
+                self.block(body, &mut assigned.clone());
+                let breaks = self.breaks.pop().flatten();
+                if constant_truth(condition) == Some(true) {
+                    // Only a `break` leaves the loop
+                    let some = std::mem::take(&mut assigned.some);
+                    *assigned = breaks.unwrap_or(Assigned { jumped: true, ..Assigned::default() });
+                    assigned.some.extend(some);
+                }
+            },
+            StmtKind::Break => {
+                if let Some(exit) = self.breaks.last_mut() {
+                    match exit {
+                        Some(exit) => exit.merge(assigned.clone()),
+                        None => *exit = Some(assigned.clone()),
+                    }
+                }
+                assigned.jumped = true;
+            },
+            StmtKind::Continue | StmtKind::Return(None) | StmtKind::Goto(_) | StmtKind::SubReturn => assigned.jumped = true,
+            StmtKind::Return(Some(expr)) => {
+                self.expression(expr, assigned);
+                assigned.jumped = true;
+            },
+            StmtKind::Function(decl) => {
+                assigned.insert(&decl.name);
+                self.function(decl);
+            },
+            StmtKind::Struct(decl) => assigned.insert(&decl.name),
+            StmtKind::Import(name) => assigned.insert(name),
+            StmtKind::Global(names) => {
+                for name in names {
+                    assigned.insert(name);
+                }
+            },
+            StmtKind::Print(args) => {
+                for arg in args {
+                    self.expression(arg, assigned);
+                }
+            },
+            StmtKind::Expr(expr) => self.expression(expr, assigned),
+            StmtKind::Gosub(_) => {},
+        }
+    }
+
+    fn function(&mut self, decl: &FunctionDecl) {
+        let mut assigned = Assigned::default();
+        for name in self.visible.iter().chain(&decl.params) {
+            assigned.insert(name);
+        }
+        // Closures run after this call has assigned its variables
+        let mut inner = assigned.some.clone();
+        assigned_names(&decl.body, &mut inner);
+        let outer = std::mem::replace(&mut self.visible, inner);
+        let breaks = std::mem::take(&mut self.breaks);
+        self.block(&decl.body, &mut assigned);
+        self.visible = outer;
+        self.breaks = breaks;
+    }
+
+    fn expression(&mut self, expr: &Expr, assigned: &mut Assigned) {
+        match &expr.kind {
+            ExprKind::Variable(name) => {
+                if !assigned.some.contains(name) {
+                    self.warnings.push(Warning {
+                        code: "read-before-assignment",
+                        message: format!("`{}` is read before anything assigns it", name),
+                        span: expr.span,
+                        labels: Vec::new(),
+                    });
+                    // Only warn about the first read
+                    assigned.insert(name);
+                } else if !assigned.all.contains(name) && !assigned.jumped {
+                    self.warnings.push(Warning {
+                        code: "maybe-uninitialized",
+                        message: format!("`{}` may be uninitialized here, since some paths to this read don't assign it", name),
+                        span: expr.span,
+                        labels: Vec::new(),
+                    });
+                    assigned.all.insert(name.clone());
+                }
+            },
+            ExprKind::Function(decl) => self.function(decl),
+            ExprKind::Array(items) => {
+                for item in items {
+                    self.expression(item, assigned);
+                }
+            },
+            ExprKind::Map(entries) => {
+                for (key, value) in entries {
+                    self.expression(key, assigned);
+                    self.expression(value, assigned);
+                }
+            },
+            ExprKind::Index { target: left, index: right } | ExprKind::Binary { left, right, .. } => {
+                self.expression(left, assigned);
+                self.expression(right, assigned);
+            },
+            ExprKind::Unary { operand, .. } | ExprKind::Field { target: operand, .. } => self.expression(operand, assigned),
+            ExprKind::Call { callee, args } => {
+                self.expression(callee, assigned);
+                for arg in args {
+                    self.expression(arg, assigned);
+                }
+            },
+            ExprKind::Number(_) | ExprKind::Float(_) | ExprKind::Str(_) => {},
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::check::{check, check_with_globals};
+    use crate::parser::{parse, parse_with_dialect, Dialect};
+
+    /// The warnings with `code` for `source`, as displayed.
+    fn warnings(code: &str, source: &str) -> Vec<String> {
+        let warnings = check(&parse(source).unwrap());
+        warnings.iter().filter(|warning| warning.code == code).map(ToString::to_string).collect()
+    }
+
+    #[test]
+    fn test_shadowing_globals() {
+        let source = "count = 0\nfn bump() {\n  count = count + 1\n  count = 5\n}";
+        assert_eq!(
+            warnings("shadowed-global", source),
+            vec!["3:3: warning: assigning to `count` creates a local that shadows the global `count`; add `global count` to change the global"]
+        );
+        assert!(warnings("shadowed-global", "count = 0\nfn bump() {\n  global count\n  count = count + 1\n}").is_empty());
+        assert!(warnings("shadowed-global", "count = 0\nfn f() {\n  let count = 1\n  count = 2\n  return count\n}").is_empty());
+        assert!(warnings("shadowed-global", "x = 0\nfn f(x) {\n  x = 1\n}\nfn g() {\n  y = 1\n}").is_empty());
+        assert_eq!(warnings("shadowed-global", "x = 0\nf = fn() {\n  if 1 { x = 1 }\n}").len(), 1);
+    }
+
+    #[test]
+    fn test_use_before_definition() {
+        assert_eq!(warnings("read-before-assignment", "total = 0\ntotl = total + 1\nprint totl + totl2"), vec![
+            "3:14: warning: `totl2` is read before anything assigns it"
+        ]);
+        assert_eq!(warnings("read-before-assignment", "x = x + 1"), vec!["1:5: warning: `x` is read before anything assigns it"]);
+        // Assigned on some path is enough
+        assert!(warnings("read-before-assignment", "if input() == 1 {\n  y = 1\n}\nprint y").is_empty());
+        assert!(warnings("read-before-assignment", "i = 0\nwhile i < 3 {\n  if i > 0 { print last }\n  last = i\n  i = i + 1\n}").is_empty());
+        // Function bodies run later, so they may use globals assigned further down
+        assert!(warnings("read-before-assignment", "fn f(a) {\n  g = fn() { return a + b + limit }\n  b = 1\n  return g\n}\nlimit = 3\nf(1)").is_empty());
+        assert_eq!(warnings("read-before-assignment", "fn f() {\n  return nope\n}"), vec!["2:10: warning: `nope` is read before anything assigns it"]);
+        assert!(warnings("read-before-assignment", "print sqrt(4)\nimport math\nprint math.pi").is_empty());
+
+        let program = parse("print time()").unwrap();
+        assert!(check_with_globals(&program, &["time"]).iter().all(|warning| warning.code != "read-before-assignment"));
+    }
+
+    #[test]
+    fn test_maybe_uninitialized() {
+        let source = "if input() == 1 {\n  y = 1\n} else {\n  z = 1\n}\nprint y\nprint y + z";
+        assert_eq!(warnings("maybe-uninitialized", source), vec![
+            "6:7: warning: `y` may be uninitialized here, since some paths to this read don't assign it",
+            "7:11: warning: `z` may be uninitialized here, since some paths to this read don't assign it",
+        ]);
+        assert!(warnings("maybe-uninitialized", "if input() {\n  y = 1\n} else {\n  y = 2\n}\nprint y").is_empty());
+        // Branches that jump away don't reach the read
+        assert!(warnings("maybe-uninitialized", "fn f(x) {\n  if x {\n    y = 1\n  } else {\n    return 0\n  }\n  return y\n}\nprint f(1)").is_empty());
+
+        // The body of a loop may not run, and later iterations differ from the first
+        let source = "i = 0\nwhile i < 3 {\n  if i > 0 { print last }\n  last = i\n  i = i + 1\n}\nprint last";
+        assert_eq!(warnings("maybe-uninitialized", source), vec![
+            "3:20: warning: `last` may be uninitialized here, since some paths to this read don't assign it",
+            "7:7: warning: `last` may be uninitialized here, since some paths to this read don't assign it",
+        ]);
+        let source = "while 1 {\n  line = input()\n  if line == \"\" { break }\n}\nprint line";
+        assert!(warnings("maybe-uninitialized", source).is_empty());
+        let source = "while 1 {\n  if input() { break }\n  line = input()\n}\nprint line";
+        assert_eq!(warnings("maybe-uninitialized", source).len(), 1);
+    }
+
+    #[test]
+    fn test_constant_overflow() {
+        let source = "x = 9223372036854775807 * 2 + 1\nfn f() {\n  return -(-9223372036854775807 - 1) + 0\n}\nprint f(), x";
+        assert_eq!(warnings("constant-overflow", source), vec![
+            "1:5: warning: constant expression overflows a 64-bit int",
+            "3:10: warning: constant expression overflows a 64-bit int",
+        ]);
+        assert!(warnings("constant-overflow", "x = 3000000000 * 2\ny = x * 9223372036854775807\nprint y").is_empty());
+    }
+
+    #[test]
+    fn test_unused() {
+        let source = "a = 1\nb = 2\nb = 3\nfn f(x, y) {\n  z = x\n  _ignored = 1\n  g = fn() { return z }\n  return g\n}\nfn helper() {\n}\nprint f";
+        assert_eq!(warnings("unused-variable", source), vec![
+            "1:1: warning: `a` is assigned but never read",
+            "2:1: warning: `b` is assigned but never read",
+        ]);
+        assert_eq!(warnings("unused-function", source), vec!["10:1: warning: function `helper` is never used"]);
+
+        // Globals count as read from anywhere, locals only from their function
+        let source = "n = 0\nfn bump() {\n  global n\n  n = n + 1\n  local = n\n}\nbump()";
+        assert_eq!(warnings("unused-variable", source), vec!["5:3: warning: `local` is assigned but never read"]);
+        let source = "fn outer() {\n  fn inner() {\n    t = 1\n  }\n}\nouter()";
+        assert_eq!(warnings("unused-variable", source), vec!["3:5: warning: `t` is assigned but never read"]);
+        assert_eq!(warnings("unused-function", source), vec!["2:3: warning: function `inner` is never used"]);
+    }
+
+    #[test]
+    fn test_unreachable() {
+        let source = "fn f(x) {\n  while x > 0 {\n    break\n    print 1\n  }\n  if x {\n    return 1\n  } else {\n    return 2\n  }\n  print 3\n}\nprint f(1)";
+        assert_eq!(warnings("unreachable-code", source), vec![
+            "4:5: warning: unreachable statement after `break`",
+            "11:3: warning: unreachable statement after `if`",
+        ]);
+        let source = "if 1 > 2 {\n  print 1\n}\nif !0 {\n  print 2\n} else {\n  print 3\n}\nwhile \"\" {\n}";
+        assert_eq!(warnings("unreachable-code", source), vec![
+            "1:4: warning: condition is always false, so this branch never runs",
+            "4:4: warning: condition is always true, so the `else` branch never runs",
+            "9:7: warning: loop condition is always false, so the body never runs",
+        ]);
+        let source = "g = fn() {\n  return 1\n  print 2\n}\nprint g";
+        assert_eq!(warnings("unreachable-code", source), vec!["3:3: warning: unreachable statement after `return`"]);
+        assert!(warnings("unreachable-code", "if 1 {\n  print 1\n}\nwhile 1 {\n  continue\n}").is_empty());
+
+        // Classic programs can jump to any line
+        let program = parse_with_dialect("10 goto 30\n20 print 1\n30 print 2", Dialect::Classic).unwrap();
+        assert!(check(&program).iter().all(|warning| warning.code != "unreachable-code"));
+    }
+}
@@ -0,0 +1,1984 @@
+use std::cell::RefCell;
+use std::collections::{HashMap, VecDeque};
+use std::fs;
+use std::io::{self, BufRead, IsTerminal, Read, Write};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::ast::Program;
+use crate::build::Project;
+use crate::bytecode;
+use crate::check;
+use crate::codes::{self, CODES};
+use crate::config::{Config, HistoryFile};
+use crate::compiler::compile;
+use crate::coverage::Coverage;
+use crate::debug::{DebugAction, DebugHook, StatementEvent};
+use crate::diagnostic::{Diagnostic, Diagnostics, Level};
+use crate::doc;
+use crate::dump::{dump, DumpFormat};
+use crate::editor::{append_history, Input, LineEditor};
+use crate::formatter;
+use crate::interpreter::{Interpreter, RuntimeError};
+use crate::json::Json;
+use crate::lexer::{Lexer, Span, Token};
+use crate::memory::{self, MemoryUsage};
+use crate::modules::{module_path, FileLoader, MemoryLoader, ModuleLoader};
+use crate::parser::{self, is_incomplete, parse_into, parse_into_with, Dialect, ParseOptions};
+use crate::profile::LineProfile;
+use crate::repl::{echo, EvalError, Repl};
+use crate::stats::RunStats;
+use crate::typecheck::typecheck;
+use crate::value::Value;
+
+const USAGE: &str = "\
+usage: tbasic <command> [options]
+       tbasic <script> [<arg>...]
+                            run a script with the arguments as its args(),
+                            as a `#!/usr/bin/env tbasic` line does
+       tbasic -e <code> [<arg>...]
+       tbasic - [<arg>...]  run the code given, or read from stdin, and
+                            print what it evaluates to
+
+commands:
+  run [--classic] [--watch] [--bench] [--profile] [--coverage]
+      [--lcov <file>] [<script>] [-- <arg>...]
+                            run a script, with --watch again whenever it
+                            or its imports change, with --bench reporting
+                            its time, statements, the process's heap
+                            allocations and peak, and calls,
+                            with --profile the time spent on each line,
+                            with --coverage the lines that ran, and with
+                            --lcov writing those to a file; the script's
+                            args() are the arguments after --
+  repl [--record <file>] [--replay <file>]
+                            evaluate lines as they are typed, saving those
+                            that ran to a script or first running one
+  check [--classic] [--deny-warnings] <script>...
+                            report problems in scripts without running them
+  build [--classic] [--deny-warnings] [--output <file>] [<script>]
+                            check a script and the modules it imports from
+                            the project's root, with --output compiling
+                            them to one .tbc file that `run` runs
+  doc [--classic] [--html] [--output <file>] [<script>]
+                            document the functions and structs of a script
+                            and the modules it imports from their `##`
+                            comments, as Markdown or with --html a web page
+  tokens <script>           print the script's tokens as JSON lines
+  ast [--format tree|sexpr|json] [--classic] <script>
+                            print the script's syntax tree
+  fmt [--check] [--classic] <script>...
+                            format scripts in place, or with --check
+                            only report those that aren't formatted
+  debug [--classic] [<script>]
+                            run a script in a debugger reading commands
+                            from stdin; `help` there lists them
+  test [--coverage] [--lcov <file>] [<dir or file>...]
+                            run the *_test.tb scripts found, by default in
+                            the current directory
+  explain [<code>]          explain a diagnostic's code, e.g. E0203, or
+                            list them all
+  help                      show this message
+
+The settings of the nearest tbasic.toml in the current directory or above
+apply to every command; a command given no script takes its `entry`,
+except `test`, which looks in the current directory.
+";
+
+/// A writer clones of which all write to the same place, so the script and
+/// the driver can share stdout.
+#[derive(Clone)]
+pub struct SharedWriter(Rc<RefCell<Box<dyn Write>>>);
+
+impl SharedWriter {
+    pub fn new(writer: Box<dyn Write>) -> SharedWriter {
+        SharedWriter(Rc::new(RefCell::new(writer)))
+    }
+}
+
+impl Write for SharedWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.borrow_mut().flush()
+    }
+}
+
+/// The name the REPL's input goes by in diagnostics.
+const REPL_PATH: &str = "<repl>";
+
+const PROMPT: &str = "> ";
+
+/// Shown while the input so far leaves a bracket open.
+const CONTINUATION_PROMPT: &str = ". ";
+
+/// Where the `tbasic` command reads and writes, so tests can supply and
+/// capture it.
+pub struct Io {
+    pub stdin: Box<dyn BufRead>,
+    pub stdout: SharedWriter,
+    pub stderr: Box<dyn Write>,
+    /// Whether stdin and stdout are a terminal. The REPL then reads the
+    /// terminal with a [`LineEditor`] instead of reading `stdin`.
+    pub terminal: bool,
+    /// Whether diagnostics on stderr are colored.
+    pub color: bool,
+    /// The working directory, where the search for a `tbasic.toml` starts.
+    pub cwd: PathBuf,
+}
+
+impl Io {
+    pub fn std() -> Io {
+        Io {
+            stdin: Box::new(io::BufReader::new(io::stdin())),
+            stdout: SharedWriter::new(Box::new(io::stdout())),
+            stderr: Box::new(io::stderr()),
+            terminal: io::stdin().is_terminal() && io::stdout().is_terminal(),
+            // See https://no-color.org
+            color: io::stderr().is_terminal() && std::env::var_os("NO_COLOR").is_none(),
+            cwd: std::env::current_dir().unwrap_or_default(),
+        }
+    }
+}
+
+/// Runs the `tbasic` command with `args`, not including the program name,
+/// and returns its exit code. The settings of the project's `tbasic.toml`
+/// apply, overridden by the options given.
+pub fn main(args: &[String], io: &mut Io) -> i32 {
+    let config = match Config::discover(&io.cwd) {
+        Ok(config) => config,
+        Err(error) => {
+            let _ = writeln!(io.stderr, "error: {}", error);
+            return 1;
+        },
+    };
+    let result = match args.first().map(String::as_str) {
+        Some("run") => run(&args[1..], &config, io),
+        Some("repl") => repl(&args[1..], &config, io),
+        Some("check") => check(&args[1..], &config, io),
+        Some("build") => build(&args[1..], &config, io),
+        Some("doc") => doc(&args[1..], &config, io),
+        Some("fmt") => fmt(&args[1..], &config, io),
+        Some("tokens") => tokens(&args[1..], &config, io),
+        Some("ast") => ast(&args[1..], &config, io),
+        Some("test") => test(&args[1..], &config, io),
+        Some("debug") => debug(&args[1..], &config, io),
+        Some("explain") => explain(&args[1..], io),
+        Some("help" | "-h" | "--help") => write!(io.stdout, "{}", USAGE).map(|_| 0),
+        Some("-e") if args.len() > 1 => eval("<eval>", &args[1], &args[2..], &config, io),
+        Some("-") => {
+            let mut source = String::new();
+            io.stdin.read_to_string(&mut source).and_then(|_| eval("<stdin>", &source, &args[1..], &config, io))
+        },
+        // How a script starting with `#!/usr/bin/env tbasic` is run
+        Some(path) if Path::new(path).is_file() => {
+            let script_args = args[1..].iter().cloned();
+            let run_args: Vec<String> = [path, "--"].into_iter().map(String::from).chain(script_args).collect();
+            run(&run_args, &config, io)
+        },
+        _ => write!(io.stderr, "{}", USAGE).map(|_| 2),
+    };
+    match result.and_then(|code| io.stdout.flush().map(|_| code)) {
+        Ok(code) => code,
+        Err(error) => {
+            let _ = writeln!(io.stderr, "error: {}", error);
+            1
+        },
+    }
+}
+
+/// The script of the project's `entry`, which commands given none take.
+fn entry(config: &Config) -> Option<String> {
+    config.entry.as_ref().map(|entry| entry.to_string_lossy().into_owned())
+}
+
+/// `tbasic run`: parses the script, printing every problem found, and runs
+/// it if there were no errors. The script's `exit` code, or the int its last
+/// statement evaluates to, becomes the exit code; a runtime error is printed
+/// with the line it happened on.
+///
+/// With `--watch` the script is run again, in a fresh interpreter, whenever
+/// it or a module it imported changes, until interrupted. With `--bench` a
+/// report of what the run took follows on stderr, and with `--coverage` or
+/// `--lcov` one of the lines that ran.
+fn run(args: &[String], config: &Config, io: &mut Io) -> io::Result<i32> {
+    let mut options = RunOptions { parse: config.parse, ..RunOptions::default() };
+    let mut watch = false;
+    let entry = entry(config);
+    let mut path = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--classic" => options.parse.dialect = Dialect::Classic,
+            "--watch" => watch = true,
+            "--bench" => options.bench = true,
+            "--profile" => options.profile = true,
+            "--" => options.args = args.by_ref().cloned().collect(),
+            _ if options.coverage_option(arg, &mut args) => {},
+            _ if path.is_none() && !arg.starts_with("--") => path = Some(arg.as_str()),
+            _ => {
+                write!(io.stderr, "{}", USAGE)?;
+                return Ok(2);
+            },
+        }
+    }
+    let Some(path) = path.or(entry.as_deref()) else {
+        write!(io.stderr, "{}", USAGE)?;
+        return Ok(2);
+    };
+    if !watch {
+        let code = run_script(path, &options, io, &mut Vec::new())?;
+        return report_coverage(&options, code, io);
+    }
+    loop {
+        if io.terminal {
+            write!(io.stdout, "\x1b[2J\x1b[H")?;
+        }
+        let mut files = vec![PathBuf::from(path)];
+        let code = run_script(path, &options, io, &mut files)?;
+        io.stdout.flush()?;
+        report_coverage(&options, code, io)?;
+        if let Some(coverage) = &options.coverage {
+            *coverage.borrow_mut() = Coverage::new();
+        }
+        writeln!(io.stderr, "[exited with {}, waiting for changes]", code)?;
+        let seen = modified(&files);
+        while modified(&files) == seen {
+            thread::sleep(WATCH_INTERVAL);
+        }
+    }
+}
+
+/// How `tbasic run` runs a script.
+#[derive(Debug, Clone, Default)]
+struct RunOptions {
+    parse: ParseOptions,
+    bench: bool,
+    /// Whether to report the time spent on each line.
+    profile: bool,
+    /// Where the lines that ran are counted, across all scripts run with
+    /// these options.
+    coverage: Option<Rc<RefCell<Coverage>>>,
+    /// Where to write the coverage in LCOV format.
+    lcov: Option<String>,
+    /// What the script's `args()` returns: the arguments after `--`.
+    args: Vec<String>,
+}
+
+impl RunOptions {
+    /// Takes `--coverage` or `--lcov <file>`, returning whether `arg` was
+    /// one of them.
+    fn coverage_option<'a>(&mut self, arg: &str, args: &mut impl Iterator<Item = &'a String>) -> bool {
+        match arg {
+            "--coverage" => {},
+            "--lcov" => match args.next() {
+                Some(file) => self.lcov = Some(file.clone()),
+                None => return false,
+            },
+            _ => return false,
+        }
+        self.coverage.get_or_insert_with(Default::default);
+        true
+    }
+}
+
+/// Prints the coverage summary after a run with `--coverage`, and writes
+/// the `--lcov` file. Returns the exit code, `code` unless the file can't be
+/// written.
+fn report_coverage(options: &RunOptions, code: i32, io: &mut Io) -> io::Result<i32> {
+    let Some(coverage) = &options.coverage else {
+        return Ok(code);
+    };
+    let coverage = coverage.borrow();
+    write!(io.stderr, "\ncoverage:\n{}", coverage.summary())?;
+    if let Some(path) = &options.lcov {
+        if let Err(error) = fs::write(path, coverage.lcov()) {
+            writeln!(io.stderr, "error: can't write `{}`: {}", path, error)?;
+            return Ok(1);
+        }
+    }
+    Ok(code)
+}
+
+/// How often `run --watch` looks for changes.
+const WATCH_INTERVAL: Duration = Duration::from_millis(200);
+
+/// When each of `files` was last modified, `None` if it can't be read, e.g.
+/// because the file doesn't exist (yet).
+fn modified(files: &[PathBuf]) -> Vec<Option<SystemTime>> {
+    files.iter().map(|file| fs::metadata(file).and_then(|metadata| metadata.modified()).ok()).collect()
+}
+
+/// Runs the script at `path` as `tbasic run` does, adding the files of the
+/// modules it tried to import to `imports`.
+fn run_script(path: &str, options: &RunOptions, io: &mut Io, imports: &mut Vec<PathBuf>) -> io::Result<i32> {
+    if path.ends_with(".tbc") {
+        return run_built(path, options, io);
+    }
+    let Some((source, program)) = parse_script(path, options.parse, io)? else {
+        return Ok(1);
+    };
+    let mut interpreter = Interpreter::with_output(Box::new(io.stdout.clone()));
+    let root = Path::new(path).parent().unwrap_or(Path::new(""));
+    let loaded = Rc::new(RefCell::new(Vec::new()));
+    interpreter.set_module_loader(Box::new(WatchedLoader { root: root.to_path_buf(), loaded: loaded.clone() }));
+    interpreter.set_stats(options.bench);
+    interpreter.set_args(options.args.clone());
+    if let Some(coverage) = &options.coverage {
+        coverage.borrow_mut().add_file(path, &program);
+    }
+    let profile = options.profile.then(|| Rc::new(RefCell::new(LineProfile::new())));
+    if options.coverage.is_some() || profile.is_some() {
+        let (coverage, profile) = (options.coverage.clone(), profile.clone());
+        interpreter.set_debug_hook(Box::new(statement_hook(path, root, move |path, line| {
+            if let Some(coverage) = &coverage {
+                coverage.borrow_mut().record(path, line);
+            }
+            if let Some(profile) = &profile {
+                profile.borrow_mut().enter(path, line);
+            }
+        })));
+    }
+    memory::reset_peak();
+    let (memory_before, started) = (memory::usage(), Instant::now());
+    let result = interpreter.run(&program);
+    let elapsed = started.elapsed();
+    if let Some(profile) = &profile {
+        profile.borrow_mut().finish();
+    }
+    let loaded = loaded.take();
+    if let Some(coverage) = &options.coverage {
+        // Modules count with all their lines, not just those that ran
+        for module in &loaded {
+            let program = fs::read_to_string(module).ok().and_then(|source| parser::parse(&source).ok());
+            if let Some(program) = program {
+                coverage.borrow_mut().add_file(&module.to_string_lossy(), &program);
+            }
+        }
+    }
+    imports.extend(loaded);
+    if let Some(stats) = interpreter.stats() {
+        io.stdout.flush()?;
+        write!(io.stderr, "{}", bench_report(elapsed, stats, memory_before.zip(memory::usage())))?;
+    }
+    if let Some(profile) = &profile {
+        io.stdout.flush()?;
+        write!(io.stderr, "\nprofile: {}", profile.borrow())?;
+    }
+    match result {
+        Ok(value) => Ok(exit_code(&value)),
+        Err(RuntimeError::Exit { code, .. }) => Ok(code),
+        Err(error) => {
+            io.stdout.flush()?;
+            write!(io.stderr, "{}", Diagnostic::from(error).render_with(path, &source, io.color))?;
+            Ok(1)
+        },
+    }
+}
+
+/// Runs a program compiled by `tbasic build --output`, importing the
+/// modules it embeds.
+fn run_built(path: &str, options: &RunOptions, io: &mut Io) -> io::Result<i32> {
+    if options.coverage.is_some() {
+        writeln!(io.stderr, "error: coverage needs the source of `{}`", path)?;
+        return Ok(2);
+    }
+    let loaded = fs::File::open(path).map_err(bytecode::LoadError::Io).and_then(bytecode::load_with_modules);
+    let (chunk, modules) = match loaded {
+        Ok(loaded) => loaded,
+        Err(error) => {
+            writeln!(io.stderr, "error: can't load `{}`: {}", path, error)?;
+            return Ok(1);
+        },
+    };
+    let loader = modules.iter().fold(MemoryLoader::new(), |loader, (name, source)| loader.with_module(name, source));
+    let mut interpreter = Interpreter::with_output(Box::new(io.stdout.clone()));
+    interpreter.set_module_loader(Box::new(loader));
+    interpreter.set_stats(options.bench);
+    interpreter.set_args(options.args.clone());
+    interpreter.set_profiling(options.profile);
+    memory::reset_peak();
+    let (memory_before, started) = (memory::usage(), Instant::now());
+    let result = interpreter.run_compiled(&chunk);
+    let elapsed = started.elapsed();
+    if let Some(stats) = interpreter.stats() {
+        io.stdout.flush()?;
+        write!(io.stderr, "{}", bench_report(elapsed, stats, memory_before.zip(memory::usage())))?;
+    }
+    if let Some(profile) = interpreter.profile() {
+        // Compiled code has no statement events to time, only instructions
+        // to count
+        io.stdout.flush()?;
+        write!(io.stderr, "\nprofile: {}", profile)?;
+    }
+    match result {
+        Ok(value) => Ok(exit_code(&value)),
+        Err(RuntimeError::Exit { code, .. }) => Ok(code),
+        Err(error) => {
+            io.stdout.flush()?;
+            // There is no source to show the line of
+            writeln!(io.stderr, "{}:{}", path, Diagnostic::from(error))?;
+            Ok(1)
+        },
+    }
+}
+
+/// The exit code of a script whose last statement evaluated to `value`: an
+/// int is the code, 1 if it doesn't fit one, and anything else succeeds.
+fn exit_code(value: &Value) -> i32 {
+    match value {
+        Value::Int(n) => i32::try_from(*n).unwrap_or(1),
+        Value::BigInt(_) => 1,
+        _ => 0,
+    }
+}
+
+/// A debug hook passing the path and line of each statement run by the
+/// script at `path`, whose imports are found in `root`, to `record`.
+fn statement_hook(path: &str, root: &Path, mut record: impl FnMut(&str, usize)) -> impl DebugHook {
+    let (path, root) = (path.to_string(), root.to_path_buf());
+    let mut module_paths: HashMap<String, String> = HashMap::new();
+    move |event: &mut StatementEvent<'_>| {
+        let line = event.span().line;
+        match event.module() {
+            None => record(&path, line),
+            Some(module) => {
+                let path = module_paths
+                    .entry(module)
+                    .or_insert_with_key(|module| module_path(&root, module).to_string_lossy().into_owned());
+                record(path, line);
+            },
+        }
+        DebugAction::Continue
+    }
+}
+
+/// What `run --bench` reports: the run's wall time, statements, calls
+/// and, if they were counted, the heap allocations the process made during
+/// the run and the peak heap bytes it used on top of what was in use
+/// before. Both count everything the process allocates, not only values.
+fn bench_report(elapsed: Duration, stats: &RunStats, memory: Option<(MemoryUsage, MemoryUsage)>) -> String {
+    let mut report = format!("\nwall time      {:.3} ms\n", elapsed.as_secs_f64() * 1000.0);
+    report += &format!("statements     {}\n", stats.statements());
+    match memory {
+        Some((before, after)) => {
+            report += &format!("allocations    {}\n", after.allocations - before.allocations);
+            report += &format!("peak heap      {} bytes\n", after.peak_heap_bytes.saturating_sub(before.in_use));
+        },
+        None => report += "allocations    not counted\n",
+    }
+    let calls = stats.calls();
+    report += &format!("calls          {}\n", calls.iter().map(|(_, count)| count).sum::<u64>());
+    for (function, count) in calls {
+        report += &format!("  {:<12} {}\n", function, count);
+    }
+    report
+}
+
+/// Reads and parses the script at `path`, printing every problem found.
+/// Returns `None` if it can't be run.
+fn parse_script(path: &str, options: ParseOptions, io: &mut Io) -> io::Result<Option<(String, Program)>> {
+    let source = match fs::read_to_string(path) {
+        Ok(source) => source,
+        Err(error) => {
+            writeln!(io.stderr, "error: can't read `{}`: {}", path, error)?;
+            return Ok(None);
+        },
+    };
+    Ok(parse_source(path, &source, options, io)?.map(|program| (source, program)))
+}
+
+/// Parses `source`, printing every problem found with `name` for its path,
+/// and returns the program if there were no errors.
+fn parse_source(name: &str, source: &str, options: ParseOptions, io: &mut Io) -> io::Result<Option<Program>> {
+    let mut diagnostics = Diagnostics::new();
+    let program = parse_into_with(source, options, &mut diagnostics);
+    diagnostics.sort();
+    for diagnostic in diagnostics.iter() {
+        write!(io.stderr, "{}", diagnostic.render_with(name, source, io.color))?;
+    }
+    Ok(program.filter(|_| !diagnostics.has_errors()))
+}
+
+/// `tbasic -e <code>` and `tbasic -`: runs code given on the command line or
+/// piped to stdin, called `name` in diagnostics, printing what its last
+/// statement evaluates to as the REPL does. `args` are the script's
+/// `args()`, and imports are found in the current directory.
+fn eval(name: &str, source: &str, args: &[String], config: &Config, io: &mut Io) -> io::Result<i32> {
+    let Some(program) = parse_source(name, source, config.parse, io)? else {
+        return Ok(1);
+    };
+    let mut interpreter = Interpreter::with_output(Box::new(io.stdout.clone()));
+    interpreter.set_module_loader(Box::new(FileLoader::new(&io.cwd)));
+    interpreter.set_args(args.to_vec());
+    match interpreter.run(&program) {
+        Ok(value) => {
+            if let Some(text) = echo(&value) {
+                writeln!(io.stdout, "{}", text)?;
+            }
+            Ok(0)
+        },
+        Err(RuntimeError::Exit { code, .. }) => Ok(code),
+        Err(error) => {
+            io.stdout.flush()?;
+            write!(io.stderr, "{}", Diagnostic::from(error).render_with(name, source, io.color))?;
+            Ok(1)
+        },
+    }
+}
+
+/// A [`FileLoader`] that notes the files it loads from, for `run --watch`.
+struct WatchedLoader {
+    root: PathBuf,
+    loaded: Rc<RefCell<Vec<PathBuf>>>,
+}
+
+impl ModuleLoader for WatchedLoader {
+    fn load(&mut self, name: &str) -> io::Result<Option<String>> {
+        self.loaded.borrow_mut().push(module_path(&self.root, name));
+        FileLoader::new(&self.root).load(name)
+    }
+}
+
+/// `tbasic debug`: runs the script under a debugger taking the commands
+/// in [`DEBUG_HELP`], starting paused before its first statement. The exit
+/// code is the script's, as with `tbasic run`. When the commands run out
+/// the script runs to the end without pausing.
+fn debug(args: &[String], config: &Config, io: &mut Io) -> io::Result<i32> {
+    let mut options = config.parse;
+    let args = match args {
+        [flag, args @ ..] if flag == "--classic" => {
+            options.dialect = Dialect::Classic;
+            args
+        },
+        args => args,
+    };
+    let entry = entry(config);
+    let Some(path) = args.first().or(entry.as_ref()).filter(|_| args.len() <= 1) else {
+        write!(io.stderr, "{}", USAGE)?;
+        return Ok(2);
+    };
+    let Some((source, program)) = parse_script(path, options, io)? else {
+        return Ok(1);
+    };
+
+    let debugger = Rc::new(RefCell::new(Debugger {
+        stdin: std::mem::replace(&mut io.stdin, Box::new(io::empty())),
+        stdout: io.stdout.clone(),
+        stderr: std::mem::replace(&mut io.stderr, Box::new(io::sink())),
+        terminal: io.terminal,
+        editor: LineEditor::new(),
+        lines: source.lines().map(str::to_string).collect(),
+        resume: Resume::Step,
+        paused_at: None,
+        last_command: String::new(),
+        detached: false,
+    }));
+    let mut interpreter = Interpreter::with_output(Box::new(io.stdout.clone()));
+    let root = Path::new(path).parent().unwrap_or(Path::new(""));
+    interpreter.set_module_loader(Box::new(FileLoader::new(root)));
+    let hook = debugger.clone();
+    interpreter.set_debug_hook(Box::new(move |event: &mut StatementEvent<'_>| hook.borrow_mut().on_statement(event)));
+    let handler = debugger.clone();
+    interpreter.set_breakpoint_handler(Box::new(move |event: &mut StatementEvent<'_>| handler.borrow_mut().on_breakpoint(event)));
+    let result = interpreter.run(&program);
+    drop(interpreter);
+    let debugger = Rc::try_unwrap(debugger).ok().expect("the interpreter holding the debugger is gone").into_inner();
+    (io.stdin, io.stderr) = (debugger.stdin, debugger.stderr);
+
+    match result {
+        Ok(value) => Ok(exit_code(&value)),
+        Err(RuntimeError::Exit { code, .. }) => Ok(code),
+        // Stopped by `quit`
+        Err(RuntimeError::Aborted { .. }) => Ok(1),
+        Err(error) => {
+            io.stdout.flush()?;
+            write!(io.stderr, "{}", Diagnostic::from(error).render_with(path, &source, io.color))?;
+            Ok(1)
+        },
+    }
+}
+
+const DEBUG_HELP: &str = "\
+break <line>   pause whenever the script reaches <line> (b)
+clear <line>   remove the breakpoint on <line>
+step           run the next statement, going into calls (s)
+next           run the next statement, stepping over calls (n)
+continue       run until a breakpoint (c)
+print [<var>]  show a variable, or all of them (p)
+backtrace      show the calls in progress (bt)
+quit           stop the script (q)
+help           show this message
+an empty line repeats the last command
+";
+
+const DEBUG_PROMPT: &str = "(debug) ";
+
+/// `tbasic debug`'s state, shared between the interpreter's debug hook and
+/// breakpoint handler.
+struct Debugger {
+    stdin: Box<dyn BufRead>,
+    stdout: SharedWriter,
+    stderr: Box<dyn Write>,
+    terminal: bool,
+    editor: LineEditor,
+    /// The script's source lines, to show where it paused.
+    lines: Vec<String>,
+    resume: Resume,
+    /// The statement the script is paused at, so a breakpoint on it doesn't
+    /// pause it a second time.
+    paused_at: Option<Span>,
+    last_command: String,
+    /// Set when the commands ran out and the script runs on unattended.
+    detached: bool,
+}
+
+/// How far a paused script runs before pausing again.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Resume {
+    /// Until the next statement.
+    Step,
+    /// Until the next statement at most this many calls deep.
+    Next(usize),
+    /// Until a breakpoint.
+    Continue,
+}
+
+impl Debugger {
+    fn on_statement(&mut self, event: &mut StatementEvent<'_>) -> DebugAction {
+        let pause = match self.resume {
+            Resume::Step => true,
+            Resume::Next(depth) => event.depth() <= depth,
+            Resume::Continue => false,
+        };
+        if !pause || self.detached {
+            self.paused_at = None;
+            return DebugAction::Continue;
+        }
+        self.paused_at = Some(event.span());
+        self.pause(event)
+    }
+
+    fn on_breakpoint(&mut self, event: &mut StatementEvent<'_>) -> DebugAction {
+        if self.detached || self.paused_at == Some(event.span()) {
+            return DebugAction::Continue;
+        }
+        self.pause(event)
+    }
+
+    fn pause(&mut self, event: &mut StatementEvent<'_>) -> DebugAction {
+        // Output can't fail the script from here; stop it instead
+        self.prompt(event).unwrap_or(DebugAction::Abort)
+    }
+
+    /// Shows where the script is and runs commands until one resumes it.
+    fn prompt(&mut self, event: &mut StatementEvent<'_>) -> io::Result<DebugAction> {
+        let span = event.span();
+        let text = self.lines.get(span.line - 1).map_or("", String::as_str);
+        writeln!(self.stdout, "stopped at {}:{}\n{} | {}", span.line, span.column, span.line, text)?;
+        loop {
+            self.stdout.flush()?;
+            let input = if self.terminal { self.editor.read_line(DEBUG_PROMPT)? } else { read_line(&mut self.stdin)? };
+            let mut line = match input {
+                Input::Line(line) => line.trim().to_string(),
+                Input::Interrupted => continue,
+                Input::Eof => {
+                    self.detached = true;
+                    return Ok(DebugAction::Continue);
+                },
+            };
+            if line.is_empty() {
+                line = self.last_command.clone();
+                if line.is_empty() {
+                    continue;
+                }
+            } else {
+                self.editor.add_history(&line);
+                self.last_command = line.clone();
+            }
+            if let Some(action) = self.command(&line, event)? {
+                return Ok(action);
+            }
+        }
+    }
+
+    /// Runs a command, returning what the script should do if it resumes
+    /// it.
+    fn command(&mut self, line: &str, event: &mut StatementEvent<'_>) -> io::Result<Option<DebugAction>> {
+        let (command, argument) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        let argument = argument.trim();
+        let resume = match (command, argument) {
+            ("break" | "b" | "clear", line) if line.parse::<usize>().is_ok_and(|line| line > 0) => {
+                let line = line.parse().unwrap();
+                if command == "clear" {
+                    event.clear_breakpoint(line);
+                    writeln!(self.stdout, "cleared the breakpoint on line {}", line)?;
+                } else {
+                    event.set_breakpoint(line);
+                    writeln!(self.stdout, "breakpoint on line {}", line)?;
+                }
+                return Ok(None);
+            },
+            ("step" | "s", "") => Resume::Step,
+            ("next" | "n", "") => Resume::Next(event.depth()),
+            ("continue" | "c", "") => Resume::Continue,
+            ("print" | "p", "") => {
+                for (name, value) in event.variables() {
+                    writeln!(self.stdout, "{} = {}", name, echo(&value).unwrap_or_else(|| "nil".to_string()))?;
+                }
+                return Ok(None);
+            },
+            ("print" | "p", name) => {
+                match event.variable(name) {
+                    Some(value) => writeln!(self.stdout, "{} = {}", name, echo(&value).unwrap_or_else(|| "nil".to_string()))?,
+                    None => writeln!(self.stderr, "error: no variable `{}`", name)?,
+                }
+                return Ok(None);
+            },
+            ("backtrace" | "bt", "") => {
+                // Each call is shown where it is at: the innermost at the
+                // paused statement, the others at the call they are in
+                let backtrace = event.backtrace();
+                let mut at = event.span();
+                for (i, frame) in backtrace.iter().enumerate() {
+                    writeln!(self.stdout, "#{} {} at {}:{}", i, frame.function, at.line, at.column)?;
+                    at = frame.call_site;
+                }
+                writeln!(self.stdout, "#{} <main> at {}:{}", backtrace.len(), at.line, at.column)?;
+                return Ok(None);
+            },
+            ("quit" | "q", "") => return Ok(Some(DebugAction::Abort)),
+            ("help" | "h", "") => {
+                write!(self.stdout, "{}", DEBUG_HELP)?;
+                return Ok(None);
+            },
+            _ => {
+                writeln!(self.stderr, "error: unknown command `{}`, `help` lists the commands", line)?;
+                return Ok(None);
+            },
+        };
+        self.resume = resume;
+        Ok(Some(DebugAction::Continue))
+    }
+}
+
+/// `tbasic test`: runs every `*_test.tb` script under the given
+/// directories, or given directly, each in a fresh interpreter. A test
+/// passes if it runs to the end or exits with 0; failed `assert`s and other
+/// errors are printed with where they happened. The exit code is 1 if any
+/// test failed. `--coverage` and `--lcov` report the lines all the tests
+/// ran together.
+fn test(args: &[String], config: &Config, io: &mut Io) -> io::Result<i32> {
+    let mut options = RunOptions { parse: config.parse, ..RunOptions::default() };
+    let mut roots = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            _ if options.coverage_option(arg, &mut args) => {},
+            _ if arg.starts_with("--") => {
+                write!(io.stderr, "{}", USAGE)?;
+                return Ok(2);
+            },
+            _ => roots.push(arg.as_str()),
+        }
+    }
+    if roots.is_empty() {
+        roots.push(".");
+    }
+    let mut tests = Vec::new();
+    for root in roots {
+        if let Err(error) = find_tests(Path::new(root), &mut tests) {
+            writeln!(io.stderr, "error: can't read `{}`: {}", root, error)?;
+            return Ok(1);
+        }
+    }
+
+    let mut failed = Vec::new();
+    for test in &tests {
+        let path = test.to_string_lossy();
+        let code = run_script(&path, &options, io, &mut Vec::new())?;
+        io.stdout.flush()?;
+        if code == 0 {
+            writeln!(io.stdout, "ok     {}", path)?;
+        } else {
+            writeln!(io.stdout, "FAILED {}", path)?;
+            failed.push(path);
+        }
+    }
+    writeln!(io.stdout, "\n{} passed, {} failed", tests.len() - failed.len(), failed.len())?;
+    io.stdout.flush()?;
+    report_coverage(&options, if failed.is_empty() { 0 } else { 1 }, io)
+}
+
+/// Adds `path` to `tests` if it is a file, or else the `*_test.tb` files in
+/// the directory and those below it, in order of their paths.
+fn find_tests(path: &Path, tests: &mut Vec<PathBuf>) -> io::Result<()> {
+    if !path.is_dir() {
+        fs::metadata(path)?;
+        tests.push(path.to_path_buf());
+        return Ok(());
+    }
+    let mut entries = fs::read_dir(path)?.map(|entry| entry.map(|entry| entry.path())).collect::<io::Result<Vec<_>>>()?;
+    entries.sort();
+    for entry in entries {
+        let is_test = entry.file_name().and_then(|name| name.to_str()).is_some_and(|name| name.ends_with("_test.tb"));
+        if entry.is_dir() || is_test {
+            find_tests(&entry, tests)?;
+        }
+    }
+    Ok(())
+}
+
+/// `tbasic check`: parses each script and runs [`check`](check::check),
+/// [`typecheck`] and the default lints over it, printing everything they
+/// find. The exit code is 1 if there were errors, or with `--deny-warnings`
+/// any diagnostics at all.
+fn check(args: &[String], config: &Config, io: &mut Io) -> io::Result<i32> {
+    let mut options = config.parse;
+    let mut deny_warnings = false;
+    let mut paths = Vec::new();
+    for arg in args {
+        match arg.as_str() {
+            "--classic" => options.dialect = Dialect::Classic,
+            "--deny-warnings" => deny_warnings = true,
+            _ => paths.push(arg.as_str()),
+        }
+    }
+    let entry = entry(config);
+    if paths.is_empty() {
+        paths.extend(entry.as_deref());
+    }
+    if paths.is_empty() {
+        write!(io.stderr, "{}", USAGE)?;
+        return Ok(2);
+    }
+    let (mut errors, mut warnings) = (0, 0);
+    for path in paths {
+        let source = match fs::read_to_string(path) {
+            Ok(source) => source,
+            Err(error) => {
+                writeln!(io.stderr, "error: can't read `{}`: {}", path, error)?;
+                errors += 1;
+                continue;
+            },
+        };
+        let mut diagnostics = Diagnostics::new();
+        if let Some(program) = parse_into_with(&source, options, &mut diagnostics) {
+            diagnostics.extend(check::check(&program));
+            diagnostics.extend(typecheck(&program));
+            diagnostics.extend(config.linter().lint(&program));
+        }
+        diagnostics.sort();
+        for diagnostic in diagnostics.iter() {
+            write!(io.stderr, "{}", diagnostic.render_with(path, &source, io.color))?;
+            match diagnostic.level {
+                Level::Error => errors += 1,
+                Level::Warning => warnings += 1,
+            }
+        }
+    }
+    if errors + warnings > 0 {
+        writeln!(io.stderr, "{}, {}", plural(errors, "error"), plural(warnings, "warning"))?;
+    }
+    Ok(if errors > 0 || (deny_warnings && warnings > 0) { 1 } else { 0 })
+}
+
+/// `tbasic build`: checks the entry and the modules it imports, found in
+/// the directory of the project's `tbasic.toml` or else the entry's, as
+/// `check` does a script and also across files. With `--output` and no
+/// errors it compiles the entry to a `.tbc` file embedding the modules.
+fn build(args: &[String], config: &Config, io: &mut Io) -> io::Result<i32> {
+    let mut options = config.parse;
+    let mut deny_warnings = false;
+    let mut output = None;
+    let mut entry = config.entry.clone();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--classic" => options.dialect = Dialect::Classic,
+            "--deny-warnings" => deny_warnings = true,
+            "--output" if output.is_none() => match args.next() {
+                Some(file) => output = Some(file.clone()),
+                None => {
+                    write!(io.stderr, "{}", USAGE)?;
+                    return Ok(2);
+                },
+            },
+            _ if !arg.starts_with("--") => entry = Some(PathBuf::from(arg)),
+            _ => {
+                write!(io.stderr, "{}", USAGE)?;
+                return Ok(2);
+            },
+        }
+    }
+    let Some(entry) = entry else {
+        write!(io.stderr, "{}", USAGE)?;
+        return Ok(2);
+    };
+    let root = project_root(config, &entry);
+    let mut project = match Project::load(&entry, &root, options) {
+        Ok(project) => project,
+        Err(error) => {
+            writeln!(io.stderr, "error: can't read `{}`: {}", entry.display(), error)?;
+            return Ok(1);
+        },
+    };
+    project.analyze(&config.linter());
+    let (mut errors, mut warnings) = (0, 0);
+    for file in &project.files {
+        let path = file.path.to_string_lossy();
+        for diagnostic in file.diagnostics.iter() {
+            write!(io.stderr, "{}", diagnostic.render_with(&path, &file.source, io.color))?;
+            match diagnostic.level {
+                Level::Error => errors += 1,
+                Level::Warning => warnings += 1,
+            }
+        }
+    }
+    if errors + warnings > 0 {
+        writeln!(io.stderr, "{}, {}", plural(errors, "error"), plural(warnings, "warning"))?;
+    }
+    if errors > 0 || (deny_warnings && warnings > 0) {
+        return Ok(1);
+    }
+    let (Some(output), Some(program)) = (output, &project.files[0].program) else {
+        return Ok(0);
+    };
+    let written = fs::File::create(&output)
+        .and_then(|file| bytecode::save_with_modules(&compile(program), &project.modules(), file));
+    if let Err(error) = written {
+        writeln!(io.stderr, "error: can't write `{}`: {}", output, error)?;
+        return Ok(1);
+    }
+    writeln!(io.stderr, "built `{}` from {}", output, plural(project.files.len(), "file"))?;
+    Ok(0)
+}
+
+/// Where a project with the script `entry` finds its modules: the directory
+/// of its `tbasic.toml`, or else the entry's.
+fn project_root(config: &Config, entry: &Path) -> PathBuf {
+    match config.path.as_deref().and_then(Path::parent) {
+        Some(dir) => dir.to_path_buf(),
+        None => entry.parent().unwrap_or(Path::new("")).to_path_buf(),
+    }
+}
+
+/// `tbasic doc`: documents the functions and structs of the entry and the
+/// modules it imports, found as `build` finds them, in Markdown or with
+/// `--html` as a web page.
+fn doc(args: &[String], config: &Config, io: &mut Io) -> io::Result<i32> {
+    let mut options = config.parse;
+    let mut html = false;
+    let mut output = None;
+    let mut entry = config.entry.clone();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--classic" => options.dialect = Dialect::Classic,
+            "--html" => html = true,
+            "--output" if output.is_none() => match args.next() {
+                Some(file) => output = Some(file.clone()),
+                None => {
+                    write!(io.stderr, "{}", USAGE)?;
+                    return Ok(2);
+                },
+            },
+            _ if !arg.starts_with("--") => entry = Some(PathBuf::from(arg)),
+            _ => {
+                write!(io.stderr, "{}", USAGE)?;
+                return Ok(2);
+            },
+        }
+    }
+    let Some(entry) = entry else {
+        write!(io.stderr, "{}", USAGE)?;
+        return Ok(2);
+    };
+    let project = match Project::load(&entry, &project_root(config, &entry), options) {
+        Ok(project) => project,
+        Err(error) => {
+            writeln!(io.stderr, "error: can't read `{}`: {}", entry.display(), error)?;
+            return Ok(1);
+        },
+    };
+    let mut pages = Vec::new();
+    let mut failed = false;
+    for file in &project.files {
+        let path = file.path.to_string_lossy();
+        // Only the files that don't parse are reported; `check` is for the rest
+        for diagnostic in file.diagnostics.iter() {
+            write!(io.stderr, "{}", diagnostic.render_with(&path, &file.source, io.color))?;
+            failed |= diagnostic.level == Level::Error;
+        }
+        if let Some(program) = &file.program {
+            let title = file.path.file_name().map_or(path.to_string(), |name| name.to_string_lossy().into_owned());
+            pages.push(doc::Page { title, items: doc::items(program) });
+        }
+    }
+    if failed {
+        return Ok(1);
+    }
+    let text = if html { doc::html(&pages) } else { doc::markdown(&pages) };
+    match output {
+        Some(output) => {
+            if let Err(error) = fs::write(&output, text) {
+                writeln!(io.stderr, "error: can't write `{}`: {}", output, error)?;
+                return Ok(1);
+            }
+        },
+        None => write!(io.stdout, "{}", text)?,
+    }
+    Ok(0)
+}
+
+fn plural(count: usize, noun: &str) -> String {
+    format!("{} {}{}", count, noun, if count == 1 { "" } else { "s" })
+}
+
+/// `tbasic fmt`: rewrites each script in the standard layout. With
+/// `--check` nothing is written; the scripts that would change are listed
+/// and make the exit code 1.
+fn fmt(args: &[String], config: &Config, io: &mut Io) -> io::Result<i32> {
+    let mut dialect = config.parse.dialect;
+    let mut check = false;
+    let mut paths = Vec::new();
+    for arg in args {
+        match arg.as_str() {
+            "--classic" => dialect = Dialect::Classic,
+            "--check" => check = true,
+            _ => paths.push(arg.as_str()),
+        }
+    }
+    let entry = entry(config);
+    if paths.is_empty() {
+        paths.extend(entry.as_deref());
+    }
+    if paths.is_empty() {
+        write!(io.stderr, "{}", USAGE)?;
+        return Ok(2);
+    }
+    let mut code = 0;
+    for path in paths {
+        let source = match fs::read_to_string(path) {
+            Ok(source) => source,
+            Err(error) => {
+                writeln!(io.stderr, "error: can't read `{}`: {}", path, error)?;
+                code = 1;
+                continue;
+            },
+        };
+        let formatted = match formatter::format_with(&source, dialect, &config.format) {
+            Ok(formatted) => formatted,
+            Err(error) => {
+                write!(io.stderr, "{}", Diagnostic::from(error).render_with(path, &source, io.color))?;
+                code = 1;
+                continue;
+            },
+        };
+        if formatted == source {
+            continue;
+        }
+        if check {
+            writeln!(io.stdout, "{} is not formatted", path)?;
+            code = 1;
+        } else if let Err(error) = fs::write(path, formatted) {
+            writeln!(io.stderr, "error: can't write `{}`: {}", path, error)?;
+            code = 1;
+        }
+    }
+    Ok(code)
+}
+
+/// `tbasic tokens`: prints one JSON object per token the lexer makes of
+/// the script, e.g. `{"kind":"Id","text":"x","value":"x","start":0,...}`,
+/// with the span's character offsets and 1-based line and column. Literals
+/// and identifiers have a `value`.
+/// `tbasic explain`: prints the explanation of a diagnostic's code, or
+/// without one the codes and their titles.
+fn explain(args: &[String], io: &mut Io) -> io::Result<i32> {
+    match args {
+        [] => {
+            for code in CODES {
+                writeln!(io.stdout, "{}  {}", code.code, code.title)?;
+            }
+            Ok(0)
+        },
+        [code] => match codes::lookup(code) {
+            Some(code) => {
+                writeln!(io.stdout, "{}: {}\n\n{}", code.code, code.title, code.explanation)?;
+                Ok(0)
+            },
+            None => {
+                writeln!(io.stderr, "error: no diagnostic has the code `{}`", code)?;
+                Ok(1)
+            },
+        },
+        _ => {
+            write!(io.stderr, "{}", USAGE)?;
+            Ok(2)
+        },
+    }
+}
+
+fn tokens(args: &[String], config: &Config, io: &mut Io) -> io::Result<i32> {
+    let entry = entry(config);
+    let Some(path) = args.first().or(entry.as_ref()).filter(|_| args.len() <= 1) else {
+        write!(io.stderr, "{}", USAGE)?;
+        return Ok(2);
+    };
+    let source = match fs::read_to_string(path) {
+        Ok(source) => source,
+        Err(error) => {
+            writeln!(io.stderr, "error: can't read `{}`: {}", path, error)?;
+            return Ok(1);
+        },
+    };
+    let tokens = match Lexer::new(&source).tokenize_spanned() {
+        Ok(tokens) => tokens,
+        Err(error) => {
+            write!(io.stderr, "{}", Diagnostic::from(error).render_with(path, &source, io.color))?;
+            return Ok(1);
+        },
+    };
+    let chars: Vec<char> = source.chars().collect();
+    for token in tokens {
+        let span = token.span;
+        let mut entries = vec![
+            ("kind", Json::from(token.token.kind())),
+            ("text", Json::from(chars[span.start..span.end].iter().collect::<String>())),
+        ];
+        let value = match token.token {
+            Token::Number(n) => Some(Json::Int(n)),
+            Token::Float(n) => Some(Json::Float(n)),
+            Token::Str(s) | Token::Id(s) | Token::Doc(s) => Some(Json::String(s)),
+            _ => None,
+        };
+        entries.extend(value.map(|value| ("value", value)));
+        entries.extend([
+            ("start", Json::from(span.start)),
+            ("end", Json::from(span.end)),
+            ("line", Json::from(span.line)),
+            ("column", Json::from(span.column)),
+        ]);
+        writeln!(io.stdout, "{}", Json::object(entries))?;
+    }
+    Ok(0)
+}
+
+/// `tbasic ast`: prints the script's syntax tree in the chosen
+/// [`DumpFormat`], the tree by default.
+fn ast(args: &[String], config: &Config, io: &mut Io) -> io::Result<i32> {
+    let mut options = config.parse;
+    let mut format = DumpFormat::default();
+    let mut path = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--classic" => options.dialect = Dialect::Classic,
+            "--format" => match args.next().and_then(|name| DumpFormat::from_name(name)) {
+                Some(chosen) => format = chosen,
+                None => {
+                    write!(io.stderr, "{}", USAGE)?;
+                    return Ok(2);
+                },
+            },
+            _ if path.is_none() => path = Some(arg.as_str()),
+            _ => {
+                write!(io.stderr, "{}", USAGE)?;
+                return Ok(2);
+            },
+        }
+    }
+    let entry = entry(config);
+    let Some(path) = path.or(entry.as_deref()) else {
+        write!(io.stderr, "{}", USAGE)?;
+        return Ok(2);
+    };
+    let source = match fs::read_to_string(path) {
+        Ok(source) => source,
+        Err(error) => {
+            writeln!(io.stderr, "error: can't read `{}`: {}", path, error)?;
+            return Ok(1);
+        },
+    };
+    let mut diagnostics = Diagnostics::new();
+    let Some(program) = parse_into_with(&source, options, &mut diagnostics) else {
+        for diagnostic in diagnostics.iter() {
+            write!(io.stderr, "{}", diagnostic.render_with(path, &source, io.color))?;
+        }
+        return Ok(1);
+    };
+    write!(io.stdout, "{}", dump(&program, format))?;
+    Ok(0)
+}
+
+/// `tbasic repl`: evaluates each line as it is entered, echoing the value
+/// of expressions, until the input ends or the script calls `exit`. Input
+/// that leaves a bracket open continues on the next line, and lines
+/// starting with `:` are [commands](REPL_HELP). Prompts are only shown on a
+/// terminal.
+///
+/// `--record` writes each entry that ran without errors to a file, which
+/// can then be run as a script; commands aren't recorded. `--replay` enters
+/// the lines of a file before reading any input. The lines typed at a
+/// terminal are kept for later sessions in the [`default_history`] file,
+/// unless `history` in the `[repl]` table of tbasic.toml says otherwise.
+fn repl(args: &[String], config: &Config, io: &mut Io) -> io::Result<i32> {
+    let mut record = None;
+    let mut replay = VecDeque::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match (arg.as_str(), args.next()) {
+            ("--record", Some(path)) => match fs::File::create(path) {
+                Ok(file) => record = Some((path, file)),
+                Err(error) => {
+                    writeln!(io.stderr, "error: can't create `{}`: {}", path, error)?;
+                    return Ok(1);
+                },
+            },
+            ("--replay", Some(path)) => match fs::read_to_string(path) {
+                Ok(transcript) => replay.extend(transcript.lines().map(str::to_string)),
+                Err(error) => {
+                    writeln!(io.stderr, "error: can't read `{}`: {}", path, error)?;
+                    return Ok(1);
+                },
+            },
+            _ => {
+                write!(io.stderr, "{}", USAGE)?;
+                return Ok(2);
+            },
+        }
+    }
+    let mut repl = new_repl(io);
+    let mut editor = LineEditor::new();
+    // Only what is typed at a terminal is kept
+    let mut history = match &config.history {
+        _ if !io.terminal => None,
+        HistoryFile::Default => default_history(),
+        HistoryFile::Path(path) => Some(path.clone()),
+        HistoryFile::Off => None,
+    };
+    if let Some(path) = &history {
+        if let Err(error) = editor.load_history(path) {
+            writeln!(io.stderr, "error: can't read `{}`: {}", path.display(), error)?;
+            history = None;
+        }
+    }
+    if io.terminal {
+        writeln!(io.stdout, "tbasic {}, :help for commands, ctrl-d to quit", env!("CARGO_PKG_VERSION"))?;
+        io.stdout.flush()?;
+    }
+    // The lines of input that doesn't parse yet because it is incomplete
+    let mut pending = String::new();
+    loop {
+        let prompt = if pending.is_empty() { PROMPT } else { CONTINUATION_PROMPT };
+        // A replayed transcript is shown as if it was typed
+        let input = match replay.pop_front() {
+            Some(line) => {
+                if io.terminal {
+                    writeln!(io.stdout, "{}{}", prompt, line)?;
+                }
+                Input::Line(line)
+            },
+            None if io.terminal => {
+                let commands = REPL_HELP.lines().filter_map(|line| line.split_whitespace().next()).map(str::to_string);
+                editor.set_completions(repl.completions().into_iter().chain(commands));
+                editor.read_line(prompt)?
+            },
+            None => read_line(&mut io.stdin)?,
+        };
+        let source = match input {
+            Input::Line(line) => {
+                let entries = editor.history().len();
+                editor.add_history(&line);
+                if let Some(path) = history.as_ref().filter(|_| editor.history().len() > entries) {
+                    if let Err(error) = append_history(path, &line) {
+                        writeln!(io.stderr, "error: can't write `{}`: {}", path.display(), error)?;
+                        history = None;
+                    }
+                }
+                if pending.is_empty() && line.trim_start().starts_with(':') {
+                    if let Some(code) = repl_command(&mut repl, line.trim(), &mut editor, history.as_deref(), io)? {
+                        return Ok(code);
+                    }
+                    io.stdout.flush()?;
+                    continue;
+                }
+                pending.push_str(&line);
+                if is_incomplete(&pending, Dialect::Modern) {
+                    pending.push('\n');
+                    continue;
+                }
+                std::mem::take(&mut pending)
+            },
+            Input::Interrupted => {
+                pending.clear();
+                continue;
+            },
+            // Report what's wrong with input left unfinished
+            Input::Eof if !pending.is_empty() => std::mem::take(&mut pending),
+            Input::Eof => return Ok(0),
+        };
+        if source.trim().is_empty() {
+            continue;
+        }
+        match repl_eval(&mut repl, REPL_PATH, &source, io)? {
+            Outcome::Ran => {
+                // Only what ran is kept, so the transcript runs as a script
+                if let Some((path, file)) = &mut record {
+                    if let Err(error) = writeln!(file, "{}", source).and_then(|_| file.flush()) {
+                        writeln!(io.stderr, "error: can't write `{}`: {}", path, error)?;
+                        record = None;
+                    }
+                }
+            },
+            Outcome::Failed => {},
+            Outcome::Exit(code) => return Ok(code),
+        }
+        io.stdout.flush()?;
+    }
+}
+
+const REPL_HELP: &str = "\
+:vars           list the variables and their values
+:tokens <code>  show the tokens the lexer makes of <code>
+:ast <code>     show the syntax tree the parser makes of <code>
+:load <file>    run a script, keeping what it defines
+:clear          forget all variables and functions
+:history        list the lines entered, kept across sessions
+:history clear  forget them
+:help           show this message
+";
+
+/// Where the REPL keeps its history unless tbasic.toml says otherwise:
+/// `tbasic/history` in the platform's directory for application data.
+fn default_history() -> Option<PathBuf> {
+    let var = |name| std::env::var_os(name).filter(|value| !value.is_empty()).map(PathBuf::from);
+    let data = if cfg!(windows) {
+        var("APPDATA")?
+    } else if cfg!(target_os = "macos") {
+        var("HOME")?.join("Library").join("Application Support")
+    } else {
+        var("XDG_DATA_HOME").or_else(|| Some(var("HOME")?.join(".local").join("share")))?
+    };
+    Some(data.join("tbasic").join("history"))
+}
+
+/// A REPL whose prints go to stdout and whose imports are found in the
+/// working directory.
+fn new_repl(io: &Io) -> Repl {
+    let mut interpreter = Interpreter::with_output(Box::new(io.stdout.clone()));
+    interpreter.set_module_loader(Box::new(FileLoader::new(".")));
+    Repl::new(interpreter)
+}
+
+/// How evaluating a REPL entry went.
+enum Outcome {
+    Ran,
+    Failed,
+    Exit(i32),
+}
+
+/// Evaluates `source`, echoing its value or printing its errors.
+fn repl_eval(repl: &mut Repl, path: &str, source: &str, io: &mut Io) -> io::Result<Outcome> {
+    match repl.eval(source) {
+        Ok(value) => {
+            if let Some(text) = echo(&value) {
+                writeln!(io.stdout, "{}", text)?;
+            }
+            Ok(Outcome::Ran)
+        },
+        Err(EvalError::Syntax(diagnostics)) => {
+            for diagnostic in diagnostics.iter() {
+                write!(io.stderr, "{}", diagnostic.render_with(path, source, io.color))?;
+            }
+            Ok(Outcome::Failed)
+        },
+        Err(EvalError::Runtime(RuntimeError::Exit { code, .. })) => Ok(Outcome::Exit(code)),
+        Err(EvalError::Runtime(error)) => {
+            io.stdout.flush()?;
+            write!(io.stderr, "{}", Diagnostic::from(error).render_with(path, source, io.color))?;
+            Ok(Outcome::Failed)
+        },
+    }
+}
+
+/// Runs one of the REPL's `:` commands. Returns the exit code if a loaded
+/// script called `exit`.
+fn repl_command(
+    repl: &mut Repl,
+    line: &str,
+    editor: &mut LineEditor,
+    history: Option<&Path>,
+    io: &mut Io,
+) -> io::Result<Option<i32>> {
+    let (command, argument) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+    let argument = argument.trim();
+    match command {
+        ":vars" => {
+            for (name, value) in repl.interpreter().variables() {
+                writeln!(io.stdout, "{} = {}", name, echo(&value).unwrap_or_else(|| "nil".to_string()))?;
+            }
+        },
+        ":tokens" => match Lexer::new(argument).tokenize_spanned() {
+            Ok(tokens) => {
+                for token in tokens {
+                    let location = format!("{}:{}", token.span.line, token.span.column);
+                    writeln!(io.stdout, "{:<7} {:?}", location, token.token)?;
+                }
+            },
+            Err(error) => write!(io.stderr, "{}", Diagnostic::from(error).render_with(REPL_PATH, argument, io.color))?,
+        },
+        ":ast" => {
+            let mut diagnostics = Diagnostics::new();
+            match parse_into(argument, Dialect::Modern, &mut diagnostics) {
+                Some(program) => write!(io.stdout, "{}", dump(&program, DumpFormat::Tree))?,
+                None => {
+                    for diagnostic in diagnostics.iter() {
+                        write!(io.stderr, "{}", diagnostic.render_with(REPL_PATH, argument, io.color))?;
+                    }
+                },
+            }
+        },
+        ":load" if !argument.is_empty() => match fs::read_to_string(argument) {
+            Ok(source) => {
+                if let Outcome::Exit(code) = repl_eval(repl, argument, &source, io)? {
+                    return Ok(Some(code));
+                }
+            },
+            Err(error) => writeln!(io.stderr, "error: can't read `{}`: {}", argument, error)?,
+        },
+        ":clear" => *repl = new_repl(io),
+        ":history" if argument.is_empty() => {
+            for (i, entry) in editor.history().iter().enumerate() {
+                writeln!(io.stdout, "{:>4}  {}", i + 1, entry)?;
+            }
+        },
+        ":history" if argument == "clear" => {
+            editor.clear_history();
+            if let Some(path) = history {
+                if let Err(error) = fs::write(path, "") {
+                    writeln!(io.stderr, "error: can't write `{}`: {}", path.display(), error)?;
+                }
+            }
+        },
+        ":help" => write!(io.stdout, "{}", REPL_HELP)?,
+        _ => writeln!(io.stderr, "error: unknown command `{}`, :help lists the commands", line)?,
+    }
+    Ok(None)
+}
+
+/// Reads a line from input that isn't a terminal.
+fn read_line(input: &mut dyn BufRead) -> io::Result<Input> {
+    let mut line = String::new();
+    if input.read_line(&mut line)? == 0 {
+        return Ok(Input::Eof);
+    }
+    Ok(Input::Line(line.trim_end_matches(['\n', '\r']).to_string()))
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+    use std::io;
+    use std::path::Path;
+
+    use crate::cli::{main, modified, run_script, Io, RunOptions, SharedWriter, REPL_HELP};
+    use crate::interpreter::SharedBuffer;
+
+    fn tbasic(args: &[&str]) -> (i32, String, String) {
+        tbasic_with_input(args, "")
+    }
+
+    fn tbasic_with_input(args: &[&str], stdin: &str) -> (i32, String, String) {
+        tbasic_in(&std::env::temp_dir(), args, stdin)
+    }
+
+    /// Runs `tbasic` in the working directory `cwd`.
+    fn tbasic_in(cwd: &Path, args: &[&str], stdin: &str) -> (i32, String, String) {
+        let (stdout, stderr) = (SharedBuffer::new(), SharedBuffer::new());
+        let mut io = Io {
+            stdin: Box::new(io::Cursor::new(stdin.to_string())),
+            stdout: SharedWriter::new(Box::new(stdout.clone())),
+            stderr: Box::new(stderr.clone()),
+            terminal: false,
+            color: false,
+            cwd: cwd.to_path_buf(),
+        };
+        let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+        let code = main(&args, &mut io);
+        (code, stdout.contents(), stderr.contents())
+    }
+
+    fn script(name: &str, source: &str) -> String {
+        let dir = std::env::temp_dir().join(format!("tbasic-cli-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(name);
+        fs::write(&path, source).unwrap();
+        path.to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn test_run() {
+        script("greet.tb", "fn greet(name) {\n  return \"hello, \" + name\n}");
+        let path = script("main.tb", "import greet\nprint greet.greet(\"world\")\nexit(3)");
+        assert_eq!(tbasic(&["run", &path]), (3, "hello, world\n".to_string(), String::new()));
+
+        let path = script("classic.tb", "10 print \"hi\"\n20 goto 40\n30 print \"skipped\"\n40 print \"bye\"");
+        assert_eq!(tbasic(&["run", "--classic", &path]).1, "hi\nbye\n");
+
+        let path = script("shebang.tb", "#!/usr/bin/env tbasic\nprint \"direct\", args()");
+        assert_eq!(tbasic(&[&path, "-v", "--", "x"]), (0, "direct [\"-v\", \"--\", \"x\"]\n".to_string(), String::new()));
+
+        let path = script("status.tb", "fn count(items) {\n  return len(items)\n}\ncount(args())");
+        assert_eq!(tbasic(&["run", &path, "--", "a", "b"]).0, 2);
+        assert_eq!(tbasic(&["run", &path]).0, 0);
+        let path = script("status_str.tb", "x = 4\n\"not a code\"");
+        assert_eq!(tbasic(&["run", &path]).0, 0);
+
+        let path = script("args.tb", "print len(args()), args()");
+        assert_eq!(tbasic(&["run", &path]).1, "0 []\n");
+        assert_eq!(tbasic(&["run", &path, "--", "a b", "--bench"]).1, "2 [\"a b\", \"--bench\"]\n");
+    }
+
+    #[test]
+    fn test_bench() {
+        let path = script("bench.tb", "fn fib(n) {\n  if n < 2 { return n }\n  return fib(n - 1) + fib(n - 2)\n}\nprint fib(5)");
+        let (code, stdout, stderr) = tbasic(&["run", "--bench", &path]);
+        assert_eq!((code, stdout.as_str()), (0, "5\n"));
+        let report: Vec<&str> = stderr.lines().collect();
+        assert!(report[1].starts_with("wall time "), "{}", stderr);
+        assert_eq!(report[2..], ["statements     32", "allocations    not counted", "calls          15", "  fib          15"]);
+    }
+
+    #[test]
+    fn test_profile() {
+        script("twice.tb", "fn twice(x) {\n  return 2 * x\n}");
+        let path = script("profiled.tb", "import twice\ni = 0\nwhile i < 3 {\n  i = i + twice.twice(1) / 2\n}\nprint i");
+        let (code, stdout, stderr) = tbasic(&["run", "--profile", &path]);
+        assert_eq!((code, stdout.as_str()), (0, "3\n"));
+        let report: Vec<&str> = stderr.lines().collect();
+        assert!(report[1].starts_with("profile: ") && report[1].ends_with(" ms in 11 statements"), "{}", stderr);
+        assert_eq!(report[3], "   time (ms)       %         hits  line");
+        let mut lines: Vec<String> = report[4..].iter().map(|row| row.split_whitespace().skip(2).collect::<Vec<_>>().join(" ")).collect();
+        lines.sort();
+        let module = Path::new(&path).with_file_name("twice.tb");
+        assert_eq!(lines, [
+            format!("1 {}:1", path),
+            format!("1 {}:2", path),
+            format!("1 {}:3", path),
+            format!("1 {}:6", path),
+            format!("1 {}:1", module.display()),
+            format!("3 {}:4", path),
+            format!("3 {}:2", module.display()),
+        ]);
+    }
+
+    #[test]
+    fn test_coverage() {
+        let module = script("shapes.tb", "fn area(w, h) {\n  return w * h\n}\nfn unused() {\n  return 0\n}");
+        let path = script("covered.tb", "import shapes\nif shapes.area(2, 3) > 10 {\n  print \"big\"\n}\nprint \"done\"");
+        let lcov = format!("{}.info", path);
+        let (code, stdout, stderr) = tbasic(&["run", "--lcov", &lcov, &path]);
+        assert_eq!((code, stdout.as_str()), (0, "done\n"));
+        let width = module.len().max(path.len());
+        assert_eq!(stderr, format!("\
+\ncoverage:
+{:<width$}     3/4     75.0%  missed 3
+{:<width$}     3/4     75.0%  missed 5
+{:<width$}     6/8     75.0%
+", path, module, "total"));
+        let lcov = fs::read_to_string(lcov).unwrap();
+        assert!(lcov.starts_with(&format!("SF:{}\nDA:1,1\nDA:2,1\nDA:3,0\nDA:5,1\nLH:3\nLF:4\nend_of_record\n", path)), "{}", lcov);
+
+        assert_eq!(tbasic(&["run", "--lcov"]).0, 2);
+    }
+
+    #[test]
+    fn test_watched_files() {
+        script("greet.tb", "fn greet(name) {\n  return \"hello, \" + name\n}");
+        let path = script("watched.tb", "import greet\nimport missing");
+        let (stdout, stderr) = (SharedBuffer::new(), SharedBuffer::new());
+        let mut io = Io {
+            stdin: Box::new(io::empty()),
+            stdout: SharedWriter::new(Box::new(stdout.clone())),
+            stderr: Box::new(stderr.clone()),
+            terminal: false,
+            color: false,
+            cwd: std::env::temp_dir(),
+        };
+        let mut files = Vec::new();
+        assert_eq!(run_script(&path, &RunOptions::default(), &mut io, &mut files).unwrap(), 1);
+        let dir = Path::new(&path).parent().unwrap();
+        // A module that isn't there yet is watched for being created
+        assert_eq!(files, vec![dir.join("greet.tb"), dir.join("missing.tb")]);
+
+        let seen = modified(&files);
+        assert!(seen[0].is_some() && seen[1].is_none());
+        script("missing.tb", "");
+        assert_ne!(modified(&files), seen);
+    }
+
+    #[test]
+    fn test_errors() {
+        let path = script("fail.tb", "print 1\nx = 1 / 0");
+        let (code, stdout, stderr) = tbasic(&["run", &path]);
+        assert_eq!((code, stdout.as_str()), (1, "1\n"));
+        assert_eq!(stderr, format!("\
+error[E0303]: division by zero
+ --> {}:2:5
+  |
+2 | x = 1 / 0
+  |     ^^^^^
+", path));
+
+        let path = script("syntax.tb", "print (1");
+        let (code, stdout, stderr) = tbasic(&["run", &path]);
+        assert_eq!((code, stdout.as_str()), (1, ""));
+        assert!(stderr.starts_with("error[E0101]"), "{}", stderr);
+
+        assert_eq!(tbasic(&["run", "/nonexistent/script.tb"]).0, 1);
+        assert_eq!(tbasic(&["run"]).0, 2);
+        assert_eq!(tbasic(&["frobnicate"]).0, 2);
+        assert_eq!(tbasic(&["help"]).0, 0);
+    }
+
+    #[test]
+    fn test_debug() {
+        let path = script("debugged.tb", "fn f(n) {\n  m = n * 2\n  return m\n}\nx = f(1)\nprint x\ny = f(x)\nprint y");
+        let commands = "break 3\ncontinue\nbt\np m\nnext\nnext\n\np x\np z\nfoo\nclear 3\nc\n";
+        let (code, stdout, stderr) = tbasic_with_input(&["debug", &path], commands);
+        assert_eq!(code, 0);
+        assert_eq!(stdout, "\
+stopped at 1:1
+1 | fn f(n) {
+breakpoint on line 3
+stopped at 3:3
+3 |   return m
+#0 f at 3:3
+#1 <main> at 5:5
+m = 2
+stopped at 6:1
+6 | print x
+2
+stopped at 7:1
+7 | y = f(x)
+stopped at 3:3
+3 |   return m
+x = 2
+cleared the breakpoint on line 3
+4
+");
+        assert_eq!(stderr, "error: no variable `z`\nerror: unknown command `foo`, `help` lists the commands\n");
+
+        // Steps go into calls, and the script runs on when the commands run out
+        let (code, stdout, _) = tbasic_with_input(&["debug", &path], "step\nstep\np n\n");
+        assert_eq!((code, stdout.lines().nth(6)), (0, Some("n = 1")));
+        assert!(stdout.ends_with("n = 1\n2\n4\n"), "{}", stdout);
+        assert_eq!(tbasic_with_input(&["debug", &path], "quit\n"), (1, "stopped at 1:1\n1 | fn f(n) {\n".to_string(), String::new()));
+        assert_eq!(tbasic(&["debug"]).0, 2);
+    }
+
+    #[test]
+    fn test_test() {
+        let dir = std::env::temp_dir().join(format!("tbasic-cli-{}", std::process::id())).join("tests");
+        fs::create_dir_all(dir.join("nested")).unwrap();
+        fs::write(dir.join("math_test.tb"), "assert(1 + 1 == 2)\nprint \"math\"").unwrap();
+        fs::write(dir.join("nested/strings_test.tb"), "s = \"ab\"\nassert(len(s) == 3, \"length\")").unwrap();
+        fs::write(dir.join("helper.tb"), "assert(false)").unwrap();
+        let dir = dir.to_string_lossy().into_owned();
+        let (code, stdout, stderr) = tbasic(&["test", &dir]);
+        assert_eq!(code, 1);
+        assert_eq!(stdout, format!("math\nok     {0}/math_test.tb\nFAILED {0}/nested/strings_test.tb\n\n1 passed, 1 failed\n", dir));
+        assert_eq!(stderr, format!("\
+error[E0324]: assertion failed: length
+ --> {}/nested/strings_test.tb:2:1
+  |
+2 | assert(len(s) == 3, \"length\")
+  | ^^^^^^^^^^^^^^^^^^^^^^^^^^^^^
+", dir));
+
+        assert_eq!(tbasic(&["test", &format!("{}/math_test.tb", dir)]).0, 0);
+        assert_eq!(tbasic(&["test", "/nonexistent"]).0, 1);
+    }
+
+    #[test]
+    fn test_check() {
+        let clean = script("clean.tb", "x = 1\nprint x\n");
+        let truthy = script("truthy.tb", "s = \"abc\"\nwhile 1 {\n  if len(s) {\n    break\n  }\n}\n");
+        let warned = script("warned.tb", "fn f() {\n  y = 1\n}\nf()\n");
+        let failing = script("failing.tb", "print \"a\" - 1\n");
+        assert_eq!(tbasic(&["check", &clean]), (0, String::new(), String::new()));
+        assert_eq!(tbasic(&["check", &truthy]), (0, String::new(), String::new()));
+
+        let (code, stdout, stderr) = tbasic(&["check", &warned]);
+        assert_eq!((code, stdout.as_str()), (0, ""));
+        assert!(stderr.starts_with("warning[W0001]"), "{}", stderr);
+        assert!(stderr.ends_with("0 errors, 1 warning\n"), "{}", stderr);
+        assert_eq!(tbasic(&["check", "--deny-warnings", &warned]).0, 1);
+
+        let (code, _, stderr) = tbasic(&["check", &clean, &failing, "/nonexistent.tb"]);
+        assert_eq!(code, 1);
+        assert!(stderr.contains(&format!("error[E0203]: cannot subtract int from string\n --> {}:1:7\n", failing)), "{}", stderr);
+        assert!(stderr.ends_with("2 errors, 0 warnings\n"), "{}", stderr);
+
+        let broken = script("unparsable.tb", "x = (");
+        assert_eq!(tbasic(&["check", &broken]).0, 1);
+        assert_eq!(tbasic(&["check"]).0, 2);
+    }
+
+    #[test]
+    fn test_fmt() {
+        let messy = script("messy.tb", "x=1\nif x{print x}");
+        let tidy = script("tidy.tb", "x = 1\n");
+        let broken = script("broken.tb", "x = (");
+        assert_eq!(tbasic(&["fmt", "--check", &messy, &tidy]), (1, format!("{} is not formatted\n", messy), String::new()));
+        assert_eq!(tbasic(&["fmt", &messy, &tidy]), (0, String::new(), String::new()));
+        assert_eq!(fs::read_to_string(&messy).unwrap(), "x = 1\nif x {\n  print x\n}\n");
+        assert_eq!(tbasic(&["fmt", "--check", &messy]).0, 0);
+
+        let (code, _, stderr) = tbasic(&["fmt", &broken]);
+        assert_eq!(code, 1);
+        assert!(stderr.starts_with("error[E0101]"), "{}", stderr);
+        assert_eq!(fs::read_to_string(&broken).unwrap(), "x = (");
+        assert_eq!(tbasic(&["fmt", "--check"]).0, 2);
+    }
+
+    #[test]
+    fn test_eval() {
+        assert_eq!(tbasic(&["-e", "1 + 2 * 3"]), (0, "7\n".to_string(), String::new()));
+        assert_eq!(tbasic(&["-e", "print \"hi\"\nupper(args()[0])", "ab"]), (0, "hi\n\"AB\"\n".to_string(), String::new()));
+        assert_eq!(tbasic(&["-e", "exit(4)"]).0, 4);
+        let (code, _, stderr) = tbasic(&["-e", "1 / 0"]);
+        assert_eq!(code, 1);
+        assert!(stderr.starts_with("error[E0303]: division by zero\n --> <eval>:1:1\n"), "{}", stderr);
+
+        assert_eq!(tbasic_with_input(&["-"], "print 42\nx = 1"), (0, "42\n".to_string(), String::new()));
+        let (code, _, stderr) = tbasic_with_input(&["-"], "print (");
+        assert_eq!(code, 1);
+        assert!(stderr.contains(" --> <stdin>:1:7\n"), "{}", stderr);
+    }
+
+    #[test]
+    fn test_config() {
+        let root = std::env::temp_dir().join(format!("tbasic-project-{}", std::process::id()));
+        let sub = root.join("sub");
+        fs::create_dir_all(&sub).unwrap();
+        let config = "entry = \"main.tb\"\ndialect = \"classic\"\n\n[lint]\nmagic-numbers = \"deny\"\n\n[fmt]\nindent = 4\n";
+        fs::write(root.join("tbasic.toml"), config).unwrap();
+        fs::write(root.join("main.tb"), "10 x = 7\n20 if x > 5 {\n  print x\n}").unwrap();
+
+        // Found from a directory below, with the entry run when no script is given
+        assert_eq!(tbasic_in(&sub, &["run"], ""), (0, "7\n".to_string(), String::new()));
+        let (code, _, stderr) = tbasic_in(&sub, &["check"], "");
+        assert_eq!(code, 1);
+        assert!(stderr.starts_with("error[W0103]: magic number 5"), "{}", stderr);
+        assert_eq!(tbasic_in(&sub, &["fmt", "--check"], "").0, 1);
+        let (code, stdout, _) = tbasic_in(&sub, &["tokens"], "");
+        assert_eq!(code, 0);
+        assert!(stdout.starts_with("{\"kind\":\"Number\",\"text\":\"10\""), "{}", stdout);
+        let (code, stdout, _) = tbasic_in(&sub, &["ast", "--format", "sexpr"], "");
+        assert_eq!(code, 0);
+        assert!(stdout.starts_with("(program (statements (line 10 (assign x 7))"), "{}", stdout);
+        let main = root.join("main.tb").to_string_lossy().into_owned();
+        let (code, _, stderr) = tbasic_in(&sub, &["check", &main], "");
+        assert_eq!(code, 1);
+        assert!(stderr.starts_with("error[W0103]: magic number 5"), "{}", stderr);
+        assert_eq!(tbasic_in(&sub, &["fmt", &main], "").0, 0);
+        assert_eq!(fs::read_to_string(&main).unwrap(), "10 x = 7\n20 if x > 5 {\n    print x\n}\n");
+
+        fs::write(root.join("tbasic.toml"), "strict = 1\n").unwrap();
+        let (code, _, stderr) = tbasic_in(&sub, &["run", &main], "");
+        let path = root.join("tbasic.toml");
+        assert_eq!((code, stderr), (1, format!("error: {}:1: `strict` must be true or false\n", path.display())));
+    }
+
+    #[test]
+    fn test_build() {
+        let root = std::env::temp_dir().join(format!("tbasic-build-project-{}", std::process::id()));
+        fs::create_dir_all(root.join("out")).unwrap();
+        fs::write(root.join("tbasic.toml"), "entry = \"main.tb\"\n").unwrap();
+        fs::write(root.join("main.tb"), "import util\nprint util.twice(args()[0])").unwrap();
+        fs::write(root.join("util.tb"), "fn twice(x) {\n  return x + x\n}").unwrap();
+
+        let output = root.join("out").join("app.tbc").to_string_lossy().into_owned();
+        let (code, stdout, stderr) = tbasic_in(&root, &["build", "--output", &output], "");
+        assert_eq!((code, stdout, stderr), (0, String::new(), format!("built `{}` from 2 files\n", output)));
+        // The modules come with the program
+        fs::remove_file(root.join("util.tb")).unwrap();
+        assert_eq!(tbasic(&["run", &output, "--", "ab"]), (0, "abab\n".to_string(), String::new()));
+        // Compiled code's profile counts instructions
+        let (code, _, stderr) = tbasic(&["run", "--profile", &output, "--", "ab"]);
+        assert_eq!(code, 0);
+        assert!(stderr.starts_with("\nprofile: ") && stderr.contains(" instructions\n\nhot lines:\n  line 2 "), "{}", stderr);
+
+        let (code, _, stderr) = tbasic_in(&root, &["build", "--output", &output], "");
+        assert_eq!(code, 1);
+        assert!(stderr.starts_with("error[E0320]: cannot import `util`: no such module\n --> "), "{}", stderr);
+        assert!(stderr.ends_with("1 error, 0 warnings\n"), "{}", stderr);
+    }
+
+    #[test]
+    fn test_doc() {
+        script("geometry.tb", "## Twice `x`.\nfn twice(x: int): int {\n  return 2 * x\n}");
+        let path = script("documented.tb", "import geometry\nstruct Point { x, y }\nprint geometry.twice(1)");
+        let (code, stdout, stderr) = tbasic(&["doc", &path]);
+        assert_eq!((code, stderr.as_str()), (0, ""));
+        assert_eq!(stdout, "\
+# documented.tb
+
+## Point
+
+    struct Point { x, y }
+
+**Fields**
+
+- `x`
+- `y`
+
+# geometry.tb
+
+## twice
+
+    fn twice(x: int): int
+
+Twice `x`.
+
+**Parameters**
+
+- `x`: `int`
+
+**Returns** `int`
+");
+        let html = format!("{}.html", path);
+        assert_eq!(tbasic(&["doc", "--html", "--output", &html, &path]), (0, String::new(), String::new()));
+        assert!(fs::read_to_string(&html).unwrap().contains("<h2 id=\"geometry.tb-twice\">twice</h2>\n"));
+
+        let path = script("misdocumented.tb", "## x\nx = 1");
+        let (code, stdout, stderr) = tbasic(&["doc", &path]);
+        assert_eq!((code, stdout.as_str()), (1, ""));
+        assert!(stderr.starts_with("error[E0110]: doc comment must come before a named function or a struct\n"), "{}", stderr);
+    }
+
+    #[test]
+    fn test_explain() {
+        let (code, stdout, stderr) = tbasic(&["explain", "e0203"]);
+        assert_eq!((code, stderr.as_str()), (0, ""));
+        assert!(stdout.starts_with("E0203: type mismatch\n\nA value's type doesn't fit"), "{}", stdout);
+        assert!(stdout.contains("\n    x: int = \"one\"\n"), "{}", stdout);
+
+        let (code, stdout, _) = tbasic(&["explain"]);
+        assert_eq!(code, 0);
+        assert!(stdout.starts_with("E0001  unterminated string\nE0002  invalid escape sequence\n"), "{}", stdout);
+
+        let (code, _, stderr) = tbasic(&["explain", "E9999"]);
+        assert_eq!((code, stderr.as_str()), (1, "error: no diagnostic has the code `E9999`\n"));
+    }
+
+    #[test]
+    fn test_tokens() {
+        let path = script("lexed.tb", "x = \"a\\n\"\nprint x, 1.5");
+        let (code, stdout, stderr) = tbasic(&["tokens", &path]);
+        assert_eq!((code, stderr.as_str()), (0, ""));
+        assert_eq!(stdout, r#"{"kind":"Id","text":"x","value":"x","start":0,"end":1,"line":1,"column":1}
+{"kind":"Assign","text":"=","start":2,"end":3,"line":1,"column":3}
+{"kind":"Str","text":"\"a\\n\"","value":"a\n","start":4,"end":9,"line":1,"column":5}
+{"kind":"Newline","text":"\n","start":9,"end":10,"line":1,"column":10}
+{"kind":"Print","text":"print","start":10,"end":15,"line":2,"column":1}
+{"kind":"Id","text":"x","value":"x","start":16,"end":17,"line":2,"column":7}
+{"kind":"Comma","text":",","start":17,"end":18,"line":2,"column":8}
+{"kind":"Float","text":"1.5","value":1.5,"start":19,"end":22,"line":2,"column":10}
+"#);
+        let bad = script("badtoken.tb", "x = $");
+        let (code, _, stderr) = tbasic(&["tokens", &bad]);
+        assert_eq!(code, 1);
+        assert!(stderr.starts_with("error[E0003]"), "{}", stderr);
+        assert_eq!(tbasic(&["tokens"]).0, 2);
+    }
+
+    #[test]
+    fn test_ast() {
+        let path = script("tree.tb", "print 1 + 2");
+        assert_eq!(tbasic(&["ast", "--format", "sexpr", &path]).1, "(program (statements (print (args (+ 1 2)))))\n");
+        assert!(tbasic(&["ast", &path]).1.starts_with("Program @1:1\n  statements:\n    Print @1:1\n"));
+        assert!(tbasic(&["ast", "--format", "json", &path]).1.starts_with("{\"kind\":\"Program\""));
+
+        let classic = script("tree-classic.tb", "10 goto 10");
+        assert_eq!(tbasic(&["ast", "--classic", "--format", "sexpr", &classic]).1, "(program (statements (line 10 (goto 10))))\n");
+        assert_eq!(tbasic(&["ast", &classic]).0, 1);
+        assert_eq!(tbasic(&["ast", "--format", "yaml", &path]).0, 2);
+    }
+
+    #[test]
+    fn test_repl() {
+        let input = "x = 2\nfn sq(n) { return n * n }\n\nsq(x)\nprint \"hi\"\nx +\nx / 0\nupper(\"ab\")\n";
+        let (code, stdout, stderr) = tbasic_with_input(&["repl"], input);
+        assert_eq!((code, stdout.as_str()), (0, "4\nhi\n\"AB\"\n"));
+        assert!(stderr.starts_with("error[E0101]"), "{}", stderr);
+        assert!(stderr.contains("error[E0303]: division by zero\n --> <repl>:1:1\n"), "{}", stderr);
+
+        assert_eq!(tbasic_with_input(&["repl"], "exit(4)\nprint 1\n"), (4, String::new(), String::new()));
+
+        // Open brackets continue onto the next line
+        let input = "fn inc(n) {\n  return n + 1\n}\nprint inc(max(1,\n  2))\nx = (1 +\n";
+        let (code, stdout, stderr) = tbasic_with_input(&["repl"], input);
+        assert_eq!((code, stdout.as_str()), (0, "3\n"));
+        assert!(stderr.starts_with("error[E0101]: expected expression, found end of input\n --> <repl>:1:8\n"), "{}", stderr);
+        assert_eq!(tbasic(&["repl", "extra"]).0, 2);
+    }
+
+    #[test]
+    fn test_record_replay() {
+        let transcript = std::env::temp_dir().join(format!("tbasic-cli-{}", std::process::id())).join("session.tb");
+        let transcript = transcript.to_string_lossy().into_owned();
+        script("session.tb", "");
+        let input = "x = 2\nfn sq(n) {\n  return n * n\n}\n:vars\nprint sq(x)\nsq(y)\nx = (\n";
+        let (code, stdout, _) = tbasic_with_input(&["repl", "--record", &transcript], input);
+        assert_eq!((code, stdout.as_str()), (0, "sq = <fn sq>\nx = 2\n4\n"));
+        assert_eq!(fs::read_to_string(&transcript).unwrap(), "x = 2\nfn sq(n) {\n  return n * n\n}\nprint sq(x)\n");
+        assert_eq!(tbasic(&["run", &transcript]).1, "4\n");
+
+        // The replayed session's definitions are there for what's typed next
+        let (code, stdout, stderr) = tbasic_with_input(&["repl", "--replay", &transcript], "sq(x + 1)\n");
+        assert_eq!((code, stdout.as_str(), stderr.as_str()), (0, "4\n9\n", ""));
+        assert_eq!(tbasic(&["repl", "--replay", "/nonexistent.tb"]).0, 1);
+        assert_eq!(tbasic(&["repl", "--record"]).0, 2);
+    }
+
+    #[test]
+    fn test_repl_history() {
+        let (code, stdout, stderr) = tbasic_with_input(&["repl"], "x = 1\n:history\n:history clear\n:history\n");
+        assert_eq!((code, stdout.as_str(), stderr.as_str()), (0, "   1  x = 1\n   2  :history\n   1  :history\n", ""));
+    }
+
+    #[test]
+    fn test_repl_commands() {
+        let path = script("defs.tb", "fn twice(n) {\n  return 2 * n\n}\nloaded = \"yes\"");
+        let input = format!(":load {}\nx = twice(4)\n:vars\n:clear\n:vars\n:tokens x + 1\n:ast -x\n:help\n:bogus\n:load\n", path);
+        let (code, stdout, stderr) = tbasic_with_input(&["repl"], &input);
+        assert_eq!(code, 0);
+        let expected = "\
+loaded = \"yes\"
+twice = <fn twice>
+x = 8
+1:1     Id(\"x\")
+1:3     Plus
+1:5     Number(1)
+Program @1:1
+  statements:
+    Expr @1:1
+      expr: Unary op=\"-\" @1:1
+        operand: Variable name=\"x\" @1:2
+";
+        assert!(stdout.starts_with(expected), "{}", stdout);
+        assert!(stdout.ends_with(REPL_HELP));
+        assert_eq!(stderr, "\
+error: unknown command `:bogus`, :help lists the commands
+error: unknown command `:load`, :help lists the commands
+");
+    }
+}
@@ -0,0 +1,791 @@
+/// A stable code identifying a kind of diagnostic, shown in brackets after
+/// `error` or `warning` and explained by `tbasic explain`. Codes never
+/// change meaning: `E00xx` are lexer errors, `E01xx` syntax errors, `E02xx`
+/// type errors, `E03xx` runtime errors, `W00xx` warnings of
+/// [`check`](crate::check) and `W01xx` the built-in lint rules.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Code {
+    pub code: &'static str,
+    pub title: &'static str,
+    /// What the diagnostic means and how to fix it, with examples indented
+    /// by four spaces.
+    pub explanation: &'static str,
+}
+
+/// Looks up `code`, ignoring case, so `e0203` finds `E0203`.
+pub fn lookup(code: &str) -> Option<&'static Code> {
+    CODES.iter().find(|entry| entry.code.eq_ignore_ascii_case(code))
+}
+
+/// The code of a [`Warning`](crate::check::Warning) or built-in lint rule
+/// from its name, e.g. `W0001` for `unused-variable`.
+pub fn named(name: &str) -> Option<&'static str> {
+    let code = match name {
+        "unused-variable" => "W0001",
+        "unused-function" => "W0002",
+        "shadowed-global" => "W0003",
+        "unreachable-code" => "W0004",
+        "constant-overflow" => "W0005",
+        "read-before-assignment" => "W0006",
+        "maybe-uninitialized" => "W0007",
+        "shadowing" => "W0101",
+        "let-shadowing" => "W0102",
+        "magic-numbers" => "W0103",
+        "deep-nesting" => "W0104",
+        _ => return None,
+    };
+    Some(code)
+}
+
+/// Every code, in order.
+pub const CODES: &[Code] = &[
+    Code {
+        code: "E0001",
+        title: "unterminated string",
+        explanation: "\
+A string literal has no closing quote, so it runs to the end of the file.
+
+    print \"hello
+
+Close the string on the line it starts on:
+
+    print \"hello\"",
+    },
+    Code {
+        code: "E0002",
+        title: "invalid escape sequence",
+        explanation: "\
+A backslash in a string is followed by a character that doesn't form an
+escape sequence. The escapes are `\\n`, `\\t`, `\\\"` and `\\\\`.
+
+    print \"C:\\data\"
+
+Write a literal backslash as `\\\\`:
+
+    print \"C:\\\\data\"",
+    },
+    Code {
+        code: "E0003",
+        title: "unexpected character",
+        explanation: "\
+The source contains a character that doesn't start any token, such as `@`
+or a single `#`, or a single `&` or `|` where `&&` or `||` was meant.
+Comments start with `##`.
+
+    if a & b {
+      print 1
+    }
+
+The logical operators are written twice:
+
+    if a && b {
+      print 1
+    }",
+    },
+    Code {
+        code: "E0004",
+        title: "integer literal is too large",
+        explanation: "\
+An integer literal doesn't fit in a 64-bit int, whose largest value is
+9223372036854775807.
+
+    x = 10000000000000000000
+
+Use a float literal for numbers this large:
+
+    x = 10000000000000000000.0",
+    },
+    Code {
+        code: "E0101",
+        title: "unexpected token",
+        explanation: "\
+The parser found a token where the grammar doesn't allow it, or the input
+ended in the middle of a statement. The message names what was expected.
+
+    print (1 + 2
+
+Add what is missing, here the closing parenthesis:
+
+    print (1 + 2)",
+    },
+    Code {
+        code: "E0102",
+        title: "invalid assignment target",
+        explanation: "\
+The left side of `=` is something that can't be assigned to. Only
+variables, indexes like `a[0]` and fields like `p.x` can.
+
+    f(x) = 1
+    1 + 2 = x
+
+To compare values, use `==`.",
+    },
+    Code {
+        code: "E0103",
+        title: "assignment to an undeclared variable",
+        explanation: "\
+With strict declarations, a variable has to be declared with `let` before
+plain `=` assigns it, so a typo can't silently create a new variable.
+
+    let count = 0
+    cuont = count + 1
+
+Declare new variables with `let`, and check the name of existing ones.",
+    },
+    Code {
+        code: "E0104",
+        title: "duplicate definition",
+        explanation: "\
+A name is defined twice where it has to be unique: a parameter of a
+function, a field of a struct, or a function in the same block.
+
+    fn add(x, x) {
+      return x + x
+    }
+    struct Point { x, x }
+
+Rename one of them, or remove the second definition.",
+    },
+    Code {
+        code: "E0105",
+        title: "`break` or `continue` outside of a loop",
+        explanation: "\
+`break` and `continue` only make sense inside a `while` loop. A function
+body doesn't belong to the loop that calls it.
+
+    while true {
+      f = fn() {
+        break
+      }
+    }
+
+Return a value from the function and break in the loop instead.",
+    },
+    Code {
+        code: "E0106",
+        title: "`return` outside of a function",
+        explanation: "\
+`return` leaves a function, so it can't appear at the top level of a
+script. In the classic dialect it returns from a `gosub` instead.
+
+    x = 1
+    return x
+
+To end a script early, call `exit()`.",
+    },
+    Code {
+        code: "E0107",
+        title: "classic-only keyword",
+        explanation: "\
+`goto` and `gosub` jump to numbered lines, which only the classic dialect
+has.
+
+    gosub 100
+
+Run the script with `--classic` and number its lines, or use functions
+and loops:
+
+    10 gosub 100
+    20 exit()
+    100 print \"hello\"
+    110 return",
+    },
+    Code {
+        code: "E0108",
+        title: "invalid line number",
+        explanation: "\
+In the classic dialect every statement starts with a line number, each
+used only once, and `goto` and `gosub` are followed by one.
+
+    10 print \"a\"
+    10 print \"b\"
+    print \"c\"
+
+Number every line, leaving gaps for lines added later.",
+    },
+    Code {
+        code: "E0109",
+        title: "`goto` or `gosub` inside a function",
+        explanation: "\
+A jump can't leave or enter a function, since it would skip the function's
+return.
+
+    fn f() {
+      goto 100
+    }
+
+Call another function, or move the jump to the top level.",
+    },
+    Code {
+        code: "E0110",
+        title: "misplaced doc comment",
+        explanation: "\
+A `##` doc comment documents the named function or struct on the line
+after it, which `tbasic doc` lists. Anything else can't follow one.
+
+    ## The number of tries.
+    tries = 3
+
+Use a doc comment only right before `fn name(...)` or `struct Name`.",
+    },
+    Code {
+        code: "E0111",
+        title: "nested too deeply",
+        explanation: "\
+Expressions or blocks nest more levels deep than the parser allows, 64 by
+default, which keeps untrusted source from exhausting the stack. A block
+counts as two levels.
+
+    x = ((((((((((1))))))))))
+
+Split the expression with variables, or move nested blocks into functions.
+An embedding program sets the limit with `Sandbox::max_nesting`.",
+    },
+    Code {
+        code: "E0201",
+        title: "unknown type",
+        explanation: "\
+A type annotation names a type that doesn't exist. The types are `any`,
+`nil`, `int`, `float`, `bool`, `string`, `array`, `map`, `function`,
+`module` and the structs the script defines.
+
+    x: integer = 1
+
+Use one of the known types:
+
+    x: int = 1",
+    },
+    Code {
+        code: "E0202",
+        title: "conflicting types",
+        explanation: "\
+In a function with annotated parameters, a variable or the return type
+without an annotation is used with two types the checker can't
+reconcile: a variable first assigned an int and later a string, or a
+function returning both. Elsewhere such values are taken to be `any`.
+
+    fn label(n: int) {
+      x = n
+      x = \"one\"
+    }
+
+Annotate it with `: any` to allow both:
+
+    fn label(n: int) {
+      x: any = n
+      x = \"one\"
+    }",
+    },
+    Code {
+        code: "E0203",
+        title: "type mismatch",
+        explanation: "\
+A value's type doesn't fit where it is used: it is assigned to a variable
+annotated with another type, passed as an argument of another type,
+returned from a function annotated to return something else, used as a
+condition while annotated as something other than a bool, or combined
+with an operator that doesn't apply to it.
+
+    x: int = \"one\"
+    y = \"count: \" + 3
+    fn show(a: array) {
+      if a {
+        print a
+      }
+    }
+
+Convert the value, or change the annotation:
+
+    x: int = 1
+    y = format(\"count: {}\", 3)
+    fn show(a: array) {
+      if len(a) > 0 {
+        print a
+      }
+    }",
+    },
+    Code {
+        code: "E0204",
+        title: "wrong number of arguments",
+        explanation: "\
+A function is called with more or fewer arguments than it takes. The
+checker knows the parameters of the script's functions and structs and of
+the builtins.
+
+    fn add(a, b) {
+      return a + b
+    }
+    print add(1)
+
+Pass one argument per parameter:
+
+    print add(1, 2)",
+    },
+    Code {
+        code: "E0301",
+        title: "undefined variable",
+        explanation: "\
+A variable was read before anything assigned it, or its name is
+misspelled. Inside a function, only its parameters, its own variables and
+the globals are visible.
+
+    total = 0
+    print totl
+
+Assign the variable first, or fix the name.",
+    },
+    Code {
+        code: "E0302",
+        title: "type mismatch at run time",
+        explanation: "\
+An operation got a value of a type it doesn't work on, such as adding a
+string to an int or indexing a number. Unlike E0203 this was found while
+running, because the types weren't known before.
+
+    a = [1, 2]
+    print a + 1
+
+Check the value's type, or convert it first.",
+    },
+    Code {
+        code: "E0303",
+        title: "division by zero",
+        explanation: "\
+An int was divided by zero, which has no result.
+
+    print 1 / 0
+
+Check the divisor before dividing:
+
+    if n != 0 {
+      print total / n
+    }",
+    },
+    Code {
+        code: "E0304",
+        title: "integer overflow",
+        explanation: "\
+An int operation's result doesn't fit in 64 bits. The interpreter can be
+set to wrap, saturate or switch to big integers instead of failing.
+
+    print 9223372036854775807 + 1
+
+Use floats for numbers this large.",
+    },
+    Code {
+        code: "E0305",
+        title: "call to an undefined function",
+        explanation: "\
+A call names a function that is neither defined by the script nor a
+builtin, or the function is defined in a block that has ended.
+
+    print sqaure(2)
+
+Define the function first, or fix the name.",
+    },
+    Code {
+        code: "E0306",
+        title: "value is not callable",
+        explanation: "\
+Something that isn't a function was called, often a variable that shadows
+a function of the same name.
+
+    len = 3
+    print len(\"abc\")
+
+Give the variable another name.",
+    },
+    Code {
+        code: "E0307",
+        title: "wrong number of arguments at run time",
+        explanation: "\
+A function was called with the wrong number of arguments, found while
+running because the function wasn't known before, for example when it was
+stored in a variable.
+
+    f = fn(a, b) {
+      return a + b
+    }
+    print f(1)
+
+Pass one argument per parameter.",
+    },
+    Code {
+        code: "E0308",
+        title: "invalid argument",
+        explanation: "\
+A builtin got an argument of the right type that it can't work with, such
+as a negative number to `sqrt` or an empty array to `pop`. The message
+says what was wrong.
+
+    print sqrt(-1)
+
+Check the argument before the call.",
+    },
+    Code {
+        code: "E0309",
+        title: "index out of bounds",
+        explanation: "\
+An array index is negative or not less than the array's length. Indexes
+start at 0.
+
+    a = [1, 2, 3]
+    print a[3]
+
+Check the index against `len(a)` first.",
+    },
+    Code {
+        code: "E0310",
+        title: "missing map key",
+        explanation: "\
+A map was indexed with a key it doesn't have.
+
+    ages = {\"ann\": 31}
+    print ages[\"bob\"]
+
+Check for the key with `has` first:
+
+    if has(ages, \"bob\") {
+      print ages[\"bob\"]
+    }",
+    },
+    Code {
+        code: "E0311",
+        title: "missing struct field",
+        explanation: "\
+A field was read or assigned that the struct doesn't declare.
+
+    struct Point { x, y }
+    p = Point(1, 2)
+    print p.z
+
+Use one of the declared fields, or add the field to the struct.",
+    },
+    Code {
+        code: "E0312",
+        title: "stack overflow",
+        explanation: "\
+Calls nested deeper than the interpreter's limit, usually because a
+recursive function never reaches its base case.
+
+    fn count(n) {
+      return count(n + 1)
+    }
+
+Make sure every recursion ends, or rewrite it as a loop.",
+    },
+    Code {
+        code: "E0313",
+        title: "out of fuel",
+        explanation: "\
+The script ran more statements than the fuel it was given allows, as set
+by a sandbox or the embedding program to stop runaway scripts.
+
+    while true {
+    }
+
+Make sure loops end, or give the script more fuel.",
+    },
+    Code {
+        code: "E0314",
+        title: "cancelled",
+        explanation: "\
+The program running the script cancelled it, for example because it
+stopped waiting for its result. The script itself did nothing wrong.",
+    },
+    Code {
+        code: "E0315",
+        title: "undefined line",
+        explanation: "\
+A `goto` or `gosub` in a classic script jumps to a line number that no
+statement has.
+
+    10 goto 30
+    20 print \"done\"
+
+Jump to an existing line.",
+    },
+    Code {
+        code: "E0316",
+        title: "`return` without `gosub`",
+        explanation: "\
+A classic script reached `return` when no `gosub` was waiting for it,
+often by running into a subroutine instead of jumping to it.
+
+    10 print \"main\"
+    20 print \"sub\"
+    30 return
+
+End the main part with `exit()` before the subroutines start.",
+    },
+    Code {
+        code: "E0317",
+        title: "aborted by the debugger",
+        explanation: "\
+The script was stopped from a debugger, for example with `quit` in
+`tbasic debug`. The script itself did nothing wrong.",
+    },
+    Code {
+        code: "E0318",
+        title: "io error",
+        explanation: "\
+Reading or writing a file or the terminal failed. The message has the
+error the operating system reported.
+
+    print read_file(\"missing.txt\")
+
+Check the file exists with `file_exists` first.",
+    },
+    Code {
+        code: "E0319",
+        title: "exit",
+        explanation: "\
+The script called `exit(code)`. This isn't an error: `tbasic run` exits
+with the code, and embedders get it back to decide what to do.
+
+    exit(2)",
+    },
+    Code {
+        code: "E0320",
+        title: "import failed",
+        explanation: "\
+An imported module couldn't be found, read or parsed. Modules are looked
+up as `<name>.tb`, or else `<name>.bas`, next to the importing script.
+
+    import utils
+
+Check the module's file exists and has no errors of its own.",
+    },
+    Code {
+        code: "E0321",
+        title: "cyclic import",
+        explanation: "\
+Modules import each other in a loop, so none of them can finish loading.
+The message lists the loop. Here `a.tb` contains
+
+    import b
+
+and `b.tb` contains
+
+    import a
+
+Move what both need into a third module.",
+    },
+    Code {
+        code: "E0322",
+        title: "not permitted",
+        explanation: "\
+The script ran in a sandbox that doesn't allow what it tried to do, such
+as reading files, reading input or calling functions of the host program.
+The embedding program decides what is permitted.",
+    },
+    Code {
+        code: "E0323",
+        title: "memory limit",
+        explanation: "\
+An array or string grew past the size the sandbox allows, or the script
+built more strings, arrays, maps and structs in total than the sandbox's
+memory budget covers.
+
+    s = \"x\"
+    while true {
+      s = s + s
+    }
+
+Keep values smaller and build fewer of them, or raise the sandbox's limits.",
+    },
+    Code {
+        code: "E0324",
+        title: "assertion failed",
+        explanation: "\
+A call to `assert` found its condition false. The optional second argument
+is shown as the message.
+
+    assert(1 + 1 == 3, \"arithmetic\")
+
+Fix the code under test, or the assertion.",
+    },
+    Code {
+        code: "E0325",
+        title: "host error",
+        explanation: "\
+A function registered by the program embedding the interpreter reported an
+error. The message comes from that program.",
+    },
+    Code {
+        code: "E0326",
+        title: "invalid bytecode",
+        explanation: "\
+The bytecode VM ran an instruction that needs more values than the operand
+stack holds. The compiler never produces such code, and loading a `.tbc`
+file or a snapshot rejects it, so it only comes from chunks put together
+by hand. Compile the program again.",
+    },
+    Code {
+        code: "W0001",
+        title: "unused variable",
+        explanation: "\
+A function assigns a variable that it never reads, which is often a typo
+or left over from a change.
+
+    fn f() {
+      result = 1
+      return 2
+    }
+
+Remove the assignment, or start the name with `_` to keep it quiet.",
+    },
+    Code {
+        code: "W0002",
+        title: "unused function",
+        explanation: "\
+A function defined inside another function is never called.
+
+    fn f() {
+      fn helper() {
+        return 1
+      }
+      return 2
+    }
+
+Remove it, or start its name with `_` to keep it quiet.",
+    },
+    Code {
+        code: "W0003",
+        title: "assignment shadows a global",
+        explanation: "\
+A function assigns a name that is also a global, which creates a local
+variable rather than changing the global.
+
+    count = 0
+    fn bump() {
+      count = count + 1
+    }
+
+Add `global count` to the function to change the global.",
+    },
+    Code {
+        code: "W0004",
+        title: "unreachable code",
+        explanation: "\
+A statement can never run, because the one before it always returns,
+breaks, continues or jumps, or because it is in a branch whose condition
+is constant.
+
+    fn f() {
+      return 1
+      print \"never\"
+    }
+
+Remove the statement, or move it before the jump.",
+    },
+    Code {
+        code: "W0005",
+        title: "constant overflow",
+        explanation: "\
+An expression of constants overflows a 64-bit int, so it fails, wraps or
+saturates every time it runs.
+
+    x = 9223372036854775807 * 2
+
+Use a float, or a smaller value.",
+    },
+    Code {
+        code: "W0006",
+        title: "read before assignment",
+        explanation: "\
+A variable is read before any statement assigns it.
+
+    print total
+    total = 1
+
+Assign it first.",
+    },
+    Code {
+        code: "W0007",
+        title: "maybe uninitialized",
+        explanation: "\
+A variable is assigned on some paths to a read but not on others, so the
+read fails when one of those runs.
+
+    if x > 0 {
+      sign = 1
+    }
+    print sign
+
+Assign it on every path, or before the `if`.",
+    },
+    Code {
+        code: "W0101",
+        title: "shadowing",
+        explanation: "\
+A parameter reuses the name of a variable of an enclosing scope, or a
+closure assigns a name its enclosing function uses, which creates a new
+local instead of changing the captured variable. This is the
+`shadowing` lint rule.
+
+    n = 10
+    fn f(n) {
+      return n
+    }
+
+Rename one of them.",
+    },
+    Code {
+        code: "W0102",
+        title: "`let` shadowing",
+        explanation: "\
+A `let` declares a variable with the name of one already visible. This is
+the `let-shadowing` lint rule, off unless enabled.
+
+    let x = 1
+    let x = 2
+
+Rename one of them, or assign with `=`.",
+    },
+    Code {
+        code: "W0103",
+        title: "magic number",
+        explanation: "\
+A number literal other than the allowed ones appears in an expression,
+where a named constant would say what it means. This is the
+`magic-numbers` lint rule, off unless enabled.
+
+    price = cost * 1.19
+
+Give the number a name:
+
+    let vat = 1.19
+    price = cost * vat",
+    },
+    Code {
+        code: "W0104",
+        title: "deep nesting",
+        explanation: "\
+Blocks are nested deeper than the `deep-nesting` lint rule allows, four
+levels by default, which makes the code hard to follow.
+
+Return early, or move inner blocks into functions.",
+    },
+];
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashSet;
+
+    use crate::codes::{lookup, named, CODES};
+
+    #[test]
+    fn test_codes() {
+        assert_eq!(lookup("e0203").map(|code| code.title), Some("type mismatch"));
+        assert_eq!(lookup("E9999"), None);
+        assert_eq!(named("unused-variable"), Some("W0001"));
+        assert_eq!(named("custom-rule"), None);
+
+        let codes: HashSet<_> = CODES.iter().map(|code| code.code).collect();
+        assert_eq!(codes.len(), CODES.len());
+        let mut sorted: Vec<_> = CODES.iter().map(|code| code.code).collect();
+        sorted.sort();
+        assert_eq!(sorted, CODES.iter().map(|code| code.code).collect::<Vec<_>>());
+    }
+}
@@ -0,0 +1,64 @@
+use core::hash::{BuildHasherDefault, Hasher};
+
+// The maps of the parts that work without `std`: the standard library's, or
+// without it hashbrown's and indexmap's with FNV hashing, which needs no
+// source of randomness. They are made with `default()`, which both have.
+
+#[cfg(feature = "std")]
+pub use std::collections::{HashMap, HashSet};
+
+#[cfg(feature = "std")]
+pub type IndexMap<K, V> = indexmap::IndexMap<K, V>;
+
+#[cfg(not(feature = "std"))]
+pub type HashMap<K, V> = hashbrown::HashMap<K, V, BuildFnvHasher>;
+
+#[cfg(not(feature = "std"))]
+pub type HashSet<T> = hashbrown::HashSet<T, BuildFnvHasher>;
+
+#[cfg(not(feature = "std"))]
+pub type IndexMap<K, V> = indexmap::IndexMap<K, V, BuildFnvHasher>;
+
+pub type BuildFnvHasher = BuildHasherDefault<FnvHasher>;
+
+/// The 64-bit FNV-1a hash, quick for the short keys of variables and maps
+/// but no defense against keys chosen to collide.
+#[derive(Debug, Clone, Copy)]
+pub struct FnvHasher(u64);
+
+impl Default for FnvHasher {
+    fn default() -> Self {
+        FnvHasher(0xcbf2_9ce4_8422_2325)
+    }
+}
+
+impl Hasher for FnvHasher {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 = (self.0 ^ byte as u64).wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use core::hash::Hasher;
+
+    use crate::collections::FnvHasher;
+
+    #[test]
+    fn test_fnv() {
+        let hash = |bytes: &[u8]| {
+            let mut hasher = FnvHasher::default();
+            hasher.write(bytes);
+            hasher.finish()
+        };
+        assert_eq!(hash(b""), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(hash(b"a"), 0xaf63_dc4c_8601_ec8c);
+        assert_eq!(hash(b"foobar"), 0x8594_4171_f739_67e8);
+    }
+}
@@ -0,0 +1,1140 @@
+use alloc::rc::Rc;
+use alloc::string::{String, ToString};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::fmt;
+
+use num_bigint::BigInt;
+
+use crate::ast::{BinaryOp, Expr, ExprKind, FunctionDecl, Program, Stmt, StmtKind, StructDecl, TypeAnnotation, UnaryOp};
+use crate::collections::HashMap;
+use crate::inline;
+use crate::interpreter::{Interpreter, RuntimeError};
+use crate::lexer::Span;
+use crate::loops;
+use crate::parser::{parse, ParseError};
+use crate::peephole;
+use crate::register::{self, Instr, Reg, RegisterChunk, RegisterFunction};
+use crate::value::Value;
+use crate::vm;
+
+/// One bytecode instruction. Instructions pop their operands off the
+/// operand stack and push their result; `u32` operands index the pools of
+/// the [`Chunk`] holding the instruction, or are code offsets for jumps.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Op {
+    /// Pushes `constants[i]`.
+    Constant(u32),
+    Nil,
+    True,
+    False,
+    Pop,
+    /// Pushes the variable `names[i]`.
+    Load(u32),
+    /// Like `Load`, for a callee: a missing name is an undefined function.
+    LoadFunction(u32),
+    /// Pops a value into the variable `names[i]`.
+    Store(u32),
+    /// `let`: makes `names[i]` local to the running call.
+    Declare(u32),
+    /// Makes `names[i]` refer to the global for the rest of the call.
+    Global(u32),
+    /// Pushes a closure of `functions[i]` over the running call.
+    Closure(u32),
+    /// Pops that many items into a new array.
+    Array(u32),
+    /// Pops that many key-value pairs into a new map.
+    Map(u32),
+    /// Fails unless the value on top of the stack is a valid map key.
+    CheckKey,
+    /// Pops an index and a target and pushes the indexed element.
+    /// `target` is the target's location in `operand_spans`.
+    Index { target: u32 },
+    /// Pops a value, an index and a target and stores the value there.
+    SetIndex { target: u32 },
+    /// Pops a struct or module and pushes its field `names[name]`.
+    Field { name: u32, target: u32 },
+    /// Pops a value and a struct and stores the value in its field `names[i]`.
+    SetField(u32),
+    Unary(UnaryOp),
+    /// Any operator but `&&` and `||`, which compile to jumps.
+    Binary(BinaryOp),
+    /// Replaces the value on top of the stack by whether it is truthy.
+    Truthy,
+    Jump(u32),
+    /// Pops a value and jumps if it is falsy.
+    JumpIfFalse(u32),
+    /// Pops a value and jumps if it is truthy.
+    JumpIfTrue(u32),
+    /// Pops that many arguments and a callee and pushes what the call returns.
+    Call { args: u32, callee: u32 },
+    /// A `Call` followed by a `Return`. Calling a compiled function from
+    /// within a call replaces that call, reusing its frame, and skips the
+    /// `Return`.
+    TailCall { args: u32, callee: u32 },
+    /// Pops the return value and leaves the running call, or the program at
+    /// the top level.
+    Return,
+    /// Pops that many values and prints them.
+    Print(u32),
+    /// Binds the module `names[i]`.
+    Import(u32),
+    /// Starts a statement: counts a step and runs debug hooks.
+    Statement,
+    /// Ends a loop iteration, counting a step.
+    Iteration,
+    /// Pops the value the program returns if it ends after this statement.
+    SetResult,
+    /// Makes the program return `nil` if it ends after this statement.
+    ClearResult,
+    /// Classic dialect: jumps to `target`, continuing at `resume` on `return`.
+    Gosub { target: u32, resume: u32 },
+    /// Classic dialect: `return` after a `gosub`.
+    SubReturn,
+    /// Classic dialect: fails with a jump to the line `constants[i]`, which
+    /// doesn't exist.
+    UndefinedLine(u32),
+}
+
+impl Op {
+    /// The instruction's name in disassembly listings.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Op::Constant(_) => "constant",
+            Op::Nil => "nil",
+            Op::True => "true",
+            Op::False => "false",
+            Op::Pop => "pop",
+            Op::Load(_) => "load",
+            Op::LoadFunction(_) => "load-function",
+            Op::Store(_) => "store",
+            Op::Declare(_) => "declare",
+            Op::Global(_) => "global",
+            Op::Closure(_) => "closure",
+            Op::Array(_) => "array",
+            Op::Map(_) => "map",
+            Op::CheckKey => "check-key",
+            Op::Index { .. } => "index",
+            Op::SetIndex { .. } => "set-index",
+            Op::Field { .. } => "field",
+            Op::SetField(_) => "set-field",
+            Op::Unary(_) => "unary",
+            Op::Binary(_) => "binary",
+            Op::Truthy => "truthy",
+            Op::Jump(_) => "jump",
+            Op::JumpIfFalse(_) => "jump-if-false",
+            Op::JumpIfTrue(_) => "jump-if-true",
+            Op::Call { .. } => "call",
+            Op::TailCall { .. } => "tail-call",
+            Op::Return => "return",
+            Op::Print(_) => "print",
+            Op::Import(_) => "import",
+            Op::Statement => "statement",
+            Op::Iteration => "iteration",
+            Op::SetResult => "set-result",
+            Op::ClearResult => "clear-result",
+            Op::Gosub { .. } => "gosub",
+            Op::SubReturn => "sub-return",
+            Op::UndefinedLine(_) => "undefined-line",
+        }
+    }
+
+    /// How many values the instruction pops off the operand stack and how
+    /// many it pushes.
+    pub fn stack_effect(&self) -> (usize, usize) {
+        match *self {
+            Op::Constant(_) | Op::Nil | Op::True | Op::False | Op::Load(_) | Op::LoadFunction(_) | Op::Closure(_) => (0, 1),
+            Op::Pop | Op::Store(_) | Op::JumpIfFalse(_) | Op::JumpIfTrue(_) | Op::Return | Op::SetResult => (1, 0),
+            Op::Array(n) => (n as usize, 1),
+            Op::Map(n) => (2 * n as usize, 1),
+            Op::CheckKey | Op::Field { .. } | Op::Unary(_) | Op::Truthy => (1, 1),
+            Op::Index { .. } | Op::Binary(_) => (2, 1),
+            Op::SetIndex { .. } => (3, 0),
+            Op::SetField(_) => (2, 0),
+            Op::Call { args, .. } | Op::TailCall { args, .. } => (args as usize + 1, 1),
+            Op::Print(n) => (n as usize, 0),
+            Op::Declare(_)
+            | Op::Global(_)
+            | Op::Jump(_)
+            | Op::Import(_)
+            | Op::Statement
+            | Op::Iteration
+            | Op::ClearResult
+            | Op::Gosub { .. }
+            | Op::SubReturn
+            | Op::UndefinedLine(_) => (0, 0),
+        }
+    }
+}
+
+/// Compiled code with the pools its instructions refer to.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Chunk {
+    pub code: Vec<Op>,
+    /// Where each instruction comes from, for errors and debug hooks.
+    pub spans: Vec<Span>,
+    pub constants: Vec<Value>,
+    /// Names of the variables, fields and modules instructions refer to.
+    pub names: Vec<String>,
+    /// Further locations of instructions that report errors at more than one
+    /// place, such as a call's callee.
+    pub operand_spans: Vec<Span>,
+    pub functions: Vec<Rc<CompiledFunction>>,
+}
+
+/// A function definition or literal, compiled.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompiledFunction {
+    /// The function's name and parameters. Its body is only kept for
+    /// reference: calls run `chunk`.
+    pub decl: Rc<FunctionDecl>,
+    pub chunk: Chunk,
+}
+
+/// Compiles `program` into bytecode running the same way as the
+/// [`Interpreter`](crate::interpreter::Interpreter) runs the AST.
+///
+/// Variables are still looked up by name at run time, since `global` and
+/// closures decide what a name refers to while a call runs.
+pub fn compile(program: &Program) -> Chunk {
+    compile_program(program, false)
+}
+
+fn compile_program(program: &Program, tail_calls: bool) -> Chunk {
+    let mut compiler = Compiler::new(Some(&program.lines));
+    compiler.tail_calls = tail_calls;
+    for statement in &program.statements {
+        compiler.starts.push(compiler.chunk.code.len());
+        compiler.statement(statement);
+        let settle = if matches!(statement.kind, StmtKind::Expr(_)) { Op::SetResult } else { Op::ClearResult };
+        compiler.emit(settle, statement.span);
+    }
+    compiler.starts.push(compiler.chunk.code.len());
+    compiler.finish()
+}
+
+/// Which VM a program is compiled for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Backend {
+    /// Stack bytecode, run by the [`vm`](crate::vm). The only code that can
+    /// be [saved](crate::bytecode::save).
+    #[default]
+    Stack,
+    /// Register code, run by the [`register`](crate::register) VM. It takes
+    /// fewer instructions per expression than stack code; `cargo bench
+    /// --bench engines` compares the two on a few scripts.
+    Register,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct CompileOptions {
+    pub backend: Backend,
+    /// Run the [peephole](crate::peephole) optimizer over stack code.
+    pub optimize: bool,
+    /// Compile `return f(...)` to a tail call, which replaces the running
+    /// call instead of nesting in it, so tail recursion isn't limited by
+    /// [`Interpreter::set_max_call_depth`]. The calls replaced are missing
+    /// from error traces.
+    pub tail_calls: bool,
+    /// [Inline](crate::inline) calls of functions returning an expression
+    /// of at most this many nodes; 0 inlines nothing.
+    pub inline_size: usize,
+    /// [Optimize loops](crate::loops) that make no calls by computing
+    /// what doesn't change once and replacing products of their counter by
+    /// additions.
+    pub optimize_loops: bool,
+}
+
+/// A program compiled by [`compile_with`] for either backend.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Compiled {
+    Stack(Chunk),
+    Register(RegisterChunk),
+}
+
+impl Compiled {
+    /// Runs the program on the VM it was compiled for.
+    pub fn run(&self, interpreter: &mut Interpreter) -> Result<Value, RuntimeError> {
+        match self {
+            Compiled::Stack(chunk) => vm::run(interpreter, chunk),
+            Compiled::Register(chunk) => register::run(interpreter, chunk),
+        }
+    }
+}
+
+/// A program compiled ahead of time, ready to run any number of times.
+/// Clones share the compiled code, constants and functions, so they are
+/// cheap enough to keep one per script in a cache and hand out for every
+/// run; each run only makes the VM state it changes.
+///
+/// Each run starts the program afresh in the interpreter it is given, with
+/// that interpreter's globals, host functions, limits and output, so one
+/// compilation can serve requests that each run in an interpreter of their
+/// own. Values are reference counted without atomics, so to serve them from
+/// several threads, hand each one a [`SharedProgram`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompiledProgram {
+    compiled: Rc<Compiled>,
+}
+
+impl CompiledProgram {
+    pub fn new(program: &Program, options: &CompileOptions) -> CompiledProgram {
+        CompiledProgram { compiled: Rc::new(compile_with(program, options)) }
+    }
+
+    /// The compiled code.
+    pub fn compiled(&self) -> &Compiled {
+        &self.compiled
+    }
+
+    /// Runs the program in `interpreter`.
+    pub fn run(&self, interpreter: &mut Interpreter) -> Result<Value, RuntimeError> {
+        self.compiled.run(interpreter)
+    }
+
+    /// The program in a form that is `Send` and `Sync`.
+    pub fn shared(&self) -> SharedProgram {
+        let compiled = match &*self.compiled {
+            Compiled::Stack(chunk) => SharedCompiled::Stack(SharedChunk::stack(chunk)),
+            Compiled::Register(chunk) => SharedCompiled::Register(SharedChunk::register(chunk)),
+        };
+        SharedProgram { compiled: Arc::new(compiled) }
+    }
+}
+
+/// A [`CompiledProgram`] threads can share. Clones share the code too; each
+/// thread takes the [`program`](SharedProgram::program) once to run it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SharedProgram {
+    compiled: Arc<SharedCompiled>,
+}
+
+impl SharedProgram {
+    /// The program for the calling thread, whose constants and functions
+    /// are rebuilt, which costs about as much as cloning the code.
+    pub fn program(&self) -> CompiledProgram {
+        let compiled = match &*self.compiled {
+            SharedCompiled::Stack(chunk) => Compiled::Stack(chunk.to_stack()),
+            SharedCompiled::Register(chunk) => Compiled::Register(chunk.to_register()),
+        };
+        CompiledProgram { compiled: Rc::new(compiled) }
+    }
+}
+
+/// The [`Compiled`] code of a [`SharedProgram`].
+#[derive(Debug, Clone, PartialEq)]
+enum SharedCompiled {
+    Stack(SharedChunk<Op>),
+    Register(SharedChunk<Instr>),
+}
+
+/// A [`Chunk`] or [`RegisterChunk`] whose constants and functions own
+/// their contents rather than sharing them through `Rc`.
+#[derive(Debug, Clone, PartialEq)]
+struct SharedChunk<I> {
+    code: Vec<I>,
+    spans: Vec<Span>,
+    constants: Vec<Constant>,
+    names: Vec<String>,
+    operand_spans: Vec<Span>,
+    functions: Vec<(Header, SharedChunk<I>)>,
+    /// The registers register code takes; 0 for stack code.
+    registers: Reg,
+}
+
+impl SharedChunk<Op> {
+    fn stack(chunk: &Chunk) -> SharedChunk<Op> {
+        SharedChunk {
+            code: chunk.code.clone(),
+            spans: chunk.spans.clone(),
+            constants: chunk.constants.iter().map(Constant::new).collect(),
+            names: chunk.names.clone(),
+            operand_spans: chunk.operand_spans.clone(),
+            functions: chunk.functions.iter().map(|f| (Header::new(&f.decl), SharedChunk::stack(&f.chunk))).collect(),
+            registers: 0,
+        }
+    }
+
+    fn to_stack(&self) -> Chunk {
+        Chunk {
+            code: self.code.clone(),
+            spans: self.spans.clone(),
+            constants: self.constants.iter().map(Constant::value).collect(),
+            names: self.names.clone(),
+            operand_spans: self.operand_spans.clone(),
+            functions: self
+                .functions
+                .iter()
+                .map(|(header, chunk)| Rc::new(CompiledFunction { decl: header.decl(), chunk: chunk.to_stack() }))
+                .collect(),
+        }
+    }
+}
+
+impl SharedChunk<Instr> {
+    fn register(chunk: &RegisterChunk) -> SharedChunk<Instr> {
+        SharedChunk {
+            code: chunk.code.clone(),
+            spans: chunk.spans.clone(),
+            constants: chunk.constants.iter().map(Constant::new).collect(),
+            names: chunk.names.clone(),
+            operand_spans: chunk.operand_spans.clone(),
+            functions: chunk.functions.iter().map(|f| (Header::new(&f.decl), SharedChunk::register(&f.chunk))).collect(),
+            registers: chunk.registers,
+        }
+    }
+
+    fn to_register(&self) -> RegisterChunk {
+        RegisterChunk {
+            code: self.code.clone(),
+            spans: self.spans.clone(),
+            constants: self.constants.iter().map(Constant::value).collect(),
+            names: self.names.clone(),
+            operand_spans: self.operand_spans.clone(),
+            functions: self
+                .functions
+                .iter()
+                .map(|(header, chunk)| Rc::new(RegisterFunction { decl: header.decl(), chunk: chunk.to_register() }))
+                .collect(),
+            registers: self.registers,
+        }
+    }
+}
+
+/// A constant of compiled code. Compilers only put the kinds of values
+/// here that [`bytecode::save`](crate::bytecode::save) writes.
+#[derive(Debug, Clone, PartialEq)]
+enum Constant {
+    Nil,
+    Bool(bool),
+    Int(i64),
+    BigInt(BigInt),
+    Float(f64),
+    Str(String),
+    StructType(StructDecl),
+}
+
+impl Constant {
+    fn new(value: &Value) -> Constant {
+        match value {
+            Value::Nil => Constant::Nil,
+            Value::Bool(b) => Constant::Bool(*b),
+            Value::Int(n) => Constant::Int(*n),
+            Value::BigInt(n) => Constant::BigInt(BigInt::clone(n)),
+            Value::Float(n) => Constant::Float(*n),
+            Value::Str(s) => Constant::Str(s.to_string()),
+            Value::StructType(decl) => Constant::StructType(StructDecl::clone(decl)),
+            other => unreachable!("compiled code has a constant {}", other.type_name()),
+        }
+    }
+
+    fn value(&self) -> Value {
+        match self {
+            Constant::Nil => Value::Nil,
+            Constant::Bool(b) => Value::Bool(*b),
+            Constant::Int(n) => Value::Int(*n),
+            Constant::BigInt(n) => Value::BigInt(Rc::new(n.clone())),
+            Constant::Float(n) => Value::Float(*n),
+            Constant::Str(s) => Value::from(s.as_str()),
+            Constant::StructType(decl) => Value::StructType(Rc::new(decl.clone())),
+        }
+    }
+}
+
+/// A compiled function's declaration without its body, which calls don't
+/// run, as [`bytecode::load`](crate::bytecode::load) leaves it too.
+#[derive(Debug, Clone, PartialEq)]
+struct Header {
+    name: String,
+    params: Vec<String>,
+    param_types: Vec<Option<TypeAnnotation>>,
+    return_type: Option<TypeAnnotation>,
+    span: Span,
+    doc: Option<String>,
+}
+
+impl Header {
+    fn new(decl: &FunctionDecl) -> Header {
+        Header {
+            name: decl.name.clone(),
+            params: decl.params.clone(),
+            param_types: decl.param_types.clone(),
+            return_type: decl.return_type.clone(),
+            span: decl.span,
+            doc: decl.doc.clone(),
+        }
+    }
+
+    fn decl(&self) -> Rc<FunctionDecl> {
+        Rc::new(FunctionDecl {
+            name: self.name.clone(),
+            params: self.params.clone(),
+            param_types: self.param_types.clone(),
+            return_type: self.return_type.clone(),
+            body: Vec::new(),
+            span: self.span,
+            doc: self.doc.clone(),
+        })
+    }
+}
+
+impl Program {
+    /// Parses and compiles `source` with the default [`CompileOptions`],
+    /// separately from running it; see [`CompiledProgram`].
+    pub fn compile(source: &str) -> Result<CompiledProgram, ParseError> {
+        Ok(CompiledProgram::new(&parse(source)?, &CompileOptions::default()))
+    }
+}
+
+/// Compiles `program` for the backend `options` selects.
+pub fn compile_with(program: &Program, options: &CompileOptions) -> Compiled {
+    let inlined;
+    let program = match options.inline_size {
+        0 => program,
+        max_size => {
+            inlined = inline::inline(program, max_size);
+            &inlined
+        },
+    };
+    let optimized;
+    let program = match options.optimize_loops {
+        true => {
+            optimized = loops::optimize(program);
+            &optimized
+        },
+        false => program,
+    };
+    match options.backend {
+        Backend::Stack => {
+            let mut chunk = compile_program(program, options.tail_calls);
+            if options.optimize {
+                peephole::optimize(&mut chunk);
+            }
+            Compiled::Stack(chunk)
+        },
+        Backend::Register => Compiled::Register(register::compile_program(program, options.tail_calls)),
+    }
+}
+
+fn index(len: usize) -> u32 {
+    u32::try_from(len).expect("chunk is too large")
+}
+
+/// A constant as a pool key. Floats compare by their bits, so `0.0` and
+/// `-0.0` stay apart.
+#[derive(PartialEq, Eq, Hash)]
+enum Key {
+    Int(i64),
+    BigInt(Rc<BigInt>),
+    Float(u64),
+    Bool(bool),
+    Str(Rc<str>),
+}
+
+/// Stores each distinct constant and name of a chunk once. Equal string
+/// constants are one `Rc`, so pushing them never copies the text.
+#[derive(Default)]
+pub(crate) struct Interner {
+    constants: HashMap<Key, u32>,
+    names: HashMap<String, u32>,
+}
+
+impl Key {
+    fn of(value: &Value) -> Option<Key> {
+        match value {
+            Value::Int(n) => Some(Key::Int(*n)),
+            Value::BigInt(n) => Some(Key::BigInt(n.clone())),
+            Value::Float(n) => Some(Key::Float(n.to_bits())),
+            Value::Bool(b) => Some(Key::Bool(*b)),
+            Value::Str(s) => Some(Key::Str(s.clone())),
+            // Struct types are told apart by identity, not contents
+            _ => None,
+        }
+    }
+}
+
+impl Interner {
+    /// An interner for adding to the finished pool `constants`.
+    pub(crate) fn of_constants(constants: &[Value]) -> Interner {
+        let mut pool = Interner::default();
+        for (i, value) in constants.iter().enumerate() {
+            if let Some(key) = Key::of(value) {
+                pool.constants.entry(key).or_insert(index(i));
+            }
+        }
+        pool
+    }
+
+    pub(crate) fn constant(&mut self, constants: &mut Vec<Value>, value: Value) -> u32 {
+        let Some(key) = Key::of(&value) else {
+            constants.push(value);
+            return index(constants.len() - 1);
+        };
+        *self.constants.entry(key).or_insert_with(|| {
+            constants.push(value);
+            index(constants.len() - 1)
+        })
+    }
+
+    pub(crate) fn name(&mut self, names: &mut Vec<String>, name: &str) -> u32 {
+        if let Some(&i) = self.names.get(name) {
+            return i;
+        }
+        names.push(name.to_string());
+        let i = index(names.len() - 1);
+        self.names.insert(name.to_string(), i);
+        i
+    }
+}
+
+/// A `while` being compiled.
+#[derive(Default)]
+struct Loop {
+    /// Jumps to patch with the loop's end.
+    breaks: Vec<usize>,
+    /// Jumps to patch with the loop's `Iteration`.
+    continues: Vec<usize>,
+}
+
+struct Compiler<'a> {
+    chunk: Chunk,
+    loops: Vec<Loop>,
+    /// Classic top level: maps line numbers to statement indexes.
+    lines: Option<&'a HashMap<i64, usize>>,
+    /// Code offset of each top-level statement, and of the end.
+    starts: Vec<usize>,
+    /// Jump instructions to patch with the start of a top-level statement:
+    /// the instruction's offset and the statement's index.
+    line_jumps: Vec<(usize, usize)>,
+    pool: Interner,
+    /// Whether `return f(...)` compiles to `TailCall`.
+    tail_calls: bool,
+}
+
+impl<'a> Compiler<'a> {
+    fn new(lines: Option<&'a HashMap<i64, usize>>) -> Compiler<'a> {
+        Compiler {
+            chunk: Chunk::default(),
+            loops: Vec::new(),
+            lines,
+            starts: Vec::new(),
+            line_jumps: Vec::new(),
+            pool: Interner::default(),
+            tail_calls: false,
+        }
+    }
+
+    fn finish(mut self) -> Chunk {
+        for (at, statement) in core::mem::take(&mut self.line_jumps) {
+            let start = index(self.starts[statement]);
+            match &mut self.chunk.code[at] {
+                Op::Jump(target) => *target = start,
+                Op::Gosub { target, resume } => {
+                    *target = start;
+                    *resume = index(self.starts[*resume as usize]);
+                },
+                op => unreachable!("{:?} is not a line jump", op),
+            }
+        }
+        self.chunk
+    }
+
+    fn emit(&mut self, op: Op, span: Span) -> usize {
+        self.chunk.code.push(op);
+        self.chunk.spans.push(span);
+        self.chunk.code.len() - 1
+    }
+
+    /// Points the jump at `at` to the next instruction.
+    fn patch(&mut self, at: usize) {
+        let here = index(self.chunk.code.len());
+        match &mut self.chunk.code[at] {
+            Op::Jump(target) | Op::JumpIfFalse(target) | Op::JumpIfTrue(target) => *target = here,
+            op => unreachable!("{:?} is not a jump", op),
+        }
+    }
+
+    fn constant(&mut self, value: Value) -> u32 {
+        self.pool.constant(&mut self.chunk.constants, value)
+    }
+
+    fn name(&mut self, name: &str) -> u32 {
+        self.pool.name(&mut self.chunk.names, name)
+    }
+
+    fn operand_span(&mut self, span: Span) -> u32 {
+        self.chunk.operand_spans.push(span);
+        index(self.chunk.operand_spans.len() - 1)
+    }
+
+    fn function(&mut self, decl: &Rc<FunctionDecl>) -> u32 {
+        let mut compiler = Compiler::new(None);
+        compiler.tail_calls = self.tail_calls;
+        compiler.block(&decl.body);
+        compiler.emit(Op::Nil, decl.span);
+        compiler.emit(Op::Return, decl.span);
+        let function = CompiledFunction { decl: decl.clone(), chunk: compiler.finish() };
+        self.chunk.functions.push(Rc::new(function));
+        index(self.chunk.functions.len() - 1)
+    }
+
+    fn block(&mut self, statements: &[Stmt]) {
+        for statement in statements {
+            self.statement(statement);
+            if matches!(statement.kind, StmtKind::Expr(_)) {
+                self.emit(Op::Pop, statement.span);
+            }
+        }
+    }
+
+    /// Compiles `statement`, leaving its value on the stack if it is an
+    /// expression.
+    fn statement(&mut self, statement: &Stmt) {
+        let span = statement.span;
+        self.emit(Op::Statement, span);
+        match &statement.kind {
+            StmtKind::Assign { name, value, declaration, .. } => {
+                self.expression(value);
+                let name = self.name(name);
+                if *declaration {
+                    self.emit(Op::Declare(name), span);
+                }
+                self.emit(Op::Store(name), span);
+            },
+            StmtKind::SetIndex { target, index, value } => {
+                self.expression(target);
+                self.expression(index);
+                self.expression(value);
+                let target = self.operand_span(target.span);
+                self.emit(Op::SetIndex { target }, index.span);
+            },
+            StmtKind::SetField { target, field, value } => {
+                self.expression(target);
+                self.expression(value);
+                let field = self.name(field);
+                self.emit(Op::SetField(field), target.span);
+            },
+            StmtKind::If { condition, then_branch, else_branch } => {
+                self.expression(condition);
+                let to_else = self.emit(Op::JumpIfFalse(0), span);
+                self.block(then_branch);
+                match else_branch {
+                    Some(else_branch) => {
+                        let to_end = self.emit(Op::Jump(0), span);
+                        self.patch(to_else);
+                        self.block(else_branch);
+                        self.patch(to_end);
+                    },
+                    None => self.patch(to_else),
+                }
+            },
+            StmtKind::While { condition, body } => self.while_loop(condition, body, span),
+            StmtKind::Break => {
+                let jump = self.emit(Op::Jump(0), span);
+                self.loops.last_mut().expect("`break` outside of a loop").breaks.push(jump);
+            },
+            StmtKind::Continue => {
+                let jump = self.emit(Op::Jump(0), span);
+                self.loops.last_mut().expect("`continue` outside of a loop").continues.push(jump);
+            },
+            StmtKind::Print(args) => {
+                for arg in args {
+                    self.expression(arg);
+                }
+                self.emit(Op::Print(index(args.len())), span);
+            },
+            StmtKind::Function(decl) => {
+                let function = self.function(decl);
+                self.emit(Op::Closure(function), span);
+                let name = self.name(&decl.name);
+                self.emit(Op::Store(name), span);
+            },
+            StmtKind::Struct(decl) => {
+                let constructor = self.constant(Value::StructType(decl.clone()));
+                self.emit(Op::Constant(constructor), span);
+                let name = self.name(&decl.name);
+                self.emit(Op::Store(name), span);
+            },
+            StmtKind::Return(value) => {
+                match value {
+                    Some(Expr { kind: ExprKind::Call { callee, args }, span }) if self.tail_calls => {
+                        self.call(callee, args, *span, true)
+                    },
+                    Some(value) => self.expression(value),
+                    None => {
+                        self.emit(Op::Nil, span);
+                    },
+                }
+                self.emit(Op::Return, span);
+            },
+            StmtKind::Global(names) => {
+                for name in names {
+                    let name = self.name(name);
+                    self.emit(Op::Global(name), span);
+                }
+            },
+            StmtKind::Import(module) => {
+                let module = self.name(module);
+                self.emit(Op::Import(module), span);
+            },
+            StmtKind::Goto(line) => self.line_jump(Op::Jump(0), *line, span),
+            StmtKind::Gosub(line) => {
+                // `return` resumes at the top-level statement after this one,
+                // whose offset `finish` fills in
+                let resume = index(self.starts.len());
+                self.line_jump(Op::Gosub { target: 0, resume }, *line, span);
+            },
+            StmtKind::SubReturn => {
+                self.emit(Op::SubReturn, span);
+            },
+            StmtKind::Expr(expr) => self.expression(expr),
+        }
+    }
+
+    /// Emits a jump to the statement labelled `line`, or an error if there
+    /// is none.
+    fn line_jump(&mut self, op: Op, line: i64, span: Span) {
+        match self.lines.and_then(|lines| lines.get(&line)) {
+            Some(&statement) => {
+                let at = self.emit(op, span);
+                self.line_jumps.push((at, statement));
+            },
+            None => {
+                let line = self.constant(Value::Int(line));
+                self.emit(Op::UndefinedLine(line), span);
+            },
+        }
+    }
+
+    fn while_loop(&mut self, condition: &Expr, body: &[Stmt], span: Span) {
+        let start = index(self.chunk.code.len());
+        self.expression(condition);
+        let exit = self.emit(Op::JumpIfFalse(0), span);
+        self.loops.push(Loop::default());
+        self.block(body);
+        let finished = self.loops.pop().unwrap();
+        for jump in finished.continues {
+            self.patch(jump);
+        }
+        self.emit(Op::Iteration, span);
+        self.emit(Op::Jump(start), span);
+        self.patch(exit);
+        for jump in finished.breaks {
+            self.patch(jump);
+        }
+    }
+
+    fn expression(&mut self, expr: &Expr) {
+        let span = expr.span;
+        match &expr.kind {
+            ExprKind::Number(n) => {
+                let constant = self.constant(Value::Int(*n));
+                self.emit(Op::Constant(constant), span);
+            },
+            ExprKind::Float(n) => {
+                let constant = self.constant(Value::Float(*n));
+                self.emit(Op::Constant(constant), span);
+            },
+            ExprKind::Str(s) => {
+                let constant = self.constant(Value::from(s.as_str()));
+                self.emit(Op::Constant(constant), span);
+            },
+            ExprKind::Variable(name) => {
+                let name = self.name(name);
+                self.emit(Op::Load(name), span);
+            },
+            ExprKind::Function(decl) => {
+                let function = self.function(decl);
+                self.emit(Op::Closure(function), span);
+            },
+            ExprKind::Array(items) => {
+                for item in items {
+                    self.expression(item);
+                }
+                self.emit(Op::Array(index(items.len())), span);
+            },
+            ExprKind::Map(entries) => {
+                for (key, value) in entries {
+                    self.expression(key);
+                    self.emit(Op::CheckKey, key.span);
+                    self.expression(value);
+                }
+                self.emit(Op::Map(index(entries.len())), span);
+            },
+            ExprKind::Index { target, index } => {
+                self.expression(target);
+                self.expression(index);
+                let target = self.operand_span(target.span);
+                self.emit(Op::Index { target }, index.span);
+            },
+            ExprKind::Field { target, field } => {
+                self.expression(target);
+                let name = self.name(field);
+                let target = self.operand_span(target.span);
+                self.emit(Op::Field { name, target }, span);
+            },
+            ExprKind::Unary { op, operand } => {
+                self.expression(operand);
+                self.emit(Op::Unary(*op), span);
+            },
+            ExprKind::Binary { op: op @ (BinaryOp::And | BinaryOp::Or), left, right } => {
+                self.short_circuit(*op, left, right, span)
+            },
+            ExprKind::Binary { op, left, right } => {
+                self.expression(left);
+                self.expression(right);
+                self.emit(Op::Binary(*op), span);
+            },
+            ExprKind::Call { callee, args } => self.call(callee, args, span, false),
+        }
+    }
+
+    fn call(&mut self, callee: &Expr, args: &[Expr], span: Span, tail: bool) {
+        match &callee.kind {
+            ExprKind::Variable(name) => {
+                let name = self.name(name);
+                self.emit(Op::LoadFunction(name), callee.span);
+            },
+            _ => self.expression(callee),
+        }
+        for arg in args {
+            self.expression(arg);
+        }
+        let (args, callee) = (index(args.len()), self.operand_span(callee.span));
+        self.emit(if tail { Op::TailCall { args, callee } } else { Op::Call { args, callee } }, span);
+    }
+
+    /// `a && b` pushes `false` without evaluating `b` if `a` is falsy, and
+    /// whether `b` is truthy otherwise; `||` the other way around.
+    fn short_circuit(&mut self, op: BinaryOp, left: &Expr, right: &Expr, span: Span) {
+        self.expression(left);
+        let decided = match op {
+            BinaryOp::And => self.emit(Op::JumpIfFalse(0), span),
+            _ => self.emit(Op::JumpIfTrue(0), span),
+        };
+        self.expression(right);
+        self.emit(Op::Truthy, span);
+        let to_end = self.emit(Op::Jump(0), span);
+        self.patch(decided);
+        self.emit(if op == BinaryOp::And { Op::False } else { Op::True }, span);
+        self.patch(to_end);
+    }
+}
+
+/// A disassembly listing: one instruction per line with its offset and
+/// source line, followed by the listings of the chunk's functions.
+impl fmt::Display for Chunk {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut last_line = None;
+        for (offset, (op, span)) in self.code.iter().zip(&self.spans).enumerate() {
+            write!(f, "{:04} ", offset)?;
+            if last_line == Some(span.line) {
+                write!(f, "   | ")?;
+            } else {
+                write!(f, "{:4} ", span.line)?;
+                last_line = Some(span.line);
+            }
+            self.write_op(f, *op)?;
+            writeln!(f)?;
+        }
+        for function in &self.functions {
+            writeln!(f, "\nfn {}({}):", function.decl.name, function.decl.params.join(", "))?;
+            write!(f, "{}", function.chunk)?;
+        }
+        Ok(())
+    }
+}
+
+impl Chunk {
+    fn write_op(&self, f: &mut fmt::Formatter<'_>, op: Op) -> fmt::Result {
+        let name = |i: u32| &self.names[i as usize];
+        match op {
+            Op::Constant(i) => match &self.constants[i as usize] {
+                Value::Str(s) => write!(f, "constant {:?}", s),
+                value => write!(f, "constant {}", value),
+            },
+            Op::Nil => write!(f, "nil"),
+            Op::True => write!(f, "true"),
+            Op::False => write!(f, "false"),
+            Op::Pop => write!(f, "pop"),
+            Op::Load(i) => write!(f, "load {}", name(i)),
+            Op::LoadFunction(i) => write!(f, "load-function {}", name(i)),
+            Op::Store(i) => write!(f, "store {}", name(i)),
+            Op::Declare(i) => write!(f, "declare {}", name(i)),
+            Op::Global(i) => write!(f, "global {}", name(i)),
+            Op::Closure(i) => write!(f, "closure {}", self.functions[i as usize].decl.name),
+            Op::Array(n) => write!(f, "array {}", n),
+            Op::Map(n) => write!(f, "map {}", n),
+            Op::CheckKey => write!(f, "check-key"),
+            Op::Index { .. } => write!(f, "index"),
+            Op::SetIndex { .. } => write!(f, "set-index"),
+            Op::Field { name: i, .. } => write!(f, "field {}", name(i)),
+            Op::SetField(i) => write!(f, "set-field {}", name(i)),
+            Op::Unary(op) => write!(f, "unary {:?}", op),
+            Op::Binary(op) => write!(f, "binary {:?}", op),
+            Op::Truthy => write!(f, "truthy"),
+            Op::Jump(target) => write!(f, "jump {:04}", target),
+            Op::JumpIfFalse(target) => write!(f, "jump-if-false {:04}", target),
+            Op::JumpIfTrue(target) => write!(f, "jump-if-true {:04}", target),
+            Op::Call { args, .. } => write!(f, "call {}", args),
+            Op::TailCall { args, .. } => write!(f, "tail-call {}", args),
+            Op::Return => write!(f, "return"),
+            Op::Print(n) => write!(f, "print {}", n),
+            Op::Import(i) => write!(f, "import {}", name(i)),
+            Op::Statement => write!(f, "statement"),
+            Op::Iteration => write!(f, "iteration"),
+            Op::SetResult => write!(f, "set-result"),
+            Op::ClearResult => write!(f, "clear-result"),
+            Op::Gosub { target, resume } => write!(f, "gosub {:04} resume {:04}", target, resume),
+            Op::SubReturn => write!(f, "sub-return"),
+            Op::UndefinedLine(i) => write!(f, "undefined-line {}", self.constants[i as usize]),
+        }
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod test {
+    use crate::ast::Program;
+    use crate::compiler::{compile, compile_program, Backend, CompileOptions, Compiled, CompiledProgram, Interner, Op, SharedProgram};
+    use crate::interpreter::{Interpreter, SharedBuffer};
+    use crate::parser::{parse, parse_with_dialect, Dialect};
+    use crate::value::Value;
+
+    #[test]
+    fn test_compile() {
+        let chunk = compile(&parse("x = 1\nwhile x < 3 {\n  x = x + 1\n}\nx").unwrap());
+        assert_eq!(chunk.to_string(), "\
+0000    1 statement
+0001    | constant 1
+0002    | store x
+0003    | clear-result
+0004    2 statement
+0005    | load x
+0006    | constant 3
+0007    | binary SmallerThan
+0008    | jump-if-false 0016
+0009    3 statement
+0010    | load x
+0011    | constant 1
+0012    | binary Add
+0013    | store x
+0014    2 iteration
+0015    | jump 0005
+0016    | clear-result
+0017    5 statement
+0018    | load x
+0019    | set-result
+");
+
+        let chunk = compile(&parse("fn f(a) {\n  return a || g()\n}").unwrap());
+        assert_eq!(chunk.functions[0].chunk.to_string(), "\
+0000    2 statement
+0001    | load a
+0002    | jump-if-true 0007
+0003    | load-function g
+0004    | call 0
+0005    | truthy
+0006    | jump 0008
+0007    | true
+0008    | return
+0009    1 nil
+0010    | return
+");
+    }
+
+    #[test]
+    fn test_compile_lines() {
+        let source = "10 gosub 40\n20 print 1\n30 goto 60\n40 return";
+        let chunk = compile(&parse_with_dialect(source, Dialect::Classic).unwrap());
+        let jumps: Vec<_> = chunk.code.iter().filter(|op| matches!(op, Op::Gosub { .. } | Op::Jump(_) | Op::UndefinedLine(_))).collect();
+        assert_eq!(jumps, vec![&Op::Gosub { target: 10, resume: 3 }, &Op::UndefinedLine(1)]);
+        assert_eq!(chunk.code[10], Op::Statement);
+        assert_eq!(chunk.code[3], Op::Statement);
+    }
+
+    #[test]
+    fn test_pools() {
+        let chunk = compile(&parse("x = \"a\" + \"a\"\nx = 1 + 1.0 + 1\nprint x, \"a\"").unwrap());
+        assert_eq!(chunk.constants, vec![Value::from("a"), Value::Int(1), Value::Float(1.0)]);
+        assert_eq!(chunk.names, vec!["x".to_string()]);
+
+        let (mut pool, mut constants) = (Interner::default(), Vec::new());
+        assert_eq!(pool.constant(&mut constants, Value::Float(0.0)), 0);
+        assert_eq!(pool.constant(&mut constants, Value::Float(-0.0)), 1);
+        assert_eq!(pool.constant(&mut constants, Value::Float(-0.0)), 1);
+
+        let function = compile(&parse("fn f() {\n  return \"a\"\n}").unwrap()).functions[0].clone();
+        assert_eq!(function.chunk.constants, vec![Value::from("a")]);
+    }
+
+    #[test]
+    fn test_compiled_program() {
+        assert!(Program::compile("x = (").is_err());
+        let compiled = Program::compile("print greeting\nn = n + 1\nn").unwrap();
+        for (greeting, n) in [("hi", 1), ("hello", 41)] {
+            let output = SharedBuffer::new();
+            let mut interpreter = Interpreter::with_output(Box::new(output.clone()));
+            interpreter.set_variable("greeting", Value::from(greeting));
+            interpreter.set_variable("n", Value::Int(n));
+            let program = compiled.clone();
+            assert_eq!(program.run(&mut interpreter), Ok(Value::Int(n + 1)));
+            assert_eq!(program.run(&mut interpreter), Ok(Value::Int(n + 2)));
+            assert_eq!(output.contents(), format!("{}\n{}\n", greeting, greeting));
+        }
+        assert!(matches!(compiled.compiled(), Compiled::Stack(_)));
+    }
+
+    #[test]
+    fn test_compiled_program_threads() {
+        fn shared<T: Send + Sync>() {}
+        shared::<SharedProgram>();
+
+        let source = "struct P { x }\nfn f(p) {\n  return p.x * 2\n}\nf(P(21)) + len(\"abc\")";
+        let options = CompileOptions { backend: Backend::Register, ..CompileOptions::default() };
+        for compiled in [Program::compile(source).unwrap(), CompiledProgram::new(&parse(source).unwrap(), &options)] {
+            let shared = compiled.shared();
+            let threads: Vec<_> = (0..2)
+                .map(|_| {
+                    let shared = shared.clone();
+                    std::thread::spawn(move || {
+                        let program = shared.program();
+                        let first = program.run(&mut Interpreter::new()).unwrap();
+                        assert_eq!(program.run(&mut Interpreter::new()), Ok(first.clone()));
+                        first.to_string()
+                    })
+                })
+                .collect();
+            for thread in threads {
+                assert_eq!(thread.join().unwrap(), "45");
+            }
+        }
+    }
+
+    #[test]
+    fn test_compile_tail_calls() {
+        let source = "fn f(n) {\n  if n { return f(n - 1) }\n  return 1 + f(0)\n}";
+        let chunk = compile_program(&parse(source).unwrap(), true);
+        assert_eq!(chunk.functions[0].chunk.to_string(), "\
+0000    2 statement
+0001    | load n
+0002    | jump-if-false 0010
+0003    | statement
+0004    | load-function f
+0005    | load n
+0006    | constant 1
+0007    | binary Sub
+0008    | tail-call 1
+0009    | return
+0010    3 statement
+0011    | constant 1
+0012    | load-function f
+0013    | constant 0
+0014    | call 1
+0015    | binary Add
+0016    | return
+0017    1 nil
+0018    | return
+");
+        assert!(!compile(&parse(source).unwrap()).functions[0].chunk.code.iter().any(|op| matches!(op, Op::TailCall { .. })));
+    }
+}
@@ -0,0 +1,336 @@
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::formatter::{BraceStyle, FormatOptions};
+use crate::lint::{Linter, Severity};
+use crate::parser::{Dialect, ParseOptions};
+
+/// The name of a project's configuration file.
+pub const CONFIG_FILE: &str = "tbasic.toml";
+
+/// A project's settings, read from a `tbasic.toml` like
+///
+/// ```toml
+/// # Run by `tbasic run` without a script
+/// entry = "src/main.tb"
+/// dialect = "classic"
+/// strict = true
+///
+/// [lint]
+/// magic-numbers = "deny"
+///
+/// [fmt]
+/// indent = 4
+/// tabs = false
+/// max-width = 80
+/// brace-style = "closing-next-line"
+///
+/// [repl]
+/// history = false
+/// ```
+///
+/// The file is the part of TOML these need: tables of keys set to strings,
+/// booleans or integers, and `#` comments.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Config {
+    /// The file the settings were read from, `None` for the defaults.
+    pub path: Option<PathBuf>,
+    /// `dialect`, and `strict` for strict declarations.
+    pub parse: ParseOptions,
+    /// The script to run when none is given, relative to the file's
+    /// directory in the file but not here.
+    pub entry: Option<PathBuf>,
+    /// The severities set in `[lint]`, in order.
+    pub lints: Vec<(String, Severity)>,
+    /// The options set in `[fmt]`.
+    pub format: FormatOptions,
+    /// `history` in `[repl]`.
+    pub history: HistoryFile,
+}
+
+/// Where the REPL keeps the lines entered across sessions.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub enum HistoryFile {
+    /// `tbasic/history` in the platform's directory for application data,
+    /// or `history = true`.
+    #[default]
+    Default,
+    /// `history = "<file>"`, relative to the configuration file.
+    Path(PathBuf),
+    /// `history = false`: the history is forgotten when the REPL exits.
+    Off,
+}
+
+/// Why a configuration file couldn't be read.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConfigError {
+    pub path: PathBuf,
+    /// The 1-based line the problem is on, 0 if the file couldn't be read.
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.line {
+            0 => write!(f, "{}: {}", self.path.display(), self.message),
+            line => write!(f, "{}:{}: {}", self.path.display(), line, self.message),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+/// A value in a configuration file.
+#[derive(Debug, Clone, PartialEq)]
+enum Value {
+    Str(String),
+    Bool(bool),
+    Int(i64),
+}
+
+impl Config {
+    /// Reads the `tbasic.toml` in `dir` or the nearest directory above it
+    /// that has one, or returns the defaults if none does.
+    pub fn discover(dir: &Path) -> Result<Config, ConfigError> {
+        match dir.ancestors().map(|dir| dir.join(CONFIG_FILE)).find(|path| path.is_file()) {
+            Some(path) => Config::load(&path),
+            None => Ok(Config::default()),
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<Config, ConfigError> {
+        let source = fs::read_to_string(path)
+            .map_err(|error| ConfigError { path: path.to_path_buf(), line: 0, message: error.to_string() })?;
+        Config::parse(&source, path)
+    }
+
+    /// Reads the settings in `source`, the contents of the file at `path`.
+    pub fn parse(source: &str, path: &Path) -> Result<Config, ConfigError> {
+        let mut config = Config { path: Some(path.to_path_buf()), ..Config::default() };
+        let mut table = String::new();
+        let mut seen = Vec::new();
+        for (i, line) in source.lines().enumerate() {
+            let error = |message: String| ConfigError { path: path.to_path_buf(), line: i + 1, message };
+            let line = strip_comment(line).trim();
+            if line.is_empty() {
+                continue;
+            }
+            if let Some(name) = line.strip_prefix('[').and_then(|line| line.strip_suffix(']')) {
+                table = name.trim().to_string();
+                if !["lint", "fmt", "repl"].contains(&table.as_str()) {
+                    return Err(error(format!("unknown table `[{}]`", table)));
+                }
+                continue;
+            }
+            let (key, value) = key_value(line).map_err(error)?;
+            if seen.contains(&(table.clone(), key.clone())) {
+                return Err(error(format!("`{}` is set twice", key)));
+            }
+            seen.push((table.clone(), key.clone()));
+            config.set(&table, &key, value, path).map_err(error)?;
+        }
+        Ok(config)
+    }
+
+    fn set(&mut self, table: &str, key: &str, value: Value, path: &Path) -> Result<(), String> {
+        match (table, key, value) {
+            ("", "entry", Value::Str(entry)) => {
+                self.entry = Some(path.parent().unwrap_or(Path::new("")).join(entry));
+            },
+            ("", "dialect", Value::Str(dialect)) => {
+                self.parse.dialect = match dialect.as_str() {
+                    "modern" => Dialect::Modern,
+                    "classic" => Dialect::Classic,
+                    _ => return Err(format!("`dialect` must be \"modern\" or \"classic\", not {:?}", dialect)),
+                }
+            },
+            ("", "strict", Value::Bool(strict)) => self.parse.strict_declarations = strict,
+            ("lint", rule, Value::Str(severity)) => {
+                if !Linter::new().has_rule(rule) {
+                    return Err(format!("there is no lint rule `{}`", rule));
+                }
+                let severity = match severity.as_str() {
+                    "allow" => Severity::Allow,
+                    "warn" => Severity::Warn,
+                    "deny" => Severity::Deny,
+                    _ => return Err(format!("`{}` must be \"allow\", \"warn\" or \"deny\", not {:?}", rule, severity)),
+                };
+                self.lints.push((rule.to_string(), severity));
+            },
+            ("fmt", "indent", Value::Int(indent)) => match usize::try_from(indent) {
+                Ok(indent @ 1..=16) => self.format.indent = indent,
+                _ => return Err("`indent` must be between 1 and 16".to_string()),
+            },
+            ("fmt", "tabs", Value::Bool(tabs)) => self.format.tabs = tabs,
+            ("fmt", "max-width", Value::Int(width)) => match usize::try_from(width) {
+                Ok(width @ 20..) => self.format.max_width = width,
+                _ => return Err("`max-width` must be at least 20".to_string()),
+            },
+            ("fmt", "brace-style", Value::Str(style)) => {
+                self.format.brace_style = match style.as_str() {
+                    "always-same-line" => BraceStyle::AlwaysSameLine,
+                    "closing-next-line" => BraceStyle::ClosingNextLine,
+                    _ => {
+                        let expected = "\"always-same-line\" or \"closing-next-line\"";
+                        return Err(format!("`brace-style` must be {}, not {:?}", expected, style));
+                    },
+                }
+            },
+            ("repl", "history", Value::Bool(true)) => self.history = HistoryFile::Default,
+            ("repl", "history", Value::Bool(false)) => self.history = HistoryFile::Off,
+            ("repl", "history", Value::Str(file)) => {
+                self.history = HistoryFile::Path(path.parent().unwrap_or(Path::new("")).join(file));
+            },
+            ("", "entry" | "dialect", _) | ("lint", ..) => return Err(format!("`{}` must be a string", key)),
+            ("repl", "history", _) => return Err("`history` must be a file name, true or false".to_string()),
+            ("", "strict", _) => return Err("`strict` must be true or false".to_string()),
+            ("fmt", "indent" | "max-width", _) => return Err(format!("`{}` must be an integer", key)),
+            ("fmt", "tabs", _) => return Err("`tabs` must be true or false".to_string()),
+            ("fmt", "brace-style", _) => return Err("`brace-style` must be a string".to_string()),
+            ("", ..) => return Err(format!("unknown setting `{}`", key)),
+            (table, ..) => return Err(format!("unknown setting `{}` in `[{}]`", key, table)),
+        }
+        Ok(())
+    }
+
+    /// The built-in lint rules at the severities set in `[lint]`.
+    pub fn linter(&self) -> Linter {
+        let mut linter = Linter::new();
+        for (rule, severity) in &self.lints {
+            linter.set_severity(rule, *severity);
+        }
+        linter
+    }
+}
+
+/// `line` up to a `#` that isn't in a string.
+fn strip_comment(line: &str) -> &str {
+    let mut in_string = false;
+    let mut escaped = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if in_string => escaped = true,
+            '"' => in_string = !in_string,
+            '#' if !in_string => return &line[..i],
+            _ => {},
+        }
+    }
+    line
+}
+
+/// Splits `key = value`, where the key is bare like `magic-numbers` or
+/// quoted.
+fn key_value(line: &str) -> Result<(String, Value), String> {
+    let Some((key, value)) = line.split_once('=') else {
+        return Err(format!("expected `key = value`, found `{}`", line));
+    };
+    let key = key.trim();
+    let bare = !key.is_empty() && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    let key = match key.strip_prefix('"').and_then(|key| key.strip_suffix('"')) {
+        Some(quoted) => quoted.to_string(),
+        None if bare => key.to_string(),
+        None => return Err(format!("invalid key `{}`", key)),
+    };
+    Ok((key, value_of(value.trim())?))
+}
+
+fn value_of(text: &str) -> Result<Value, String> {
+    if let Some(quoted) = text.strip_prefix('"') {
+        let mut s = String::new();
+        let mut chars = quoted.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '"' if chars.as_str().is_empty() => return Ok(Value::Str(s)),
+                '\\' => match chars.next() {
+                    Some('"') => s.push('"'),
+                    Some('\\') => s.push('\\'),
+                    Some('n') => s.push('\n'),
+                    Some('t') => s.push('\t'),
+                    _ => return Err("invalid escape sequence in string".to_string()),
+                },
+                c => s.push(c),
+            }
+        }
+        return Err(format!("invalid string `{}`", text));
+    }
+    match text {
+        "true" => Ok(Value::Bool(true)),
+        "false" => Ok(Value::Bool(false)),
+        _ => text.replace('_', "").parse().map(Value::Int).map_err(|_| format!("invalid value `{}`", text)),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+    use std::path::Path;
+
+    use crate::config::{Config, ConfigError, HistoryFile};
+    use crate::formatter::{BraceStyle, FormatOptions};
+    use crate::lint::Severity;
+    use crate::parser::Dialect;
+
+    #[test]
+    fn test_parse() {
+        let source = "\
+# The project
+entry = \"src/main.tb\"  # relative to this file
+dialect = \"classic\"
+strict = true
+
+[lint]
+magic-numbers = \"deny\"
+\"deep-nesting\" = \"allow\"
+
+[fmt]
+indent = 4
+tabs = true
+max-width = 80
+brace-style = \"closing-next-line\"
+
+[repl]
+history = \".history\"
+";
+        let config = Config::parse(source, Path::new("/project/tbasic.toml")).unwrap();
+        assert_eq!(config.entry.as_deref(), Some(Path::new("/project/src/main.tb")));
+        assert_eq!((config.parse.dialect, config.parse.strict_declarations), (Dialect::Classic, true));
+        let lints = vec![("magic-numbers".to_string(), Severity::Deny), ("deep-nesting".to_string(), Severity::Allow)];
+        assert_eq!(config.lints, lints);
+        let format = FormatOptions { indent: 4, tabs: true, max_width: 80, brace_style: BraceStyle::ClosingNextLine };
+        assert_eq!(config.format, format);
+        assert_eq!(config.history, HistoryFile::Path("/project/.history".into()));
+        let history = Config::parse("[repl]\nhistory = false", Path::new("tbasic.toml")).unwrap().history;
+        assert_eq!(history, HistoryFile::Off);
+
+        let error = |source: &str| Config::parse(source, Path::new("tbasic.toml")).unwrap_err().to_string();
+        assert_eq!(error("strict = true\n\ndialect = \"new\""), "tbasic.toml:3: `dialect` must be \"modern\" or \"classic\", not \"new\"");
+        assert_eq!(error("[lint]\nunused = \"deny\""), "tbasic.toml:2: there is no lint rule `unused`");
+        assert_eq!(error("[fmt]\nindnt = 4"), "tbasic.toml:2: unknown setting `indnt` in `[fmt]`");
+        assert_eq!(error("strict = \"yes\""), "tbasic.toml:1: `strict` must be true or false");
+        assert_eq!(error("strict = true\nstrict = false"), "tbasic.toml:2: `strict` is set twice");
+        assert_eq!(error("[build]"), "tbasic.toml:1: unknown table `[build]`");
+        assert_eq!(error("[fmt]\nmax-width = 10"), "tbasic.toml:2: `max-width` must be at least 20");
+        assert_eq!(error("[fmt]\nbrace-style = \"allman\""), "tbasic.toml:2: `brace-style` must be \"always-same-line\" or \"closing-next-line\", not \"allman\"");
+        assert_eq!(error("[repl]\nhistory = 1"), "tbasic.toml:2: `history` must be a file name, true or false");
+        assert_eq!(error("entry = \"main.tb"), "tbasic.toml:1: invalid string `\"main.tb`");
+    }
+
+    #[test]
+    fn test_discover() {
+        let root = std::env::temp_dir().join(format!("tbasic-config-{}", std::process::id()));
+        let nested = root.join("src").join("lib");
+        fs::create_dir_all(&nested).unwrap();
+        assert_eq!(Config::discover(&nested).unwrap().path, None);
+
+        fs::write(root.join("tbasic.toml"), "[fmt]\nindent = 3\n").unwrap();
+        let config = Config::discover(&nested).unwrap();
+        assert_eq!((config.path, config.format.indent), (Some(root.join("tbasic.toml")), 3));
+
+        fs::write(root.join("tbasic.toml"), "indent = 3\n").unwrap();
+        let error = Config::discover(&nested).unwrap_err();
+        assert_eq!(error, ConfigError { path: root.join("tbasic.toml"), line: 1, message: "unknown setting `indent`".to_string() });
+    }
+}
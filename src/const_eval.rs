@@ -0,0 +1,118 @@
+use crate::ast::{BinaryOp, Expr, ExprKind, UnaryOp};
+use crate::interpreter::{binary_op, unary_op, Arithmetic, RuntimeError};
+use crate::lexer::Span;
+use crate::value::Value;
+
+/// Evaluates `expr` without running the program if its value can't depend
+/// on anything but itself, using the default arithmetic modes.
+///
+/// Returns `None` for expressions that aren't constant, and `Some(Err(_))`
+/// for constant ones that would fail at run time, such as `1 / 0`.
+pub fn const_eval(expr: &Expr) -> Option<Result<Value, RuntimeError>> {
+    const_eval_with(expr, Arithmetic::default())
+}
+
+/// Like [`const_eval`], following the interpreter's `arithmetic` settings.
+///
+/// Only literals and operators applied to constants are constant. Array and
+/// map literals aren't, since each evaluation creates a new shared value,
+/// and neither are calls, even to builtins, because scripts can redefine
+/// them. `&&` and `||` are constant if their left operand decides the
+/// result.
+pub fn const_eval_with(expr: &Expr, arithmetic: Arithmetic) -> Option<Result<Value, RuntimeError>> {
+    match &expr.kind {
+        ExprKind::Number(n) => Some(Ok(Value::Int(*n))),
+        ExprKind::Float(n) => Some(Ok(Value::Float(*n))),
+        ExprKind::Str(s) => Some(Ok(Value::from(s.as_str()))),
+        ExprKind::Unary { op, operand } => {
+            let value = const_eval_with(operand, arithmetic)?;
+            Some(value.and_then(|value| unary_op(*op, value, arithmetic, expr.span)))
+        },
+        ExprKind::Binary { op: op @ (BinaryOp::And | BinaryOp::Or), left, right } => {
+            let left = match const_eval_with(left, arithmetic)? {
+                Ok(left) => left.is_truthy(),
+                Err(error) => return Some(Err(error)),
+            };
+            if left == (*op == BinaryOp::Or) {
+                return Some(Ok(Value::Bool(left)));
+            }
+            let right = const_eval_with(right, arithmetic)?;
+            Some(right.map(|right| Value::Bool(right.is_truthy())))
+        },
+        ExprKind::Binary { op, left, right } => {
+            let left = const_eval_with(left, arithmetic)?;
+            let right = const_eval_with(right, arithmetic)?;
+            Some(left.and_then(|left| binary_op(*op, left, right?, arithmetic, expr.span)))
+        },
+        ExprKind::Variable(_)
+        | ExprKind::Array(_)
+        | ExprKind::Map(_)
+        | ExprKind::Index { .. }
+        | ExprKind::Field { .. }
+        | ExprKind::Call { .. }
+        | ExprKind::Function(_) => None,
+    }
+}
+
+/// The value of the constant operands `left` and `right` combined by `op`,
+/// as [`const_eval`] would compute it, if it is the same under every
+/// arithmetic setting and doesn't fail.
+pub fn fold_binary(op: BinaryOp, left: Value, right: Value) -> Option<Value> {
+    let ints = matches!(left, Value::Int(_)) && matches!(right, Value::Int(_));
+    if op == BinaryOp::Div && ints {
+        return None;
+    }
+    // Without overflow, the overflow mode makes no difference
+    binary_op(op, left, right, Arithmetic::default(), Span::default()).ok()
+}
+
+/// Like [`fold_binary`] for a unary operator.
+pub fn fold_unary(op: UnaryOp, operand: Value) -> Option<Value> {
+    unary_op(op, operand, Arithmetic::default(), Span::default()).ok()
+}
+
+#[cfg(test)]
+mod test {
+    use crate::ast::StmtKind;
+    use crate::ast::{BinaryOp, UnaryOp};
+    use crate::const_eval::{const_eval, const_eval_with, fold_binary, fold_unary};
+    use crate::interpreter::{Arithmetic, DivisionMode, RuntimeError};
+    use crate::parser::parse;
+    use crate::value::Value;
+
+    fn eval(source: &str) -> Option<Result<Value, RuntimeError>> {
+        let program = parse(source).unwrap();
+        match &program.statements[0].kind {
+            StmtKind::Expr(expr) => const_eval(expr),
+            other => panic!("unexpected statement {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_constants() {
+        assert_eq!(eval("1 + 2 * 3"), Some(Ok(Value::Int(7))));
+        assert_eq!(eval("-(2.5) + 1"), Some(Ok(Value::Float(-1.5))));
+        assert_eq!(eval("\"a\" + \"b\" == \"ab\""), Some(Ok(Value::Bool(true))));
+        assert_eq!(eval("!(3 > 4)"), Some(Ok(Value::Bool(true))));
+        assert_eq!(eval("0 && x"), Some(Ok(Value::Bool(false))));
+        assert_eq!(eval("1 || f()"), Some(Ok(Value::Bool(true))));
+        assert_eq!(eval("1 && x"), None);
+        assert_eq!(eval("x + 1"), None);
+        assert_eq!(eval("[1][0]"), None);
+        assert_eq!(eval("len(\"a\")"), None);
+
+        assert!(matches!(eval("1 / 0"), Some(Err(RuntimeError::DivisionByZero { .. }))));
+        assert!(matches!(eval("9223372036854775807 + 1"), Some(Err(RuntimeError::IntegerOverflow { .. }))));
+        assert!(matches!(eval("1 - \"a\""), Some(Err(RuntimeError::TypeMismatch { .. }))));
+
+        let program = parse("7 / 2").unwrap();
+        let StmtKind::Expr(expr) = &program.statements[0].kind else { unreachable!() };
+        let arithmetic = Arithmetic { division: DivisionMode::Float, ..Arithmetic::default() };
+        assert_eq!(const_eval_with(expr, arithmetic), Some(Ok(Value::Float(3.5))));
+
+        assert_eq!(fold_binary(BinaryOp::Mul, Value::Int(6), Value::Float(0.5)), Some(Value::Float(3.0)));
+        assert_eq!(fold_binary(BinaryOp::Div, Value::Int(7), Value::Int(2)), None);
+        assert_eq!(fold_binary(BinaryOp::Add, Value::Int(i64::MAX), Value::Int(1)), None);
+        assert_eq!(fold_unary(UnaryOp::Neg, Value::Int(1)), Some(Value::Int(-1)));
+    }
+}
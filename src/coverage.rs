@@ -0,0 +1,214 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+
+use crate::ast::{ExprKind, Program, Stmt, StmtKind};
+use crate::check::visit_statement;
+
+/// Which lines of some source files ran, and how often. Lines are added by
+/// [`add_file`](Coverage::add_file), which knows the lines that have
+/// statements, and by [`record`](Coverage::record) as statements run; runs
+/// of several programs add up.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Coverage {
+    files: BTreeMap<String, FileCoverage>,
+}
+
+/// How often each line of one file with a statement on it ran.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct FileCoverage {
+    lines: BTreeMap<usize, u64>,
+}
+
+impl Coverage {
+    pub fn new() -> Coverage {
+        Coverage::default()
+    }
+
+    /// Makes the lines of `program`, the source of `path`, count as
+    /// executable, including the bodies of functions that never ran.
+    pub fn add_file(&mut self, path: &str, program: &Program) {
+        let mut lines = BTreeSet::new();
+        executable_lines(&program.statements, &mut lines);
+        let file = self.file(path);
+        for line in lines {
+            file.lines.entry(line).or_insert(0);
+        }
+    }
+
+    /// Counts a run of a statement on `line` of `path`.
+    pub fn record(&mut self, path: &str, line: usize) {
+        *self.file(path).lines.entry(line).or_insert(0) += 1;
+    }
+
+    fn file(&mut self, path: &str) -> &mut FileCoverage {
+        if !self.files.contains_key(path) {
+            self.files.insert(path.to_string(), FileCoverage::default());
+        }
+        self.files.get_mut(path).unwrap()
+    }
+
+    /// The files, in order of their paths.
+    pub fn files(&self) -> impl Iterator<Item = (&str, &FileCoverage)> {
+        self.files.iter().map(|(path, file)| (path.as_str(), file))
+    }
+
+    /// A table of how many lines of each file ran, listing those that
+    /// didn't.
+    pub fn summary(&self) -> String {
+        let width = self.files.keys().map(|path| path.chars().count()).chain([5]).max().unwrap_or(0);
+        let mut summary = String::new();
+        let (mut covered, mut total) = (0, 0);
+        for (path, file) in &self.files {
+            let _ = write!(summary, "{:<width$}  {}", path, ratio(file.covered(), file.total()));
+            let missed = ranges(&file.missed());
+            if !missed.is_empty() {
+                let _ = write!(summary, "  missed {}", missed);
+            }
+            summary.push('\n');
+            covered += file.covered();
+            total += file.total();
+        }
+        let _ = writeln!(summary, "{:<width$}  {}", "total", ratio(covered, total));
+        summary
+    }
+
+    /// The coverage in the LCOV tracefile format read by `genhtml` and
+    /// coverage services.
+    pub fn lcov(&self) -> String {
+        let mut lcov = String::new();
+        for (path, file) in &self.files {
+            let _ = writeln!(lcov, "SF:{}", path);
+            for (line, hits) in &file.lines {
+                let _ = writeln!(lcov, "DA:{},{}", line, hits);
+            }
+            let _ = writeln!(lcov, "LH:{}\nLF:{}\nend_of_record", file.covered(), file.total());
+        }
+        lcov
+    }
+}
+
+impl FileCoverage {
+    /// How often the statements on `line` ran, `None` if it has none.
+    pub fn hits(&self, line: usize) -> Option<u64> {
+        self.lines.get(&line).copied()
+    }
+
+    /// How many lines with statements ran.
+    pub fn covered(&self) -> usize {
+        self.lines.values().filter(|&&hits| hits > 0).count()
+    }
+
+    /// How many lines have statements.
+    pub fn total(&self) -> usize {
+        self.lines.len()
+    }
+
+    /// The lines with statements that never ran.
+    pub fn missed(&self) -> Vec<usize> {
+        self.lines.iter().filter(|(_, &hits)| hits == 0).map(|(&line, _)| line).collect()
+    }
+}
+
+fn ratio(covered: usize, total: usize) -> String {
+    let percent = if total == 0 { 100.0 } else { 100.0 * covered as f64 / total as f64 };
+    format!("{:>4}/{:<4} {:5.1}%", covered, total, percent)
+}
+
+/// `lines` as a comma-separated list, runs of consecutive lines as
+/// `first-last`.
+fn ranges(lines: &[usize]) -> String {
+    let mut ranges: Vec<(usize, usize)> = Vec::new();
+    for &line in lines {
+        match ranges.last_mut() {
+            Some((_, last)) if *last + 1 == line => *last = line,
+            _ => ranges.push((line, line)),
+        }
+    }
+    let ranges: Vec<String> = ranges
+        .iter()
+        .map(|&(first, last)| if first == last { first.to_string() } else { format!("{}-{}", first, last) })
+        .collect();
+    ranges.join(", ")
+}
+
+/// Adds the line of every statement in `statements` and the blocks and
+/// functions nested in them to `lines`.
+fn executable_lines(statements: &[Stmt], lines: &mut BTreeSet<usize>) {
+    for statement in statements {
+        lines.insert(statement.span.line);
+        match &statement.kind {
+            StmtKind::If { then_branch, else_branch, .. } => {
+                executable_lines(then_branch, lines);
+                executable_lines(else_branch.as_deref().unwrap_or_default(), lines);
+            },
+            StmtKind::While { body, .. } => executable_lines(body, lines),
+            StmtKind::Function(decl) => executable_lines(&decl.body, lines),
+            _ => {},
+        }
+        // Anonymous functions, which may be nested in each other
+        visit_statement(statement, &mut |expr| {
+            if let ExprKind::Function(decl) = &expr.kind {
+                executable_lines(&decl.body, lines);
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use crate::coverage::Coverage;
+    use crate::debug::{DebugAction, StatementEvent};
+    use crate::interpreter::{Interpreter, SharedBuffer};
+    use crate::modules::MemoryLoader;
+    use crate::parser::parse;
+
+    #[test]
+    fn test_coverage() {
+        let source = "\
+import util
+fn unused() {
+  return 1
+}
+f = fn(x) {
+  if x > 0 {
+    return util.twice(x)
+  } else {
+    return 0
+  }
+}
+print f(2)";
+        let module = "fn twice(x) {\n  return 2 * x\n}\nfn thrice(x) {\n  return 3 * x\n}";
+        let program = parse(source).unwrap();
+        let mut coverage = Coverage::new();
+        coverage.add_file("main.tb", &program);
+        coverage.add_file("util.tb", &parse(module).unwrap());
+
+        let recorded = Rc::new(RefCell::new(coverage));
+        let hook = recorded.clone();
+        let mut interpreter = Interpreter::with_output(Box::new(SharedBuffer::new()));
+        interpreter.set_module_loader(Box::new(MemoryLoader::new().with_module("util", module)));
+        interpreter.set_debug_hook(Box::new(move |event: &mut StatementEvent<'_>| {
+            let path = event.module().map_or("main.tb".to_string(), |module| format!("{}.tb", module));
+            hook.borrow_mut().record(&path, event.span().line);
+            DebugAction::Continue
+        }));
+        interpreter.run(&program).unwrap();
+        // A module's top level only runs when it is first imported
+        interpreter.run(&program).unwrap();
+
+        let coverage = recorded.borrow();
+        let main = coverage.files().next().unwrap().1;
+        assert_eq!((main.hits(1), main.hits(3), main.hits(7), main.hits(8)), (Some(2), Some(0), Some(2), None));
+        assert_eq!(main.missed(), vec![3, 9]);
+        assert_eq!(coverage.summary(), "\
+main.tb     6/8     75.0%  missed 3, 9
+util.tb     3/4     75.0%  missed 5
+total       9/12    75.0%
+");
+        assert!(coverage.lcov().starts_with("SF:main.tb\nDA:1,2\nDA:2,2\nDA:3,0\n"), "{}", coverage.lcov());
+        assert!(coverage.lcov().ends_with("SF:util.tb\nDA:1,1\nDA:2,2\nDA:4,1\nDA:5,0\nLH:3\nLF:4\nend_of_record\n"));
+    }
+}
@@ -0,0 +1,198 @@
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use crate::interpreter::{Interpreter, TraceFrame};
+use crate::lexer::Span;
+use crate::value::Value;
+
+/// What the interpreter should do after a debug hook returns.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DebugAction {
+    Continue,
+    /// Stop the script with [`RuntimeError::Aborted`](crate::interpreter::RuntimeError::Aborted).
+    Abort,
+}
+
+/// Receives a callback before every statement the interpreter runs, or
+/// when a breakpoint is hit.
+pub trait DebugHook {
+    fn on_statement(&mut self, event: &mut StatementEvent<'_>) -> DebugAction;
+}
+
+impl<F> DebugHook for F
+where
+    F: FnMut(&mut StatementEvent<'_>) -> DebugAction,
+{
+    fn on_statement(&mut self, event: &mut StatementEvent<'_>) -> DebugAction {
+        self(event)
+    }
+}
+
+/// The statement about to run and the state it will run in.
+pub struct StatementEvent<'a> {
+    pub(crate) span: Span,
+    pub(crate) interpreter: &'a mut Interpreter,
+}
+
+impl StatementEvent<'_> {
+    pub fn span(&self) -> Span {
+        self.span
+    }
+
+    /// Number of script function calls in progress; 0 at the top level.
+    pub fn depth(&self) -> usize {
+        self.interpreter.call_depth()
+    }
+
+    /// The name of the module the statement is in, or `None` if it is in
+    /// the main program.
+    pub fn module(&self) -> Option<String> {
+        self.interpreter.current_module()
+    }
+
+    /// The calls in progress, innermost first.
+    pub fn backtrace(&self) -> Vec<TraceFrame> {
+        self.interpreter.backtrace()
+    }
+
+    /// Snapshot of every variable visible to the statement, sorted by name.
+    pub fn variables(&self) -> Vec<(String, Value)> {
+        self.interpreter.variables()
+    }
+
+    pub fn variable(&self, name: &str) -> Option<Value> {
+        self.interpreter.variable(name)
+    }
+
+    /// Adds a breakpoint while the script is paused; see
+    /// [`Interpreter::set_breakpoint`].
+    pub fn set_breakpoint(&mut self, line: usize) {
+        self.interpreter.set_breakpoint(line);
+    }
+
+    pub fn clear_breakpoint(&mut self, line: usize) {
+        self.interpreter.clear_breakpoint(line);
+    }
+
+    /// Changes a variable before the statement runs; see
+    /// [`Interpreter::set_variable`].
+    pub fn set_variable(&mut self, name: &str, value: Value) {
+        self.interpreter.set_variable(name, value);
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod test {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use crate::debug::{DebugAction, StatementEvent};
+    use crate::interpreter::{Interpreter, RuntimeError, SharedBuffer};
+    use crate::parser::parse;
+    use crate::value::Value;
+
+    #[test]
+    fn test_trace() {
+        let trace = Rc::new(RefCell::new(Vec::new()));
+        let mut interpreter = Interpreter::with_output(Box::new(SharedBuffer::new()));
+        let recorded = trace.clone();
+        interpreter.set_debug_hook(Box::new(move |event: &mut StatementEvent<'_>| {
+            let vars: Vec<String> = event.variables().into_iter().map(|(name, value)| format!("{}={}", name, value)).collect();
+            recorded.borrow_mut().push(format!("{}:{} {}", event.span().line, event.depth(), vars.join(",")));
+            DebugAction::Continue
+        }));
+        let program = parse("x = 1\nfn f(a) {\n  return a + x\n}\ny = f(2)").unwrap();
+        assert_eq!(interpreter.run(&program), Ok(Value::Nil));
+        assert_eq!(*trace.borrow(), vec!["1:0 ", "2:0 x=1", "5:0 f=<fn f>,x=1", "3:1 a=2,f=<fn f>,x=1"]);
+    }
+
+    #[test]
+    fn test_abort() {
+        let mut interpreter = Interpreter::with_output(Box::new(SharedBuffer::new()));
+        interpreter.set_debug_hook(Box::new(|event: &mut StatementEvent<'_>| {
+            if event.span().line == 3 {
+                DebugAction::Abort
+            } else {
+                DebugAction::Continue
+            }
+        }));
+        let result = interpreter.run(&parse("x = 1\nx = 2\nx = 3").unwrap());
+        assert!(matches!(result, Err(RuntimeError::Aborted { .. })));
+        assert_eq!(interpreter.get_var("x"), Some(&Value::Int(2)));
+    }
+
+    #[test]
+    fn test_breakpoints() {
+        let hits = Rc::new(RefCell::new(Vec::new()));
+        let mut interpreter = Interpreter::with_output(Box::new(SharedBuffer::new()));
+        let recorded = hits.clone();
+        interpreter.set_breakpoint_handler(Box::new(move |event: &mut StatementEvent<'_>| {
+            let i = event.variables().into_iter().find(|(name, _)| name == "i").map(|(_, value)| value);
+            recorded.borrow_mut().push((event.span().line, i));
+            DebugAction::Continue
+        }));
+        interpreter.set_breakpoint(3);
+        interpreter.set_breakpoint(5);
+        interpreter.clear_breakpoint(5);
+        assert_eq!(interpreter.breakpoints(), vec![3]);
+
+        let program = parse("i = 0\nwhile i < 2 {\n  if i < 5 { i = i + 1 }\n}\nprint i").unwrap();
+        assert_eq!(interpreter.run(&program), Ok(Value::Nil));
+        assert_eq!(*hits.borrow(), vec![(3, Some(Value::Int(0))), (3, Some(Value::Int(1)))]);
+    }
+
+    #[test]
+    fn test_backtrace() {
+        let traces = Rc::new(RefCell::new(Vec::new()));
+        let mut interpreter = Interpreter::with_output(Box::new(SharedBuffer::new()));
+        let recorded = traces.clone();
+        interpreter.set_breakpoint_handler(Box::new(move |event: &mut StatementEvent<'_>| {
+            let trace: Vec<String> = event
+                .backtrace()
+                .iter()
+                .map(|frame| format!("{}@{}:{}", frame.function, frame.call_site.line, frame.call_site.column))
+                .collect();
+            recorded.borrow_mut().push(trace.join(" "));
+            // Breakpoints set from a paused script take effect right away
+            event.set_breakpoint(5);
+            DebugAction::Continue
+        }));
+        interpreter.set_breakpoint(2);
+        let program = parse("fn f(n) {\n  return n\n}\nfn g() {\n  return f(1)\n}\nx = g()\ny = g()").unwrap();
+        interpreter.run(&program).unwrap();
+        assert_eq!(*traces.borrow(), vec!["f@5:10 g@7:5", "g@8:5", "f@5:10 g@8:5"]);
+        assert_eq!(interpreter.backtrace(), Vec::new());
+    }
+
+    #[test]
+    fn test_abort_at_breakpoint() {
+        let mut interpreter = Interpreter::with_output(Box::new(SharedBuffer::new()));
+        interpreter.set_breakpoint_handler(Box::new(|_: &mut StatementEvent<'_>| DebugAction::Abort));
+        interpreter.set_breakpoint(2);
+        let result = interpreter.run(&parse("x = 1\nx = 2").unwrap());
+        assert_eq!(result.unwrap_err().to_string(), "2:1: execution aborted by debugger");
+        assert_eq!(interpreter.get_var("x"), Some(&Value::Int(1)));
+    }
+
+    #[test]
+    fn test_inspect_and_mutate() {
+        let mut interpreter = Interpreter::with_output(Box::new(SharedBuffer::new()));
+        interpreter.set_breakpoint_handler(Box::new(|event: &mut StatementEvent<'_>| {
+            assert_eq!(event.variable("a"), Some(Value::Int(2)));
+            event.set_variable("a", Value::Int(10));
+            event.set_variable("total", Value::Int(100));
+            event.set_variable("fresh", Value::Int(1));
+            DebugAction::Continue
+        }));
+        interpreter.set_breakpoint(4);
+        let program = parse("total = 0\nfn f(a) {\n  b = fn() { return a }\n  return b() + total\n}\nr = f(2)").unwrap();
+        interpreter.run(&program).unwrap();
+        assert_eq!(interpreter.get_var("r"), Some(&Value::Int(110)));
+        assert_eq!(interpreter.get_var("total"), Some(&Value::Int(100)));
+        assert_eq!(interpreter.get_var("fresh"), None);
+
+        interpreter.set_variable("fresh", Value::Int(3));
+        assert_eq!(interpreter.variable("fresh"), Some(Value::Int(3)));
+        assert_eq!(interpreter.variables().len(), 4);
+    }
+}
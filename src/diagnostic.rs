@@ -0,0 +1,385 @@
+use std::fmt::{self, Write};
+
+use crate::check::Warning;
+use crate::codes;
+use crate::interpreter::{RuntimeError, TRACE_DISPLAY_LIMIT};
+use crate::lexer::{LexError, Span};
+use crate::lint::{Lint, Severity};
+use crate::parser::ParseError;
+use crate::typecheck::TypeError;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    /// The program can't run, or would fail when it gets there.
+    Error,
+    Warning,
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Level::Error => write!(f, "error"),
+            Level::Warning => write!(f, "warning"),
+        }
+    }
+}
+
+/// Anything the lexer, parser, [`check`](crate::check),
+/// [`typecheck`](crate::typecheck) or a [`Linter`](crate::lint::Linter)
+/// reports, in one shape for frontends to render.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub level: Level,
+    /// Identifies the kind of diagnostic for tools, e.g. `syntax-error` or
+    /// the [`Warning::code`] or lint rule it comes from.
+    pub code: &'static str,
+    /// The stable [code](crate::codes) `tbasic explain` explains, e.g.
+    /// `E0203`. `None` for lint rules that aren't built in.
+    pub id: Option<&'static str>,
+    pub message: String,
+    pub span: Span,
+    /// Further explanations, shown below the message.
+    pub notes: Vec<String>,
+    /// Other places in the source that explain the diagnostic.
+    pub labels: Vec<Label>,
+}
+
+/// A span of the source shown with a diagnostic, underlined and captioned.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Label {
+    pub span: Span,
+    pub message: String,
+}
+
+impl Label {
+    pub fn new(span: Span, message: impl Into<String>) -> Label {
+        Label { span, message: message.into() }
+    }
+}
+
+/// The escape sequences [`Diagnostic::render_with`] colors its parts with,
+/// all empty for plain text.
+struct Style {
+    error: &'static str,
+    warning: &'static str,
+    label: &'static str,
+    bold: &'static str,
+    reset: &'static str,
+}
+
+const PLAIN: Style = Style { error: "", warning: "", label: "", bold: "", reset: "" };
+
+const COLORED: Style =
+    Style { error: "\x1b[1;31m", warning: "\x1b[1;33m", label: "\x1b[1;34m", bold: "\x1b[1m", reset: "\x1b[0m" };
+
+impl Diagnostic {
+    pub fn new(level: Level, code: &'static str, message: impl Into<String>, span: Span) -> Diagnostic {
+        let id = codes::named(code);
+        Diagnostic { level, code, id, message: message.into(), span, notes: Vec::new(), labels: Vec::new() }
+    }
+
+    /// The [`id`](Diagnostic::id) if there is one, the `code` otherwise:
+    /// what the rendered diagnostic shows in brackets.
+    pub fn tag(&self) -> &'static str {
+        self.id.unwrap_or(self.code)
+    }
+
+    pub fn with_note(mut self, note: impl Into<String>) -> Diagnostic {
+        self.notes.push(note.into());
+        self
+    }
+
+    pub fn with_label(mut self, label: Label) -> Diagnostic {
+        self.labels.push(label);
+        self
+    }
+
+    /// The diagnostic with the line of `source` it points at, the span
+    /// underlined, for printing to a terminal. `path` names the source in
+    /// the location line.
+    pub fn render(&self, path: &str, source: &str) -> String {
+        self.render_with(path, source, false)
+    }
+
+    /// Like [`render`](Diagnostic::render), also showing the lines of the
+    /// labels, their spans underlined with `-` and followed by their
+    /// messages. With `color` the parts are highlighted with ANSI escape
+    /// sequences.
+    pub fn render_with(&self, path: &str, source: &str, color: bool) -> String {
+        let style = if color { &COLORED } else { &PLAIN };
+        let accent = match self.level {
+            Level::Error => style.error,
+            Level::Warning => style.warning,
+        };
+        let (bold, reset) = (style.bold, style.reset);
+        let mut out = format!("{}{}[{}]{}{}: {}{}\n", accent, self.level, self.tag(), reset, bold, self.message, reset);
+        let lines: Vec<&str> = source.lines().collect();
+        let text = |line: usize| lines.get(line.wrapping_sub(1)).copied();
+
+        // The primary span first, so it is underlined first on its line
+        let mut marks = vec![(self.span, '^', accent, "")];
+        marks.extend(self.labels.iter().map(|label| (label.span, '-', style.label, label.message.as_str())));
+        marks.retain(|(span, ..)| text(span.line).is_some());
+        marks.sort_by_key(|(span, ..)| span.line);
+        let widest = marks.iter().map(|(span, ..)| span.line).max().unwrap_or(self.span.line);
+        let gutter = " ".repeat(widest.to_string().len());
+
+        if text(self.span.line).is_none() {
+            let _ = writeln!(out, "{}{}-->{} {}", gutter, style.label, reset, path);
+            marks.clear();
+        } else {
+            let _ = writeln!(out, "{}{}-->{} {}:{}:{}", gutter, style.label, reset, path, self.span.line, self.span.column);
+            let _ = writeln!(out, "{} {}|{}", gutter, style.label, reset);
+        }
+        let mut previous = None;
+        for &(span, underline, accent, message) in &marks {
+            let line = text(span.line).unwrap_or_default();
+            if previous != Some(span.line) {
+                if previous.is_some_and(|previous| previous + 1 < span.line) {
+                    let _ = writeln!(out, "{}...{}", style.label, reset);
+                }
+                let _ = writeln!(out, "{}{:>width$} |{} {}", style.label, span.line, reset, line, width = gutter.len());
+                previous = Some(span.line);
+            }
+            // Keep tabs so the underline lines up with the text above
+            let indent: String = line.chars().take(span.column - 1).map(|c| if c == '\t' { '\t' } else { ' ' }).collect();
+            let rest = line.chars().count().saturating_sub(span.column - 1);
+            let width = (span.end - span.start).min(rest).max(1);
+            let caption = if message.is_empty() { String::new() } else { format!(" {}", message) };
+            let underline = underline.to_string().repeat(width);
+            let _ = writeln!(out, "{} {}|{} {}{}{}{}{}", gutter, style.label, reset, indent, accent, underline, caption, reset);
+        }
+        for note in &self.notes {
+            let _ = writeln!(out, "{} {}={} {}note{}: {}", gutter, style.label, reset, bold, reset, note);
+        }
+        out
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}: {}[{}]: {}", self.span.line, self.span.column, self.level, self.tag(), self.message)?;
+        for note in &self.notes {
+            write!(f, "\n  note: {}", note)?;
+        }
+        Ok(())
+    }
+}
+
+impl From<LexError> for Diagnostic {
+    fn from(error: LexError) -> Self {
+        Diagnostic { id: Some(error.code), ..Diagnostic::new(Level::Error, "invalid-token", error.message, error.span) }
+    }
+}
+
+impl From<ParseError> for Diagnostic {
+    fn from(error: ParseError) -> Self {
+        Diagnostic { id: Some(error.code), ..Diagnostic::new(Level::Error, "syntax-error", error.message, error.span) }
+    }
+}
+
+impl From<Warning> for Diagnostic {
+    fn from(warning: Warning) -> Self {
+        let diagnostic = Diagnostic::new(Level::Warning, warning.code, warning.message, warning.span);
+        Diagnostic { labels: warning.labels, ..diagnostic }
+    }
+}
+
+impl From<TypeError> for Diagnostic {
+    fn from(error: TypeError) -> Self {
+        Diagnostic { id: Some(error.code), ..Diagnostic::new(Level::Error, "type-error", error.message, error.span) }
+    }
+}
+
+/// One note per call the error propagated through, eliding the middle of
+/// long traces like the error's `Display` does.
+impl From<RuntimeError> for Diagnostic {
+    fn from(error: RuntimeError) -> Self {
+        let diagnostic = Diagnostic::new(Level::Error, "runtime-error", error.message(), error.span());
+        let mut diagnostic = Diagnostic { id: Some(error.code()), ..diagnostic };
+        let trace = error.trace();
+        let elided = trace.len().saturating_sub(2 * TRACE_DISPLAY_LIMIT);
+        for (i, frame) in trace.iter().enumerate() {
+            if elided > 0 && i == TRACE_DISPLAY_LIMIT {
+                diagnostic = diagnostic.with_note(format!("... {} more calls", elided));
+            }
+            if elided > 0 && (TRACE_DISPLAY_LIMIT..TRACE_DISPLAY_LIMIT + elided).contains(&i) {
+                continue;
+            }
+            let span = frame.call_site;
+            diagnostic = diagnostic.with_note(format!("in `{}` called at {}:{}", frame.function, span.line, span.column));
+        }
+        diagnostic
+    }
+}
+
+impl From<Lint> for Diagnostic {
+    fn from(lint: Lint) -> Self {
+        match lint.severity {
+            Severity::Deny => Diagnostic::new(Level::Error, lint.rule, lint.message, lint.span)
+                .with_note(format!("the `{}` rule is set to deny", lint.rule)),
+            _ => Diagnostic::new(Level::Warning, lint.rule, lint.message, lint.span),
+        }
+    }
+}
+
+/// Collects the diagnostics of every pass run over a program.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Diagnostics {
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Diagnostics {
+    pub fn new() -> Diagnostics {
+        Diagnostics::default()
+    }
+
+    pub fn push(&mut self, diagnostic: impl Into<Diagnostic>) {
+        self.diagnostics.push(diagnostic.into());
+    }
+
+    pub fn has_errors(&self) -> bool {
+        self.diagnostics.iter().any(|diagnostic| diagnostic.level == Level::Error)
+    }
+
+    pub fn len(&self) -> usize {
+        self.diagnostics.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.diagnostics.is_empty()
+    }
+
+    pub fn iter(&self) -> std::slice::Iter<'_, Diagnostic> {
+        self.diagnostics.iter()
+    }
+
+    /// Orders the diagnostics by where they point, keeping the order of those
+    /// at the same place.
+    pub fn sort(&mut self) {
+        self.diagnostics.sort_by_key(|diagnostic| (diagnostic.span.line, diagnostic.span.column));
+    }
+}
+
+impl<D: Into<Diagnostic>> Extend<D> for Diagnostics {
+    fn extend<I: IntoIterator<Item = D>>(&mut self, diagnostics: I) {
+        for diagnostic in diagnostics {
+            self.push(diagnostic);
+        }
+    }
+}
+
+impl IntoIterator for Diagnostics {
+    type Item = Diagnostic;
+    type IntoIter = std::vec::IntoIter<Diagnostic>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.diagnostics.into_iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a Diagnostics {
+    type Item = &'a Diagnostic;
+    type IntoIter = std::slice::Iter<'a, Diagnostic>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.diagnostics.iter()
+    }
+}
+
+/// One diagnostic per line.
+impl fmt::Display for Diagnostics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for diagnostic in &self.diagnostics {
+            writeln!(f, "{}", diagnostic)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::check::check;
+    use crate::diagnostic::{Diagnostic, Diagnostics, Label, Level};
+    use crate::interpreter::{Interpreter, SharedBuffer};
+    use crate::lexer::Span;
+    use crate::lint::{DeepNesting, Linter, Severity};
+    use crate::parser::{parse, parse_into, Dialect};
+    use crate::typecheck::typecheck;
+
+    #[test]
+    fn test_render() {
+        let source = "fn f(x) {\n\treturn x / 0\n}\nprint f(1)";
+        let error = Interpreter::with_output(Box::new(SharedBuffer::new())).run(&parse(source).unwrap()).unwrap_err();
+        assert_eq!(Diagnostic::from(error).render("div.tb", source), "\
+error[E0303]: division by zero
+ --> div.tb:2:9
+  |
+2 | \treturn x / 0
+  | \t       ^^^^^
+  = note: in `f` called at 4:7
+");
+        let diagnostic = Diagnostic::new(Level::Warning, "unused-variable", "`x` is never read", Span::default());
+        assert_eq!(diagnostic.render("-", ""), "warning[W0001]: `x` is never read\n --> -\n");
+    }
+
+    #[test]
+    fn test_render_labels() {
+        let source = "fn f() {\n  return 1\n  print 2\n}\n\n\n\n\n\nx = 1\n";
+        let warning = check(&parse(source).unwrap()).into_iter().find(|warning| warning.code == "unreachable-code").unwrap();
+        let diagnostic = Diagnostic::from(warning).with_label(Label::new(Span { start: 0, end: 0, line: 10, column: 1 }, "and here"));
+        assert_eq!(diagnostic.render("f.tb", source), "\
+warning[W0004]: unreachable statement after `return`
+  --> f.tb:3:3
+   |
+ 2 |   return 1
+   |   -------- any code after this is unreachable
+ 3 |   print 2
+   |   ^^^^^^^
+...
+10 | x = 1
+   | - and here
+");
+        let colored = Diagnostic::new(Level::Error, "type-error", "bad", Span { start: 0, end: 1, line: 1, column: 1 })
+            .with_note("see")
+            .render_with("f.tb", "x", true);
+        assert_eq!(colored, "\
+\x1b[1;31merror[type-error]\x1b[0m\x1b[1m: bad\x1b[0m
+ \x1b[1;34m-->\x1b[0m f.tb:1:1
+  \x1b[1;34m|\x1b[0m
+\x1b[1;34m1 |\x1b[0m x
+  \x1b[1;34m|\x1b[0m \x1b[1;31m^\x1b[0m
+  \x1b[1;34m=\x1b[0m \x1b[1mnote\x1b[0m: see
+");
+    }
+
+    #[test]
+    fn test_diagnostics() {
+        let mut diagnostics = Diagnostics::new();
+        assert!(parse_into("x = \"abc", Dialect::Modern, &mut diagnostics).is_none());
+        assert!(parse_into("x = (1", Dialect::Modern, &mut diagnostics).is_none());
+        assert_eq!(diagnostics.to_string(), "1:5: error[E0001]: unterminated string\n1:6: error[E0101]: expected `)`, found end of input\n");
+
+        let source = "a = 1\nb = 2\nif a > 0 {\n  if a > 1 {\n    print \"x\" - 1\n  }\n}";
+        let mut diagnostics = Diagnostics::new();
+        let program = parse_into(source, Dialect::Modern, &mut diagnostics).unwrap();
+        let mut linter = Linter::new();
+        linter.set_severity("deep-nesting", Severity::Deny);
+        linter.register(Box::new(DeepNesting { max_depth: 1 }));
+        diagnostics.extend(linter.lint(&program));
+        diagnostics.extend(typecheck(&program));
+        diagnostics.extend(check(&program));
+        diagnostics.sort();
+        assert!(diagnostics.has_errors());
+        let levels: Vec<_> = diagnostics.iter().map(|diagnostic| (diagnostic.level, diagnostic.code)).collect();
+        assert_eq!(levels, vec![
+            (Level::Warning, "unused-variable"),
+            (Level::Error, "deep-nesting"),
+            (Level::Error, "type-error"),
+        ]);
+        assert_eq!(
+            diagnostics.iter().nth(1).unwrap().to_string(),
+            "4:3: error[W0104]: block nested 2 levels deep, more than the limit of 1\n  note: the `deep-nesting` rule is set to deny"
+        );
+    }
+}
@@ -0,0 +1,260 @@
+use std::fmt::Write;
+
+use crate::ast::{Program, StmtKind};
+
+/// A named function or a struct defined at the top level of a file, as
+/// `tbasic doc` documents it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Item {
+    pub name: String,
+    /// How it is declared, without the body: `fn f(x: int): int` or
+    /// `struct P { x, y }`.
+    pub signature: String,
+    pub kind: ItemKind,
+    /// Its `##` comment.
+    pub doc: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ItemKind {
+    /// The parameters with their type annotations, and the return type.
+    Function { params: Vec<(String, Option<String>)>, returns: Option<String> },
+    Struct { fields: Vec<String> },
+}
+
+/// The documentation of one file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Page {
+    pub title: String,
+    pub items: Vec<Item>,
+}
+
+/// The functions and structs a file defines at its top level, in order,
+/// except those whose names start with `_`, which are kept to the file.
+pub fn items(program: &Program) -> Vec<Item> {
+    let mut items = Vec::new();
+    for statement in &program.statements {
+        let item = match &statement.kind {
+            StmtKind::Function(decl) => {
+                let params: Vec<(String, Option<String>)> =
+                    decl.params.iter().zip(&decl.param_types).map(|(param, ty)| (param.clone(), ty.as_ref().map(|ty| ty.name.clone()))).collect();
+                let returns = decl.return_type.as_ref().map(|ty| ty.name.clone());
+                let typed = |name: &str, ty: &Option<String>| ty.as_ref().map_or(name.to_string(), |ty| format!("{}: {}", name, ty));
+                let list: Vec<String> = params.iter().map(|(param, ty)| typed(param, ty)).collect();
+                let signature = typed(&format!("fn {}({})", decl.name, list.join(", ")), &returns);
+                Item { name: decl.name.clone(), signature, kind: ItemKind::Function { params, returns }, doc: decl.doc.clone() }
+            },
+            StmtKind::Struct(decl) => {
+                let signature = if decl.fields.is_empty() {
+                    format!("struct {} {{}}", decl.name)
+                } else {
+                    format!("struct {} {{ {} }}", decl.name, decl.fields.join(", "))
+                };
+                let kind = ItemKind::Struct { fields: decl.fields.clone() };
+                Item { name: decl.name.clone(), signature, kind, doc: decl.doc.clone() }
+            },
+            _ => continue,
+        };
+        if !item.name.starts_with('_') {
+            items.push(item);
+        }
+    }
+    items
+}
+
+/// The pages as Markdown, a `#` heading per page and a `##` one per item.
+pub fn markdown(pages: &[Page]) -> String {
+    let mut out = String::new();
+    for (i, page) in pages.iter().enumerate() {
+        if i > 0 {
+            out.push('\n');
+        }
+        let _ = writeln!(out, "# {}", page.title);
+        if page.items.is_empty() {
+            out.push_str("\nNo functions or structs.\n");
+        }
+        for item in &page.items {
+            let _ = writeln!(out, "\n## {}\n\n    {}", item.name, item.signature);
+            if let Some(doc) = &item.doc {
+                let _ = writeln!(out, "\n{}", doc);
+            }
+            match &item.kind {
+                ItemKind::Function { params, returns } => {
+                    if !params.is_empty() {
+                        out.push_str("\n**Parameters**\n\n");
+                    }
+                    for (param, ty) in params {
+                        let _ = write!(out, "- `{}`", param);
+                        if let Some(ty) = ty {
+                            let _ = write!(out, ": `{}`", ty);
+                        }
+                        out.push('\n');
+                    }
+                    if let Some(returns) = returns {
+                        let _ = writeln!(out, "\n**Returns** `{}`", returns);
+                    }
+                },
+                ItemKind::Struct { fields } => {
+                    if !fields.is_empty() {
+                        out.push_str("\n**Fields**\n\n");
+                    }
+                    for field in fields {
+                        let _ = writeln!(out, "- `{}`", field);
+                    }
+                },
+            }
+        }
+    }
+    out
+}
+
+/// The pages as one HTML document, laid out as [`markdown`] lays them out.
+/// In doc comments, blank lines separate paragraphs and backticks quote
+/// code.
+pub fn html(pages: &[Page]) -> String {
+    let title = pages.first().map_or("", |page| page.title.as_str());
+    let mut out = String::new();
+    let _ = writeln!(out, "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{}</title>\n</head>\n<body>", escape(title));
+    for page in pages {
+        let _ = writeln!(out, "<h1>{}</h1>", escape(&page.title));
+        if page.items.is_empty() {
+            out.push_str("<p>No functions or structs.</p>\n");
+        }
+        for item in &page.items {
+            let id = format!("{}-{}", page.title, item.name);
+            let _ = writeln!(out, "<h2 id=\"{}\">{}</h2>", escape(&id), escape(&item.name));
+            let _ = writeln!(out, "<pre><code>{}</code></pre>", escape(&item.signature));
+            for paragraph in item.doc.iter().flat_map(|doc| doc.split("\n\n")).filter(|paragraph| !paragraph.trim().is_empty()) {
+                let _ = writeln!(out, "<p>{}</p>", inline(paragraph.trim()));
+            }
+            let (heading, names): (&str, Vec<(&String, Option<&String>)>) = match &item.kind {
+                ItemKind::Function { params, .. } => ("Parameters", params.iter().map(|(param, ty)| (param, ty.as_ref())).collect()),
+                ItemKind::Struct { fields } => ("Fields", fields.iter().map(|field| (field, None)).collect()),
+            };
+            if !names.is_empty() {
+                let _ = writeln!(out, "<p><strong>{}</strong></p>\n<ul>", heading);
+                for (name, ty) in names {
+                    let _ = write!(out, "<li><code>{}</code>", escape(name));
+                    if let Some(ty) = ty {
+                        let _ = write!(out, ": <code>{}</code>", escape(ty));
+                    }
+                    out.push_str("</li>\n");
+                }
+                out.push_str("</ul>\n");
+            }
+            if let ItemKind::Function { returns: Some(returns), .. } = &item.kind {
+                let _ = writeln!(out, "<p><strong>Returns</strong> <code>{}</code></p>", escape(returns));
+            }
+        }
+    }
+    out.push_str("</body>\n</html>\n");
+    out
+}
+
+/// `text` escaped for HTML, with the stretches between backticks as code.
+fn inline(text: &str) -> String {
+    let parts: Vec<String> = text
+        .split('`')
+        .enumerate()
+        .map(|(i, part)| if i % 2 == 1 { format!("<code>{}</code>", escape(part)) } else { escape(part) })
+        .collect();
+    parts.concat()
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod test {
+    use crate::doc::{html, items, markdown, Page};
+    use crate::parser::parse;
+
+    const SOURCE: &str = "\
+## The area of a `w` by `h` rectangle.
+##
+## Both must be positive.
+fn area(w: int, h: int): int {
+  return w * h
+}
+fn _helper() {}
+x = 1
+## A point on the plane.
+struct Point { x, y }
+fn untyped(a) {}";
+
+    #[test]
+    fn test_items() {
+        let items = items(&parse(SOURCE).unwrap());
+        let signatures: Vec<&str> = items.iter().map(|item| item.signature.as_str()).collect();
+        assert_eq!(signatures, ["fn area(w: int, h: int): int", "struct Point { x, y }", "fn untyped(a)"]);
+        assert_eq!(items[1].doc.as_deref(), Some("A point on the plane."));
+        assert_eq!(items[2].doc, None);
+    }
+
+    #[test]
+    fn test_markdown() {
+        let pages = [Page { title: "shapes.tb".to_string(), items: items(&parse(SOURCE).unwrap()) }];
+        assert_eq!(markdown(&pages), "\
+# shapes.tb
+
+## area
+
+    fn area(w: int, h: int): int
+
+The area of a `w` by `h` rectangle.
+
+Both must be positive.
+
+**Parameters**
+
+- `w`: `int`
+- `h`: `int`
+
+**Returns** `int`
+
+## Point
+
+    struct Point { x, y }
+
+A point on the plane.
+
+**Fields**
+
+- `x`
+- `y`
+
+## untyped
+
+    fn untyped(a)
+
+**Parameters**
+
+- `a`
+");
+        let empty = Page { title: "main.tb".to_string(), items: Vec::new() };
+        assert_eq!(markdown(&[empty]), "# main.tb\n\nNo functions or structs.\n");
+    }
+
+    #[test]
+    fn test_html() {
+        let pages = [Page { title: "a&b.tb".to_string(), items: items(&parse(SOURCE).unwrap())[..1].to_vec() }];
+        let html = html(&pages);
+        assert!(html.starts_with("<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>a&amp;b.tb</title>\n"), "{}", html);
+        assert!(html.contains("\
+<h1>a&amp;b.tb</h1>
+<h2 id=\"a&amp;b.tb-area\">area</h2>
+<pre><code>fn area(w: int, h: int): int</code></pre>
+<p>The area of a <code>w</code> by <code>h</code> rectangle.</p>
+<p>Both must be positive.</p>
+<p><strong>Parameters</strong></p>
+<ul>
+<li><code>w</code>: <code>int</code></li>
+<li><code>h</code>: <code>int</code></li>
+</ul>
+<p><strong>Returns</strong> <code>int</code></p>
+</body>
+</html>
+"), "{}", html);
+    }
+}
@@ -0,0 +1,433 @@
+use std::collections::HashMap;
+use std::fmt::Write;
+
+use crate::ast::{BinaryOp, Expr, ExprKind, FunctionDecl, Program, Stmt, StmtKind, TypeAnnotation, UnaryOp};
+use crate::json::Json;
+use crate::lexer::Span;
+
+/// How [`dump`] prints a syntax tree.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DumpFormat {
+    /// One node per line, children indented under their parent, with the
+    /// line and column each node starts at.
+    #[default]
+    Tree,
+    /// Nested lists like `(assign x (+ 1 2))`, without locations.
+    Sexpr,
+    /// A JSON object per node, with its kind, span, attributes and
+    /// children, on one line.
+    Json,
+}
+
+impl DumpFormat {
+    /// The format called `name` on the command line: `tree`, `sexpr` or
+    /// `json`.
+    pub fn from_name(name: &str) -> Option<DumpFormat> {
+        match name {
+            "tree" => Some(DumpFormat::Tree),
+            "sexpr" => Some(DumpFormat::Sexpr),
+            "json" => Some(DumpFormat::Json),
+            _ => None,
+        }
+    }
+}
+
+/// Prints `program`'s syntax tree in `format`, ending with a newline.
+pub fn dump(program: &Program, format: DumpFormat) -> String {
+    let labels: HashMap<usize, i64> = program.lines.iter().map(|(line, index)| (*index, *line)).collect();
+    let statements = program
+        .statements
+        .iter()
+        .enumerate()
+        .map(|(i, statement)| {
+            let mut node = statement_node(statement);
+            if let Some(line) = labels.get(&i) {
+                node.attrs.insert(0, ("line", Json::Int(*line)));
+            }
+            node
+        })
+        .collect();
+    let span = program.statements.first().map_or(Span::default(), |first| first.span.to(program.statements.last().unwrap().span));
+    let root = Node { kind: "Program", span, attrs: Vec::new(), children: vec![("statements", Child::List(statements))] };
+    let mut out = String::new();
+    match format {
+        DumpFormat::Tree => tree(&mut out, &root, None, 0),
+        DumpFormat::Sexpr => {
+            sexpr(&mut out, &root);
+            out.push('\n');
+        },
+        DumpFormat::Json => {
+            let _ = writeln!(out, "{}", json(&root));
+        },
+    }
+    out
+}
+
+/// A syntax tree node in a shape all formats can print.
+struct Node {
+    kind: &'static str,
+    span: Span,
+    attrs: Vec<(&'static str, Json)>,
+    children: Vec<(&'static str, Child)>,
+}
+
+enum Child {
+    One(Node),
+    List(Vec<Node>),
+}
+
+impl Node {
+    fn new(kind: &'static str, span: Span) -> Node {
+        Node { kind, span, attrs: Vec::new(), children: Vec::new() }
+    }
+
+    fn attr(mut self, name: &'static str, value: impl Into<Json>) -> Node {
+        self.attrs.push((name, value.into()));
+        self
+    }
+
+    fn child(mut self, name: &'static str, node: Node) -> Node {
+        self.children.push((name, Child::One(node)));
+        self
+    }
+
+    fn list(mut self, name: &'static str, nodes: Vec<Node>) -> Node {
+        self.children.push((name, Child::List(nodes)));
+        self
+    }
+}
+
+fn statement_nodes(statements: &[Stmt]) -> Vec<Node> {
+    statements.iter().map(statement_node).collect()
+}
+
+fn expr_nodes(exprs: &[Expr]) -> Vec<Node> {
+    exprs.iter().map(expr_node).collect()
+}
+
+fn type_attr(node: Node, name: &'static str, ty: &Option<TypeAnnotation>) -> Node {
+    match ty {
+        Some(ty) => node.attr(name, ty.name.as_str()),
+        None => node,
+    }
+}
+
+fn names(names: &[String]) -> Json {
+    Json::Array(names.iter().map(|name| Json::from(name.as_str())).collect())
+}
+
+fn statement_node(statement: &Stmt) -> Node {
+    let node = |kind| Node::new(kind, statement.span);
+    match &statement.kind {
+        StmtKind::Assign { name, ty, value, declaration } => {
+            let assign = node("Assign").attr("name", name.as_str());
+            let assign = type_attr(assign, "type", ty);
+            let assign = if *declaration { assign.attr("let", true) } else { assign };
+            assign.child("value", expr_node(value))
+        },
+        StmtKind::SetIndex { target, index, value } => node("SetIndex")
+            .child("target", expr_node(target))
+            .child("index", expr_node(index))
+            .child("value", expr_node(value)),
+        StmtKind::SetField { target, field, value } => node("SetField")
+            .attr("field", field.as_str())
+            .child("target", expr_node(target))
+            .child("value", expr_node(value)),
+        StmtKind::If { condition, then_branch, else_branch } => {
+            let node = node("If").child("condition", expr_node(condition)).list("then", statement_nodes(then_branch));
+            match else_branch {
+                Some(branch) => node.list("else", statement_nodes(branch)),
+                None => node,
+            }
+        },
+        StmtKind::While { condition, body } => node("While").child("condition", expr_node(condition)).list("body", statement_nodes(body)),
+        StmtKind::Break => node("Break"),
+        StmtKind::Continue => node("Continue"),
+        StmtKind::Print(args) => node("Print").list("args", expr_nodes(args)),
+        StmtKind::Function(decl) => function_node(decl),
+        StmtKind::Struct(decl) => node("Struct").attr("name", decl.name.as_str()).attr("fields", names(&decl.fields)),
+        StmtKind::Return(value) => match value {
+            Some(value) => node("Return").child("value", expr_node(value)),
+            None => node("Return"),
+        },
+        StmtKind::Global(globals) => node("Global").attr("names", names(globals)),
+        StmtKind::Import(name) => node("Import").attr("name", name.as_str()),
+        StmtKind::Goto(line) => node("Goto").attr("target", *line),
+        StmtKind::Gosub(line) => node("Gosub").attr("target", *line),
+        StmtKind::SubReturn => node("SubReturn"),
+        StmtKind::Expr(expr) => node("Expr").child("expr", expr_node(expr)),
+    }
+}
+
+fn function_node(decl: &FunctionDecl) -> Node {
+    let node = Node::new("Function", decl.span).attr("name", decl.name.as_str()).attr("params", names(&decl.params));
+    let node = if decl.param_types.iter().any(Option::is_some) {
+        let types = decl.param_types.iter().map(|ty| ty.as_ref().map_or(Json::Null, |ty| Json::from(ty.name.as_str())));
+        node.attr("types", Json::Array(types.collect()))
+    } else {
+        node
+    };
+    type_attr(node, "returns", &decl.return_type).list("body", statement_nodes(&decl.body))
+}
+
+fn expr_node(expr: &Expr) -> Node {
+    let node = |kind| Node::new(kind, expr.span);
+    match &expr.kind {
+        ExprKind::Number(n) => node("Number").attr("value", *n),
+        ExprKind::Float(n) => node("Float").attr("value", Json::Float(*n)),
+        ExprKind::Str(s) => node("Str").attr("value", s.as_str()),
+        ExprKind::Variable(name) => node("Variable").attr("name", name.as_str()),
+        ExprKind::Array(items) => node("Array").list("items", expr_nodes(items)),
+        ExprKind::Map(entries) => {
+            let entries = entries
+                .iter()
+                .map(|(key, value)| Node::new("Entry", key.span.to(value.span)).child("key", expr_node(key)).child("value", expr_node(value)))
+                .collect();
+            node("Map").list("entries", entries)
+        },
+        ExprKind::Index { target, index } => node("Index").child("target", expr_node(target)).child("index", expr_node(index)),
+        ExprKind::Field { target, field } => node("Field").attr("field", field.as_str()).child("target", expr_node(target)),
+        ExprKind::Unary { op, operand } => {
+            let op = match op {
+                UnaryOp::Neg => "-",
+                UnaryOp::Not => "!",
+            };
+            node("Unary").attr("op", op).child("operand", expr_node(operand))
+        },
+        ExprKind::Binary { op, left, right } => node("Binary")
+            .attr("op", symbol(*op))
+            .child("left", expr_node(left))
+            .child("right", expr_node(right)),
+        ExprKind::Call { callee, args } => node("Call").child("callee", expr_node(callee)).list("args", expr_nodes(args)),
+        ExprKind::Function(decl) => function_node(decl),
+    }
+}
+
+fn symbol(op: BinaryOp) -> &'static str {
+    match op {
+        BinaryOp::Add => "+",
+        BinaryOp::Sub => "-",
+        BinaryOp::Mul => "*",
+        BinaryOp::Div => "/",
+        BinaryOp::Equals => "==",
+        BinaryOp::NotEquals => "!=",
+        BinaryOp::SmallerThan => "<",
+        BinaryOp::GreaterThan => ">",
+        BinaryOp::SmallerEquals => "<=",
+        BinaryOp::GreaterEquals => ">=",
+        BinaryOp::And => "&&",
+        BinaryOp::Or => "||",
+    }
+}
+
+/// `Kind attr=value ... @line:col`, then the children a level deeper,
+/// each prefixed by the field it is in.
+fn tree(out: &mut String, node: &Node, field: Option<&str>, depth: usize) {
+    let indent = "  ".repeat(depth);
+    out.push_str(&indent);
+    if let Some(field) = field {
+        let _ = write!(out, "{}: ", field);
+    }
+    out.push_str(node.kind);
+    for (name, value) in &node.attrs {
+        let _ = write!(out, " {}={}", name, value);
+    }
+    if node.span.line > 0 {
+        let _ = write!(out, " @{}:{}", node.span.line, node.span.column);
+    }
+    out.push('\n');
+    for (name, child) in &node.children {
+        match child {
+            Child::One(child) => tree(out, child, Some(name), depth + 1),
+            Child::List(children) if children.is_empty() => {
+                let _ = writeln!(out, "{}  {}: []", indent, name);
+            },
+            Child::List(children) => {
+                let _ = writeln!(out, "{}  {}:", indent, name);
+                for child in children {
+                    tree(out, child, None, depth + 2);
+                }
+            },
+        }
+    }
+}
+
+/// Literals and variables are atoms and operators head their operands;
+/// other nodes are `(kind attrs... children...)`, list children wrapped in
+/// a list headed by the field's name.
+fn sexpr(out: &mut String, node: &Node) {
+    let attr = |name| node.attrs.iter().find(|(key, _)| *key == name).map(|(_, value)| value);
+    match node.kind {
+        "Number" | "Float" | "Str" => {
+            let _ = write!(out, "{}", attr("value").unwrap());
+            return;
+        },
+        "Variable" => {
+            sexpr_atom(out, attr("name").unwrap());
+            return;
+        },
+        _ => {},
+    }
+    // Classic dialect line numbers label the statement
+    let line = attr("line");
+    if let Some(line) = line {
+        let _ = write!(out, "(line {} ", line);
+    }
+    out.push('(');
+    match node.kind {
+        "Binary" | "Unary" => sexpr_atom(out, attr("op").unwrap()),
+        kind => out.push_str(&kind.to_lowercase()),
+    }
+    for (name, value) in &node.attrs {
+        if node.kind == "Binary" || node.kind == "Unary" {
+            break;
+        }
+        match value {
+            _ if *name == "line" => {},
+            Json::Bool(true) => {
+                let _ = write!(out, " {}", name);
+            },
+            Json::Bool(false) | Json::Null => {},
+            value => {
+                out.push(' ');
+                sexpr_atom(out, value);
+            },
+        }
+    }
+    for (name, child) in &node.children {
+        out.push(' ');
+        match child {
+            Child::One(child) => sexpr(out, child),
+            Child::List(children) => {
+                let _ = write!(out, "({}", name);
+                for child in children {
+                    out.push(' ');
+                    sexpr(out, child);
+                }
+                out.push(')');
+            },
+        }
+    }
+    out.push(')');
+    if line.is_some() {
+        out.push(')');
+    }
+}
+
+/// Names print bare, lists of them in parentheses.
+fn sexpr_atom(out: &mut String, value: &Json) {
+    match value {
+        Json::String(s) => out.push_str(s),
+        Json::Array(items) => {
+            out.push('(');
+            for (i, item) in items.iter().enumerate() {
+                if i > 0 {
+                    out.push(' ');
+                }
+                sexpr_atom(out, item);
+            }
+            out.push(')');
+        },
+        value => {
+            let _ = write!(out, "{}", value);
+        },
+    }
+}
+
+fn json(node: &Node) -> Json {
+    let span = Json::object([
+        ("start", Json::from(node.span.start)),
+        ("end", Json::from(node.span.end)),
+        ("line", Json::from(node.span.line)),
+        ("column", Json::from(node.span.column)),
+    ]);
+    let mut entries = vec![("kind", Json::from(node.kind)), ("span", span)];
+    entries.extend(node.attrs.iter().map(|(name, value)| (*name, value.clone())));
+    for (name, child) in &node.children {
+        let value = match child {
+            Child::One(child) => json(child),
+            Child::List(children) => Json::Array(children.iter().map(json).collect()),
+        };
+        entries.push((name, value));
+    }
+    Json::object(entries)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::dump::{dump, DumpFormat};
+    use crate::parser::{parse, parse_with_dialect, Dialect};
+
+    const SOURCE: &str = "fn area(w: int, h) {\n  return w * (h + 1)\n}\nlet x = -area(2, 3)\nif x < 0 && !done {\n  print \"neg\", [1], {\"k\": 2.5}\n}";
+
+    #[test]
+    fn test_tree() {
+        let program = parse(SOURCE).unwrap();
+        assert_eq!(dump(&program, DumpFormat::Tree), r#"Program @1:1
+  statements:
+    Function name="area" params=["w","h"] types=["int",null] @1:1
+      body:
+        Return @2:3
+          value: Binary op="*" @2:10
+            left: Variable name="w" @2:10
+            right: Binary op="+" @2:14
+              left: Variable name="h" @2:15
+              right: Number value=1 @2:19
+    Assign name="x" let=true @4:1
+      value: Unary op="-" @4:9
+        operand: Call @4:10
+          callee: Variable name="area" @4:10
+          args:
+            Number value=2 @4:15
+            Number value=3 @4:18
+    If @5:1
+      condition: Binary op="&&" @5:4
+        left: Binary op="<" @5:4
+          left: Variable name="x" @5:4
+          right: Number value=0 @5:8
+        right: Unary op="!" @5:13
+          operand: Variable name="done" @5:14
+      then:
+        Print @6:3
+          args:
+            Str value="neg" @6:9
+            Array @6:16
+              items:
+                Number value=1 @6:17
+            Map @6:21
+              entries:
+                Entry @6:22
+                  key: Str value="k" @6:22
+                  value: Float value=2.5 @6:27
+"#);
+    }
+
+    #[test]
+    fn test_sexpr() {
+        let program = parse(SOURCE).unwrap();
+        assert_eq!(dump(&program, DumpFormat::Sexpr), concat!(
+            r#"(program (statements "#,
+            r#"(function area (w h) (int null) (body (return (* w (+ h 1))))) "#,
+            r#"(assign x let (- (call area (args 2 3)))) "#,
+            r#"(if (&& (< x 0) (! done)) (then (print (args "neg" (array (items 1)) (map (entries (entry "k" 2.5)))))))))"#,
+            "\n",
+        ));
+        let program = parse_with_dialect("10 gosub 20\n20 return", Dialect::Classic).unwrap();
+        assert_eq!(dump(&program, DumpFormat::Sexpr), "(program (statements (line 10 (gosub 20)) (line 20 (subreturn))))\n");
+    }
+
+    #[test]
+    fn test_json() {
+        let program = parse("print x\nwhile 1 {}").unwrap();
+        assert_eq!(dump(&program, DumpFormat::Json), concat!(
+            r#"{"kind":"Program","span":{"start":0,"end":18,"line":1,"column":1},"statements":["#,
+            r#"{"kind":"Print","span":{"start":0,"end":7,"line":1,"column":1},"args":["#,
+            r#"{"kind":"Variable","span":{"start":6,"end":7,"line":1,"column":7},"name":"x"}]},"#,
+            r#"{"kind":"While","span":{"start":8,"end":18,"line":2,"column":1},"condition":"#,
+            r#"{"kind":"Number","span":{"start":14,"end":15,"line":2,"column":7},"value":1},"body":[]}]}"#,
+            "\n",
+        ));
+        assert_eq!(DumpFormat::from_name("json"), Some(DumpFormat::Json));
+        assert_eq!(DumpFormat::from_name("xml"), None);
+    }
+}
@@ -0,0 +1,488 @@
+use std::fs::{self, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::Path;
+
+/// What [`LineEditor::read_line`] read.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Input {
+    Line(String),
+    /// Ctrl-C: the line typed so far was abandoned.
+    Interrupted,
+    /// Ctrl-D on an empty line, or the input was closed.
+    Eof,
+}
+
+/// A key press, decoded from the bytes a terminal sends for it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Key {
+    Char(char),
+    Enter,
+    Backspace,
+    Delete,
+    Left,
+    Right,
+    Up,
+    Down,
+    Home,
+    End,
+    /// Completes the word before the cursor.
+    Tab,
+    /// Ctrl-U: delete from the start of the line to the cursor.
+    KillStart,
+    /// Ctrl-K: delete from the cursor to the end of the line.
+    KillEnd,
+    Interrupt,
+    /// Ctrl-D: end of input on an empty line, otherwise like Delete.
+    Eof,
+    /// Any other key or escape sequence; ignored.
+    Other,
+}
+
+/// Reads one key press from `input`, or `None` once it is closed.
+pub fn read_key(input: &mut impl Read) -> io::Result<Option<Key>> {
+    let Some(byte) = read_byte(input)? else {
+        return Ok(None);
+    };
+    let key = match byte {
+        b'\r' | b'\n' => Key::Enter,
+        0x7f | 0x08 => Key::Backspace,
+        0x01 => Key::Home,
+        0x02 => Key::Left,
+        0x03 => Key::Interrupt,
+        0x04 => Key::Eof,
+        0x05 => Key::End,
+        0x06 => Key::Right,
+        b'\t' => Key::Tab,
+        0x0b => Key::KillEnd,
+        0x0e => Key::Down,
+        0x10 => Key::Up,
+        0x15 => Key::KillStart,
+        0x1b => read_escape(input)?,
+        byte if byte < 0x20 => Key::Other,
+        byte => {
+            // The rest of a UTF-8 sequence follows its first byte
+            let len = match byte {
+                0xc0..=0xdf => 2,
+                0xe0..=0xef => 3,
+                0xf0..=0xf7 => 4,
+                _ => 1,
+            };
+            let mut bytes = vec![byte];
+            while bytes.len() < len {
+                match read_byte(input)? {
+                    Some(byte) => bytes.push(byte),
+                    None => break,
+                }
+            }
+            match std::str::from_utf8(&bytes) {
+                Ok(s) => Key::Char(s.chars().next().unwrap()),
+                Err(_) => Key::Other,
+            }
+        },
+    };
+    Ok(Some(key))
+}
+
+fn read_byte(input: &mut impl Read) -> io::Result<Option<u8>> {
+    let mut byte = [0];
+    loop {
+        match input.read(&mut byte) {
+            Ok(0) => return Ok(None),
+            Ok(_) => return Ok(Some(byte[0])),
+            Err(error) if error.kind() == io::ErrorKind::Interrupted => continue,
+            Err(error) => return Err(error),
+        }
+    }
+}
+
+/// Decodes the `ESC [ ...` and `ESC O ...` sequences of the cursor and
+/// editing keys.
+fn read_escape(input: &mut impl Read) -> io::Result<Key> {
+    let Some(b'[' | b'O') = read_byte(input)? else {
+        return Ok(Key::Other);
+    };
+    let mut parameter = String::new();
+    loop {
+        let Some(byte) = read_byte(input)? else {
+            return Ok(Key::Other);
+        };
+        match byte {
+            b'0'..=b'9' | b';' => parameter.push(byte as char),
+            b'A' => return Ok(Key::Up),
+            b'B' => return Ok(Key::Down),
+            b'C' => return Ok(Key::Right),
+            b'D' => return Ok(Key::Left),
+            b'H' => return Ok(Key::Home),
+            b'F' => return Ok(Key::End),
+            b'~' => {
+                return Ok(match parameter.as_str() {
+                    "1" | "7" => Key::Home,
+                    "3" => Key::Delete,
+                    "4" | "8" => Key::End,
+                    _ => Key::Other,
+                })
+            },
+            _ => return Ok(Key::Other),
+        }
+    }
+}
+
+/// How many lines of history [`LineEditor::load_history`] keeps.
+pub const MAX_HISTORY: usize = 1000;
+
+/// Reads lines from a terminal, with the cursor keys moving within the line
+/// and through the lines entered before, and Tab completing words, like a
+/// shell.
+#[derive(Debug, Default)]
+pub struct LineEditor {
+    history: Vec<String>,
+    completions: Vec<String>,
+}
+
+impl LineEditor {
+    pub fn new() -> LineEditor {
+        LineEditor::default()
+    }
+
+    /// The lines entered so far, oldest first.
+    pub fn history(&self) -> &[String] {
+        &self.history
+    }
+
+    /// Remembers `line` for the Up key, unless it's blank or repeats the
+    /// previous entry.
+    pub fn add_history(&mut self, line: &str) {
+        if !line.trim().is_empty() && self.history.last().map(String::as_str) != Some(line) {
+            self.history.push(line.to_string());
+        }
+    }
+
+    pub fn clear_history(&mut self) {
+        self.history.clear();
+    }
+
+    /// Adds the lines of the history file at `path`, which
+    /// [`append_history`] writes, keeping the last [`MAX_HISTORY`]. A file
+    /// that doesn't exist yet counts as empty; one that grew longer is cut
+    /// down to those.
+    pub fn load_history(&mut self, path: &Path) -> io::Result<()> {
+        let contents = match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(error) => return Err(error),
+        };
+        let lines: Vec<&str> = contents.lines().collect();
+        for line in &lines[lines.len().saturating_sub(MAX_HISTORY)..] {
+            self.add_history(line);
+        }
+        if lines.len() > MAX_HISTORY {
+            let kept = lines.len() - MAX_HISTORY;
+            fs::write(path, lines[kept..].iter().map(|line| format!("{}\n", line)).collect::<String>())?;
+        }
+        let excess = self.history.len().saturating_sub(MAX_HISTORY);
+        self.history.drain(..excess);
+        Ok(())
+    }
+
+    /// Sets the words Tab completes to. It fills in as much of the word
+    /// before the cursor as all that start with it share, or lists them
+    /// if that adds nothing.
+    pub fn set_completions(&mut self, words: impl IntoIterator<Item = String>) {
+        self.completions = words.into_iter().collect();
+        self.completions.sort();
+        self.completions.dedup();
+    }
+
+    /// Shows `prompt` and reads a line from the terminal on stdin. When
+    /// stdin isn't a terminal the line is read as is, without editing.
+    pub fn read_line(&mut self, prompt: &str) -> io::Result<Input> {
+        let mut stdout = io::stdout();
+        match RawMode::enable() {
+            Ok(_raw) => self.edit(prompt, &mut io::stdin(), &mut stdout),
+            Err(_) => {
+                write!(stdout, "{}", prompt)?;
+                stdout.flush()?;
+                let mut line = String::new();
+                if io::stdin().read_line(&mut line)? == 0 {
+                    return Ok(Input::Eof);
+                }
+                Ok(Input::Line(line.trim_end_matches(['\n', '\r']).to_string()))
+            },
+        }
+    }
+
+    /// Edits a line from the key presses on `input`, redrawing it on
+    /// `output`, which is expected to be a terminal in raw mode.
+    pub fn edit(&mut self, prompt: &str, input: &mut impl Read, output: &mut impl Write) -> io::Result<Input> {
+        let mut line: Vec<char> = Vec::new();
+        let mut cursor = 0;
+        // Where Up and Down are in the history; the line being typed is
+        // kept so Down can get back to it
+        let mut position = self.history.len();
+        let mut draft = Vec::new();
+        write!(output, "{}", prompt)?;
+        output.flush()?;
+        loop {
+            let Some(key) = read_key(input)? else {
+                write!(output, "\r\n")?;
+                return Ok(if line.is_empty() { Input::Eof } else { Input::Line(line.into_iter().collect()) });
+            };
+            match key {
+                Key::Char(c) => {
+                    line.insert(cursor, c);
+                    cursor += 1;
+                },
+                Key::Enter => {
+                    write!(output, "\r\n")?;
+                    output.flush()?;
+                    return Ok(Input::Line(line.into_iter().collect()));
+                },
+                Key::Interrupt => {
+                    write!(output, "^C\r\n")?;
+                    output.flush()?;
+                    return Ok(Input::Interrupted);
+                },
+                Key::Eof if line.is_empty() => {
+                    write!(output, "\r\n")?;
+                    output.flush()?;
+                    return Ok(Input::Eof);
+                },
+                Key::Backspace if cursor > 0 => {
+                    cursor -= 1;
+                    line.remove(cursor);
+                },
+                Key::Delete | Key::Eof if cursor < line.len() => {
+                    line.remove(cursor);
+                },
+                Key::Left => cursor = cursor.saturating_sub(1),
+                Key::Right => cursor = (cursor + 1).min(line.len()),
+                Key::Home => cursor = 0,
+                Key::End => cursor = line.len(),
+                Key::KillStart => {
+                    line.drain(..cursor);
+                    cursor = 0;
+                },
+                Key::KillEnd => line.truncate(cursor),
+                Key::Tab => {
+                    let start = word_start(&line, cursor);
+                    let word: String = line[start..cursor].iter().collect();
+                    // A field can't be told from the value it belongs to
+                    let field = start > 0 && line[start - 1] == '.';
+                    let matches: Vec<&str> = match word.is_empty() || field {
+                        true => Vec::new(),
+                        false => self.completions.iter().map(String::as_str).filter(|c| c.starts_with(&word)).collect(),
+                    };
+                    let Some(&first) = matches.first() else {
+                        continue;
+                    };
+                    let common = matches.iter().fold(first, |common, word| common_prefix(common, word));
+                    if common.len() > word.len() {
+                        for c in common[word.len()..].chars() {
+                            line.insert(cursor, c);
+                            cursor += 1;
+                        }
+                    } else if matches.len() > 1 {
+                        write!(output, "\r\n{}\r\n", matches.join("  "))?;
+                    } else {
+                        continue;
+                    }
+                },
+                Key::Up if position > 0 => {
+                    if position == self.history.len() {
+                        draft = line;
+                    }
+                    position -= 1;
+                    line = self.history[position].chars().collect();
+                    cursor = line.len();
+                },
+                Key::Down if position < self.history.len() => {
+                    position += 1;
+                    line = match self.history.get(position) {
+                        Some(entry) => entry.chars().collect(),
+                        None => std::mem::take(&mut draft),
+                    };
+                    cursor = line.len();
+                },
+                _ => continue,
+            }
+            redraw(output, prompt, &line, cursor)?;
+        }
+    }
+}
+
+/// Adds `line` to the end of the history file at `path`, creating it and
+/// its directory if need be.
+pub fn append_history(path: &Path, line: &str) -> io::Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    writeln!(OpenOptions::new().create(true).append(true).open(path)?, "{}", line)
+}
+
+/// Where the word ending at `cursor` starts: a name, or a REPL command
+/// starting with `:` at the start of the line.
+fn word_start(line: &[char], cursor: usize) -> usize {
+    let mut start = cursor;
+    while start > 0 && (line[start - 1].is_alphanumeric() || line[start - 1] == '_') {
+        start -= 1;
+    }
+    if start == 1 && line[0] == ':' {
+        start = 0;
+    }
+    start
+}
+
+fn common_prefix<'a>(a: &'a str, b: &str) -> &'a str {
+    let len = a.char_indices().zip(b.chars()).find(|((_, x), y)| x != y).map_or(a.len().min(b.len()), |((i, _), _)| i);
+    &a[..len]
+}
+
+/// Rewrites the line the cursor is on and puts the cursor back in place.
+fn redraw(output: &mut impl Write, prompt: &str, line: &[char], cursor: usize) -> io::Result<()> {
+    let text: String = line.iter().collect();
+    write!(output, "\r{}{}\x1b[K", prompt, text)?;
+    if cursor < line.len() {
+        write!(output, "\x1b[{}D", line.len() - cursor)?;
+    }
+    output.flush()
+}
+
+/// Puts the terminal on stdin in raw mode, so key presses arrive as they are
+/// typed and aren't echoed, until dropped.
+struct RawMode {
+    #[cfg(unix)]
+    original: libc::termios,
+}
+
+impl RawMode {
+    #[cfg(unix)]
+    fn enable() -> io::Result<RawMode> {
+        // SAFETY: `termios` is plain data, filled in by `tcgetattr` before
+        // it is read
+        let mut original: libc::termios = unsafe { std::mem::zeroed() };
+        if unsafe { libc::tcgetattr(libc::STDIN_FILENO, &mut original) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let mut raw = original;
+        raw.c_iflag &= !(libc::ICRNL | libc::IXON);
+        raw.c_lflag &= !(libc::ECHO | libc::ICANON | libc::ISIG | libc::IEXTEN);
+        raw.c_cc[libc::VMIN] = 1;
+        raw.c_cc[libc::VTIME] = 0;
+        if unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSAFLUSH, &raw) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(RawMode { original })
+    }
+
+    #[cfg(not(unix))]
+    fn enable() -> io::Result<RawMode> {
+        Err(io::Error::new(io::ErrorKind::Unsupported, "line editing needs a unix terminal"))
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        #[cfg(unix)]
+        unsafe {
+            libc::tcsetattr(libc::STDIN_FILENO, libc::TCSAFLUSH, &self.original);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+
+    use crate::editor::{append_history, read_key, Input, Key, LineEditor, MAX_HISTORY};
+
+    #[test]
+    fn test_read_key() {
+        let mut input: &[u8] = b"a\xc3\xa9\x1b[A\x1b[3~\x1bOH\x7f\t\r";
+        let mut keys = Vec::new();
+        while let Some(key) = read_key(&mut input).unwrap() {
+            keys.push(key);
+        }
+        assert_eq!(keys, vec![
+            Key::Char('a'),
+            Key::Char('é'),
+            Key::Up,
+            Key::Delete,
+            Key::Home,
+            Key::Backspace,
+            Key::Tab,
+            Key::Enter,
+        ]);
+    }
+
+    #[test]
+    fn test_edit() {
+        let mut editor = LineEditor::new();
+        let mut edit = |keys: &[u8]| {
+            let mut output = Vec::new();
+            let line = editor.edit("> ", &mut &keys[..], &mut output).unwrap();
+            if let Input::Line(line) = &line {
+                editor.add_history(line);
+            }
+            line
+        };
+        // Left twice, insert; Home, delete; End, backspace
+        assert_eq!(edit(b"prnt 1\x1b[D\x1b[D\x1b[D\x1b[Di\x01\x1b[3~p\x05\x7f2\r"), Input::Line("print 2".to_string()));
+        assert_eq!(edit(b"x = 1\r"), Input::Line("x = 1".to_string()));
+        // Up goes back through the history, Down returns to the draft
+        assert_eq!(edit(b"\x1b[A\x1b[A\r"), Input::Line("print 2".to_string()));
+        assert_eq!(edit(b"y\x1b[A\x1b[B!\r"), Input::Line("y!".to_string()));
+        assert_eq!(edit(b"abc\x1b[D\x15\r"), Input::Line("c".to_string()));
+        assert_eq!(edit(b"abc\x1b[D\x0b\r"), Input::Line("ab".to_string()));
+        assert_eq!(edit(b"half\x03"), Input::Interrupted);
+        assert_eq!(edit(b"\x04"), Input::Eof);
+        assert_eq!(edit(b""), Input::Eof);
+        assert_eq!(editor.history(), ["print 2", "x = 1", "print 2", "y!", "c", "ab"]);
+    }
+
+    #[test]
+    fn test_complete() {
+        let mut editor = LineEditor::new();
+        editor.set_completions(["print", "println", "count", "counter", ":vars"].map(String::from));
+        let mut edit = |keys: &[u8]| {
+            let mut output = Vec::new();
+            let line = editor.edit("> ", &mut &keys[..], &mut output).unwrap();
+            (line, String::from_utf8(output).unwrap())
+        };
+        // The shared part is filled in, then the choices listed
+        let (line, output) = edit(b"pr\t\t\r");
+        assert_eq!(line, Input::Line("print".to_string()));
+        assert!(output.contains("\r\nprint  println\r\n"), "{:?}", output);
+        assert_eq!(edit(b"x = cou\te\t(1)\r").0, Input::Line("x = counter(1)".to_string()));
+        // In the middle of the line, and for commands
+        assert_eq!(edit(b"(1)\x01coun\t\r").0, Input::Line("count(1)".to_string()));
+        assert_eq!(edit(b":v\t\r").0, Input::Line(":vars".to_string()));
+        // Nothing to complete, or after a `.`
+        assert_eq!(edit(b"zz\t x.pr\t\r").0, Input::Line("zz x.pr".to_string()));
+    }
+
+    #[test]
+    fn test_history_file() {
+        let path = std::env::temp_dir().join(format!("tbasic-history-{}", std::process::id())).join("history");
+        let _ = fs::remove_file(&path);
+        let mut editor = LineEditor::new();
+        editor.load_history(&path).unwrap();
+        assert!(editor.history().is_empty());
+
+        for line in ["x = 1", "print x", "print x"] {
+            append_history(&path, line).unwrap();
+        }
+        editor.load_history(&path).unwrap();
+        assert_eq!(editor.history(), ["x = 1", "print x"]);
+        editor.clear_history();
+        assert!(editor.history().is_empty());
+
+        fs::remove_file(&path).unwrap();
+        for i in 0..MAX_HISTORY + 5 {
+            append_history(&path, &format!("print {}", i)).unwrap();
+        }
+        editor.load_history(&path).unwrap();
+        assert_eq!((editor.history().len(), editor.history()[0].as_str()), (MAX_HISTORY, "print 5"));
+        let lines = fs::read_to_string(&path).unwrap();
+        assert_eq!((lines.lines().count(), lines.lines().next()), (MAX_HISTORY, Some("print 5")));
+    }
+}
@@ -0,0 +1,168 @@
+use std::marker::PhantomData;
+
+use corosensei::stack::DefaultStack;
+use corosensei::{Coroutine, CoroutineResult, Yielder};
+
+use crate::ast::Program;
+use crate::interpreter::{Interpreter, RuntimeError};
+use crate::value::Value;
+
+/// Host stack for a stepped script. Only touched pages are committed, so
+/// this matches what a script gets on the main thread without costing that
+/// much memory.
+const STACK_SIZE: usize = 8 << 20;
+
+/// Where a stepped script stopped.
+#[derive(Debug, Clone, PartialEq)]
+pub enum StepResult {
+    /// Out of steps for now; resume with [`Execution::step`] or
+    /// [`Execution::run_for`].
+    Paused,
+    /// The script ran to completion with this value, as [`Interpreter::run`]
+    /// would return it.
+    Finished(Value),
+}
+
+/// Connects a running script's checkpoints to the coroutine driving it.
+pub(crate) struct Stepping {
+    yielder: *const Yielder<u64, ()>,
+    steps_left: u64,
+}
+
+impl Stepping {
+    /// Spends one step, first suspending the script until it is resumed if
+    /// none are left.
+    pub(crate) fn take_step(&mut self) {
+        if self.steps_left == 0 {
+            // SAFETY: `stepping` is only set while the coroutine that owns the
+            // yielder is running, and the yielder lives until it returns.
+            self.steps_left = unsafe { &*self.yielder }.suspend(());
+        }
+        self.steps_left -= 1;
+    }
+}
+
+/// A script started with [`Interpreter::start`] that runs a bounded number
+/// of steps at a time, so hosts such as game loops can interleave it with
+/// their own work. A step is the same unit fuel is counted in: one
+/// statement or one loop iteration.
+///
+/// Dropping an unfinished execution abandons the script.
+pub struct Execution<'a> {
+    coroutine: Coroutine<u64, (), Result<Value, RuntimeError>>,
+    finished: Option<Result<Value, RuntimeError>>,
+    interpreter: *mut Interpreter,
+    _borrow: PhantomData<&'a mut Interpreter>,
+}
+
+impl<'a> Execution<'a> {
+    pub(crate) fn new(interpreter: &'a mut Interpreter, program: &'a Program) -> Execution<'a> {
+        let interpreter_ptr: *mut Interpreter = interpreter;
+        let body = move |yielder: &Yielder<u64, ()>, steps: u64| {
+            // SAFETY: the execution holds the `'a` borrow of the interpreter
+            // for as long as the coroutine can run, and doesn't touch the
+            // interpreter itself until the coroutine is done.
+            let interpreter = unsafe { &mut *interpreter_ptr };
+            interpreter.stepping = Some(Stepping { yielder, steps_left: steps });
+            let result = interpreter.run(program);
+            interpreter.stepping = None;
+            result
+        };
+        let stack = DefaultStack::new(STACK_SIZE).expect("failed to allocate a stack for the script");
+        // SAFETY: everything `body` borrows outlives `'a`, and the execution
+        // can't outlive `'a`.
+        let coroutine = unsafe { Coroutine::with_stack_unchecked(stack, body) };
+        Execution { coroutine, finished: None, interpreter: interpreter_ptr, _borrow: PhantomData }
+    }
+
+    /// Runs the script for a single step.
+    pub fn step(&mut self) -> Result<StepResult, RuntimeError> {
+        self.run_for(1)
+    }
+
+    /// Runs the script for up to `steps` steps. Once it has finished, every
+    /// call returns the same outcome again.
+    pub fn run_for(&mut self, steps: u64) -> Result<StepResult, RuntimeError> {
+        if self.finished.is_none() && steps > 0 {
+            if let CoroutineResult::Return(result) = self.coroutine.resume(steps) {
+                self.finished = Some(result);
+            }
+        }
+        match &self.finished {
+            Some(Ok(value)) => Ok(StepResult::Finished(value.clone())),
+            Some(Err(error)) => Err(error.clone()),
+            None => Ok(StepResult::Paused),
+        }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.finished.is_some()
+    }
+}
+
+impl Drop for Execution<'_> {
+    fn drop(&mut self) {
+        if self.coroutine.started() && !self.coroutine.done() {
+            self.coroutine.force_unwind();
+            // SAFETY: the coroutine has unwound, so nothing else refers to the
+            // interpreter any more.
+            unsafe { (*self.interpreter).stepping = None };
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::execution::StepResult;
+    use crate::interpreter::{Interpreter, RuntimeError, SharedBuffer};
+    use crate::parser::parse;
+    use crate::value::Value;
+
+    #[test]
+    fn test_step() {
+        let output = SharedBuffer::new();
+        let mut interpreter = Interpreter::with_output(Box::new(output.clone()));
+        let program = parse("fn f(x) {\n  print x\n}\nf(1)\nf(2)\n3").unwrap();
+        let mut execution = interpreter.start(&program);
+        // The declaration, then the first call and the print inside it
+        assert_eq!(execution.run_for(3), Ok(StepResult::Paused));
+        assert_eq!(output.contents(), "1\n");
+        assert_eq!(execution.step(), Ok(StepResult::Paused));
+        assert_eq!(output.contents(), "1\n");
+        assert_eq!(execution.run_for(100), Ok(StepResult::Finished(Value::Int(3))));
+        assert_eq!(execution.step(), Ok(StepResult::Finished(Value::Int(3))));
+        assert!(execution.is_finished());
+        assert_eq!(output.contents(), "1\n2\n");
+    }
+
+    #[test]
+    fn test_frames() {
+        let output = SharedBuffer::new();
+        let mut interpreter = Interpreter::with_output(Box::new(output.clone()));
+        let program = parse("i = 0\nwhile i < 10 {\n  i = i + 1\n  print i\n}").unwrap();
+        let mut execution = interpreter.start(&program);
+        let mut frames = 0;
+        while let Ok(StepResult::Paused) = execution.run_for(5) {
+            frames += 1;
+        }
+        drop(execution);
+        assert_eq!(frames, 6);
+        assert_eq!(interpreter.get_var("i"), Some(&Value::Int(10)));
+    }
+
+    #[test]
+    fn test_errors_and_abandoning() {
+        let mut interpreter = Interpreter::with_output(Box::new(SharedBuffer::new()));
+        let program = parse("x = 1\ny = x / 0").unwrap();
+        let mut execution = interpreter.start(&program);
+        assert!(matches!(execution.run_for(10), Err(RuntimeError::DivisionByZero { .. })));
+        assert!(matches!(execution.step(), Err(RuntimeError::DivisionByZero { .. })));
+        drop(execution);
+
+        let endless = parse("fn f() {\n  while 1 {\n  }\n}\nf()").unwrap();
+        let mut execution = interpreter.start(&endless);
+        assert_eq!(execution.run_for(50), Ok(StepResult::Paused));
+        drop(execution);
+        assert_eq!(interpreter.run(&parse("x = 5\nx").unwrap()), Ok(Value::Int(5)));
+    }
+}
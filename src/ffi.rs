@@ -0,0 +1,264 @@
+use std::ffi::{c_char, c_int, c_void, CStr, CString};
+use std::ptr;
+
+use crate::interpreter::{Interpreter, RuntimeError};
+use crate::value::Value;
+
+/// An interpreter as C sees it, with the strings it has handed out: the
+/// `tb_interp` of the C API that `ffi/include/tbasic.h` declares.
+pub struct Tb {
+    interpreter: Interpreter,
+    /// The message of the last failed [`tb_eval`].
+    error: Option<CString>,
+    /// The strings of the values returned by the last call.
+    strings: Vec<CString>,
+}
+
+/// What a [`TbValue`] holds.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TbType {
+    Nil,
+    Bool,
+    Int,
+    Float,
+    String,
+    /// An array, map, function or anything else C has no type for, or an
+    /// int too big for `int64_t`.
+    Other,
+}
+
+/// A value crossing the API. Only the field of its `kind` is meaningful,
+/// and `string` is also set for [`TbType::Other`], to the value as `print`
+/// shows it. Strings end at their first NUL.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct TbValue {
+    pub kind: TbType,
+    pub boolean: bool,
+    pub integer: i64,
+    pub number: f64,
+    pub string: *const c_char,
+}
+
+/// A function registered with [`tb_register_fn`]. It returns `false` to
+/// fail the call, with the message in `result.string` if that isn't null.
+pub type TbFn = unsafe extern "C" fn(args: *const TbValue, count: usize, result: *mut TbValue, data: *mut c_void) -> bool;
+
+impl TbValue {
+    const NIL: TbValue = TbValue { kind: TbType::Nil, boolean: false, integer: 0, number: 0.0, string: ptr::null() };
+
+    /// `value` for C, keeping its string in `strings`.
+    fn new(value: &Value, strings: &mut Vec<CString>) -> TbValue {
+        let mut string = |text: String| {
+            let mut text = text.into_bytes();
+            text.truncate(text.iter().position(|&byte| byte == 0).unwrap_or(text.len()));
+            strings.push(CString::new(text).expect("the string has no NUL"));
+            strings[strings.len() - 1].as_ptr()
+        };
+        match value {
+            Value::Nil => TbValue::NIL,
+            Value::Bool(b) => TbValue { kind: TbType::Bool, boolean: *b, ..TbValue::NIL },
+            Value::Int(n) => TbValue { kind: TbType::Int, integer: *n, ..TbValue::NIL },
+            Value::Float(n) => TbValue { kind: TbType::Float, number: *n, ..TbValue::NIL },
+            Value::Str(s) => TbValue { kind: TbType::String, string: string(s.to_string()), ..TbValue::NIL },
+            other => TbValue { kind: TbType::Other, string: string(other.to_string()), ..TbValue::NIL },
+        }
+    }
+
+    /// # Safety
+    ///
+    /// `string` must be null or point to a NUL-terminated string.
+    unsafe fn to_value(self) -> Result<Value, RuntimeError> {
+        Ok(match self.kind {
+            TbType::Nil => Value::Nil,
+            TbType::Bool => Value::Bool(self.boolean),
+            TbType::Int => Value::Int(self.integer),
+            TbType::Float => Value::Float(self.number),
+            TbType::String if self.string.is_null() => Value::from(""),
+            TbType::String => Value::from(unsafe { CStr::from_ptr(self.string) }.to_string_lossy().into_owned()),
+            TbType::Other => return Err(RuntimeError::host("a C function returned a value of type `other`")),
+        })
+    }
+}
+
+/// `text` as a string, or `None` if it is null or not UTF-8.
+///
+/// # Safety
+///
+/// `text` must be null or point to a NUL-terminated string that lives for
+/// `'a`.
+unsafe fn str<'a>(text: *const c_char) -> Option<&'a str> {
+    if text.is_null() {
+        return None;
+    }
+    unsafe { CStr::from_ptr(text) }.to_str().ok()
+}
+
+/// Creates an interpreter that prints to stdout, to be freed with
+/// [`tb_free`].
+#[no_mangle]
+pub extern "C" fn tb_new() -> *mut Tb {
+    let tb = Tb { interpreter: Interpreter::new(), error: None, strings: Vec::new() };
+    Box::into_raw(Box::new(tb))
+}
+
+/// Frees an interpreter from [`tb_new`]; null is ignored.
+///
+/// # Safety
+///
+/// `tb` must be null or come from [`tb_new`], and not be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn tb_free(tb: *mut Tb) {
+    if !tb.is_null() {
+        drop(unsafe { Box::from_raw(tb) });
+    }
+}
+
+/// Parses and runs `source`, keeping the globals it defines for later
+/// calls. Returns 0 and stores what it evaluated to in `result`, if that
+/// isn't null, or returns -1 and keeps the message for [`tb_error`].
+///
+/// # Safety
+///
+/// `tb` must come from [`tb_new`], `source` must be a NUL-terminated
+/// string and `result` null or valid for writes. Strings in `result` live
+/// until the next call with `tb`.
+#[no_mangle]
+pub unsafe extern "C" fn tb_eval(tb: *mut Tb, source: *const c_char, result: *mut TbValue) -> c_int {
+    let tb = unsafe { &mut *tb };
+    tb.strings.clear();
+    let outcome = match unsafe { str(source) } {
+        Some(source) => crate::execute_with(source, &mut tb.interpreter).map_err(|error| error.to_string()),
+        None => Err("the source is null or not UTF-8".to_string()),
+    };
+    match outcome {
+        Ok(value) => {
+            tb.error = None;
+            if !result.is_null() {
+                unsafe { *result = TbValue::new(&value, &mut tb.strings) };
+            }
+            0
+        },
+        Err(message) => {
+            tb.error = Some(CString::new(message.replace('\0', "")).expect("the message has no NUL"));
+            -1
+        },
+    }
+}
+
+/// The message of the last [`tb_eval`] if it failed, and null otherwise.
+/// It lives until the next call with `tb`.
+///
+/// # Safety
+///
+/// `tb` must come from [`tb_new`].
+#[no_mangle]
+pub unsafe extern "C" fn tb_error(tb: *const Tb) -> *const c_char {
+    unsafe { &*tb }.error.as_ref().map_or(ptr::null(), |error| error.as_ptr())
+}
+
+/// Stores the global `name` in `result`, returning whether it exists.
+///
+/// # Safety
+///
+/// `tb` must come from [`tb_new`], `name` must be a NUL-terminated string
+/// and `result` valid for writes. Strings in `result` live until the next
+/// call with `tb`.
+#[no_mangle]
+pub unsafe extern "C" fn tb_get_var(tb: *mut Tb, name: *const c_char, result: *mut TbValue) -> bool {
+    let tb = unsafe { &mut *tb };
+    tb.strings.clear();
+    let Some(value) = unsafe { str(name) }.and_then(|name| tb.interpreter.get_var(name)) else {
+        return false;
+    };
+    unsafe { *result = TbValue::new(value, &mut tb.strings) };
+    true
+}
+
+/// Exposes `func` to scripts as the global function `name`, as
+/// [`Interpreter::register_fn`] does. Each call passes it the arguments,
+/// whose strings live until it returns, and `data`. The string of a result
+/// it returns is copied.
+///
+/// # Safety
+///
+/// `tb` must come from [`tb_new`] and `name` must be a NUL-terminated
+/// string. `func` must be safe to call with `data` for as long as `tb`
+/// lives.
+#[no_mangle]
+pub unsafe extern "C" fn tb_register_fn(tb: *mut Tb, name: *const c_char, func: TbFn, data: *mut c_void) -> bool {
+    let tb = unsafe { &mut *tb };
+    let Some(name) = (unsafe { str(name) }) else {
+        return false;
+    };
+    let failed = format!("`{}` failed", name);
+    tb.interpreter.register_fn(name, move |args| {
+        let mut strings = Vec::new();
+        let args: Vec<TbValue> = args.iter().map(|arg| TbValue::new(arg, &mut strings)).collect();
+        let mut result = TbValue::NIL;
+        // SAFETY: the caller of `tb_register_fn` vouches for `func` and
+        // `data`, and the arguments and their strings outlive the call.
+        if unsafe { func(args.as_ptr(), args.len(), &mut result, data) } {
+            unsafe { result.to_value() }
+        } else {
+            let message = unsafe { str(result.string) }.map_or(failed.clone(), str::to_string);
+            Err(RuntimeError::host(message))
+        }
+    });
+    true
+}
+
+#[cfg(test)]
+mod test {
+    use std::ffi::{c_void, CStr};
+    use std::ptr;
+
+    use crate::ffi::{tb_error, tb_eval, tb_free, tb_get_var, tb_new, tb_register_fn, TbType, TbValue};
+
+    /// Adds its int arguments, counting its calls in `data`, and fails
+    /// on anything else.
+    unsafe extern "C" fn add(args: *const TbValue, count: usize, result: *mut TbValue, data: *mut c_void) -> bool {
+        unsafe { *(data as *mut i32) += 1 };
+        let args = unsafe { std::slice::from_raw_parts(args, count) };
+        if args.iter().any(|arg| arg.kind != TbType::Int) {
+            unsafe { (*result).string = c"add takes ints".as_ptr() };
+            return false;
+        }
+        unsafe { *result = TbValue { kind: TbType::Int, integer: args.iter().map(|arg| arg.integer).sum(), ..TbValue::NIL } };
+        true
+    }
+
+    #[test]
+    fn test_api() {
+        unsafe {
+            let tb = tb_new();
+            let mut result = TbValue::NIL;
+            assert_eq!(tb_eval(tb, c"x = 2 * 21\n\"a\" + \"b\"".as_ptr(), &mut result), 0);
+            assert_eq!(result.kind, TbType::String);
+            assert_eq!(CStr::from_ptr(result.string).to_str(), Ok("ab"));
+            assert!(tb_error(tb).is_null());
+
+            assert!(tb_get_var(tb, c"x".as_ptr(), &mut result));
+            assert_eq!((result.kind, result.integer), (TbType::Int, 42));
+            assert!(!tb_get_var(tb, c"missing".as_ptr(), &mut result));
+            assert_eq!(tb_eval(tb, c"a = [1, \"two\"]".as_ptr(), ptr::null_mut()), 0);
+            assert!(tb_get_var(tb, c"a".as_ptr(), &mut result));
+            assert_eq!(result.kind, TbType::Other);
+            assert_eq!(CStr::from_ptr(result.string).to_str(), Ok("[1, \"two\"]"));
+
+            let mut calls = 0;
+            assert!(tb_register_fn(tb, c"add".as_ptr(), add, &mut calls as *mut i32 as *mut c_void));
+            assert_eq!(tb_eval(tb, c"add(x, 1, 2)".as_ptr(), &mut result), 0);
+            assert_eq!((result.kind, result.integer), (TbType::Int, 45));
+            assert_eq!(tb_eval(tb, c"add(1, \"2\")".as_ptr(), &mut result), -1);
+            assert_eq!(CStr::from_ptr(tb_error(tb)).to_str(), Ok("1:1: add takes ints"));
+            assert_eq!(calls, 2);
+
+            assert_eq!(tb_eval(tb, c"x = ".as_ptr(), &mut result), -1);
+            assert!(!tb_error(tb).is_null());
+            tb_free(tb);
+            tb_free(ptr::null_mut());
+        }
+    }
+}
@@ -0,0 +1,594 @@
+use std::collections::HashMap;
+use std::fmt::Write;
+
+use crate::ast::{BinaryOp, Expr, ExprKind, FunctionDecl, Program, Stmt, StmtKind, TypeAnnotation, UnaryOp};
+use crate::lexer::Span;
+use crate::parser::{parse_with_dialect, Dialect, ParseError, ANONYMOUS};
+
+/// The layout choices [`format_with`] leaves to the user.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FormatOptions {
+    /// Spaces per block level, or with `tabs` the columns a tab counts for
+    /// against `max_width`.
+    pub indent: usize,
+    /// Indent with a tab per block level instead of spaces.
+    pub tabs: bool,
+    /// How many columns a line may take before the items of the brackets
+    /// that make it too wide are put on lines of their own. A line without
+    /// brackets to break stays as it is.
+    pub max_width: usize,
+    pub brace_style: BraceStyle,
+}
+
+impl Default for FormatOptions {
+    fn default() -> Self {
+        FormatOptions { indent: 2, tabs: false, max_width: 100, brace_style: BraceStyle::AlwaysSameLine }
+    }
+}
+
+/// Where `else` goes after the closing brace of an `if`. An opening brace
+/// always stays on the line of its statement, since a newline before it
+/// would end the statement.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BraceStyle {
+    /// `} else {`
+    #[default]
+    AlwaysSameLine,
+    /// `}` and then `else {` on the next line.
+    ClosingNextLine,
+}
+
+/// Reprints `source` in the standard layout: one statement per line, blocks
+/// indented two spaces with the opening brace on the statement's line and
+/// `else` after the closing one, single spaces around binary operators and
+/// after commas, and only the parentheses precedence needs. Brackets that
+/// make a line wider than 100 columns get their items on lines of their
+/// own. Runs of blank lines between statements become a single one.
+///
+/// The language has no comments, so the syntax tree holds everything worth
+/// keeping; the source is only consulted for where the blank lines were and
+/// for a `#!` line, which is kept as it is.
+pub fn format(source: &str, dialect: Dialect) -> Result<String, ParseError> {
+    format_with(source, dialect, &FormatOptions::default())
+}
+
+/// Like [`format`], laid out according to `options`.
+pub fn format_with(source: &str, dialect: Dialect, options: &FormatOptions) -> Result<String, ParseError> {
+    let program = parse_with_dialect(source, dialect)?;
+    Ok(format_program_with(&program, source, options))
+}
+
+/// Like [`format`], for a program already parsed from `source`.
+pub fn format_program(program: &Program, source: &str) -> String {
+    format_program_with(program, source, &FormatOptions::default())
+}
+
+/// Like [`format_with`], for a program already parsed from `source`.
+pub fn format_program_with(program: &Program, source: &str, options: &FormatOptions) -> String {
+    let labels: HashMap<usize, i64> = program.lines.iter().map(|(line, index)| (*index, *line)).collect();
+    let shebang = source.lines().next().filter(|line| line.starts_with("#!"));
+    let out = shebang.map_or(String::new(), |line| format!("{}\n", line));
+    let indent = if options.tabs { "\t".to_string() } else { " ".repeat(options.indent) };
+    let mut formatter = Formatter { out, newlines: newlines(source), labels, indent, options: *options, flat: false, depth: 0 };
+    formatter.statements(&program.statements, true);
+    formatter.out
+}
+
+/// Whether formatting `source` would leave it as it is.
+pub fn is_formatted(source: &str, dialect: Dialect) -> Result<bool, ParseError> {
+    Ok(format(source, dialect)? == source)
+}
+
+/// The character offsets of the newlines in `source`.
+fn newlines(source: &str) -> Vec<usize> {
+    source.chars().enumerate().filter(|(_, c)| *c == '\n').map(|(i, _)| i).collect()
+}
+
+struct Formatter {
+    out: String,
+    newlines: Vec<usize>,
+    /// Classic dialect: the line number of each top-level statement.
+    labels: HashMap<usize, i64>,
+    /// Indentation per block level.
+    indent: String,
+    options: FormatOptions,
+    /// Whether brackets are being tried on one line.
+    flat: bool,
+    depth: usize,
+}
+
+impl Formatter {
+    /// The 1-based line `offset` is on.
+    fn line_of(&self, offset: usize) -> usize {
+        self.newlines.partition_point(|&newline| newline < offset) + 1
+    }
+
+    fn indent(&mut self) {
+        for _ in 0..self.depth {
+            self.out.push_str(&self.indent);
+        }
+    }
+
+    fn statements(&mut self, statements: &[Stmt], top_level: bool) {
+        let mut previous_end: Option<Span> = None;
+        for (i, statement) in statements.iter().enumerate() {
+            let doc = match &statement.kind {
+                StmtKind::Function(decl) => decl.doc.as_deref(),
+                StmtKind::Struct(decl) => decl.doc.as_deref(),
+                _ => None,
+            };
+            // The doc comment is on the lines right before the statement
+            let first_line = statement.span.line - doc.map_or(0, |doc| doc.split('\n').count());
+            if let Some(end) = previous_end {
+                // The end offset is one past the statement's last character
+                if first_line > self.line_of(end.end.saturating_sub(1)) + 1 {
+                    self.out.push('\n');
+                }
+            }
+            previous_end = Some(statement.span);
+            for line in doc.into_iter().flat_map(|doc| doc.split('\n')) {
+                self.indent();
+                self.out.push_str(if line.is_empty() { "##" } else { "## " });
+                self.out.push_str(line);
+                self.out.push('\n');
+            }
+            self.indent();
+            if let Some(label) = self.labels.get(&i).filter(|_| top_level) {
+                let _ = write!(self.out, "{} ", label);
+            }
+            self.statement(statement);
+            self.out.push('\n');
+        }
+    }
+
+    /// `{`, the statements indented, then `}` on a line of its own. Empty
+    /// blocks are written `{}`.
+    fn block(&mut self, statements: &[Stmt]) {
+        if statements.is_empty() {
+            self.out.push_str("{}");
+            return;
+        }
+        self.out.push_str("{\n");
+        self.depth += 1;
+        self.statements(statements, false);
+        self.depth -= 1;
+        self.indent();
+        self.out.push('}');
+    }
+
+    fn statement(&mut self, statement: &Stmt) {
+        match &statement.kind {
+            StmtKind::Assign { name, ty, value, declaration } => {
+                if *declaration {
+                    self.out.push_str("let ");
+                }
+                self.out.push_str(name);
+                self.annotation(ty);
+                self.out.push_str(" = ");
+                self.expr(value);
+            },
+            StmtKind::SetIndex { target, index, value } => {
+                self.postfix_target(target);
+                self.out.push('[');
+                self.expr(index);
+                self.out.push_str("] = ");
+                self.expr(value);
+            },
+            StmtKind::SetField { target, field, value } => {
+                self.postfix_target(target);
+                let _ = write!(self.out, ".{} = ", field);
+                self.expr(value);
+            },
+            StmtKind::If { condition, then_branch, else_branch } => {
+                self.out.push_str("if ");
+                self.expr(condition);
+                self.out.push(' ');
+                self.block(then_branch);
+                match else_branch.as_deref() {
+                    // `else if` ends where the whole statement ends, while
+                    // an `if` alone in an `else` block ends before its `}`
+                    Some([nested]) if matches!(nested.kind, StmtKind::If { .. }) && nested.span.end == statement.span.end => {
+                        self.else_keyword();
+                        self.statement(nested);
+                    },
+                    Some(branch) => {
+                        self.else_keyword();
+                        self.block(branch);
+                    },
+                    None => {},
+                }
+            },
+            StmtKind::While { condition, body } => {
+                self.out.push_str("while ");
+                self.expr(condition);
+                self.out.push(' ');
+                self.block(body);
+            },
+            StmtKind::Break => self.out.push_str("break"),
+            StmtKind::Continue => self.out.push_str("continue"),
+            StmtKind::Print(args) => {
+                self.out.push_str("print");
+                for (i, arg) in args.iter().enumerate() {
+                    self.out.push_str(if i == 0 { " " } else { ", " });
+                    self.expr(arg);
+                }
+            },
+            StmtKind::Function(decl) => self.function(decl),
+            StmtKind::Struct(decl) => {
+                let _ = write!(self.out, "struct {} ", decl.name);
+                if decl.fields.is_empty() {
+                    self.out.push_str("{}");
+                } else {
+                    let _ = write!(self.out, "{{ {} }}", decl.fields.join(", "));
+                }
+            },
+            StmtKind::Return(value) => {
+                self.out.push_str("return");
+                if let Some(value) = value {
+                    self.out.push(' ');
+                    self.expr(value);
+                }
+            },
+            StmtKind::Global(names) => {
+                let _ = write!(self.out, "global {}", names.join(", "));
+            },
+            StmtKind::Import(name) => {
+                let _ = write!(self.out, "import {}", name);
+            },
+            StmtKind::Goto(line) => {
+                let _ = write!(self.out, "goto {}", line);
+            },
+            StmtKind::Gosub(line) => {
+                let _ = write!(self.out, "gosub {}", line);
+            },
+            StmtKind::SubReturn => self.out.push_str("return"),
+            StmtKind::Expr(expr) => self.expr(expr),
+        }
+    }
+
+    fn else_keyword(&mut self) {
+        match self.options.brace_style {
+            BraceStyle::AlwaysSameLine => self.out.push_str(" else "),
+            BraceStyle::ClosingNextLine => {
+                self.out.push('\n');
+                self.indent();
+                self.out.push_str("else ");
+            },
+        }
+    }
+
+    /// `fn name(params): type { body }`; anonymous functions have no name.
+    fn function(&mut self, decl: &FunctionDecl) {
+        self.out.push_str("fn");
+        if decl.name != ANONYMOUS {
+            let _ = write!(self.out, " {}", decl.name);
+        }
+        self.out.push('(');
+        for (i, (param, ty)) in decl.params.iter().zip(&decl.param_types).enumerate() {
+            if i > 0 {
+                self.out.push_str(", ");
+            }
+            self.out.push_str(param);
+            self.annotation(ty);
+        }
+        self.out.push(')');
+        self.annotation(&decl.return_type);
+        self.out.push(' ');
+        self.block(&decl.body);
+    }
+
+    fn annotation(&mut self, ty: &Option<TypeAnnotation>) {
+        if let Some(ty) = ty {
+            let _ = write!(self.out, ": {}", ty.name);
+        }
+    }
+
+    fn expr(&mut self, expr: &Expr) {
+        match &expr.kind {
+            ExprKind::Number(n) => {
+                let _ = write!(self.out, "{}", n);
+            },
+            ExprKind::Float(n) => {
+                // `Display` never uses an exponent, which the lexer wouldn't read
+                let text = n.to_string();
+                self.out.push_str(&text);
+                if !text.contains('.') {
+                    self.out.push_str(".0");
+                }
+            },
+            ExprKind::Str(s) => self.string(s),
+            ExprKind::Variable(name) => self.out.push_str(name),
+            ExprKind::Array(items) => self.list('[', items, ']'),
+            ExprKind::Map(entries) => {
+                self.bracketed('{', entries.len(), '}', |formatter, i| {
+                    formatter.expr(&entries[i].0);
+                    formatter.out.push_str(": ");
+                    formatter.expr(&entries[i].1);
+                });
+            },
+            ExprKind::Index { target, index } => {
+                self.postfix_target(target);
+                self.out.push('[');
+                self.expr(index);
+                self.out.push(']');
+            },
+            ExprKind::Field { target, field } => {
+                self.postfix_target(target);
+                let _ = write!(self.out, ".{}", field);
+            },
+            ExprKind::Unary { op, operand } => {
+                self.out.push(match op {
+                    UnaryOp::Neg => '-',
+                    UnaryOp::Not => '!',
+                });
+                // `-(-1)` rather than `--1`
+                let negative = match &operand.kind {
+                    ExprKind::Unary { op: UnaryOp::Neg, .. } => true,
+                    ExprKind::Number(n) => *n < 0,
+                    ExprKind::Float(n) => n.is_sign_negative(),
+                    _ => false,
+                };
+                let negative = negative && *op == UnaryOp::Neg;
+                self.operand(operand, negative || matches!(operand.kind, ExprKind::Binary { .. }));
+            },
+            ExprKind::Binary { op, left, right } => {
+                // Operators of the same precedence group to the left
+                let precedence = precedence(*op);
+                self.operand(left, binary_precedence(left).is_some_and(|p| p < precedence));
+                let _ = write!(self.out, " {} ", symbol(*op));
+                self.operand(right, binary_precedence(right).is_some_and(|p| p <= precedence));
+            },
+            ExprKind::Call { callee, args } => {
+                self.postfix_target(callee);
+                self.list('(', args, ')');
+            },
+            ExprKind::Function(decl) => self.function(decl),
+        }
+    }
+
+    fn operand(&mut self, expr: &Expr, parenthesize: bool) {
+        if parenthesize {
+            self.out.push('(');
+            self.expr(expr);
+            self.out.push(')');
+        } else {
+            self.expr(expr);
+        }
+    }
+
+    /// What a call, index or field access applies to, which binds tighter
+    /// than any operator.
+    fn postfix_target(&mut self, target: &Expr) {
+        self.operand(target, matches!(target.kind, ExprKind::Unary { .. } | ExprKind::Binary { .. }));
+    }
+
+    fn list(&mut self, open: char, items: &[Expr], close: char) {
+        self.bracketed(open, items.len(), close, |formatter, i| formatter.expr(&items[i]));
+    }
+
+    /// `len` items written by `item` between brackets, separated by commas.
+    /// If that makes the line wider than the maximum, each item goes on a
+    /// line of its own instead, indented and followed by a comma.
+    fn bracketed(&mut self, open: char, len: usize, close: char, item: impl Fn(&mut Formatter, usize)) {
+        // Brackets inside stay on one line while these are tried on one, so
+        // the outermost are the first to break
+        let start = self.out.len();
+        let flat = std::mem::replace(&mut self.flat, true);
+        self.out.push(open);
+        for i in 0..len {
+            if i > 0 {
+                self.out.push_str(", ");
+            }
+            item(self, i);
+        }
+        self.out.push(close);
+        self.flat = flat;
+        if flat || len == 0 || self.line_width(start) <= self.options.max_width {
+            return;
+        }
+        self.out.truncate(start);
+        self.out.push(open);
+        self.out.push('\n');
+        self.depth += 1;
+        for i in 0..len {
+            self.indent();
+            item(self, i);
+            self.out.push_str(",\n");
+        }
+        self.depth -= 1;
+        self.indent();
+        self.out.push(close);
+    }
+
+    /// The width of the line of the output that `start` is on, up to its
+    /// end or the first newline after `start`.
+    fn line_width(&self, start: usize) -> usize {
+        let line_start = self.out[..start].rfind('\n').map_or(0, |i| i + 1);
+        let end = self.out[start..].find('\n').map_or(self.out.len(), |i| start + i);
+        self.out[line_start..end].chars().map(|c| if c == '\t' { self.options.indent } else { 1 }).sum()
+    }
+
+    /// A string literal with the escapes the lexer understands.
+    fn string(&mut self, s: &str) {
+        self.out.push('"');
+        for c in s.chars() {
+            match c {
+                '\n' => self.out.push_str("\\n"),
+                '\t' => self.out.push_str("\\t"),
+                '"' => self.out.push_str("\\\""),
+                '\\' => self.out.push_str("\\\\"),
+                c => self.out.push(c),
+            }
+        }
+        self.out.push('"');
+    }
+}
+
+/// Binding strength of binary operators, loosest first.
+fn precedence(op: BinaryOp) -> u8 {
+    match op {
+        BinaryOp::Or => 0,
+        BinaryOp::And => 1,
+        BinaryOp::Equals
+        | BinaryOp::NotEquals
+        | BinaryOp::SmallerThan
+        | BinaryOp::GreaterThan
+        | BinaryOp::SmallerEquals
+        | BinaryOp::GreaterEquals => 2,
+        BinaryOp::Add | BinaryOp::Sub => 3,
+        BinaryOp::Mul | BinaryOp::Div => 4,
+    }
+}
+
+fn binary_precedence(expr: &Expr) -> Option<u8> {
+    match &expr.kind {
+        ExprKind::Binary { op, .. } => Some(precedence(*op)),
+        _ => None,
+    }
+}
+
+fn symbol(op: BinaryOp) -> &'static str {
+    match op {
+        BinaryOp::Add => "+",
+        BinaryOp::Sub => "-",
+        BinaryOp::Mul => "*",
+        BinaryOp::Div => "/",
+        BinaryOp::Equals => "==",
+        BinaryOp::NotEquals => "!=",
+        BinaryOp::SmallerThan => "<",
+        BinaryOp::GreaterThan => ">",
+        BinaryOp::SmallerEquals => "<=",
+        BinaryOp::GreaterEquals => ">=",
+        BinaryOp::And => "&&",
+        BinaryOp::Or => "||",
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::ast::{ExprKind, StmtKind};
+    use crate::formatter::{format, format_program, format_with, is_formatted, BraceStyle, FormatOptions};
+    use crate::interpreter::{Interpreter, SharedBuffer};
+    use crate::parser::{parse_with_dialect, Dialect};
+
+    const MESSY: &str = "\
+fn  area(shape:string,size) :int{
+if shape==\"square\"{return size*size}
+    else   if shape == \"circle\" { return 3*size*size } else {
+  if size>0 {return -1}
+}
+}
+
+
+struct Point{x,y}
+items=[1,2,(3+4)*5,-(1-2),{\"a\":1, \"b\":[2]}]
+p = Point(1,2)
+p.x=p.x-(1-2)
+items[0]=-area(\"square\" ,3)
+let  total:int=0
+while total<10&&!(total==5||total==6){total=total+1
+if total == 2 { continue }}
+print
+print \"tab\\t\\\"quoted\\\"\", 2.50 , 1/(2*3), (1+2)+3, 1+(2+3), 1 - (2 - 3)
+double = fn(x){return x*2}
+print double(4), fn(){}()
+";
+
+    #[test]
+    fn test_format() {
+        assert_eq!(format(MESSY, Dialect::Modern).unwrap(), "\
+fn area(shape: string, size): int {
+  if shape == \"square\" {
+    return size * size
+  } else if shape == \"circle\" {
+    return 3 * size * size
+  } else {
+    if size > 0 {
+      return -1
+    }
+  }
+}
+
+struct Point { x, y }
+items = [1, 2, (3 + 4) * 5, -(1 - 2), {\"a\": 1, \"b\": [2]}]
+p = Point(1, 2)
+p.x = p.x - (1 - 2)
+items[0] = -area(\"square\", 3)
+let total: int = 0
+while total < 10 && !(total == 5 || total == 6) {
+  total = total + 1
+  if total == 2 {
+    continue
+  }
+}
+print
+print \"tab\\t\\\"quoted\\\"\", 2.5, 1 / (2 * 3), 1 + 2 + 3, 1 + (2 + 3), 1 - (2 - 3)
+double = fn(x) {
+  return x * 2
+}
+print double(4), fn() {}()
+");
+    }
+
+    #[test]
+    fn test_idempotent() {
+        let formatted = format(MESSY, Dialect::Modern).unwrap();
+        assert!(is_formatted(&formatted, Dialect::Modern).unwrap());
+        assert!(!is_formatted(MESSY, Dialect::Modern).unwrap());
+        assert!(format("x = (", Dialect::Modern).is_err());
+
+        let classic = "10   x=1\n20 if x<3 { x=x+1 \n goto 20 }\n30 gosub 50\n40 print x\n50 return";
+        let formatted = format(classic, Dialect::Classic).unwrap();
+        assert_eq!(formatted, "10 x = 1\n20 if x < 3 {\n  x = x + 1\n  goto 20\n}\n30 gosub 50\n40 print x\n50 return\n");
+        assert!(is_formatted(&formatted, Dialect::Classic).unwrap());
+
+        let documented = format("x = 1\n  ##   Twice `x`.\n##\nfn twice(x) {\n## A point.\nstruct P {x}\n}", Dialect::Modern).unwrap();
+        assert_eq!(documented, "x = 1\n##   Twice `x`.\n##\nfn twice(x) {\n  ## A point.\n  struct P { x }\n}\n");
+        assert!(is_formatted(&documented, Dialect::Modern).unwrap());
+
+        let negated = format("x = -(-1)\ny = - -a\nz = !!a", Dialect::Modern).unwrap();
+        assert_eq!(negated, "x = -(-1)\ny = -(-a)\nz = !!a\n");
+        assert_eq!(format(&negated, Dialect::Modern).unwrap(), negated);
+        // Negative literals only come from rewriting the AST
+        let mut program = parse_with_dialect("x = -y", Dialect::Modern).unwrap();
+        if let StmtKind::Assign { value, .. } = &mut program.statements[0].kind {
+            if let ExprKind::Unary { operand, .. } = &mut value.kind {
+                operand.kind = ExprKind::Number(-1);
+            }
+        }
+        assert_eq!(format_program(&program, "x = -y"), "x = -(-1)\n");
+
+        let script = format("#!/usr/bin/env tbasic\nprint   1", Dialect::Modern).unwrap();
+        assert_eq!(script, "#!/usr/bin/env tbasic\nprint 1\n");
+        assert!(is_formatted(&script, Dialect::Modern).unwrap());
+    }
+
+    #[test]
+    fn test_options() {
+        let source = "if x { y = [100, 200, 300] } else { print {\"a\": f(1, 2)} }";
+        let options = FormatOptions { tabs: true, max_width: 16, brace_style: BraceStyle::ClosingNextLine, ..FormatOptions::default() };
+        let formatted = format_with(source, Dialect::Modern, &options).unwrap();
+        assert_eq!(formatted, "if x {\n\ty = [\n\t\t100,\n\t\t200,\n\t\t300,\n\t]\n}\nelse {\n\tprint {\n\t\t\"a\": f(1, 2),\n\t}\n}\n");
+        assert_eq!(format_with(&formatted, Dialect::Modern, &options).unwrap(), formatted);
+        assert_eq!(format(&formatted, Dialect::Modern).unwrap(), format(source, Dialect::Modern).unwrap());
+
+        // Only as many brackets break as needed
+        let options = FormatOptions { indent: 4, max_width: 24, ..FormatOptions::default() };
+        let formatted = format_with("total = sum([1, 2, 3], fn(x) { return x }, limit(10, 20))", Dialect::Modern, &options);
+        assert_eq!(formatted.unwrap(), "total = sum(\n    [1, 2, 3],\n    fn(x) {\n        return x\n    },\n    limit(10, 20),\n)\n");
+    }
+
+    #[test]
+    fn test_behavior_kept() {
+        let run = |source: &str| {
+            let output = SharedBuffer::new();
+            let mut interpreter = Interpreter::with_output(Box::new(output.clone()));
+            let program = parse_with_dialect(source, Dialect::Modern).unwrap();
+            let _ = interpreter.run(&program);
+            output.contents()
+        };
+        let formatted = format(MESSY, Dialect::Modern).unwrap();
+        assert_eq!(run(&formatted), run(MESSY));
+        assert!(run(MESSY).contains("tab\t\"quoted\" 2.5"));
+    }
+}
@@ -0,0 +1,182 @@
+use crate::lexer::{Lexer, Span, Token};
+
+/// What a stretch of source is, for syntax highlighting.
+///
+/// Brackets, commas and the like aren't classified.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TokenClass {
+    Keyword,
+    Number,
+    String,
+    Identifier,
+    Operator,
+    /// A `##` doc comment.
+    Comment,
+}
+
+impl TokenClass {
+    /// The class's index in [`LEGEND`].
+    pub fn index(self) -> u32 {
+        self as u32
+    }
+}
+
+/// The semantic token types [`lsp_data`] refers to by index, in the order a
+/// language server announces them.
+pub const LEGEND: [&str; 6] = ["keyword", "number", "string", "variable", "operator", "comment"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SemanticToken {
+    pub class: TokenClass,
+    pub span: Span,
+}
+
+/// Classifies the tokens of `source`, in order. Source that doesn't lex is
+/// classified as far as possible: an unterminated string is a string up to
+/// the end, other bad characters are skipped.
+pub fn highlight(source: &str) -> Vec<SemanticToken> {
+    let (tokens, errors) = Lexer::new(source).tokenize_recovering();
+    let chars: Vec<char> = source.chars().collect();
+    let mut highlighted: Vec<SemanticToken> = tokens
+        .iter()
+        .filter_map(|token| classify(&token.token).map(|class| SemanticToken { class, span: token.span }))
+        .collect();
+    for error in errors {
+        if chars.get(error.span.start) == Some(&'"') {
+            highlighted.push(SemanticToken { class: TokenClass::String, span: error.span });
+        }
+    }
+    highlighted.sort_by_key(|token| token.span.start);
+    highlighted
+}
+
+fn classify(token: &Token) -> Option<TokenClass> {
+    let class = match token {
+        Token::Number(_) | Token::Float(_) => TokenClass::Number,
+        Token::Str(_) => TokenClass::String,
+        Token::Doc(_) => TokenClass::Comment,
+        Token::Id(_) => TokenClass::Identifier,
+        Token::If
+        | Token::Else
+        | Token::While
+        | Token::Break
+        | Token::Continue
+        | Token::Print
+        | Token::Fn
+        | Token::Return
+        | Token::Global
+        | Token::Let
+        | Token::Struct
+        | Token::Import
+        | Token::Goto
+        | Token::Gosub => TokenClass::Keyword,
+        Token::Plus
+        | Token::Minus
+        | Token::Multiply
+        | Token::Divide
+        | Token::Assign
+        | Token::Equals
+        | Token::NotEquals
+        | Token::SmallerThan
+        | Token::GreaterThan
+        | Token::SmallerEquals
+        | Token::GreaterEquals
+        | Token::Not
+        | Token::And
+        | Token::Or => TokenClass::Operator,
+        Token::Lparen
+        | Token::Rparen
+        | Token::Lbracket
+        | Token::Rbracket
+        | Token::CurlyL
+        | Token::CurlyR
+        | Token::Comma
+        | Token::Colon
+        | Token::Dot
+        | Token::Newline => return None,
+    };
+    Some(class)
+}
+
+/// `tokens` of `source` in the encoding of the LSP `textDocument/semanticTokens`
+/// response: five numbers per token, its line and start relative to the
+/// previous token's, its length, its type's index in [`LEGEND`] and no
+/// modifiers. Positions are 0-based and count UTF-16 code units, as LSP
+/// expects. A token spanning lines, which only strings can, is cut off at
+/// the end of its first line.
+pub fn lsp_data(source: &str, tokens: &[SemanticToken]) -> Vec<u32> {
+    // The UTF-16 offset of each char from the start of its line
+    let mut columns = Vec::new();
+    let mut column = 0;
+    for c in source.chars() {
+        columns.push(column);
+        column = if c == '\n' { 0 } else { column + c.len_utf16() as u32 };
+    }
+    columns.push(column);
+
+    let chars: Vec<char> = source.chars().collect();
+    let mut data = Vec::with_capacity(tokens.len() * 5);
+    let (mut previous_line, mut previous_start) = (0, 0);
+    for token in tokens {
+        let span = token.span;
+        let line = span.line as u32 - 1;
+        let start = columns[span.start];
+        let end = chars[span.start..span.end].iter().position(|&c| c == '\n').map_or(span.end, |i| span.start + i);
+        let length: u32 = chars[span.start..end].iter().map(|c| c.len_utf16() as u32).sum();
+        let delta_start = if line == previous_line { start - previous_start } else { start };
+        data.extend([line - previous_line, delta_start, length, token.class.index(), 0]);
+        (previous_line, previous_start) = (line, start);
+    }
+    data
+}
+
+#[cfg(test)]
+mod test {
+    use crate::highlight::{highlight, lsp_data, TokenClass, LEGEND};
+
+    #[test]
+    fn test_highlight() {
+        let source = "fn f(x) {\n  return x * 2.5\n}\nprint f(1) && \"é\"";
+        let classes: Vec<_> = highlight(source).iter().map(|token| (token.class, token.span.start, token.span.end)).collect();
+        assert_eq!(classes, vec![
+            (TokenClass::Keyword, 0, 2),
+            (TokenClass::Identifier, 3, 4),
+            (TokenClass::Identifier, 5, 6),
+            (TokenClass::Keyword, 12, 18),
+            (TokenClass::Identifier, 19, 20),
+            (TokenClass::Operator, 21, 22),
+            (TokenClass::Number, 23, 26),
+            (TokenClass::Keyword, 29, 34),
+            (TokenClass::Identifier, 35, 36),
+            (TokenClass::Number, 37, 38),
+            (TokenClass::Operator, 40, 42),
+            (TokenClass::String, 43, 46),
+        ]);
+        assert_eq!(LEGEND[TokenClass::Identifier.index() as usize], "variable");
+
+        // Bad characters are skipped and unfinished strings still highlighted
+        let classes: Vec<_> = highlight("x @ 1\ny = \"abc").iter().map(|token| token.class).collect();
+        assert_eq!(classes, vec![
+            TokenClass::Identifier,
+            TokenClass::Number,
+            TokenClass::Identifier,
+            TokenClass::Operator,
+            TokenClass::String,
+        ]);
+    }
+
+    #[test]
+    fn test_lsp_data() {
+        let source = "s = \"😀\" + t\n  print s";
+        let data = lsp_data(source, &highlight(source));
+        assert_eq!(data, vec![
+            0, 0, 1, 3, 0, // s
+            0, 2, 1, 4, 0, // =
+            0, 2, 4, 2, 0, // the string, two UTF-16 units for the emoji
+            0, 5, 1, 4, 0, // +
+            0, 2, 1, 3, 0, // t
+            1, 2, 5, 0, 0, // print
+            0, 6, 1, 3, 0, // s
+        ]);
+    }
+}
@@ -0,0 +1,296 @@
+use alloc::boxed::Box;
+use alloc::rc::Rc;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+
+use crate::ast::{Expr, ExprKind, FunctionDecl, Program, Stmt, StmtKind};
+use crate::collections::HashMap;
+use crate::transpile::{each_subexpr, functions_in, walk_blocks};
+
+/// Replaces calls of small functions by the functions' bodies, saving the
+/// call in loops such as `while i < n { total = total + square(i) }`.
+///
+/// A function is inlined if it is defined once, by a top-level `fn`, and
+/// its body is a single `return` of an expression of at most `max_size`
+/// nodes that refers to nothing but its parameters and makes no calls, so
+/// it can't recurse. Only calls that run after the definition and whose
+/// arguments are literals or variables are replaced; programs with `goto`
+/// or `gosub` are left alone.
+///
+/// Programs that run without errors behave the same. Ones that fail may
+/// report another error, such as an undefined variable passed to a
+/// parameter the body doesn't use, and inlined calls are missing from
+/// error traces. The pass assumes later programs run on the same
+/// interpreter don't redefine the functions.
+pub fn inline(program: &Program, max_size: usize) -> Program {
+    let mut bindings = HashMap::default();
+    if max_size == 0 || count_bindings(&program.statements, &mut bindings) {
+        return program.clone();
+    }
+    let mut inliner = Inliner { functions: HashMap::default() };
+    let statements = program
+        .statements
+        .iter()
+        .map(|statement| {
+            let rewritten = inliner.statement(statement);
+            if let StmtKind::Function(decl) = &statement.kind {
+                if let Some(body) = inlinable(decl, max_size).filter(|_| bindings[&decl.name] == 1) {
+                    inliner.functions.insert(decl.name.clone(), (decl.clone(), body.clone()));
+                }
+            }
+            rewritten
+        })
+        .collect();
+    Program { statements, lines: program.lines.clone() }
+}
+
+/// Counts how often `statements` and the functions in them bind each
+/// name, returning whether they jump.
+fn count_bindings(statements: &[Stmt], bindings: &mut HashMap<String, usize>) -> bool {
+    let mut bind = |name: &str| *bindings.entry(name.to_string()).or_insert(0) += 1;
+    let mut jumps = false;
+    let mut nested = Vec::new();
+    for statement in statements {
+        walk_blocks(statement, &mut |kind| match kind {
+            StmtKind::Assign { name, .. } => bind(name),
+            StmtKind::Function(decl) => bind(&decl.name),
+            StmtKind::Struct(decl) => bind(&decl.name),
+            StmtKind::Import(module) => bind(module),
+            StmtKind::Global(names) => names.iter().for_each(|name| bind(name)),
+            StmtKind::Goto(_) | StmtKind::Gosub(_) => jumps = true,
+            _ => {},
+        });
+        functions_in(statement, &mut nested);
+    }
+    for decl in nested {
+        for param in &decl.params {
+            *bindings.entry(param.clone()).or_insert(0) += 1;
+        }
+        jumps |= count_bindings(&decl.body, bindings);
+    }
+    jumps
+}
+
+/// The expression `decl` returns, if the function can be inlined.
+fn inlinable(decl: &FunctionDecl, max_size: usize) -> Option<&Expr> {
+    let [Stmt { kind: StmtKind::Return(Some(body)), .. }] = decl.body.as_slice() else {
+        return None;
+    };
+    let distinct = decl.params.iter().enumerate().all(|(i, param)| !decl.params[..i].contains(param));
+    let mut size = 0;
+    (distinct && pure(body, &decl.params, &mut size) && size <= max_size).then_some(body)
+}
+
+/// Whether `expr` only reads `params` and makes no calls, adding the number
+/// of its nodes to `size`.
+fn pure(expr: &Expr, params: &[String], size: &mut usize) -> bool {
+    *size += 1;
+    match &expr.kind {
+        ExprKind::Variable(name) => params.contains(name),
+        ExprKind::Call { .. } | ExprKind::Function(_) => false,
+        _ => {
+            let mut pure_operands = true;
+            each_subexpr(expr, &mut |operand| pure_operands &= pure(operand, params, size));
+            pure_operands
+        },
+    }
+}
+
+/// `body` with each parameter replaced by its argument.
+fn substitute(body: &Expr, params: &[String], args: &[Expr]) -> Expr {
+    let kind = match &body.kind {
+        ExprKind::Variable(name) => match params.iter().position(|param| param == name) {
+            Some(i) => return args[i].clone(),
+            None => body.kind.clone(),
+        },
+        ExprKind::Array(items) => ExprKind::Array(items.iter().map(|item| substitute(item, params, args)).collect()),
+        ExprKind::Map(entries) => ExprKind::Map(
+            entries
+                .iter()
+                .map(|(key, value)| (substitute(key, params, args), substitute(value, params, args)))
+                .collect(),
+        ),
+        ExprKind::Index { target, index } => ExprKind::Index {
+            target: Box::new(substitute(target, params, args)),
+            index: Box::new(substitute(index, params, args)),
+        },
+        ExprKind::Field { target, field } => {
+            ExprKind::Field { target: Box::new(substitute(target, params, args)), field: field.clone() }
+        },
+        ExprKind::Unary { op, operand } => ExprKind::Unary { op: *op, operand: Box::new(substitute(operand, params, args)) },
+        ExprKind::Binary { op, left, right } => ExprKind::Binary {
+            op: *op,
+            left: Box::new(substitute(left, params, args)),
+            right: Box::new(substitute(right, params, args)),
+        },
+        kind => kind.clone(),
+    };
+    Expr { kind, span: body.span }
+}
+
+struct Inliner {
+    /// The functions that can be inlined so far, with the expressions they
+    /// return.
+    functions: HashMap<String, (Rc<FunctionDecl>, Expr)>,
+}
+
+impl Inliner {
+    fn block(&self, statements: &[Stmt]) -> Vec<Stmt> {
+        statements.iter().map(|statement| self.statement(statement)).collect()
+    }
+
+    fn function(&self, decl: &Rc<FunctionDecl>) -> Rc<FunctionDecl> {
+        Rc::new(FunctionDecl { body: self.block(&decl.body), ..(**decl).clone() })
+    }
+
+    fn statement(&self, statement: &Stmt) -> Stmt {
+        let kind = match &statement.kind {
+            StmtKind::Assign { name, ty, value, declaration } => StmtKind::Assign {
+                name: name.clone(),
+                ty: ty.clone(),
+                value: self.expression(value),
+                declaration: *declaration,
+            },
+            StmtKind::SetIndex { target, index, value } => StmtKind::SetIndex {
+                target: self.expression(target),
+                index: self.expression(index),
+                value: self.expression(value),
+            },
+            StmtKind::SetField { target, field, value } => {
+                StmtKind::SetField { target: self.expression(target), field: field.clone(), value: self.expression(value) }
+            },
+            StmtKind::If { condition, then_branch, else_branch } => StmtKind::If {
+                condition: self.expression(condition),
+                then_branch: self.block(then_branch),
+                else_branch: else_branch.as_ref().map(|branch| self.block(branch)),
+            },
+            StmtKind::While { condition, body } => {
+                StmtKind::While { condition: self.expression(condition), body: self.block(body) }
+            },
+            StmtKind::Print(args) => StmtKind::Print(args.iter().map(|arg| self.expression(arg)).collect()),
+            StmtKind::Function(decl) => StmtKind::Function(self.function(decl)),
+            StmtKind::Return(value) => StmtKind::Return(value.as_ref().map(|value| self.expression(value))),
+            StmtKind::Expr(expr) => StmtKind::Expr(self.expression(expr)),
+            kind => kind.clone(),
+        };
+        Stmt { kind, span: statement.span }
+    }
+
+    fn expression(&self, expr: &Expr) -> Expr {
+        let expression = |expr: &Expr| Box::new(self.expression(expr));
+        let kind = match &expr.kind {
+            ExprKind::Call { callee, args } => {
+                let args: Vec<Expr> = args.iter().map(|arg| self.expression(arg)).collect();
+                let simple = args.iter().all(|arg| {
+                    matches!(arg.kind, ExprKind::Number(_) | ExprKind::Float(_) | ExprKind::Str(_) | ExprKind::Variable(_))
+                });
+                if let ExprKind::Variable(name) = &callee.kind {
+                    if let Some((decl, body)) = self.functions.get(name).filter(|_| simple) {
+                        if decl.params.len() == args.len() {
+                            return substitute(body, &decl.params, &args);
+                        }
+                    }
+                }
+                ExprKind::Call { callee: expression(callee), args }
+            },
+            ExprKind::Array(items) => ExprKind::Array(items.iter().map(|item| self.expression(item)).collect()),
+            ExprKind::Map(entries) => {
+                ExprKind::Map(entries.iter().map(|(key, value)| (self.expression(key), self.expression(value))).collect())
+            },
+            ExprKind::Index { target, index } => ExprKind::Index { target: expression(target), index: expression(index) },
+            ExprKind::Field { target, field } => ExprKind::Field { target: expression(target), field: field.clone() },
+            ExprKind::Unary { op, operand } => ExprKind::Unary { op: *op, operand: expression(operand) },
+            ExprKind::Binary { op, left, right } => ExprKind::Binary { op: *op, left: expression(left), right: expression(right) },
+            ExprKind::Function(decl) => ExprKind::Function(self.function(decl)),
+            kind => kind.clone(),
+        };
+        Expr { kind, span: expr.span }
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod test {
+    use crate::ast::{Expr, ExprKind, Program, Stmt, StmtKind};
+    use crate::compiler::{compile_with, CompileOptions};
+    use crate::inline::inline;
+    use crate::interpreter::{Interpreter, SharedBuffer};
+    use crate::parser::{parse, parse_with_dialect, Dialect};
+
+    /// How many calls `program`'s top-level expression statements make.
+    fn calls(program: &Program) -> usize {
+        fn count(expr: &Expr) -> usize {
+            match &expr.kind {
+                ExprKind::Call { callee, args } => 1 + count(callee) + args.iter().map(count).sum::<usize>(),
+                ExprKind::Binary { left, right, .. } => count(left) + count(right),
+                ExprKind::Unary { operand, .. } => count(operand),
+                ExprKind::Array(items) => items.iter().map(count).sum(),
+                ExprKind::Index { target, index } => count(target) + count(index),
+                _ => 0,
+            }
+        }
+        let expr = |statement: &Stmt| match &statement.kind {
+            StmtKind::Expr(expr) => count(expr),
+            _ => 0,
+        };
+        program.statements.iter().map(expr).sum()
+    }
+
+    #[test]
+    fn test_inline() {
+        let cases = [
+            ("fn sq(x) {\n  return x * x\n}\nx = 2\nsq(3) + sq(x)", 5, 0),
+            ("fn first(a, b) {\n  return [a, b][0]\n}\nx = 1\nfirst(x, \"b\")", 5, 0),
+            // Too large
+            ("fn sq(x) {\n  return x * x\n}\nsq(3)", 2, 1),
+            // Called before it is defined
+            ("sq(3)\nfn sq(x) {\n  return x * x\n}", 5, 1),
+            // Not a literal or variable argument, or the wrong number of them
+            ("fn sq(x) {\n  return x * x\n}\nsq([3][0])\nsq(1, 2)", 5, 2),
+            // Recursive, calling, or reading a global
+            ("fn f(x) {\n  return f(x)\n}\nf(1)", 5, 1),
+            ("fn f(x) {\n  return len(x)\n}\nf(1)", 5, 1),
+            ("y = 1\nfn f(x) {\n  return x + y\n}\nf(1)", 5, 1),
+            // Redefined, or shadowed in a function
+            ("fn f(x) {\n  return x\n}\nf = 1\nf(1)", 5, 1),
+            ("fn f(x) {\n  return x\n}\nfn g(f) {\n  return f\n}\nf(1)", 5, 1),
+            ("fn f(x) {\n  return x\n}\ng = fn() {\n  let f = 2\n}\nf(1)", 5, 1),
+            ("fn f(x) {\n  return x\n}\nf(1)", 0, 1),
+        ];
+        for (source, max_size, remaining) in cases {
+            let program = inline(&parse(source).unwrap(), max_size);
+            assert_eq!(calls(&program), remaining, "{:?}", source);
+        }
+
+        let source = "10 fn f(x) {\n  return x\n}\n20 f(1)\n30 goto 20";
+        let program = parse_with_dialect(source, Dialect::Classic).unwrap();
+        assert_eq!(inline(&program, 5), program);
+    }
+
+    #[test]
+    fn test_behavior_kept() {
+        let programs = [
+            "fn sq(x) {\n  return x * x\n}\nfn g(n) {\n  total = 0\n  i = 0\n  while i < n {\n    total = total + sq(i)\n    i = i + 1\n  }\n  return total\n}\nprint g(10), sq(1.5)",
+            "struct P { x, y }\nfn norm(p) {\n  return p.x * p.x + p.y * p.y\n}\nfn pair(a, b) {\n  return {\"a\": a, \"b\": [b, a]}\n}\np = P(3, 4)\n[norm(p), pair(\"k\", 2), pair(p, p)[\"b\"][0] == p]",
+            "fn both(a, b) {\n  return a && b || !a\n}\n[both(0, 0), both(1, 0), both(1, \"x\")]",
+        ];
+        for source in programs {
+            let program = parse(source).unwrap();
+            let inlined = inline(&program, 20);
+            assert_ne!(inlined, program);
+            let expected_output = SharedBuffer::new();
+            let expected = Interpreter::with_output(Box::new(expected_output.clone())).run(&program);
+            let output = SharedBuffer::new();
+            let result = Interpreter::with_output(Box::new(output.clone())).run(&inlined);
+            assert_eq!(result, expected, "{:?}", source);
+            assert_eq!(output.contents(), expected_output.contents(), "{:?}", source);
+
+            let options = CompileOptions { inline_size: 20, ..CompileOptions::default() };
+            let compiled = compile_with(&program, &options);
+            assert_ne!(compiled, compile_with(&program, &CompileOptions::default()));
+            let output = SharedBuffer::new();
+            let result = compiled.run(&mut Interpreter::with_output(Box::new(output.clone())));
+            assert_eq!(result, expected, "{:?}", source);
+            assert_eq!(output.contents(), expected_output.contents(), "{:?}", source);
+        }
+    }
+}
@@ -0,0 +1,2248 @@
+use alloc::boxed::Box;
+#[cfg(feature = "std")]
+use alloc::collections::VecDeque;
+use alloc::rc::Rc;
+use alloc::string::{String, ToString};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use alloc::{format, vec};
+use core::cell::{Cell, RefCell};
+use core::fmt;
+#[cfg(not(feature = "std"))]
+use core::fmt::Write;
+use core::sync::atomic::{AtomicBool, Ordering};
+#[cfg(feature = "std")]
+use std::io::{self, Write};
+#[cfg(feature = "std")]
+use std::time::Instant;
+
+use num_bigint::BigInt;
+use num_traits::ToPrimitive;
+
+use crate::ast::{BinaryOp, Expr, ExprKind, FunctionDecl, Program, Stmt, StmtKind, StructDecl, UnaryOp};
+use crate::builtins::{self, HostFunction};
+use crate::collections::{HashMap, HashSet, IndexMap};
+use crate::compiler::Chunk;
+use crate::debug::{DebugAction, DebugHook, StatementEvent};
+#[cfg(feature = "std")]
+use crate::execution::{Execution, Stepping};
+use crate::lexer::Span;
+#[cfg(feature = "std")]
+use crate::modules::FileLoader;
+#[cfg(not(feature = "std"))]
+use crate::modules::MemoryLoader;
+use crate::modules::ModuleLoader;
+use crate::parser;
+use crate::profile::Profile;
+use crate::random::Rng;
+use crate::sandbox::Sandbox;
+use crate::stats::RunStats;
+use crate::register;
+use crate::value::{Bytecode, Function, Instance, Module, Value};
+use crate::vm;
+#[cfg(feature = "std")]
+use crate::vm::VmExecution;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum RuntimeError {
+    UndefinedVariable { name: String, span: Span },
+    TypeMismatch { message: String, span: Span },
+    DivisionByZero { span: Span },
+    IntegerOverflow { span: Span },
+    UndefinedFunction { name: String, span: Span },
+    NotCallable { type_name: &'static str, span: Span },
+    WrongArgumentCount { name: String, expected: usize, found: usize, span: Span },
+    InvalidArgument { message: String, span: Span },
+    IndexOutOfBounds { index: i64, len: usize, span: Span },
+    MissingKey { key: String, span: Span },
+    MissingField { struct_name: String, field: String, span: Span },
+    StackOverflow { depth: usize, span: Span },
+    FuelExhausted { span: Span },
+    Cancelled { span: Span },
+    UndefinedLine { line: i64, span: Span },
+    ReturnWithoutGosub { span: Span },
+    Aborted { span: Span },
+    Io { message: String, span: Span },
+    /// The script called `exit(code)`. Not really an error, but it unwinds
+    /// the script the same way; never carries a stack trace.
+    Exit { code: i32, span: Span },
+    /// A module couldn't be found, read or parsed.
+    ImportFailed { module: String, message: String, span: Span },
+    /// Modules importing each other in a loop, starting and ending with the
+    /// same module.
+    CyclicImport { cycle: Vec<String>, span: Span },
+    /// The sandbox doesn't grant what the script tried to do.
+    NotPermitted { what: String, span: Span },
+    /// An array or string grew past the sandbox's size limit, or the script
+    /// used up the sandbox's memory budget.
+    MemoryLimit { span: Span },
+    /// A call to `assert` found its condition false.
+    AssertionFailed { message: Option<String>, span: Span },
+    /// Raised by a function registered with [`Interpreter::register_fn`].
+    Host { message: String, span: Span },
+    /// The VM ran code that pops more values than it pushed. Loading
+    /// bytecode rejects such code, so only chunks built by hand get this far.
+    InvalidBytecode { message: String, span: Span },
+    /// An error raised inside script function calls, with the calls it
+    /// propagated through.
+    Traced { error: Box<RuntimeError>, trace: Vec<TraceFrame> },
+}
+
+/// A script function call that was in progress when an error was raised.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TraceFrame {
+    pub function: String,
+    /// Where the function was called.
+    pub call_site: Span,
+}
+
+/// How many trace frames [`RuntimeError`]'s `Display` shows at either end of
+/// a long trace.
+pub(crate) const TRACE_DISPLAY_LIMIT: usize = 10;
+
+impl RuntimeError {
+    /// An error for host functions to return. The interpreter fills in the
+    /// span of the failing call.
+    pub fn host(message: impl Into<String>) -> RuntimeError {
+        RuntimeError::Host { message: message.into(), span: Span::default() }
+    }
+
+    pub fn span(&self) -> Span {
+        match self {
+            RuntimeError::UndefinedVariable { span, .. }
+            | RuntimeError::TypeMismatch { span, .. }
+            | RuntimeError::DivisionByZero { span }
+            | RuntimeError::IntegerOverflow { span }
+            | RuntimeError::UndefinedFunction { span, .. }
+            | RuntimeError::NotCallable { span, .. }
+            | RuntimeError::WrongArgumentCount { span, .. }
+            | RuntimeError::InvalidArgument { span, .. }
+            | RuntimeError::IndexOutOfBounds { span, .. }
+            | RuntimeError::MissingKey { span, .. }
+            | RuntimeError::MissingField { span, .. }
+            | RuntimeError::StackOverflow { span, .. }
+            | RuntimeError::FuelExhausted { span }
+            | RuntimeError::Cancelled { span }
+            | RuntimeError::UndefinedLine { span, .. }
+            | RuntimeError::ReturnWithoutGosub { span }
+            | RuntimeError::Aborted { span }
+            | RuntimeError::Io { span, .. }
+            | RuntimeError::Exit { span, .. }
+            | RuntimeError::ImportFailed { span, .. }
+            | RuntimeError::CyclicImport { span, .. }
+            | RuntimeError::NotPermitted { span, .. }
+            | RuntimeError::MemoryLimit { span }
+            | RuntimeError::AssertionFailed { span, .. }
+            | RuntimeError::Host { span, .. }
+            | RuntimeError::InvalidBytecode { span, .. } => *span,
+            RuntimeError::Traced { error, .. } => error.span(),
+        }
+    }
+
+    /// The error without its stack trace.
+    pub fn root(&self) -> &RuntimeError {
+        match self {
+            RuntimeError::Traced { error, .. } => error,
+            error => error,
+        }
+    }
+
+    /// Calls the error propagated through, innermost first. Empty for errors
+    /// raised at the top level.
+    pub fn trace(&self) -> &[TraceFrame] {
+        match self {
+            RuntimeError::Traced { trace, .. } => trace,
+            _ => &[],
+        }
+    }
+
+    /// What went wrong, without the location or the stack trace.
+    pub fn message(&self) -> String {
+        match self {
+            RuntimeError::UndefinedVariable { name, .. } => format!("undefined variable `{}`", name),
+            RuntimeError::TypeMismatch { message, .. } => format!("type mismatch: {}", message),
+            RuntimeError::DivisionByZero { .. } => "division by zero".to_string(),
+            RuntimeError::IntegerOverflow { .. } => "integer overflow".to_string(),
+            RuntimeError::UndefinedFunction { name, .. } => format!("call to undefined function `{}`", name),
+            RuntimeError::NotCallable { type_name, .. } => format!("value of type {} is not callable", type_name),
+            RuntimeError::WrongArgumentCount { name, expected, found, .. } => format!(
+                "`{}` expects {} argument{}, but {} {} given",
+                name,
+                expected,
+                if *expected == 1 { "" } else { "s" },
+                found,
+                if *found == 1 { "was" } else { "were" }
+            ),
+            RuntimeError::InvalidArgument { message, .. } => format!("invalid argument: {}", message),
+            RuntimeError::IndexOutOfBounds { index, len, .. } => {
+                format!("index {} is out of bounds for an array of length {}", index, len)
+            },
+            RuntimeError::MissingKey { key, .. } => format!("map has no key {:?}", key),
+            RuntimeError::MissingField { struct_name, field, .. } => {
+                format!("struct `{}` has no field `{}`", struct_name, field)
+            },
+            RuntimeError::StackOverflow { depth, .. } => {
+                format!("stack overflow in script: more than {} nested calls", depth)
+            },
+            RuntimeError::FuelExhausted { .. } => "script ran out of fuel".to_string(),
+            RuntimeError::Cancelled { .. } => "script was cancelled".to_string(),
+            RuntimeError::UndefinedLine { line, .. } => format!("there is no line {}", line),
+            RuntimeError::ReturnWithoutGosub { .. } => "`return` without a pending `gosub`".to_string(),
+            RuntimeError::Aborted { .. } => "execution aborted by debugger".to_string(),
+            RuntimeError::Io { message, .. } => format!("io error: {}", message),
+            RuntimeError::Exit { code, .. } => format!("script exited with code {}", code),
+            RuntimeError::ImportFailed { module, message, .. } => format!("cannot import `{}`: {}", module, message),
+            RuntimeError::CyclicImport { cycle, .. } => format!("cyclic import: {}", cycle.join(" -> ")),
+            RuntimeError::NotPermitted { what, .. } => format!("{} is not permitted in this sandbox", what),
+            RuntimeError::MemoryLimit { .. } => "script exceeds the sandbox's memory limits".to_string(),
+            RuntimeError::AssertionFailed { message: None, .. } => "assertion failed".to_string(),
+            RuntimeError::AssertionFailed { message: Some(message), .. } => format!("assertion failed: {}", message),
+            RuntimeError::Host { message, .. } => message.clone(),
+            RuntimeError::InvalidBytecode { message, .. } => format!("invalid bytecode: {}", message),
+            RuntimeError::Traced { error, .. } => error.message(),
+        }
+    }
+
+    /// The error's [stable code](crate::codes), e.g. `E0303`.
+    pub fn code(&self) -> &'static str {
+        match self {
+            RuntimeError::UndefinedVariable { .. } => "E0301",
+            RuntimeError::TypeMismatch { .. } => "E0302",
+            RuntimeError::DivisionByZero { .. } => "E0303",
+            RuntimeError::IntegerOverflow { .. } => "E0304",
+            RuntimeError::UndefinedFunction { .. } => "E0305",
+            RuntimeError::NotCallable { .. } => "E0306",
+            RuntimeError::WrongArgumentCount { .. } => "E0307",
+            RuntimeError::InvalidArgument { .. } => "E0308",
+            RuntimeError::IndexOutOfBounds { .. } => "E0309",
+            RuntimeError::MissingKey { .. } => "E0310",
+            RuntimeError::MissingField { .. } => "E0311",
+            RuntimeError::StackOverflow { .. } => "E0312",
+            RuntimeError::FuelExhausted { .. } => "E0313",
+            RuntimeError::Cancelled { .. } => "E0314",
+            RuntimeError::UndefinedLine { .. } => "E0315",
+            RuntimeError::ReturnWithoutGosub { .. } => "E0316",
+            RuntimeError::Aborted { .. } => "E0317",
+            RuntimeError::Io { .. } => "E0318",
+            RuntimeError::Exit { .. } => "E0319",
+            RuntimeError::ImportFailed { .. } => "E0320",
+            RuntimeError::CyclicImport { .. } => "E0321",
+            RuntimeError::NotPermitted { .. } => "E0322",
+            RuntimeError::MemoryLimit { .. } => "E0323",
+            RuntimeError::AssertionFailed { .. } => "E0324",
+            RuntimeError::Host { .. } => "E0325",
+            RuntimeError::InvalidBytecode { .. } => "E0326",
+            RuntimeError::Traced { error, .. } => error.code(),
+        }
+    }
+
+    /// Records that the error propagated out of a call to `function`.
+    pub(crate) fn traced(self, function: &str, call_site: Span) -> RuntimeError {
+        let frame = TraceFrame { function: function.to_string(), call_site };
+        match self {
+            RuntimeError::Exit { .. } => self,
+            RuntimeError::Traced { error, mut trace } => {
+                trace.push(frame);
+                RuntimeError::Traced { error, trace }
+            },
+            error => RuntimeError::Traced { error: Box::new(error), trace: vec![frame] },
+        }
+    }
+
+    fn span_mut(&mut self) -> &mut Span {
+        match self {
+            RuntimeError::UndefinedVariable { span, .. }
+            | RuntimeError::TypeMismatch { span, .. }
+            | RuntimeError::DivisionByZero { span }
+            | RuntimeError::IntegerOverflow { span }
+            | RuntimeError::UndefinedFunction { span, .. }
+            | RuntimeError::NotCallable { span, .. }
+            | RuntimeError::WrongArgumentCount { span, .. }
+            | RuntimeError::InvalidArgument { span, .. }
+            | RuntimeError::IndexOutOfBounds { span, .. }
+            | RuntimeError::MissingKey { span, .. }
+            | RuntimeError::MissingField { span, .. }
+            | RuntimeError::StackOverflow { span, .. }
+            | RuntimeError::FuelExhausted { span }
+            | RuntimeError::Cancelled { span }
+            | RuntimeError::UndefinedLine { span, .. }
+            | RuntimeError::ReturnWithoutGosub { span }
+            | RuntimeError::Aborted { span }
+            | RuntimeError::Io { span, .. }
+            | RuntimeError::Exit { span, .. }
+            | RuntimeError::ImportFailed { span, .. }
+            | RuntimeError::CyclicImport { span, .. }
+            | RuntimeError::NotPermitted { span, .. }
+            | RuntimeError::MemoryLimit { span }
+            | RuntimeError::AssertionFailed { span, .. }
+            | RuntimeError::Host { span, .. }
+            | RuntimeError::InvalidBytecode { span, .. } => span,
+            RuntimeError::Traced { error, .. } => error.span_mut(),
+        }
+    }
+
+    /// Gives errors created without a location the span `span`.
+    pub(crate) fn or_span(mut self, span: Span) -> RuntimeError {
+        if self.span() == Span::default() {
+            *self.span_mut() = span;
+        }
+        self
+    }
+}
+
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let RuntimeError::Traced { error, trace } = self {
+            write!(f, "{}\nstack trace:", error)?;
+            let elided = trace.len().saturating_sub(2 * TRACE_DISPLAY_LIMIT);
+            for (i, frame) in trace.iter().enumerate() {
+                if elided > 0 && i == TRACE_DISPLAY_LIMIT {
+                    write!(f, "\n  ... {} more calls", elided)?;
+                }
+                if elided > 0 && (TRACE_DISPLAY_LIMIT..TRACE_DISPLAY_LIMIT + elided).contains(&i) {
+                    continue;
+                }
+                let span = frame.call_site;
+                write!(f, "\n  in `{}` called at {}:{}", frame.function, span.line, span.column)?;
+            }
+            return Ok(());
+        }
+        let span = self.span();
+        write!(f, "{}:{}: {}", span.line, span.column, self.message())
+    }
+}
+
+impl core::error::Error for RuntimeError {}
+
+/// An output sink that can be handed to the interpreter while the host keeps
+/// a handle to read back what the script printed.
+#[derive(Debug, Clone, Default)]
+pub struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+impl SharedBuffer {
+    pub fn new() -> SharedBuffer {
+        SharedBuffer::default()
+    }
+
+    pub fn contents(&self) -> String {
+        String::from_utf8_lossy(&self.0.borrow()).into_owned()
+    }
+}
+
+#[cfg(feature = "std")]
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(not(feature = "std"))]
+impl Write for SharedBuffer {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.0.borrow_mut().extend_from_slice(s.as_bytes());
+        Ok(())
+    }
+}
+
+/// Lets a host stop a running script from another thread. Clones share the
+/// same flag; once cancelled, the script stops at its next statement or loop
+/// iteration with [`RuntimeError::Cancelled`].
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    pub fn new() -> CancellationToken {
+        CancellationToken::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Where `input` reads its lines from.
+#[cfg(feature = "std")]
+pub trait InputSource {
+    /// Reads the next line without its line terminator, or `None` once the
+    /// input is exhausted.
+    fn read_line(&mut self) -> io::Result<Option<String>>;
+}
+
+/// Reads `input` lines from the process's standard input.
+#[cfg(feature = "std")]
+#[derive(Debug, Default)]
+pub struct StdinSource;
+
+#[cfg(feature = "std")]
+impl InputSource for StdinSource {
+    fn read_line(&mut self) -> io::Result<Option<String>> {
+        let mut line = String::new();
+        if io::stdin().read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let trimmed = line.trim_end_matches(['\n', '\r']).len();
+        line.truncate(trimmed);
+        Ok(Some(line))
+    }
+}
+
+/// Feeds `input` a fixed list of lines, e.g. in tests.
+#[cfg(feature = "std")]
+#[derive(Debug, Default)]
+pub struct CannedInput {
+    lines: VecDeque<String>,
+}
+
+#[cfg(feature = "std")]
+impl CannedInput {
+    pub fn new<I, S>(lines: I) -> CannedInput
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        CannedInput { lines: lines.into_iter().map(Into::into).collect() }
+    }
+}
+
+#[cfg(feature = "std")]
+impl InputSource for CannedInput {
+    fn read_line(&mut self) -> io::Result<Option<String>> {
+        Ok(self.lines.pop_front())
+    }
+}
+
+/// Variables of one function call, linked to those of the call the function
+/// was defined in.
+#[derive(Debug, Default)]
+pub struct Environment {
+    pub(crate) vars: HashMap<String, Value>,
+    pub(crate) parent: Option<Rc<RefCell<Environment>>>,
+    /// Set on a module's namespace: the module's name.
+    pub(crate) module: Option<String>,
+}
+
+impl Environment {
+    fn get(&self, name: &str) -> Option<Value> {
+        match self.vars.get(name) {
+            Some(value) => Some(value.clone()),
+            None => self.parent.as_ref().and_then(|parent| parent.borrow().get(name)),
+        }
+    }
+
+    /// Overwrites `name` in the nearest environment that defines it, handing
+    /// the value back if none does.
+    fn set_existing(&mut self, name: &str, value: Value) -> Result<(), Value> {
+        if let Some(slot) = self.vars.get_mut(name) {
+            *slot = value;
+            return Ok(());
+        }
+        match &self.parent {
+            Some(parent) => parent.borrow_mut().set_existing(name, value),
+            None => Err(value),
+        }
+    }
+}
+
+/// One active call of a script function.
+pub(crate) struct Frame {
+    pub(crate) locals: Rc<RefCell<Environment>>,
+    /// Names declared `global` in this call.
+    pub(crate) globals: HashSet<String>,
+    /// The function called and where, or the module being imported.
+    pub(crate) call: TraceFrame,
+}
+
+/// How execution continues after a statement.
+enum Flow {
+    /// On to the next statement. Holds the value of expression statements.
+    Next(Value),
+    Return(Value),
+    Break,
+    Continue,
+    Goto { line: i64, span: Span },
+    Gosub { line: i64, span: Span },
+    SubReturn { span: Span },
+}
+
+/// How operators treat operands of different types.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TypeMode {
+    /// Mixing types fails with [`RuntimeError::TypeMismatch`].
+    #[default]
+    Strict,
+    /// Operands are converted: `+` with a string on either side concatenates
+    /// (`"1" + 1 == "11"`), and numeric strings, bools and `nil` count as
+    /// numbers elsewhere.
+    Coercing,
+}
+
+/// What happens when int arithmetic overflows `i64`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OverflowMode {
+    /// Fail with [`RuntimeError::IntegerOverflow`].
+    #[default]
+    Error,
+    /// Wrap around in two's complement.
+    Wrap,
+    /// Clamp to the smallest or largest `i64`.
+    Saturate,
+    /// Switch to arbitrary-precision ints.
+    Promote,
+}
+
+/// How arrays treat negative indexes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum NegativeIndexMode {
+    /// Negative indexes fail with [`RuntimeError::IndexOutOfBounds`].
+    #[default]
+    Error,
+    /// `-1` is the last element, `-2` the one before it, and so on.
+    FromEnd,
+}
+
+/// What `/` does with two ints.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DivisionMode {
+    /// Integer division, truncating toward zero: `7 / 2 == 3`.
+    #[default]
+    Integer,
+    /// BASIC-style: `/` always divides exactly, so `7 / 2 == 3.5`.
+    Float,
+}
+
+/// The options deciding what arithmetic operators produce.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Arithmetic {
+    pub overflow: OverflowMode,
+    pub division: DivisionMode,
+}
+
+/// Default limit on nested script function calls. Each script call costs
+/// several host stack frames, so this stays well below what a 2 MiB thread
+/// stack can hold.
+pub const DEFAULT_MAX_CALL_DEPTH: usize = 100;
+
+pub struct Interpreter {
+    pub(crate) globals: HashMap<String, Value>,
+    pub(crate) frames: Vec<Frame>,
+    /// Classic dialect: index of the statement each pending `gosub` returns to.
+    pub(crate) return_stack: Vec<usize>,
+    max_call_depth: usize,
+    type_mode: TypeMode,
+    pub(crate) arithmetic: Arithmetic,
+    negative_indexes: NegativeIndexMode,
+    fuel: Option<u64>,
+    /// Bytes left of the sandbox's [memory budget](Sandbox::max_memory).
+    /// Values are built behind shared references, hence the cell.
+    memory: Cell<Option<usize>>,
+    cancellation: Option<CancellationToken>,
+    debug_hook: Option<Box<dyn DebugHook>>,
+    breakpoints: HashSet<usize>,
+    breakpoint_handler: Option<Box<dyn DebugHook>>,
+    /// Source line of the previous statement, so a breakpoint only fires once
+    /// when several statements share its line.
+    last_line: usize,
+    pub(crate) sandbox: Sandbox,
+    /// Counts the bytecode VMs add to while profiling is on.
+    pub(crate) profile: Option<Profile>,
+    stats: Option<RunStats>,
+    /// Set while the interpreter is driven by an [`Execution`].
+    #[cfg(feature = "std")]
+    pub(crate) stepping: Option<Stepping>,
+    module_loader: Box<dyn ModuleLoader>,
+    /// Modules imported so far, so each one only runs once.
+    modules: HashMap<String, Rc<Module>>,
+    /// Modules whose top level is running, outermost first.
+    importing: Vec<String>,
+    pub(crate) rng: Rng,
+    /// When the interpreter was created; `clock_ms` counts from here.
+    #[cfg(feature = "std")]
+    pub(crate) started: Instant,
+    /// What `args()` returns.
+    pub(crate) args: Vec<String>,
+    /// An [`io::Write`], or a [`fmt::Write`] without `std`.
+    pub(crate) output: Box<dyn Write>,
+    #[cfg(feature = "std")]
+    pub(crate) input: Box<dyn InputSource>,
+}
+
+#[cfg(feature = "std")]
+impl Default for Interpreter {
+    fn default() -> Self {
+        Interpreter::new()
+    }
+}
+
+impl Interpreter {
+    /// Creates an interpreter that prints to stdout.
+    #[cfg(feature = "std")]
+    pub fn new() -> Interpreter {
+        Interpreter::with_output(Box::new(io::stdout()))
+    }
+
+    /// Creates an interpreter that prints to `output`. Without `std` it is
+    /// the only way to make one; `import` then finds no modules until
+    /// [`set_module_loader`](Interpreter::set_module_loader) is called, and
+    /// the random numbers start from the same seed every time.
+    pub fn with_output(output: Box<dyn Write>) -> Interpreter {
+        let mut globals = HashMap::default();
+        builtins::register(&mut globals);
+        Interpreter {
+            globals,
+            frames: Vec::new(),
+            return_stack: Vec::new(),
+            max_call_depth: DEFAULT_MAX_CALL_DEPTH,
+            type_mode: TypeMode::Strict,
+            arithmetic: Arithmetic::default(),
+            negative_indexes: NegativeIndexMode::Error,
+            fuel: None,
+            memory: Cell::new(None),
+            cancellation: None,
+            debug_hook: None,
+            breakpoints: HashSet::default(),
+            breakpoint_handler: None,
+            last_line: 0,
+            sandbox: Sandbox::default(),
+            profile: None,
+            stats: None,
+            #[cfg(feature = "std")]
+            stepping: None,
+            #[cfg(feature = "std")]
+            module_loader: Box::new(FileLoader::new(".")),
+            #[cfg(not(feature = "std"))]
+            module_loader: Box::new(MemoryLoader::new()),
+            modules: HashMap::default(),
+            importing: Vec::new(),
+            #[cfg(feature = "std")]
+            rng: Rng::from_time(),
+            #[cfg(not(feature = "std"))]
+            rng: Rng::new(0),
+            #[cfg(feature = "std")]
+            started: Instant::now(),
+            args: Vec::new(),
+            output,
+            #[cfg(feature = "std")]
+            input: Box::new(StdinSource),
+        }
+    }
+
+    /// Replaces the destination of `print`.
+    pub fn set_output(&mut self, output: Box<dyn Write>) {
+        self.output = output;
+    }
+
+    /// Replaces the source `input` reads from.
+    #[cfg(feature = "std")]
+    pub fn set_input(&mut self, input: Box<dyn InputSource>) {
+        self.input = input;
+    }
+
+    /// Sets the command-line arguments scripts see through `args()`.
+    pub fn set_args(&mut self, args: Vec<String>) {
+        self.args = args;
+    }
+
+    /// Replaces where `import` finds modules. By default `import name` reads
+    /// `name.tb`, or else `name.bas`, from the working directory.
+    pub fn set_module_loader(&mut self, loader: Box<dyn ModuleLoader>) {
+        self.module_loader = loader;
+    }
+
+    /// Limits how deeply script functions may call each other. Exceeding the
+    /// limit fails with [`RuntimeError::StackOverflow`].
+    pub fn set_max_call_depth(&mut self, depth: usize) {
+        self.max_call_depth = depth;
+    }
+
+    /// Limits how many more steps scripts may take, or lifts the limit with
+    /// `None`. Every executed statement and every loop iteration costs one
+    /// unit of fuel; running out fails with [`RuntimeError::FuelExhausted`].
+    /// The budget is shared by all subsequent runs until it is set again.
+    pub fn set_fuel(&mut self, fuel: Option<u64>) {
+        self.fuel = fuel;
+    }
+
+    /// Parses `source` under the sandbox's
+    /// [nesting limit](Sandbox::max_nesting), as imports are parsed.
+    pub fn parse(&self, source: &str) -> Result<Program, parser::ParseError> {
+        parser::parse_with(source, parser::ParseOptions { max_nesting: self.sandbox.max_nesting, ..parser::ParseOptions::default() })
+    }
+
+    /// Restricts what scripts may do from now on. Also sets the fuel and
+    /// memory budgets and the call depth limit to the sandbox's.
+    pub fn set_sandbox(&mut self, sandbox: Sandbox) {
+        self.fuel = sandbox.fuel;
+        self.memory.set(sandbox.max_memory);
+        self.max_call_depth = sandbox.max_call_depth;
+        self.sandbox = sandbox;
+    }
+
+    /// Checks that `index` is an int within `0..len`, counting negative
+    /// indexes from the end if so configured, and returns it as a position.
+    pub(crate) fn array_index(&self, index: &Value, len: usize, span: Span) -> Result<usize, RuntimeError> {
+        let Value::Int(index) = *index else {
+            return Err(RuntimeError::TypeMismatch {
+                message: format!("array index must be an int, found {}", index.type_name()),
+                span,
+            });
+        };
+        let position = match self.negative_indexes {
+            NegativeIndexMode::FromEnd if index < 0 => (len as i64).checked_add(index),
+            _ => Some(index),
+        };
+        position
+            .and_then(|i| usize::try_from(i).ok())
+            .filter(|i| *i < len)
+            .ok_or(RuntimeError::IndexOutOfBounds { index, len, span })
+    }
+
+    /// Fails with [`RuntimeError::MemoryLimit`] if `value`, which the script
+    /// just built, is larger than the sandbox allows or doesn't fit in what
+    /// is left of its memory budget, which it is charged to.
+    pub(crate) fn check_size(&self, value: &Value, span: Span) -> Result<(), RuntimeError> {
+        self.check_len(value, span)?;
+        self.charge(allocated_size(value), span)
+    }
+
+    /// Like [`check_size`](Interpreter::check_size) for `container` after
+    /// it grew by an element taking `added` bytes of its own.
+    pub(crate) fn check_growth(&self, container: &Value, added: usize, span: Span) -> Result<(), RuntimeError> {
+        self.check_len(container, span)?;
+        self.charge(added, span)
+    }
+
+    fn charge(&self, bytes: usize, span: Span) -> Result<(), RuntimeError> {
+        match self.memory.get() {
+            Some(left) if bytes > left => Err(RuntimeError::MemoryLimit { span }),
+            Some(left) => {
+                self.memory.set(Some(left - bytes));
+                Ok(())
+            },
+            None => Ok(()),
+        }
+    }
+
+    fn check_len(&self, value: &Value, span: Span) -> Result<(), RuntimeError> {
+        let too_large = match value {
+            Value::Str(s) => self.sandbox.max_string_len.is_some_and(|max| s.len() > max),
+            // Big ints count against the string limit by their size in bytes
+            Value::BigInt(n) => self.sandbox.max_string_len.is_some_and(|max| n.bits() / 8 > max as u64),
+            Value::Array(values) => self.sandbox.max_array_len.is_some_and(|max| values.borrow().len() > max),
+            Value::Map(entries) => self.sandbox.max_array_len.is_some_and(|max| entries.borrow().len() > max),
+            _ => false,
+        };
+        if too_large {
+            Err(RuntimeError::MemoryLimit { span })
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Fuel left, or `None` if execution is unlimited.
+    pub fn fuel(&self) -> Option<u64> {
+        self.fuel
+    }
+
+    /// Bytes left of the sandbox's memory budget, or `None` if it sets none.
+    pub fn memory_left(&self) -> Option<usize> {
+        self.memory.get()
+    }
+
+    /// Makes running scripts stop once `token` is cancelled.
+    pub fn set_cancellation_token(&mut self, token: CancellationToken) {
+        self.cancellation = Some(token);
+    }
+
+    pub(crate) fn is_cancelled(&self) -> bool {
+        self.cancellation.as_ref().is_some_and(CancellationToken::is_cancelled)
+    }
+
+    pub fn set_type_mode(&mut self, mode: TypeMode) {
+        self.type_mode = mode;
+    }
+
+    pub fn set_overflow_mode(&mut self, mode: OverflowMode) {
+        self.arithmetic.overflow = mode;
+    }
+
+    pub fn set_division_mode(&mut self, mode: DivisionMode) {
+        self.arithmetic.division = mode;
+    }
+
+    /// Applies to indexing and to the array builtins taking an index.
+    pub fn set_negative_index_mode(&mut self, mode: NegativeIndexMode) {
+        self.negative_indexes = mode;
+    }
+
+    /// Seeds the generator behind `rnd` and `random` so runs are
+    /// reproducible. Scripts can do the same with `seed(n)`.
+    pub fn set_seed(&mut self, seed: u64) {
+        self.rng = Rng::new(seed);
+    }
+
+    /// Calls `hook` before every statement, replacing any previous hook.
+    pub fn set_debug_hook(&mut self, hook: Box<dyn DebugHook>) {
+        self.debug_hook = Some(hook);
+    }
+
+    pub fn clear_debug_hook(&mut self) {
+        self.debug_hook = None;
+    }
+
+    /// Pauses before the first statement on source `line` each time
+    /// execution reaches it, handing control to the breakpoint handler.
+    pub fn set_breakpoint(&mut self, line: usize) {
+        self.breakpoints.insert(line);
+    }
+
+    pub fn clear_breakpoint(&mut self, line: usize) {
+        self.breakpoints.remove(&line);
+    }
+
+    pub fn clear_breakpoints(&mut self) {
+        self.breakpoints.clear();
+    }
+
+    pub fn breakpoints(&self) -> Vec<usize> {
+        let mut lines: Vec<usize> = self.breakpoints.iter().copied().collect();
+        lines.sort_unstable();
+        lines
+    }
+
+    /// Sets the callback run when a breakpoint is hit. It can inspect the
+    /// paused state and either resume or abort the script. Without a handler
+    /// breakpoints are ignored.
+    pub fn set_breakpoint_handler(&mut self, handler: Box<dyn DebugHook>) {
+        self.breakpoint_handler = Some(handler);
+    }
+
+    pub fn get_var(&self, name: &str) -> Option<&Value> {
+        self.globals.get(name)
+    }
+
+    /// Exposes a Rust closure to scripts as the global function `name`,
+    /// replacing any variable or builtin of that name. Errors returned by
+    /// `func` are reported at the call site; [`RuntimeError::host`] builds
+    /// one from a message.
+    pub fn register_fn<F>(&mut self, name: &str, func: F)
+    where
+        F: FnMut(&[Value]) -> Result<Value, RuntimeError> + 'static,
+    {
+        let host = HostFunction::new(name, func);
+        self.globals.insert(name.to_string(), Value::Host(Rc::new(host)));
+    }
+
+    /// Looks `name` up the way the running statement would: locals, then
+    /// captured variables, then globals.
+    pub fn variable(&self, name: &str) -> Option<Value> {
+        self.lookup(name)
+    }
+
+    /// Overwrites the variable `name` resolves to from the running statement,
+    /// or defines it in the current scope if it doesn't exist yet. Meant for
+    /// debuggers and REPLs poking at a paused script.
+    pub fn set_variable(&mut self, name: &str, value: Value) {
+        let value = match self.frames.last().filter(|frame| !frame.globals.contains(name)) {
+            Some(frame) => match frame.locals.borrow_mut().set_existing(name, value) {
+                Ok(()) => return,
+                Err(value) => value,
+            },
+            None => value,
+        };
+        match self.globals.get_mut(name) {
+            Some(slot) => *slot = value,
+            None => self.assign(name, value),
+        }
+    }
+
+    pub(crate) fn call_depth(&self) -> usize {
+        self.frames.len()
+    }
+
+    /// The module whose code is running, `None` for the main program. Code
+    /// belongs to the module whose namespace its variables are looked up
+    /// in last, before the globals.
+    pub(crate) fn current_module(&self) -> Option<String> {
+        let mut env = self.frames.last()?.locals.clone();
+        loop {
+            let parent = env.borrow().parent.clone();
+            match parent {
+                Some(parent) => env = parent,
+                None => return env.borrow().module.clone(),
+            }
+        }
+    }
+
+    /// The calls in progress, innermost first, as in the trace of a
+    /// [`RuntimeError::Traced`].
+    pub fn backtrace(&self) -> Vec<TraceFrame> {
+        self.frames.iter().rev().map(|frame| frame.call.clone()).collect()
+    }
+
+    /// Every variable the current statement can see except builtins, with
+    /// locals shadowing captured variables and globals. Sorted by name.
+    pub fn variables(&self) -> Vec<(String, Value)> {
+        let mut variables: HashMap<String, Value> = self
+            .globals
+            .iter()
+            .filter(|(_, value)| !matches!(value, Value::Builtin(_) | Value::Host(_)))
+            .map(|(name, value)| (name.clone(), value.clone()))
+            .collect();
+        let mut scopes = Vec::new();
+        let mut scope = self.frames.last().map(|frame| frame.locals.clone());
+        while let Some(env) = scope {
+            scope = env.borrow().parent.clone();
+            scopes.push(env);
+        }
+        for env in scopes.iter().rev() {
+            for (name, value) in &env.borrow().vars {
+                variables.insert(name.clone(), value.clone());
+            }
+        }
+        let mut variables: Vec<_> = variables.into_iter().collect();
+        variables.sort_by(|a, b| a.0.cmp(&b.0));
+        variables
+    }
+
+    /// Starts `program` without running any of it; see [`Execution`].
+    #[cfg(feature = "std")]
+    pub fn start<'a>(&'a mut self, program: &'a Program) -> Execution<'a> {
+        Execution::new(self, program)
+    }
+
+    /// Starts counting the instructions the bytecode VMs run, from zero, or
+    /// stops counting and drops the counts. Counts add up across runs.
+    pub fn set_profiling(&mut self, enabled: bool) {
+        self.profile = enabled.then(Profile::default);
+    }
+
+    /// What the bytecode VMs ran since profiling was switched on, or `None`
+    /// if it is off.
+    pub fn profile(&self) -> Option<&Profile> {
+        self.profile.as_ref()
+    }
+
+    /// Starts counting statements and calls, from zero, or stops counting
+    /// and drops the counts. Counts add up across runs.
+    pub fn set_stats(&mut self, enabled: bool) {
+        self.stats = enabled.then(RunStats::default);
+    }
+
+    /// What ran since collecting stats was switched on, or `None` if it is
+    /// off.
+    pub fn stats(&self) -> Option<&RunStats> {
+        self.stats.as_ref()
+    }
+
+    /// Starts `chunk` on the bytecode VM without running any of it; see
+    /// [`VmExecution`].
+    #[cfg(feature = "std")]
+    pub fn start_compiled(&mut self, chunk: &Chunk) -> VmExecution<'_> {
+        VmExecution::new(self, chunk.clone())
+    }
+
+    /// Runs a program compiled with [`compile`](crate::compiler::compile) on
+    /// the bytecode VM. It behaves like [`run`](Interpreter::run) on the
+    /// program's AST, using this interpreter's variables and settings.
+    pub fn run_compiled(&mut self, chunk: &Chunk) -> Result<Value, RuntimeError> {
+        vm::run(self, chunk)
+    }
+
+    /// Runs `program`, returning the value of its last statement if that
+    /// statement is an expression and `Nil` otherwise.
+    ///
+    /// In classic programs `return` resumes at the line after the one whose
+    /// statement ran the `gosub`, even if the `gosub` was nested in a block.
+    pub fn run(&mut self, program: &Program) -> Result<Value, RuntimeError> {
+        let statements = &program.statements;
+        let target = |line: i64, span: Span| {
+            program.lines.get(&line).copied().ok_or(RuntimeError::UndefinedLine { line, span })
+        };
+        self.reset();
+        let mut last = Value::Nil;
+        let mut pc = 0;
+        while let Some(statement) = statements.get(pc) {
+            match self.execute(statement)? {
+                Flow::Next(value) => {
+                    last = value;
+                    pc += 1;
+                },
+                Flow::Goto { line, span } => pc = target(line, span)?,
+                Flow::Gosub { line, span } => {
+                    self.gosub(pc + 1, span)?;
+                    pc = target(line, span)?;
+                },
+                Flow::SubReturn { span } => pc = self.sub_return(span)?,
+                Flow::Return(value) => return Ok(value),
+                Flow::Break | Flow::Continue => pc += 1,
+            }
+        }
+        Ok(last)
+    }
+
+    /// Forgets the state of an earlier run.
+    pub(crate) fn reset(&mut self) {
+        self.frames.clear();
+        self.return_stack.clear();
+        self.last_line = 0;
+    }
+
+    /// Classic dialect: records where the `return` of a `gosub` continues.
+    pub(crate) fn gosub(&mut self, resume: usize, span: Span) -> Result<(), RuntimeError> {
+        if self.return_stack.len() >= self.max_call_depth {
+            return Err(RuntimeError::StackOverflow { depth: self.max_call_depth, span });
+        }
+        self.return_stack.push(resume);
+        Ok(())
+    }
+
+    /// Classic dialect: where the pending `gosub` continues.
+    pub(crate) fn sub_return(&mut self, span: Span) -> Result<usize, RuntimeError> {
+        self.return_stack.pop().ok_or(RuntimeError::ReturnWithoutGosub { span })
+    }
+
+    fn execute_block(&mut self, statements: &[Stmt]) -> Result<Flow, RuntimeError> {
+        let mut last = Value::Nil;
+        for statement in statements {
+            match self.execute(statement)? {
+                Flow::Next(value) => last = value,
+                flow => return Ok(flow),
+            }
+        }
+        Ok(Flow::Next(last))
+    }
+
+    // `execute` and `evaluate` recurse for every nested statement, expression
+    // and script call, so anything bulky lives in helpers to keep their stack
+    // frames small.
+    fn execute(&mut self, statement: &Stmt) -> Result<Flow, RuntimeError> {
+        self.enter_statement(statement.span)?;
+        match &statement.kind {
+            StmtKind::Assign { name, value, declaration, .. } => {
+                let value = self.evaluate(value)?;
+                if *declaration {
+                    self.declare(name);
+                }
+                self.assign(name, value);
+            },
+            StmtKind::SetIndex { target, index, value } => self.set_index(target, index, value)?,
+            StmtKind::SetField { target, field, value } => self.set_field(target, field, value)?,
+            StmtKind::Struct(decl) => self.assign(&decl.name, Value::StructType(decl.clone())),
+            StmtKind::If { condition, then_branch, else_branch } => {
+                return self.execute_if(condition, then_branch, else_branch.as_deref())
+            },
+            StmtKind::While { condition, body } => return self.execute_while(condition, body, statement.span),
+            StmtKind::Goto(line) => return Ok(Flow::Goto { line: *line, span: statement.span }),
+            StmtKind::Gosub(line) => return Ok(Flow::Gosub { line: *line, span: statement.span }),
+            StmtKind::SubReturn => return Ok(Flow::SubReturn { span: statement.span }),
+            StmtKind::Break => return Ok(Flow::Break),
+            StmtKind::Continue => return Ok(Flow::Continue),
+            StmtKind::Print(args) => self.print(args, statement.span)?,
+            StmtKind::Import(name) => self.import(name, statement.span)?,
+            StmtKind::Global(names) => {
+                for name in names {
+                    self.declare_global(name);
+                }
+            },
+            StmtKind::Function(decl) => {
+                let function = self.make_function(decl);
+                self.assign(&decl.name, function);
+            },
+            StmtKind::Return(value) => {
+                let value = match value {
+                    Some(value) => self.evaluate(value)?,
+                    None => Value::Nil,
+                };
+                return Ok(Flow::Return(value));
+            },
+            StmtKind::Expr(expr) => return Ok(Flow::Next(self.evaluate(expr)?)),
+        }
+        Ok(Flow::Next(Value::Nil))
+    }
+
+    /// Runs before every statement.
+    pub(crate) fn enter_statement(&mut self, span: Span) -> Result<(), RuntimeError> {
+        self.checkpoint(span)?;
+        if let Some(stats) = &mut self.stats {
+            stats.record_statement();
+        }
+        if self.debug_hook.is_some() || self.breakpoint_handler.is_some() {
+            self.run_debug_hooks(span)?;
+        }
+        Ok(())
+    }
+
+    /// Runs after every loop iteration.
+    pub(crate) fn end_iteration(&mut self, span: Span) -> Result<(), RuntimeError> {
+        self.checkpoint(span)?;
+        // Re-arm breakpoints inside the body for the next iteration
+        self.last_line = 0;
+        Ok(())
+    }
+
+    fn run_debug_hooks(&mut self, span: Span) -> Result<(), RuntimeError> {
+        let new_line = core::mem::replace(&mut self.last_line, span.line) != span.line;
+        if let Some(mut hook) = self.debug_hook.take() {
+            let action = hook.on_statement(&mut StatementEvent { span, interpreter: self });
+            // The hook may have installed a replacement for itself
+            self.debug_hook.get_or_insert(hook);
+            if action == DebugAction::Abort {
+                return Err(RuntimeError::Aborted { span });
+            }
+        }
+        if new_line && self.breakpoints.contains(&span.line) {
+            if let Some(mut handler) = self.breakpoint_handler.take() {
+                let action = handler.on_statement(&mut StatementEvent { span, interpreter: self });
+                self.breakpoint_handler.get_or_insert(handler);
+                if action == DebugAction::Abort {
+                    return Err(RuntimeError::Aborted { span });
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn set_index(&mut self, target: &Expr, index: &Expr, value: &Expr) -> Result<(), RuntimeError> {
+        let target_value = self.evaluate(target)?;
+        let index_value = self.evaluate(index)?;
+        let value = self.evaluate(value)?;
+        self.store_index(target_value, index_value, value, target.span, index.span)
+    }
+
+    pub(crate) fn store_index(
+        &self,
+        target: Value,
+        index: Value,
+        value: Value,
+        target_span: Span,
+        index_span: Span,
+    ) -> Result<(), RuntimeError> {
+        match &target {
+            Value::Array(values) => {
+                let mut values = values.borrow_mut();
+                let i = self.array_index(&index, values.len(), index_span)?;
+                values[i] = value;
+            },
+            Value::Map(entries) => {
+                let key = map_key(index, index_span)?;
+                let added = entry_size(&key);
+                entries.borrow_mut().insert(key, value);
+                self.check_growth(&target, added, target_span)?;
+            },
+            _ => return Err(not_indexable(&target, target_span)),
+        }
+        Ok(())
+    }
+
+    fn set_field(&mut self, target: &Expr, field: &str, value: &Expr) -> Result<(), RuntimeError> {
+        let target_value = self.evaluate(target)?;
+        let value = self.evaluate(value)?;
+        store_field(&target_value, field, value, target.span)
+    }
+
+    fn execute_if(&mut self, condition: &Expr, then_branch: &[Stmt], else_branch: Option<&[Stmt]>) -> Result<Flow, RuntimeError> {
+        let flow = if self.evaluate(condition)?.is_truthy() {
+            self.execute_block(then_branch)?
+        } else if let Some(else_branch) = else_branch {
+            self.execute_block(else_branch)?
+        } else {
+            return Ok(Flow::Next(Value::Nil));
+        };
+        match flow {
+            Flow::Next(_) => Ok(Flow::Next(Value::Nil)),
+            flow => Ok(flow),
+        }
+    }
+
+    fn execute_while(&mut self, condition: &Expr, body: &[Stmt], span: Span) -> Result<Flow, RuntimeError> {
+        while self.evaluate(condition)?.is_truthy() {
+            match self.execute_block(body)? {
+                Flow::Break => break,
+                Flow::Next(_) | Flow::Continue => {},
+                flow => return Ok(flow),
+            }
+            self.end_iteration(span)?;
+        }
+        Ok(Flow::Next(Value::Nil))
+    }
+
+    fn print(&mut self, args: &[Expr], span: Span) -> Result<(), RuntimeError> {
+        let values = self.evaluate_all(args)?;
+        self.print_values(&values, span)
+    }
+
+    pub(crate) fn print_values(&mut self, values: &[Value], span: Span) -> Result<(), RuntimeError> {
+        let line: Vec<String> = values.iter().map(Value::to_string).collect();
+        writeln!(self.output, "{}", line.join(" ")).map_err(|e| RuntimeError::Io { message: e.to_string(), span })
+    }
+
+    /// Runs before every statement and loop iteration to pause stepped
+    /// scripts, enforce the fuel budget and honor cancellation.
+    fn checkpoint(&mut self, span: Span) -> Result<(), RuntimeError> {
+        // Pausing comes first so a host that cancels a paused script stops it
+        // before its next step
+        #[cfg(feature = "std")]
+        if let Some(stepping) = &mut self.stepping {
+            stepping.take_step();
+        }
+        if self.is_cancelled() {
+            return Err(RuntimeError::Cancelled { span });
+        }
+        match &mut self.fuel {
+            Some(0) => Err(RuntimeError::FuelExhausted { span }),
+            Some(fuel) => {
+                *fuel -= 1;
+                Ok(())
+            },
+            None => Ok(()),
+        }
+    }
+
+    /// Binds the module `name`, running it first unless it was imported
+    /// before.
+    pub(crate) fn import(&mut self, name: &str, span: Span) -> Result<(), RuntimeError> {
+        let module = match self.modules.get(name) {
+            Some(module) => module.clone(),
+            None => self.load_module(name, span)?,
+        };
+        self.assign(name, Value::Module(module));
+        Ok(())
+    }
+
+    /// Runs a module's top level in a frame of its own, whose variables
+    /// become the module's namespace. Functions it defines capture that
+    /// namespace like closures capture the call they are defined in.
+    fn load_module(&mut self, name: &str, span: Span) -> Result<Rc<Module>, RuntimeError> {
+        if let Some(start) = self.importing.iter().position(|module| module == name) {
+            let mut cycle = self.importing[start..].to_vec();
+            cycle.push(name.to_string());
+            return Err(RuntimeError::CyclicImport { cycle, span });
+        }
+        let failed = |message: String| RuntimeError::ImportFailed { module: name.to_string(), message, span };
+        let source = self
+            .module_loader
+            .load(name)
+            .map_err(|error| failed(error.to_string()))?
+            .ok_or_else(|| failed("no such module".to_string()))?;
+        let program = self.parse(&source).map_err(|error| failed(error.to_string()))?;
+        let namespace = Rc::new(RefCell::new(Environment { module: Some(name.to_string()), ..Environment::default() }));
+        let call = TraceFrame { function: format!("module {}", name), call_site: span };
+        self.frames.push(Frame { locals: namespace.clone(), globals: HashSet::default(), call });
+        self.importing.push(name.to_string());
+        let result = self.execute_block(&program.statements);
+        self.importing.pop();
+        self.frames.pop();
+        result.map_err(|error| error.traced(&format!("module {}", name), span))?;
+        let module = Rc::new(Module { name: name.to_string(), namespace });
+        self.modules.insert(name.to_string(), module.clone());
+        Ok(module)
+    }
+
+    fn make_function(&self, decl: &Rc<FunctionDecl>) -> Value {
+        let captured = self.frames.last().map(|frame| frame.locals.clone());
+        Value::Function(Rc::new(Function { decl: decl.clone(), captured, code: None }))
+    }
+
+    /// Like `make_function`, for a function a VM runs.
+    pub(crate) fn make_compiled_function(&self, decl: &Rc<FunctionDecl>, code: Bytecode) -> Value {
+        let captured = self.frames.last().map(|frame| frame.locals.clone());
+        Value::Function(Rc::new(Function { decl: decl.clone(), captured, code: Some(code) }))
+    }
+
+    /// Resolves `name` in the current function's locals and the variables it
+    /// captured, then in the globals.
+    pub(crate) fn lookup(&self, name: &str) -> Option<Value> {
+        if let Some(frame) = self.frames.last().filter(|frame| !frame.globals.contains(name)) {
+            if let Some(value) = frame.locals.borrow().get(name) {
+                return Some(value);
+            }
+        }
+        self.globals.get(name).cloned()
+    }
+
+    /// Assigns to a local when inside a function and to a global otherwise,
+    /// or when the function declared the name `global`.
+    pub(crate) fn assign(&mut self, name: &str, value: Value) {
+        match self.frames.last().filter(|frame| !frame.globals.contains(name)) {
+            Some(frame) => set_var(&mut frame.locals.borrow_mut().vars, name, value),
+            None => set_var(&mut self.globals, name, value),
+        }
+    }
+
+    /// Makes `name` refer to a local for the rest of the current call, even
+    /// if the function declared it `global`.
+    pub(crate) fn declare(&mut self, name: &str) {
+        if let Some(frame) = self.frames.last_mut() {
+            frame.globals.remove(name);
+        }
+    }
+
+    /// Makes `name` refer to the global for the rest of the current call.
+    pub(crate) fn declare_global(&mut self, name: &str) {
+        if let Some(frame) = self.frames.last_mut() {
+            frame.globals.insert(name.to_string());
+        }
+    }
+
+    fn call_function(&mut self, function: Rc<Function>, args: Vec<Value>, span: Span) -> Result<Value, RuntimeError> {
+        match &function.code {
+            Some(Bytecode::Stack(code)) => return vm::call(self, &function, code, args, span),
+            Some(Bytecode::Register(code)) => return register::call(self, &function, code, args, span),
+            None => {},
+        }
+        let decl = &function.decl;
+        self.push_frame(&function, args, span)?;
+        let result = self.execute_block(&decl.body);
+        self.pop_frame();
+        match result.map_err(|error| error.traced(&decl.name, span))? {
+            Flow::Return(value) => Ok(value),
+            // The parser keeps jumps from leaving a function body
+            _ => Ok(Value::Nil),
+        }
+    }
+
+    /// Starts a call of `function`, binding its parameters to `args`.
+    pub(crate) fn push_frame(&mut self, function: &Function, args: Vec<Value>, span: Span) -> Result<(), RuntimeError> {
+        let decl = &function.decl;
+        if args.len() != decl.params.len() {
+            return Err(RuntimeError::WrongArgumentCount {
+                name: decl.name.clone(),
+                expected: decl.params.len(),
+                found: args.len(),
+                span,
+            });
+        }
+        if self.frames.len() >= self.max_call_depth {
+            return Err(RuntimeError::StackOverflow { depth: self.max_call_depth, span });
+        }
+        let vars = decl.params.iter().cloned().zip(args).collect();
+        let locals = Environment { vars, parent: function.captured.clone(), module: None };
+        let call = TraceFrame { function: decl.name.clone(), call_site: span };
+        self.frames.push(Frame { locals: Rc::new(RefCell::new(locals)), globals: HashSet::default(), call });
+        if let Some(stats) = &mut self.stats {
+            stats.record_call(&decl.name);
+        }
+        Ok(())
+    }
+
+    pub(crate) fn pop_frame(&mut self) {
+        self.frames.pop();
+    }
+
+    /// Replaces the running call by a call of `function`, for a tail call.
+    /// The running call is kept if the new one can't start.
+    pub(crate) fn replace_frame(&mut self, function: &Function, args: Vec<Value>, span: Span) -> Result<(), RuntimeError> {
+        let frame = self.frames.pop();
+        let started = self.push_frame(function, args, span);
+        if started.is_err() {
+            self.frames.extend(frame);
+        }
+        started
+    }
+
+    /// Operands are evaluated left to right: binary operands, array items and
+    /// call arguments in source order, a callee before its arguments and an
+    /// indexed value before its index. `&&` and `||` skip their right operand
+    /// when the left one decides the result. Assignments evaluate their
+    /// target's array and index before the value.
+    fn evaluate(&mut self, expr: &Expr) -> Result<Value, RuntimeError> {
+        match &expr.kind {
+            ExprKind::Number(n) => Ok(Value::Int(*n)),
+            ExprKind::Float(n) => Ok(Value::Float(*n)),
+            ExprKind::Str(s) => Ok(Value::from(s.as_str())),
+            ExprKind::Variable(name) => self
+                .lookup(name)
+                .ok_or_else(|| RuntimeError::UndefinedVariable { name: name.clone(), span: expr.span }),
+            ExprKind::Function(decl) => Ok(self.make_function(decl)),
+            ExprKind::Array(items) => {
+                let array = Value::array(self.evaluate_all(items)?);
+                self.check_size(&array, expr.span)?;
+                Ok(array)
+            },
+            ExprKind::Map(entries) => self.evaluate_map(entries, expr.span),
+            ExprKind::Index { target, index } => self.evaluate_index(target, index),
+            ExprKind::Field { target, field } => self.evaluate_field(target, field, expr.span),
+            ExprKind::Unary { op, operand } => self.evaluate_unary(*op, operand, expr.span),
+            ExprKind::Binary { op, left, right } => self.evaluate_binary(*op, left, right, expr.span),
+            ExprKind::Call { callee, args } => self.evaluate_call(callee, args, expr.span),
+        }
+    }
+
+    /// `&&` and `||` short-circuit; every other operator evaluates both sides, left first.
+    fn evaluate_binary(&mut self, op: BinaryOp, left: &Expr, right: &Expr, span: Span) -> Result<Value, RuntimeError> {
+        match op {
+            BinaryOp::And => Ok(Value::Bool(self.evaluate(left)?.is_truthy() && self.evaluate(right)?.is_truthy())),
+            BinaryOp::Or => Ok(Value::Bool(self.evaluate(left)?.is_truthy() || self.evaluate(right)?.is_truthy())),
+            _ => {
+                let left = self.evaluate(left)?;
+                let right = self.evaluate(right)?;
+                self.binary(op, left, right, span)
+            },
+        }
+    }
+
+    /// Applies `op` to evaluated operands under the interpreter's settings.
+    pub(crate) fn binary(&self, op: BinaryOp, left: Value, right: Value, span: Span) -> Result<Value, RuntimeError> {
+        let (left, right) = match self.type_mode {
+            TypeMode::Strict => (left, right),
+            TypeMode::Coercing => coerce(op, left, right),
+        };
+        let value = binary_op(op, left, right, self.arithmetic, span)?;
+        self.check_size(&value, span)?;
+        Ok(value)
+    }
+
+    fn evaluate_all(&mut self, exprs: &[Expr]) -> Result<Vec<Value>, RuntimeError> {
+        let mut values = Vec::with_capacity(exprs.len());
+        for expr in exprs {
+            values.push(self.evaluate(expr)?);
+        }
+        Ok(values)
+    }
+
+    fn evaluate_map(&mut self, entries: &[(Expr, Expr)], span: Span) -> Result<Value, RuntimeError> {
+        let mut map = IndexMap::with_capacity_and_hasher(entries.len(), Default::default());
+        for (key, value) in entries {
+            let key_value = self.evaluate(key)?;
+            map.insert(map_key(key_value, key.span)?, self.evaluate(value)?);
+        }
+        let map = Value::map(map);
+        self.check_size(&map, span)?;
+        Ok(map)
+    }
+
+    fn evaluate_field(&mut self, target: &Expr, field: &str, span: Span) -> Result<Value, RuntimeError> {
+        let target_value = self.evaluate(target)?;
+        field_value(&target_value, field, target.span, span)
+    }
+
+    fn evaluate_index(&mut self, target: &Expr, index: &Expr) -> Result<Value, RuntimeError> {
+        let target_value = self.evaluate(target)?;
+        let index_value = self.evaluate(index)?;
+        self.index_value(target_value, index_value, target.span, index.span)
+    }
+
+    pub(crate) fn index_value(&self, target: Value, index: Value, target_span: Span, index_span: Span) -> Result<Value, RuntimeError> {
+        match target {
+            Value::Array(values) => {
+                let values = values.borrow();
+                let i = self.array_index(&index, values.len(), index_span)?;
+                Ok(values[i].clone())
+            },
+            Value::Map(entries) => {
+                let key = map_key(index, index_span)?;
+                let value = entries.borrow().get(&key).cloned();
+                value.ok_or(RuntimeError::MissingKey { key, span: index_span })
+            },
+            other => Err(not_indexable(&other, target_span)),
+        }
+    }
+
+    fn evaluate_unary(&mut self, op: UnaryOp, operand: &Expr, span: Span) -> Result<Value, RuntimeError> {
+        let value = self.evaluate(operand)?;
+        unary_op(op, value, self.arithmetic, span)
+    }
+
+    fn evaluate_call(&mut self, callee: &Expr, args: &[Expr], span: Span) -> Result<Value, RuntimeError> {
+        let function = match &callee.kind {
+            ExprKind::Variable(name) => self
+                .lookup(name)
+                .ok_or_else(|| RuntimeError::UndefinedFunction { name: name.clone(), span: callee.span })?,
+            _ => self.evaluate(callee)?,
+        };
+        let values = self.evaluate_all(args)?;
+        self.call_value(function, values, span, callee.span)
+    }
+
+    /// Calls an evaluated callee, `span` being the call and `callee_span` the
+    /// callee.
+    pub(crate) fn call_value(&mut self, function: Value, values: Vec<Value>, span: Span, callee_span: Span) -> Result<Value, RuntimeError> {
+        match function {
+            Value::Builtin(builtin) => (builtin.func)(self, &values, span),
+            Value::Host(host) => {
+                if !self.sandbox.allows_host_function(&host.name) {
+                    return Err(RuntimeError::NotPermitted { what: format!("host function `{}`", host.name), span });
+                }
+                host.call(&values).map_err(|e| e.or_span(span))
+            },
+            Value::Function(function) => self.call_function(function, values, span),
+            Value::StructType(decl) => {
+                let instance = construct(decl, values, span)?;
+                self.check_size(&instance, span)?;
+                Ok(instance)
+            },
+            other => Err(RuntimeError::NotCallable { type_name: other.type_name(), span: callee_span }),
+        }
+    }
+}
+
+/// Sets `name` in `vars`, only allocating its key if it is new.
+fn set_var(vars: &mut HashMap<String, Value>, name: &str, value: Value) {
+    match vars.get_mut(name) {
+        Some(slot) => *slot = value,
+        None => {
+            vars.insert(name.to_string(), value);
+        },
+    }
+}
+
+/// Checks that `key` can be used as a map key.
+pub(crate) fn map_key(key: Value, span: Span) -> Result<String, RuntimeError> {
+    match key {
+        Value::Str(key) => Ok(key.to_string()),
+        other => Err(RuntimeError::TypeMismatch {
+            message: format!("map keys must be strings, found {}", other.type_name()),
+            span,
+        }),
+    }
+}
+
+/// Roughly the bytes `value` took to build, not counting what it holds
+/// that was built before.
+fn allocated_size(value: &Value) -> usize {
+    match value {
+        Value::Str(s) => s.len(),
+        Value::BigInt(n) => (n.bits() / 8) as usize,
+        Value::Array(values) => values.borrow().len() * size_of::<Value>(),
+        Value::Map(entries) => entries.borrow().keys().map(|key| entry_size(key)).sum(),
+        Value::Struct(instance) => instance.borrow().fields.len() * size_of::<Value>(),
+        _ => 0,
+    }
+}
+
+/// The bytes a map entry under `key` takes besides its value's own.
+pub(crate) fn entry_size(key: &str) -> usize {
+    key.len() + size_of::<String>() + size_of::<Value>()
+}
+
+fn construct(decl: Rc<StructDecl>, fields: Vec<Value>, span: Span) -> Result<Value, RuntimeError> {
+    if fields.len() != decl.fields.len() {
+        return Err(RuntimeError::WrongArgumentCount {
+            name: decl.name.clone(),
+            expected: decl.fields.len(),
+            found: fields.len(),
+            span,
+        });
+    }
+    Ok(Value::Struct(Rc::new(RefCell::new(Instance { decl, fields }))))
+}
+
+fn struct_instance(value: &Value, span: Span) -> Result<&Rc<RefCell<Instance>>, RuntimeError> {
+    match value {
+        Value::Struct(instance) => Ok(instance),
+        other => Err(RuntimeError::TypeMismatch { message: format!("{} has no fields", other.type_name()), span }),
+    }
+}
+
+fn field_index(instance: &Instance, field: &str, span: Span) -> Result<usize, RuntimeError> {
+    instance.field_index(field).ok_or_else(|| RuntimeError::MissingField {
+        struct_name: instance.decl.name.clone(),
+        field: field.to_string(),
+        span,
+    })
+}
+
+/// The field `field` of a struct instance or module.
+pub(crate) fn field_value(target: &Value, field: &str, target_span: Span, span: Span) -> Result<Value, RuntimeError> {
+    if let Value::Module(module) = target {
+        return module.namespace.borrow().vars.get(field).cloned().ok_or_else(|| {
+            RuntimeError::UndefinedVariable { name: format!("{}.{}", module.name, field), span }
+        });
+    }
+    let instance = struct_instance(target, target_span)?.borrow();
+    let i = field_index(&instance, field, span)?;
+    Ok(instance.fields[i].clone())
+}
+
+pub(crate) fn store_field(target: &Value, field: &str, value: Value, target_span: Span) -> Result<(), RuntimeError> {
+    let instance = struct_instance(target, target_span)?;
+    let mut instance = instance.borrow_mut();
+    let i = field_index(&instance, field, target_span)?;
+    instance.fields[i] = value;
+    Ok(())
+}
+
+fn not_indexable(value: &Value, span: Span) -> RuntimeError {
+    RuntimeError::TypeMismatch { message: format!("cannot index into {}", value.type_name()), span }
+}
+
+
+/// Converts operands for [`TypeMode::Coercing`] so that `binary_op` accepts
+/// them wherever a conversion makes sense.
+fn coerce(op: BinaryOp, left: Value, right: Value) -> (Value, Value) {
+    match (op, left, right) {
+        (BinaryOp::And | BinaryOp::Or, l, r) => (l, r),
+        (BinaryOp::Add, l @ Value::Str(_), r) | (BinaryOp::Add, l, r @ Value::Str(_)) => {
+            (Value::from(l.to_string()), Value::from(r.to_string()))
+        },
+        // Strings compare as strings unless the other side is a number
+        (_, l @ Value::Str(_), r @ Value::Str(_)) => (l, r),
+        (BinaryOp::Equals | BinaryOp::NotEquals, l, r) if !(is_numeric(&l) && is_numeric(&r)) => (l, r),
+        (_, l, r) => (to_number(l), to_number(r)),
+    }
+}
+
+fn is_numeric(value: &Value) -> bool {
+    matches!(to_number(value.clone()), Value::Int(_) | Value::BigInt(_) | Value::Float(_))
+}
+
+/// The number `value` stands for when coercing, or `value` itself.
+fn to_number(value: Value) -> Value {
+    match value {
+        Value::Bool(b) => Value::Int(b.into()),
+        Value::Nil => Value::Int(0),
+        Value::Str(s) => match (s.trim().parse::<i64>(), s.trim().parse::<f64>()) {
+            (Ok(n), _) => Value::Int(n),
+            (_, Ok(n)) => Value::Float(n),
+            _ => Value::Str(s),
+        },
+        other => other,
+    }
+}
+
+/// The value of an int operation that overflowed: `wrapping` or `saturating`
+/// in those modes, or the `exact` result when promoting.
+pub(crate) fn overflowed(
+    mode: OverflowMode,
+    wrapping: i64,
+    saturating: i64,
+    exact: impl FnOnce() -> BigInt,
+    span: Span,
+) -> Result<Value, RuntimeError> {
+    match mode {
+        OverflowMode::Error => Err(RuntimeError::IntegerOverflow { span }),
+        OverflowMode::Wrap => Ok(Value::Int(wrapping)),
+        OverflowMode::Saturate => Ok(Value::Int(saturating)),
+        OverflowMode::Promote => Ok(Value::from_big(exact())),
+    }
+}
+
+fn int_op(op: BinaryOp, l: i64, r: i64, mode: OverflowMode, span: Span) -> Result<Value, RuntimeError> {
+    let (checked, wrapping, saturating) = match op {
+        BinaryOp::Add => (l.checked_add(r), l.wrapping_add(r), l.saturating_add(r)),
+        BinaryOp::Sub => (l.checked_sub(r), l.wrapping_sub(r), l.saturating_sub(r)),
+        BinaryOp::Mul => (l.checked_mul(r), l.wrapping_mul(r), l.saturating_mul(r)),
+        BinaryOp::Div if r == 0 => return Err(RuntimeError::DivisionByZero { span }),
+        // Only `i64::MIN / -1` overflows
+        BinaryOp::Div => (l.checked_div(r), l.wrapping_div(r), l.saturating_div(r)),
+        _ => unreachable!("{:?} is not arithmetic", op),
+    };
+    match checked {
+        Some(n) => Ok(Value::Int(n)),
+        None => overflowed(mode, wrapping, saturating, || big_op(op, &BigInt::from(l), &BigInt::from(r)), span),
+    }
+}
+
+/// What `binary` gives for two ints whenever it doesn't depend on the
+/// interpreter's settings, so the VMs can skip moving their operands out:
+/// `None` for division and results that overflow.
+pub(crate) fn int_binary(op: BinaryOp, l: i64, r: i64) -> Option<Value> {
+    Some(match op {
+        BinaryOp::Add => Value::Int(l.checked_add(r)?),
+        BinaryOp::Sub => Value::Int(l.checked_sub(r)?),
+        BinaryOp::Mul => Value::Int(l.checked_mul(r)?),
+        BinaryOp::Equals => Value::Bool(l == r),
+        BinaryOp::NotEquals => Value::Bool(l != r),
+        BinaryOp::SmallerThan => Value::Bool(l < r),
+        BinaryOp::GreaterThan => Value::Bool(l > r),
+        BinaryOp::SmallerEquals => Value::Bool(l <= r),
+        BinaryOp::GreaterEquals => Value::Bool(l >= r),
+        BinaryOp::Div | BinaryOp::And | BinaryOp::Or => return None,
+    })
+}
+
+fn big_op(op: BinaryOp, l: &BigInt, r: &BigInt) -> BigInt {
+    match op {
+        BinaryOp::Add => l + r,
+        BinaryOp::Sub => l - r,
+        BinaryOp::Mul => l * r,
+        BinaryOp::Div => l / r,
+        _ => unreachable!("{:?} is not arithmetic", op),
+    }
+}
+
+fn int_to_float(value: &Value) -> f64 {
+    match value {
+        Value::Int(n) => *n as f64,
+        Value::BigInt(n) => n.to_f64().unwrap_or(f64::NAN),
+        _ => unreachable!("{} is not an int", value.type_name()),
+    }
+}
+
+/// Either kind of int as a `BigInt`.
+fn as_big(value: &Value) -> Option<BigInt> {
+    match value {
+        Value::Int(n) => Some(BigInt::from(*n)),
+        Value::BigInt(n) => Some((**n).clone()),
+        _ => None,
+    }
+}
+
+/// Applies a binary operator to two evaluated operands.
+///
+/// An int mixed with a float is converted to a float for arithmetic,
+/// comparisons and equality, so `1 + 2.5 == 3.5` and `1 == 1.0`.
+pub(crate) fn unary_op(op: UnaryOp, value: Value, arithmetic: Arithmetic, span: Span) -> Result<Value, RuntimeError> {
+    match (op, value) {
+        (UnaryOp::Neg, Value::Int(n)) => match n.checked_neg() {
+            Some(n) => Ok(Value::Int(n)),
+            None => overflowed(arithmetic.overflow, n.wrapping_neg(), i64::MAX, || -BigInt::from(n), span),
+        },
+        (UnaryOp::Neg, Value::BigInt(n)) => Ok(Value::from_big(-&*n)),
+        (UnaryOp::Neg, Value::Float(n)) => Ok(Value::Float(-n)),
+        (UnaryOp::Not, value) => Ok(Value::Bool(!value.is_truthy())),
+        (UnaryOp::Neg, value) => Err(RuntimeError::TypeMismatch {
+            message: format!("cannot negate {}", value.type_name()),
+            span,
+        }),
+    }
+}
+
+pub(crate) fn binary_op(op: BinaryOp, left: Value, right: Value, arithmetic: Arithmetic, span: Span) -> Result<Value, RuntimeError> {
+    use Value::*;
+
+    let (left, right) = match (op, left, right) {
+        (BinaryOp::And | BinaryOp::Or, l, r) => (l, r),
+        (_, l @ (Int(_) | BigInt(_)), Float(r)) => (Float(int_to_float(&l)), Float(r)),
+        (_, Float(l), r @ (Int(_) | BigInt(_))) => (Float(l), Float(int_to_float(&r))),
+        (_, l, r) => (l, r),
+    };
+    match (op, left, right) {
+        // `evaluate` short-circuits these; this covers operands that are
+        // already values
+        (BinaryOp::And, l, r) => Ok(Bool(l.is_truthy() && r.is_truthy())),
+        (BinaryOp::Or, l, r) => Ok(Bool(l.is_truthy() || r.is_truthy())),
+        (BinaryOp::Equals, l, r) => Ok(Bool(l == r)),
+        (BinaryOp::NotEquals, l, r) => Ok(Bool(l != r)),
+        (BinaryOp::Div, l @ (Int(_) | BigInt(_)), r @ (Int(_) | BigInt(_))) if arithmetic.division == DivisionMode::Float => {
+            if r == Int(0) {
+                return Err(RuntimeError::DivisionByZero { span });
+            }
+            Ok(Float(int_to_float(&l) / int_to_float(&r)))
+        },
+        (BinaryOp::Add | BinaryOp::Sub | BinaryOp::Mul | BinaryOp::Div, Int(l), Int(r)) => {
+            int_op(op, l, r, arithmetic.overflow, span)
+        },
+        (BinaryOp::SmallerThan, Int(l), Int(r)) => Ok(Bool(l < r)),
+        (BinaryOp::GreaterThan, Int(l), Int(r)) => Ok(Bool(l > r)),
+        (BinaryOp::SmallerEquals, Int(l), Int(r)) => Ok(Bool(l <= r)),
+        (BinaryOp::GreaterEquals, Int(l), Int(r)) => Ok(Bool(l >= r)),
+        (op, l @ (BigInt(_) | Int(_)), r @ (BigInt(_) | Int(_))) => {
+            let (l, r) = (as_big(&l).unwrap(), as_big(&r).unwrap());
+            match op {
+                BinaryOp::Div if r == num_bigint::BigInt::ZERO => Err(RuntimeError::DivisionByZero { span }),
+                BinaryOp::Add | BinaryOp::Sub | BinaryOp::Mul | BinaryOp::Div => Ok(Value::from_big(big_op(op, &l, &r))),
+                BinaryOp::SmallerThan => Ok(Bool(l < r)),
+                BinaryOp::GreaterThan => Ok(Bool(l > r)),
+                BinaryOp::SmallerEquals => Ok(Bool(l <= r)),
+                BinaryOp::GreaterEquals => Ok(Bool(l >= r)),
+                _ => unreachable!("{:?} is handled above", op),
+            }
+        },
+        (BinaryOp::Add, Float(l), Float(r)) => Ok(Float(l + r)),
+        (BinaryOp::Sub, Float(l), Float(r)) => Ok(Float(l - r)),
+        (BinaryOp::Mul, Float(l), Float(r)) => Ok(Float(l * r)),
+        (BinaryOp::Div, Float(l), Float(r)) => Ok(Float(l / r)),
+        (BinaryOp::Add, Str(l), Str(r)) => Ok(Value::from(format!("{}{}", l, r))),
+        (BinaryOp::SmallerThan, Float(l), Float(r)) => Ok(Bool(l < r)),
+        (BinaryOp::GreaterThan, Float(l), Float(r)) => Ok(Bool(l > r)),
+        (BinaryOp::SmallerEquals, Float(l), Float(r)) => Ok(Bool(l <= r)),
+        (BinaryOp::GreaterEquals, Float(l), Float(r)) => Ok(Bool(l >= r)),
+        (BinaryOp::SmallerThan, Str(l), Str(r)) => Ok(Bool(l < r)),
+        (BinaryOp::GreaterThan, Str(l), Str(r)) => Ok(Bool(l > r)),
+        (BinaryOp::SmallerEquals, Str(l), Str(r)) => Ok(Bool(l <= r)),
+        (BinaryOp::GreaterEquals, Str(l), Str(r)) => Ok(Bool(l >= r)),
+        (op, l, r) => Err(RuntimeError::TypeMismatch {
+            message: format!("unsupported operands for {:?}: {} and {}", op, l.type_name(), r.type_name()),
+            span,
+        }),
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod test {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use std::thread;
+    use std::time::Duration;
+
+    use crate::interpreter::{
+        CancellationToken, CannedInput, DivisionMode, Interpreter, NegativeIndexMode, OverflowMode, RuntimeError, SharedBuffer, TypeMode, DEFAULT_MAX_CALL_DEPTH,
+    };
+    use crate::modules::MemoryLoader;
+    use crate::parser::{parse, parse_with_dialect, Dialect};
+    use crate::value::Value;
+
+    fn run(source: &str) -> (Result<Value, RuntimeError>, String) {
+        let output = SharedBuffer::new();
+        let mut interpreter = Interpreter::with_output(Box::new(output.clone()));
+        let result = interpreter.run(&parse(source).unwrap());
+        (result, output.contents())
+    }
+
+    #[test]
+    fn test_print() {
+        let (result, output) = run("x = 2\nprint x * 3 + 1\nprint \"hello\"");
+        assert_eq!(result, Ok(Value::Nil));
+        assert_eq!(output, "7\nhello\n");
+
+        let (_, output) = run("print \"x =\", 1, [2]\nprint\nprint format(\"{}!\", 3)");
+        assert_eq!(output, "x = 1 [2]\n\n3!\n");
+    }
+
+    #[test]
+    fn test_if_else() {
+        let (_, output) = run("x = 10\nif x == 10 { print \"ten\" } else { print \"other\" }");
+        assert_eq!(output, "ten\n");
+
+        let (_, output) = run("x = 3\nif x > 5 { print 1 } else if x > 2 { print 2 } else { print 3 }");
+        assert_eq!(output, "2\n");
+    }
+
+    #[test]
+    fn test_short_circuit() {
+        let (result, output) = run("fn t(x) {\n  print x\n  return x\n}\nt(0) && t(1)\nt(2) || t(3)\nt(0) || t(4)");
+        assert_eq!(result, Ok(Value::Bool(true)));
+        assert_eq!(output, "0\n2\n0\n4\n");
+
+        let (result, _) = run("x = 0\nx != 0 && 10 / x > 1");
+        assert_eq!(result, Ok(Value::Bool(false)));
+        let (result, _) = run("x = 0\nx == 0 || 10 / x > 1");
+        assert_eq!(result, Ok(Value::Bool(true)));
+    }
+
+    #[test]
+    fn test_overflow_modes() {
+        let mut interpreter = Interpreter::with_output(Box::new(SharedBuffer::new()));
+        let max = "9223372036854775807";
+        let program = parse(&format!("m = {}\n[m + 1, -(0 - m - 1), m * 2, pow(2, 64), abs(0 - m - 1)]", max)).unwrap();
+        assert!(matches!(interpreter.run(&program), Err(RuntimeError::IntegerOverflow { .. })));
+
+        interpreter.set_overflow_mode(OverflowMode::Wrap);
+        let result = interpreter.run(&program).unwrap();
+        assert_eq!(result.to_string(), format!("[-{0}, -{0}, -2, 0, -{0}]", "9223372036854775808"));
+
+        interpreter.set_overflow_mode(OverflowMode::Saturate);
+        let result = interpreter.run(&program).unwrap();
+        assert_eq!(result.to_string(), format!("[{0}, {0}, {0}, {0}, {0}]", max));
+
+        interpreter.set_overflow_mode(OverflowMode::Promote);
+        let result = interpreter.run(&program).unwrap();
+        let big = "9223372036854775808";
+        assert_eq!(result.to_string(), format!("[{0}, {0}, 18446744073709551614, 18446744073709551616, {0}]", big));
+        // Results that fit go back to plain ints
+        let source = format!("m = {}\nbig = m * m\n[big / m == m, big > m, big - big, big * 0 + 1]", max);
+        let result = interpreter.run(&parse(&source).unwrap()).unwrap();
+        assert_eq!(result, Value::array(vec![Value::Bool(true), Value::Bool(true), Value::Int(0), Value::Int(1)]));
+    }
+
+    #[test]
+    fn test_mixed_arithmetic() {
+        let (result, _) = run("[1 + 2.5, 3.0 * 2, 7 / 2, 7.0 / 2, 1 == 1.0, 2 > 1.5, 1 != 1.5]");
+        assert_eq!(result.unwrap().to_string(), "[3.5, 6.0, 3, 3.5, true, true, true]");
+
+        let mut interpreter = Interpreter::with_output(Box::new(SharedBuffer::new()));
+        interpreter.set_division_mode(DivisionMode::Float);
+        let result = interpreter.run(&parse("[7 / 2, 8 / 2]").unwrap());
+        assert_eq!(result.unwrap().to_string(), "[3.5, 4.0]");
+        assert!(matches!(interpreter.run(&parse("1 / 0").unwrap()), Err(RuntimeError::DivisionByZero { .. })));
+    }
+
+    #[test]
+    fn test_index_bounds() {
+        let (result, _) = run("a = [1, 2]\na[2]");
+        assert_eq!(result.unwrap_err().to_string(), "2:3: index 2 is out of bounds for an array of length 2");
+        let (result, _) = run("a = [1, 2]\na[-1] = 0");
+        assert!(matches!(result, Err(RuntimeError::IndexOutOfBounds { index: -1, len: 2, .. })));
+
+        let mut interpreter = Interpreter::with_output(Box::new(SharedBuffer::new()));
+        interpreter.set_negative_index_mode(NegativeIndexMode::FromEnd);
+        let program = parse("a = [1, 2, 3]\na[-1] = 4\nremove(a, -3)\n[a[-2], a, a[-3]]").unwrap();
+        assert!(matches!(interpreter.run(&program), Err(RuntimeError::IndexOutOfBounds { index: -3, len: 2, .. })));
+        assert_eq!(interpreter.get_var("a").unwrap().to_string(), "[2, 4]");
+    }
+
+    #[test]
+    fn test_maps() {
+        let source = "m = {\n  \"b\": 1,\n  \"a\": [2],\n}\nm[\"c\"] = 3\nm[\"b\"] = 4\nn = m\nn[\"d\"] = 5\nprint m\nm[\"a\"][0]";
+        let (result, output) = run(source);
+        assert_eq!(output, "{\"b\": 4, \"a\": [2], \"c\": 3, \"d\": 5}\n");
+        assert_eq!(result, Ok(Value::Int(2)));
+
+        let (result, _) = run("{\"x\": [1], \"y\": 2} == {\"y\": 2, \"x\": [1]}");
+        assert_eq!(result, Ok(Value::Bool(true)));
+        let (result, _) = run("m = {}\nm[\"nope\"]");
+        assert_eq!(result.unwrap_err().to_string(), "2:3: map has no key \"nope\"");
+        let (result, _) = run("{1: 2}");
+        assert!(matches!(result, Err(RuntimeError::TypeMismatch { .. })));
+    }
+
+    #[test]
+    fn test_structs() {
+        let source = "struct Point {\n  x,\n  y\n}\np = Point(1, [2])\nq = p\nq.x = 5\nprint p, p.y[0]\np == Point(5, [2])";
+        let (result, output) = run(source);
+        assert_eq!(output, "Point { x: 5, y: [2] } 2\n");
+        assert_eq!(result, Ok(Value::Bool(true)));
+
+        let (result, _) = run("struct A { v }\nstruct B { v }\nA(1) == B(1)");
+        assert_eq!(result, Ok(Value::Bool(false)));
+        let (result, _) = run("struct P { x }\np = P(1)\np.z");
+        assert_eq!(result.unwrap_err().to_string(), "3:1: struct `P` has no field `z`");
+        let (result, _) = run("struct P { x }\nP(1).z = 2");
+        assert!(matches!(result, Err(RuntimeError::MissingField { .. })));
+        let (result, _) = run("struct P { x }\nP()");
+        assert!(matches!(result, Err(RuntimeError::WrongArgumentCount { expected: 1, found: 0, .. })));
+        let (result, _) = run("x = 1\nx.y");
+        assert!(matches!(result, Err(RuntimeError::TypeMismatch { .. })));
+    }
+
+    #[test]
+    fn test_modules() {
+        let loader = MemoryLoader::new()
+            .with_module("math", "print \"loading math\"\nlimit = 10\nfn clamp(x, lo, hi) {\n  return min(max(x, lo), hi)\n}\nfn cap(x) {\n  return clamp(x, 0, limit)\n}")
+            .with_module("geometry", "import math\nfn area(w) {\n  return math.cap(w) * 2\n}")
+            .with_module("a", "import b")
+            .with_module("b", "import a")
+            .with_module("broken", "x = ");
+        let output = SharedBuffer::new();
+        let mut interpreter = Interpreter::with_output(Box::new(output.clone()));
+        interpreter.set_module_loader(Box::new(loader));
+        let mut eval = |source: &str| interpreter.run(&parse(source).unwrap());
+
+        let source = "import math\nimport geometry\nimport math\nprint math.clamp(15, 0, 10), math.limit, geometry.area(20)\nmath";
+        assert_eq!(eval(source).unwrap().to_string(), "<module math>");
+        assert!(matches!(eval("limit"), Err(RuntimeError::UndefinedVariable { .. })));
+        assert_eq!(eval("math.nope").unwrap_err().to_string(), "1:1: undefined variable `math.nope`");
+
+        let error = eval("import a").unwrap_err();
+        assert_eq!(error.root().to_string(), "1:1: cyclic import: a -> b -> a");
+        assert!(matches!(eval("import missing"), Err(RuntimeError::ImportFailed { .. })));
+        assert!(matches!(eval("import broken"), Err(RuntimeError::ImportFailed { .. })));
+        assert_eq!(output.contents(), "loading math\n10 10 20\n");
+    }
+
+    #[test]
+    fn test_type_modes() {
+        let mut interpreter = Interpreter::with_output(Box::new(SharedBuffer::new()));
+        let mut eval = |source: &str| interpreter.run(&parse(source).unwrap());
+        assert!(matches!(eval("\"1\" + 1"), Err(RuntimeError::TypeMismatch { .. })));
+
+        interpreter.set_type_mode(TypeMode::Coercing);
+        let mut eval = |source: &str| interpreter.run(&parse(source).unwrap());
+        assert_eq!(eval("\"1\" + 1"), Ok(Value::Str("11".into())));
+        assert_eq!(eval("[1] + \"!\""), Ok(Value::Str("[1]!".into())));
+        assert_eq!(eval("\"3\" * 2 - 1.5"), Ok(Value::Float(4.5)));
+        assert_eq!(eval("\"10\" > 9"), Ok(Value::Bool(true)));
+        assert_eq!(eval("\"10\" > \"9\""), Ok(Value::Bool(false)));
+        assert_eq!(eval("\"2\" == 2"), Ok(Value::Bool(true)));
+        assert_eq!(eval("\"a\" == 0"), Ok(Value::Bool(false)));
+        assert_eq!(eval("(1 == 1) + 1"), Ok(Value::Int(2)));
+        assert!(matches!(eval("\"a\" * 2"), Err(RuntimeError::TypeMismatch { .. })));
+    }
+
+    #[test]
+    fn test_runtime_errors() {
+        let (result, _) = run("print y");
+        assert!(matches!(result, Err(RuntimeError::UndefinedVariable { name, .. }) if name == "y"));
+
+        let (result, _) = run("x = 1 / 0");
+        assert!(matches!(result, Err(RuntimeError::DivisionByZero { .. })));
+
+        let (result, _) = run("x = \"a\" - 1");
+        assert!(matches!(result, Err(RuntimeError::TypeMismatch { .. })));
+    }
+
+    #[test]
+    fn test_input() {
+        let output = SharedBuffer::new();
+        let mut interpreter = Interpreter::with_output(Box::new(output.clone()));
+        interpreter.set_input(Box::new(CannedInput::new(["21", "Ada"])));
+        let program = parse("n = input(\"number? \")\nname = input()\nprint n * 2\nprint name\ninput()").unwrap();
+        assert_eq!(interpreter.run(&program), Ok(Value::Nil));
+        assert_eq!(output.contents(), "number? 42\nAda\n");
+        assert_eq!(interpreter.get_var("n"), Some(&Value::Int(21)));
+    }
+
+    #[test]
+    fn test_call_errors() {
+        let (result, _) = run("input(1, 2)");
+        assert!(matches!(result, Err(RuntimeError::WrongArgumentCount { expected: 1, found: 2, .. })));
+
+        let (result, _) = run("nope()");
+        assert!(matches!(result, Err(RuntimeError::UndefinedFunction { name, .. }) if name == "nope"));
+    }
+
+    #[test]
+    fn test_arrays() {
+        let (result, output) = run("a = [1, 2, 3]\nb = a\nb[0] = 10\nprint a\na[2]");
+        assert_eq!(result, Ok(Value::Int(3)));
+        assert_eq!(output, "[10, 2, 3]\n");
+
+        let (result, _) = run("a = [1]\na[1]");
+        assert!(matches!(result, Err(RuntimeError::IndexOutOfBounds { index: 1, len: 1, .. })));
+
+        let (result, _) = run("x = 1\nx[0]");
+        assert!(matches!(result, Err(RuntimeError::TypeMismatch { .. })));
+    }
+
+    #[test]
+    fn test_functions() {
+        let source = "
+x = 1
+fn add(a, b) {
+    x = a + b
+    if x > 10 {
+        return 10
+    }
+    return x
+}
+fn greet(name) {
+    print \"hi \" + name
+}
+print add(2, 3)
+print add(20, 3)
+print greet(\"bob\")
+x
+";
+        let (result, output) = run(source);
+        assert_eq!(output, "5\n10\nhi bob\nnil\n");
+        // Assignments inside a function stay local to it
+        assert_eq!(result, Ok(Value::Int(1)));
+    }
+
+    #[test]
+    fn test_function_errors() {
+        let (result, _) = run("fn f(a) { return a }\nf(1, 2)");
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "2:1: `f` expects 1 argument, but 2 were given"
+        );
+
+        let (result, _) = run("fn f() { return y }\nf()");
+        let error = result.unwrap_err();
+        assert!(matches!(error.root(), RuntimeError::UndefinedVariable { name, .. } if name == "y"));
+    }
+
+    #[test]
+    fn test_stack_trace() {
+        let (result, _) = run("fn inner(x) {\n  return x / 0\n}\nfn outer() {\n  return inner(1)\n}\ny = outer()");
+        let error = result.unwrap_err();
+        assert_eq!(error.span().line, 2);
+        assert_eq!(
+            error.to_string(),
+            "2:10: division by zero\nstack trace:\n  in `inner` called at 5:10\n  in `outer` called at 7:5"
+        );
+
+        let (result, _) = run("fn f(n) {\n  return f(n + 1)\n}\nf(0)");
+        let message = result.unwrap_err().to_string();
+        assert!(message.contains("\n  ... 80 more calls\n"));
+        assert_eq!(message.lines().count(), 2 + 20 + 1);
+    }
+
+    #[test]
+    fn test_closures() {
+        let source = "
+fn map(values, f) {
+    result = []
+    step = fn(i) {
+        if i < len(values) {
+            push(result, f(values[i]))
+            step(i + 1)
+        }
+    }
+    step(0)
+    return result
+}
+fn adder(n) {
+    return fn(x) { return x + n }
+}
+fn later() {
+    n = 1
+    get = fn() { return n }
+    n = 2
+    return get
+}
+print map([1, 2, 3], adder(10))
+print later()()
+";
+        let (result, output) = run(source);
+        assert_eq!(result, Ok(Value::Nil));
+        assert_eq!(output, "[11, 12, 13]\n2\n");
+    }
+
+    #[test]
+    fn test_closure_assignment_is_local() {
+        let source = "fn outer() {\n n = 1\n f = fn() { n = 5\n return n }\n return f() * 10 + n\n}\nouter()";
+        let (result, _) = run(source);
+        assert_eq!(result, Ok(Value::Int(51)));
+    }
+
+    #[test]
+    fn test_global() {
+        let source = "count = 0\nfn bump() {\n  global count\n  count = count + 1\n  other = 1\n}\nbump()\nbump()\ncount";
+        let mut interpreter = Interpreter::with_output(Box::new(SharedBuffer::new()));
+        assert_eq!(interpreter.run(&parse(source).unwrap()), Ok(Value::Int(2)));
+        assert_eq!(interpreter.get_var("other"), None);
+
+        // Only the function that declares the name is affected
+        let source = "x = 1\nfn f() {\n  global x\n  g = fn() { x = 2 }\n  g()\n  x = 3\n}\nf()\nx";
+        let (result, _) = run(source);
+        assert_eq!(result, Ok(Value::Int(3)));
+        let (result, _) = run("global x\nx = 4\nx");
+        assert_eq!(result, Ok(Value::Int(4)));
+    }
+
+    #[test]
+    fn test_let_shadowing() {
+        // `let` makes a local even after `global`, and shadows captured variables
+        let source = "x = 1\nfn f() {\n  global x\n  x = 2\n  let x = 10\n  x = x + 1\n  g = fn() {\n    let x = 100\n    return x\n  }\n  return x + g()\n}\nf() * 10 + x";
+        let (result, _) = run(source);
+        assert_eq!(result, Ok(Value::Int(1112)));
+        let (result, _) = run("let s = 1\nlet s = \"one\"\ns");
+        assert_eq!(result, Ok(Value::Str("one".into())));
+    }
+
+    #[test]
+    fn test_recursion() {
+        let (result, _) = run("fn fib(n) {\n if n < 2 { return n }\n return fib(n - 1) + fib(n - 2)\n}\nfib(15)");
+        assert_eq!(result, Ok(Value::Int(610)));
+
+        let source = "fn down(n) {\n if n == 0 { return 0 }\n return 1 + down(n - 1)\n}\ndown(depth)";
+        let mut interpreter = Interpreter::with_output(Box::new(SharedBuffer::new()));
+        let program = parse(&source.replace("depth", &DEFAULT_MAX_CALL_DEPTH.to_string())).unwrap();
+        let error = interpreter.run(&program).unwrap_err();
+        assert!(matches!(error.root(), RuntimeError::StackOverflow { .. }));
+        assert_eq!(error.trace().len(), DEFAULT_MAX_CALL_DEPTH);
+        let program = parse(&source.replace("depth", &(DEFAULT_MAX_CALL_DEPTH - 1).to_string())).unwrap();
+        assert_eq!(interpreter.run(&program), Ok(Value::Int(DEFAULT_MAX_CALL_DEPTH as i64 - 1)));
+
+        interpreter.set_max_call_depth(10);
+        let program = parse(&source.replace("depth", "10")).unwrap();
+        let error = interpreter.run(&program).unwrap_err();
+        assert_eq!(error.root().to_string(), "3:13: stack overflow in script: more than 10 nested calls");
+    }
+
+    #[test]
+    fn test_while() {
+        let (_, output) = run("i = 0\nwhile i < 10 {\n i = i + 1\n if i == 2 { continue }\n if i > 4 { break }\n print i\n}");
+        assert_eq!(output, "1\n3\n4\n");
+    }
+
+    #[test]
+    fn test_fuel() {
+        let mut interpreter = Interpreter::with_output(Box::new(SharedBuffer::new()));
+        interpreter.set_fuel(Some(1000));
+        let result = interpreter.run(&parse("while 1 {}").unwrap());
+        assert!(matches!(result, Err(RuntimeError::FuelExhausted { .. })));
+        assert_eq!(interpreter.fuel(), Some(0));
+
+        interpreter.set_fuel(Some(10));
+        let program = parse("i = 0\nwhile i < 3 { i = i + 1 }").unwrap();
+        assert_eq!(interpreter.run(&program), Ok(Value::Nil));
+        assert_eq!(interpreter.fuel(), Some(2));
+
+        interpreter.set_fuel(Some(50));
+        let error = interpreter.run(&parse("fn f() { return f() }\nf()").unwrap()).unwrap_err();
+        assert!(matches!(error.root(), RuntimeError::FuelExhausted { .. }));
+    }
+
+    #[test]
+    fn test_cancellation() {
+        let token = CancellationToken::new();
+        let mut interpreter = Interpreter::with_output(Box::new(SharedBuffer::new()));
+        interpreter.set_cancellation_token(token.clone());
+        let canceller = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(20));
+            token.cancel();
+        });
+        let result = interpreter.run(&parse("i = 0\nwhile 1 { i = i + 1 }").unwrap());
+        canceller.join().unwrap();
+        assert!(matches!(result, Err(RuntimeError::Cancelled { .. })));
+    }
+
+    fn run_classic(source: &str) -> (Result<Value, RuntimeError>, String) {
+        let output = SharedBuffer::new();
+        let mut interpreter = Interpreter::with_output(Box::new(output.clone()));
+        let result = interpreter.run(&parse_with_dialect(source, Dialect::Classic).unwrap());
+        (result, output.contents())
+    }
+
+    #[test]
+    fn test_gosub() {
+        let source = "
+10 x = 1
+20 gosub 100
+30 if x < 4 { gosub 100 }
+40 print \"done\"
+50 return
+100 print x
+110 x = x * 2
+120 gosub 200
+130 return
+200 print \"nested\"
+210 return
+";
+        let (result, output) = run_classic(source);
+        assert_eq!(output, "1\nnested\n2\nnested\ndone\n");
+        assert!(matches!(result, Err(RuntimeError::ReturnWithoutGosub { .. })));
+
+        let (result, _) = run_classic("10 gosub 99");
+        assert!(matches!(result, Err(RuntimeError::UndefinedLine { line: 99, .. })));
+    }
+
+    #[test]
+    fn test_goto() {
+        let source = "
+10 i = 0
+20 i = i + 1
+30 if i > 3 { goto 60 }
+40 print i
+50 goto 20
+60 print \"end\"
+";
+        let (result, output) = run_classic(source);
+        assert_eq!(result, Ok(Value::Nil));
+        assert_eq!(output, "1\n2\n3\nend\n");
+
+        // Falling through goes on to the next number, not the next line written
+        let (_, output) = run_classic("10 goto 30\n40 print \"forty\"\n20 print \"twenty\"\n30 print \"thirty\"");
+        assert_eq!(output, "thirty\nforty\n");
+
+        let (result, _) = run_classic("10 goto 15");
+        assert_eq!(result.unwrap_err().to_string(), "1:4: there is no line 15");
+    }
+
+    #[test]
+    fn test_final_expression_value() {
+        let (result, _) = run("x = 4\nx * x");
+        assert_eq!(result, Ok(Value::Int(16)));
+    }
+
+    #[test]
+    fn test_register_fn() {
+        let log = Rc::new(RefCell::new(Vec::new()));
+        let output = SharedBuffer::new();
+        let mut interpreter = Interpreter::with_output(Box::new(output.clone()));
+        let recorded = log.clone();
+        interpreter.register_fn("log", move |args| {
+            recorded.borrow_mut().extend(args.iter().map(Value::to_string));
+            Ok(Value::Nil)
+        });
+        interpreter.register_fn("fail", |_| Err(RuntimeError::host("database is offline")));
+
+        let program = parse("log(1, \"two\")\nprint log\nx = 1\nfail()").unwrap();
+        let error = interpreter.run(&program).unwrap_err();
+        assert_eq!(*log.borrow(), vec!["1", "two"]);
+        assert_eq!(output.contents(), "<builtin log>\n");
+        assert_eq!(error.to_string(), "4:1: database is offline");
+    }
+}
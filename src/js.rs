@@ -0,0 +1,621 @@
+use std::collections::BTreeSet;
+use std::fmt::Write;
+use std::rc::Rc;
+
+use indexmap::IndexSet;
+
+use crate::ast::{BinaryOp, Expr, ExprKind, FunctionDecl, Program, Stmt, StmtKind, StructDecl, UnaryOp};
+use crate::collections::HashMap;
+use crate::lexer::Span;
+use crate::transpile::{mangle, walk_blocks, Scopes, TranspileError};
+
+const RUNTIME: &str = include_str!("js_runtime.js");
+
+/// The builtins the JavaScript runtime implements.
+const BUILTINS: &[&str] = &[
+    "input", "abs", "min", "max", "sqrt", "pow", "floor", "ceil", "round", "len", "substr", "upper", "lower", "trim",
+    "split", "contains", "replace", "format", "push", "pop", "insert", "remove", "sort", "reverse", "keys", "values",
+    "has", "rnd", "random", "seed", "now", "clock_ms", "getenv", "args", "exit",
+];
+
+/// Translates `program` into a script defining one function,
+/// `tbasicMain(host)`, which runs the program and returns its exit status.
+///
+/// The script runs in browsers and in Node.js. `host` connects the program
+/// to the page, and each of its members is optional:
+///
+/// - `print(line)` receives each line `print` writes, without the newline;
+///   it defaults to `console.log`.
+/// - `input(prompt)` returns the next line of input as a string, or `null`
+///   at the end of the input; it defaults to the browser's `prompt`.
+/// - `error(message)` receives a runtime error as `line:column: message`;
+///   it defaults to logging to `console.error`. The program then returns 1.
+/// - `args` is the array `args()` returns and `env` the object `getenv`
+///   reads.
+///
+/// Otherwise the program behaves like the interpreter with its default
+/// settings. Imports, `sleep` and the file builtins are rejected.
+pub fn transpile(program: &Program) -> Result<String, TranspileError> {
+    let scopes = Scopes::new(program, BUILTINS)?;
+    let mut generator = Generator::new(&scopes);
+    let body = generator.main(program);
+    Ok(generator.finish(&body))
+}
+
+struct Generator<'a> {
+    scopes: &'a Scopes,
+    types: HashMap<*const StructDecl, usize>,
+    type_definitions: String,
+    /// The globals the program reads or writes.
+    globals: IndexSet<String>,
+    /// The function being generated, `None` for the top level.
+    function: Option<usize>,
+    indent: usize,
+    /// Classic dialect: the statement each line number labels, if the
+    /// program jumps.
+    lines: Option<HashMap<i64, usize>>,
+}
+
+impl<'a> Generator<'a> {
+    fn new(scopes: &'a Scopes) -> Generator<'a> {
+        Generator {
+            scopes,
+            types: HashMap::default(),
+            type_definitions: String::new(),
+            globals: IndexSet::new(),
+            function: None,
+            indent: 2,
+            lines: None,
+        }
+    }
+
+    fn finish(self, body: &str) -> String {
+        let mut js = String::from("function tbasicMain(host) {\n");
+        for line in RUNTIME.lines() {
+            if !line.is_empty() {
+                js.push_str("    ");
+            }
+            js.push_str(line);
+            js.push('\n');
+        }
+        js.push_str("\n    // Program\n\n");
+        js.push_str(&self.type_definitions);
+        for name in &self.globals {
+            match BUILTINS.contains(&name.as_str()) {
+                true => {
+                    let _ = writeln!(js, "    let g_{} = new TbBuiltin(\"{}\", {});", mangle(name), name, builtin_function(name));
+                },
+                false => {
+                    let _ = writeln!(js, "    let g_{};", mangle(name));
+                },
+            }
+        }
+        js.push_str("\n    return tbRun(() => {\n");
+        js.push_str(body);
+        js.push_str("    });\n}\n");
+        js
+    }
+
+    fn main(&mut self, program: &Program) -> String {
+        let mut out = String::new();
+        let mut jumps = false;
+        let mut cases = BTreeSet::new();
+        for (i, statement) in program.statements.iter().enumerate() {
+            walk_blocks(statement, &mut |kind| match kind {
+                StmtKind::Goto(line) | StmtKind::Gosub(line) => {
+                    jumps = true;
+                    cases.extend(program.lines.get(line));
+                    if matches!(kind, StmtKind::Gosub(_)) {
+                        cases.insert(i + 1);
+                    }
+                },
+                StmtKind::SubReturn => jumps = true,
+                _ => {},
+            });
+        }
+        if !jumps {
+            self.block(&mut out, &program.statements, 0);
+            return out;
+        }
+        // Jumps go through a `switch` on the index of the next statement
+        self.lines = Some(program.lines.clone());
+        self.line(&mut out, "let tbPc = 0;");
+        self.line(&mut out, "tbDispatch: for (;;) {");
+        self.indent += 1;
+        self.line(&mut out, "switch (tbPc) {");
+        self.line(&mut out, "case 0:");
+        for i in 0..=program.statements.len() {
+            if i > 0 && cases.contains(&i) {
+                self.line(&mut out, &format!("case {}:", i));
+            }
+            if let Some(statement) = program.statements.get(i) {
+                self.indent += 1;
+                self.statement(&mut out, statement, i + 1);
+                self.indent -= 1;
+            }
+        }
+        self.line(&mut out, "}");
+        self.line(&mut out, "return;");
+        self.indent -= 1;
+        self.line(&mut out, "}");
+        out
+    }
+
+    fn line(&self, out: &mut String, line: &str) {
+        for _ in 0..self.indent {
+            out.push_str("    ");
+        }
+        out.push_str(line);
+        out.push('\n');
+    }
+
+    /// A `gosub` nested in `statements` continues at `resume`, after the
+    /// top-level statement it is in.
+    fn block(&mut self, out: &mut String, statements: &[Stmt], resume: usize) {
+        for statement in statements {
+            self.statement(out, statement, resume);
+        }
+    }
+
+    fn nested(&mut self, out: &mut String, statements: &[Stmt], resume: usize) {
+        self.indent += 1;
+        self.block(out, statements, resume);
+        self.indent -= 1;
+    }
+
+    /// `resume` is the statement a `gosub` in `statement` continues at.
+    fn statement(&mut self, out: &mut String, statement: &Stmt, resume: usize) {
+        let at = js_span(statement.span);
+        match &statement.kind {
+            StmtKind::Assign { name, value, declaration, .. } => {
+                let value = self.expr(value);
+                self.assign(out, name, &value, *declaration);
+            },
+            StmtKind::SetIndex { target, index, value } => {
+                let line = format!(
+                    "tbSetIndex({}, {}, {}, {}, {});",
+                    self.expr(target),
+                    self.expr(index),
+                    self.expr(value),
+                    js_span(target.span),
+                    js_span(index.span)
+                );
+                self.line(out, &line);
+            },
+            StmtKind::SetField { target, field, value } => {
+                let line = format!(
+                    "tbSetField({}, {}, {}, {});",
+                    self.expr(target),
+                    js_string(field),
+                    self.expr(value),
+                    js_span(target.span)
+                );
+                self.line(out, &line);
+            },
+            StmtKind::If { condition, then_branch, else_branch } => {
+                let condition = self.expr(condition);
+                self.line(out, &format!("if (tbTruthy({})) {{", condition));
+                self.nested(out, then_branch, resume);
+                if let Some(else_branch) = else_branch {
+                    self.line(out, "} else {");
+                    self.nested(out, else_branch, resume);
+                }
+                self.line(out, "}");
+            },
+            StmtKind::While { condition, body } => {
+                let condition = self.expr(condition);
+                self.line(out, &format!("while (tbTruthy({})) {{", condition));
+                self.nested(out, body, resume);
+                self.line(out, "}");
+            },
+            StmtKind::Break => self.line(out, "break;"),
+            StmtKind::Continue => self.line(out, "continue;"),
+            StmtKind::Print(args) => {
+                let values = self.exprs(args);
+                self.line(out, &format!("tbPrintValues([{}]);", values));
+            },
+            StmtKind::Function(decl) => {
+                let function = self.function_value(decl);
+                self.assign(out, &decl.name, &function, false);
+            },
+            StmtKind::Struct(decl) => {
+                let i = self.struct_type(decl);
+                self.assign(out, &decl.name, &format!("tbType{}", i), false);
+            },
+            StmtKind::Return(value) => {
+                let value = match value {
+                    Some(value) => self.expr(value),
+                    None => "null".to_string(),
+                };
+                match self.function {
+                    Some(_) => self.line(out, &format!("return {};", value)),
+                    None => {
+                        self.line(out, &format!("{};", value));
+                        self.line(out, "return;");
+                    },
+                }
+            },
+            StmtKind::Global(names) => {
+                for name in names {
+                    if let Some(flag) = self.flag(name) {
+                        self.line(out, &format!("{} = true;", flag));
+                    }
+                }
+            },
+            // Rejected by `Scopes::new`
+            StmtKind::Import(_) => {},
+            StmtKind::Goto(line) => self.jump(out, *line, &at),
+            StmtKind::Gosub(line) => {
+                self.line(out, &format!("tbGosub({}, {});", resume, at));
+                self.jump(out, *line, &at);
+            },
+            StmtKind::SubReturn => {
+                self.line(out, &format!("tbPc = tbSubReturn({});", at));
+                self.line(out, "continue tbDispatch;");
+            },
+            StmtKind::Expr(expr) => {
+                let value = self.expr(expr);
+                self.line(out, &format!("{};", value));
+            },
+        }
+    }
+
+    fn jump(&self, out: &mut String, line: i64, at: &str) {
+        match self.lines.as_ref().and_then(|lines| lines.get(&line)) {
+            Some(i) => {
+                self.line(out, &format!("tbPc = {};", i));
+                self.line(out, "continue tbDispatch;");
+            },
+            None => self.line(out, &format!("tbFail({}, \"there is no line {}\");", at, line)),
+        }
+    }
+
+    fn exprs(&mut self, exprs: &[Expr]) -> String {
+        exprs.iter().map(|expr| self.expr(expr)).collect::<Vec<_>>().join(", ")
+    }
+
+    /// A JavaScript expression evaluating `expr`. JavaScript evaluates
+    /// operands and arguments left to right, like the interpreter.
+    fn expr(&mut self, expr: &Expr) -> String {
+        let at = js_span(expr.span);
+        match &expr.kind {
+            ExprKind::Number(n) => format!("{}n", n),
+            ExprKind::Float(f) if f.is_infinite() => "Infinity".to_string(),
+            ExprKind::Float(f) => format!("{:e}", f),
+            ExprKind::Str(s) => js_string(s),
+            ExprKind::Variable(name) => self.read(name, expr.span, "tbGlobal"),
+            ExprKind::Function(decl) => self.function_value(decl),
+            ExprKind::Array(items) => format!("[{}]", self.exprs(items)),
+            ExprKind::Map(entries) => {
+                let entries: Vec<String> = entries
+                    .iter()
+                    .map(|(key, value)| format!("[tbKey({}, {}), {}]", self.expr(key), js_span(key.span), self.expr(value)))
+                    .collect();
+                format!("new Map([{}])", entries.join(", "))
+            },
+            ExprKind::Index { target, index } => format!(
+                "tbIndex({}, {}, {}, {})",
+                self.expr(target),
+                self.expr(index),
+                js_span(target.span),
+                js_span(index.span)
+            ),
+            ExprKind::Field { target, field } => {
+                format!("tbField({}, {}, {}, {})", self.expr(target), js_string(field), js_span(target.span), at)
+            },
+            ExprKind::Unary { op: UnaryOp::Neg, operand } => format!("tbNeg({}, {})", self.expr(operand), at),
+            ExprKind::Unary { op: UnaryOp::Not, operand } => format!("!tbTruthy({})", self.expr(operand)),
+            ExprKind::Binary { op: BinaryOp::And, left, right } => {
+                format!("(tbTruthy({}) && tbTruthy({}))", self.expr(left), self.expr(right))
+            },
+            ExprKind::Binary { op: BinaryOp::Or, left, right } => {
+                format!("(tbTruthy({}) || tbTruthy({}))", self.expr(left), self.expr(right))
+            },
+            ExprKind::Binary { op, left, right } => {
+                format!("tbBinary(\"{}\", {}, {}, {})", js_operator(*op), self.expr(left), self.expr(right), at)
+            },
+            ExprKind::Call { callee, args } => {
+                let function = match &callee.kind {
+                    ExprKind::Variable(name) => self.read(name, callee.span, "tbGlobalFunction"),
+                    _ => self.expr(callee),
+                };
+                format!("tbCall({}, [{}], {}, {})", function, self.exprs(args), at, js_span(callee.span))
+            },
+        }
+    }
+
+    /// Reads the variable `name`, using `global` to read a global.
+    fn read(&mut self, name: &str, span: Span, global: &str) -> String {
+        let global = format!("{}({}, {}, {})", global, self.global(name), js_string(name), js_span(span));
+        let Some(function) = self.function else {
+            return global;
+        };
+        let holders = self.scopes.holders(function, name);
+        if holders.is_empty() {
+            return global;
+        }
+        let mut value = global.clone();
+        for holder in holders.into_iter().rev() {
+            value = format!("{0} !== undefined ? {0} : {1}", local(holder, name), value);
+        }
+        match self.flag(name) {
+            Some(flag) => format!("({} ? {} : {})", flag, global, value),
+            None => format!("({})", value),
+        }
+    }
+
+    /// `declaration` is whether the assignment starts with `let`.
+    fn assign(&mut self, out: &mut String, name: &str, value: &str, declaration: bool) {
+        let global = self.global(name);
+        let Some(function) = self.function else {
+            self.line(out, &format!("{} = {};", global, value));
+            return;
+        };
+        let slot = local(function, name);
+        match self.flag(name) {
+            Some(flag) if declaration => {
+                self.line(out, &format!("{} = {};", slot, value));
+                self.line(out, &format!("{} = false;", flag));
+            },
+            Some(flag) => {
+                self.line(out, "{");
+                self.indent += 1;
+                self.line(out, &format!("const value = {};", value));
+                self.line(out, &format!("if ({}) {} = value; else {} = value;", flag, global, slot));
+                self.indent -= 1;
+                self.line(out, "}");
+            },
+            None => self.line(out, &format!("{} = {};", slot, value)),
+        }
+    }
+
+    /// The flag telling whether the current function declared `name`
+    /// global.
+    fn flag(&self, name: &str) -> Option<String> {
+        let function = self.function?;
+        self.scopes.functions[function].globals.contains(name).then(|| format!("gl_{}", mangle(name)))
+    }
+
+    fn global(&mut self, name: &str) -> String {
+        self.globals.insert(name.to_string());
+        format!("g_{}", mangle(name))
+    }
+
+    /// A new function value for `decl`, whose code closes over the current
+    /// call.
+    fn function_value(&mut self, decl: &Rc<FunctionDecl>) -> String {
+        let i = self.scopes.of(decl);
+        let scope = &self.scopes.functions[i];
+        let outer = self.function.replace(i);
+        let mut out = format!("new TbFunction({}, {}, (args) => {{\n", js_string(&decl.name), decl.params.len());
+        self.indent += 1;
+        let mut locals: Vec<String> = Vec::new();
+        for (n, name) in scope.locals.iter().enumerate() {
+            match n < decl.params.len() {
+                true => locals.push(format!("{} = args[{}]", local(i, name), n)),
+                false => locals.push(local(i, name)),
+            }
+        }
+        if !locals.is_empty() {
+            self.line(&mut out, &format!("let {};", locals.join(", ")));
+        }
+        let mut globals: Vec<&String> = scope.globals.iter().collect();
+        globals.sort();
+        for name in globals {
+            self.line(&mut out, &format!("let gl_{} = false;", mangle(name)));
+        }
+        self.block(&mut out, &decl.body, 0);
+        self.line(&mut out, "return null;");
+        self.indent -= 1;
+        for _ in 0..self.indent {
+            out.push_str("    ");
+        }
+        out.push_str("})");
+        self.function = outer;
+        out
+    }
+
+    fn struct_type(&mut self, decl: &Rc<StructDecl>) -> usize {
+        let next = self.types.len();
+        let i = *self.types.entry(Rc::as_ptr(decl)).or_insert(next);
+        if i == next {
+            let fields: Vec<String> = decl.fields.iter().map(|field| js_string(field)).collect();
+            let _ = writeln!(
+                self.type_definitions,
+                "    const tbType{} = new TbType({}, [{}]);",
+                i,
+                js_string(&decl.name),
+                fields.join(", ")
+            );
+        }
+        i
+    }
+}
+
+/// The variable holding `name` in calls of `function`.
+fn local(function: usize, name: &str) -> String {
+    format!("v{}_{}", function, mangle(name))
+}
+
+/// The runtime function implementing the builtin `name`.
+fn builtin_function(name: &str) -> String {
+    let mut function = String::from("tbBuiltin");
+    for word in name.split('_') {
+        let mut chars = word.chars();
+        function.extend(chars.next().map(|c| c.to_ascii_uppercase()));
+        function.extend(chars);
+    }
+    function
+}
+
+fn js_span(span: Span) -> String {
+    format!("[{}, {}]", span.line, span.column)
+}
+
+fn js_operator(op: BinaryOp) -> &'static str {
+    match op {
+        BinaryOp::Add => "+",
+        BinaryOp::Sub => "-",
+        BinaryOp::Mul => "*",
+        BinaryOp::Div => "/",
+        BinaryOp::Equals => "==",
+        BinaryOp::NotEquals => "!=",
+        BinaryOp::SmallerThan => "<",
+        BinaryOp::GreaterThan => ">",
+        BinaryOp::SmallerEquals => "<=",
+        BinaryOp::GreaterEquals => ">=",
+        BinaryOp::And | BinaryOp::Or => unreachable!("`&&` and `||` short-circuit"),
+    }
+}
+
+/// A JavaScript string literal for `s` in plain ASCII, which can go inside
+/// an HTML `<script>` element.
+fn js_string(s: &str) -> String {
+    let mut literal = String::from("\"");
+    for unit in s.encode_utf16() {
+        match unit {
+            0x22 | 0x5c => {
+                literal.push('\\');
+                literal.push(unit as u8 as char);
+            },
+            0x20..=0x7e if unit != 0x3c => literal.push(unit as u8 as char),
+            _ => {
+                let _ = write!(literal, "\\u{:04x}", unit);
+            },
+        }
+    }
+    literal.push('"');
+    literal
+}
+
+#[cfg(test)]
+mod test {
+    use std::process::Command;
+
+    use crate::interpreter::{Interpreter, SharedBuffer};
+    use crate::js::{builtin_function, js_string, transpile};
+    use crate::parser::{parse, parse_with_dialect, Dialect};
+
+    /// Runs the JavaScript version of `source` on Node.js, with `host` as
+    /// the host object, returning its exit status, stdout and stderr, or
+    /// `None` without Node.js.
+    fn run_js(name: &str, source: &str, dialect: Dialect, host: &str) -> Option<(i32, String, String)> {
+        let js = transpile(&parse_with_dialect(source, dialect).unwrap()).unwrap();
+        let file = std::env::temp_dir().join(format!("tbasic-js-{}-{}.js", std::process::id(), name));
+        std::fs::write(&file, format!("{}\nprocess.exitCode = tbasicMain({});\n", js, host)).unwrap();
+        let run = Command::new("node").arg(&file).output();
+        std::fs::remove_file(&file).unwrap();
+        let run = run.ok()?;
+        let text = |bytes: Vec<u8>| String::from_utf8(bytes).unwrap();
+        Some((run.status.code().unwrap(), text(run.stdout), text(run.stderr)))
+    }
+
+    /// Checks that the JavaScript version of `source` prints what the
+    /// interpreter prints.
+    fn check(name: &str, source: &str, dialect: Dialect) {
+        let output = SharedBuffer::new();
+        let result = Interpreter::with_output(Box::new(output.clone())).run(&parse_with_dialect(source, dialect).unwrap());
+        assert!(result.is_ok(), "{:?}", result);
+        if let Some((status, stdout, stderr)) = run_js(name, source, dialect, "{}") {
+            assert_eq!((status, stdout.as_str(), stderr.as_str()), (0, output.contents().as_str(), ""), "{:?}", source);
+        }
+    }
+
+    #[test]
+    fn test_matches_interpreter() {
+        check(
+            "values",
+            "fn f() {}\nprint 1, 2.5, -0.0, 10000000000000000.0, 0.00001, 0.0001, 1.0 / 0.0, \"a\\tb\", f() == f(), f()\n\
+             print [1, \"x\", [2.0]], {\"k\": \"v\", \"n\": {}}, [] == [], 7 / 2, 7.0 / 2, -7 / 2, 0.1 + 0.2\n\
+             print \"ab\" + \"c\", \"b\" < \"ab\", 1 == 1.0, !0, 1 && 0, 0 || \"\", len, fn() {}, \"<\\\"π\\\">\"",
+            Dialect::Modern,
+        );
+        check(
+            "closures",
+            "fn counter() {\n  n = 0\n  return fn() {\n    n = n + 1\n    return n\n  }\n}\n\
+             c = counter()\nc()\nprint c(), c(), counter()()\n\
+             fn adder(a) {\n  return fn(b) {\n    return fn(c) { return a + b + c }\n  }\n}\nprint adder(1)(2)(3)\n\
+             fn outer() {\n  x = 1\n  f = fn() { return x }\n  x = 2\n  return f\n}\nprint outer()()\n\
+             fn fib(n) {\n  if n < 2 { return n }\n  return fib(n - 1) + fib(n - 2)\n}\nprint fib(15)",
+            Dialect::Modern,
+        );
+        check(
+            "scopes",
+            "g = 1\nfn set() {\n  global g\n  g = 2\n  let g = 3\n  print g\n}\nset()\nprint g\n\
+             fn shadow() {\n  print g\n  g = 5\n  print g\n}\nshadow()\nprint g",
+            Dialect::Modern,
+        );
+        check(
+            "data",
+            "struct P { x, y }\np = P(1, 2)\np.x = p.x + 10\nprint p, p.x, P\n\
+             m = {\"b\": 1, \"a\": 2}\nm[\"c\"] = 3\nm[\"b\"] = 4\nprint m, keys(m), values(m), has(m, \"a\"), remove(m, \"a\"), m\n\
+             a = [3, 1, 2]\npush(a, 0)\nsort(a)\nprint a, pop(a), reverse(a), len(\"héllo\"), upper(\"abc\")\n\
+             i = 0\nwhile 1 {\n  i = i + 1\n  if i == 2 { continue }\n  if i > 4 { break }\n  print i\n}\n\
+             print split(\"a,b\", \",\"), substr(\"hello\", 1, 3), trim(\"  x \"), replace(\"aaa\", \"a\", \"b\")\n\
+             print format(\"{} and {}\", 1, [2]), min(3, 1.5), max(2, 4), abs(-3), pow(2, 10), sqrt(16.0), round(-2.5)\n\
+             seed(42)\nprint random(1, 100), rnd()",
+            Dialect::Modern,
+        );
+        check(
+            "classic",
+            "10 n = 0\n20 gosub 100\n30 if n < 3 { goto 20 }\n40 print \"done\", n\n45 if n > 0 { gosub 100 }\n\
+             50 goto 200\n100 n = n + 1\n110 print \"sub\", n\n120 return\n200 print \"end\"",
+            Dialect::Classic,
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        let programs = [
+            ("undefined", "x = 1\nprint y", "error: 2:7: undefined variable `y`"),
+            ("call", "f(1)", "error: 1:1: call to undefined function `f`"),
+            ("arity", "fn f(a) {}\nf()", "error: 2:1: `f` expects 1 argument, but 0 were given"),
+            ("overflow", "print 9223372036854775807 + 1", "error: 1:7: integer overflow"),
+            ("index", "a = [1]\nprint a[1]", "error: 2:9: index 1 is out of bounds for an array of length 1"),
+            ("recursion", "fn f() { return f() }\nf()", "error: 1:17: stack overflow in script: more than 100 nested calls"),
+        ];
+        for (name, source, message) in programs {
+            let Some((status, stdout, stderr)) = run_js(name, source, Dialect::Modern, "{}") else { return };
+            let expected = Interpreter::with_output(Box::new(SharedBuffer::new())).run(&parse(source).unwrap()).unwrap_err();
+            assert!(expected.to_string().starts_with(&message["error: ".len()..]), "{}", expected);
+            assert_eq!((status, stdout.as_str(), stderr.trim_end()), (1, "", message));
+        }
+        let Some((status, stdout, _)) = run_js("exit", "print 1\nexit(3)\nprint 2", Dialect::Modern, "{}") else { return };
+        assert_eq!((status, stdout.as_str()), (3, "1\n"));
+    }
+
+    #[test]
+    fn test_host() {
+        let host = r#"(() => {
+            const lines = ["7", "  x "];
+            const shown = [];
+            process.on("exit", () => console.log(JSON.stringify(shown)));
+            return {
+                print: (line) => shown.push("print " + line),
+                input: (prompt) => (shown.push("input " + prompt), lines.length ? lines.shift() : null),
+                error: (message) => shown.push("error " + message),
+                args: ["a"],
+                env: { HOME: "/home" },
+            };
+        })()"#;
+        let source = "print input(\"n? \") + 1, input(), input()\nprint args(), getenv(\"HOME\"), getenv(\"NONE\")\nprint [][0]";
+        let Some((status, stdout, _)) = run_js("host", source, Dialect::Modern, host) else { return };
+        assert_eq!(status, 1);
+        assert_eq!(
+            stdout.trim_end(),
+            r#"["input n? ","input ","input ","print 8   x  nil","print [\"a\"] /home nil","error 3:10: index 0 is out of bounds for an array of length 0"]"#
+        );
+    }
+
+    #[test]
+    fn test_rejected() {
+        assert_eq!(transpile(&parse("import m").unwrap()).unwrap_err().to_string(), "1:1: modules can't be transpiled");
+        assert_eq!(transpile(&parse("sleep(1)").unwrap()).unwrap_err().to_string(), "1:1: the builtin `sleep` isn't available");
+    }
+
+    #[test]
+    fn test_names() {
+        assert_eq!(builtin_function("clock_ms"), "tbBuiltinClockMs");
+        assert_eq!(js_string("\"é</script>\n"), "\"\\\"\\u00e9\\u003c/script>\\u000a\"");
+    }
+}
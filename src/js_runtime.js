@@ -0,0 +1,851 @@
+// Runtime for JavaScript transpiled from tbasic: values, operators and
+// builtins, behaving like the interpreter with its default settings. The
+// transpiler wraps it and the program in `function tbasicMain(host)`.
+//
+// Ints are bigints, floats numbers, nil `null` and strings JavaScript
+// strings, counted in Unicode characters; a variable that hasn't been
+// assigned is `undefined`.
+
+"use strict";
+
+host = host || {};
+const tbPrint = host.print || ((line) => console.log(line));
+const tbError = host.error || ((message) => console.error("error: " + message));
+const tbInput = host.input || ((prompt) => (typeof globalThis.prompt === "function" ? globalThis.prompt(prompt) : null));
+const tbArgs = host.args || [];
+const tbEnv = host.env || {};
+
+// Same as the interpreter's default limit on nested calls.
+const TB_MAX_DEPTH = 100;
+const TB_INT_MIN = -(2n ** 63n);
+const TB_INT_MAX = 2n ** 63n - 1n;
+const TB_STARTED = Date.now();
+
+class TbError extends Error {
+    constructor(at, message) {
+        super(message);
+        this.line = at[0];
+        this.column = at[1];
+    }
+}
+
+class TbExit {
+    constructor(code) {
+        this.code = code;
+    }
+}
+
+class TbType {
+    constructor(name, fields) {
+        this.name = name;
+        this.fields = fields;
+    }
+}
+
+class TbStruct {
+    constructor(type, fields) {
+        this.type = type;
+        this.fields = fields;
+    }
+}
+
+class TbFunction {
+    // `code` takes the array of arguments.
+    constructor(name, arity, code) {
+        this.name = name;
+        this.arity = arity;
+        this.code = code;
+    }
+}
+
+class TbBuiltin {
+    // `code` takes the array of arguments and the location of the call.
+    constructor(name, code) {
+        this.name = name;
+        this.code = code;
+    }
+}
+
+let tbDepth = 0;
+
+function tbFail(at, message) {
+    throw new TbError(at, message);
+}
+
+// Runs the program, returning its exit status.
+function tbRun(program) {
+    try {
+        program();
+        return 0;
+    } catch (e) {
+        if (e instanceof TbExit) {
+            return e.code;
+        }
+        if (e instanceof TbError) {
+            tbError(`${e.line}:${e.column}: ${e.message}`);
+            return 1;
+        }
+        throw e;
+    }
+}
+
+// Values
+
+function tbTypeName(v) {
+    switch (typeof v) {
+    case "undefined": return "nil";
+    case "bigint": return "int";
+    case "number": return "float";
+    case "boolean": return "bool";
+    case "string": return "string";
+    }
+    if (v === null) return "nil";
+    if (Array.isArray(v)) return "array";
+    if (v instanceof Map) return "map";
+    if (v instanceof TbStruct) return "struct";
+    return "function";
+}
+
+function tbTruthy(v) {
+    switch (typeof v) {
+    case "undefined": return false;
+    case "bigint": return v !== 0n;
+    case "number": return v !== 0;
+    case "boolean": return v;
+    case "string": return v.length !== 0;
+    }
+    if (v === null) return false;
+    if (Array.isArray(v)) return v.length !== 0;
+    if (v instanceof Map) return v.size !== 0;
+    return true;
+}
+
+function tbChecked(n, at) {
+    if (n < TB_INT_MIN || n > TB_INT_MAX) {
+        tbFail(at, "integer overflow");
+    }
+    return n;
+}
+
+// Variables
+
+function tbGlobal(value, name, at) {
+    if (value === undefined) {
+        tbFail(at, `undefined variable \`${name}\``);
+    }
+    return value;
+}
+
+// A global being called.
+function tbGlobalFunction(value, name, at) {
+    if (value === undefined) {
+        tbFail(at, `call to undefined function \`${name}\``);
+    }
+    return value;
+}
+
+// Printing
+
+function tbShowFloat(f) {
+    if (Number.isNaN(f)) return "NaN";
+    if (f === Infinity) return "inf";
+    if (f === -Infinity) return "-inf";
+    const sign = f < 0 || Object.is(f, -0) ? "-" : "";
+    f = Math.abs(f);
+    if (f === 0) return sign + "0.0";
+    // The fewest digits reading back as `f`
+    const [mantissa, exp] = f.toExponential().split("e");
+    const digits = mantissa.replace(".", "");
+    const exponent = Number(exp);
+    if (exponent < -4 || exponent >= 16) {
+        return sign + digits[0] + (digits.length > 1 ? "." + digits.slice(1) : "") + "e" + exponent;
+    }
+    if (exponent < 0) {
+        return sign + "0." + "0".repeat(-exponent - 1) + digits;
+    }
+    if (digits.length > exponent + 1) {
+        return sign + digits.slice(0, exponent + 1) + "." + digits.slice(exponent + 1);
+    }
+    return sign + digits + "0".repeat(exponent + 1 - digits.length) + ".0";
+}
+
+// A string in double quotes with escapes, as nested values print.
+function tbQuoted(s) {
+    let quoted = "\"";
+    for (const c of s) {
+        switch (c) {
+        case "\"": quoted += "\\\""; break;
+        case "\\": quoted += "\\\\"; break;
+        case "\n": quoted += "\\n"; break;
+        case "\r": quoted += "\\r"; break;
+        case "\t": quoted += "\\t"; break;
+        case "\0": quoted += "\\0"; break;
+        default: {
+            const code = c.codePointAt(0);
+            quoted += code < 0x20 || code === 0x7f ? `\\u{${code.toString(16)}}` : c;
+        }
+        }
+    }
+    return quoted + "\"";
+}
+
+function tbShow(v, nested) {
+    switch (typeof v) {
+    case "undefined": return "nil";
+    case "bigint": return v.toString();
+    case "number": return tbShowFloat(v);
+    case "boolean": return v ? "true" : "false";
+    case "string": return nested ? tbQuoted(v) : v;
+    }
+    if (v === null) return "nil";
+    if (Array.isArray(v)) {
+        return "[" + v.map((item) => tbShow(item, true)).join(", ") + "]";
+    }
+    if (v instanceof Map) {
+        return "{" + Array.from(v, ([key, value]) => tbQuoted(key) + ": " + tbShow(value, true)).join(", ") + "}";
+    }
+    if (v instanceof TbStruct) {
+        const fields = v.type.fields.map((field, i) => field + ": " + tbShow(v.fields[i], true));
+        return v.type.name + (fields.length ? " { " + fields.join(", ") + " }" : " {}");
+    }
+    if (v instanceof TbType) return `<struct ${v.name}>`;
+    if (v instanceof TbFunction) return `<fn ${v.name}>`;
+    return `<builtin ${v.name}>`;
+}
+
+function tbPrintValues(values) {
+    tbPrint(values.map((v) => tbShow(v, false)).join(" "));
+}
+
+// Operators
+
+// Orders strings by Unicode character like the interpreter, rather than by
+// UTF-16 unit.
+function tbCompareStrings(a, b) {
+    const left = a[Symbol.iterator]();
+    const right = b[Symbol.iterator]();
+    for (;;) {
+        const l = left.next();
+        const r = right.next();
+        if (l.done || r.done) {
+            return l.done ? (r.done ? 0 : -1) : 1;
+        }
+        const difference = l.value.codePointAt(0) - r.value.codePointAt(0);
+        if (difference !== 0) {
+            return difference;
+        }
+    }
+}
+
+function tbEqual(a, b) {
+    if (tbTypeName(a) !== tbTypeName(b)) {
+        return false;
+    }
+    if (Array.isArray(a)) {
+        return a.length === b.length && a.every((item, i) => tbEqual(item, b[i]));
+    }
+    if (a instanceof Map) {
+        if (a.size !== b.size) {
+            return false;
+        }
+        for (const [key, value] of a) {
+            if (!b.has(key) || !tbEqual(value, b.get(key))) {
+                return false;
+            }
+        }
+        return true;
+    }
+    if (a instanceof TbStruct) {
+        return a.type === b.type && a.fields.every((field, i) => tbEqual(field, b.fields[i]));
+    }
+    if (a === undefined || a === null) {
+        return b === undefined || b === null;
+    }
+    return a === b;
+}
+
+const TB_OP_NAMES = {
+    "+": "Add", "-": "Sub", "*": "Mul", "/": "Div", "==": "Equals", "!=": "NotEquals",
+    "<": "SmallerThan", ">": "GreaterThan", "<=": "SmallerEquals", ">=": "GreaterEquals",
+};
+
+function tbCompare(op, order) {
+    switch (op) {
+    case "<": return order < 0;
+    case ">": return order > 0;
+    case "<=": return order <= 0;
+    default: return order >= 0;
+    }
+}
+
+function tbBinary(op, l, r, at) {
+    if (typeof l === "bigint" && typeof r === "number") {
+        l = Number(l);
+    } else if (typeof l === "number" && typeof r === "bigint") {
+        r = Number(r);
+    }
+    if (op === "==") return tbEqual(l, r);
+    if (op === "!=") return !tbEqual(l, r);
+    if (typeof l === "bigint" && typeof r === "bigint") {
+        switch (op) {
+        case "+": return tbChecked(l + r, at);
+        case "-": return tbChecked(l - r, at);
+        case "*": return tbChecked(l * r, at);
+        case "/":
+            if (r === 0n) tbFail(at, "division by zero");
+            return tbChecked(l / r, at);
+        default: return tbCompare(op, l < r ? -1 : l > r ? 1 : 0);
+        }
+    }
+    if (typeof l === "number" && typeof r === "number") {
+        switch (op) {
+        case "+": return l + r;
+        case "-": return l - r;
+        case "*": return l * r;
+        case "/": return l / r;
+        case "<": return l < r;
+        case ">": return l > r;
+        case "<=": return l <= r;
+        default: return l >= r;
+        }
+    }
+    if (typeof l === "string" && typeof r === "string") {
+        if (op === "+") return l + r;
+        if (op !== "-" && op !== "*" && op !== "/") return tbCompare(op, tbCompareStrings(l, r));
+    }
+    return tbFail(at, `type mismatch: unsupported operands for ${TB_OP_NAMES[op]}: ${tbTypeName(l)} and ${tbTypeName(r)}`);
+}
+
+function tbNeg(v, at) {
+    if (typeof v === "bigint") return tbChecked(-v, at);
+    if (typeof v === "number") return -v;
+    return tbFail(at, `type mismatch: cannot negate ${tbTypeName(v)}`);
+}
+
+// Arrays, maps and structs
+
+function tbArrayIndex(index, length, at) {
+    if (typeof index !== "bigint") {
+        tbFail(at, `type mismatch: array index must be an int, found ${tbTypeName(index)}`);
+    }
+    if (index < 0n || index >= BigInt(length)) {
+        tbFail(at, `index ${index} is out of bounds for an array of length ${length}`);
+    }
+    return Number(index);
+}
+
+function tbKey(key, at) {
+    if (typeof key !== "string") {
+        tbFail(at, `type mismatch: map keys must be strings, found ${tbTypeName(key)}`);
+    }
+    return key;
+}
+
+function tbIndex(target, index, targetAt, indexAt) {
+    if (Array.isArray(target)) {
+        return target[tbArrayIndex(index, target.length, indexAt)];
+    }
+    if (target instanceof Map) {
+        const key = tbKey(index, indexAt);
+        if (!target.has(key)) {
+            tbFail(indexAt, `map has no key ${tbQuoted(key)}`);
+        }
+        return target.get(key);
+    }
+    return tbFail(targetAt, `type mismatch: cannot index into ${tbTypeName(target)}`);
+}
+
+function tbSetIndex(target, index, value, targetAt, indexAt) {
+    if (Array.isArray(target)) {
+        target[tbArrayIndex(index, target.length, indexAt)] = value;
+    } else if (target instanceof Map) {
+        target.set(tbKey(index, indexAt), value);
+    } else {
+        tbFail(targetAt, `type mismatch: cannot index into ${tbTypeName(target)}`);
+    }
+}
+
+function tbFieldIndex(target, field, targetAt, at) {
+    if (!(target instanceof TbStruct)) {
+        tbFail(targetAt, `type mismatch: ${tbTypeName(target)} has no fields`);
+    }
+    const i = target.type.fields.indexOf(field);
+    if (i < 0) {
+        tbFail(at, `struct \`${target.type.name}\` has no field \`${field}\``);
+    }
+    return i;
+}
+
+function tbField(target, field, targetAt, at) {
+    return target.fields[tbFieldIndex(target, field, targetAt, at)];
+}
+
+function tbSetField(target, field, value, targetAt) {
+    target.fields[tbFieldIndex(target, field, targetAt, targetAt)] = value;
+}
+
+// Calls
+
+function tbExpectArgs(name, expected, found, at) {
+    if (expected !== found) {
+        const s = expected === 1 ? "" : "s";
+        tbFail(at, `\`${name}\` expects ${expected} argument${s}, but ${found} ${found === 1 ? "was" : "were"} given`);
+    }
+}
+
+function tbCall(callee, args, at, calleeAt) {
+    if (callee instanceof TbFunction) {
+        tbExpectArgs(callee.name, callee.arity, args.length, at);
+        if (tbDepth >= TB_MAX_DEPTH) {
+            tbFail(at, `stack overflow in script: more than ${TB_MAX_DEPTH} nested calls`);
+        }
+        tbDepth++;
+        try {
+            return callee.code(args);
+        } finally {
+            tbDepth--;
+        }
+    }
+    if (callee instanceof TbBuiltin) {
+        return callee.code(args, at);
+    }
+    if (callee instanceof TbType) {
+        tbExpectArgs(callee.name, callee.fields.length, args.length, at);
+        return new TbStruct(callee, args);
+    }
+    return tbFail(calleeAt, `value of type ${tbTypeName(callee)} is not callable`);
+}
+
+// Classic `gosub`
+
+const tbReturns = [];
+
+function tbGosub(resume, at) {
+    if (tbReturns.length >= TB_MAX_DEPTH) {
+        tbFail(at, `stack overflow in script: more than ${TB_MAX_DEPTH} nested calls`);
+    }
+    tbReturns.push(resume);
+}
+
+function tbSubReturn(at) {
+    if (tbReturns.length === 0) {
+        tbFail(at, "`return` without a pending `gosub`");
+    }
+    return tbReturns.pop();
+}
+
+// Builtins
+
+function tbNumber(name, v, at) {
+    if (typeof v === "bigint") return Number(v);
+    if (typeof v !== "number") {
+        tbFail(at, `type mismatch: \`${name}\` expects a number, found ${tbTypeName(v)}`);
+    }
+    return v;
+}
+
+function tbExpect(name, v, type, article, at) {
+    if (tbTypeName(v) !== type) {
+        tbFail(at, `type mismatch: \`${name}\` expects ${article} ${type}, found ${tbTypeName(v)}`);
+    }
+    return v;
+}
+
+function tbInvalid(at, message) {
+    tbFail(at, `invalid argument: ${message}`);
+}
+
+// Parses an int the way Rust's `str::parse::<i64>` does.
+function tbParseInt(s) {
+    if (!/^[+-]?[0-9]+$/.test(s)) {
+        return null;
+    }
+    const n = BigInt(s);
+    return n < TB_INT_MIN || n > TB_INT_MAX ? null : n;
+}
+
+const TB_SPACE = "[\\t-\\r \\u0085\\u00a0\\u1680\\u2000-\\u200a\\u2028\\u2029\\u202f\\u205f\\u3000]";
+const TB_TRIM = new RegExp(`^${TB_SPACE}+|${TB_SPACE}+$`, "g");
+
+function tbTrim(s) {
+    return s.replace(TB_TRIM, "");
+}
+
+function tbBuiltinInput(args, at) {
+    if (args.length > 1) {
+        tbExpectArgs("input", 1, args.length, at);
+    }
+    let line = tbInput(args.length === 1 ? tbShow(args[0], false) : "");
+    if (line === null || line === undefined) {
+        return null;
+    }
+    line = String(line).replace(/\r+$/, "");
+    const n = tbParseInt(tbTrim(line));
+    return n === null ? line : n;
+}
+
+function tbBuiltinAbs(args, at) {
+    tbExpectArgs("abs", 1, args.length, at);
+    if (typeof args[0] === "bigint") {
+        return tbChecked(args[0] < 0n ? -args[0] : args[0], at);
+    }
+    return Math.abs(tbNumber("abs", args[0], at));
+}
+
+function tbExtremum(name, args, at, smallest) {
+    if (args.length < 2) {
+        tbExpectArgs(name, 2, args.length, at);
+    }
+    let best = 0;
+    let bestNumber = tbNumber(name, args[0], at);
+    for (let i = 1; i < args.length; i++) {
+        const n = tbNumber(name, args[i], at);
+        if (smallest ? n < bestNumber : n > bestNumber) {
+            best = i;
+            bestNumber = n;
+        }
+    }
+    return args[best];
+}
+
+function tbBuiltinMin(args, at) {
+    return tbExtremum("min", args, at, true);
+}
+
+function tbBuiltinMax(args, at) {
+    return tbExtremum("max", args, at, false);
+}
+
+function tbBuiltinSqrt(args, at) {
+    tbExpectArgs("sqrt", 1, args.length, at);
+    const n = tbNumber("sqrt", args[0], at);
+    if (n < 0) {
+        tbInvalid(at, "cannot take the square root of a negative number");
+    }
+    return Math.sqrt(n);
+}
+
+function tbBuiltinPow(args, at) {
+    tbExpectArgs("pow", 2, args.length, at);
+    let [base, exp] = args;
+    if (typeof base === "bigint" && typeof exp === "bigint" && exp >= 0n) {
+        if (exp > 0xffffffffn) {
+            tbFail(at, "integer overflow");
+        }
+        // Squaring only overflows if the result would
+        let result = 1n;
+        while (exp > 0n) {
+            if (exp & 1n) {
+                result = tbChecked(result * base, at);
+            }
+            exp >>= 1n;
+            if (exp > 0n) {
+                base = tbChecked(base * base, at);
+            }
+        }
+        return result;
+    }
+    return Math.pow(tbNumber("pow", base, at), tbNumber("pow", exp, at));
+}
+
+function tbRounding(name, args, at, op) {
+    tbExpectArgs(name, 1, args.length, at);
+    if (typeof args[0] === "bigint") {
+        return args[0];
+    }
+    const n = op(tbNumber(name, args[0], at));
+    if (!(n >= -(2 ** 63) && n < 2 ** 63)) {
+        tbFail(at, "integer overflow");
+    }
+    return BigInt(n);
+}
+
+function tbBuiltinFloor(args, at) {
+    return tbRounding("floor", args, at, Math.floor);
+}
+
+function tbBuiltinCeil(args, at) {
+    return tbRounding("ceil", args, at, Math.ceil);
+}
+
+function tbBuiltinRound(args, at) {
+    // Halfway cases round away from zero
+    return tbRounding("round", args, at, (n) => Math.sign(n) * Math.round(Math.abs(n)));
+}
+
+function tbBuiltinLen(args, at) {
+    tbExpectArgs("len", 1, args.length, at);
+    if (Array.isArray(args[0])) return BigInt(args[0].length);
+    if (args[0] instanceof Map) return BigInt(args[0].size);
+    return BigInt(Array.from(tbExpect("len", args[0], "string", "a", at)).length);
+}
+
+function tbBuiltinSubstr(args, at) {
+    if (args.length !== 2) {
+        tbExpectArgs("substr", 3, args.length, at);
+    }
+    const chars = Array.from(tbExpect("substr", args[0], "string", "a", at));
+    const start = tbExpect("substr", args[1], "int", "an", at);
+    if (start < 0n || start > BigInt(chars.length)) {
+        tbFail(at, `invalid argument: substring start ${start} is out of range for a string of length ${chars.length}`);
+    }
+    let end = chars.length;
+    if (args.length === 3) {
+        const count = tbExpect("substr", args[2], "int", "an", at);
+        if (count < 0n) {
+            tbFail(at, `invalid argument: substring length ${count} is negative`);
+        }
+        if (count < BigInt(chars.length) - start) {
+            end = Number(start + count);
+        }
+    }
+    return chars.slice(Number(start), end).join("");
+}
+
+function tbBuiltinUpper(args, at) {
+    tbExpectArgs("upper", 1, args.length, at);
+    return tbExpect("upper", args[0], "string", "a", at).toUpperCase();
+}
+
+function tbBuiltinLower(args, at) {
+    tbExpectArgs("lower", 1, args.length, at);
+    return tbExpect("lower", args[0], "string", "a", at).toLowerCase();
+}
+
+function tbBuiltinTrim(args, at) {
+    tbExpectArgs("trim", 1, args.length, at);
+    return tbTrim(tbExpect("trim", args[0], "string", "a", at));
+}
+
+function tbBuiltinSplit(args, at) {
+    tbExpectArgs("split", 2, args.length, at);
+    const s = tbExpect("split", args[0], "string", "a", at);
+    const separator = tbExpect("split", args[1], "string", "a", at);
+    return separator === "" ? Array.from(s) : s.split(separator);
+}
+
+function tbBuiltinContains(args, at) {
+    tbExpectArgs("contains", 2, args.length, at);
+    const s = tbExpect("contains", args[0], "string", "a", at);
+    return s.includes(tbExpect("contains", args[1], "string", "a", at));
+}
+
+function tbBuiltinReplace(args, at) {
+    tbExpectArgs("replace", 3, args.length, at);
+    const s = tbExpect("replace", args[0], "string", "a", at);
+    const from = tbExpect("replace", args[1], "string", "a", at);
+    const to = tbExpect("replace", args[2], "string", "a", at);
+    if (from === "") {
+        // An empty pattern matches around every character
+        return to + Array.from(s, (c) => c + to).join("");
+    }
+    return s.split(from).join(to);
+}
+
+function tbBuiltinFormat(args, at) {
+    if (args.length === 0) {
+        tbExpectArgs("format", 1, args.length, at);
+    }
+    const pattern = tbExpect("format", args[0], "string", "a", at);
+    let formatted = "";
+    let next = 1;
+    for (let i = 0; i < pattern.length; i++) {
+        const c = pattern[i];
+        const following = pattern[i + 1];
+        if ((c === "{" && following === "{") || (c === "}" && following === "}")) {
+            formatted += c;
+            i++;
+        } else if (c === "{" && following === "}") {
+            if (next === args.length) {
+                tbInvalid(at, "more `{}` than arguments in format string");
+            }
+            formatted += tbShow(args[next++], false);
+            i++;
+        } else if (c === "{" || c === "}") {
+            tbInvalid(at, "unmatched brace in format string, use `{{` or `}}` for a literal one");
+        } else {
+            formatted += c;
+        }
+    }
+    if (next !== args.length) {
+        tbInvalid(at, "more arguments than `{}` in format string");
+    }
+    return formatted;
+}
+
+function tbBuiltinPush(args, at) {
+    tbExpectArgs("push", 2, args.length, at);
+    tbExpect("push", args[0], "array", "an", at).push(args[1]);
+    return null;
+}
+
+function tbBuiltinPop(args, at) {
+    tbExpectArgs("pop", 1, args.length, at);
+    const array = tbExpect("pop", args[0], "array", "an", at);
+    if (array.length === 0) {
+        tbInvalid(at, "cannot pop from an empty array");
+    }
+    return array.pop();
+}
+
+function tbBuiltinInsert(args, at) {
+    tbExpectArgs("insert", 3, args.length, at);
+    const array = tbExpect("insert", args[0], "array", "an", at);
+    const index = args[1] === BigInt(array.length) ? array.length : tbArrayIndex(args[1], array.length, at);
+    array.splice(index, 0, args[2]);
+    return null;
+}
+
+function tbBuiltinRemove(args, at) {
+    tbExpectArgs("remove", 2, args.length, at);
+    if (args[0] instanceof Map) {
+        const key = tbKey(args[1], at);
+        if (!args[0].has(key)) {
+            tbFail(at, `map has no key ${tbQuoted(key)}`);
+        }
+        const removed = args[0].get(key);
+        args[0].delete(key);
+        return removed;
+    }
+    const array = tbExpect("remove", args[0], "array", "an", at);
+    return array.splice(tbArrayIndex(args[1], array.length, at), 1)[0];
+}
+
+function tbBuiltinSort(args, at) {
+    tbExpectArgs("sort", 1, args.length, at);
+    const array = tbExpect("sort", args[0], "array", "an", at);
+    // The first pair of elements that couldn't be compared
+    let mismatch = null;
+    const less = (a, b) => {
+        if (typeof a === "string" && typeof b === "string") return tbCompareStrings(a, b) < 0;
+        if (typeof a === "bigint" && typeof b === "bigint") return a < b;
+        const numbers = ["bigint", "number"];
+        if (numbers.includes(typeof a) && numbers.includes(typeof b)) return Number(a) < Number(b);
+        mismatch = mismatch || [tbTypeName(a), tbTypeName(b)];
+        return false;
+    };
+    const sort = (items) => {
+        if (items.length <= 20) {
+            for (let i = 1; i < items.length; i++) {
+                const item = items[i];
+                let j = i;
+                for (; j > 0 && less(item, items[j - 1]); j--) {
+                    items[j] = items[j - 1];
+                }
+                items[j] = item;
+            }
+            return items;
+        }
+        const half = items.length >> 1;
+        const left = sort(items.slice(0, half));
+        const right = sort(items.slice(half));
+        let i = 0;
+        let j = 0;
+        for (let k = 0; i < left.length; k++) {
+            items[k] = j < right.length && less(right[j], left[i]) ? right[j++] : left[i++];
+        }
+        return items;
+    };
+    sort(array);
+    if (mismatch) {
+        tbFail(at, `type mismatch: cannot compare ${mismatch[0]} with ${mismatch[1]}`);
+    }
+    return null;
+}
+
+function tbBuiltinReverse(args, at) {
+    tbExpectArgs("reverse", 1, args.length, at);
+    tbExpect("reverse", args[0], "array", "an", at).reverse();
+    return null;
+}
+
+function tbBuiltinKeys(args, at) {
+    tbExpectArgs("keys", 1, args.length, at);
+    return Array.from(tbExpect("keys", args[0], "map", "a", at).keys());
+}
+
+function tbBuiltinValues(args, at) {
+    tbExpectArgs("values", 1, args.length, at);
+    return Array.from(tbExpect("values", args[0], "map", "a", at).values());
+}
+
+function tbBuiltinHas(args, at) {
+    tbExpectArgs("has", 2, args.length, at);
+    return tbExpect("has", args[0], "map", "a", at).has(tbKey(args[1], at));
+}
+
+// SplitMix64, as in the interpreter, so seeded programs draw the same
+// numbers.
+const TB_U64 = 2n ** 64n - 1n;
+let tbRngState = (BigInt(Date.now()) * 1000000007n) & TB_U64;
+
+function tbNextU64() {
+    tbRngState = (tbRngState + 0x9e3779b97f4a7c15n) & TB_U64;
+    let z = tbRngState;
+    z = ((z ^ (z >> 30n)) * 0xbf58476d1ce4e5b9n) & TB_U64;
+    z = ((z ^ (z >> 27n)) * 0x94d049bb133111ebn) & TB_U64;
+    return z ^ (z >> 31n);
+}
+
+function tbBuiltinRnd(args, at) {
+    tbExpectArgs("rnd", 0, args.length, at);
+    return Number(tbNextU64() >> 11n) / 2 ** 53;
+}
+
+function tbBuiltinRandom(args, at) {
+    tbExpectArgs("random", 2, args.length, at);
+    const min = tbExpect("random", args[0], "int", "an", at);
+    const max = tbExpect("random", args[1], "int", "an", at);
+    if (min > max) {
+        tbFail(at, `invalid argument: random range is empty: ${min} is greater than ${max}`);
+    }
+    const span = max - min;
+    if (span === TB_U64) {
+        return BigInt.asIntN(64, tbNextU64());
+    }
+    const bound = span + 1n;
+    const zone = TB_U64 - (TB_U64 % bound) - 1n;
+    let n;
+    do {
+        n = tbNextU64();
+    } while (n > zone);
+    return min + (n % bound);
+}
+
+function tbBuiltinSeed(args, at) {
+    tbExpectArgs("seed", 1, args.length, at);
+    tbRngState = BigInt.asUintN(64, tbExpect("seed", args[0], "int", "an", at));
+    return null;
+}
+
+function tbBuiltinNow(args, at) {
+    tbExpectArgs("now", 0, args.length, at);
+    return Date.now() / 1000;
+}
+
+function tbBuiltinClockMs(args, at) {
+    tbExpectArgs("clock_ms", 0, args.length, at);
+    return BigInt(Math.max(0, Date.now() - TB_STARTED));
+}
+
+function tbBuiltinGetenv(args, at) {
+    tbExpectArgs("getenv", 1, args.length, at);
+    const value = tbEnv[tbExpect("getenv", args[0], "string", "a", at)];
+    return value === undefined ? null : String(value);
+}
+
+function tbBuiltinArgs(args, at) {
+    tbExpectArgs("args", 0, args.length, at);
+    return tbArgs.map(String);
+}
+
+function tbBuiltinExit(args, at) {
+    if (args.length > 1) {
+        tbExpectArgs("exit", 1, args.length, at);
+    }
+    const code = args.length === 1 ? tbExpect("exit", args[0], "int", "an", at) : 0n;
+    if (code < -(2n ** 31n) || code >= 2n ** 31n) {
+        tbFail(at, `invalid argument: exit code ${code} is out of range`);
+    }
+    throw new TbExit(Number(code));
+}
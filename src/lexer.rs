@@ -1,23 +1,47 @@
-use std::str::Chars;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt;
+use core::str::Chars;
 
 use crate::lexer::Token::*;
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Token {
-    Number(i32),
+    Number(i64),
+    Float(f64),
+    Str(String),
     Plus,
     Minus,
     Multiply,
     Divide,
     Lparen,
     Rparen,
+    Lbracket,
+    Rbracket,
+    Comma,
+    Colon,
+    Dot,
     Id(String),
     Assign,
     If,
     Else,
+    While,
+    Break,
+    Continue,
+    Print,
+    Fn,
+    Return,
+    Global,
+    Let,
+    Struct,
+    Import,
+    Goto,
+    Gosub,
     CurlyL,
     CurlyR,
     Equals,
+    NotEquals,
     SmallerThan,
     GreaterThan,
     SmallerEquals,
@@ -25,131 +49,437 @@ pub enum Token {
     Not,
     And,
     Or,
+    Newline,
+    /// A line starting with `##`, documenting the function or struct after
+    /// it. The text leaves out the `##`, one space after it and the newline.
+    Doc(String),
 }
 
+impl Token {
+    /// The name of the token's variant, e.g. `Id` or `CurlyL`, for tools
+    /// that consume the token stream.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Number(_) => "Number",
+            Float(_) => "Float",
+            Str(_) => "Str",
+            Plus => "Plus",
+            Minus => "Minus",
+            Multiply => "Multiply",
+            Divide => "Divide",
+            Lparen => "Lparen",
+            Rparen => "Rparen",
+            Lbracket => "Lbracket",
+            Rbracket => "Rbracket",
+            Comma => "Comma",
+            Colon => "Colon",
+            Dot => "Dot",
+            Id(_) => "Id",
+            Assign => "Assign",
+            If => "If",
+            Else => "Else",
+            While => "While",
+            Break => "Break",
+            Continue => "Continue",
+            Print => "Print",
+            Fn => "Fn",
+            Return => "Return",
+            Global => "Global",
+            Let => "Let",
+            Struct => "Struct",
+            Import => "Import",
+            Goto => "Goto",
+            Gosub => "Gosub",
+            CurlyL => "CurlyL",
+            CurlyR => "CurlyR",
+            Equals => "Equals",
+            NotEquals => "NotEquals",
+            SmallerThan => "SmallerThan",
+            GreaterThan => "GreaterThan",
+            SmallerEquals => "SmallerEquals",
+            GreaterEquals => "GreaterEquals",
+            Not => "Not",
+            And => "And",
+            Or => "Or",
+            Newline => "Newline",
+            Doc(_) => "Doc",
+        }
+    }
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Number(n) => write!(f, "{}", n),
+            Float(n) => write!(f, "{:?}", n),
+            Str(s) => write!(f, "{:?}", s),
+            Id(id) => write!(f, "{}", id),
+            Plus => write!(f, "+"),
+            Minus => write!(f, "-"),
+            Multiply => write!(f, "*"),
+            Divide => write!(f, "/"),
+            Lparen => write!(f, "("),
+            Rparen => write!(f, ")"),
+            Lbracket => write!(f, "["),
+            Rbracket => write!(f, "]"),
+            Comma => write!(f, ","),
+            Colon => write!(f, ":"),
+            Dot => write!(f, "."),
+            Assign => write!(f, "="),
+            If => write!(f, "if"),
+            Else => write!(f, "else"),
+            While => write!(f, "while"),
+            Break => write!(f, "break"),
+            Continue => write!(f, "continue"),
+            Print => write!(f, "print"),
+            Fn => write!(f, "fn"),
+            Return => write!(f, "return"),
+            Goto => write!(f, "goto"),
+            Gosub => write!(f, "gosub"),
+            Global => write!(f, "global"),
+            Let => write!(f, "let"),
+            Struct => write!(f, "struct"),
+            Import => write!(f, "import"),
+            CurlyL => write!(f, "{{"),
+            CurlyR => write!(f, "}}"),
+            Equals => write!(f, "=="),
+            NotEquals => write!(f, "!="),
+            SmallerThan => write!(f, "<"),
+            GreaterThan => write!(f, ">"),
+            SmallerEquals => write!(f, "<="),
+            GreaterEquals => write!(f, ">="),
+            Not => write!(f, "!"),
+            And => write!(f, "&&"),
+            Or => write!(f, "||"),
+            Newline => write!(f, "newline"),
+            Doc(text) => write!(f, "## {}", text),
+        }
+    }
+}
+
+/// The words the lexer reads as keywords rather than identifiers.
+pub const KEYWORDS: [&str; 14] =
+    ["if", "else", "while", "break", "continue", "print", "fn", "return", "global", "let", "struct", "import", "goto", "gosub"];
+
+/// Location of a token in the source. `start` and `end` are character
+/// offsets, `line` and `column` are 1-based.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Span {
+    /// Smallest span covering both `self` and `other`.
+    pub fn to(self, other: Span) -> Span {
+        Span { end: other.end, ..self }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpannedToken {
+    pub token: Token,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LexError {
+    /// The error's [stable code](crate::codes), e.g. `E0001`.
+    pub code: &'static str,
+    pub message: String,
+    pub span: Span,
+}
+
+impl fmt::Display for LexError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.span.line, self.span.column, self.message)
+    }
+}
+
+impl core::error::Error for LexError {}
+
 pub struct Lexer<'a> {
     input: Chars<'a>,
     position: usize,
+    line: usize,
+    column: usize,
+    // Newlines inside parentheses or brackets don't end a statement
+    depth: usize,
 }
 
 impl<'a> Lexer<'a> {
-    pub fn new(input: &str) -> Lexer {
-        Lexer { input: input.chars(), position: 0 }
+    /// A `#!` line at the very start, as in `#!/usr/bin/env tbasic`, is
+    /// skipped so scripts can be run directly on Unix.
+    pub fn new(input: &str) -> Lexer<'_> {
+        let mut lexer = Lexer { input: input.chars(), position: 0, line: 1, column: 1, depth: 0 };
+        if input.starts_with("#!") {
+            while lexer.lookahead().is_some_and(|char| char != '\n') {
+                lexer.advance();
+            }
+        }
+        lexer
     }
 
+    /// Tokenizes the input, stopping at the first character that can't be lexed.
     pub fn tokenize(&mut self) -> Vec<Token> {
         let mut tokens = Vec::new();
-        while let Some(token) = self.next_token() {
+        while let Ok(Some(token)) = self.next_token() {
             tokens.push(token);
         }
         tokens
     }
 
-    fn next_token(&mut self) -> Option<Token> {
-        if let Some(char) = self.input.next() {
-            self.position += 1;
-            return match char {
+    /// Tokenizes the input, keeping the location of every token.
+    pub fn tokenize_spanned(&mut self) -> Result<Vec<SpannedToken>, LexError> {
+        let mut tokens = Vec::new();
+        loop {
+            self.skip_whitespace();
+            let start = self.span_here();
+            match self.next_token()? {
+                Some(token) => tokens.push(SpannedToken { token, span: Span { end: self.position, ..start } }),
+                None => break,
+            }
+        }
+        Ok(tokens)
+    }
+
+    /// Like [`tokenize_spanned`](Lexer::tokenize_spanned), but carries on
+    /// after an error with the characters following those that caused it,
+    /// for tools that work on input that is still being typed.
+    pub fn tokenize_recovering(&mut self) -> (Vec<SpannedToken>, Vec<LexError>) {
+        let mut tokens = Vec::new();
+        let mut errors = Vec::new();
+        loop {
+            self.skip_whitespace();
+            let start = self.span_here();
+            match self.next_token() {
+                Ok(Some(token)) => tokens.push(SpannedToken { token, span: Span { end: self.position, ..start } }),
+                Ok(None) => break,
+                Err(error) => errors.push(error),
+            }
+        }
+        (tokens, errors)
+    }
+
+    fn next_token(&mut self) -> Result<Option<Token>, LexError> {
+        self.skip_whitespace();
+        let start = self.span_here();
+        if let Some(char) = self.advance() {
+            let token = match char {
                 '0'..='9' => {
-                    let mut total = char.to_digit(10)? as i32;
-                    // Look ahead in a clone to see if the next character is also a digit
-                    while let Some(ch) = self.input.clone().next() {
-                        if let Some(num) = ch.to_digit(10) {
-                            total = total * 10 + num as i32;
-                            // Consume the character on the real iterator
-                            self.input.next();
-                        } else {
-                            break
-                        }
+                    let mut digits = char.to_string();
+                    self.read_digits(&mut digits);
+                    // A `.` only starts a fraction if a digit follows it
+                    let mut ahead = self.input.clone();
+                    if ahead.next() == Some('.') && ahead.next().is_some_and(|ch| ch.is_ascii_digit()) {
+                        self.advance();
+                        digits.push('.');
+                        self.read_digits(&mut digits);
+                        Float(digits.parse().unwrap())
+                    } else {
+                        Number(digits.parse().map_err(|_| self.error("E0004", "integer literal is too large", start))?)
                     }
-                    Some(Number(total))
                 },
-                'a'..='z' => {
+                'a'..='z' | 'A'..='Z' | '_' => {
                     let mut id = self.read_string();
                     id.insert(0, char);
 
                     // Check if the id is a keyword
                     match id.as_str() {
-                        "if" => Some(If),
-                        "else" => Some(Else),
-                        _ => Some(Id(id)),
+                        "if" => If,
+                        "else" => Else,
+                        "while" => While,
+                        "break" => Break,
+                        "continue" => Continue,
+                        "print" => Print,
+                        "fn" => Fn,
+                        "return" => Return,
+                        "global" => Global,
+                        "let" => Let,
+                        "struct" => Struct,
+                        "import" => Import,
+                        "goto" => Goto,
+                        "gosub" => Gosub,
+                        _ => Id(id),
                     }
                 },
-                '+' => Some(Plus),
-                '-' => Some(Minus),
-                '*' => Some(Multiply),
-                '/' => Some(Divide),
-                '(' => Some(Lparen),
-                ')' => Some(Rparen),
-                ' ' => self.next_token(),
+                '"' => Str(self.read_quoted(start)?),
+                '+' => Plus,
+                '-' => Minus,
+                '*' => Multiply,
+                '/' => Divide,
+                '(' => {
+                    self.depth += 1;
+                    Lparen
+                },
+                ')' => {
+                    self.depth = self.depth.saturating_sub(1);
+                    Rparen
+                },
+                '[' => {
+                    self.depth += 1;
+                    Lbracket
+                },
+                ']' => {
+                    self.depth = self.depth.saturating_sub(1);
+                    Rbracket
+                },
+                ',' => Comma,
+                ':' => Colon,
+                '.' => Dot,
+                '\n' => Newline,
                 '=' => {
-                    if let Some('=') = self.lookahead() {
-                        self.input.next();
-                        Some(Equals)
+                    if self.eat('=') {
+                        Equals
                     } else {
-                        Some(Assign)
+                        Assign
                     }
                 },
-                '{' => Some(CurlyL),
-                '}' => Some(CurlyR),
+                '{' => CurlyL,
+                '}' => CurlyR,
                 '<' => {
-                    if let Some('=') = self.lookahead() {
-                        self.input.next();
-                        Some(SmallerEquals)
+                    if self.eat('=') {
+                        SmallerEquals
                     } else {
-                        Some(SmallerThan)
+                        SmallerThan
                     }
                 },
                 '>' => {
-                    if let Some('=') = self.lookahead() {
-                        self.input.next();
-                        Some(GreaterEquals)
+                    if self.eat('=') {
+                        GreaterEquals
+                    } else {
+                        GreaterThan
+                    }
+                },
+                '!' => {
+                    if self.eat('=') {
+                        NotEquals
                     } else {
-                        Some(GreaterThan)
+                        Not
                     }
                 },
-                '!' => Some(Not),
                 '&' => {
-                    if let Some('&') = self.lookahead() {
-                        self.input.next();
-                        Some(And)
+                    if self.eat('&') {
+                        And
                     } else {
-                        None
+                        return Err(self.error("E0003", "expected `&&`", start));
                     }
                 },
                 '|' => {
-                    if let Some('|') = self.lookahead() {
-                        self.input.next();
-                        Some(Or)
+                    if self.eat('|') {
+                        Or
                     } else {
-                        None
+                        return Err(self.error("E0003", "expected `||`", start));
                     }
                 },
-                _ => None,
-            }
+                '#' if self.eat('#') => {
+                    let mut text = String::new();
+                    while let Some(char) = self.lookahead().filter(|&char| char != '\n') {
+                        text.push(char);
+                        self.advance();
+                    }
+                    let text = text.strip_prefix(' ').unwrap_or(&text).trim_end();
+                    Doc(text.to_string())
+                },
+                '#' => return Err(self.error("E0003", "unexpected character `#`; comments start with `##`", start)),
+                _ => return Err(self.error("E0003", &format!("unexpected character `{}`", char), start)),
+            };
+            return Ok(Some(token));
+        }
+        Ok(None)
+    }
+
+    fn advance(&mut self) -> Option<char> {
+        let char = self.input.next()?;
+        self.position += 1;
+        if char == '\n' {
+            self.line += 1;
+            self.column = 1;
+        } else {
+            self.column += 1;
+        }
+        Some(char)
+    }
+
+    fn eat(&mut self, expected: char) -> bool {
+        if self.lookahead() == Some(expected) {
+            self.advance();
+            true
+        } else {
+            false
         }
-        None
     }
 
     fn lookahead(&self) -> Option<char> {
         self.input.clone().next()
     }
 
+    fn skip_whitespace(&mut self) {
+        while let Some(char) = self.lookahead() {
+            match char {
+                ' ' | '\t' | '\r' => {},
+                '\n' if self.depth > 0 => {},
+                _ => break,
+            }
+            self.advance();
+        }
+    }
+
+    fn span_here(&self) -> Span {
+        Span { start: self.position, end: self.position, line: self.line, column: self.column }
+    }
+
+    fn error(&self, code: &'static str, message: &str, start: Span) -> LexError {
+        LexError { code, message: message.to_string(), span: Span { end: self.position, ..start } }
+    }
+
+    fn read_digits(&mut self, digits: &mut String) {
+        while let Some(char) = self.lookahead().filter(|ch| ch.is_ascii_digit()) {
+            digits.push(char);
+            self.advance();
+        }
+    }
+
     fn read_string(&mut self) -> String {
         let mut str = String::new();
         while let Some(char) = self.lookahead() {
-            if char.is_alphanumeric() {
+            if char.is_alphanumeric() || char == '_' {
                 str.push(char);
-                self.input.next();
+                self.advance();
             } else {
                 break;
             }
         }
         str
     }
+
+    fn read_quoted(&mut self, start: Span) -> Result<String, LexError> {
+        let mut str = String::new();
+        loop {
+            match self.advance() {
+                Some('"') => return Ok(str),
+                Some('\\') => match self.advance() {
+                    Some('n') => str.push('\n'),
+                    Some('t') => str.push('\t'),
+                    Some('"') => str.push('"'),
+                    Some('\\') => str.push('\\'),
+                    _ => return Err(self.error("E0002", "invalid escape sequence in string", start)),
+                },
+                Some(char) => str.push(char),
+                None => return Err(self.error("E0001", "unterminated string", start)),
+            }
+        }
+    }
 }
 
 #[cfg(test)]
 mod test {
+    use alloc::string::{String, ToString};
+    use alloc::vec;
+    use alloc::vec::Vec;
+
     use crate::lexer::{Lexer, Token};
 
     #[test]
@@ -300,4 +630,102 @@ mod test {
             ]
         );
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_print_string() {
+        let mut lexer = Lexer::new("print \"hi \\\"there\\\"\"\nprint (1 +\n 2)");
+        let tokens = lexer.tokenize();
+        assert_eq!(
+            tokens,
+            vec![
+                Token::Print,
+                Token::Str("hi \"there\"".to_string()),
+                Token::Newline,
+                Token::Print,
+                Token::Lparen,
+                Token::Number(1),
+                Token::Plus,
+                Token::Number(2),
+                Token::Rparen,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_floats() {
+        let mut lexer = Lexer::new("x = 2.5 * 10.25 + 3");
+        let tokens = lexer.tokenize();
+        assert_eq!(
+            tokens,
+            vec![
+                Token::Id("x".to_string()),
+                Token::Assign,
+                Token::Float(2.5),
+                Token::Multiply,
+                Token::Float(10.25),
+                Token::Plus,
+                Token::Number(3),
+            ]
+        );
+
+        let error = Lexer::new("99999999999999999999").tokenize_spanned().unwrap_err();
+        assert_eq!(error.message, "integer literal is too large");
+    }
+
+    #[test]
+    fn test_not_equals() {
+        let tokens = Lexer::new("!a != b").tokenize();
+        assert_eq!(
+            tokens,
+            vec![Token::Not, Token::Id("a".to_string()), Token::NotEquals, Token::Id("b".to_string())]
+        );
+    }
+
+    #[test]
+    fn test_spans_and_errors() {
+        let tokens = Lexer::new("x = 1\n  y").tokenize_spanned().unwrap();
+        let span = tokens.last().unwrap().span;
+        assert_eq!((span.line, span.column, span.start, span.end), (2, 3, 8, 9));
+
+        let error = Lexer::new("x = \"abc").tokenize_spanned().unwrap_err();
+        assert_eq!(error.message, "unterminated string");
+        assert_eq!(error.span.column, 5);
+
+        let error = Lexer::new("x = 1 @ 2").tokenize_spanned().unwrap_err();
+        assert_eq!(error.message, "unexpected character `@`");
+
+        let (tokens, errors) = Lexer::new("x @ 2 $").tokenize_recovering();
+        let tokens: Vec<_> = tokens.into_iter().map(|token| token.token).collect();
+        assert_eq!(tokens, vec![Token::Id("x".to_string()), Token::Number(2)]);
+        assert_eq!(errors.iter().map(|error| error.span.column).collect::<Vec<_>>(), vec![3, 7]);
+    }
+
+    #[test]
+    fn test_shebang() {
+        let tokens = Lexer::new("#!/usr/bin/env tbasic
+print 1").tokenize_spanned().unwrap();
+        assert_eq!(tokens[0].token, Token::Newline);
+        let span = tokens[1].span;
+        assert_eq!((span.line, span.column, span.start), (2, 1, 22));
+
+        // Only on the first line
+        assert!(Lexer::new("print 1
+#!/bin/sh").tokenize_spanned().is_err());
+    }
+
+    #[test]
+    fn test_doc_comments() {
+        let tokens = Lexer::new("## Adds  one.  \n##\n  ##x\nfn").tokenize();
+        assert_eq!(tokens, vec![
+            Token::Doc("Adds  one.".to_string()),
+            Token::Newline,
+            Token::Doc(String::new()),
+            Token::Newline,
+            Token::Doc("x".to_string()),
+            Token::Newline,
+            Token::Fn,
+        ]);
+        let error = Lexer::new("x = 1 # not a comment").tokenize_spanned().unwrap_err();
+        assert_eq!((error.message.as_str(), error.span.column), ("unexpected character `#`; comments start with `##`", 7));
+    }
+}
@@ -1 +1,155 @@
-pub mod lexer;
\ No newline at end of file
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
+pub mod ast;
+#[cfg(feature = "std")]
+pub mod build;
+pub mod builtins;
+#[cfg(feature = "std")]
+pub mod bytecode;
+#[cfg(feature = "std")]
+pub mod c;
+#[cfg(feature = "std")]
+pub mod check;
+#[cfg(feature = "std")]
+pub mod cli;
+#[cfg(feature = "std")]
+pub mod codes;
+pub mod collections;
+#[cfg(feature = "std")]
+pub mod config;
+pub mod compiler;
+pub mod const_eval;
+#[cfg(feature = "std")]
+pub mod coverage;
+pub mod debug;
+#[cfg(feature = "std")]
+pub mod diagnostic;
+#[cfg(feature = "std")]
+pub mod doc;
+#[cfg(feature = "std")]
+pub mod dump;
+#[cfg(feature = "std")]
+pub mod editor;
+#[cfg(feature = "std")]
+pub mod execution;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+#[cfg(feature = "std")]
+pub mod formatter;
+#[cfg(feature = "std")]
+pub mod highlight;
+pub mod inline;
+pub mod interpreter;
+#[cfg(feature = "std")]
+pub mod js;
+#[cfg(feature = "std")]
+pub mod json;
+pub mod lexer;
+#[cfg(feature = "std")]
+pub mod lint;
+pub mod loops;
+#[cfg(feature = "std")]
+pub mod memory;
+pub mod modules;
+pub mod parser;
+pub mod peephole;
+pub mod profile;
+pub mod random;
+pub mod register;
+#[cfg(feature = "std")]
+pub mod repl;
+pub mod sandbox;
+#[cfg(feature = "std")]
+pub mod snapshot;
+pub mod stats;
+pub mod transpile;
+#[cfg(feature = "std")]
+pub mod typecheck;
+pub mod value;
+pub mod vm;
+#[cfg(feature = "std")]
+pub mod wasm;
+#[cfg(feature = "std")]
+mod wasm_runtime;
+
+use core::fmt;
+
+use crate::interpreter::{Interpreter, RuntimeError};
+use crate::parser::ParseError;
+use crate::value::Value;
+
+/// Why [`execute`] failed.
+#[derive(Debug)]
+pub enum Error {
+    Parse(ParseError),
+    /// Includes the script calling `exit`, as [`RuntimeError::Exit`].
+    Runtime(RuntimeError),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Parse(error) => write!(f, "{}", error),
+            Error::Runtime(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+impl core::error::Error for Error {
+    fn source(&self) -> Option<&(dyn core::error::Error + 'static)> {
+        match self {
+            Error::Parse(error) => Some(error),
+            Error::Runtime(error) => Some(error),
+        }
+    }
+}
+
+impl From<ParseError> for Error {
+    fn from(error: ParseError) -> Self {
+        Error::Parse(error)
+    }
+}
+
+impl From<RuntimeError> for Error {
+    fn from(error: RuntimeError) -> Self {
+        Error::Runtime(error)
+    }
+}
+
+/// Parses and runs `source` in a new interpreter printing to stdout, and
+/// returns what it evaluated to, as [`Interpreter::run`] does.
+#[cfg(feature = "std")]
+pub fn execute(source: &str) -> Result<Value, Error> {
+    execute_with(source, &mut Interpreter::new())
+}
+
+/// Like [`execute`], in `interpreter`: its output, host functions and limits
+/// apply, and the globals the script defines are kept for the next run.
+pub fn execute_with(source: &str, interpreter: &mut Interpreter) -> Result<Value, Error> {
+    let program = interpreter.parse(source)?;
+    Ok(interpreter.run(&program)?)
+}
+
+#[cfg(all(test, feature = "std"))]
+mod test {
+    use crate::interpreter::{Interpreter, RuntimeError, SharedBuffer};
+    use crate::value::Value;
+    use crate::{execute, execute_with, Error};
+
+    #[test]
+    fn test_execute() {
+        assert_eq!(execute("x = 20\nx * 2 + 2").unwrap(), Value::Int(42));
+        assert!(matches!(execute("x = ("), Err(Error::Parse(error)) if error.code == "E0101"));
+        assert!(matches!(execute("exit(3)"), Err(Error::Runtime(RuntimeError::Exit { code: 3, .. }))));
+
+        let output = SharedBuffer::new();
+        let mut interpreter = Interpreter::with_output(Box::new(output.clone()));
+        execute_with("fn square(x) {\n  return x * x\n}", &mut interpreter).unwrap();
+        assert_eq!(execute_with("print square(3)\nsquare(4)", &mut interpreter).unwrap(), Value::Int(16));
+        assert_eq!(output.contents(), "9\n");
+        let error = execute_with("print nope", &mut interpreter).unwrap_err();
+        assert!(error.to_string().contains("nope"), "{}", error);
+    }
+}
@@ -0,0 +1,454 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+use crate::ast::{Expr, ExprKind, FunctionDecl, Program, Stmt, StmtKind, UnaryOp};
+use crate::check::{assigned_names, scope_definitions, shallow_statement_exprs};
+use crate::lexer::Span;
+
+/// How a rule's findings are reported.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    /// The rule doesn't run.
+    Allow,
+    Warn,
+    /// Findings are errors, e.g. to fail a CI job.
+    Deny,
+}
+
+/// A finding of a [`LintRule`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct Lint {
+    pub rule: &'static str,
+    pub severity: Severity,
+    pub message: String,
+    pub span: Span,
+}
+
+impl fmt::Display for Lint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let level = if self.severity == Severity::Deny { "error" } else { "warning" };
+        write!(f, "{}:{}: {}[{}]: {}", self.span.line, self.span.column, level, self.rule, self.message)
+    }
+}
+
+/// Collects what a rule finds in a program.
+#[derive(Debug, Default)]
+pub struct LintContext {
+    findings: Vec<(String, Span)>,
+}
+
+impl LintContext {
+    pub fn report(&mut self, message: impl Into<String>, span: Span) {
+        self.findings.push((message.into(), span));
+    }
+}
+
+/// A style check run by a [`Linter`].
+pub trait LintRule {
+    /// Identifies the rule in findings and when configuring its severity,
+    /// e.g. `magic-numbers`.
+    fn name(&self) -> &'static str;
+
+    fn default_severity(&self) -> Severity {
+        Severity::Warn
+    }
+
+    fn check(&self, program: &Program, cx: &mut LintContext);
+}
+
+/// Runs a set of lint rules, each at its configured severity.
+pub struct Linter {
+    rules: Vec<Box<dyn LintRule>>,
+    severities: HashMap<String, Severity>,
+}
+
+impl Default for Linter {
+    fn default() -> Self {
+        Linter::new()
+    }
+}
+
+impl Linter {
+    /// A linter with the built-in rules: [`Shadowing`], [`DeepNesting`] and
+    /// the opt-in [`LetShadowing`] and [`MagicNumbers`].
+    pub fn new() -> Linter {
+        let mut linter = Linter::empty();
+        linter.register(Box::new(Shadowing));
+        linter.register(Box::new(LetShadowing));
+        linter.register(Box::new(MagicNumbers::default()));
+        linter.register(Box::new(DeepNesting::default()));
+        linter
+    }
+
+    pub fn empty() -> Linter {
+        Linter { rules: Vec::new(), severities: HashMap::new() }
+    }
+
+    /// Adds `rule`, replacing any rule of the same name.
+    pub fn register(&mut self, rule: Box<dyn LintRule>) {
+        self.rules.retain(|existing| existing.name() != rule.name());
+        self.rules.push(rule);
+    }
+
+    /// Whether a rule called `name` is registered.
+    pub fn has_rule(&self, name: &str) -> bool {
+        self.rules.iter().any(|rule| rule.name() == name)
+    }
+
+    /// Overrides the severity of the rule called `rule`.
+    pub fn set_severity(&mut self, rule: &str, severity: Severity) {
+        self.severities.insert(rule.to_string(), severity);
+    }
+
+    pub fn severity(&self, rule: &dyn LintRule) -> Severity {
+        self.severities.get(rule.name()).copied().unwrap_or_else(|| rule.default_severity())
+    }
+
+    /// Runs every rule that isn't allowed, returning the findings in source
+    /// order.
+    pub fn lint(&self, program: &Program) -> Vec<Lint> {
+        let mut lints = Vec::new();
+        for rule in &self.rules {
+            let severity = self.severity(rule.as_ref());
+            if severity == Severity::Allow {
+                continue;
+            }
+            let mut cx = LintContext::default();
+            rule.check(program, &mut cx);
+            lints.extend(cx.findings.into_iter().map(|(message, span)| Lint { rule: rule.name(), severity, message, span }));
+        }
+        lints.sort_by_key(|lint| (lint.span.line, lint.span.column));
+        lints
+    }
+}
+
+/// Flags parameters reusing the name of a variable from an enclosing scope,
+/// and closures assigning to a name their enclosing function uses, which
+/// creates a local rather than changing the captured variable. Doing so with
+/// `let` is deliberate and left to [`LetShadowing`].
+pub struct Shadowing;
+
+impl LintRule for Shadowing {
+    fn name(&self) -> &'static str {
+        "shadowing"
+    }
+
+    fn check(&self, program: &Program, cx: &mut LintContext) {
+        let mut globals = HashSet::new();
+        assigned_names(&program.statements, &mut globals);
+        shadowing_in(&program.statements, &mut vec![globals], cx);
+    }
+}
+
+/// Checks the functions defined in `body`, whose enclosing scopes' variables
+/// are `scopes`, the globals first.
+fn shadowing_in(body: &[Stmt], scopes: &mut Vec<HashSet<String>>, cx: &mut LintContext) {
+    let mut functions = Vec::new();
+    scope_definitions(body, &mut HashSet::new(), &mut Vec::new(), &mut functions);
+    for (decl, _) in functions {
+        let enclosing = |name: &str| scopes.iter().any(|scope| scope.contains(name));
+        for param in &decl.params {
+            if enclosing(param) {
+                cx.report(format!("parameter `{}` shadows a variable of the same name", param), decl.span);
+            }
+        }
+        let mut locals = Vec::new();
+        let mut declared_global = HashSet::new();
+        scope_definitions(&decl.body, &mut declared_global, &mut locals, &mut Vec::new());
+        let mut reported = HashSet::new();
+        let mut lets = Vec::new();
+        let_declarations(&decl.body, &mut lets);
+        for (name, span) in locals {
+            if lets.contains(&(name.as_str(), span)) {
+                // The local is created on purpose
+                reported.insert(name);
+                continue;
+            }
+            // Assigning a global without `global` is reported by `check`
+            let captured = scopes[1..].iter().any(|scope| scope.contains(&name));
+            if captured && !decl.params.contains(&name) && !declared_global.contains(&name) && reported.insert(name.clone()) {
+                cx.report(format!("assigning `{}` creates a local instead of changing the enclosing function's `{}`", name, name), span);
+            }
+        }
+        scopes.push(function_scope(decl));
+        shadowing_in(&decl.body, scopes, cx);
+        scopes.pop();
+    }
+}
+
+/// Flags `let` declarations shadowing a variable of the same name: a global,
+/// a variable of an enclosing function, or one declared earlier in the same
+/// scope.
+pub struct LetShadowing;
+
+impl LintRule for LetShadowing {
+    fn name(&self) -> &'static str {
+        "let-shadowing"
+    }
+
+    /// Off unless enabled: shadowing with `let` is allowed on purpose.
+    fn default_severity(&self) -> Severity {
+        Severity::Allow
+    }
+
+    fn check(&self, program: &Program, cx: &mut LintContext) {
+        let_shadowing_in(&program.statements, &[], &mut Vec::new(), cx);
+    }
+}
+
+/// Checks the `let`s in the scope `body`, whose enclosing scopes' variables
+/// are `scopes`, the globals first, then does the same for its functions.
+fn let_shadowing_in(body: &[Stmt], params: &[String], scopes: &mut Vec<HashSet<String>>, cx: &mut LintContext) {
+    let mut earlier: HashSet<String> = params.iter().cloned().collect();
+    in_order(body, &mut |statement| {
+        let name = match &statement.kind {
+            StmtKind::Assign { name, declaration: true, .. } => {
+                if earlier.contains(name) {
+                    cx.report(format!("`let {}` shadows an earlier `{}` in the same scope", name, name), statement.span);
+                } else if let Some(depth) = scopes.iter().rposition(|scope| scope.contains(name)) {
+                    let shadowed = if depth == 0 { "global" } else { "enclosing function's" };
+                    cx.report(format!("`let {}` shadows the {} `{}`", name, shadowed, name), statement.span);
+                }
+                name
+            },
+            StmtKind::Assign { name, .. } | StmtKind::Import(name) => name,
+            StmtKind::Function(decl) => &decl.name,
+            StmtKind::Struct(decl) => &decl.name,
+            _ => return,
+        };
+        earlier.insert(name.clone());
+    });
+
+    let mut functions = Vec::new();
+    scope_definitions(body, &mut HashSet::new(), &mut Vec::new(), &mut functions);
+    let mut scope = params.iter().cloned().collect();
+    assigned_names(body, &mut scope);
+    scopes.push(scope);
+    for (decl, _) in functions {
+        let_shadowing_in(&decl.body, &decl.params, scopes, cx);
+    }
+    scopes.pop();
+}
+
+/// Calls `visit` on `statements` and those in their nested blocks in
+/// source order, without looking into functions.
+fn in_order<'a>(statements: &'a [Stmt], visit: &mut impl FnMut(&'a Stmt)) {
+    for statement in statements {
+        visit(statement);
+        match &statement.kind {
+            StmtKind::If { then_branch, else_branch, .. } => {
+                in_order(then_branch, visit);
+                in_order(else_branch.as_deref().unwrap_or_default(), visit);
+            },
+            StmtKind::While { body, .. } => in_order(body, visit),
+            _ => {},
+        }
+    }
+}
+
+/// The names `statements` declare with `let` and where.
+fn let_declarations<'a>(statements: &'a [Stmt], lets: &mut Vec<(&'a str, Span)>) {
+    in_order(statements, &mut |statement| {
+        if let StmtKind::Assign { name, declaration: true, .. } = &statement.kind {
+            lets.push((name, statement.span));
+        }
+    });
+}
+
+fn function_scope(decl: &FunctionDecl) -> HashSet<String> {
+    let mut names: HashSet<String> = decl.params.iter().cloned().collect();
+    assigned_names(&decl.body, &mut names);
+    names
+}
+
+/// Flags numeric literals other than a few common ones, except where they
+/// are assigned straight to a variable, which gives them a name.
+pub struct MagicNumbers {
+    pub allowed: Vec<i64>,
+}
+
+impl Default for MagicNumbers {
+    fn default() -> Self {
+        MagicNumbers { allowed: vec![0, 1] }
+    }
+}
+
+impl LintRule for MagicNumbers {
+    fn name(&self) -> &'static str {
+        "magic-numbers"
+    }
+
+    /// Off unless enabled: most scripts are full of literals.
+    fn default_severity(&self) -> Severity {
+        Severity::Allow
+    }
+
+    fn check(&self, program: &Program, cx: &mut LintContext) {
+        self.statements(&program.statements, cx);
+    }
+}
+
+impl MagicNumbers {
+    fn statements(&self, statements: &[Stmt], cx: &mut LintContext) {
+        for statement in statements {
+            match &statement.kind {
+                StmtKind::Assign { value, .. } if is_number(value) => continue,
+                StmtKind::If { then_branch, else_branch, .. } => {
+                    self.statements(then_branch, cx);
+                    self.statements(else_branch.as_deref().unwrap_or_default(), cx);
+                },
+                StmtKind::While { body, .. } => self.statements(body, cx),
+                StmtKind::Function(decl) => self.statements(&decl.body, cx),
+                _ => {},
+            }
+            shallow_statement_exprs(statement, &mut |expr| match &expr.kind {
+                ExprKind::Number(n) if !self.allowed.contains(n) => {
+                    cx.report(format!("magic number {}; assign it to a named variable", n), expr.span);
+                },
+                ExprKind::Float(n) if *n != 0.0 && *n != 1.0 => {
+                    cx.report(format!("magic number {:?}; assign it to a named variable", n), expr.span);
+                },
+                ExprKind::Function(decl) => self.statements(&decl.body, cx),
+                _ => {},
+            });
+        }
+    }
+}
+
+fn is_number(expr: &Expr) -> bool {
+    match &expr.kind {
+        ExprKind::Number(_) | ExprKind::Float(_) => true,
+        ExprKind::Unary { op: UnaryOp::Neg, operand } => is_number(operand),
+        _ => false,
+    }
+}
+
+/// Flags blocks nested more than `max_depth` levels deep within the top
+/// level or a function body.
+pub struct DeepNesting {
+    pub max_depth: usize,
+}
+
+impl Default for DeepNesting {
+    fn default() -> Self {
+        DeepNesting { max_depth: 4 }
+    }
+}
+
+impl LintRule for DeepNesting {
+    fn name(&self) -> &'static str {
+        "deep-nesting"
+    }
+
+    fn check(&self, program: &Program, cx: &mut LintContext) {
+        self.block(&program.statements, 0, cx);
+    }
+}
+
+impl DeepNesting {
+    fn block(&self, statements: &[Stmt], depth: usize, cx: &mut LintContext) {
+        for statement in statements {
+            shallow_statement_exprs(statement, &mut |expr| {
+                if let ExprKind::Function(decl) = &expr.kind {
+                    self.block(&decl.body, 0, cx);
+                }
+            });
+            let blocks: Vec<&[Stmt]> = match &statement.kind {
+                StmtKind::If { then_branch, else_branch, .. } => {
+                    [Some(then_branch.as_slice()), else_branch.as_deref()].into_iter().flatten().collect()
+                },
+                StmtKind::While { body, .. } => vec![body],
+                StmtKind::Function(decl) => {
+                    self.block(&decl.body, 0, cx);
+                    continue;
+                },
+                _ => continue,
+            };
+            if depth + 1 > self.max_depth {
+                let message = format!("block nested {} levels deep, more than the limit of {}", depth + 1, self.max_depth);
+                cx.report(message, statement.span);
+                continue;
+            }
+            for block in blocks {
+                self.block(block, depth + 1, cx);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::ast::{Program, StmtKind};
+    use crate::lint::{DeepNesting, LetShadowing, LintContext, LintRule, Linter, Severity};
+    use crate::parser::parse;
+
+    fn lints(linter: &Linter, source: &str) -> Vec<String> {
+        linter.lint(&parse(source).unwrap()).iter().map(ToString::to_string).collect()
+    }
+
+    #[test]
+    fn test_builtin_rules() {
+        let linter = Linter::new();
+        let source = "x = 1\nfn f(x) {\n  total = 0\n  add = fn(n) {\n    total = total + n\n  }\n  return total\n}";
+        assert_eq!(lints(&linter, source), vec![
+            "2:1: warning[shadowing]: parameter `x` shadows a variable of the same name",
+            "5:5: warning[shadowing]: assigning `total` creates a local instead of changing the enclosing function's `total`",
+        ]);
+
+        let source = "fn f() {\n  n = 1\n  g = fn() {\n    let n = 2\n    return n\n  }\n  return g\n}\nprint f";
+        assert!(lints(&linter, source).is_empty());
+        let mut strict = Linter::empty();
+        strict.register(Box::new(LetShadowing));
+        strict.set_severity("let-shadowing", Severity::Warn);
+        let source = "let x = 1\nlet x = 2\nfn f(a) {\n  let a = 3\n  let b = fn() {\n    let x = 4\n    let a = 5\n  }\n}";
+        assert_eq!(lints(&strict, source), vec![
+            "2:1: warning[let-shadowing]: `let x` shadows an earlier `x` in the same scope",
+            "4:3: warning[let-shadowing]: `let a` shadows an earlier `a` in the same scope",
+            "6:5: warning[let-shadowing]: `let x` shadows the global `x`",
+            "7:5: warning[let-shadowing]: `let a` shadows the enclosing function's `a`",
+        ]);
+        assert!(lints(&linter, source).is_empty());
+
+        let nested = "if 1 {\n  while 1 {\n    if 1 {\n      if 1 {\n        if 1 {\n          print 1\n        }\n      }\n    }\n  }\n}";
+        assert_eq!(lints(&linter, nested), vec!["5:9: warning[deep-nesting]: block nested 5 levels deep, more than the limit of 4"]);
+
+        let source = "limit = 60\nx = limit * 60 + 1\nf = fn() { return 2.5 }";
+        assert!(lints(&linter, source).is_empty());
+        let mut linter = Linter::new();
+        linter.set_severity("magic-numbers", Severity::Deny);
+        linter.set_severity("deep-nesting", Severity::Allow);
+        assert_eq!(lints(&linter, source), vec![
+            "2:13: error[magic-numbers]: magic number 60; assign it to a named variable",
+            "3:19: error[magic-numbers]: magic number 2.5; assign it to a named variable",
+        ]);
+        assert!(lints(&linter, nested).is_empty());
+    }
+
+    struct NoPrint;
+
+    impl LintRule for NoPrint {
+        fn name(&self) -> &'static str {
+            "no-print"
+        }
+
+        fn check(&self, program: &Program, cx: &mut LintContext) {
+            for statement in &program.statements {
+                if let StmtKind::Print(_) = statement.kind {
+                    cx.report("use the logger instead of `print`", statement.span);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_custom_rules() {
+        let mut linter = Linter::empty();
+        linter.register(Box::new(NoPrint));
+        linter.register(Box::new(DeepNesting { max_depth: 1 }));
+        let source = "print 1\nif 1 {\n  if 1 {\n  }\n}";
+        assert_eq!(lints(&linter, source), vec![
+            "1:1: warning[no-print]: use the logger instead of `print`",
+            "3:3: warning[deep-nesting]: block nested 2 levels deep, more than the limit of 1",
+        ]);
+    }
+}
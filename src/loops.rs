@@ -0,0 +1,466 @@
+use alloc::boxed::Box;
+use alloc::format;
+use alloc::rc::Rc;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+
+use crate::ast::{BinaryOp, Expr, ExprKind, Program, Stmt, StmtKind};
+use crate::collections::{HashMap, HashSet};
+use crate::lexer::Span;
+use crate::transpile::{each_subexpr, walk_blocks};
+
+/// Optimizes `while` loops that make no calls and define no functions, so
+/// nothing but their own assignments can change the variables they read:
+///
+/// - Subexpressions computed from literals and variables the loop doesn't
+///   assign, in the condition or in the statements each iteration starts
+///   with, are computed once before the loop instead.
+/// - If the loop's counter is set to an int literal by the assignments
+///   right before it and changed once per iteration, by a statement of the
+///   loop's body adding or subtracting an int literal,
+///   products of the counter and an int literal are kept up to date by
+///   adding to them instead of multiplying every iteration.
+///
+/// Optimized loops are wrapped in an `if` that checks the condition once
+/// before the precomputed values are set, so loops that never run don't
+/// compute anything. The values live in variables scripts can't name.
+///
+/// Programs that run without errors behave the same, though the extra
+/// statements count towards fuel and are seen by debug hooks, and products
+/// of the counter are assumed not to overflow. Ones that fail may report
+/// another error, or the same one before the output of the iteration that
+/// failed. Programs with `goto` or `gosub` are left alone.
+pub fn optimize(program: &Program) -> Program {
+    let mut jumps = false;
+    for statement in &program.statements {
+        walk_blocks(statement, &mut |kind| jumps |= matches!(kind, StmtKind::Goto(_) | StmtKind::Gosub(_)));
+    }
+    let mut program = program.clone();
+    if !jumps {
+        Optimizer { temps: 0 }.block(&mut program.statements);
+    }
+    program
+}
+
+/// Calls `visit` with the expressions `statement` holds directly.
+fn each_expr_mut(statement: &mut Stmt, visit: &mut dyn FnMut(&mut Expr)) {
+    match &mut statement.kind {
+        StmtKind::Assign { value, .. } => visit(value),
+        StmtKind::SetIndex { target, index, value } => {
+            visit(target);
+            visit(index);
+            visit(value);
+        },
+        StmtKind::SetField { target, value, .. } => {
+            visit(target);
+            visit(value);
+        },
+        StmtKind::If { condition, .. } | StmtKind::While { condition, .. } => visit(condition),
+        StmtKind::Print(args) => args.iter_mut().for_each(visit),
+        StmtKind::Return(Some(value)) | StmtKind::Expr(value) => visit(value),
+        _ => {},
+    }
+}
+
+fn each_subexpr_mut(expr: &mut Expr, visit: &mut dyn FnMut(&mut Expr)) {
+    match &mut expr.kind {
+        ExprKind::Array(items) => items.iter_mut().for_each(visit),
+        ExprKind::Map(entries) => {
+            for (key, value) in entries {
+                visit(key);
+                visit(value);
+            }
+        },
+        ExprKind::Index { target, index } => {
+            visit(target);
+            visit(index);
+        },
+        ExprKind::Field { target, .. } => visit(target),
+        ExprKind::Unary { operand, .. } => visit(operand),
+        ExprKind::Binary { left, right, .. } => {
+            visit(left);
+            visit(right);
+        },
+        ExprKind::Call { callee, args } => {
+            visit(callee);
+            args.iter_mut().for_each(visit);
+        },
+        _ => {},
+    }
+}
+
+/// Calls `visit` with every expression of `statements`, nested blocks
+/// included.
+fn each_expr_in(statements: &mut [Stmt], visit: &mut dyn FnMut(&mut Expr)) {
+    for statement in statements {
+        each_expr_mut(statement, visit);
+        match &mut statement.kind {
+            StmtKind::If { then_branch, else_branch, .. } => {
+                each_expr_in(then_branch, visit);
+                if let Some(branch) = else_branch {
+                    each_expr_in(branch, visit);
+                }
+            },
+            StmtKind::While { body, .. } => each_expr_in(body, visit),
+            _ => {},
+        }
+    }
+}
+
+/// Whether `expr` and its operands make no calls and define no functions.
+fn no_calls(expr: &Expr) -> bool {
+    match &expr.kind {
+        ExprKind::Call { .. } | ExprKind::Function(_) => false,
+        _ => {
+            let mut none = true;
+            each_subexpr(expr, &mut |operand| none &= no_calls(operand));
+            none
+        },
+    }
+}
+
+/// Whether the assignments at the end of `before` set `name` to an int
+/// literal, with nothing after that could change it.
+fn set_to_int(before: &[Stmt], name: &str) -> bool {
+    for statement in before.iter().rev() {
+        let StmtKind::Assign { name: assigned, value, .. } = &statement.kind else {
+            return false;
+        };
+        if assigned == name {
+            return matches!(value.kind, ExprKind::Number(_));
+        }
+        if !no_calls(value) {
+            return false;
+        }
+    }
+    false
+}
+
+fn variable(name: &str, span: Span) -> Expr {
+    Expr { kind: ExprKind::Variable(name.to_string()), span }
+}
+
+fn assign(name: &str, value: Expr, span: Span) -> Stmt {
+    Stmt { kind: StmtKind::Assign { name: name.to_string(), ty: None, value, declaration: false }, span }
+}
+
+/// The counter and step of `statement` if it is `name = name + n` or
+/// `name = name - n`.
+fn counter_step(statement: &Stmt) -> Option<(&str, i64)> {
+    let StmtKind::Assign { name, value, declaration: false, .. } = &statement.kind else {
+        return None;
+    };
+    let ExprKind::Binary { op, left, right } = &value.kind else {
+        return None;
+    };
+    match (op, &left.kind, &right.kind) {
+        (BinaryOp::Add, ExprKind::Variable(counter), ExprKind::Number(n)) if counter == name => Some((name, *n)),
+        (BinaryOp::Sub, ExprKind::Variable(counter), ExprKind::Number(n)) if counter == name => Some((name, n.checked_neg()?)),
+        _ => None,
+    }
+}
+
+/// The int literal `expr` multiplies `counter` by, if it does.
+fn counter_factor(expr: &Expr, counter: &str) -> Option<i64> {
+    let ExprKind::Binary { op: BinaryOp::Mul, left, right } = &expr.kind else {
+        return None;
+    };
+    match (&left.kind, &right.kind) {
+        (ExprKind::Variable(name), ExprKind::Number(n)) | (ExprKind::Number(n), ExprKind::Variable(name)) if name == counter => {
+            Some(*n)
+        },
+        _ => None,
+    }
+}
+
+struct Optimizer {
+    /// How many variables the optimizer has introduced, so each gets a name
+    /// of its own.
+    temps: usize,
+}
+
+impl Optimizer {
+    fn temp(&mut self) -> String {
+        self.temps += 1;
+        // Not an identifier, so scripts can't refer to it
+        format!("loop#{}", self.temps)
+    }
+
+    fn block(&mut self, statements: &mut [Stmt]) {
+        for i in 0..statements.len() {
+            let (before, rest) = statements.split_at_mut(i);
+            let statement = &mut rest[0];
+            self.functions(statement);
+            match &mut statement.kind {
+                StmtKind::If { then_branch, else_branch, .. } => {
+                    self.block(then_branch);
+                    if let Some(branch) = else_branch {
+                        self.block(branch);
+                    }
+                },
+                StmtKind::While { body, .. } => {
+                    self.block(body);
+                    self.optimize_loop(statement, before);
+                },
+                _ => {},
+            }
+        }
+    }
+
+    /// Optimizes the loops in the functions `statement` defines directly.
+    fn functions(&mut self, statement: &mut Stmt) {
+        if let StmtKind::Function(decl) = &mut statement.kind {
+            self.block(&mut Rc::make_mut(decl).body);
+        }
+        each_expr_mut(statement, &mut |expr| self.function_literals(expr));
+    }
+
+    fn function_literals(&mut self, expr: &mut Expr) {
+        if let ExprKind::Function(decl) = &mut expr.kind {
+            self.block(&mut Rc::make_mut(decl).body);
+        }
+        each_subexpr_mut(expr, &mut |operand| self.function_literals(operand));
+    }
+
+    /// Rewrites the `while` loop `statement`, which follows the statements
+    /// `before` in its block, if it can be optimized.
+    fn optimize_loop(&mut self, statement: &mut Stmt, before: &[Stmt]) {
+        let span = statement.span;
+        let StmtKind::While { condition, body } = &mut statement.kind else {
+            return;
+        };
+        let mut simple = no_calls(condition);
+        let mut assignments: HashMap<String, usize> = HashMap::default();
+        for statement in body.iter() {
+            walk_blocks(statement, &mut |kind| match kind {
+                StmtKind::Assign { name, .. } => *assignments.entry(name.clone()).or_insert(0) += 1,
+                StmtKind::Function(_) | StmtKind::Struct(_) | StmtKind::Global(_) | StmtKind::Import(_) => simple = false,
+                _ => {},
+            });
+        }
+        each_expr_in(body, &mut |expr| simple &= no_calls(expr));
+        if !simple {
+            return;
+        }
+
+        let guard = condition.clone();
+        let mut assigned: HashSet<String> = assignments.keys().cloned().collect();
+        let counter = body.iter().find_map(|statement| {
+            counter_step(statement).filter(|(name, _)| assignments[*name] == 1 && set_to_int(before, name))
+        });
+        let mut prelude = Vec::new();
+        if let Some((counter, step)) = counter {
+            let counter = counter.to_string();
+            for (temp, initial) in self.reduce(condition, body, &counter, step, span) {
+                prelude.push(assign(&temp, initial, span));
+                assigned.insert(temp);
+            }
+        }
+        let mut hoisted = Vec::new();
+        self.hoist(condition, &assigned, &mut hoisted);
+        for statement in body.iter_mut() {
+            if !matches!(
+                statement.kind,
+                StmtKind::Assign { .. } | StmtKind::SetIndex { .. } | StmtKind::SetField { .. } | StmtKind::Print(_) | StmtKind::Expr(_)
+            ) {
+                break;
+            }
+            each_expr_mut(statement, &mut |expr| self.hoist(expr, &assigned, &mut hoisted));
+        }
+        prelude.extend(hoisted);
+        if prelude.is_empty() {
+            return;
+        }
+        let kind = core::mem::replace(&mut statement.kind, StmtKind::Break);
+        prelude.push(Stmt { kind, span });
+        statement.kind = StmtKind::If { condition: guard, then_branch: prelude, else_branch: None };
+    }
+
+    /// Replaces products of `counter` and int literals by variables updated
+    /// along with the counter, returning the variables with the values they
+    /// start with.
+    fn reduce(&mut self, condition: &mut Expr, body: &mut Vec<Stmt>, counter: &str, step: i64, span: Span) -> Vec<(String, Expr)> {
+        let position = body
+            .iter()
+            .position(|statement| counter_step(statement).is_some_and(|(name, _)| name == counter))
+            .expect("the counter is changed by the loop");
+        // One variable per factor, with how much it changes per iteration
+        let mut products: Vec<(i64, String, i64)> = Vec::new();
+        let mut replace = |expr: &mut Expr| self.replace_products(expr, counter, step, &mut products);
+        replace(condition);
+        each_expr_in(body, &mut replace);
+        let mut initial = Vec::new();
+        for (i, (factor, temp, increment)) in products.into_iter().enumerate() {
+            let product = ExprKind::Binary {
+                op: BinaryOp::Mul,
+                left: Box::new(variable(counter, span)),
+                right: Box::new(Expr { kind: ExprKind::Number(factor), span }),
+            };
+            let update = ExprKind::Binary {
+                op: BinaryOp::Add,
+                left: Box::new(variable(&temp, span)),
+                right: Box::new(Expr { kind: ExprKind::Number(increment), span }),
+            };
+            let update_span = body[position].span;
+            body.insert(position + 1 + i, assign(&temp, Expr { kind: update, span: update_span }, update_span));
+            initial.push((temp, Expr { kind: product, span }));
+        }
+        initial
+    }
+
+    fn replace_products(&mut self, expr: &mut Expr, counter: &str, step: i64, products: &mut Vec<(i64, String, i64)>) {
+        if let Some(factor) = counter_factor(expr, counter) {
+            if let Some(increment) = step.checked_mul(factor) {
+                let temp = match products.iter().find(|(known, ..)| *known == factor) {
+                    Some((_, temp, _)) => temp.clone(),
+                    None => {
+                        let temp = self.temp();
+                        products.push((factor, temp.clone(), increment));
+                        temp
+                    },
+                };
+                *expr = variable(&temp, expr.span);
+                return;
+            }
+        }
+        each_subexpr_mut(expr, &mut |operand| self.replace_products(operand, counter, step, products));
+    }
+
+    /// Replaces the largest subexpressions of `expr` that compute something
+    /// from literals and variables not in `assigned` by variables, adding
+    /// their assignments to `hoisted`. Operands that may not be evaluated
+    /// are left alone.
+    fn hoist(&mut self, expr: &mut Expr, assigned: &HashSet<String>, hoisted: &mut Vec<Stmt>) {
+        let computed = matches!(expr.kind, ExprKind::Unary { .. } | ExprKind::Binary { .. });
+        if computed && reads_variable(expr) && invariant(expr, assigned) {
+            let temp = self.temp();
+            let value = core::mem::replace(expr, variable(&temp, expr.span));
+            hoisted.push(assign(&temp, value, expr.span));
+            return;
+        }
+        match &mut expr.kind {
+            ExprKind::Binary { op: BinaryOp::And | BinaryOp::Or, left, .. } => self.hoist(left, assigned, hoisted),
+            _ => each_subexpr_mut(expr, &mut |operand| self.hoist(operand, assigned, hoisted)),
+        }
+    }
+}
+
+/// Whether `expr` refers to a variable, so it isn't just a literal.
+fn reads_variable(expr: &Expr) -> bool {
+    let mut reads = matches!(expr.kind, ExprKind::Variable(_));
+    each_subexpr(expr, &mut |operand| reads |= reads_variable(operand));
+    reads
+}
+
+/// Whether `expr` computes the same value every time the loop that assigns
+/// `assigned` evaluates it.
+fn invariant(expr: &mut Expr, assigned: &HashSet<String>) -> bool {
+    match &expr.kind {
+        ExprKind::Number(_) | ExprKind::Float(_) | ExprKind::Str(_) => true,
+        ExprKind::Variable(name) => !assigned.contains(name),
+        ExprKind::Unary { .. } | ExprKind::Binary { .. } => {
+            let mut operands = true;
+            each_subexpr_mut(expr, &mut |operand| operands &= invariant(operand, assigned));
+            operands
+        },
+        _ => false,
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod test {
+    use crate::ast::{BinaryOp, Expr, ExprKind, Program, Stmt, StmtKind};
+    use crate::compiler::{compile_with, Backend, CompileOptions};
+    use crate::interpreter::{Interpreter, SharedBuffer};
+    use crate::loops::optimize;
+    use crate::parser::{parse, parse_with_dialect, Dialect};
+
+    /// How many multiplications the loop bodies of `statements` make.
+    fn loop_muls(statements: &[Stmt], in_loop: bool) -> usize {
+        fn count(expr: &Expr) -> usize {
+            match &expr.kind {
+                ExprKind::Binary { op, left, right } => usize::from(*op == BinaryOp::Mul) + count(left) + count(right),
+                ExprKind::Unary { operand, .. } => count(operand),
+                ExprKind::Index { target, index } => count(target) + count(index),
+                _ => 0,
+            }
+        }
+        statements
+            .iter()
+            .map(|statement| match &statement.kind {
+                StmtKind::While { condition, body } => count(condition) + loop_muls(body, true),
+                StmtKind::If { condition, then_branch, else_branch } => {
+                    usize::from(in_loop) * count(condition)
+                        + loop_muls(then_branch, in_loop)
+                        + else_branch.as_ref().map_or(0, |branch| loop_muls(branch, in_loop))
+                },
+                StmtKind::Assign { value: expr, .. } | StmtKind::Expr(expr) if in_loop => count(expr),
+                StmtKind::Print(args) if in_loop => args.iter().map(count).sum(),
+                StmtKind::Function(decl) => loop_muls(&decl.body, false),
+                _ => 0,
+            })
+            .sum()
+    }
+
+    const PROGRAMS: [&str; 8] = [
+        // Strength reduction
+        "i = 0\ntotal = 0\nwhile i < 100 {\n  total = total + i * 3 + 4 * i\n  i = i + 2\n}\n[total, i]",
+        "i = 10\nwhile i > 0 {\n  print i * 5, i * 5\n  i = i - 1\n}",
+        // Hoisting, in the condition and the body
+        "n = 4\nk = 3\ni = 0\nwhile i < n * k {\n  x = (n + 1) * k\n  i = i + 1\n}\n[i, x]",
+        // Loops that don't run compute nothing
+        "i = 0\nd = 0\nwhile i < 0 {\n  x = 1 / d\n  i = i + 1\n}\ni",
+        // Nested loops and loops in functions
+        "fn f(n) {\n  s = 0\n  j = 0\n  while j < n {\n    i = 0\n    while i < n {\n      s = s + i * 7 + j * 2 + n * n\n      i = i + 1\n    }\n    j = j + 1\n  }\n  return s\n}\nf(5)",
+        // Conditional operands stay where they are
+        "a = 0\nb = 0\ni = 0\nwhile i < 3 {\n  if i > 5 {\n    b = b + 10 / a\n  }\n  c = a && 1 / a\n  i = i + 1\n}\n[i, b]",
+        "s = \"ab\"\ni = 0\nt = \"\"\nwhile i < 3 {\n  t = t + s + \"-\"\n  i = i + 1\n}\nt",
+        "i = 0\nx = 2\nwhile i < 5 {\n  i = i + 1\n  if i == 3 {\n    continue\n  }\n  print i * 4, x * x\n}",
+    ];
+
+    #[test]
+    fn test_optimize() {
+        let cases = [(PROGRAMS[0], 2, 0), (PROGRAMS[1], 2, 0), (PROGRAMS[2], 2, 0), (PROGRAMS[4], 3, 2), (PROGRAMS[7], 2, 1)];
+        for (source, before, after) in cases {
+            let program = parse(source).unwrap();
+            assert_eq!(loop_muls(&program.statements, false), before, "{:?}", source);
+            let optimized = optimize(&program);
+            assert_eq!(loop_muls(&optimized.statements, false), after, "{:?}", source);
+        }
+
+        // Loops that call, or change a counter more than once, stay as they are
+        let unchanged = [
+            "i = 0\nwhile i < 3 {\n  print i * 2, len(\"ab\") * 2\n  i = i + 1\n}",
+            "i = 0\nn = 2\nwhile i < 9 {\n  i = i + 1\n  if i == 4 {\n    i = i + 1\n  }\n  print i * 2\n}",
+            "i = 0\nn = 2\nwhile i < 3 {\n  n = n + 1\n  print i + n\n  i = i + 1\n}",
+        ];
+        for source in unchanged {
+            let program = parse(source).unwrap();
+            assert_eq!(optimize(&program), program, "{:?}", source);
+        }
+        let source = "10 k = 2\n20 i = 0\n30 while i < k * k { i = i + 1 }\n40 goto 60\n50 print 1\n60 print i";
+        let program = parse_with_dialect(source, Dialect::Classic).unwrap();
+        assert_eq!(optimize(&program), program);
+    }
+
+    #[test]
+    fn test_behavior_kept() {
+        for source in PROGRAMS {
+            let program = parse(source).unwrap();
+            let output = SharedBuffer::new();
+            let expected = Interpreter::with_output(Box::new(output.clone())).run(&program);
+            let expected_output = output.contents();
+            let optimized: Program = optimize(&program);
+            let output = SharedBuffer::new();
+            assert_eq!(Interpreter::with_output(Box::new(output.clone())).run(&optimized), expected, "{:?}", source);
+            assert_eq!(output.contents(), expected_output, "{:?}", source);
+            for backend in [Backend::Stack, Backend::Register] {
+                let options = CompileOptions { backend, optimize_loops: true, ..CompileOptions::default() };
+                let output = SharedBuffer::new();
+                let result = compile_with(&program, &options).run(&mut Interpreter::with_output(Box::new(output.clone())));
+                assert_eq!(result, expected, "{:?} on {:?}", source, backend);
+                assert_eq!(output.contents(), expected_output, "{:?} on {:?}", source, backend);
+            }
+        }
+    }
+}
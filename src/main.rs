@@ -1,3 +1,10 @@
+use tbasic_rsc::cli::{self, Io};
+use tbasic_rsc::memory::CountingAllocator;
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
 fn main() {
-    println!("Hello, world!");
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    std::process::exit(cli::main(&args, &mut Io::std()));
 }
@@ -0,0 +1,74 @@
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+static IN_USE: AtomicUsize = AtomicUsize::new(0);
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+
+/// The system allocator, counting allocations and the bytes in use for
+/// [`usage`]. A program registers it with `#[global_allocator]`, as the
+/// `tbasic` binary does for `run --bench`.
+///
+/// It counts every allocation of the process, on any thread and whether
+/// for script values or the interpreter's and host's own data, so what it
+/// measures around a run is the process's heap, not what the script built.
+pub struct CountingAllocator;
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = System.alloc(layout);
+        if !ptr.is_null() {
+            allocated(layout.size());
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout);
+        IN_USE.fetch_sub(layout.size(), Ordering::Relaxed);
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_ptr = System.realloc(ptr, layout, new_size);
+        if !new_ptr.is_null() {
+            IN_USE.fetch_sub(layout.size(), Ordering::Relaxed);
+            allocated(new_size);
+        }
+        new_ptr
+    }
+}
+
+fn allocated(size: usize) {
+    ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+    let in_use = IN_USE.fetch_add(size, Ordering::Relaxed) + size;
+    PEAK.fetch_max(in_use, Ordering::Relaxed);
+}
+
+/// What [`CountingAllocator`] counted so far, for the whole process.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryUsage {
+    /// Allocations made, including reallocations.
+    pub allocations: usize,
+    /// Bytes allocated and not freed yet.
+    pub in_use: usize,
+    /// The peak heap bytes: the most that were in use at once since
+    /// [`reset_peak`].
+    pub peak_heap_bytes: usize,
+}
+
+/// What the allocator counted, or `None` if [`CountingAllocator`] isn't the
+/// global allocator.
+pub fn usage() -> Option<MemoryUsage> {
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed);
+    // Any program that got as far as asking has allocated something
+    (allocations > 0).then(|| MemoryUsage {
+        allocations,
+        in_use: IN_USE.load(Ordering::Relaxed),
+        peak_heap_bytes: PEAK.load(Ordering::Relaxed),
+    })
+}
+
+/// Starts measuring the peak again from the bytes in use now.
+pub fn reset_peak() {
+    PEAK.store(IN_USE.load(Ordering::Relaxed), Ordering::Relaxed);
+}
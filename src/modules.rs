@@ -0,0 +1,123 @@
+#[cfg(feature = "std")]
+use alloc::format;
+use alloc::string::{String, ToString};
+#[cfg(feature = "std")]
+use std::fs;
+#[cfg(feature = "std")]
+use std::io;
+#[cfg(feature = "std")]
+use std::path::{Path, PathBuf};
+
+use crate::collections::HashMap;
+
+/// Why a [`ModuleLoader`] couldn't read a module.
+#[cfg(feature = "std")]
+pub type LoadError = io::Error;
+
+/// Why a [`ModuleLoader`] couldn't read a module.
+#[cfg(not(feature = "std"))]
+pub type LoadError = String;
+
+/// Where `import` finds the source of modules.
+pub trait ModuleLoader {
+    /// Returns the source of the module `name`, or `None` if there is no such
+    /// module.
+    fn load(&mut self, name: &str) -> Result<Option<String>, LoadError>;
+}
+
+/// The extension of script and module files.
+pub const SOURCE_EXT: &str = "tb";
+
+/// The extension modules had before [`SOURCE_EXT`], still looked up when
+/// there is no `.tb` file.
+pub const LEGACY_SOURCE_EXT: &str = "bas";
+
+/// The file `import name` reads in `root`: `name.tb`, or `name.bas` if
+/// only that exists.
+#[cfg(feature = "std")]
+pub fn module_path(root: &Path, name: &str) -> PathBuf {
+    let path = root.join(format!("{}.{}", name, SOURCE_EXT));
+    let legacy = root.join(format!("{}.{}", name, LEGACY_SOURCE_EXT));
+    if !path.exists() && legacy.exists() {
+        legacy
+    } else {
+        path
+    }
+}
+
+/// Loads `import name` from the file `name.tb` in a directory, or from
+/// `name.bas` if there is no `name.tb`.
+#[cfg(feature = "std")]
+#[derive(Debug, Clone)]
+pub struct FileLoader {
+    root: PathBuf,
+}
+
+#[cfg(feature = "std")]
+impl FileLoader {
+    pub fn new(root: impl Into<PathBuf>) -> FileLoader {
+        FileLoader { root: root.into() }
+    }
+}
+
+#[cfg(feature = "std")]
+impl ModuleLoader for FileLoader {
+    fn load(&mut self, name: &str) -> io::Result<Option<String>> {
+        match fs::read_to_string(module_path(&self.root, name)) {
+            Ok(source) => Ok(Some(source)),
+            Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(error) => Err(error),
+        }
+    }
+}
+
+/// Serves modules from sources held in memory, e.g. in tests or when the
+/// host embeds its scripts.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryLoader {
+    modules: HashMap<String, String>,
+}
+
+impl MemoryLoader {
+    pub fn new() -> MemoryLoader {
+        MemoryLoader::default()
+    }
+
+    pub fn with_module(mut self, name: &str, source: &str) -> MemoryLoader {
+        self.modules.insert(name.to_string(), source.to_string());
+        self
+    }
+}
+
+impl ModuleLoader for MemoryLoader {
+    fn load(&mut self, name: &str) -> Result<Option<String>, LoadError> {
+        Ok(self.modules.get(name).cloned())
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod test {
+    use std::fs;
+
+    use crate::interpreter::Interpreter;
+    use crate::modules::{module_path, FileLoader, ModuleLoader};
+    use crate::value::Value;
+
+    #[test]
+    fn test_file_loader() {
+        let root = std::env::temp_dir().join(format!("tbasic-modules-{}", std::process::id()));
+        fs::create_dir_all(&root).unwrap();
+        fs::write(root.join("util.tb"), "fn twice(x) {\n  return x + x\n}").unwrap();
+        let mut interpreter = Interpreter::new();
+        interpreter.set_module_loader(Box::new(FileLoader::new(&root)));
+        assert_eq!(crate::execute_with("import util\nutil.twice(21)", &mut interpreter).unwrap(), Value::Int(42));
+
+        // `.bas` is only read when there is no `.tb`
+        fs::write(root.join("old.bas"), "x = 1").unwrap();
+        assert_eq!(module_path(&root, "old"), root.join("old.bas"));
+        fs::write(root.join("old.tb"), "x = 2").unwrap();
+        assert_eq!(FileLoader::new(&root).load("old").unwrap().as_deref(), Some("x = 2"));
+        assert_eq!(module_path(&root, "missing"), root.join("missing.tb"));
+        assert_eq!(FileLoader::new(&root).load("missing").unwrap(), None);
+    }
+}
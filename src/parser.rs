@@ -0,0 +1,1046 @@
+use alloc::boxed::Box;
+use alloc::rc::Rc;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use alloc::{format, vec};
+use core::fmt;
+
+use crate::ast::{BinaryOp, Expr, ExprKind, FunctionDecl, Program, Stmt, StmtKind, StructDecl, TypeAnnotation, UnaryOp};
+use crate::collections::{HashMap, HashSet};
+#[cfg(feature = "std")]
+use crate::diagnostic::Diagnostics;
+use crate::lexer::{LexError, Lexer, Span, SpannedToken, Token};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    /// The error's [stable code](crate::codes), e.g. `E0101`.
+    pub code: &'static str,
+    pub message: String,
+    pub span: Span,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.span.line, self.span.column, self.message)
+    }
+}
+
+impl core::error::Error for ParseError {}
+
+impl From<LexError> for ParseError {
+    fn from(error: LexError) -> Self {
+        ParseError { code: error.code, message: error.message, span: error.span }
+    }
+}
+
+/// Name given to functions defined with an `fn(...) { ... }` expression.
+pub const ANONYMOUS: &str = "<anonymous>";
+
+/// Language variants the parser understands.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Dialect {
+    #[default]
+    Modern,
+    /// Line-numbered programs in the style of classic BASIC: every top-level
+    /// line starts with a line number, and `gosub`/`return` jump between them.
+    Classic,
+}
+
+/// Default limit on how deeply expressions and blocks may nest. The parser
+/// and the passes over its tree recurse once per level, so this stays well
+/// below what a 2 MiB thread stack can hold in a debug build.
+pub const DEFAULT_MAX_NESTING: usize = 64;
+
+/// The choices a frontend offers for how a program is parsed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ParseOptions {
+    pub dialect: Dialect,
+    /// See [`Parser::set_strict_declarations`].
+    pub strict_declarations: bool,
+    /// See [`Parser::set_max_nesting`].
+    pub max_nesting: usize,
+}
+
+impl Default for ParseOptions {
+    fn default() -> ParseOptions {
+        ParseOptions { dialect: Dialect::default(), strict_declarations: false, max_nesting: DEFAULT_MAX_NESTING }
+    }
+}
+
+/// Lexes and parses `source` into a program.
+pub fn parse(source: &str) -> Result<Program, ParseError> {
+    parse_with_dialect(source, Dialect::Modern)
+}
+
+pub fn parse_with_dialect(source: &str, dialect: Dialect) -> Result<Program, ParseError> {
+    parse_with(source, ParseOptions { dialect, ..ParseOptions::default() })
+}
+
+/// Like [`parse`], with all of the `options`.
+pub fn parse_with(source: &str, options: ParseOptions) -> Result<Program, ParseError> {
+    let tokens = Lexer::new(source).tokenize_spanned()?;
+    let mut parser = Parser::new(tokens);
+    parser.set_options(options);
+    parser.parse()
+}
+
+/// Like [`parse_with_dialect`], reporting a failure to `diagnostics` as a
+/// lexer or a parser error.
+#[cfg(feature = "std")]
+pub fn parse_into(source: &str, dialect: Dialect, diagnostics: &mut Diagnostics) -> Option<Program> {
+    parse_into_with(source, ParseOptions { dialect, ..ParseOptions::default() }, diagnostics)
+}
+
+/// Like [`parse_into`], with all of the `options`.
+#[cfg(feature = "std")]
+pub fn parse_into_with(source: &str, options: ParseOptions, diagnostics: &mut Diagnostics) -> Option<Program> {
+    let tokens = match Lexer::new(source).tokenize_spanned() {
+        Ok(tokens) => tokens,
+        Err(error) => {
+            diagnostics.push(error);
+            return None;
+        },
+    };
+    let mut parser = Parser::new(tokens);
+    parser.set_options(options);
+    parser.parse().map_err(|error| diagnostics.push(error)).ok()
+}
+
+/// Whether `source` only fails to parse because it ends inside an unclosed
+/// `(`, `[` or `{`, so that more lines could still complete it. The REPL
+/// uses this to ask for another line instead of reporting an error.
+pub fn is_incomplete(source: &str, dialect: Dialect) -> bool {
+    let Ok(tokens) = Lexer::new(source).tokenize_spanned() else {
+        return false;
+    };
+    let mut depth = 0i64;
+    for token in &tokens {
+        match token.token {
+            Token::Lparen | Token::Lbracket | Token::CurlyL => depth += 1,
+            Token::Rparen | Token::Rbracket | Token::CurlyR => depth -= 1,
+            _ => {},
+        }
+    }
+    let (true, Some(end)) = (depth > 0, tokens.last().map(|token| token.span.end)) else {
+        return false;
+    };
+    let mut parser = Parser::new(tokens);
+    parser.set_dialect(dialect);
+    match parser.parse() {
+        Ok(_) => false,
+        Err(error) => error.span.start >= end,
+    }
+}
+
+pub struct Parser {
+    tokens: Vec<SpannedToken>,
+    position: usize,
+    dialect: Dialect,
+    // Number of function bodies enclosing the current position
+    function_depth: usize,
+    // Number of loops enclosing the current position within the current function
+    loop_depth: usize,
+    strict_declarations: bool,
+    // Number of expressions and blocks enclosing the current position
+    nesting: usize,
+    max_nesting: usize,
+    // Names declared so far at the top level, then in each function enclosing
+    // the current position
+    declared: Vec<HashSet<String>>,
+}
+
+impl Parser {
+    pub fn new(tokens: Vec<SpannedToken>) -> Parser {
+        Parser {
+            tokens,
+            position: 0,
+            dialect: Dialect::Modern,
+            function_depth: 0,
+            loop_depth: 0,
+            strict_declarations: false,
+            nesting: 0,
+            max_nesting: DEFAULT_MAX_NESTING,
+            declared: vec![HashSet::default()],
+        }
+    }
+
+    pub fn set_options(&mut self, options: ParseOptions) {
+        self.set_dialect(options.dialect);
+        self.set_strict_declarations(options.strict_declarations);
+        self.set_max_nesting(options.max_nesting);
+    }
+
+    pub fn set_dialect(&mut self, dialect: Dialect) {
+        self.dialect = dialect;
+    }
+
+    /// Makes assigning to a variable an error unless `let`, a parameter,
+    /// `global`, `fn`, `struct` or `import` declared it earlier in the same
+    /// function or at the top level, catching misspelt names. Reads aren't
+    /// affected.
+    pub fn set_strict_declarations(&mut self, strict: bool) {
+        self.strict_declarations = strict;
+    }
+
+    /// Makes nesting expressions or blocks more than `max` levels deep an
+    /// error, so that untrusted source can't overflow the stack of the
+    /// parser or of what runs the program. A block counts as two levels,
+    /// since parsing a statement takes about twice the stack of an
+    /// expression.
+    pub fn set_max_nesting(&mut self, max: usize) {
+        self.max_nesting = max;
+    }
+
+    fn declare(&mut self, name: &str) {
+        self.declared.last_mut().unwrap().insert(name.to_string());
+    }
+
+    /// Parses the whole program. Classic programs run in the order of their
+    /// line numbers, whatever order the lines are written in.
+    pub fn parse(&mut self) -> Result<Program, ParseError> {
+        let mut numbered = Vec::new();
+        let mut numbers: HashSet<i64> = HashSet::default();
+        let mut functions = HashSet::default();
+        self.skip_newlines();
+        while self.peek().is_some() {
+            let mut line = 0;
+            if self.dialect == Dialect::Classic {
+                let span = self.current_span();
+                line = self.line_number()?;
+                if !numbers.insert(line) {
+                    return Err(self.error_at("E0108", &format!("duplicate line number {}", line), span));
+                }
+            }
+            let statement = self.statement()?;
+            self.no_redefinition(&mut functions, &statement)?;
+            numbered.push((line, statement));
+            self.end_of_statement()?;
+            self.skip_newlines();
+        }
+        let mut lines = HashMap::default();
+        if self.dialect == Dialect::Classic {
+            numbered.sort_by_key(|&(line, _)| line);
+            lines.extend(numbered.iter().enumerate().map(|(i, &(line, _))| (line, i)));
+        }
+        let statements = numbered.into_iter().map(|(_, statement)| statement).collect();
+        Ok(Program { statements, lines })
+    }
+
+    fn line_number(&mut self) -> Result<i64, ParseError> {
+        match self.peek() {
+            Some(Token::Number(n)) => {
+                let n = *n;
+                self.advance();
+                Ok(n)
+            },
+            Some(token) => Err(self.error("E0108", &format!("expected line number, found `{}`", token))),
+            None => Err(self.error("E0108", "expected line number, found end of input")),
+        }
+    }
+
+    fn classic_only(&self, keyword: &str, span: Span) -> Result<(), ParseError> {
+        if self.dialect == Dialect::Classic {
+            Ok(())
+        } else {
+            Err(self.error_at("E0107", &format!("`{}` is only available in the classic dialect", keyword), span))
+        }
+    }
+
+    fn statement(&mut self) -> Result<Stmt, ParseError> {
+        let start = self.current_span();
+        match self.peek() {
+            Some(Token::Doc(_)) => self.documented(),
+            Some(Token::If) => self.if_statement(),
+            Some(Token::Fn) if matches!(self.tokens.get(self.position + 1).map(|t| &t.token), Some(Token::Id(_))) => {
+                self.advance();
+                let name = self.identifier()?;
+                self.declare(&name);
+                let decl = self.function_rest(name, start)?;
+                let span = decl.span;
+                Ok(Stmt { kind: StmtKind::Function(Rc::new(decl)), span })
+            },
+            Some(Token::While) => {
+                self.advance();
+                let condition = self.expression()?;
+                self.loop_depth += 1;
+                let body = self.block();
+                self.loop_depth -= 1;
+                let body = body?;
+                Ok(Stmt { kind: StmtKind::While { condition, body }, span: start.to(self.previous_span()) })
+            },
+            Some(Token::Break) | Some(Token::Continue) => {
+                let kind = if self.check(&Token::Break) { StmtKind::Break } else { StmtKind::Continue };
+                let keyword = self.peek().unwrap().to_string();
+                self.advance();
+                if self.loop_depth == 0 {
+                    return Err(self.error_at("E0105", &format!("`{}` outside of a loop", keyword), start));
+                }
+                Ok(Stmt { kind, span: start })
+            },
+            Some(Token::Goto) | Some(Token::Gosub) => {
+                let keyword = self.peek().unwrap().to_string();
+                let gosub = self.check(&Token::Gosub);
+                self.advance();
+                self.classic_only(&keyword, start)?;
+                if self.function_depth > 0 {
+                    return Err(self.error_at("E0109", &format!("`{}` inside a function", keyword), start));
+                }
+                let line = self.line_number()?;
+                let kind = if gosub { StmtKind::Gosub(line) } else { StmtKind::Goto(line) };
+                Ok(Stmt { kind, span: start.to(self.previous_span()) })
+            },
+            Some(Token::Return) if self.function_depth == 0 && self.dialect == Dialect::Classic => {
+                self.advance();
+                Ok(Stmt { kind: StmtKind::SubReturn, span: start })
+            },
+            Some(Token::Return) => {
+                self.advance();
+                if self.function_depth == 0 {
+                    return Err(self.error_at("E0106", "`return` outside of a function", start));
+                }
+                let value = match self.peek() {
+                    None | Some(Token::Newline) | Some(Token::CurlyR) => None,
+                    _ => Some(self.expression()?),
+                };
+                let span = value.as_ref().map_or(start, |value| start.to(value.span));
+                Ok(Stmt { kind: StmtKind::Return(value), span })
+            },
+            Some(Token::Struct) => {
+                self.advance();
+                let name = self.identifier()?;
+                self.declare(&name);
+                self.expect(&Token::CurlyL)?;
+                let mut fields = Vec::new();
+                self.skip_newlines();
+                while !self.check(&Token::CurlyR) {
+                    let span = self.current_span();
+                    let field = self.identifier()?;
+                    if fields.contains(&field) {
+                        let message = format!("duplicate field `{}` in struct `{}`", field, name);
+                        return Err(self.error_at("E0104", &message, span));
+                    }
+                    fields.push(field);
+                    self.skip_newlines();
+                    if !self.eat(&Token::Comma) {
+                        break;
+                    }
+                    self.skip_newlines();
+                }
+                let span = start.to(self.expect(&Token::CurlyR)?);
+                Ok(Stmt { kind: StmtKind::Struct(Rc::new(StructDecl { name, fields, span, doc: None })), span })
+            },
+            Some(Token::Global) => {
+                self.advance();
+                let mut names = vec![self.identifier()?];
+                while self.eat(&Token::Comma) {
+                    names.push(self.identifier()?);
+                }
+                for name in &names {
+                    self.declare(name);
+                }
+                Ok(Stmt { kind: StmtKind::Global(names), span: start.to(self.previous_span()) })
+            },
+            Some(Token::Import) => {
+                self.advance();
+                let name = self.identifier()?;
+                self.declare(&name);
+                Ok(Stmt { kind: StmtKind::Import(name), span: start.to(self.previous_span()) })
+            },
+            Some(Token::Let) => {
+                self.advance();
+                let name = self.identifier()?;
+                let ty = if self.eat(&Token::Colon) { Some(self.annotation()?) } else { None };
+                self.expect(&Token::Assign)?;
+                let value = self.expression()?;
+                self.declare(&name);
+                let span = start.to(value.span);
+                Ok(Stmt { kind: StmtKind::Assign { name, ty, value, declaration: true }, span })
+            },
+            Some(Token::Print) => {
+                self.advance();
+                let mut args = Vec::new();
+                if !matches!(self.peek(), None | Some(Token::Newline) | Some(Token::CurlyR)) {
+                    args.push(self.expression()?);
+                    while self.eat(&Token::Comma) {
+                        args.push(self.expression()?);
+                    }
+                }
+                Ok(Stmt { kind: StmtKind::Print(args), span: start.to(self.previous_span()) })
+            },
+            _ => {
+                let expr = self.expression()?;
+                let ty = match expr.kind {
+                    ExprKind::Variable(_) if self.eat(&Token::Colon) => Some(self.annotation()?),
+                    _ => None,
+                };
+                if ty.is_some() || self.check(&Token::Assign) {
+                    self.expect(&Token::Assign)?;
+                    let value = self.expression()?;
+                    let span = start.to(value.span);
+                    let kind = match expr.kind {
+                        ExprKind::Variable(name) => {
+                            if self.strict_declarations && !self.declared.last().unwrap().contains(&name) {
+                                let message = format!("assignment to undeclared variable `{}`; declare it with `let`", name);
+                                return Err(self.error_at("E0103", &message, expr.span));
+                            }
+                            StmtKind::Assign { name, ty, value, declaration: false }
+                        },
+                        ExprKind::Index { target, index } => StmtKind::SetIndex { target: *target, index: *index, value },
+                        ExprKind::Field { target, field } => StmtKind::SetField { target: *target, field, value },
+                        _ => return Err(self.error_at("E0102", "invalid assignment target", expr.span)),
+                    };
+                    Ok(Stmt { kind, span })
+                } else {
+                    let span = expr.span;
+                    Ok(Stmt { kind: StmtKind::Expr(expr), span })
+                }
+            },
+        }
+    }
+
+    /// A named function or a struct after the lines of its doc comment.
+    fn documented(&mut self) -> Result<Stmt, ParseError> {
+        let start = self.current_span();
+        let mut lines = Vec::new();
+        while let Some(Token::Doc(line)) = self.peek() {
+            lines.push(line.clone());
+            self.advance();
+            self.eat(&Token::Newline);
+        }
+        let named_function = matches!(self.tokens.get(self.position + 1).map(|t| &t.token), Some(Token::Id(_)));
+        if !matches!(self.peek(), Some(Token::Fn) if named_function) && !self.check(&Token::Struct) {
+            return Err(self.error_at("E0110", "doc comment must come before a named function or a struct", start));
+        }
+        let mut statement = self.statement()?;
+        let doc = Some(lines.join("\n"));
+        match &mut statement.kind {
+            StmtKind::Function(decl) => Rc::get_mut(decl).unwrap().doc = doc,
+            StmtKind::Struct(decl) => Rc::get_mut(decl).unwrap().doc = doc,
+            _ => unreachable!(),
+        }
+        Ok(statement)
+    }
+
+    fn if_statement(&mut self) -> Result<Stmt, ParseError> {
+        let start = self.expect(&Token::If)?;
+        let condition = self.expression()?;
+        let then_branch = self.block()?;
+        let mut end = self.previous_span();
+
+        // Allow `else` on the line after the closing brace
+        let mut lookahead = self.position;
+        while let Some(Token::Newline) = self.tokens.get(lookahead).map(|t| &t.token) {
+            lookahead += 1;
+        }
+        let else_branch = if let Some(Token::Else) = self.tokens.get(lookahead).map(|t| &t.token) {
+            self.position = lookahead + 1;
+            let branch = if let Some(Token::If) = self.peek() {
+                vec![self.if_statement()?]
+            } else {
+                self.block()?
+            };
+            end = self.previous_span();
+            Some(branch)
+        } else {
+            None
+        };
+
+        Ok(Stmt { kind: StmtKind::If { condition, then_branch, else_branch }, span: start.to(end) })
+    }
+
+    /// Parses a function's parameter list and body.
+    fn function_rest(&mut self, name: String, start: Span) -> Result<FunctionDecl, ParseError> {
+        self.expect(&Token::Lparen)?;
+        let mut params = Vec::new();
+        let mut param_types = Vec::new();
+        while !self.check(&Token::Rparen) {
+            let span = self.current_span();
+            let param = self.identifier()?;
+            if params.contains(&param) {
+                return Err(self.error_at("E0104", &format!("duplicate parameter `{}`", param), span));
+            }
+            params.push(param);
+            param_types.push(if self.eat(&Token::Colon) { Some(self.annotation()?) } else { None });
+            if !self.eat(&Token::Comma) {
+                break;
+            }
+        }
+        self.expect(&Token::Rparen)?;
+        let return_type = if self.eat(&Token::Colon) { Some(self.annotation()?) } else { None };
+        let loop_depth = core::mem::replace(&mut self.loop_depth, 0);
+        self.function_depth += 1;
+        self.declared.push(params.iter().cloned().collect());
+        let body = self.block();
+        self.declared.pop();
+        self.function_depth -= 1;
+        self.loop_depth = loop_depth;
+        let body = body?;
+        let span = start.to(self.previous_span());
+        Ok(FunctionDecl { name, params, param_types, return_type, body, span, doc: None })
+    }
+
+    fn annotation(&mut self) -> Result<TypeAnnotation, ParseError> {
+        let span = self.current_span();
+        let name = self.identifier()?;
+        Ok(TypeAnnotation { name, span })
+    }
+
+    fn identifier(&mut self) -> Result<String, ParseError> {
+        match self.peek().cloned() {
+            Some(Token::Id(name)) => {
+                self.advance();
+                Ok(name)
+            },
+            Some(token) => Err(self.error("E0101", &format!("expected identifier, found `{}`", token))),
+            None => Err(self.error("E0101", "expected identifier, found end of input")),
+        }
+    }
+
+    fn block(&mut self) -> Result<Vec<Stmt>, ParseError> {
+        self.nested("block", 2, Parser::statements)
+    }
+
+    fn statements(&mut self) -> Result<Vec<Stmt>, ParseError> {
+        self.expect(&Token::CurlyL)?;
+        let mut statements = Vec::new();
+        let mut functions = HashSet::default();
+        self.skip_newlines();
+        while !self.check(&Token::CurlyR) {
+            if self.peek().is_none() {
+                return Err(self.error("E0101", "expected `}` to close block"));
+            }
+            let statement = self.statement()?;
+            self.no_redefinition(&mut functions, &statement)?;
+            statements.push(statement);
+            self.end_of_statement()?;
+            self.skip_newlines();
+        }
+        self.expect(&Token::CurlyR)?;
+        Ok(statements)
+    }
+
+    /// Fails if `statement` defines a function named like one the same block
+    /// defined before, whose name `functions` holds.
+    fn no_redefinition(&self, functions: &mut HashSet<String>, statement: &Stmt) -> Result<(), ParseError> {
+        match &statement.kind {
+            StmtKind::Function(decl) if !functions.insert(decl.name.clone()) => {
+                let message = format!("function `{}` is already defined in this block", decl.name);
+                Err(self.error_at("E0104", &message, statement.span))
+            },
+            _ => Ok(()),
+        }
+    }
+
+    fn end_of_statement(&mut self) -> Result<(), ParseError> {
+        match self.peek() {
+            None | Some(Token::CurlyR) => Ok(()),
+            Some(Token::Newline) => {
+                self.advance();
+                Ok(())
+            },
+            Some(token) => Err(self.error("E0101", &format!("expected end of statement, found `{}`", token))),
+        }
+    }
+
+    fn expression(&mut self) -> Result<Expr, ParseError> {
+        self.nested("expression", 1, Parser::or)
+    }
+
+    /// Runs `parse` `levels` deeper, failing past the nesting limit.
+    fn nested<T>(&mut self, what: &str, levels: usize, parse: fn(&mut Parser) -> Result<T, ParseError>) -> Result<T, ParseError> {
+        if self.nesting + levels > self.max_nesting {
+            return Err(self.error("E0111", &format!("{} nested too deeply, more than {} levels", what, self.max_nesting)));
+        }
+        self.nesting += levels;
+        let result = parse(self);
+        self.nesting -= levels;
+        result
+    }
+
+    fn or(&mut self) -> Result<Expr, ParseError> {
+        self.chain(Parser::and, |token| matches!(token, Token::Or).then_some(BinaryOp::Or))
+    }
+
+    fn and(&mut self) -> Result<Expr, ParseError> {
+        self.chain(Parser::comparison, |token| matches!(token, Token::And).then_some(BinaryOp::And))
+    }
+
+    fn comparison(&mut self) -> Result<Expr, ParseError> {
+        self.chain(Parser::additive, |token| match token {
+            Token::Equals => Some(BinaryOp::Equals),
+            Token::NotEquals => Some(BinaryOp::NotEquals),
+            Token::SmallerThan => Some(BinaryOp::SmallerThan),
+            Token::GreaterThan => Some(BinaryOp::GreaterThan),
+            Token::SmallerEquals => Some(BinaryOp::SmallerEquals),
+            Token::GreaterEquals => Some(BinaryOp::GreaterEquals),
+            _ => None,
+        })
+    }
+
+    fn additive(&mut self) -> Result<Expr, ParseError> {
+        self.chain(Parser::multiplicative, |token| match token {
+            Token::Plus => Some(BinaryOp::Add),
+            Token::Minus => Some(BinaryOp::Sub),
+            _ => None,
+        })
+    }
+
+    fn multiplicative(&mut self) -> Result<Expr, ParseError> {
+        self.chain(Parser::unary, |token| match token {
+            Token::Multiply => Some(BinaryOp::Mul),
+            Token::Divide => Some(BinaryOp::Div),
+            _ => None,
+        })
+    }
+
+    /// Parses `operand`s joined by the operators `op` recognizes, grouping
+    /// them to the left. Every operator nests the chain one level deeper,
+    /// since what walks the tree recurses into its left operand.
+    fn chain(&mut self, operand: fn(&mut Parser) -> Result<Expr, ParseError>, op: fn(&Token) -> Option<BinaryOp>) -> Result<Expr, ParseError> {
+        let mut left = operand(self)?;
+        let nesting = self.nesting;
+        while let Some(op) = self.peek().and_then(op) {
+            if self.nesting >= self.max_nesting {
+                return Err(self.error("E0111", &format!("expression nested too deeply, more than {} levels", self.max_nesting)));
+            }
+            self.nesting += 1;
+            self.advance();
+            let right = operand(self)?;
+            left = binary(op, left, right);
+        }
+        self.nesting = nesting;
+        Ok(left)
+    }
+
+    fn unary(&mut self) -> Result<Expr, ParseError> {
+        let start = self.current_span();
+        let op = match self.peek() {
+            Some(Token::Minus) => UnaryOp::Neg,
+            Some(Token::Not) => UnaryOp::Not,
+            _ => return self.call(),
+        };
+        self.advance();
+        let operand = self.nested("expression", 1, Parser::unary)?;
+        let span = start.to(operand.span);
+        Ok(Expr { kind: ExprKind::Unary { op, operand: Box::new(operand) }, span })
+    }
+
+    fn call(&mut self) -> Result<Expr, ParseError> {
+        let mut expr = self.primary()?;
+        loop {
+            if self.eat(&Token::Lparen) {
+                let args = self.list(&Token::Rparen)?;
+                let end = self.expect(&Token::Rparen)?;
+                let span = expr.span.to(end);
+                expr = Expr { kind: ExprKind::Call { callee: Box::new(expr), args }, span };
+            } else if self.eat(&Token::Dot) {
+                let field = self.identifier()?;
+                let span = expr.span.to(self.previous_span());
+                expr = Expr { kind: ExprKind::Field { target: Box::new(expr), field }, span };
+            } else if self.eat(&Token::Lbracket) {
+                let index = self.expression()?;
+                let end = self.expect(&Token::Rbracket)?;
+                let span = expr.span.to(end);
+                expr = Expr { kind: ExprKind::Index { target: Box::new(expr), index: Box::new(index) }, span };
+            } else {
+                return Ok(expr);
+            }
+        }
+    }
+
+    /// Parses comma-separated expressions up to, but not including, `end`.
+    fn list(&mut self, end: &Token) -> Result<Vec<Expr>, ParseError> {
+        let mut items = Vec::new();
+        while !self.check(end) {
+            items.push(self.expression()?);
+            if !self.eat(&Token::Comma) {
+                break;
+            }
+        }
+        Ok(items)
+    }
+
+    /// Entries of a map literal, which unlike other brackets may span lines.
+    fn map_entries(&mut self) -> Result<Vec<(Expr, Expr)>, ParseError> {
+        let mut entries = Vec::new();
+        self.skip_newlines();
+        while !self.check(&Token::CurlyR) {
+            let key = self.expression()?;
+            self.expect(&Token::Colon)?;
+            entries.push((key, self.expression()?));
+            self.skip_newlines();
+            if !self.eat(&Token::Comma) {
+                break;
+            }
+            self.skip_newlines();
+        }
+        Ok(entries)
+    }
+
+    fn primary(&mut self) -> Result<Expr, ParseError> {
+        let span = self.current_span();
+        let kind = match self.peek().cloned() {
+            Some(Token::Number(n)) => ExprKind::Number(n),
+            Some(Token::Float(n)) => ExprKind::Float(n),
+            Some(Token::Str(s)) => ExprKind::Str(s),
+            Some(Token::Id(name)) => ExprKind::Variable(name),
+            Some(Token::Lparen) => {
+                self.advance();
+                let mut inner = self.expression()?;
+                let end = self.expect(&Token::Rparen)?;
+                inner.span = span.to(end);
+                return Ok(inner);
+            },
+            Some(Token::Fn) => {
+                self.advance();
+                let decl = self.function_rest(ANONYMOUS.to_string(), span)?;
+                let span = decl.span;
+                return Ok(Expr { kind: ExprKind::Function(Rc::new(decl)), span });
+            },
+            Some(Token::Lbracket) => {
+                self.advance();
+                let items = self.list(&Token::Rbracket)?;
+                let end = self.expect(&Token::Rbracket)?;
+                return Ok(Expr { kind: ExprKind::Array(items), span: span.to(end) });
+            },
+            Some(Token::CurlyL) => {
+                self.advance();
+                let entries = self.map_entries()?;
+                let end = self.expect(&Token::CurlyR)?;
+                return Ok(Expr { kind: ExprKind::Map(entries), span: span.to(end) });
+            },
+            Some(token) => return Err(self.error("E0101", &format!("expected expression, found `{}`", token))),
+            None => return Err(self.error("E0101", "expected expression, found end of input")),
+        };
+        self.advance();
+        Ok(Expr { kind, span })
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position).map(|t| &t.token)
+    }
+
+    fn check(&self, token: &Token) -> bool {
+        self.peek() == Some(token)
+    }
+
+    fn advance(&mut self) -> Option<&SpannedToken> {
+        let token = self.tokens.get(self.position);
+        if token.is_some() {
+            self.position += 1;
+        }
+        token
+    }
+
+    fn eat(&mut self, token: &Token) -> bool {
+        if self.check(token) {
+            self.advance();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, token: &Token) -> Result<Span, ParseError> {
+        if self.check(token) {
+            Ok(self.advance().unwrap().span)
+        } else {
+            let found = match self.peek() {
+                Some(found) => format!("`{}`", found),
+                None => "end of input".to_string(),
+            };
+            Err(self.error("E0101", &format!("expected `{}`, found {}", token, found)))
+        }
+    }
+
+    fn skip_newlines(&mut self) {
+        while self.eat(&Token::Newline) {}
+    }
+
+    fn current_span(&self) -> Span {
+        match self.tokens.get(self.position) {
+            Some(token) => token.span,
+            None => {
+                let end = self.previous_span();
+                Span { start: end.end, ..end }
+            },
+        }
+    }
+
+    fn previous_span(&self) -> Span {
+        self.position
+            .checked_sub(1)
+            .and_then(|i| self.tokens.get(i))
+            .map(|t| t.span)
+            .unwrap_or_default()
+    }
+
+    fn error(&self, code: &'static str, message: &str) -> ParseError {
+        self.error_at(code, message, self.current_span())
+    }
+
+    fn error_at(&self, code: &'static str, message: &str, span: Span) -> ParseError {
+        ParseError { code, message: message.to_string(), span }
+    }
+}
+
+fn binary(op: BinaryOp, left: Expr, right: Expr) -> Expr {
+    let span = left.span.to(right.span);
+    Expr { kind: ExprKind::Binary { op, left: Box::new(left), right: Box::new(right) }, span }
+}
+
+#[cfg(test)]
+mod test {
+    use alloc::format;
+    use alloc::string::ToString;
+    use alloc::vec;
+    use alloc::vec::Vec;
+
+    use crate::ast::{BinaryOp, ExprKind, StmtKind};
+    use crate::lexer::Lexer;
+    use crate::parser::{is_incomplete, parse, parse_with, parse_with_dialect, Dialect, ParseOptions, Parser};
+
+    #[test]
+    fn test_precedence() {
+        let program = parse("x = 1 + 2 * 3").unwrap();
+        match &program.statements[0].kind {
+            StmtKind::Assign { name, value, .. } => {
+                assert_eq!(name, "x");
+                match &value.kind {
+                    ExprKind::Binary { op: BinaryOp::Add, right, .. } => {
+                        assert!(matches!(right.kind, ExprKind::Binary { op: BinaryOp::Mul, .. }));
+                    },
+                    other => panic!("unexpected expression {:?}", other),
+                }
+            },
+            other => panic!("unexpected statement {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_if_else_across_lines() {
+        let program = parse("if x == 1 {\n  y = 2\n}\nelse {\n  y = 3\n}\nprint y").unwrap();
+        assert_eq!(program.statements.len(), 2);
+        assert!(matches!(program.statements[0].kind, StmtKind::If { else_branch: Some(_), .. }));
+    }
+
+    #[test]
+    fn test_call() {
+        let program = parse("x = input(\"name? \", 1 + 2)").unwrap();
+        match &program.statements[0].kind {
+            StmtKind::Assign { value, .. } => match &value.kind {
+                ExprKind::Call { callee, args } => {
+                    assert_eq!(callee.kind, ExprKind::Variable("input".to_string()));
+                    assert_eq!(args.len(), 2);
+                },
+                other => panic!("unexpected expression {:?}", other),
+            },
+            other => panic!("unexpected statement {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_index_assignment() {
+        let program = parse("a = [1, [2, 3],]\na[1][0] = 4").unwrap();
+        assert!(matches!(&program.statements[0].kind, StmtKind::Assign { value, .. } if matches!(&value.kind, ExprKind::Array(items) if items.len() == 2)));
+        match &program.statements[1].kind {
+            StmtKind::SetIndex { target, index, .. } => {
+                assert!(matches!(target.kind, ExprKind::Index { .. }));
+                assert_eq!(index.kind, ExprKind::Number(0));
+            },
+            other => panic!("unexpected statement {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_function() {
+        let program = parse("fn add(a, b) {\n  return a + b\n}\nadd(1, 2)").unwrap();
+        match &program.statements[0].kind {
+            StmtKind::Function(decl) => {
+                assert_eq!(decl.name, "add");
+                assert_eq!(decl.params, vec!["a".to_string(), "b".to_string()]);
+                assert!(matches!(decl.body[0].kind, StmtKind::Return(Some(_))));
+            },
+            other => panic!("unexpected statement {:?}", other),
+        }
+
+        let error = parse("return 1").unwrap_err();
+        assert_eq!(error.message, "`return` outside of a function");
+    }
+
+    #[test]
+    fn test_doc_comments() {
+        let program = parse("## Adds `a` and `b`.\n##\n## Both are ints.\nfn add(a, b) {\n  ## A point.\n  struct P { x }\n}").unwrap();
+        let StmtKind::Function(decl) = &program.statements[0].kind else {
+            panic!("unexpected statement {:?}", program.statements[0]);
+        };
+        assert_eq!(decl.doc.as_deref(), Some("Adds `a` and `b`.\n\nBoth are ints."));
+        assert_eq!(decl.span.line, 4);
+        assert!(matches!(&decl.body[0].kind, StmtKind::Struct(decl) if decl.doc.as_deref() == Some("A point.")));
+
+        for source in ["## x\nx = 1", "## f\nf = fn() {}", "## end"] {
+            let error = parse(source).unwrap_err();
+            assert_eq!((error.code, error.span.line), ("E0110", 1), "{}", source);
+        }
+    }
+
+    #[test]
+    fn test_anonymous_function() {
+        let program = parse("double = fn(x) { return x * 2 }\nfn(){}()").unwrap();
+        match &program.statements[0].kind {
+            StmtKind::Assign { value, .. } => {
+                assert!(matches!(&value.kind, ExprKind::Function(decl) if decl.params.len() == 1));
+            },
+            other => panic!("unexpected statement {:?}", other),
+        }
+        assert!(matches!(&program.statements[1].kind, StmtKind::Expr(expr) if matches!(expr.kind, ExprKind::Call { .. })));
+    }
+
+    #[test]
+    fn test_annotations() {
+        let program = parse("x: int = 1\nfn f(a: string, b): bool {\n  return a == b\n}").unwrap();
+        assert!(matches!(&program.statements[0].kind, StmtKind::Assign { ty: Some(ty), .. } if ty.name == "int"));
+        match &program.statements[1].kind {
+            StmtKind::Function(decl) => {
+                let types: Vec<_> = decl.param_types.iter().map(|ty| ty.as_ref().map(|ty| ty.name.as_str())).collect();
+                assert_eq!(types, vec![Some("string"), None]);
+                assert_eq!(decl.return_type.as_ref().unwrap().name, "bool");
+            },
+            other => panic!("unexpected statement {:?}", other),
+        }
+        assert!(parse("x: int").is_err());
+        assert!(parse("a[0]: int = 1").is_err());
+    }
+
+    #[test]
+    fn test_loops() {
+        let program = parse("while x < 10 {\n  if x == 5 { break }\n  continue\n}").unwrap();
+        assert!(matches!(&program.statements[0].kind, StmtKind::While { body, .. } if body.len() == 2));
+
+        let error = parse("break").unwrap_err();
+        assert_eq!(error.message, "`break` outside of a loop");
+        let error = parse("while 1 { fn f() { continue } }").unwrap_err();
+        assert_eq!(error.message, "`continue` outside of a loop");
+    }
+
+    #[test]
+    fn test_classic_lines() {
+        let program = parse_with_dialect("10 gosub 30\n20 print 1\n30 print 2\n40 return", Dialect::Classic).unwrap();
+        assert_eq!(program.lines.get(&30), Some(&2));
+        assert_eq!(program.statements[0].kind, StmtKind::Gosub(30));
+        assert_eq!(program.statements[3].kind, StmtKind::SubReturn);
+
+        // Lines are ordered by number, not by where they are written
+        let program = parse_with_dialect("20 print 2\n10 print 1", Dialect::Classic).unwrap();
+        assert_eq!((program.lines.get(&10), program.lines.get(&20)), (Some(&0), Some(&1)));
+        assert_eq!(program.statements[0].span.line, 2);
+
+        let error = parse_with_dialect("10 print 1\n10 print 2", Dialect::Classic).unwrap_err();
+        assert_eq!(error.message, "duplicate line number 10");
+        let error = parse_with_dialect("print 1", Dialect::Classic).unwrap_err();
+        assert_eq!(error.message, "expected line number, found `print`");
+        let error = parse("gosub 10").unwrap_err();
+        assert_eq!(error.message, "`gosub` is only available in the classic dialect");
+        let error = parse_with_dialect("10 fn f() { goto 10 }", Dialect::Classic).unwrap_err();
+        assert_eq!(error.message, "`goto` inside a function");
+    }
+
+    #[test]
+    fn test_is_incomplete() {
+        for source in ["fn f(x) {", "while x < 3 {\n  x = x + 1\n", "print max(1,\n", "a = [1, 2", "if x {\n} else {"] {
+            assert!(is_incomplete(source, Dialect::Modern), "{:?}", source);
+        }
+        for source in ["", "x = 1", "fn f() { return 1 }", "x = (1 + ]", "x = ", "print \"{", "if x { 1 2"] {
+            assert!(!is_incomplete(source, Dialect::Modern), "{:?}", source);
+        }
+        assert!(is_incomplete("10 if x {", Dialect::Classic));
+    }
+
+    #[test]
+    fn test_errors() {
+        let error = parse("x = (1 + 2").unwrap_err();
+        assert_eq!(error.message, "expected `)`, found end of input");
+
+        let error = parse("x = 1 2").unwrap_err();
+        assert_eq!(error.message, "expected end of statement, found `2`");
+        assert_eq!((error.span.line, error.span.column), (1, 7));
+    }
+
+    #[test]
+    fn test_nesting() {
+        let nested = |open: &str, close: &str, depth: usize| format!("x = {}1{}", open.repeat(depth), close.repeat(depth));
+        assert!(parse(&format!("x = 1{}", " + 1".repeat(63))).is_ok());
+        assert!(parse(&nested("(", ")", 63)).is_ok());
+        assert!(parse(&nested("[", "]", 63)).is_ok());
+        for source in [nested("(", ")", 10_000), nested("[", "]", 10_000), nested("-", "", 100_000), nested("f(", ")", 10_000), nested("", " + 1", 100_000)] {
+            let error = parse(&source).unwrap_err();
+            assert_eq!((error.code, error.message.as_str()), ("E0111", "expression nested too deeply, more than 64 levels"));
+        }
+        let blocks = |depth: usize| format!("{}x = 1{}", "if 1 {\n".repeat(depth), "\n}".repeat(depth));
+        assert!(parse(&blocks(31)).is_ok());
+        assert_eq!(parse(&blocks(10_000)).unwrap_err().code, "E0111");
+        let error = parse(&format!("{}}}", "fn f() {\n".repeat(10_000))).unwrap_err();
+        assert_eq!(error.message, "block nested too deeply, more than 64 levels");
+
+        let options = ParseOptions { max_nesting: 3, ..ParseOptions::default() };
+        assert!(parse_with("x = ((1))", options).is_ok());
+        assert!(parse_with("x = (((1)))", options).is_err());
+    }
+
+    #[test]
+    fn test_strict_declarations() {
+        let strict = |source: &str| {
+            let mut parser = Parser::new(Lexer::new(source).tokenize_spanned().unwrap());
+            parser.set_strict_declarations(true);
+            parser.parse()
+        };
+        let source = "let total = 0\ntotal = total + 1\nfn f(n) {\n  global total\n  let k: int = n\n  k = k + 1\n  total = k\n}\nimport math\nmath = 1";
+        let program = strict(source).unwrap();
+        assert!(matches!(&program.statements[0].kind, StmtKind::Assign { declaration: true, .. }));
+        assert!(matches!(&program.statements[1].kind, StmtKind::Assign { declaration: false, .. }));
+
+        let error = strict("let total = 0\ntotl = total + 1").unwrap_err();
+        assert_eq!(error.message, "assignment to undeclared variable `totl`; declare it with `let`");
+        assert_eq!((error.span.line, error.span.column), (2, 1));
+        // Functions have their own declarations
+        assert!(strict("let x = 0\nfn f() {\n  x = 1\n}").is_err());
+        assert!(strict("f = fn() {}").is_err());
+        assert!(parse("x = 1\nlet y = x").is_ok());
+        assert!(parse("let x").is_err());
+    }
+
+    #[test]
+    fn test_duplicate_definitions() {
+        let error = parse("fn f(a, b, a) {
+}").unwrap_err();
+        assert_eq!(error.message, "duplicate parameter `a`");
+        assert_eq!((error.span.line, error.span.column), (1, 12));
+        let error = parse("struct Point { x, y, x }").unwrap_err();
+        assert_eq!(error.message, "duplicate field `x` in struct `Point`");
+        let error = parse("fn f() {
+}
+fn g() {
+  fn h() {}
+  fn h() {}
+}
+fn f() {
+}").unwrap_err();
+        assert_eq!(error.message, "function `h` is already defined in this block");
+        assert_eq!((error.span.line, error.span.column), (5, 3));
+        assert!(parse("fn f() {
+  return 1
+}
+fn f() {
+}").is_err());
+
+        // Defining a function differently depending on a condition is fine
+        assert!(parse("if x {
+  fn f() { return 1 }
+} else {
+  fn f() { return 2 }
+}
+fn g() {
+  fn f() {}
+}").is_ok());
+    }
+}
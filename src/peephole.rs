@@ -0,0 +1,323 @@
+use alloc::rc::Rc;
+use alloc::vec::Vec;
+use alloc::vec;
+
+use crate::collections::HashSet;
+use crate::compiler::{Chunk, Interner, Op};
+use crate::const_eval::{fold_binary, fold_unary};
+use crate::value::Value;
+
+/// Rewrites `chunk` and its functions into equivalent but shorter code:
+///
+/// - values pushed only to be popped again aren't pushed,
+/// - operators applied to constant numbers are computed ahead of time, and
+///   conditional jumps on constants become plain jumps or disappear,
+/// - jumps to jumps go straight to the final target,
+/// - instructions no path reaches are removed.
+///
+/// `Statement` and `Iteration` instructions are kept, so optimized code
+/// uses the same fuel and stops at the same breakpoints. Folding leaves
+/// anything alone whose result could depend on the interpreter's
+/// arithmetic or type settings, or that would fail.
+pub fn optimize(chunk: &mut Chunk) {
+    for function in &mut chunk.functions {
+        optimize(&mut Rc::make_mut(function).chunk);
+    }
+    loop {
+        let mut changed = thread_jumps(chunk);
+        changed |= fold(chunk);
+        changed |= strip_unreachable(chunk);
+        if !changed {
+            break;
+        }
+    }
+}
+
+fn jump_target(op: &mut Op) -> Option<&mut u32> {
+    match op {
+        Op::Jump(target) | Op::JumpIfFalse(target) | Op::JumpIfTrue(target) | Op::Gosub { target, .. } => Some(target),
+        _ => None,
+    }
+}
+
+/// Offsets execution can arrive at other than from the previous
+/// instruction.
+fn jump_targets(chunk: &Chunk) -> HashSet<usize> {
+    let mut targets = HashSet::default();
+    for op in &chunk.code {
+        match *op {
+            Op::Jump(target) | Op::JumpIfFalse(target) | Op::JumpIfTrue(target) => {
+                targets.insert(target as usize);
+            },
+            Op::Gosub { target, resume } => {
+                targets.insert(target as usize);
+                targets.insert(resume as usize);
+            },
+            _ => {},
+        }
+    }
+    targets
+}
+
+/// Points jumps landing on an unconditional jump at its target instead.
+fn thread_jumps(chunk: &mut Chunk) -> bool {
+    let mut changed = false;
+    for i in 0..chunk.code.len() {
+        let Some(&mut start) = jump_target(&mut chunk.code[i]) else {
+            continue;
+        };
+        let mut target = start;
+        // Bounded, since jumps may form a loop
+        for _ in 0..chunk.code.len() {
+            match chunk.code.get(target as usize) {
+                Some(Op::Jump(next)) if *next != target => target = *next,
+                _ => break,
+            }
+        }
+        if target != start {
+            *jump_target(&mut chunk.code[i]).unwrap() = target;
+            changed = true;
+        }
+    }
+    changed
+}
+
+/// The number `op` pushes, if it pushes a constant number.
+fn number(chunk: &Chunk, op: Op) -> Option<Value> {
+    match op {
+        Op::Constant(i) => match &chunk.constants[i as usize] {
+            value @ (Value::Int(_) | Value::Float(_)) => Some(value.clone()),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Whether `op` pushes a constant, and if so, whether it is truthy.
+fn constant_truthiness(chunk: &Chunk, op: Op) -> Option<bool> {
+    match op {
+        Op::Constant(i) => Some(chunk.constants[i as usize].is_truthy()),
+        Op::True => Some(true),
+        Op::False | Op::Nil => Some(false),
+        _ => None,
+    }
+}
+
+/// Replaces constant operands and the operator they feed by the result, and
+/// value pushes followed by a pop by nothing. Results are added to the
+/// constant pool like the compiler's constants, once each.
+fn fold(chunk: &mut Chunk) -> bool {
+    let targets = jump_targets(chunk);
+    let mut pool = Interner::of_constants(&chunk.constants);
+    // Folding only spans instructions nothing jumps into the middle of
+    let straight = |from: usize, to: usize| (from + 1..=to).all(|i| !targets.contains(&i));
+    let mut dead = vec![false; chunk.code.len()];
+    let mut changed = false;
+    let mut i = 0;
+    while i < chunk.code.len() {
+        let op = chunk.code[i];
+        let next = chunk.code.get(i + 1).copied();
+        let after = chunk.code.get(i + 2).copied();
+        if let (Op::Constant(_), Some(Op::Constant(_)), Some(Op::Binary(binary))) = (op, next, after) {
+            let operands = number(chunk, op).zip(next.and_then(|next| number(chunk, next)));
+            if let Some(value) = operands.and_then(|(l, r)| fold_binary(binary, l, r)).filter(|_| straight(i, i + 2)) {
+                chunk.code[i] = Op::Constant(pool.constant(&mut chunk.constants, value));
+                chunk.spans[i] = chunk.spans[i + 2];
+                dead[i + 1] = true;
+                dead[i + 2] = true;
+                changed = true;
+                i += 3;
+                continue;
+            }
+        }
+        if let (Some(operand), Some(Op::Unary(unary))) = (number(chunk, op), next) {
+            if let Some(value) = fold_unary(unary, operand).filter(|_| straight(i, i + 1)) {
+                chunk.code[i] = Op::Constant(pool.constant(&mut chunk.constants, value));
+                chunk.spans[i] = chunk.spans[i + 1];
+                dead[i + 1] = true;
+                changed = true;
+                i += 2;
+                continue;
+            }
+        }
+        let pushes_value = matches!(op, Op::Constant(_) | Op::Nil | Op::True | Op::False | Op::Closure(_));
+        if pushes_value && next == Some(Op::Pop) && straight(i, i + 1) {
+            dead[i] = true;
+            dead[i + 1] = true;
+            changed = true;
+            i += 2;
+            continue;
+        }
+        if let (Some(truthy), Some(Op::JumpIfFalse(target) | Op::JumpIfTrue(target))) = (constant_truthiness(chunk, op), next) {
+            if straight(i, i + 1) {
+                let jumps = truthy == matches!(next, Some(Op::JumpIfTrue(_)));
+                dead[i] = true;
+                if jumps {
+                    chunk.code[i + 1] = Op::Jump(target);
+                } else {
+                    dead[i + 1] = true;
+                }
+                changed = true;
+                i += 2;
+                continue;
+            }
+        }
+        if op == Op::Jump(i as u32 + 1) {
+            dead[i] = true;
+            changed = true;
+        }
+        i += 1;
+    }
+    if changed {
+        remove(chunk, &dead);
+    }
+    changed
+}
+
+/// Removes the instructions no path from the start reaches.
+fn strip_unreachable(chunk: &mut Chunk) -> bool {
+    let len = chunk.code.len();
+    let mut reached = vec![false; len];
+    // `return` continues after a `gosub`, wherever that was
+    let mut pending: Vec<usize> = chunk
+        .code
+        .iter()
+        .filter_map(|op| match op {
+            Op::Gosub { resume, .. } => Some(*resume as usize),
+            _ => None,
+        })
+        .collect();
+    pending.push(0);
+    while let Some(i) = pending.pop() {
+        if i >= len || reached[i] {
+            continue;
+        }
+        reached[i] = true;
+        match chunk.code[i] {
+            Op::Jump(target) | Op::Gosub { target, .. } => pending.push(target as usize),
+            Op::JumpIfFalse(target) | Op::JumpIfTrue(target) => {
+                pending.push(target as usize);
+                pending.push(i + 1);
+            },
+            Op::Return | Op::SubReturn | Op::UndefinedLine(_) => {},
+            _ => pending.push(i + 1),
+        }
+    }
+    let dead: Vec<bool> = reached.iter().map(|reached| !reached).collect();
+    let changed = dead.contains(&true);
+    if changed {
+        remove(chunk, &dead);
+    }
+    changed
+}
+
+/// Drops the instructions marked `dead`, moving jump targets along.
+fn remove(chunk: &mut Chunk, dead: &[bool]) {
+    // Where each old offset ends up; a removed instruction's offset goes to
+    // the next one kept
+    let mut offsets = Vec::with_capacity(dead.len() + 1);
+    let mut kept = 0u32;
+    for is_dead in dead {
+        offsets.push(kept);
+        if !is_dead {
+            kept += 1;
+        }
+    }
+    offsets.push(kept);
+    let mut i = 0;
+    chunk.code.retain(|_| {
+        i += 1;
+        !dead[i - 1]
+    });
+    let mut i = 0;
+    chunk.spans.retain(|_| {
+        i += 1;
+        !dead[i - 1]
+    });
+    for op in &mut chunk.code {
+        if let Op::Gosub { resume, .. } = op {
+            *resume = offsets[*resume as usize];
+        }
+        if let Some(target) = jump_target(op) {
+            *target = offsets[*target as usize];
+        }
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod test {
+    use crate::compiler::{compile, compile_with, Backend, CompileOptions};
+    use crate::interpreter::{Interpreter, SharedBuffer};
+    use crate::parser::{parse, parse_with_dialect, Dialect};
+    use crate::peephole::optimize;
+    use crate::value::Value;
+
+    #[test]
+    fn test_optimize() {
+        let mut chunk = compile(&parse("if 0 {\n  print 1\n}\nx = 2 + 3 * 4 - -1\n7\nx").unwrap());
+        optimize(&mut chunk);
+        assert_eq!(chunk.to_string(), "\
+0000    1 statement
+0001    | clear-result
+0002    4 statement
+0003    | constant 15
+0004    | store x
+0005    | clear-result
+0006    5 statement
+0007    | constant 7
+0008    | set-result
+0009    6 statement
+0010    | load x
+0011    | set-result
+");
+        assert_eq!(chunk.constants.iter().filter(|value| **value == Value::Int(15)).count(), 1);
+
+        let mut chunk = compile(&parse("x = 15\ny = 5 * 3\nz = -15\nw = -(5 * 3)").unwrap());
+        optimize(&mut chunk);
+        assert_eq!(chunk.constants, vec![Value::Int(15), Value::Int(5), Value::Int(3), Value::Int(-15)]);
+
+        let mut chunk = compile(&parse("fn f(a) {\n  while 1 {\n    if a { break }\n    1\n  }\n  return 7 / 2\n}").unwrap());
+        optimize(&mut chunk);
+        assert_eq!(chunk.functions[0].chunk.to_string(), "\
+0000    2 statement
+0001    3 statement
+0002    | load a
+0003    | jump-if-false 0006
+0004    | statement
+0005    | jump 0009
+0006    4 statement
+0007    2 iteration
+0008    | jump 0001
+0009    6 statement
+0010    | constant 7
+0011    | constant 2
+0012    | binary Div
+0013    | return
+");
+    }
+
+    #[test]
+    fn test_behavior_kept() {
+        let programs = [
+            (Dialect::Modern, "x = 0\nwhile x < 10 && !(x == 7) {\n  x = x + 1 * 2.5\n  if 1 || y { continue }\n}\n[x, 1 + 2 > 2, 9223372036854775807 + 1 > 0]"),
+            (Dialect::Modern, "fn f() {\n  return 1\n  print 2\n}\nf() + f()"),
+            (Dialect::Classic, "10 n = 0\n20 gosub 100\n30 if n < 3 { goto 20 }\n40 print n\n50 goto 200\n100 n = n + 1\n110 return"),
+        ];
+        for (dialect, source) in programs {
+            let program = parse_with_dialect(source, dialect).unwrap();
+            let plain = compile_with(&program, &CompileOptions { backend: Backend::Stack, optimize: false, tail_calls: false, inline_size: 0, optimize_loops: false });
+            let optimized = compile_with(&program, &CompileOptions { backend: Backend::Stack, optimize: true, tail_calls: false, inline_size: 0, optimize_loops: false });
+            assert_ne!(plain, optimized);
+            let run = |interpreter: &mut Interpreter| {
+                interpreter.set_fuel(Some(1000));
+                let result = optimized.run(interpreter);
+                (result, interpreter.fuel())
+            };
+            let mut expected = Interpreter::with_output(Box::new(SharedBuffer::new()));
+            expected.set_fuel(Some(1000));
+            let expected_result = plain.run(&mut expected);
+            let mut interpreter = Interpreter::with_output(Box::new(SharedBuffer::new()));
+            assert_eq!(run(&mut interpreter), (expected_result, expected.fuel()), "{:?}", source);
+        }
+    }
+}
@@ -0,0 +1,254 @@
+#[cfg(feature = "std")]
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt;
+use core::time::Duration;
+#[cfg(feature = "std")]
+use std::time::Instant;
+
+use crate::collections::HashMap;
+
+/// How many lines the reports [`Profile`] and [`LineProfile`] display list.
+const REPORT_LINES: usize = 10;
+
+/// Execution counts collected by the bytecode VMs while
+/// [profiling](crate::interpreter::Interpreter::set_profiling) is on: how
+/// often each kind of instruction ran, and how many instructions ran for
+/// each source line. Code the tree-walker runs isn't counted.
+///
+/// Displaying a profile gives a report of the hottest lines and the
+/// instruction counts.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Profile {
+    ops: HashMap<&'static str, u64>,
+    lines: HashMap<usize, u64>,
+}
+
+impl Profile {
+    pub(crate) fn record(&mut self, op: &'static str, line: usize) {
+        *self.ops.entry(op).or_insert(0) += 1;
+        *self.lines.entry(line).or_insert(0) += 1;
+    }
+
+    /// How many instructions ran in all.
+    pub fn instructions(&self) -> u64 {
+        self.ops.values().sum()
+    }
+
+    /// How often each kind of instruction ran, most frequent first. Kinds
+    /// are named as in disassembly listings.
+    pub fn ops(&self) -> Vec<(&'static str, u64)> {
+        let mut ops: Vec<_> = self.ops.iter().map(|(op, count)| (*op, *count)).collect();
+        ops.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+        ops
+    }
+
+    /// How many instructions ran for `line`.
+    pub fn line(&self, line: usize) -> u64 {
+        self.lines.get(&line).copied().unwrap_or(0)
+    }
+
+    /// The `n` lines the most instructions ran for, with their counts,
+    /// hottest first.
+    pub fn hot_lines(&self, n: usize) -> Vec<(usize, u64)> {
+        let mut lines: Vec<_> = self.lines.iter().map(|(line, count)| (*line, *count)).collect();
+        lines.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        lines.truncate(n);
+        lines
+    }
+}
+
+impl fmt::Display for Profile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let total = self.instructions();
+        let percent = |count: u64| 100.0 * count as f64 / total.max(1) as f64;
+        writeln!(f, "{} instructions", total)?;
+        writeln!(f, "\nhot lines:")?;
+        for (line, count) in self.hot_lines(REPORT_LINES) {
+            writeln!(f, "  line {:<6} {:>12} {:5.1}%", line, count, percent(count))?;
+        }
+        writeln!(f, "\ninstructions:")?;
+        for (op, count) in self.ops() {
+            writeln!(f, "  {:<15} {:>12} {:5.1}%", op, count, percent(count))?;
+        }
+        Ok(())
+    }
+}
+
+/// Where the tree-walker spent the time of a run, line by line: each
+/// statement is charged the time from it starting to the next one starting.
+/// That is a line's own time, not that of the functions it calls, whose
+/// lines count it.
+///
+/// Displaying a line profile gives a table of the hottest lines.
+#[cfg(feature = "std")]
+#[derive(Debug, Clone, Default)]
+pub struct LineProfile {
+    files: Vec<String>,
+    /// By the index of the file in `files` and the line.
+    lines: HashMap<(usize, usize), LineTime>,
+    /// The line of the statement running, and when it started.
+    running: Option<((usize, usize), Instant)>,
+}
+
+/// How often the statements on a line ran, and how long they took.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct LineTime {
+    pub hits: u64,
+    pub time: Duration,
+}
+
+#[cfg(feature = "std")]
+impl LineProfile {
+    pub fn new() -> LineProfile {
+        LineProfile::default()
+    }
+
+    /// Starts a statement on `line` of `path`, ending the one running.
+    pub fn enter(&mut self, path: &str, line: usize) {
+        self.enter_at(path, line, Instant::now());
+    }
+
+    fn enter_at(&mut self, path: &str, line: usize, now: Instant) {
+        self.finish_at(now);
+        let file = match self.files.iter().position(|file| file == path) {
+            Some(file) => file,
+            None => {
+                self.files.push(path.to_string());
+                self.files.len() - 1
+            },
+        };
+        self.lines.entry((file, line)).or_default().hits += 1;
+        self.running = Some(((file, line), now));
+    }
+
+    /// Ends the statement running, at the end of a run.
+    pub fn finish(&mut self) {
+        self.finish_at(Instant::now());
+    }
+
+    fn finish_at(&mut self, now: Instant) {
+        if let Some((line, started)) = self.running.take() {
+            self.lines.entry(line).or_default().time += now.duration_since(started);
+        }
+    }
+
+    /// The time of all lines.
+    pub fn total(&self) -> Duration {
+        self.lines.values().map(|line| line.time).sum()
+    }
+
+    /// The `n` lines that took the longest, as their path and line number,
+    /// slowest first.
+    pub fn hot_lines(&self, n: usize) -> Vec<(&str, usize, LineTime)> {
+        let mut lines: Vec<_> = self.lines.iter().map(|(&(file, line), &time)| (self.files[file].as_str(), line, time)).collect();
+        lines.sort_by(|a, b| b.2.time.cmp(&a.2.time).then(b.2.hits.cmp(&a.2.hits)).then(a.0.cmp(b.0)).then(a.1.cmp(&b.1)));
+        lines.truncate(n);
+        lines
+    }
+}
+
+#[cfg(feature = "std")]
+impl fmt::Display for LineProfile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let total = self.total();
+        let hits: u64 = self.lines.values().map(|line| line.hits).sum();
+        let percent = |time: Duration| 100.0 * time.as_secs_f64() / total.as_secs_f64().max(f64::MIN_POSITIVE);
+        writeln!(f, "{:.3} ms in {} statements\n", total.as_secs_f64() * 1000.0, hits)?;
+        writeln!(f, "   time (ms)       %         hits  line")?;
+        for (path, line, time) in self.hot_lines(REPORT_LINES) {
+            let seconds = time.time.as_secs_f64();
+            writeln!(f, "{:>12.3} {:6.1}% {:>12}  {}:{}", seconds * 1000.0, percent(time.time), time.hits, path, line)?;
+        }
+        if self.lines.len() > REPORT_LINES {
+            writeln!(f, "and {} more lines", self.lines.len() - REPORT_LINES)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod test {
+    use std::time::{Duration, Instant};
+
+    use crate::compiler::{compile_with, Backend, CompileOptions};
+    use crate::interpreter::{Interpreter, SharedBuffer};
+    use crate::parser::parse;
+    use crate::profile::{LineProfile, LineTime, Profile};
+
+    #[test]
+    fn test_report() {
+        let mut profile = Profile::default();
+        for (op, line) in [("load", 2), ("load", 2), ("call", 3), ("statement", 1)] {
+            profile.record(op, line);
+        }
+        assert_eq!(profile.instructions(), 4);
+        assert_eq!(profile.hot_lines(2), vec![(2, 2), (1, 1)]);
+        assert_eq!(profile.line(3), 1);
+        assert_eq!(profile.line(4), 0);
+        assert_eq!(profile.to_string(), "\
+4 instructions
+
+hot lines:
+  line 2                 2  50.0%
+  line 1                 1  25.0%
+  line 3                 1  25.0%
+
+instructions:
+  load                       2  50.0%
+  call                       1  25.0%
+  statement                  1  25.0%
+");
+    }
+
+    #[test]
+    fn test_profiling() {
+        let program = parse("i = 0\nwhile i < 10 {\n  i = i + 1\n}\nprint i").unwrap();
+        for backend in [Backend::Stack, Backend::Register] {
+            let compiled = compile_with(&program, &CompileOptions { backend, ..CompileOptions::default() });
+            let mut interpreter = Interpreter::with_output(Box::new(SharedBuffer::new()));
+            compiled.run(&mut interpreter).unwrap();
+            assert_eq!(interpreter.profile(), None);
+
+            interpreter.set_profiling(true);
+            compiled.run(&mut interpreter).unwrap();
+            let profile = interpreter.profile().unwrap().clone();
+            assert_eq!(profile.hot_lines(1)[0].0, 2, "{:?}", backend);
+            assert_eq!(profile.ops().iter().find(|(op, _)| *op == "iteration"), Some(&("iteration", 10)));
+            assert_eq!(profile.line(5), 4, "{:?}", backend);
+
+            // Counts add up across runs until profiling is switched on again
+            compiled.run(&mut interpreter).unwrap();
+            assert_eq!(interpreter.profile().unwrap().instructions(), 2 * profile.instructions());
+            interpreter.set_profiling(true);
+            assert_eq!(interpreter.profile(), Some(&Profile::default()));
+            interpreter.set_profiling(false);
+            assert_eq!(interpreter.profile(), None);
+        }
+    }
+
+    #[test]
+    fn test_line_profile() {
+        let start = Instant::now();
+        let at = |ms: u64| start + Duration::from_millis(ms);
+        let mut profile = LineProfile::new();
+        for (path, line, ms) in [("main.tb", 1, 0), ("main.tb", 2, 1), ("util.tb", 2, 2), ("main.tb", 2, 6), ("main.tb", 3, 8)] {
+            profile.enter_at(path, line, at(ms));
+        }
+        profile.finish_at(at(10));
+        profile.finish_at(at(20));
+        assert_eq!(profile.total(), Duration::from_millis(10));
+        let hot: Vec<_> = profile.hot_lines(2).into_iter().map(|(path, line, time)| (path, line, time.hits, time.time.as_millis())).collect();
+        assert_eq!(hot, vec![("util.tb", 2, 1, 4), ("main.tb", 2, 2, 3)]);
+        assert_eq!(profile.to_string(), "\
+10.000 ms in 5 statements
+
+   time (ms)       %         hits  line
+       4.000   40.0%            1  util.tb:2
+       3.000   30.0%            2  main.tb:2
+       2.000   20.0%            1  main.tb:3
+       1.000   10.0%            1  main.tb:1
+");
+        assert_eq!(profile.hot_lines(1)[0].2, LineTime { hits: 1, time: Duration::from_millis(4) });
+    }
+}
@@ -0,0 +1,86 @@
+#[cfg(feature = "std")]
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Small deterministic PRNG (SplitMix64) behind `rnd` and `random`. Not
+/// suitable for anything security related.
+#[derive(Debug, Clone)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Rng {
+        Rng { state: seed }
+    }
+
+    /// Seeds from the system clock.
+    #[cfg(feature = "std")]
+    pub fn from_time() -> Rng {
+        let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_nanos() as u64).unwrap_or(0);
+        Rng::new(nanos)
+    }
+
+    /// What the generator continues from; `Rng::new` with it picks up
+    /// the same sequence.
+    #[cfg(feature = "std")]
+    pub(crate) fn state(&self) -> u64 {
+        self.state
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// Uniform float in `[0, 1)`.
+    pub fn next_float(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// Uniform int in `min..=max`. `min` must not exceed `max`.
+    pub fn range(&mut self, min: i64, max: i64) -> i64 {
+        let span = max.wrapping_sub(min) as u64;
+        if span == u64::MAX {
+            return self.next_u64() as i64;
+        }
+        // Rejection sampling keeps the distribution uniform
+        let bound = span + 1;
+        let zone = u64::MAX - (u64::MAX % bound) - 1;
+        loop {
+            let n = self.next_u64();
+            if n <= zone {
+                return min.wrapping_add((n % bound) as i64);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::random::Rng;
+
+    #[test]
+    fn test_seeded_sequence_repeats() {
+        let mut a = Rng::new(42);
+        let mut b = Rng::new(42);
+        for _ in 0..10 {
+            assert_eq!(a.next_u64(), b.next_u64());
+        }
+        assert_ne!(Rng::new(1).next_u64(), Rng::new(2).next_u64());
+    }
+
+    #[test]
+    fn test_range_bounds() {
+        let mut rng = Rng::new(7);
+        for _ in 0..1000 {
+            let n = rng.range(-3, 3);
+            assert!((-3..=3).contains(&n));
+            let f = rng.next_float();
+            assert!((0.0..1.0).contains(&f));
+        }
+        assert_eq!(rng.range(5, 5), 5);
+    }
+}
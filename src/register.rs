@@ -0,0 +1,952 @@
+use alloc::rc::Rc;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use alloc::{format, vec};
+use core::fmt;
+
+use crate::ast::{BinaryOp, Expr, ExprKind, FunctionDecl, Program, Stmt, StmtKind, UnaryOp};
+use crate::collections::{HashMap, IndexMap};
+use crate::interpreter::{field_value, int_binary, map_key, store_field, unary_op, Interpreter, RuntimeError};
+use crate::compiler::Interner;
+use crate::lexer::Span;
+use crate::value::{Bytecode, Function, Value};
+
+/// A register of the running call.
+pub type Reg = u16;
+
+/// One instruction of the register backend. Instead of an operand stack,
+/// each call has a fixed number of registers, and instructions name the
+/// registers they read and write. Other `u32` operands index the pools of
+/// the [`RegisterChunk`] holding the instruction, or are code offsets.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Instr {
+    Constant { dst: Reg, constant: u32 },
+    Nil { dst: Reg },
+    Bool { dst: Reg, value: bool },
+    Load { dst: Reg, name: u32 },
+    /// Like `Load`, for a callee: a missing name is an undefined function.
+    LoadFunction { dst: Reg, name: u32 },
+    Store { name: u32, src: Reg },
+    /// `let`: makes `names[i]` local to the running call.
+    Declare(u32),
+    /// Makes `names[i]` refer to the global for the rest of the call.
+    Global(u32),
+    Closure { dst: Reg, function: u32 },
+    /// Collects the `count` registers from `start` on into a new array.
+    Array { dst: Reg, start: Reg, count: u32 },
+    /// Collects `count` key-value pairs from `start` on into a new map.
+    Map { dst: Reg, start: Reg, count: u32 },
+    /// Fails unless `key` holds a valid map key.
+    CheckKey { key: Reg },
+    /// `target_span` is the target's location in `operand_spans`.
+    Index { dst: Reg, target: Reg, index: Reg, target_span: u32 },
+    SetIndex { target: Reg, index: Reg, value: Reg, target_span: u32 },
+    Field { dst: Reg, target: Reg, name: u32, target_span: u32 },
+    SetField { target: Reg, name: u32, value: Reg },
+    Unary { op: UnaryOp, dst: Reg, src: Reg },
+    /// Any operator but `&&` and `||`, which compile to jumps.
+    Binary { op: BinaryOp, dst: Reg, left: Reg, right: Reg },
+    /// A binary operator whose right operand is `constants[constant]`.
+    BinaryConstant { op: BinaryOp, dst: Reg, left: Reg, constant: u32 },
+    Truthy { dst: Reg, src: Reg },
+    Jump(u32),
+    JumpIfFalse { cond: Reg, target: u32 },
+    JumpIfTrue { cond: Reg, target: u32 },
+    /// Calls the callee in `start` with the `count` arguments after it.
+    Call { dst: Reg, start: Reg, count: u32, callee_span: u32 },
+    /// A `Call` followed by a `Return { src: dst }`. Calling a register
+    /// function from within a call replaces that call, reusing its frame,
+    /// and skips the `Return`.
+    TailCall { dst: Reg, start: Reg, count: u32, callee_span: u32 },
+    /// Leaves the running call, or the program at the top level.
+    Return { src: Reg },
+    Print { start: Reg, count: u32 },
+    Import(u32),
+    /// Starts a statement: counts a step and runs debug hooks.
+    Statement,
+    /// Ends a loop iteration, counting a step.
+    Iteration,
+    /// Makes the program return `src` if it ends after this statement.
+    SetResult { src: Reg },
+    /// Makes the program return `nil` if it ends after this statement.
+    ClearResult,
+    /// Classic dialect: jumps to `target`, continuing at `resume` on `return`.
+    Gosub { target: u32, resume: u32 },
+    SubReturn,
+    /// Classic dialect: fails with a jump to the line `constants[i]`.
+    UndefinedLine(u32),
+}
+
+impl Instr {
+    /// The instruction's name, as profiles count it.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Instr::Constant { .. } => "constant",
+            Instr::Nil { .. } => "nil",
+            Instr::Bool { .. } => "bool",
+            Instr::Load { .. } => "load",
+            Instr::LoadFunction { .. } => "load-function",
+            Instr::Store { .. } => "store",
+            Instr::Declare(_) => "declare",
+            Instr::Global(_) => "global",
+            Instr::Closure { .. } => "closure",
+            Instr::Array { .. } => "array",
+            Instr::Map { .. } => "map",
+            Instr::CheckKey { .. } => "check-key",
+            Instr::Index { .. } => "index",
+            Instr::SetIndex { .. } => "set-index",
+            Instr::Field { .. } => "field",
+            Instr::SetField { .. } => "set-field",
+            Instr::Unary { .. } => "unary",
+            Instr::Binary { .. } => "binary",
+            Instr::BinaryConstant { .. } => "binary-constant",
+            Instr::Truthy { .. } => "truthy",
+            Instr::Jump(_) => "jump",
+            Instr::JumpIfFalse { .. } => "jump-if-false",
+            Instr::JumpIfTrue { .. } => "jump-if-true",
+            Instr::Call { .. } => "call",
+            Instr::TailCall { .. } => "tail-call",
+            Instr::Return { .. } => "return",
+            Instr::Print { .. } => "print",
+            Instr::Import(_) => "import",
+            Instr::Statement => "statement",
+            Instr::Iteration => "iteration",
+            Instr::SetResult { .. } => "set-result",
+            Instr::ClearResult => "clear-result",
+            Instr::Gosub { .. } => "gosub",
+            Instr::SubReturn => "sub-return",
+            Instr::UndefinedLine(_) => "undefined-line",
+        }
+    }
+}
+
+/// Register code with the pools its instructions refer to.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct RegisterChunk {
+    pub code: Vec<Instr>,
+    /// Where each instruction comes from, for errors and debug hooks.
+    pub spans: Vec<Span>,
+    pub constants: Vec<Value>,
+    pub names: Vec<String>,
+    pub operand_spans: Vec<Span>,
+    pub functions: Vec<Rc<RegisterFunction>>,
+    /// How many registers running the code takes.
+    pub registers: Reg,
+}
+
+/// A function compiled for the register backend.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RegisterFunction {
+    pub decl: Rc<FunctionDecl>,
+    pub chunk: RegisterChunk,
+}
+
+/// Compiles `program` for the register backend. Like
+/// [`compile`](crate::compiler::compile), variables are still looked up by
+/// name; registers only hold the temporaries that the stack backend pushes
+/// and pops, and arithmetic on a literal uses it directly.
+pub fn compile(program: &Program) -> RegisterChunk {
+    compile_program(program, false)
+}
+
+/// [`compile`], with tail calls if `tail_calls` is set.
+pub(crate) fn compile_program(program: &Program, tail_calls: bool) -> RegisterChunk {
+    let mut generator = Generator::new(Some(&program.lines));
+    generator.tail_calls = tail_calls;
+    for statement in &program.statements {
+        generator.starts.push(generator.chunk.code.len());
+        match &statement.kind {
+            StmtKind::Expr(expr) => {
+                generator.emit(Instr::Statement, statement.span);
+                let src = generator.alloc(1);
+                generator.expression(expr, src);
+                generator.emit(Instr::SetResult { src }, statement.span);
+                generator.next = src;
+            },
+            _ => {
+                generator.statement(statement);
+                generator.emit(Instr::ClearResult, statement.span);
+            },
+        }
+    }
+    generator.starts.push(generator.chunk.code.len());
+    generator.finish()
+}
+
+/// Runs `chunk` the way [`vm::run`](crate::vm::run) runs stack bytecode.
+pub fn run(interpreter: &mut Interpreter, chunk: &RegisterChunk) -> Result<Value, RuntimeError> {
+    interpreter.reset();
+    Machine::new(interpreter, chunk).execute()
+}
+
+/// Calls a register function for code outside this VM.
+pub(crate) fn call(
+    interpreter: &mut Interpreter,
+    function: &Function,
+    code: &RegisterFunction,
+    args: Vec<Value>,
+    span: Span,
+) -> Result<Value, RuntimeError> {
+    interpreter.push_frame(function, args, span)?;
+    let result = Machine::new(interpreter, &code.chunk).execute();
+    interpreter.pop_frame();
+    result.map_err(|error| error.traced(&function.decl.name, span))
+}
+
+fn index(len: usize) -> u32 {
+    u32::try_from(len).expect("chunk is too large")
+}
+
+#[derive(Default)]
+struct Loop {
+    breaks: Vec<usize>,
+    continues: Vec<usize>,
+}
+
+struct Generator<'a> {
+    chunk: RegisterChunk,
+    loops: Vec<Loop>,
+    /// Classic top level: maps line numbers to statement indexes.
+    lines: Option<&'a HashMap<i64, usize>>,
+    /// Code offset of each top-level statement, and of the end.
+    starts: Vec<usize>,
+    /// Jumps to patch with the start of a top-level statement.
+    line_jumps: Vec<(usize, usize)>,
+    /// The first free register. Registers are freed in the reverse order
+    /// they are allocated.
+    next: Reg,
+    pool: Interner,
+    /// Whether `return f(...)` compiles to `TailCall`.
+    tail_calls: bool,
+}
+
+impl<'a> Generator<'a> {
+    fn new(lines: Option<&'a HashMap<i64, usize>>) -> Generator<'a> {
+        Generator {
+            chunk: RegisterChunk::default(),
+            loops: Vec::new(),
+            lines,
+            starts: Vec::new(),
+            line_jumps: Vec::new(),
+            next: 0,
+            pool: Interner::default(),
+            tail_calls: false,
+        }
+    }
+
+    fn finish(mut self) -> RegisterChunk {
+        for (at, statement) in core::mem::take(&mut self.line_jumps) {
+            let start = index(self.starts[statement]);
+            match &mut self.chunk.code[at] {
+                Instr::Jump(target) => *target = start,
+                Instr::Gosub { target, resume } => {
+                    *target = start;
+                    *resume = index(self.starts[*resume as usize]);
+                },
+                instr => unreachable!("{:?} is not a line jump", instr),
+            }
+        }
+        self.chunk
+    }
+
+    /// Allocates `n` consecutive registers, returning the first.
+    fn alloc(&mut self, n: usize) -> Reg {
+        let first = self.next;
+        self.next = u16::try_from(n).ok().and_then(|n| first.checked_add(n)).expect("function needs too many registers");
+        self.chunk.registers = self.chunk.registers.max(self.next);
+        first
+    }
+
+    fn emit(&mut self, instr: Instr, span: Span) -> usize {
+        self.chunk.code.push(instr);
+        self.chunk.spans.push(span);
+        self.chunk.code.len() - 1
+    }
+
+    fn patch(&mut self, at: usize) {
+        let here = index(self.chunk.code.len());
+        match &mut self.chunk.code[at] {
+            Instr::Jump(target) | Instr::JumpIfFalse { target, .. } | Instr::JumpIfTrue { target, .. } => *target = here,
+            instr => unreachable!("{:?} is not a jump", instr),
+        }
+    }
+
+    fn constant(&mut self, value: Value) -> u32 {
+        self.pool.constant(&mut self.chunk.constants, value)
+    }
+
+    fn name(&mut self, name: &str) -> u32 {
+        self.pool.name(&mut self.chunk.names, name)
+    }
+
+    fn operand_span(&mut self, span: Span) -> u32 {
+        self.chunk.operand_spans.push(span);
+        index(self.chunk.operand_spans.len() - 1)
+    }
+
+    fn function(&mut self, decl: &Rc<FunctionDecl>) -> u32 {
+        let mut generator = Generator::new(None);
+        generator.tail_calls = self.tail_calls;
+        generator.block(&decl.body);
+        let src = generator.alloc(1);
+        generator.emit(Instr::Nil { dst: src }, decl.span);
+        generator.emit(Instr::Return { src }, decl.span);
+        let function = RegisterFunction { decl: decl.clone(), chunk: generator.finish() };
+        self.chunk.functions.push(Rc::new(function));
+        index(self.chunk.functions.len() - 1)
+    }
+
+    fn block(&mut self, statements: &[Stmt]) {
+        for statement in statements {
+            self.statement(statement);
+        }
+    }
+
+    /// Evaluates `expr` into a fresh register, passing it to `then` before
+    /// freeing it.
+    fn with_value(&mut self, expr: &Expr, then: impl FnOnce(&mut Self, Reg)) {
+        let src = self.alloc(1);
+        self.expression(expr, src);
+        then(self, src);
+        self.next = src;
+    }
+
+    fn statement(&mut self, statement: &Stmt) {
+        let span = statement.span;
+        self.emit(Instr::Statement, span);
+        match &statement.kind {
+            StmtKind::Assign { name, value, declaration, .. } => self.with_value(value, |this, src| {
+                let name = this.name(name);
+                if *declaration {
+                    this.emit(Instr::Declare(name), span);
+                }
+                this.emit(Instr::Store { name, src }, span);
+            }),
+            StmtKind::SetIndex { target, index, value } => {
+                let start = self.alloc(3);
+                self.expression(target, start);
+                self.expression(index, start + 1);
+                self.expression(value, start + 2);
+                let target_span = self.operand_span(target.span);
+                self.emit(Instr::SetIndex { target: start, index: start + 1, value: start + 2, target_span }, index.span);
+                self.next = start;
+            },
+            StmtKind::SetField { target, field, value } => {
+                let start = self.alloc(2);
+                self.expression(target, start);
+                self.expression(value, start + 1);
+                let name = self.name(field);
+                self.emit(Instr::SetField { target: start, name, value: start + 1 }, target.span);
+                self.next = start;
+            },
+            StmtKind::If { condition, then_branch, else_branch } => {
+                let mut to_else = 0;
+                self.with_value(condition, |this, cond| to_else = this.emit(Instr::JumpIfFalse { cond, target: 0 }, span));
+                self.block(then_branch);
+                match else_branch {
+                    Some(else_branch) => {
+                        let to_end = self.emit(Instr::Jump(0), span);
+                        self.patch(to_else);
+                        self.block(else_branch);
+                        self.patch(to_end);
+                    },
+                    None => self.patch(to_else),
+                }
+            },
+            StmtKind::While { condition, body } => {
+                let start = index(self.chunk.code.len());
+                let mut exit = 0;
+                self.with_value(condition, |this, cond| exit = this.emit(Instr::JumpIfFalse { cond, target: 0 }, span));
+                self.loops.push(Loop::default());
+                self.block(body);
+                let finished = self.loops.pop().unwrap();
+                for jump in finished.continues {
+                    self.patch(jump);
+                }
+                self.emit(Instr::Iteration, span);
+                self.emit(Instr::Jump(start), span);
+                self.patch(exit);
+                for jump in finished.breaks {
+                    self.patch(jump);
+                }
+            },
+            StmtKind::Break => {
+                let jump = self.emit(Instr::Jump(0), span);
+                self.loops.last_mut().expect("`break` outside of a loop").breaks.push(jump);
+            },
+            StmtKind::Continue => {
+                let jump = self.emit(Instr::Jump(0), span);
+                self.loops.last_mut().expect("`continue` outside of a loop").continues.push(jump);
+            },
+            StmtKind::Print(args) => {
+                let start = self.alloc(args.len());
+                for (i, arg) in args.iter().enumerate() {
+                    self.expression(arg, start + i as Reg);
+                }
+                self.emit(Instr::Print { start, count: index(args.len()) }, span);
+                self.next = start;
+            },
+            StmtKind::Function(decl) => {
+                let function = self.function(decl);
+                let src = self.alloc(1);
+                self.emit(Instr::Closure { dst: src, function }, span);
+                let name = self.name(&decl.name);
+                self.emit(Instr::Store { name, src }, span);
+                self.next = src;
+            },
+            StmtKind::Struct(decl) => {
+                let constant = self.constant(Value::StructType(decl.clone()));
+                let src = self.alloc(1);
+                self.emit(Instr::Constant { dst: src, constant }, span);
+                let name = self.name(&decl.name);
+                self.emit(Instr::Store { name, src }, span);
+                self.next = src;
+            },
+            StmtKind::Return(value) => {
+                let src = self.alloc(1);
+                match value {
+                    Some(Expr { kind: ExprKind::Call { callee, args }, span }) if self.tail_calls => {
+                        self.call(callee, args, src, *span, true)
+                    },
+                    Some(value) => self.expression(value, src),
+                    None => {
+                        self.emit(Instr::Nil { dst: src }, span);
+                    },
+                }
+                self.emit(Instr::Return { src }, span);
+                self.next = src;
+            },
+            StmtKind::Global(names) => {
+                for name in names {
+                    let name = self.name(name);
+                    self.emit(Instr::Global(name), span);
+                }
+            },
+            StmtKind::Import(module) => {
+                let module = self.name(module);
+                self.emit(Instr::Import(module), span);
+            },
+            StmtKind::Goto(line) => self.line_jump(Instr::Jump(0), *line, span),
+            StmtKind::Gosub(line) => {
+                let resume = index(self.starts.len());
+                self.line_jump(Instr::Gosub { target: 0, resume }, *line, span);
+            },
+            StmtKind::SubReturn => {
+                self.emit(Instr::SubReturn, span);
+            },
+            StmtKind::Expr(expr) => self.with_value(expr, |_, _| {}),
+        }
+    }
+
+    fn line_jump(&mut self, instr: Instr, line: i64, span: Span) {
+        match self.lines.and_then(|lines| lines.get(&line)) {
+            Some(&statement) => {
+                let at = self.emit(instr, span);
+                self.line_jumps.push((at, statement));
+            },
+            None => {
+                let line = self.constant(Value::Int(line));
+                self.emit(Instr::UndefinedLine(line), span);
+            },
+        }
+    }
+
+    /// The pool entry of a literal.
+    fn literal(&mut self, expr: &Expr) -> Option<u32> {
+        let value = match &expr.kind {
+            ExprKind::Number(n) => Value::Int(*n),
+            ExprKind::Float(n) => Value::Float(*n),
+            ExprKind::Str(s) => Value::from(s.as_str()),
+            _ => return None,
+        };
+        Some(self.constant(value))
+    }
+
+    /// Compiles `expr` to leave its value in `dst`. Registers from `dst` on
+    /// are free to use as temporaries.
+    fn expression(&mut self, expr: &Expr, dst: Reg) {
+        let span = expr.span;
+        match &expr.kind {
+            ExprKind::Number(_) | ExprKind::Float(_) | ExprKind::Str(_) => {
+                let constant = self.literal(expr).unwrap();
+                self.emit(Instr::Constant { dst, constant }, span);
+            },
+            ExprKind::Variable(name) => {
+                let name = self.name(name);
+                self.emit(Instr::Load { dst, name }, span);
+            },
+            ExprKind::Function(decl) => {
+                let function = self.function(decl);
+                self.emit(Instr::Closure { dst, function }, span);
+            },
+            ExprKind::Array(items) => {
+                let start = self.alloc(items.len());
+                for (i, item) in items.iter().enumerate() {
+                    self.expression(item, start + i as Reg);
+                }
+                self.emit(Instr::Array { dst, start, count: index(items.len()) }, span);
+                self.next = start;
+            },
+            ExprKind::Map(entries) => {
+                let start = self.alloc(2 * entries.len());
+                for (i, (key, value)) in entries.iter().enumerate() {
+                    let key_reg = start + 2 * i as Reg;
+                    self.expression(key, key_reg);
+                    self.emit(Instr::CheckKey { key: key_reg }, key.span);
+                    self.expression(value, key_reg + 1);
+                }
+                self.emit(Instr::Map { dst, start, count: index(entries.len()) }, span);
+                self.next = start;
+            },
+            ExprKind::Index { target, index } => {
+                self.expression(target, dst);
+                self.with_value(index, |this, index_reg| {
+                    let target_span = this.operand_span(target.span);
+                    this.emit(Instr::Index { dst, target: dst, index: index_reg, target_span }, index.span);
+                });
+            },
+            ExprKind::Field { target, field } => {
+                self.expression(target, dst);
+                let name = self.name(field);
+                let target_span = self.operand_span(target.span);
+                self.emit(Instr::Field { dst, target: dst, name, target_span }, span);
+            },
+            ExprKind::Unary { op, operand } => {
+                self.expression(operand, dst);
+                self.emit(Instr::Unary { op: *op, dst, src: dst }, span);
+            },
+            ExprKind::Binary { op: op @ (BinaryOp::And | BinaryOp::Or), left, right } => {
+                self.expression(left, dst);
+                let decided = match op {
+                    BinaryOp::And => self.emit(Instr::JumpIfFalse { cond: dst, target: 0 }, span),
+                    _ => self.emit(Instr::JumpIfTrue { cond: dst, target: 0 }, span),
+                };
+                self.expression(right, dst);
+                self.emit(Instr::Truthy { dst, src: dst }, span);
+                let to_end = self.emit(Instr::Jump(0), span);
+                self.patch(decided);
+                self.emit(Instr::Bool { dst, value: *op == BinaryOp::Or }, span);
+                self.patch(to_end);
+            },
+            ExprKind::Binary { op, left, right } => {
+                self.expression(left, dst);
+                match self.literal(right) {
+                    Some(constant) => {
+                        self.emit(Instr::BinaryConstant { op: *op, dst, left: dst, constant }, span);
+                    },
+                    None => self.with_value(right, |this, right| {
+                        this.emit(Instr::Binary { op: *op, dst, left: dst, right }, span);
+                    }),
+                }
+            },
+            ExprKind::Call { callee, args } => self.call(callee, args, dst, span, false),
+        }
+    }
+
+    fn call(&mut self, callee: &Expr, args: &[Expr], dst: Reg, span: Span, tail: bool) {
+        let start = self.alloc(1 + args.len());
+        match &callee.kind {
+            ExprKind::Variable(name) => {
+                let name = self.name(name);
+                self.emit(Instr::LoadFunction { dst: start, name }, callee.span);
+            },
+            _ => self.expression(callee, start),
+        }
+        for (i, arg) in args.iter().enumerate() {
+            self.expression(arg, start + 1 + i as Reg);
+        }
+        let (count, callee_span) = (index(args.len()), self.operand_span(callee.span));
+        let call = if tail {
+            Instr::TailCall { dst, start, count, callee_span }
+        } else {
+            Instr::Call { dst, start, count, callee_span }
+        };
+        self.emit(call, span);
+        self.next = start;
+    }
+}
+
+/// A call of a register function the VM is running.
+struct CallFrame {
+    function: Rc<RegisterFunction>,
+    /// The caller's code, `None` for the chunk the VM started with.
+    caller: Option<Rc<RegisterFunction>>,
+    return_to: usize,
+    /// Where the caller's registers start.
+    base: usize,
+    /// The caller's register receiving the return value.
+    dst: Reg,
+    call_site: Span,
+}
+
+struct Machine<'a> {
+    interpreter: &'a mut Interpreter,
+    main: &'a RegisterChunk,
+    /// The registers of every call in progress, the innermost last.
+    registers: Vec<Value>,
+    calls: Vec<CallFrame>,
+}
+
+impl<'a> Machine<'a> {
+    fn new(interpreter: &'a mut Interpreter, main: &'a RegisterChunk) -> Machine<'a> {
+        Machine { interpreter, main, registers: vec![Value::Nil; main.registers as usize], calls: Vec::new() }
+    }
+
+    fn execute(&mut self) -> Result<Value, RuntimeError> {
+        self.dispatch().map_err(|mut error| {
+            while let Some(call) = self.calls.pop() {
+                self.interpreter.pop_frame();
+                error = error.traced(&call.function.decl.name, call.call_site);
+            }
+            error
+        })
+    }
+
+    fn dispatch(&mut self) -> Result<Value, RuntimeError> {
+        let main = self.main;
+        let mut current: Option<Rc<RegisterFunction>> = None;
+        let mut pc = 0;
+        let mut base = 0;
+        let mut result = Value::Nil;
+        macro_rules! reg {
+            ($reg:expr) => {
+                self.registers[base + $reg as usize]
+            };
+        }
+        loop {
+            let chunk = match &current {
+                Some(function) => &function.chunk,
+                None => main,
+            };
+            let Some(&instr) = chunk.code.get(pc) else {
+                return Ok(result);
+            };
+            let span = chunk.spans[pc];
+            pc += 1;
+            if let Some(profile) = &mut self.interpreter.profile {
+                profile.record(instr.name(), span.line);
+            }
+            match instr {
+                Instr::Constant { dst, constant } => reg!(dst) = chunk.constants[constant as usize].clone(),
+                Instr::Nil { dst } => reg!(dst) = Value::Nil,
+                Instr::Bool { dst, value } => reg!(dst) = Value::Bool(value),
+                Instr::Load { dst, name } => {
+                    let name = &chunk.names[name as usize];
+                    reg!(dst) = self
+                        .interpreter
+                        .lookup(name)
+                        .ok_or_else(|| RuntimeError::UndefinedVariable { name: name.clone(), span })?;
+                },
+                Instr::LoadFunction { dst, name } => {
+                    let name = &chunk.names[name as usize];
+                    reg!(dst) = self
+                        .interpreter
+                        .lookup(name)
+                        .ok_or_else(|| RuntimeError::UndefinedFunction { name: name.clone(), span })?;
+                },
+                Instr::Store { name, src } => {
+                    let value = core::mem::take(&mut reg!(src));
+                    self.interpreter.assign(&chunk.names[name as usize], value);
+                },
+                Instr::Declare(name) => self.interpreter.declare(&chunk.names[name as usize]),
+                Instr::Global(name) => self.interpreter.declare_global(&chunk.names[name as usize]),
+                Instr::Closure { dst, function } => {
+                    let code = &chunk.functions[function as usize];
+                    reg!(dst) = self.interpreter.make_compiled_function(&code.decl, Bytecode::Register(code.clone()));
+                },
+                Instr::Array { dst, start, count } => {
+                    let items = self.take(base + start as usize, count as usize);
+                    let array = Value::array(items);
+                    self.interpreter.check_size(&array, span)?;
+                    reg!(dst) = array;
+                },
+                Instr::Map { dst, start, count } => {
+                    let mut map = IndexMap::with_capacity_and_hasher(count as usize, Default::default());
+                    let mut entries = self.take(base + start as usize, 2 * count as usize).into_iter();
+                    while let (Some(key), Some(value)) = (entries.next(), entries.next()) {
+                        map.insert(map_key(key, span)?, value);
+                    }
+                    let map = Value::map(map);
+                    self.interpreter.check_size(&map, span)?;
+                    reg!(dst) = map;
+                },
+                Instr::CheckKey { key } => {
+                    if !matches!(reg!(key), Value::Str(_)) {
+                        return Err(map_key(reg!(key).clone(), span).unwrap_err());
+                    }
+                },
+                Instr::Index { dst, target, index, target_span } => {
+                    let value = core::mem::take(&mut reg!(target));
+                    let index = core::mem::take(&mut reg!(index));
+                    let target_span = chunk.operand_spans[target_span as usize];
+                    reg!(dst) = self.interpreter.index_value(value, index, target_span, span)?;
+                },
+                Instr::SetIndex { target, index, value, target_span } => {
+                    let array = core::mem::take(&mut reg!(target));
+                    let index = core::mem::take(&mut reg!(index));
+                    let value = core::mem::take(&mut reg!(value));
+                    let target_span = chunk.operand_spans[target_span as usize];
+                    self.interpreter.store_index(array, index, value, target_span, span)?;
+                },
+                Instr::Field { dst, target, name, target_span } => {
+                    let target_span = chunk.operand_spans[target_span as usize];
+                    reg!(dst) = field_value(&reg!(target), &chunk.names[name as usize], target_span, span)?;
+                },
+                Instr::SetField { target, name, value } => {
+                    let value = core::mem::take(&mut reg!(value));
+                    store_field(&reg!(target), &chunk.names[name as usize], value, span)?;
+                },
+                Instr::Unary { op, dst, src } => {
+                    let operand = core::mem::take(&mut reg!(src));
+                    reg!(dst) = unary_op(op, operand, self.interpreter.arithmetic, span)?;
+                },
+                Instr::Binary { op, dst, left, right } => {
+                    if let (&Value::Int(l), &Value::Int(r)) = (&reg!(left), &reg!(right)) {
+                        if let Some(value) = int_binary(op, l, r) {
+                            reg!(dst) = value;
+                            continue;
+                        }
+                    }
+                    let left = core::mem::take(&mut reg!(left));
+                    let right = core::mem::take(&mut reg!(right));
+                    reg!(dst) = self.interpreter.binary(op, left, right, span)?;
+                },
+                Instr::BinaryConstant { op, dst, left, constant } => {
+                    if let (&Value::Int(l), &Value::Int(r)) = (&reg!(left), &chunk.constants[constant as usize]) {
+                        if let Some(value) = int_binary(op, l, r) {
+                            reg!(dst) = value;
+                            continue;
+                        }
+                    }
+                    let left = core::mem::take(&mut reg!(left));
+                    let right = chunk.constants[constant as usize].clone();
+                    reg!(dst) = self.interpreter.binary(op, left, right, span)?;
+                },
+                Instr::Truthy { dst, src } => reg!(dst) = Value::Bool(reg!(src).is_truthy()),
+                Instr::Jump(target) => pc = target as usize,
+                Instr::JumpIfFalse { cond, target } => {
+                    if !reg!(cond).is_truthy() {
+                        pc = target as usize;
+                    }
+                },
+                Instr::JumpIfTrue { cond, target } => {
+                    if reg!(cond).is_truthy() {
+                        pc = target as usize;
+                    }
+                },
+                Instr::Call { dst, start, count, callee_span } | Instr::TailCall { dst, start, count, callee_span } => {
+                    let mut args = self.take(base + start as usize, 1 + count as usize);
+                    let function = args.remove(0);
+                    if let Value::Function(function) = &function {
+                        if let Some(Bytecode::Register(code)) = &function.code {
+                            let code = code.clone();
+                            match self.calls.last_mut().filter(|_| matches!(instr, Instr::TailCall { .. })) {
+                                Some(call) => {
+                                    self.interpreter.replace_frame(function, args, span)?;
+                                    self.registers.truncate(base);
+                                    call.function = code.clone();
+                                    call.call_site = span;
+                                },
+                                None => {
+                                    self.interpreter.push_frame(function, args, span)?;
+                                    self.calls.push(CallFrame {
+                                        function: code.clone(),
+                                        caller: current.take(),
+                                        return_to: pc,
+                                        base,
+                                        dst,
+                                        call_site: span,
+                                    });
+                                    base = self.registers.len();
+                                },
+                            }
+                            self.registers.resize(base + code.chunk.registers as usize, Value::Nil);
+                            current = Some(code);
+                            pc = 0;
+                            continue;
+                        }
+                    }
+                    let callee_span = chunk.operand_spans[callee_span as usize];
+                    reg!(dst) = self.interpreter.call_value(function, args, span, callee_span)?;
+                },
+                Instr::Return { src } => {
+                    let value = core::mem::take(&mut reg!(src));
+                    let Some(call) = self.calls.pop() else {
+                        return Ok(value);
+                    };
+                    self.interpreter.pop_frame();
+                    self.registers.truncate(base);
+                    base = call.base;
+                    reg!(call.dst) = value;
+                    current = call.caller;
+                    pc = call.return_to;
+                },
+                Instr::Print { start, count } => {
+                    let values = self.take(base + start as usize, count as usize);
+                    self.interpreter.print_values(&values, span)?;
+                },
+                Instr::Import(name) => self.interpreter.import(&chunk.names[name as usize], span)?,
+                Instr::Statement => self.interpreter.enter_statement(span)?,
+                Instr::Iteration => self.interpreter.end_iteration(span)?,
+                Instr::SetResult { src } => result = core::mem::take(&mut reg!(src)),
+                Instr::ClearResult => result = Value::Nil,
+                Instr::Gosub { target, resume } => {
+                    self.interpreter.gosub(resume as usize, span)?;
+                    pc = target as usize;
+                },
+                Instr::SubReturn => pc = self.interpreter.sub_return(span)?,
+                Instr::UndefinedLine(i) => {
+                    let Value::Int(line) = chunk.constants[i as usize] else {
+                        unreachable!("line numbers are ints");
+                    };
+                    return Err(RuntimeError::UndefinedLine { line, span });
+                },
+            }
+        }
+    }
+
+    /// Moves the values out of `count` registers from `start` on.
+    fn take(&mut self, start: usize, count: usize) -> Vec<Value> {
+        self.registers[start..start + count].iter_mut().map(core::mem::take).collect()
+    }
+}
+
+/// A disassembly listing like that of [`Chunk`](crate::compiler::Chunk).
+impl fmt::Display for RegisterChunk {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut last_line = None;
+        for (offset, (instr, span)) in self.code.iter().zip(&self.spans).enumerate() {
+            write!(f, "{:04} ", offset)?;
+            if last_line == Some(span.line) {
+                write!(f, "   | ")?;
+            } else {
+                write!(f, "{:4} ", span.line)?;
+                last_line = Some(span.line);
+            }
+            self.write_instr(f, *instr)?;
+            writeln!(f)?;
+        }
+        for function in &self.functions {
+            writeln!(f, "\nfn {}({}):", function.decl.name, function.decl.params.join(", "))?;
+            write!(f, "{}", function.chunk)?;
+        }
+        Ok(())
+    }
+}
+
+impl RegisterChunk {
+    fn write_instr(&self, f: &mut fmt::Formatter<'_>, instr: Instr) -> fmt::Result {
+        let name = |i: u32| &self.names[i as usize];
+        let constant = |i: u32| match &self.constants[i as usize] {
+            Value::Str(s) => format!("{:?}", s),
+            value => value.to_string(),
+        };
+        match instr {
+            Instr::Constant { dst, constant: i } => write!(f, "r{} = {}", dst, constant(i)),
+            Instr::Nil { dst } => write!(f, "r{} = nil", dst),
+            Instr::Bool { dst, value } => write!(f, "r{} = {}", dst, value),
+            Instr::Load { dst, name: i } => write!(f, "r{} = load {}", dst, name(i)),
+            Instr::LoadFunction { dst, name: i } => write!(f, "r{} = load-function {}", dst, name(i)),
+            Instr::Store { name: i, src } => write!(f, "store {} r{}", name(i), src),
+            Instr::Declare(i) => write!(f, "declare {}", name(i)),
+            Instr::Global(i) => write!(f, "global {}", name(i)),
+            Instr::Closure { dst, function } => write!(f, "r{} = closure {}", dst, self.functions[function as usize].decl.name),
+            Instr::Array { dst, start, count } => write!(f, "r{} = array r{} {}", dst, start, count),
+            Instr::Map { dst, start, count } => write!(f, "r{} = map r{} {}", dst, start, count),
+            Instr::CheckKey { key } => write!(f, "check-key r{}", key),
+            Instr::Index { dst, target, index, .. } => write!(f, "r{} = r{}[r{}]", dst, target, index),
+            Instr::SetIndex { target, index, value, .. } => write!(f, "r{}[r{}] = r{}", target, index, value),
+            Instr::Field { dst, target, name: i, .. } => write!(f, "r{} = r{}.{}", dst, target, name(i)),
+            Instr::SetField { target, name: i, value } => write!(f, "r{}.{} = r{}", target, name(i), value),
+            Instr::Unary { op, dst, src } => write!(f, "r{} = {:?} r{}", dst, op, src),
+            Instr::Binary { op, dst, left, right } => write!(f, "r{} = r{} {:?} r{}", dst, left, op, right),
+            Instr::BinaryConstant { op, dst, left, constant: i } => write!(f, "r{} = r{} {:?} {}", dst, left, op, constant(i)),
+            Instr::Truthy { dst, src } => write!(f, "r{} = truthy r{}", dst, src),
+            Instr::Jump(target) => write!(f, "jump {:04}", target),
+            Instr::JumpIfFalse { cond, target } => write!(f, "jump-if-false r{} {:04}", cond, target),
+            Instr::JumpIfTrue { cond, target } => write!(f, "jump-if-true r{} {:04}", cond, target),
+            Instr::Call { dst, start, count, .. } => write!(f, "r{} = call r{} {}", dst, start, count),
+            Instr::TailCall { dst, start, count, .. } => write!(f, "r{} = tail-call r{} {}", dst, start, count),
+            Instr::Return { src } => write!(f, "return r{}", src),
+            Instr::Print { start, count } => write!(f, "print r{} {}", start, count),
+            Instr::Import(i) => write!(f, "import {}", name(i)),
+            Instr::Statement => write!(f, "statement"),
+            Instr::Iteration => write!(f, "iteration"),
+            Instr::SetResult { src } => write!(f, "set-result r{}", src),
+            Instr::ClearResult => write!(f, "clear-result"),
+            Instr::Gosub { target, resume } => write!(f, "gosub {:04} resume {:04}", target, resume),
+            Instr::SubReturn => write!(f, "sub-return"),
+            Instr::UndefinedLine(i) => write!(f, "undefined-line {}", constant(i)),
+        }
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod test {
+    use crate::compiler::{compile_with, Backend, CompileOptions};
+    use crate::interpreter::{Interpreter, RuntimeError, SharedBuffer};
+    use crate::parser::{parse, parse_with_dialect, Dialect};
+    use crate::register::compile;
+    use crate::value::Value;
+
+    #[test]
+    fn test_compile() {
+        let chunk = compile(&parse("x = 2\nprint x * x + 1, f(x)").unwrap());
+        assert_eq!(chunk.to_string(), "\
+0000    1 statement
+0001    | r0 = 2
+0002    | store x r0
+0003    | clear-result
+0004    2 statement
+0005    | r0 = load x
+0006    | r2 = load x
+0007    | r0 = r0 Mul r2
+0008    | r0 = r0 Add 1
+0009    | r2 = load-function f
+0010    | r3 = load x
+0011    | r1 = call r2 1
+0012    | print r0 2
+0013    | clear-result
+");
+        assert_eq!(chunk.registers, 4);
+    }
+
+    #[test]
+    fn test_matches_interpreter() {
+        let programs = [
+            (Dialect::Modern, "x = 1\nwhile x < 100 {\n  x = x * 2\n  if x == 16 { continue }\n  print x\n}\nx"),
+            (Dialect::Modern, "fn fib(n) {\n  if n < 2 { return n }\n  return fib(n - 1) + fib(n - 2)\n}\nprint fib(15)"),
+            (Dialect::Modern, "fn counter() {\n  n = 0\n  return fn() {\n    global total\n    total = total + 1\n    return n\n  }\n}\ntotal = 0\nc = counter()\nc()\nc()\ntotal"),
+            (Dialect::Modern, "struct P { x, y }\np = P(1, 2)\np.y = 5\nm = {\"a\": [p.x, p.y], \"b\": -p.x}\nm[\"c\"] = 3\n[m, m[\"a\"][1], 0 && x, 1 || x, !0]"),
+            (Dialect::Modern, "fn f(a) {\n  return a[5]\n}\nfn g() {\n  return f([1])\n}\ng()"),
+            (Dialect::Modern, "x = {\"a\": 1}\nx[1]"),
+            (Dialect::Modern, "{1: 2}"),
+            (Dialect::Modern, "fn f() {}\nf(1)"),
+            (Dialect::Modern, "x = 9223372036854775807\n[x - 1, x * 1 == x, 7 / 2, x >= 3, 2 != 2]"),
+            (Dialect::Modern, "x = 9223372036854775807\nx + 1"),
+            (Dialect::Classic, "10 n = 0\n20 gosub 100\n30 if n < 3 { goto 20 }\n40 print n\n50 goto 200\n100 n = n + 1\n110 return"),
+        ];
+        for (dialect, source) in programs {
+            let program = parse_with_dialect(source, dialect).unwrap();
+            let expected_output = SharedBuffer::new();
+            let expected = Interpreter::with_output(Box::new(expected_output.clone())).run(&program);
+            let output = SharedBuffer::new();
+            let options = CompileOptions { backend: Backend::Register, optimize: false, tail_calls: false, inline_size: 0, optimize_loops: false };
+            let result = compile_with(&program, &options).run(&mut Interpreter::with_output(Box::new(output.clone())));
+            assert_eq!(result, expected, "results differ for {:?}", source);
+            assert_eq!(output.contents(), expected_output.contents(), "output differs for {:?}", source);
+        }
+    }
+
+    #[test]
+    fn test_deep_recursion() {
+        let program = parse("fn f(n) {\n  if n == 0 { return 0 }\n  return 1 + f(n - 1)\n}\nf(10000)").unwrap();
+        let mut interpreter = Interpreter::with_output(Box::new(SharedBuffer::new()));
+        interpreter.set_max_call_depth(20000);
+        assert_eq!(crate::register::run(&mut interpreter, &compile(&program)), Ok(Value::Int(10000)));
+        interpreter.set_max_call_depth(100);
+        let error = crate::register::run(&mut interpreter, &compile(&program)).unwrap_err();
+        assert!(matches!(error.root(), RuntimeError::StackOverflow { .. }));
+        assert_eq!(error.trace().len(), 100);
+    }
+}
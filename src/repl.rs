@@ -0,0 +1,107 @@
+use crate::diagnostic::Diagnostics;
+use crate::interpreter::{Interpreter, RuntimeError};
+use crate::lexer::KEYWORDS;
+use crate::parser::{parse_into, Dialect};
+use crate::value::Value;
+
+/// Why [`Repl::eval`] failed.
+#[derive(Debug)]
+pub enum EvalError {
+    /// The input didn't parse.
+    Syntax(Diagnostics),
+    Runtime(RuntimeError),
+}
+
+/// Evaluates input one line at a time, as `tbasic repl` does. The lines run
+/// in the same interpreter, so variables and functions defined by one line
+/// can be used by the next.
+pub struct Repl {
+    interpreter: Interpreter,
+}
+
+impl Repl {
+    pub fn new(interpreter: Interpreter) -> Repl {
+        Repl { interpreter }
+    }
+
+    pub fn interpreter(&mut self) -> &mut Interpreter {
+        &mut self.interpreter
+    }
+
+    /// The names a line can use, for completing them: the keywords, the
+    /// builtins and host functions, and the variables defined so far.
+    pub fn completions(&self) -> Vec<String> {
+        let mut names: Vec<String> = KEYWORDS.iter().map(|keyword| keyword.to_string()).collect();
+        names.extend(self.interpreter.globals.keys().cloned());
+        names.extend(self.interpreter.variables().into_iter().map(|(name, _)| name));
+        names.sort();
+        names.dedup();
+        names
+    }
+
+    /// Runs `source`, returning the value of its last statement if that is
+    /// an expression and `Nil` otherwise.
+    pub fn eval(&mut self, source: &str) -> Result<Value, EvalError> {
+        let mut diagnostics = Diagnostics::new();
+        let program = parse_into(source, Dialect::Modern, &mut diagnostics);
+        let Some(program) = program.filter(|_| !diagnostics.has_errors()) else {
+            return Err(EvalError::Syntax(diagnostics));
+        };
+        let result = self.interpreter.run(&program);
+        // Don't leave the frames of a failed call around for `variables`
+        self.interpreter.reset();
+        result.map_err(EvalError::Runtime)
+    }
+}
+
+/// How the REPL shows the value a line evaluated to: strings are quoted so
+/// they can be told from numbers, and `nil` isn't shown at all.
+pub fn echo(value: &Value) -> Option<String> {
+    match value {
+        Value::Nil => None,
+        Value::Str(s) => Some(format!("{:?}", s)),
+        other => Some(other.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::interpreter::{Interpreter, SharedBuffer};
+    use crate::repl::{echo, EvalError, Repl};
+    use crate::value::Value;
+
+    #[test]
+    fn test_eval() {
+        let output = SharedBuffer::new();
+        let mut repl = Repl::new(Interpreter::with_output(Box::new(output.clone())));
+        let mut eval = |source: &str| match repl.eval(source) {
+            Ok(value) => echo(&value),
+            Err(EvalError::Syntax(diagnostics)) => Some(format!("syntax: {}", diagnostics.iter().next().unwrap().message)),
+            Err(EvalError::Runtime(error)) => Some(format!("runtime: {}", error.message())),
+        };
+        assert_eq!(eval("x = 20"), None);
+        assert_eq!(eval("fn double(n) { return n * 2 }"), None);
+        assert_eq!(eval("double(x) + 2"), Some("42".to_string()));
+        assert_eq!(eval("\"a\" + \"b\""), Some("\"ab\"".to_string()));
+        assert_eq!(eval("[1, \"two\"]"), Some("[1, \"two\"]".to_string()));
+        assert_eq!(eval("print x"), None);
+        assert!(eval("x = ").unwrap().starts_with("syntax: "));
+        assert_eq!(eval("y"), Some("runtime: undefined variable `y`".to_string()));
+        // Errors don't lose what was defined before
+        assert_eq!(eval("x"), Some("20".to_string()));
+        assert_eq!(output.contents(), "20\n");
+        assert_eq!(echo(&Value::Nil), None);
+    }
+
+    #[test]
+    fn test_completions() {
+        let mut repl = Repl::new(Interpreter::new());
+        repl.interpreter().register_fn("host_sum", |_| Ok(Value::Int(0)));
+        repl.eval("total = 1\nfn tally() { return total }").unwrap();
+        let completions = repl.completions();
+        for name in ["while", "len", "host_sum", "total", "tally"] {
+            assert!(completions.iter().any(|completion| completion == name), "{} in {:?}", name, completions);
+        }
+        assert!(completions.windows(2).all(|pair| pair[0] < pair[1]));
+    }
+}
@@ -0,0 +1,145 @@
+use alloc::string::{String, ToString};
+
+use crate::collections::HashSet;
+
+/// Capabilities and resource limits for running untrusted scripts, applied
+/// with [`Interpreter::set_sandbox`](crate::interpreter::Interpreter::set_sandbox).
+///
+/// The default allows everything; [`Sandbox::strict`] is a locked-down
+/// starting point to loosen as needed. No limit stops a script from leaking
+/// memory through values that hold themselves, which are never freed; see
+/// [`Value`](crate::value::Value).
+#[derive(Debug, Clone, PartialEq)]
+pub struct Sandbox {
+    /// Whether `input` may read from the host.
+    pub allow_input: bool,
+    /// Whether builtins that touch the file system may run.
+    pub allow_file_io: bool,
+    /// Whether `getenv`, `args` and `exit` may reach the host process.
+    pub allow_process: bool,
+    /// Whether `sleep` may pause the thread running the script.
+    pub allow_sleep: bool,
+    /// Host functions scripts may call, or `None` to allow all of them.
+    pub host_functions: Option<HashSet<String>>,
+    pub fuel: Option<u64>,
+    pub max_call_depth: usize,
+    /// How deeply expressions and blocks may nest in source the interpreter
+    /// parses: modules it imports and what [`execute_with`](crate::execute_with)
+    /// runs.
+    pub max_nesting: usize,
+    /// Longest array a script may build.
+    pub max_array_len: Option<usize>,
+    /// Longest string, in bytes, a script may build.
+    pub max_string_len: Option<usize>,
+    /// Bytes of strings, arrays, maps and structs scripts may build in
+    /// total. Like fuel this is a budget: what a script builds counts
+    /// whether it keeps it or not, since values aren't tracked once built,
+    /// and it is shared by all runs until the sandbox is set again.
+    pub max_memory: Option<usize>,
+}
+
+impl Default for Sandbox {
+    fn default() -> Sandbox {
+        Sandbox {
+            allow_input: true,
+            allow_file_io: true,
+            allow_process: true,
+            allow_sleep: true,
+            host_functions: None,
+            fuel: None,
+            max_call_depth: crate::interpreter::DEFAULT_MAX_CALL_DEPTH,
+            max_nesting: crate::parser::DEFAULT_MAX_NESTING,
+            max_array_len: None,
+            max_string_len: None,
+            max_memory: None,
+        }
+    }
+}
+
+impl Sandbox {
+    /// No input, no file IO, no process access, no sleeping, no host functions, and bounded fuel and memory.
+    pub fn strict() -> Sandbox {
+        Sandbox {
+            allow_input: false,
+            allow_file_io: false,
+            allow_process: false,
+            allow_sleep: false,
+            host_functions: Some(HashSet::default()),
+            fuel: Some(1_000_000),
+            max_call_depth: 64,
+            max_nesting: 32,
+            max_array_len: Some(100_000),
+            max_string_len: Some(1 << 20),
+            max_memory: Some(64 << 20),
+        }
+    }
+
+    /// Adds `name` to the host functions scripts may call.
+    pub fn allow_host_function(mut self, name: &str) -> Sandbox {
+        self.host_functions.get_or_insert_with(HashSet::default).insert(name.to_string());
+        self
+    }
+
+    pub(crate) fn allows_host_function(&self, name: &str) -> bool {
+        self.host_functions.as_ref().is_none_or(|names| names.contains(name))
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod test {
+    use crate::interpreter::{CannedInput, Interpreter, RuntimeError, SharedBuffer};
+    use crate::parser::parse;
+    use crate::sandbox::Sandbox;
+    use crate::value::Value;
+
+    fn run_sandboxed(sandbox: Sandbox, source: &str) -> Result<Value, RuntimeError> {
+        let mut interpreter = Interpreter::with_output(Box::new(SharedBuffer::new()));
+        interpreter.set_input(Box::new(CannedInput::new(["5"])));
+        interpreter.register_fn("time", |_| Ok(Value::Int(0)));
+        interpreter.register_fn("shutdown", |_| Ok(Value::Nil));
+        interpreter.set_sandbox(sandbox);
+        interpreter.run(&parse(source).unwrap())
+    }
+
+    #[test]
+    fn test_capabilities() {
+        assert_eq!(run_sandboxed(Sandbox::default(), "input()"), Ok(Value::Int(5)));
+        let error = run_sandboxed(Sandbox::strict(), "input()").unwrap_err();
+        assert_eq!(error.to_string(), "1:1: `input` is not permitted in this sandbox");
+        let error = run_sandboxed(Sandbox::strict(), "exit(1)").unwrap_err();
+        assert_eq!(error.to_string(), "1:1: `exit` is not permitted in this sandbox");
+        let error = run_sandboxed(Sandbox::strict(), "sleep(9223372036854775807)").unwrap_err();
+        assert_eq!(error.to_string(), "1:1: `sleep` is not permitted in this sandbox");
+
+        let sandbox = Sandbox::strict().allow_host_function("time");
+        assert_eq!(run_sandboxed(sandbox.clone(), "time()"), Ok(Value::Int(0)));
+        let error = run_sandboxed(sandbox, "shutdown()").unwrap_err();
+        assert_eq!(error.to_string(), "1:1: host function `shutdown` is not permitted in this sandbox");
+    }
+
+    #[test]
+    fn test_limits() {
+        let sandbox = Sandbox { max_array_len: Some(3), max_string_len: Some(4), ..Sandbox::default() };
+        assert!(run_sandboxed(sandbox.clone(), "a = [1, 2]\npush(a, 3)").is_ok());
+        let error = run_sandboxed(sandbox.clone(), "a = [1, 2, 3]\npush(a, 4)").unwrap_err();
+        assert!(matches!(error, RuntimeError::MemoryLimit { .. }));
+        assert!(matches!(run_sandboxed(sandbox.clone(), "s = \"ab\" + \"abc\""), Err(RuntimeError::MemoryLimit { .. })));
+        assert!(matches!(run_sandboxed(sandbox, "replace(\"ab\", \"b\", \"bbbb\")"), Err(RuntimeError::MemoryLimit { .. })));
+
+        let source = format!("s = \"{}\"\na = []\ni = 0\nwhile i < 100 {{\npush(a, upper(s))\ni = i + 1\n}}", "a".repeat(1000));
+        let sandbox = Sandbox { max_string_len: Some(1000), max_memory: Some(200_000), ..Sandbox::default() };
+        assert!(run_sandboxed(sandbox, &source).is_ok());
+        let sandbox = Sandbox { max_string_len: Some(1000), max_memory: Some(50_000), ..Sandbox::default() };
+        assert!(matches!(run_sandboxed(sandbox, &source), Err(RuntimeError::MemoryLimit { .. })));
+
+        let error = run_sandboxed(Sandbox::strict(), "while 1 {\n}").unwrap_err();
+        assert!(matches!(error, RuntimeError::FuelExhausted { .. }));
+
+        let source = format!("x = {}1{}", "(".repeat(40), ")".repeat(40));
+        let mut interpreter = Interpreter::with_output(Box::new(SharedBuffer::new()));
+        assert!(interpreter.parse(&source).is_ok());
+        interpreter.set_sandbox(Sandbox::strict());
+        assert_eq!(interpreter.parse(&source).unwrap_err().code, "E0111");
+        assert!(matches!(crate::execute_with(&source, &mut interpreter), Err(crate::Error::Parse(_))));
+    }
+}
@@ -0,0 +1,672 @@
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::io::{self, Read, Write};
+use std::rc::Rc;
+
+use indexmap::IndexMap;
+
+use crate::builtins;
+use crate::bytecode::{
+    encode_bytes, encode_chunk, encode_len, encode_span, encode_uint, encode_value, stack_heights, Decoder, LoadError, VERSION,
+};
+use crate::compiler::{Chunk, CompiledFunction, Op};
+use crate::interpreter::{Environment, Frame, Interpreter, TraceFrame};
+use crate::random::Rng;
+use crate::value::{Bytecode, Function, Instance, Value};
+use crate::vm::{CallFrame, State};
+
+/// The first bytes of every snapshot a
+/// [`VmExecution`](crate::vm::VmExecution) saves. Snapshots are versioned
+/// along with the bytecode they embed, by [`VERSION`].
+pub const MAGIC: &[u8; 4] = b"TBS\0";
+
+/// A value that is shared by reference, so a snapshot holds it once however
+/// many values refer to it.
+#[derive(Clone)]
+enum Object {
+    Array(Rc<RefCell<Vec<Value>>>),
+    Map(Rc<RefCell<IndexMap<String, Value>>>),
+    Struct(Rc<RefCell<Instance>>),
+    Environment(Rc<RefCell<Environment>>),
+    Function(Rc<Function>),
+}
+
+impl Object {
+    fn address(&self) -> *const () {
+        match self {
+            Object::Array(array) => Rc::as_ptr(array) as *const (),
+            Object::Map(map) => Rc::as_ptr(map) as *const (),
+            Object::Struct(instance) => Rc::as_ptr(instance) as *const (),
+            Object::Environment(env) => Rc::as_ptr(env) as *const (),
+            Object::Function(function) => Rc::as_ptr(function) as *const (),
+        }
+    }
+}
+
+/// Writes a snapshot: [`MAGIC`], [`VERSION`] as a little-endian `u16`, the
+/// program's chunk as in a `.tbc` file, the random number generator's state,
+/// then the shared objects and finally the interpreter's globals, call
+/// frames and `gosub` returns followed by the VM's operand stack, calls and
+/// position. Values refer to objects by their index, so sharing and cycles
+/// survive, and to compiled functions by their position in the chunk.
+pub(crate) fn save(interpreter: &Interpreter, chunk: &Chunk, state: &State, mut writer: impl Write) -> io::Result<()> {
+    let mut paths = HashMap::new();
+    index_functions(chunk, &mut Vec::new(), &mut paths);
+    let mut encoder = Encoder { paths, ids: HashMap::new(), objects: Vec::new() };
+
+    let mut roots = Vec::new();
+    let mut globals: Vec<_> = interpreter.globals.iter().collect();
+    globals.sort_by(|a, b| a.0.cmp(b.0));
+    encode_len(&mut roots, globals.len());
+    for (name, value) in globals {
+        encode_bytes(&mut roots, name.as_bytes());
+        encoder.value(&mut roots, value)?;
+    }
+    encode_len(&mut roots, interpreter.frames.len());
+    for frame in &interpreter.frames {
+        let locals = encoder.object(Object::Environment(frame.locals.clone()));
+        encode_len(&mut roots, locals);
+        let mut names: Vec<_> = frame.globals.iter().collect();
+        names.sort();
+        encode_len(&mut roots, names.len());
+        for name in names {
+            encode_bytes(&mut roots, name.as_bytes());
+        }
+    }
+    encode_len(&mut roots, interpreter.return_stack.len());
+    for resume in &interpreter.return_stack {
+        encode_len(&mut roots, *resume);
+    }
+    encode_len(&mut roots, state.stack.len());
+    for value in &state.stack {
+        encoder.value(&mut roots, value)?;
+    }
+    encode_len(&mut roots, state.calls.len());
+    for call in &state.calls {
+        encoder.code(&mut roots, Some(&call.function))?;
+        encoder.code(&mut roots, call.caller.as_ref())?;
+        encode_len(&mut roots, call.return_to);
+        encode_len(&mut roots, call.base);
+        encode_span(&mut roots, call.call_site);
+    }
+    encoder.code(&mut roots, state.current.as_ref())?;
+    encode_len(&mut roots, state.pc);
+    encoder.value(&mut roots, &state.result)?;
+
+    // Bodies can refer to objects not seen yet, which get bodies of their own
+    let mut headers = Vec::new();
+    let mut bodies = Vec::new();
+    let mut i = 0;
+    while let Some(object) = encoder.objects.get(i).cloned() {
+        encoder.object_header(&mut headers, &object)?;
+        encoder.object_body(&mut bodies, &object)?;
+        i += 1;
+    }
+
+    let mut out = Vec::from(&MAGIC[..]);
+    out.extend_from_slice(&VERSION.to_le_bytes());
+    encode_chunk(&mut out, chunk)?;
+    encode_uint(&mut out, interpreter.rng.state());
+    encode_len(&mut out, encoder.objects.len());
+    out.extend_from_slice(&headers);
+    out.extend_from_slice(&bodies);
+    out.extend_from_slice(&roots);
+    writer.write_all(&out)
+}
+
+/// Reads a snapshot written by [`save`] and installs its globals, frames and
+/// `gosub` returns in `interpreter`, returning the program and where the VM
+/// resumes it. Like [`bytecode::load`](crate::bytecode::load) this checks
+/// references and positions but not that the operand stack fits the code.
+pub(crate) fn load(interpreter: &mut Interpreter, mut reader: impl Read) -> Result<(Chunk, State), LoadError> {
+    let mut data = Vec::new();
+    reader.read_to_end(&mut data)?;
+    let rest = data.strip_prefix(&MAGIC[..]).ok_or(LoadError::NotBytecode)?;
+    let mut decoder = Decoder::new(rest);
+    let version = u16::from_le_bytes([decoder.byte()?, decoder.byte()?]);
+    if version != VERSION {
+        return Err(LoadError::UnsupportedVersion(version));
+    }
+    let chunk = decoder.chunk()?;
+    let rng = decoder.uint()?;
+    let hosts = interpreter
+        .globals
+        .values()
+        .filter_map(|value| match value {
+            Value::Host(host) => Some((host.name.clone(), value.clone())),
+            _ => None,
+        })
+        .collect();
+    let mut loader = Loader { decoder, chunk: &chunk, objects: Vec::new(), hosts };
+    loader.objects()?;
+
+    let mut globals = HashMap::new();
+    for _ in 0..loader.decoder.len()? {
+        let name = loader.decoder.string()?;
+        let value = loader.value()?;
+        globals.insert(name, value);
+    }
+    let mut scopes = Vec::new();
+    for _ in 0..loader.decoder.len()? {
+        let Object::Environment(locals) = loader.object()? else {
+            return Err(malformed("a frame's locals aren't an environment"));
+        };
+        let mut names = HashSet::new();
+        for _ in 0..loader.decoder.len()? {
+            names.insert(loader.decoder.string()?);
+        }
+        scopes.push((locals, names));
+    }
+    let mut return_stack = Vec::new();
+    for _ in 0..loader.decoder.len()? {
+        let resume = loader.index()?;
+        if !chunk.code.iter().any(|op| matches!(*op, Op::Gosub { resume: r, .. } if r as usize == resume)) {
+            return Err(malformed("a `gosub` return isn't after a `gosub`"));
+        }
+        return_stack.push(resume);
+    }
+    let mut state = State::default();
+    for _ in 0..loader.decoder.len()? {
+        let value = loader.value()?;
+        state.stack.push(value);
+    }
+    for _ in 0..loader.decoder.len()? {
+        let function = loader.code()?.ok_or_else(|| malformed("a call runs the main program"))?;
+        let caller = loader.code()?;
+        let return_to = loader.index()?;
+        let base = loader.index()?;
+        if base > state.stack.len() {
+            return Err(malformed("a call's base is above the operand stack"));
+        }
+        // Returning leaves the value on the caller's part of the stack
+        let below = state.calls.last().map_or(0, |call| call.base);
+        let height = base.checked_sub(below).ok_or_else(|| malformed("a call's base is below its caller's"))?;
+        check_height(caller.as_ref().map_or(&chunk, |caller| &caller.chunk), return_to, height + 1)?;
+        let call_site = loader.decoder.span()?;
+        state.calls.push(CallFrame { function, caller, return_to, base, call_site });
+    }
+    state.current = loader.code()?;
+    state.pc = loader.index()?;
+    let below = state.calls.last().map_or(0, |call| call.base);
+    check_height(state.current.as_ref().map_or(&chunk, |current| &current.chunk), state.pc, state.stack.len() - below)?;
+    state.result = loader.value()?;
+    if !loader.decoder.data.is_empty() {
+        return Err(malformed("trailing data after the snapshot"));
+    }
+    if scopes.len() != state.calls.len() {
+        return Err(malformed("the frames don't match the calls"));
+    }
+    let frames = scopes
+        .into_iter()
+        .zip(&state.calls)
+        .map(|((locals, globals), call)| {
+            let call = TraceFrame { function: call.function.decl.name.clone(), call_site: call.call_site };
+            Frame { locals, globals, call }
+        })
+        .collect();
+
+    interpreter.reset();
+    interpreter.globals = globals;
+    interpreter.frames = frames;
+    interpreter.return_stack = return_stack;
+    interpreter.rng = Rng::new(rng);
+    Ok((chunk, state))
+}
+
+fn malformed(message: &str) -> LoadError {
+    LoadError::Malformed(message.to_string())
+}
+
+fn unsupported(what: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, format!("{} can't be saved in a snapshot", what))
+}
+
+/// Records where each function of `chunk` is, as the indexes into the
+/// `functions` of the chunks enclosing it.
+fn index_functions(chunk: &Chunk, path: &mut Vec<usize>, paths: &mut HashMap<*const CompiledFunction, Vec<usize>>) {
+    for (i, function) in chunk.functions.iter().enumerate() {
+        path.push(i);
+        paths.insert(Rc::as_ptr(function), path.clone());
+        index_functions(&function.chunk, path, paths);
+        path.pop();
+    }
+}
+
+/// Checks that `pc` is an instruction of `chunk` that runs with `height`
+/// values on the operand stack.
+fn check_height(chunk: &Chunk, pc: usize, height: usize) -> Result<(), LoadError> {
+    match stack_heights(chunk)?.get(pc) {
+        Some(&Some(expected)) if expected == height => Ok(()),
+        Some(Some(_)) => Err(malformed("the operand stack doesn't match the code")),
+        _ => Err(malformed("a position isn't an instruction the code reaches")),
+    }
+}
+
+struct Encoder {
+    paths: HashMap<*const CompiledFunction, Vec<usize>>,
+    ids: HashMap<*const (), usize>,
+    /// Every object found so far, by index.
+    objects: Vec<Object>,
+}
+
+impl Encoder {
+    /// The index of `object`, numbering it if it's new.
+    fn object(&mut self, object: Object) -> usize {
+        let next = self.objects.len();
+        *self.ids.entry(object.address()).or_insert_with(|| {
+            self.objects.push(object);
+            next
+        })
+    }
+
+    fn value(&mut self, out: &mut Vec<u8>, value: &Value) -> io::Result<()> {
+        let (tag, id) = match value {
+            Value::Array(array) => (7, self.object(Object::Array(array.clone()))),
+            Value::Map(map) => (8, self.object(Object::Map(map.clone()))),
+            Value::Struct(instance) => (9, self.object(Object::Struct(instance.clone()))),
+            Value::Builtin(builtin) => {
+                out.push(10);
+                encode_bytes(out, builtin.name.as_bytes());
+                return Ok(());
+            },
+            Value::Host(host) => {
+                out.push(11);
+                encode_bytes(out, host.name.as_bytes());
+                return Ok(());
+            },
+            Value::Function(function) => (12, self.object(Object::Function(function.clone()))),
+            Value::Module(_) => return Err(unsupported("a module")),
+            constant => return encode_value(out, constant),
+        };
+        out.push(tag);
+        encode_len(out, id);
+        Ok(())
+    }
+
+    /// Writes where `function` is in the program, or an empty path for the
+    /// main chunk.
+    fn code(&self, out: &mut Vec<u8>, function: Option<&Rc<CompiledFunction>>) -> io::Result<()> {
+        let path = match function {
+            Some(function) => {
+                self.paths.get(&Rc::as_ptr(function)).ok_or_else(|| unsupported("code of another program"))?.as_slice()
+            },
+            None => &[],
+        };
+        encode_len(out, path.len());
+        for i in path {
+            encode_len(out, *i);
+        }
+        Ok(())
+    }
+
+    fn parent(&mut self, out: &mut Vec<u8>, env: Option<&Rc<RefCell<Environment>>>) {
+        match env {
+            Some(env) => {
+                let id = self.object(Object::Environment(env.clone()));
+                encode_len(out, id + 1);
+            },
+            None => encode_len(out, 0),
+        }
+    }
+
+    /// Writes what's needed to create `object` before its contents can be
+    /// read.
+    fn object_header(&mut self, out: &mut Vec<u8>, object: &Object) -> io::Result<()> {
+        match object {
+            Object::Array(_) => out.push(0),
+            Object::Map(_) => out.push(1),
+            Object::Struct(instance) => {
+                out.push(2);
+                encode_value(out, &Value::StructType(instance.borrow().decl.clone()))?;
+            },
+            Object::Environment(_) => out.push(3),
+            Object::Function(function) => {
+                let Some(Bytecode::Stack(code)) = &function.code else {
+                    return Err(unsupported("a function not compiled for this VM"));
+                };
+                out.push(4);
+                self.code(out, Some(code))?;
+                self.parent(out, function.captured.as_ref());
+            },
+        }
+        Ok(())
+    }
+
+    fn object_body(&mut self, out: &mut Vec<u8>, object: &Object) -> io::Result<()> {
+        match object {
+            Object::Array(array) => {
+                let array = array.borrow();
+                encode_len(out, array.len());
+                for value in array.iter() {
+                    self.value(out, value)?;
+                }
+            },
+            Object::Map(map) => {
+                let map = map.borrow();
+                encode_len(out, map.len());
+                for (key, value) in map.iter() {
+                    encode_bytes(out, key.as_bytes());
+                    self.value(out, value)?;
+                }
+            },
+            Object::Struct(instance) => {
+                for value in &instance.borrow().fields {
+                    self.value(out, value)?;
+                }
+            },
+            Object::Environment(env) => {
+                let env = env.borrow();
+                let mut vars: Vec<_> = env.vars.iter().collect();
+                vars.sort_by(|a, b| a.0.cmp(b.0));
+                encode_len(out, vars.len());
+                for (name, value) in vars {
+                    encode_bytes(out, name.as_bytes());
+                    self.value(out, value)?;
+                }
+                self.parent(out, env.parent.as_ref());
+            },
+            Object::Function(_) => {},
+        }
+        Ok(())
+    }
+}
+
+struct Loader<'a, 'b> {
+    decoder: Decoder<'a>,
+    chunk: &'b Chunk,
+    objects: Vec<Object>,
+    /// The interpreter's host functions by name.
+    hosts: HashMap<String, Value>,
+}
+
+impl Loader<'_, '_> {
+    /// An index that isn't followed by as many items, such as a position in
+    /// the code.
+    fn index(&mut self) -> Result<usize, LoadError> {
+        usize::try_from(self.decoder.uint()?).map_err(|_| malformed("index is out of range"))
+    }
+
+    fn object(&mut self) -> Result<Object, LoadError> {
+        let id = self.index()?;
+        self.objects.get(id).cloned().ok_or_else(|| malformed("reference to a missing object"))
+    }
+
+    /// The environment a reference written by [`Encoder::parent`] is to.
+    fn environment(&self, reference: usize) -> Result<Option<Rc<RefCell<Environment>>>, LoadError> {
+        match reference.checked_sub(1) {
+            None => Ok(None),
+            Some(id) => match self.objects.get(id) {
+                Some(Object::Environment(env)) => Ok(Some(env.clone())),
+                _ => Err(malformed("captured variables aren't an environment")),
+            },
+        }
+    }
+
+    fn code(&mut self) -> Result<Option<Rc<CompiledFunction>>, LoadError> {
+        let mut chunk = self.chunk;
+        let mut function = None;
+        for _ in 0..self.decoder.len()? {
+            let i = self.index()?;
+            let code = chunk.functions.get(i).ok_or_else(|| malformed("reference to a missing function"))?;
+            chunk = &code.chunk;
+            function = Some(code);
+        }
+        Ok(function.cloned())
+    }
+
+    /// Reads the objects' headers and then their contents, so contents can
+    /// refer to any object.
+    fn objects(&mut self) -> Result<(), LoadError> {
+        let count = self.decoder.len()?;
+        let mut functions = Vec::new();
+        for i in 0..count {
+            let object = match self.decoder.byte()? {
+                0 => Object::Array(Rc::default()),
+                1 => Object::Map(Rc::default()),
+                2 => {
+                    let tag = self.decoder.byte()?;
+                    let Value::StructType(decl) = self.decoder.constant(tag)? else {
+                        return Err(malformed("a struct's type isn't a struct type"));
+                    };
+                    let fields = vec![Value::Nil; decl.fields.len()];
+                    Object::Struct(Rc::new(RefCell::new(Instance { decl, fields })))
+                },
+                3 => Object::Environment(Rc::default()),
+                4 => {
+                    let code = self.code()?.ok_or_else(|| malformed("a function runs the main program"))?;
+                    let captured = self.index()?;
+                    functions.push((i, code, captured));
+                    // Replaced once every environment it can capture exists
+                    Object::Array(Rc::default())
+                },
+                kind => return Err(LoadError::Malformed(format!("unknown object kind {}", kind))),
+            };
+            self.objects.push(object);
+        }
+        for (i, code, captured) in functions {
+            let captured = self.environment(captured)?;
+            let function = Function { decl: code.decl.clone(), captured, code: Some(Bytecode::Stack(code)) };
+            self.objects[i] = Object::Function(Rc::new(function));
+        }
+        for i in 0..count {
+            match self.objects[i].clone() {
+                Object::Array(array) => {
+                    for _ in 0..self.decoder.len()? {
+                        let value = self.value()?;
+                        array.borrow_mut().push(value);
+                    }
+                },
+                Object::Map(map) => {
+                    for _ in 0..self.decoder.len()? {
+                        let key = self.decoder.string()?;
+                        let value = self.value()?;
+                        map.borrow_mut().insert(key, value);
+                    }
+                },
+                Object::Struct(instance) => {
+                    let fields = instance.borrow().fields.len();
+                    for field in 0..fields {
+                        let value = self.value()?;
+                        instance.borrow_mut().fields[field] = value;
+                    }
+                },
+                Object::Environment(env) => {
+                    for _ in 0..self.decoder.len()? {
+                        let name = self.decoder.string()?;
+                        let value = self.value()?;
+                        env.borrow_mut().vars.insert(name, value);
+                    }
+                    let parent = self.index()?;
+                    env.borrow_mut().parent = self.environment(parent)?;
+                },
+                Object::Function(_) => {},
+            }
+        }
+        Ok(())
+    }
+
+    fn value(&mut self) -> Result<Value, LoadError> {
+        let tag = self.decoder.byte()?;
+        Ok(match tag {
+            7..=9 | 12 => match (tag, self.object()?) {
+                (7, Object::Array(array)) => Value::Array(array),
+                (8, Object::Map(map)) => Value::Map(map),
+                (9, Object::Struct(instance)) => Value::Struct(instance),
+                (12, Object::Function(function)) => Value::Function(function),
+                _ => return Err(malformed("a value refers to an object of another kind")),
+            },
+            10 => {
+                let name = self.decoder.string()?;
+                let builtin = builtins::lookup(&name).ok_or_else(|| LoadError::Malformed(format!("unknown builtin `{}`", name)))?;
+                Value::Builtin(builtin)
+            },
+            11 => {
+                let name = self.decoder.string()?;
+                self.hosts.get(&name).cloned().ok_or(LoadError::UnknownHostFunction(name))?
+            },
+            tag => self.decoder.constant(tag)?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::bytecode::LoadError;
+    use crate::compiler::compile;
+    use crate::execution::StepResult;
+    use crate::interpreter::{Interpreter, RuntimeError, SharedBuffer};
+    use crate::parser::{parse, parse_with_dialect, Dialect};
+    use crate::value::Value;
+    use crate::vm::VmExecution;
+
+    fn interpreter(output: &SharedBuffer, seed: u64) -> Interpreter {
+        let mut interpreter = Interpreter::with_output(Box::new(output.clone()));
+        interpreter.set_seed(seed);
+        interpreter.register_fn("twice", |args| match args {
+            [Value::Int(n)] => Ok(Value::Int(n * 2)),
+            _ => Err(RuntimeError::host("expected an int")),
+        });
+        interpreter
+    }
+
+    /// Pauses `source` after each number of steps in turn, saving it and
+    /// finishing it from the snapshot, and checks that prints the same and
+    /// returns the same as running it in one go.
+    fn check_resumes(source: &str, dialect: Dialect) {
+        let chunk = compile(&parse_with_dialect(source, dialect).unwrap());
+        let output = SharedBuffer::new();
+        let expected = interpreter(&output, 7).run_compiled(&chunk);
+        let expected_output = output.contents();
+
+        for steps in 0.. {
+            let before = SharedBuffer::new();
+            let mut first = interpreter(&before, 7);
+            let mut execution = first.start_compiled(&chunk);
+            if execution.run_for(steps) != Ok(StepResult::Paused) {
+                assert!(steps > 0, "{:?} finished without running", source);
+                break;
+            }
+            let mut bytes = Vec::new();
+            execution.save(&mut bytes).unwrap();
+            drop(execution);
+
+            let after = SharedBuffer::new();
+            let mut second = interpreter(&after, 1);
+            let mut resumed = VmExecution::load(&mut second, &bytes[..]).unwrap();
+            let result = match resumed.run_for(u64::MAX) {
+                Ok(StepResult::Finished(value)) => Ok(value),
+                Ok(StepResult::Paused) => panic!("{:?} didn't finish", source),
+                Err(error) => Err(error),
+            };
+            assert_eq!(result, expected, "{:?} paused after {} steps", source, steps);
+            assert_eq!(before.contents() + &after.contents(), expected_output, "{:?} paused after {} steps", source, steps);
+        }
+    }
+
+    #[test]
+    fn test_resume() {
+        let source = "struct P { x, y }
+fn counter(start) {
+  cell = [start]
+  return fn() {
+    cell[0] = cell[0] + 1
+    return cell[0]
+  }
+}
+a = [1, 2]
+m = {\"a\": a, \"p\": P(a, 0)}
+push(a, m)
+next = counter(10)
+fn sum(n) {
+  total = 0
+  i = 0
+  while i < n {
+    total = total + next() + floor(rnd() * 10)
+    i = i + 1
+  }
+  return total
+}
+print sum(3), twice(2)
+m[\"p\"].y = sum(2)
+print m[\"p\"].y, len(a), a[0], floor(rnd() * 1000)
+[a[2][\"p\"].x[2][\"p\"].y, next(), len ]";
+        check_resumes(source, Dialect::Modern);
+        check_resumes("fn f(n) {\n  if n == 0 { return 0 }\n  return 1 + f(n - 1)\n}\nprint f(3)\nf(4) + 1", Dialect::Modern);
+        let source = "10 n = 0\n20 gosub 100\n30 if n < 3 { goto 20 }\n40 print n\n50 end = 1\n60 goto 200\n100 n = n + 1\n110 return";
+        check_resumes(source, Dialect::Classic);
+    }
+
+    #[test]
+    fn test_save_errors() {
+        let chunk = compile(&parse("x = 1\nprint x\nx").unwrap());
+        let mut interpreter = Interpreter::with_output(Box::new(SharedBuffer::new()));
+        interpreter.run(&parse("fn old() {\n  return 1\n}").unwrap()).unwrap();
+        let mut execution = interpreter.start_compiled(&chunk);
+        execution.step().unwrap();
+        let error = execution.save(&mut Vec::new()).unwrap_err();
+        assert_eq!(error.to_string(), "a function not compiled for this VM can't be saved in a snapshot");
+
+        let mut interpreter = Interpreter::with_output(Box::new(SharedBuffer::new()));
+        let mut execution = interpreter.start_compiled(&chunk);
+        assert_eq!(execution.run_for(10), Ok(StepResult::Finished(Value::Int(1))));
+        assert!(execution.is_finished());
+        assert!(execution.save(&mut Vec::new()).is_err());
+    }
+
+    #[test]
+    fn test_load_errors() {
+        let chunk = compile(&parse("f = twice\nprint f(1)\n[f]").unwrap());
+        let output = SharedBuffer::new();
+        let mut interpreter = interpreter(&output, 0);
+        let mut execution = interpreter.start_compiled(&chunk);
+        execution.step().unwrap();
+        let mut bytes = Vec::new();
+        execution.save(&mut bytes).unwrap();
+        drop(execution);
+        assert!(bytes.starts_with(b"TBS\0\x02\x00"));
+
+        let mut plain = Interpreter::with_output(Box::new(SharedBuffer::new()));
+        let error = VmExecution::load(&mut plain, &bytes[..]).err().unwrap();
+        assert_eq!(error.to_string(), "host function `twice` is not registered");
+        assert!(matches!(VmExecution::load(&mut plain, &b"TBC\0\x01\x00"[..]), Err(LoadError::NotBytecode)));
+        for len in 6..bytes.len() {
+            let loaded = VmExecution::load(&mut interpreter, &bytes[..len]);
+            assert!(matches!(loaded, Err(LoadError::Malformed(_))), "loaded {} bytes", len);
+        }
+        let loaded = VmExecution::load(&mut interpreter, &[&bytes[..], &[0]].concat()[..]);
+        assert!(matches!(loaded, Err(LoadError::Malformed(_))));
+        drop(loaded);
+
+        let mut resumed = VmExecution::load(&mut interpreter, &bytes[..]).unwrap();
+        assert!(matches!(resumed.run_for(10), Ok(StepResult::Finished(_))));
+        assert_eq!(output.contents(), "2\n");
+    }
+
+    #[test]
+    fn test_mutated() {
+        // Paused in `f`, with the array being built below its call
+        let chunk = compile(&parse("fn f(n) {\n  x = n + 1\n  return x\n}\nprint [1, f(2), 3]").unwrap());
+        let mut first = interpreter(&SharedBuffer::new(), 0);
+        let mut execution = first.start_compiled(&chunk);
+        assert_eq!(execution.run_for(2), Ok(StepResult::Paused));
+        let mut bytes = Vec::new();
+        execution.save(&mut bytes).unwrap();
+        drop(execution);
+
+        // Resuming a mutated snapshot fails or not, but never panics, and
+        // the operand stack always holds what the code expects
+        for i in 6..bytes.len() {
+            for byte in [0, 1, 2, 0x7f, 0x80, 0xff, bytes[i] ^ 1, bytes[i].wrapping_add(1)] {
+                let mut mutated = bytes.clone();
+                mutated[i] = byte;
+                let mut other = interpreter(&SharedBuffer::new(), 0);
+                other.set_fuel(Some(1000));
+                if let Ok(mut resumed) = VmExecution::load(&mut other, &mutated[..]) {
+                    if let Err(error) = resumed.run_for(1000) {
+                        assert!(!matches!(error.root(), RuntimeError::InvalidBytecode { .. }), "{}", error);
+                    }
+                };
+            }
+        }
+    }
+}
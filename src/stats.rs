@@ -0,0 +1,70 @@
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+
+use crate::collections::HashMap;
+
+/// What the interpreter did while
+/// [collecting stats](crate::interpreter::Interpreter::set_stats) was on:
+/// how many statements the tree-walker ran and how often each script
+/// function was called, by any engine.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct RunStats {
+    statements: u64,
+    calls: HashMap<String, u64>,
+}
+
+impl RunStats {
+    pub(crate) fn record_statement(&mut self) {
+        self.statements += 1;
+    }
+
+    pub(crate) fn record_call(&mut self, function: &str) {
+        match self.calls.get_mut(function) {
+            Some(count) => *count += 1,
+            None => {
+                self.calls.insert(function.to_string(), 1);
+            },
+        }
+    }
+
+    pub fn statements(&self) -> u64 {
+        self.statements
+    }
+
+    /// How many times each function was called, most called first.
+    pub fn calls(&self) -> Vec<(&str, u64)> {
+        let mut calls: Vec<_> = self.calls.iter().map(|(name, count)| (name.as_str(), *count)).collect();
+        calls.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+        calls
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod test {
+    use crate::compiler::{compile_with, Backend, CompileOptions};
+    use crate::interpreter::{Interpreter, SharedBuffer};
+    use crate::parser::parse;
+
+    #[test]
+    fn test_stats() {
+        let program = parse("fn fib(n) {\n  if n < 2 { return n }\n  return fib(n - 1) + fib(n - 2)\n}\nfn main() {\n  return fib(5)\n}\nprint main()").unwrap();
+        let mut interpreter = Interpreter::with_output(Box::new(SharedBuffer::new()));
+        interpreter.run(&program).unwrap();
+        assert_eq!(interpreter.stats(), None);
+
+        interpreter.set_stats(true);
+        interpreter.run(&program).unwrap();
+        let stats = interpreter.stats().unwrap();
+        assert_eq!(stats.calls(), vec![("fib", 15), ("main", 1)]);
+        // 3 at the top, 1 in main and 2 in each call of fib
+        assert_eq!(stats.statements(), 3 + 1 + 2 * 15);
+
+        // The VMs count calls but not statements
+        let compiled = compile_with(&program, &CompileOptions { backend: Backend::Stack, ..CompileOptions::default() });
+        interpreter.set_stats(true);
+        compiled.run(&mut interpreter).unwrap();
+        assert_eq!(interpreter.stats().unwrap().calls(), vec![("fib", 15), ("main", 1)]);
+        interpreter.set_stats(false);
+        assert_eq!(interpreter.stats(), None);
+    }
+}
@@ -0,0 +1,391 @@
+// Without `std` only the optimizer's walks over the AST are used; the rest
+// is for the JavaScript, C and WebAssembly backends.
+#![cfg_attr(not(feature = "std"), allow(dead_code))]
+
+use alloc::format;
+use alloc::rc::Rc;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt::{self, Write};
+
+use crate::ast::{Expr, ExprKind, FunctionDecl, Program, Stmt, StmtKind};
+use crate::builtins;
+use crate::collections::{HashMap, HashSet};
+use crate::lexer::Span;
+
+/// Something in a program that a transpiler can't translate.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TranspileError {
+    pub message: String,
+    pub span: Span,
+}
+
+impl fmt::Display for TranspileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.span.line, self.span.column, self.message)
+    }
+}
+
+impl core::error::Error for TranspileError {}
+
+/// Where the variables of each function of a program live, worked out
+/// before generating code for it.
+///
+/// A name a function reads is looked up the way the interpreter does: in
+/// the function's own locals, then in those of the functions it is defined
+/// in, then in the globals; all of these may not have been assigned yet
+/// when the read happens.
+pub(crate) struct Scopes {
+    /// Every function of the program, each after the one it is defined in.
+    pub(crate) functions: Vec<Scope>,
+    index: HashMap<*const FunctionDecl, usize>,
+}
+
+pub(crate) struct Scope {
+    pub(crate) decl: Rc<FunctionDecl>,
+    /// The function this one is defined in.
+    pub(crate) parent: Option<usize>,
+    /// The parameters, then the other names the function assigns, in order
+    /// of appearance.
+    pub(crate) locals: Vec<String>,
+    /// Locals read by functions defined inside this one, which may run after
+    /// the call has returned.
+    pub(crate) captured: HashSet<String>,
+    /// Names the function declares `global`.
+    pub(crate) globals: HashSet<String>,
+}
+
+impl Scopes {
+    /// Analyzes `program`, failing on imports and on calls of builtins
+    /// outside of `supported_builtins` that the program doesn't define
+    /// itself.
+    pub(crate) fn new(program: &Program, supported_builtins: &[&str]) -> Result<Scopes, TranspileError> {
+        let mut scopes = Scopes { functions: Vec::new(), index: HashMap::default() };
+        scopes.block(&program.statements, None);
+        let mut assigned = HashSet::default();
+        let mut reads = Vec::new();
+        unsupported(&program.statements, &mut assigned, &mut reads)?;
+        for (name, span) in reads {
+            if builtins::lookup(&name).is_some() && !supported_builtins.contains(&name.as_str()) && !assigned.contains(&name) {
+                return Err(TranspileError { message: format!("the builtin `{}` isn't available", name), span });
+            }
+        }
+        Ok(scopes)
+    }
+
+    /// The index in `functions` of `decl`.
+    pub(crate) fn of(&self, decl: &Rc<FunctionDecl>) -> usize {
+        self.index[&Rc::as_ptr(decl)]
+    }
+
+    /// The functions whose variables a read of `name` in `function` looks at
+    /// before the globals, innermost first.
+    pub(crate) fn holders(&self, function: usize, name: &str) -> Vec<usize> {
+        let scope = &self.functions[function];
+        let mut holders = Vec::new();
+        if scope.locals.iter().any(|local| local == name) {
+            holders.push(function);
+        }
+        let mut parent = scope.parent;
+        while let Some(i) = parent {
+            if self.functions[i].captured.contains(name) {
+                holders.push(i);
+            }
+            parent = self.functions[i].parent;
+        }
+        holders
+    }
+
+    fn block(&mut self, statements: &[Stmt], parent: Option<usize>) {
+        let mut nested = Vec::new();
+        for statement in statements {
+            functions_in(statement, &mut nested);
+        }
+        for decl in nested {
+            self.function(&decl, parent);
+        }
+    }
+
+    fn function(&mut self, decl: &Rc<FunctionDecl>, parent: Option<usize>) {
+        let i = self.functions.len();
+        let mut locals = decl.params.clone();
+        let mut globals = HashSet::default();
+        for statement in &decl.body {
+            assigned_in(statement, &mut locals, &mut globals);
+        }
+        // What functions defined inside this one read, at any depth
+        let mut nested = Vec::new();
+        for statement in &decl.body {
+            functions_in(statement, &mut nested);
+        }
+        let mut reads = HashSet::default();
+        for inner in &nested {
+            reads_in(&inner.body, &mut reads);
+        }
+        let captured = locals.iter().filter(|name| reads.contains(*name)).cloned().collect();
+        self.index.insert(Rc::as_ptr(decl), i);
+        self.functions.push(Scope { decl: decl.clone(), parent, locals, captured, globals });
+        self.block(&decl.body, Some(i));
+    }
+}
+
+/// The functions `statement` defines, not counting those inside them.
+pub(crate) fn functions_in(statement: &Stmt, found: &mut Vec<Rc<FunctionDecl>>) {
+    let mut expr = |expr: &Expr| functions_in_expr(expr, found);
+    match &statement.kind {
+        StmtKind::Function(decl) => found.push(decl.clone()),
+        StmtKind::If { condition, then_branch, else_branch } => {
+            expr(condition);
+            for statement in then_branch.iter().chain(else_branch.iter().flatten()) {
+                functions_in(statement, found);
+            }
+        },
+        StmtKind::While { condition, body } => {
+            expr(condition);
+            for statement in body {
+                functions_in(statement, found);
+            }
+        },
+        _ => each_expr(statement, &mut expr),
+    }
+}
+
+fn functions_in_expr(expr: &Expr, found: &mut Vec<Rc<FunctionDecl>>) {
+    match &expr.kind {
+        ExprKind::Function(decl) => found.push(decl.clone()),
+        _ => each_subexpr(expr, &mut |expr| functions_in_expr(expr, found)),
+    }
+}
+
+/// Adds the names `statement` assigns to `locals` and those it declares
+/// global to `globals`, leaving out nested functions.
+fn assigned_in(statement: &Stmt, locals: &mut Vec<String>, globals: &mut HashSet<String>) {
+    let mut add = |name: &String| {
+        if !locals.contains(name) {
+            locals.push(name.clone());
+        }
+    };
+    match &statement.kind {
+        StmtKind::Assign { name, .. } => add(name),
+        StmtKind::Function(decl) => add(&decl.name),
+        StmtKind::Struct(decl) => add(&decl.name),
+        StmtKind::Global(names) => globals.extend(names.iter().cloned()),
+        StmtKind::If { then_branch, else_branch, .. } => {
+            for statement in then_branch.iter().chain(else_branch.iter().flatten()) {
+                assigned_in(statement, locals, globals);
+            }
+        },
+        StmtKind::While { body, .. } => {
+            for statement in body {
+                assigned_in(statement, locals, globals);
+            }
+        },
+        _ => {},
+    }
+}
+
+/// The names read anywhere in `statements`, including nested functions.
+fn reads_in(statements: &[Stmt], reads: &mut HashSet<String>) {
+    for statement in statements {
+        match &statement.kind {
+            StmtKind::Function(decl) => reads_in(&decl.body, reads),
+            StmtKind::If { then_branch, else_branch, .. } => {
+                reads_in(then_branch, reads);
+                reads_in(else_branch.as_deref().unwrap_or_default(), reads);
+            },
+            StmtKind::While { body, .. } => reads_in(body, reads),
+            _ => {},
+        }
+        each_expr(statement, &mut |expr| reads_in_expr(expr, reads));
+    }
+}
+
+fn reads_in_expr(expr: &Expr, reads: &mut HashSet<String>) {
+    match &expr.kind {
+        ExprKind::Variable(name) => {
+            reads.insert(name.clone());
+        },
+        ExprKind::Function(decl) => reads_in(&decl.body, reads),
+        _ => each_subexpr(expr, &mut |expr| reads_in_expr(expr, reads)),
+    }
+}
+
+/// Fails on `import`, and collects every name assigned and read.
+fn unsupported(statements: &[Stmt], assigned: &mut HashSet<String>, reads: &mut Vec<(String, Span)>) -> Result<(), TranspileError> {
+    for statement in statements {
+        match &statement.kind {
+            StmtKind::Import(_) => {
+                return Err(TranspileError { message: "modules can't be transpiled".to_string(), span: statement.span })
+            },
+            StmtKind::Assign { name, .. } => {
+                assigned.insert(name.clone());
+            },
+            StmtKind::Function(decl) => {
+                assigned.insert(decl.name.clone());
+                unsupported(&decl.body, assigned, reads)?;
+            },
+            StmtKind::Struct(decl) => {
+                assigned.insert(decl.name.clone());
+            },
+            StmtKind::If { then_branch, else_branch, .. } => {
+                unsupported(then_branch, assigned, reads)?;
+                unsupported(else_branch.as_deref().unwrap_or_default(), assigned, reads)?;
+            },
+            StmtKind::While { body, .. } => unsupported(body, assigned, reads)?,
+            _ => {},
+        }
+        let mut result = Ok(());
+        each_expr(statement, &mut |expr| {
+            if result.is_ok() {
+                result = unsupported_expr(expr, assigned, reads);
+            }
+        });
+        result?;
+    }
+    Ok(())
+}
+
+fn unsupported_expr(expr: &Expr, assigned: &mut HashSet<String>, reads: &mut Vec<(String, Span)>) -> Result<(), TranspileError> {
+    match &expr.kind {
+        ExprKind::Variable(name) => reads.push((name.clone(), expr.span)),
+        ExprKind::Function(decl) => unsupported(&decl.body, assigned, reads)?,
+        _ => {
+            let mut result = Ok(());
+            each_subexpr(expr, &mut |expr| {
+                if result.is_ok() {
+                    result = unsupported_expr(expr, assigned, reads);
+                }
+            });
+            result?;
+        },
+    }
+    Ok(())
+}
+
+/// An identifier for the tbasic name `name` in the generated code,
+/// distinct for distinct names.
+pub(crate) fn mangle(name: &str) -> String {
+    let mut mangled = String::new();
+    for c in name.chars() {
+        match c {
+            '_' => mangled.push_str("__"),
+            c if c.is_ascii_alphanumeric() => mangled.push(c),
+            c => {
+                let _ = write!(mangled, "_{:x}_", c as u32);
+            },
+        }
+    }
+    mangled
+}
+
+/// Calls `visit` with `statement` and the statements nested in its blocks.
+pub(crate) fn walk_blocks(statement: &Stmt, visit: &mut dyn FnMut(&StmtKind)) {
+    visit(&statement.kind);
+    match &statement.kind {
+        StmtKind::If { then_branch, else_branch, .. } => {
+            for statement in then_branch.iter().chain(else_branch.iter().flatten()) {
+                walk_blocks(statement, visit);
+            }
+        },
+        StmtKind::While { body, .. } => {
+            for statement in body {
+                walk_blocks(statement, visit);
+            }
+        },
+        _ => {},
+    }
+}
+
+/// Calls `visit` with the expressions `statement` holds directly, leaving
+/// out those in nested blocks.
+pub(crate) fn each_expr(statement: &Stmt, visit: &mut dyn FnMut(&Expr)) {
+    match &statement.kind {
+        StmtKind::Assign { value, .. } => visit(value),
+        StmtKind::SetIndex { target, index, value } => {
+            visit(target);
+            visit(index);
+            visit(value);
+        },
+        StmtKind::SetField { target, value, .. } => {
+            visit(target);
+            visit(value);
+        },
+        StmtKind::If { condition, .. } | StmtKind::While { condition, .. } => visit(condition),
+        StmtKind::Print(args) => args.iter().for_each(visit),
+        StmtKind::Return(Some(value)) | StmtKind::Expr(value) => visit(value),
+        _ => {},
+    }
+}
+
+pub(crate) fn each_subexpr(expr: &Expr, visit: &mut dyn FnMut(&Expr)) {
+    match &expr.kind {
+        ExprKind::Array(items) => items.iter().for_each(visit),
+        ExprKind::Map(entries) => {
+            for (key, value) in entries {
+                visit(key);
+                visit(value);
+            }
+        },
+        ExprKind::Index { target, index } => {
+            visit(target);
+            visit(index);
+        },
+        ExprKind::Field { target, .. } => visit(target),
+        ExprKind::Unary { operand, .. } => visit(operand),
+        ExprKind::Binary { left, right, .. } => {
+            visit(left);
+            visit(right);
+        },
+        ExprKind::Call { callee, args } => {
+            visit(callee);
+            args.iter().for_each(visit);
+        },
+        _ => {},
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod test {
+    use crate::parser::parse;
+    use crate::transpile::{mangle, Scopes};
+
+    #[test]
+    fn test_scopes() {
+        let source = "\
+fn outer(a) {
+  b = 1
+  global g
+  inner = fn() {
+    c = a + b
+    return fn() { return c + g }
+  }
+  return inner
+}";
+        let scopes = Scopes::new(&parse(source).unwrap(), &[]).unwrap();
+        let [outer, inner, innermost] = &scopes.functions[..] else { panic!("expected three functions") };
+        assert_eq!(outer.locals, ["a", "b", "inner"]);
+        assert_eq!(outer.captured, ["a", "b"].map(String::from).into());
+        assert_eq!(outer.globals, ["g".to_string()].into());
+        assert_eq!(inner.captured, ["c".to_string()].into());
+        assert_eq!(innermost.parent, Some(1));
+        assert_eq!(scopes.holders(2, "c"), [1]);
+        assert_eq!(scopes.holders(1, "a"), [0]);
+        assert_eq!(scopes.holders(1, "c"), [1]);
+        assert_eq!(scopes.holders(2, "g"), Vec::<usize>::new());
+    }
+
+    #[test]
+    fn test_unsupported() {
+        let error = Scopes::new(&parse("x = 1\nimport lib").unwrap(), &[]).err().unwrap();
+        assert_eq!(error.to_string(), "2:1: modules can't be transpiled");
+        let error = Scopes::new(&parse("print sleep(1)").unwrap(), &["len"]).err().unwrap();
+        assert_eq!(error.to_string(), "1:7: the builtin `sleep` isn't available");
+        assert!(Scopes::new(&parse("fn sleep(ms) {}\nsleep(1)").unwrap(), &[]).is_ok());
+    }
+
+    #[test]
+    fn test_mangle() {
+        assert_eq!(mangle("a_b"), "a__b");
+        assert_eq!(mangle("π"), "_3c0_");
+    }
+}
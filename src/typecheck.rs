@@ -0,0 +1,711 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+use crate::ast::{BinaryOp, Expr, ExprKind, FunctionDecl, Program, Stmt, StmtKind, TypeAnnotation, UnaryOp};
+use crate::builtins::{self, Arity};
+use crate::lexer::Span;
+
+/// The type of a value as far as the checker can tell before running.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Type {
+    /// Unknown until run time; compatible with every type.
+    Any,
+    Nil,
+    Int,
+    Float,
+    Bool,
+    Str,
+    Array,
+    Map,
+    Function,
+    Module,
+    Struct(String),
+}
+
+impl Type {
+    fn is_numeric(&self) -> bool {
+        matches!(self, Type::Int | Type::Float)
+    }
+
+    /// Whether a value of this type may be stored where `target` is expected.
+    /// Ints widen to floats like they do in mixed arithmetic.
+    fn fits(&self, target: &Type) -> bool {
+        self == target || *self == Type::Any || *target == Type::Any || (*self == Type::Int && *target == Type::Float)
+    }
+
+    /// The type covering both `self` and `other`, or `None` if only
+    /// [`Type::Any`] would. Unknown types don't narrow the result.
+    fn join(&self, other: &Type) -> Option<Type> {
+        match (self, other) {
+            (Type::Any, other) | (other, Type::Any) => Some(other.clone()),
+            (Type::Int, Type::Float) | (Type::Float, Type::Int) => Some(Type::Float),
+            (a, b) if a == b => Some(a.clone()),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Type::Any => write!(f, "any"),
+            Type::Nil => write!(f, "nil"),
+            Type::Int => write!(f, "int"),
+            Type::Float => write!(f, "float"),
+            Type::Bool => write!(f, "bool"),
+            Type::Str => write!(f, "string"),
+            Type::Array => write!(f, "array"),
+            Type::Map => write!(f, "map"),
+            Type::Function => write!(f, "function"),
+            Type::Module => write!(f, "module"),
+            Type::Struct(name) => write!(f, "{}", name),
+        }
+    }
+}
+
+/// An operation the checker can prove would fail at run time.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TypeError {
+    /// The error's [stable code](crate::codes), e.g. `E0203`.
+    pub code: &'static str,
+    pub message: String,
+    pub span: Span,
+}
+
+impl fmt::Display for TypeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}: error: {}", self.span.line, self.span.column, self.message)
+    }
+}
+
+/// Checks `program` against its type annotations and the types of its
+/// literals without running it.
+///
+/// Unannotated variables take the type of their first assignment, and
+/// unannotated functions the type their `return`s agree on. A variable
+/// assigned a value of another type, or a function returning different
+/// types, becomes [`Type::Any`]. Only in functions with annotated
+/// parameters is that reported as ambiguous; annotating `: any` allows it
+/// there. Calls
+/// to named functions, struct constructors and builtins are checked against
+/// the number of arguments they take.
+///
+/// Conditions may be any truthy value, as in the interpreter, unless an
+/// annotation says they aren't bools: `if n` is reported for a parameter
+/// `n: int` but not for `if len(s)`. The checker assumes the default
+/// [`TypeMode::Strict`](crate::interpreter::TypeMode::Strict) arithmetic.
+/// Whatever it can't work out is treated as [`Type::Any`] and never
+/// reported.
+pub fn typecheck(program: &Program) -> Vec<TypeError> {
+    let mut checker = TypeChecker {
+        structs: HashMap::new(),
+        signatures: HashMap::new(),
+        scopes: vec![Scope::default()],
+        return_types: Vec::new(),
+        errors: Vec::new(),
+    };
+    checker.collect_declarations(&program.statements);
+    checker.infer_returns(&program.statements);
+    for statement in &program.statements {
+        checker.statement(statement);
+    }
+    checker.errors
+}
+
+/// Parameter and return types of a named function.
+struct Signature {
+    params: Vec<Type>,
+    ret: Type,
+    /// Whether `ret` comes from an annotation rather than the body.
+    annotated: bool,
+    /// The number of parameters, or `None` if functions of the same name
+    /// take different numbers of them.
+    arity: Option<usize>,
+}
+
+/// Types of the variables in one function, or of the globals.
+#[derive(Default)]
+struct Scope {
+    vars: HashMap<String, Type>,
+    /// Variables with a type annotation rather than an inferred type.
+    annotated: HashSet<String>,
+    /// Names declared `global`, which assignments don't make local.
+    globals: HashSet<String>,
+    /// Whether this is a function with annotated parameters, whose
+    /// variables may not change type.
+    typed: bool,
+}
+
+/// What a function being checked returns.
+struct Returns {
+    annotated: Option<Type>,
+    found: Vec<Type>,
+}
+
+struct TypeChecker {
+    /// Field counts of the declared structs.
+    structs: HashMap<String, usize>,
+    signatures: HashMap<String, Signature>,
+    /// The globals, then the variables of the function being checked.
+    scopes: Vec<Scope>,
+    /// Each function being checked, innermost last.
+    return_types: Vec<Returns>,
+    errors: Vec<TypeError>,
+}
+
+impl TypeChecker {
+    fn error(&mut self, code: &'static str, message: String, span: Span) {
+        self.errors.push(TypeError { code, message, span });
+    }
+
+    /// Registers struct names first so annotations can refer to structs
+    /// declared further down, then the signatures of named functions.
+    fn collect_declarations(&mut self, statements: &[Stmt]) {
+        let mut functions = Vec::new();
+        collect(statements, &mut |statement| match &statement.kind {
+            StmtKind::Struct(decl) => {
+                self.structs.insert(decl.name.clone(), decl.fields.len());
+            },
+            StmtKind::Function(decl) => functions.push(decl.clone()),
+            _ => {},
+        });
+        for decl in functions {
+            let params = decl.param_types.iter().map(|ty| self.resolve(ty.as_ref())).collect();
+            let ret = self.resolve(decl.return_type.as_ref());
+            let arity = match self.signatures.get(&decl.name) {
+                Some(other) if other.arity != Some(decl.params.len()) => None,
+                _ => Some(decl.params.len()),
+            };
+            let annotated = decl.return_type.is_some();
+            self.signatures.insert(decl.name.clone(), Signature { params, ret, annotated, arity });
+        }
+    }
+
+    /// Fills in the return types of unannotated named functions. A function
+    /// calling one further down only sees its type in the second round.
+    fn infer_returns(&mut self, statements: &[Stmt]) {
+        let mut functions = Vec::new();
+        collect(statements, &mut |statement| {
+            if let StmtKind::Function(decl) = &statement.kind {
+                if decl.return_type.is_none() {
+                    functions.push(decl.clone());
+                }
+            }
+        });
+        let errors = self.errors.len();
+        // Function bodies assigning globals mustn't leave types behind either
+        let globals = std::mem::take(&mut self.scopes[0]);
+        for _ in 0..2 {
+            for decl in &functions {
+                let ret = self.function(decl);
+                if let Some(signature) = self.signatures.get_mut(&decl.name) {
+                    signature.ret = ret;
+                }
+            }
+        }
+        // Everything gets reported by the real pass
+        self.errors.truncate(errors);
+        self.scopes[0] = globals;
+    }
+
+    /// The type an annotation names, reporting names that aren't types.
+    fn resolve(&mut self, annotation: Option<&TypeAnnotation>) -> Type {
+        let Some(annotation) = annotation else {
+            return Type::Any;
+        };
+        match annotation.name.as_str() {
+            "any" => Type::Any,
+            "nil" => Type::Nil,
+            "int" => Type::Int,
+            "float" => Type::Float,
+            "bool" => Type::Bool,
+            "string" => Type::Str,
+            "array" => Type::Array,
+            "map" => Type::Map,
+            "function" => Type::Function,
+            "module" => Type::Module,
+            name if self.structs.contains_key(name) => Type::Struct(name.to_string()),
+            name => {
+                self.error("E0201", format!("unknown type `{}`", name), annotation.span);
+                Type::Any
+            },
+        }
+    }
+
+    fn variable_type(&self, name: &str) -> Option<&Type> {
+        // Functions only see their own variables and the globals
+        let local = self.scopes.last().filter(|scope| !scope.globals.contains(name));
+        let local = local.and_then(|scope| scope.vars.get(name));
+        local.or_else(|| self.scopes.first().and_then(|scope| scope.vars.get(name)))
+    }
+
+    /// Whether `expr` has the type of an annotation: it is an annotated
+    /// variable or a call to a function with an annotated return type.
+    fn declared(&self, expr: &Expr) -> bool {
+        match &expr.kind {
+            ExprKind::Variable(name) => {
+                let local = self.scopes.last().filter(|scope| !scope.globals.contains(name) && scope.vars.contains_key(name));
+                local.or(self.scopes.first()).is_some_and(|scope| scope.annotated.contains(name))
+            },
+            ExprKind::Call { callee, .. } => match &callee.kind {
+                ExprKind::Variable(name) if self.variable_type(name).is_none() => {
+                    self.signatures.get(name).is_some_and(|signature| signature.annotated)
+                },
+                _ => false,
+            },
+            _ => false,
+        }
+    }
+
+    /// The scope an assignment to `name` changes.
+    fn assigned_scope(&mut self, name: &str) -> &mut Scope {
+        let global = self.scopes.last().unwrap().globals.contains(name);
+        if global {
+            self.scopes.first_mut().unwrap()
+        } else {
+            self.scopes.last_mut().unwrap()
+        }
+    }
+
+    /// Starts a new variable `name` in the current scope, which may have a
+    /// different type than the variable it shadows.
+    fn declare(&mut self, name: &str) {
+        let scope = self.scopes.last_mut().unwrap();
+        scope.globals.remove(name);
+        scope.vars.remove(name);
+        scope.annotated.remove(name);
+    }
+
+    fn assign(&mut self, name: &str, ty: Option<&TypeAnnotation>, found: Type, span: Span) {
+        if let Some(annotation) = ty {
+            let expected = self.resolve(Some(annotation));
+            if !found.fits(&expected) {
+                self.error("E0203", format!("cannot assign {} to `{}` of type {}", found, name, expected), span);
+            }
+            let scope = self.assigned_scope(name);
+            scope.vars.insert(name.to_string(), expected);
+            scope.annotated.insert(name.to_string());
+            return;
+        }
+        let scope = self.assigned_scope(name);
+        let Some(current) = scope.vars.get(name).cloned() else {
+            scope.vars.insert(name.to_string(), found);
+            return;
+        };
+        if found.fits(&current) {
+            return;
+        }
+        if scope.annotated.contains(name) {
+            self.error("E0203", format!("cannot assign {} to `{}` of type {}", found, name, current), span);
+            return;
+        }
+        if current == Type::Int && found == Type::Float {
+            scope.vars.insert(name.to_string(), Type::Float);
+            return;
+        }
+        if !scope.typed {
+            scope.vars.insert(name.to_string(), Type::Any);
+            return;
+        }
+        let message = format!(
+            "`{}` was inferred as {} but is assigned {} here; annotate it with `: any` to allow both",
+            name, current, found
+        );
+        self.error("E0202", message, span);
+    }
+
+    fn statement(&mut self, statement: &Stmt) {
+        match &statement.kind {
+            StmtKind::Assign { name, ty, value, declaration } => {
+                let found = self.expression(value);
+                if *declaration {
+                    self.declare(name);
+                }
+                self.assign(name, ty.as_ref(), found, value.span);
+            },
+            StmtKind::SetIndex { target, index, value } => {
+                let target_type = self.expression(target);
+                self.index(&target_type, index);
+                self.expression(value);
+            },
+            StmtKind::SetField { target, value, .. } => {
+                self.expression(target);
+                self.expression(value);
+            },
+            StmtKind::If { condition, then_branch, else_branch } => {
+                self.condition(condition);
+                for statement in then_branch.iter().chain(else_branch.iter().flatten()) {
+                    self.statement(statement);
+                }
+            },
+            StmtKind::While { condition, body } => {
+                self.condition(condition);
+                for statement in body {
+                    self.statement(statement);
+                }
+            },
+            StmtKind::Print(args) => {
+                for arg in args {
+                    self.expression(arg);
+                }
+            },
+            StmtKind::Function(decl) => {
+                self.function(decl);
+            },
+            StmtKind::Return(value) => {
+                let found = value.as_ref().map_or(Type::Nil, |value| self.expression(value));
+                let Some(returns) = self.return_types.last_mut() else {
+                    return;
+                };
+                returns.found.push(found.clone());
+                if let Some(expected) = returns.annotated.clone().filter(|expected| !found.fits(expected)) {
+                    let span = value.as_ref().map_or(statement.span, |value| value.span);
+                    self.error("E0203", format!("function must return {}, found {}", expected, found), span);
+                }
+            },
+            StmtKind::Global(names) => {
+                if self.scopes.len() > 1 {
+                    self.scopes.last_mut().unwrap().globals.extend(names.iter().cloned());
+                }
+            },
+            StmtKind::Expr(expr) => {
+                self.expression(expr);
+            },
+            StmtKind::Struct(_)
+            | StmtKind::Import(_)
+            | StmtKind::Break
+            | StmtKind::Continue
+            | StmtKind::Goto(_)
+            | StmtKind::Gosub(_)
+            | StmtKind::SubReturn => {},
+        }
+    }
+
+    fn condition(&mut self, condition: &Expr) {
+        let found = self.expression(condition);
+        if self.declared(condition) && !found.fits(&Type::Bool) {
+            self.error("E0203", format!("condition must be bool, found {}", found), condition.span);
+        }
+    }
+
+    /// Checks a function's body and returns its return type.
+    fn function(&mut self, decl: &FunctionDecl) -> Type {
+        let typed = decl.param_types.iter().any(Option::is_some);
+        let mut scope = Scope { typed, ..Scope::default() };
+        for (param, ty) in decl.params.iter().zip(&decl.param_types) {
+            if ty.is_some() {
+                scope.annotated.insert(param.clone());
+            }
+            let ty = self.resolve(ty.as_ref());
+            scope.vars.insert(param.clone(), ty);
+        }
+        let annotated = decl.return_type.as_ref().map(|ty| self.resolve(Some(ty)));
+        self.scopes.push(scope);
+        self.return_types.push(Returns { annotated, found: Vec::new() });
+        for statement in &decl.body {
+            self.statement(statement);
+        }
+        let returns = self.return_types.pop().unwrap();
+        self.scopes.pop();
+        if let Some(annotated) = returns.annotated {
+            return annotated;
+        }
+        let mut found = returns.found.iter();
+        let Some(first) = found.next() else {
+            return Type::Nil;
+        };
+        let mut ret = first.clone();
+        for ty in found {
+            match ret.join(ty) {
+                Some(joined) => ret = joined,
+                None if !typed => return Type::Any,
+                None => {
+                    let message = format!(
+                        "`{}` returns both {} and {}; annotate its return type with `: any` to allow both",
+                        decl.name, ret, ty
+                    );
+                    self.error("E0202", message, decl.span);
+                    return Type::Any;
+                },
+            }
+        }
+        ret
+    }
+
+    fn index(&mut self, target: &Type, index: &Expr) {
+        let index_type = self.expression(index);
+        let expected = match target {
+            Type::Array => Type::Int,
+            Type::Map => Type::Str,
+            _ => return,
+        };
+        if !index_type.fits(&expected) {
+            self.error("E0203", format!("{} index must be {}, found {}", target, expected, index_type), index.span);
+        }
+    }
+
+    fn expression(&mut self, expr: &Expr) -> Type {
+        match &expr.kind {
+            ExprKind::Number(_) => Type::Int,
+            ExprKind::Float(_) => Type::Float,
+            ExprKind::Str(_) => Type::Str,
+            ExprKind::Variable(name) => match self.variable_type(name) {
+                Some(ty) => ty.clone(),
+                None if self.signatures.contains_key(name) || self.structs.contains_key(name) => Type::Function,
+                None => Type::Any,
+            },
+            ExprKind::Array(items) => {
+                for item in items {
+                    self.expression(item);
+                }
+                Type::Array
+            },
+            ExprKind::Map(entries) => {
+                for (key, value) in entries {
+                    let found = self.expression(key);
+                    if !found.fits(&Type::Str) {
+                        self.error("E0203", format!("map key must be string, found {}", found), key.span);
+                    }
+                    self.expression(value);
+                }
+                Type::Map
+            },
+            ExprKind::Index { target, index } => {
+                let target_type = self.expression(target);
+                self.index(&target_type, index);
+                Type::Any
+            },
+            ExprKind::Field { target, .. } => {
+                self.expression(target);
+                Type::Any
+            },
+            ExprKind::Unary { op: UnaryOp::Not, operand } => {
+                self.expression(operand);
+                Type::Bool
+            },
+            ExprKind::Unary { op: UnaryOp::Neg, operand } => {
+                let found = self.expression(operand);
+                if found.is_numeric() || found == Type::Any {
+                    found
+                } else {
+                    self.error("E0203", format!("cannot negate {}", found), expr.span);
+                    Type::Any
+                }
+            },
+            ExprKind::Binary { op, left, right } => {
+                let left = self.expression(left);
+                let right = self.expression(right);
+                self.binary(*op, left, right, expr.span)
+            },
+            ExprKind::Call { callee, args } => self.call(callee, args, expr.span),
+            ExprKind::Function(decl) => {
+                self.function(decl);
+                Type::Function
+            },
+        }
+    }
+
+    fn binary(&mut self, op: BinaryOp, left: Type, right: Type, span: Span) -> Type {
+        use Type::*;
+
+        match (op, &left, &right) {
+            (BinaryOp::And | BinaryOp::Or | BinaryOp::Equals | BinaryOp::NotEquals, _, _) => Bool,
+            (BinaryOp::SmallerThan | BinaryOp::GreaterThan | BinaryOp::SmallerEquals | BinaryOp::GreaterEquals, l, r) => {
+                let comparable = *l == Any || *r == Any || (l.is_numeric() && r.is_numeric()) || (*l == Str && *r == Str);
+                if !comparable {
+                    self.error("E0203", format!("cannot compare {} with {}", l, r), span);
+                }
+                Bool
+            },
+            (BinaryOp::Add, Str, Str | Any) | (BinaryOp::Add, Any, Str) => Str,
+            (_, Int, Int) => Int,
+            (_, l, r) if l.is_numeric() && r.is_numeric() => Float,
+            (_, Any, _) | (_, _, Any) => Any,
+            (_, l, r) => {
+                let message = match op {
+                    BinaryOp::Add => format!("cannot add {} to {}", r, l),
+                    BinaryOp::Sub => format!("cannot subtract {} from {}", r, l),
+                    BinaryOp::Mul => format!("cannot multiply {} by {}", l, r),
+                    _ => format!("cannot divide {} by {}", l, r),
+                };
+                self.error("E0203", message, span);
+                Any
+            },
+        }
+    }
+
+    fn call(&mut self, callee: &Expr, args: &[Expr], span: Span) -> Type {
+        self.expression(callee);
+        let found: Vec<Type> = args.iter().map(|arg| self.expression(arg)).collect();
+        let ExprKind::Variable(name) = &callee.kind else {
+            return Type::Any;
+        };
+        if self.variable_type(name).is_some() {
+            return Type::Any;
+        }
+        if let Some(&fields) = self.structs.get(name) {
+            self.arity(name, &Arity::exactly(fields), args.len(), span);
+            return Type::Struct(name.clone());
+        }
+        let Some(signature) = self.signatures.get(name) else {
+            if let Some(builtin) = builtins::lookup(name) {
+                self.arity(name, &builtin.arity, args.len(), span);
+            }
+            return builtin_type(name);
+        };
+        let ret = signature.ret.clone();
+        if let Some(params) = signature.arity {
+            self.arity(name, &Arity::exactly(params), args.len(), span);
+        }
+        let signature = &self.signatures[name];
+        let mismatches: Vec<_> = signature
+            .params
+            .iter()
+            .zip(&found)
+            .enumerate()
+            .filter(|(_, (expected, found))| !found.fits(expected))
+            .map(|(i, (expected, found))| {
+                (format!("argument {} of `{}` must be {}, found {}", i + 1, name, expected, found), args[i].span)
+            })
+            .collect();
+        for (message, span) in mismatches {
+            self.error("E0203", message, span);
+        }
+        ret
+    }
+
+    /// Reports calling `name`, which takes `arity` arguments, with `found`.
+    fn arity(&mut self, name: &str, arity: &Arity, found: usize, span: Span) {
+        if !arity.accepts(found) {
+            let plural = if arity.min == 1 && arity.max.is_none_or(|max| max == 1) { "" } else { "s" };
+            let verb = if found == 1 { "was" } else { "were" };
+            let message = format!("`{}` expects {} argument{}, but {} {} given", name, arity, plural, found, verb);
+            self.error("E0204", message, span);
+        }
+    }
+}
+
+/// What the builtin `name` returns, where that doesn't depend on its
+/// arguments.
+fn builtin_type(name: &str) -> Type {
+    match name {
+        "len" | "floor" | "ceil" | "round" | "random" | "clock_ms" => Type::Int,
+        "sqrt" | "rnd" | "now" => Type::Float,
+        "substr" | "upper" | "lower" | "trim" | "replace" | "format" | "read_file" => Type::Str,
+        "split" | "keys" | "values" | "args" => Type::Array,
+        "contains" | "has" | "file_exists" => Type::Bool,
+        _ => Type::Any,
+    }
+}
+
+/// Calls `visit` on every statement in `statements` and the blocks nested in
+/// them, without descending into function bodies' expressions.
+fn collect<'a>(statements: &'a [Stmt], visit: &mut impl FnMut(&'a Stmt)) {
+    for statement in statements {
+        visit(statement);
+        match &statement.kind {
+            StmtKind::If { then_branch, else_branch, .. } => {
+                collect(then_branch, visit);
+                collect(else_branch.as_deref().unwrap_or_default(), visit);
+            },
+            StmtKind::While { body, .. } => collect(body, visit),
+            StmtKind::Function(decl) => collect(&decl.body, visit),
+            _ => {},
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::parser::parse;
+    use crate::typecheck::typecheck;
+
+    fn errors(source: &str) -> Vec<String> {
+        typecheck(&parse(source).unwrap()).iter().map(ToString::to_string).collect()
+    }
+
+    #[test]
+    fn test_literals() {
+        assert_eq!(errors("x = 1 + \"a\""), vec!["1:5: error: cannot add string to int"]);
+        // Conditions are only checked against annotations
+        assert!(errors("while 1 {\n}\nif len(\"s\") {\n}\nif 1 + 2 {\n}").is_empty());
+        assert_eq!(errors("fn f(n: int) {\n  if n {\n  }\n}"), vec!["2:6: error: condition must be bool, found int"]);
+        assert_eq!(errors("fn g(): string {\n  return \"\"\n}\nwhile g() {\n}"), vec!["4:7: error: condition must be bool, found string"]);
+        assert_eq!(errors("while \"x\" < 2 {\n}"), vec!["1:7: error: cannot compare string with int"]);
+        assert_eq!(errors("x = -\"a\" * [1]"), vec!["1:5: error: cannot negate string"]);
+        assert_eq!(errors("m = {1: 2}\nm[\"a\"] = [1][\"b\"]"), vec![
+            "1:6: error: map key must be string, found int",
+            "2:14: error: array index must be int, found string",
+        ]);
+        assert!(errors("x = 1 + 2.5 * 3\ny = \"a\" + \"b\"\nif x > 1 && y == \"ab\" {\n}").is_empty());
+        // Without annotations variables could hold anything
+        assert!(errors("x = 1\nx = \"a\"\ny = x + 1").is_empty());
+    }
+
+    #[test]
+    fn test_inference() {
+        assert_eq!(errors("fn f(n: int) {\n  x = n\n  x = \"a\"\n}"), vec![
+            "3:7: error: `x` was inferred as int but is assigned string here; annotate it with `: any` to allow both"
+        ]);
+        assert!(errors("fn f(n: int) {\n  x: any = n\n  x = \"a\"\n  x = 2.5\n  m = 1\n  m = m + 0.5\n}").is_empty());
+        // `let` starts a new variable
+        assert_eq!(errors("x = 1\nlet x = \"a\"\nx = x - 1"), vec!["3:5: error: cannot subtract int from string"]);
+        assert_eq!(errors("name = upper(\"a\")\nprint name - len(name)"), vec!["2:7: error: cannot subtract int from string"]);
+
+        // Functions see globals but assigning inside them makes a local
+        let source = "total = 0\nfn f() {\n  total = \"x\"\n  return total + 1\n}\nfn g(n: int) {\n  global total\n  total = n\n  total = total - \"y\"\n}";
+        assert_eq!(errors(source), vec![
+            "4:10: error: cannot add int to string",
+            "9:11: error: cannot subtract string from int",
+        ]);
+
+        let source = "x = twice(2) + \"a\"\nfn twice(n) {\n  return double(n)\n}\nfn double(n) {\n  return 2 * 2\n}";
+        assert_eq!(errors(source), vec!["1:5: error: cannot add string to int"]);
+        let source = "fn f(n: int) {\n  if n > 0 {\n    return 1\n  }\n  return \"none\"\n}";
+        assert_eq!(errors(source), vec![
+            "1:1: error: `f` returns both int and string; annotate its return type with `: any` to allow both"
+        ]);
+        assert!(errors("fn f(n) {\n  if n > 0 {\n    return 1\n  }\n  return \"none\"\n}\nx = f(1) + 1").is_empty());
+        assert!(errors("fn f(n) {\n  if n > 0 {\n    return 1\n  }\n  return n\n}\nfn g(n: int): any {\n  return \"a\"\n  return 1\n}").is_empty());
+    }
+
+    #[test]
+    fn test_annotations() {
+        assert_eq!(errors("x: int = \"a\""), vec!["1:10: error: cannot assign string to `x` of type int"]);
+        assert_eq!(errors("x: float = 1\nx = [1]"), vec!["2:5: error: cannot assign array to `x` of type float"]);
+        assert_eq!(errors("x: string = \"a\"\ny = x - 1"), vec!["2:5: error: cannot subtract int from string"]);
+        assert_eq!(errors("x: number = 1"), vec!["1:4: error: unknown type `number`"]);
+
+        let source = "fn half(n: int): float {\n  if n == 0 {\n    return \"none\"\n  }\n  return n / 2\n}\nx = half(\"4\") + 1";
+        assert_eq!(errors(source), vec![
+            "3:12: error: function must return float, found string",
+            "7:10: error: argument 1 of `half` must be int, found string",
+        ]);
+        assert_eq!(errors("fn f(): bool {\n  return\n}\nx = f() * 2"), vec![
+            "2:3: error: function must return bool, found nil",
+            "4:5: error: cannot multiply bool by int",
+        ]);
+
+        let source = "p: Point = Point(1, 2)\nstruct Point { x, y }\nq: Point = 5";
+        assert_eq!(errors(source), vec!["3:12: error: cannot assign int to `q` of type Point"]);
+    }
+
+    #[test]
+    fn test_arity() {
+        let source = "fn add(a, b) {\n  return a + b\n}\nprint add(1)\nstruct P { x }\np = P(1, 2)";
+        assert_eq!(errors(source), vec![
+            "4:7: error: `add` expects 2 arguments, but 1 was given",
+            "6:5: error: `P` expects 1 argument, but 2 were given",
+        ]);
+        assert_eq!(errors("print len(\"a\", 2)\nprint substr(\"abc\")\nprint format()"), vec![
+            "1:7: error: `len` expects 1 argument, but 2 were given",
+            "2:7: error: `substr` expects 2 to 3 arguments, but 1 was given",
+            "3:7: error: `format` expects at least 1 argument, but 0 were given",
+        ]);
+        assert!(errors("print min(1, 2, 3)\nprint input()\nsubstr(\"abc\", 1, 1)").is_empty());
+
+        // Unless the name may refer to something else
+        assert!(errors("len = fn(a, b) { return a }\nprint len(1, 2)\nfn f(n) {\n  return n(1, 2)\n}").is_empty());
+        assert!(errors("if input() {\n  fn f(a) {}\n} else {\n  fn f(a, b) {}\n}\nf(1)").is_empty());
+    }
+}
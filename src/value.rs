@@ -0,0 +1,590 @@
+use alloc::rc::Rc;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::cell::RefCell;
+use core::fmt;
+
+use num_bigint::BigInt;
+
+use crate::ast::{FunctionDecl, StructDecl};
+use crate::builtins::{Builtin, HostFunction};
+use crate::collections::{HashMap, IndexMap};
+use crate::compiler::CompiledFunction;
+use crate::register::RegisterFunction;
+use crate::interpreter::{Environment, RuntimeError};
+use crate::lexer::Span;
+
+/// A script value. Arrays, maps and structs may end up holding themselves;
+/// comparing, printing and debug-formatting such values stops where the
+/// cycle closes.
+///
+/// Values are reference counted, and nothing collects cycles: an array,
+/// map or struct holding itself, directly or through other values, stays
+/// allocated after the script drops it, and so does a closure stored in a
+/// variable of the call that created it. Every run of a script building
+/// cycles leaks them, so a host running untrusted scripts in a long-lived
+/// process should expect that memory to be lost for good.
+#[derive(Clone, Default)]
+pub enum Value {
+    #[default]
+    Nil,
+    Int(i64),
+    /// An int outside the range of `i64`, produced by
+    /// [`OverflowMode::Promote`](crate::interpreter::OverflowMode::Promote).
+    /// Scripts see it as an ordinary int.
+    BigInt(Rc<BigInt>),
+    Float(f64),
+    Bool(bool),
+    /// Strings are immutable, so copies share the text.
+    Str(Rc<str>),
+    /// Arrays are shared: copies of an array value refer to the same elements.
+    Array(Rc<RefCell<Vec<Value>>>),
+    /// String-keyed and shared like arrays. Keys keep their insertion order;
+    /// two maps are equal if they hold equal values under the same keys.
+    Map(Rc<RefCell<IndexMap<String, Value>>>),
+    /// A struct instance, shared like arrays.
+    Struct(Rc<RefCell<Instance>>),
+    /// A struct's constructor.
+    StructType(Rc<StructDecl>),
+    Builtin(Builtin),
+    Host(Rc<HostFunction>),
+    Function(Rc<Function>),
+    Module(Rc<Module>),
+}
+
+/// A function defined by the script.
+///
+/// Functions defined inside another function capture that call's variables
+/// by reference: they see later updates made by the enclosing function, but
+/// assigning to a captured name inside the closure creates a local of the
+/// closure instead of changing the captured variable.
+#[derive(Debug)]
+pub struct Function {
+    pub decl: Rc<FunctionDecl>,
+    /// Variables of the enclosing call, or `None` for top-level functions.
+    pub captured: Option<Rc<RefCell<Environment>>>,
+    /// The bytecode calls run, for functions created by a VM. Others run
+    /// `decl`'s body.
+    pub code: Option<Bytecode>,
+}
+
+/// The compiled code of a [`Function`], for the VM that created it.
+#[derive(Debug, Clone)]
+pub enum Bytecode {
+    /// Runs on the [`vm`](crate::vm).
+    Stack(Rc<CompiledFunction>),
+    /// Runs on the [`register`](crate::register) VM.
+    Register(Rc<RegisterFunction>),
+}
+
+/// A value of a script-defined struct.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Instance {
+    pub decl: Rc<StructDecl>,
+    /// One value per field, in declaration order.
+    pub fields: Vec<Value>,
+}
+
+impl Instance {
+    pub fn field_index(&self, field: &str) -> Option<usize> {
+        self.decl.fields.iter().position(|name| name == field)
+    }
+}
+
+/// The namespace of an imported module.
+#[derive(Debug)]
+pub struct Module {
+    pub name: String,
+    /// Variables defined at the module's top level.
+    pub namespace: Rc<RefCell<Environment>>,
+}
+
+impl PartialEq for Module {
+    /// A module is only equal to itself; importing it again yields the same
+    /// module.
+    fn eq(&self, other: &Self) -> bool {
+        core::ptr::eq(self, other)
+    }
+}
+
+impl PartialEq for Function {
+    /// Functions are only equal to themselves.
+    fn eq(&self, other: &Self) -> bool {
+        core::ptr::eq(self, other)
+    }
+}
+
+impl Value {
+    pub fn array(values: Vec<Value>) -> Value {
+        Value::Array(Rc::new(RefCell::new(values)))
+    }
+
+    pub fn map(entries: IndexMap<String, Value>) -> Value {
+        Value::Map(Rc::new(RefCell::new(entries)))
+    }
+
+    /// An int value, using `Int` whenever `n` fits in an `i64`.
+    pub fn from_big(n: BigInt) -> Value {
+        match i64::try_from(&n) {
+            Ok(n) => Value::Int(n),
+            Err(_) => Value::BigInt(Rc::new(n)),
+        }
+    }
+
+    pub fn is_truthy(&self) -> bool {
+        match self {
+            Value::Nil => false,
+            Value::Int(n) => *n != 0,
+            Value::BigInt(_) => true,
+            Value::Float(n) => *n != 0.0,
+            Value::Bool(b) => *b,
+            Value::Str(s) => !s.is_empty(),
+            Value::Array(values) => !values.borrow().is_empty(),
+            Value::Map(entries) => !entries.borrow().is_empty(),
+            Value::Struct(_) | Value::StructType(_) | Value::Module(_) => true,
+            Value::Builtin(_) | Value::Host(_) | Value::Function(_) => true,
+        }
+    }
+
+    /// Name of the value's type as used in error messages.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Nil => "nil",
+            Value::Int(_) | Value::BigInt(_) => "int",
+            Value::Float(_) => "float",
+            Value::Bool(_) => "bool",
+            Value::Str(_) => "string",
+            Value::Array(_) => "array",
+            Value::Map(_) => "map",
+            Value::Struct(_) => "struct",
+            Value::StructType(_) => "function",
+            Value::Module(_) => "module",
+            Value::Builtin(_) | Value::Host(_) | Value::Function(_) => "function",
+        }
+    }
+}
+
+/// Where an `Rc` points, to recognize a container met again.
+fn address<T: ?Sized>(rc: &Rc<T>) -> *const () {
+    Rc::as_ptr(rc) as *const ()
+}
+
+/// Runs `walk` inside the container `key`, which `seen` holds while it
+/// runs, or returns `None` if the walk is inside it already.
+fn visit<K: PartialEq, R>(seen: &mut Vec<K>, key: K, walk: impl FnOnce(&mut Vec<K>) -> R) -> Option<R> {
+    if seen.contains(&key) {
+        return None;
+    }
+    seen.push(key);
+    let result = walk(seen);
+    seen.pop();
+    Some(result)
+}
+
+impl PartialEq for Value {
+    fn eq(&self, other: &Value) -> bool {
+        self.equals(other, &mut Vec::new())
+    }
+}
+
+impl Value {
+    /// Compares like `==`, where `seen` holds the pairs of containers being
+    /// compared. Meeting a pair again means nothing found them different.
+    fn equals(&self, other: &Value, seen: &mut Vec<(*const (), *const ())>) -> bool {
+        match (self, other) {
+            (Value::Nil, Value::Nil) => true,
+            (Value::Int(a), Value::Int(b)) => a == b,
+            (Value::BigInt(a), Value::BigInt(b)) => a == b,
+            (Value::Float(a), Value::Float(b)) => a == b,
+            (Value::Bool(a), Value::Bool(b)) => a == b,
+            (Value::Str(a), Value::Str(b)) => a == b,
+            (Value::Array(a), Value::Array(b)) => visit(seen, (address(a), address(b)), |seen| {
+                let (a, b) = (a.borrow(), b.borrow());
+                a.len() == b.len() && a.iter().zip(b.iter()).all(|(a, b)| a.equals(b, seen))
+            })
+            .unwrap_or(true),
+            (Value::Map(a), Value::Map(b)) => visit(seen, (address(a), address(b)), |seen| {
+                let (a, b) = (a.borrow(), b.borrow());
+                a.len() == b.len() && a.iter().all(|(key, a)| b.get(key).is_some_and(|b| a.equals(b, seen)))
+            })
+            .unwrap_or(true),
+            (Value::Struct(a), Value::Struct(b)) => visit(seen, (address(a), address(b)), |seen| {
+                let (a, b) = (a.borrow(), b.borrow());
+                a.decl == b.decl && a.fields.iter().zip(&b.fields).all(|(a, b)| a.equals(b, seen))
+            })
+            .unwrap_or(true),
+            (Value::StructType(a), Value::StructType(b)) => a == b,
+            (Value::Builtin(a), Value::Builtin(b)) => a == b,
+            (Value::Host(a), Value::Host(b)) => a == b,
+            (Value::Function(a), Value::Function(b)) => a == b,
+            (Value::Module(a), Value::Module(b)) => a == b,
+            _ => false,
+        }
+    }
+
+    /// Formats the value for `print`, where `seen` holds the containers
+    /// being formatted. One met again shows as `[...]`, `{...}` or
+    /// `Name {...}`.
+    fn display(&self, f: &mut fmt::Formatter<'_>, seen: &mut Vec<*const ()>) -> fmt::Result {
+        match self {
+            Value::Array(values) => visit(seen, address(values), |seen| {
+                write!(f, "[")?;
+                for (i, value) in values.borrow().iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    value.display_nested(f, seen)?;
+                }
+                write!(f, "]")
+            })
+            .unwrap_or_else(|| write!(f, "[...]")),
+            Value::Map(entries) => visit(seen, address(entries), |seen| {
+                write!(f, "{{")?;
+                for (i, (key, value)) in entries.borrow().iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{:?}: ", key)?;
+                    value.display_nested(f, seen)?;
+                }
+                write!(f, "}}")
+            })
+            .unwrap_or_else(|| write!(f, "{{...}}")),
+            Value::Struct(instance) => visit(seen, address(instance), |seen| {
+                let instance = instance.borrow();
+                write!(f, "{} {{", instance.decl.name)?;
+                for (i, (name, value)) in instance.decl.fields.iter().zip(&instance.fields).enumerate() {
+                    write!(f, "{}{}: ", if i > 0 { ", " } else { " " }, name)?;
+                    value.display_nested(f, seen)?;
+                }
+                write!(f, "{}}}", if instance.fields.is_empty() { "" } else { " " })
+            })
+            .unwrap_or_else(|| write!(f, "{} {{...}}", instance.borrow().decl.name)),
+            Value::Nil => write!(f, "nil"),
+            Value::Int(n) => write!(f, "{}", n),
+            Value::BigInt(n) => write!(f, "{}", n),
+            Value::Float(n) => write!(f, "{:?}", n),
+            Value::Bool(b) => write!(f, "{}", b),
+            Value::Str(s) => write!(f, "{}", s),
+            Value::StructType(decl) => write!(f, "<struct {}>", decl.name),
+            Value::Builtin(builtin) => write!(f, "<builtin {}>", builtin.name),
+            Value::Host(host) => write!(f, "<builtin {}>", host.name),
+            Value::Function(function) => write!(f, "<fn {}>", function.decl.name),
+            Value::Module(module) => write!(f, "<module {}>", module.name),
+        }
+    }
+
+    /// Formats an element of an array or map, quoting strings.
+    fn display_nested(&self, f: &mut fmt::Formatter<'_>, seen: &mut Vec<*const ()>) -> fmt::Result {
+        match self {
+            Value::Str(s) => write!(f, "{:?}", s),
+            other => other.display(f, seen),
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.display(f, &mut Vec::new())
+    }
+}
+
+impl fmt::Debug for Value {
+    /// Functions, modules and struct types show by name only, since a
+    /// closure's captured variables may hold the closure.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        Traced { value: self, seen: &RefCell::new(Vec::new()) }.fmt(f)
+    }
+}
+
+/// A value being debug-formatted, with the containers it is inside of.
+struct Traced<'a> {
+    value: &'a Value,
+    seen: &'a RefCell<Vec<*const ()>>,
+}
+
+impl Traced<'_> {
+    fn nested<'a>(&'a self, value: &'a Value) -> Traced<'a> {
+        Traced { value, seen: self.seen }
+    }
+
+    /// Writes `name(...)` for the container `key`, with `walk` writing
+    /// what is inside unless the container is being formatted already.
+    fn container(&self, f: &mut fmt::Formatter<'_>, name: &str, key: *const (), walk: impl FnOnce(&mut fmt::Formatter<'_>) -> fmt::Result) -> fmt::Result {
+        write!(f, "{}(", name)?;
+        if self.seen.borrow().contains(&key) {
+            write!(f, "...")?;
+        } else {
+            self.seen.borrow_mut().push(key);
+            let result = walk(f);
+            self.seen.borrow_mut().pop();
+            result?;
+        }
+        write!(f, ")")
+    }
+}
+
+impl fmt::Debug for Traced<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.value {
+            Value::Nil => write!(f, "Nil"),
+            Value::Int(n) => f.debug_tuple("Int").field(n).finish(),
+            Value::BigInt(n) => f.debug_tuple("BigInt").field(n).finish(),
+            Value::Float(n) => f.debug_tuple("Float").field(n).finish(),
+            Value::Bool(b) => f.debug_tuple("Bool").field(b).finish(),
+            Value::Str(s) => f.debug_tuple("Str").field(s).finish(),
+            Value::Array(values) => self.container(f, "Array", address(values), |f| {
+                f.debug_list().entries(values.borrow().iter().map(|value| self.nested(value))).finish()
+            }),
+            Value::Map(entries) => self.container(f, "Map", address(entries), |f| {
+                f.debug_map().entries(entries.borrow().iter().map(|(key, value)| (key, self.nested(value)))).finish()
+            }),
+            Value::Struct(instance) => self.container(f, "Struct", address(instance), |f| {
+                let instance = instance.borrow();
+                let mut fields = f.debug_struct(&instance.decl.name);
+                for (name, value) in instance.decl.fields.iter().zip(&instance.fields) {
+                    fields.field(name, &self.nested(value));
+                }
+                fields.finish()
+            }),
+            Value::StructType(decl) => f.debug_tuple("StructType").field(&decl.name).finish(),
+            Value::Builtin(builtin) => f.debug_tuple("Builtin").field(&builtin.name).finish(),
+            Value::Host(host) => f.debug_tuple("Host").field(&host.name).finish(),
+            Value::Function(function) => f.debug_tuple("Function").field(&function.decl.name).finish(),
+            Value::Module(module) => f.debug_tuple("Module").field(&module.name).finish(),
+        }
+    }
+}
+
+/// Returned when a [`Value`] isn't of the Rust type it's converted to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConversionError {
+    pub expected: &'static str,
+    pub found: &'static str,
+}
+
+impl fmt::Display for ConversionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "expected {}, found {}", self.expected, self.found)
+    }
+}
+
+impl core::error::Error for ConversionError {}
+
+impl From<ConversionError> for RuntimeError {
+    /// Lets host functions use `?` on conversions; the interpreter reports
+    /// the error at the call site.
+    fn from(error: ConversionError) -> RuntimeError {
+        RuntimeError::TypeMismatch { message: error.to_string(), span: Span::default() }
+    }
+}
+
+impl From<i64> for Value {
+    fn from(n: i64) -> Value {
+        Value::Int(n)
+    }
+}
+
+impl From<i32> for Value {
+    fn from(n: i32) -> Value {
+        Value::Int(n.into())
+    }
+}
+
+impl From<f64> for Value {
+    fn from(n: f64) -> Value {
+        Value::Float(n)
+    }
+}
+
+impl From<bool> for Value {
+    fn from(b: bool) -> Value {
+        Value::Bool(b)
+    }
+}
+
+impl From<String> for Value {
+    fn from(s: String) -> Value {
+        Value::Str(s.into())
+    }
+}
+
+impl From<&str> for Value {
+    fn from(s: &str) -> Value {
+        Value::Str(s.into())
+    }
+}
+
+impl From<()> for Value {
+    fn from(_: ()) -> Value {
+        Value::Nil
+    }
+}
+
+impl<T: Into<Value>> From<Vec<T>> for Value {
+    fn from(values: Vec<T>) -> Value {
+        Value::array(values.into_iter().map(Into::into).collect())
+    }
+}
+
+impl From<HashMap<String, Value>> for Value {
+    /// The map's keys end up sorted, since a `HashMap` has no order of its
+    /// own.
+    fn from(entries: HashMap<String, Value>) -> Value {
+        let mut entries: Vec<_> = entries.into_iter().collect();
+        entries.sort_by(|a, b| a.0.cmp(&b.0));
+        Value::map(entries.into_iter().collect())
+    }
+}
+
+impl From<IndexMap<String, Value>> for Value {
+    fn from(entries: IndexMap<String, Value>) -> Value {
+        Value::map(entries)
+    }
+}
+
+impl<T: Into<Value>> From<Option<T>> for Value {
+    /// `None` becomes `nil`.
+    fn from(value: Option<T>) -> Value {
+        value.map_or(Value::Nil, Into::into)
+    }
+}
+
+fn mismatch(expected: &'static str, value: &Value) -> ConversionError {
+    ConversionError { expected, found: value.type_name() }
+}
+
+impl TryFrom<Value> for i64 {
+    type Error = ConversionError;
+
+    fn try_from(value: Value) -> Result<i64, ConversionError> {
+        match value {
+            Value::Int(n) => Ok(n),
+            other => Err(mismatch("int", &other)),
+        }
+    }
+}
+
+impl TryFrom<Value> for f64 {
+    type Error = ConversionError;
+
+    /// Ints are widened to floats.
+    fn try_from(value: Value) -> Result<f64, ConversionError> {
+        match value {
+            Value::Float(n) => Ok(n),
+            Value::Int(n) => Ok(n as f64),
+            other => Err(mismatch("float", &other)),
+        }
+    }
+}
+
+impl TryFrom<Value> for bool {
+    type Error = ConversionError;
+
+    fn try_from(value: Value) -> Result<bool, ConversionError> {
+        match value {
+            Value::Bool(b) => Ok(b),
+            other => Err(mismatch("bool", &other)),
+        }
+    }
+}
+
+impl TryFrom<Value> for String {
+    type Error = ConversionError;
+
+    fn try_from(value: Value) -> Result<String, ConversionError> {
+        match value {
+            Value::Str(s) => Ok(s.to_string()),
+            other => Err(mismatch("string", &other)),
+        }
+    }
+}
+
+impl TryFrom<Value> for Vec<Value> {
+    type Error = ConversionError;
+
+    /// Copies the elements out; the copy no longer shares them with the
+    /// script.
+    fn try_from(value: Value) -> Result<Vec<Value>, ConversionError> {
+        match value {
+            Value::Array(values) => Ok(values.borrow().clone()),
+            other => Err(mismatch("array", &other)),
+        }
+    }
+}
+
+impl TryFrom<Value> for HashMap<String, Value> {
+    type Error = ConversionError;
+
+    /// Copies the entries out, like the `Vec` conversion.
+    fn try_from(value: Value) -> Result<HashMap<String, Value>, ConversionError> {
+        match value {
+            Value::Map(entries) => Ok(entries.borrow().iter().map(|(k, v)| (k.clone(), v.clone())).collect()),
+            other => Err(mismatch("map", &other)),
+        }
+    }
+}
+
+impl TryFrom<Value> for IndexMap<String, Value> {
+    type Error = ConversionError;
+
+    fn try_from(value: Value) -> Result<IndexMap<String, Value>, ConversionError> {
+        match value {
+            Value::Map(entries) => Ok(entries.borrow().clone()),
+            other => Err(mismatch("map", &other)),
+        }
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod test {
+    use std::collections::HashMap;
+
+    use crate::execute_with;
+    use crate::interpreter::{Interpreter, RuntimeError, SharedBuffer};
+    use crate::sandbox::Sandbox;
+    use crate::value::{ConversionError, Value};
+
+    #[test]
+    fn test_into_value() {
+        assert_eq!(Value::from(3), Value::Int(3));
+        assert_eq!(Value::from(1.5), Value::Float(1.5));
+        assert_eq!(Value::from("hi"), Value::Str("hi".into()));
+        assert_eq!(Value::from(None::<i64>), Value::Nil);
+        assert_eq!(Value::from(vec![1, 2]).to_string(), "[1, 2]");
+        let entries = HashMap::from([("b".to_string(), Value::from("x")), ("a".to_string(), Value::from(1))]);
+        assert_eq!(Value::from(entries).to_string(), "{\"a\": 1, \"b\": \"x\"}");
+    }
+
+    #[test]
+    fn test_from_value() {
+        assert_eq!(i64::try_from(Value::Int(3)), Ok(3));
+        assert_eq!(f64::try_from(Value::Int(3)), Ok(3.0));
+        assert_eq!(String::try_from(Value::from("a")), Ok("a".to_string()));
+        assert_eq!(Vec::<Value>::try_from(Value::from(vec![true])), Ok(vec![Value::Bool(true)]));
+        let entries = HashMap::from([("k".to_string(), Value::Int(1))]);
+        assert_eq!(HashMap::try_from(Value::from(entries.clone())), Ok(entries));
+
+        let error = bool::try_from(Value::Int(1)).unwrap_err();
+        assert_eq!(error, ConversionError { expected: "bool", found: "int" });
+        assert_eq!(RuntimeError::from(error).to_string(), "0:0: type mismatch: expected bool, found int");
+    }
+
+    #[test]
+    fn test_cycles() {
+        for sandbox in [Sandbox::default(), Sandbox::strict()] {
+            let output = SharedBuffer::new();
+            let mut interpreter = Interpreter::with_output(Box::new(output.clone()));
+            interpreter.set_sandbox(sandbox);
+            let mut run = |source: &str| execute_with(source, &mut interpreter).unwrap();
+
+            let array = run("a = [1]\npush(a, a)\nprint a\nprint format(\"{}\", a)\na");
+            assert_eq!(format!("{:?}", array), "Array([Int(1), Array(...)])");
+            let equal = run("b = [1]\npush(b, b)\n[a == a, a == b, a == [1, [1]], a == [1, a]]");
+            assert_eq!(equal.to_string(), "[true, true, false, true]");
+            run("m = {}\nm[\"self\"] = m\nprint m\nprint m == m");
+            run("struct Node { next }\nn = Node(0)\nn.next = [n]\nprint n");
+            assert_eq!(output.contents(), "[1, [...]]\n[1, [...]]\n{\"self\": {...}}\ntrue\nNode { next: [Node {...}] }\n");
+        }
+    }
+}
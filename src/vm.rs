@@ -0,0 +1,571 @@
+use alloc::rc::Rc;
+use alloc::string::ToString;
+use alloc::vec::Vec;
+#[cfg(feature = "std")]
+use std::io::{self, Read, Write};
+
+#[cfg(feature = "std")]
+use crate::bytecode::LoadError;
+use crate::collections::IndexMap;
+use crate::compiler::{Chunk, CompiledFunction, Op};
+#[cfg(feature = "std")]
+use crate::execution::StepResult;
+use crate::interpreter::{field_value, int_binary, map_key, store_field, unary_op, Interpreter, RuntimeError};
+use crate::lexer::Span;
+#[cfg(feature = "std")]
+use crate::snapshot;
+use crate::value::{Bytecode, Function, Value};
+
+/// Runs `chunk`, compiled from a whole program, the way
+/// [`Interpreter::run`] runs the program.
+///
+/// The VM keeps its operands on a stack of its own, but variables, settings,
+/// fuel, debug hooks, builtins and host functions are the interpreter's.
+/// Calls between compiled functions don't recurse on the host stack, so
+/// only [`Interpreter::set_max_call_depth`] limits how deep they go, and
+/// tail calls don't count against it.
+/// Imported modules and functions created by the tree-walker run on the
+/// tree-walker.
+pub fn run(interpreter: &mut Interpreter, chunk: &Chunk) -> Result<Value, RuntimeError> {
+    interpreter.reset();
+    Ok(Vm::new(interpreter, chunk).execute()?.expect("the VM only pauses when stepped"))
+}
+
+/// Calls a compiled function for code outside the VM.
+pub(crate) fn call(
+    interpreter: &mut Interpreter,
+    function: &Function,
+    code: &CompiledFunction,
+    args: Vec<Value>,
+    span: Span,
+) -> Result<Value, RuntimeError> {
+    interpreter.push_frame(function, args, span)?;
+    let result = Vm::new(interpreter, &code.chunk).execute().map(|value| value.expect("the VM only pauses when stepped"));
+    interpreter.pop_frame();
+    result.map_err(|error| error.traced(&function.decl.name, span))
+}
+
+/// A program started on the VM with [`Interpreter::start_compiled`] that
+/// runs a bounded number of steps at a time, like an
+/// [`Execution`](crate::execution::Execution) of the tree-walker. Between
+/// steps its whole state can be [saved](VmExecution::save) and
+/// [loaded](VmExecution::load) again later, in another interpreter or
+/// process, for example as part of a saved game.
+///
+/// Only the program's own code pauses: functions it calls on the
+/// tree-walker, and compiled functions that builtins call back, finish
+/// within the step that calls them. Dropping an unfinished execution
+/// abandons the program.
+#[cfg(feature = "std")]
+pub struct VmExecution<'a> {
+    interpreter: &'a mut Interpreter,
+    chunk: Chunk,
+    state: State,
+    finished: Option<Result<Value, RuntimeError>>,
+}
+
+#[cfg(feature = "std")]
+impl<'a> VmExecution<'a> {
+    pub(crate) fn new(interpreter: &'a mut Interpreter, chunk: Chunk) -> VmExecution<'a> {
+        interpreter.reset();
+        VmExecution { interpreter, chunk, state: State::default(), finished: None }
+    }
+
+    /// Continues a program from a snapshot [`save`](VmExecution::save)
+    /// wrote. The program's variables replace the interpreter's globals;
+    /// its settings, output and registered host functions stay, and host
+    /// functions the program refers to must be registered under the same
+    /// names beforehand.
+    pub fn load(interpreter: &'a mut Interpreter, reader: impl Read) -> Result<VmExecution<'a>, LoadError> {
+        let (chunk, state) = snapshot::load(interpreter, reader)?;
+        Ok(VmExecution { interpreter, chunk, state, finished: None })
+    }
+
+    /// Runs the program for a single step.
+    pub fn step(&mut self) -> Result<StepResult, RuntimeError> {
+        self.run_for(1)
+    }
+
+    /// Runs the program for up to `steps` steps. Once it has finished, every
+    /// call returns the same outcome again.
+    pub fn run_for(&mut self, steps: u64) -> Result<StepResult, RuntimeError> {
+        if self.finished.is_none() && steps > 0 {
+            let state = core::mem::take(&mut self.state);
+            let mut vm = Vm { interpreter: &mut *self.interpreter, main: &self.chunk, state, steps: Some(steps) };
+            match vm.execute() {
+                Ok(None) => self.state = vm.state,
+                Ok(Some(value)) => self.finished = Some(Ok(value)),
+                Err(error) => self.finished = Some(Err(error)),
+            }
+        }
+        match &self.finished {
+            Some(Ok(value)) => Ok(StepResult::Finished(value.clone())),
+            Some(Err(error)) => Err(error.clone()),
+            None => Ok(StepResult::Paused),
+        }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.finished.is_some()
+    }
+
+    /// Writes everything needed to resume the program: its bytecode, the
+    /// variables, the operand stack, the calls in progress, where it
+    /// paused and the random number generator's state. See
+    /// [`snapshot`] for the format.
+    ///
+    /// Fails with [`io::ErrorKind::InvalidInput`] if the program has
+    /// finished or can reach values that can't be saved: modules, and
+    /// functions not created by this program's bytecode.
+    pub fn save(&self, writer: impl Write) -> io::Result<()> {
+        if self.finished.is_some() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "the program has finished"));
+        }
+        snapshot::save(self.interpreter, &self.chunk, &self.state, writer)
+    }
+}
+
+#[cfg(feature = "std")]
+impl Drop for VmExecution<'_> {
+    fn drop(&mut self) {
+        if self.finished.is_none() {
+            self.interpreter.reset();
+        }
+    }
+}
+
+fn underflow(span: Span) -> RuntimeError {
+    RuntimeError::InvalidBytecode { message: "operand stack underflow".to_string(), span }
+}
+
+/// A call of a compiled function the VM is running.
+pub(crate) struct CallFrame {
+    pub(crate) function: Rc<CompiledFunction>,
+    /// The caller's code, `None` for the chunk the VM started with.
+    pub(crate) caller: Option<Rc<CompiledFunction>>,
+    /// Where the caller continues.
+    pub(crate) return_to: usize,
+    /// Height of the operand stack below the call.
+    pub(crate) base: usize,
+    pub(crate) call_site: Span,
+}
+
+/// Where a run of the VM is, between two instructions.
+#[derive(Default)]
+pub(crate) struct State {
+    pub(crate) stack: Vec<Value>,
+    pub(crate) calls: Vec<CallFrame>,
+    /// The running function's code, `None` for the chunk the VM started
+    /// with.
+    pub(crate) current: Option<Rc<CompiledFunction>>,
+    /// The next instruction of `current`.
+    pub(crate) pc: usize,
+    /// What the program returns if it ends after the running statement.
+    pub(crate) result: Value,
+}
+
+struct Vm<'a> {
+    interpreter: &'a mut Interpreter,
+    /// The code the VM started with.
+    main: &'a Chunk,
+    state: State,
+    /// How many more steps the VM takes before pausing, if it pauses.
+    steps: Option<u64>,
+}
+
+impl<'a> Vm<'a> {
+    fn new(interpreter: &'a mut Interpreter, main: &'a Chunk) -> Vm<'a> {
+        Vm { interpreter, main, state: State::default(), steps: None }
+    }
+
+    /// Runs until `main` returns or ends, unwinding the calls in progress if
+    /// an error is raised. Returns `None` if the VM paused instead.
+    fn execute(&mut self) -> Result<Option<Value>, RuntimeError> {
+        self.dispatch().map_err(|mut error| {
+            while let Some(call) = self.state.calls.pop() {
+                self.interpreter.pop_frame();
+                error = error.traced(&call.function.decl.name, call.call_site);
+            }
+            error
+        })
+    }
+
+    fn take_step(&mut self) {
+        if let Some(steps) = &mut self.steps {
+            *steps -= 1;
+        }
+    }
+
+    fn pop(&mut self, span: Span) -> Result<Value, RuntimeError> {
+        self.state.stack.pop().ok_or_else(|| underflow(span))
+    }
+
+    /// Pops the top `n` values, deepest first.
+    fn pop_n(&mut self, n: u32, span: Span) -> Result<Vec<Value>, RuntimeError> {
+        let at = self.state.stack.len().checked_sub(n as usize).ok_or_else(|| underflow(span))?;
+        Ok(self.state.stack.split_off(at))
+    }
+
+    fn dispatch(&mut self) -> Result<Option<Value>, RuntimeError> {
+        let main = self.main;
+        let mut current = self.state.current.take();
+        let mut pc = self.state.pc;
+        let mut result = core::mem::take(&mut self.state.result);
+        loop {
+            let chunk = match &current {
+                Some(function) => &function.chunk,
+                None => main,
+            };
+            let Some(&op) = chunk.code.get(pc) else {
+                return Ok(Some(result));
+            };
+            if self.steps == Some(0) && matches!(op, Op::Statement | Op::Iteration) {
+                self.state.current = current;
+                self.state.pc = pc;
+                self.state.result = result;
+                return Ok(None);
+            }
+            let span = chunk.spans[pc];
+            pc += 1;
+            if let Some(profile) = &mut self.interpreter.profile {
+                profile.record(op.name(), span.line);
+            }
+            match op {
+                Op::Constant(i) => self.state.stack.push(chunk.constants[i as usize].clone()),
+                Op::Nil => self.state.stack.push(Value::Nil),
+                Op::True => self.state.stack.push(Value::Bool(true)),
+                Op::False => self.state.stack.push(Value::Bool(false)),
+                Op::Pop => {
+                    self.pop(span)?;
+                },
+                Op::Load(i) => {
+                    let name = &chunk.names[i as usize];
+                    let value = self
+                        .interpreter
+                        .lookup(name)
+                        .ok_or_else(|| RuntimeError::UndefinedVariable { name: name.clone(), span })?;
+                    self.state.stack.push(value);
+                },
+                Op::LoadFunction(i) => {
+                    let name = &chunk.names[i as usize];
+                    let value = self
+                        .interpreter
+                        .lookup(name)
+                        .ok_or_else(|| RuntimeError::UndefinedFunction { name: name.clone(), span })?;
+                    self.state.stack.push(value);
+                },
+                Op::Store(i) => {
+                    let value = self.pop(span)?;
+                    self.interpreter.assign(&chunk.names[i as usize], value);
+                },
+                Op::Declare(i) => self.interpreter.declare(&chunk.names[i as usize]),
+                Op::Global(i) => self.interpreter.declare_global(&chunk.names[i as usize]),
+                Op::Closure(i) => {
+                    let code = &chunk.functions[i as usize];
+                    let function = self.interpreter.make_compiled_function(&code.decl, Bytecode::Stack(code.clone()));
+                    self.state.stack.push(function);
+                },
+                Op::Array(n) => {
+                    let array = Value::array(self.pop_n(n, span)?);
+                    self.interpreter.check_size(&array, span)?;
+                    self.state.stack.push(array);
+                },
+                Op::Map(n) => {
+                    let mut map = IndexMap::with_capacity_and_hasher(n as usize, Default::default());
+                    let mut entries = self.pop_n(2 * n, span)?.into_iter();
+                    while let (Some(key), Some(value)) = (entries.next(), entries.next()) {
+                        map.insert(map_key(key, span)?, value);
+                    }
+                    let map = Value::map(map);
+                    self.interpreter.check_size(&map, span)?;
+                    self.state.stack.push(map);
+                },
+                Op::CheckKey => {
+                    let key = self.state.stack.last().ok_or_else(|| underflow(span))?;
+                    if !matches!(key, Value::Str(_)) {
+                        return Err(map_key(key.clone(), span).unwrap_err());
+                    }
+                },
+                Op::Index { target } => {
+                    let index = self.pop(span)?;
+                    let value = self.pop(span)?;
+                    let target = chunk.operand_spans[target as usize];
+                    let element = self.interpreter.index_value(value, index, target, span)?;
+                    self.state.stack.push(element);
+                },
+                Op::SetIndex { target } => {
+                    let value = self.pop(span)?;
+                    let index = self.pop(span)?;
+                    let array = self.pop(span)?;
+                    let target = chunk.operand_spans[target as usize];
+                    self.interpreter.store_index(array, index, value, target, span)?;
+                },
+                Op::Field { name, target } => {
+                    let value = self.pop(span)?;
+                    let target = chunk.operand_spans[target as usize];
+                    let field = field_value(&value, &chunk.names[name as usize], target, span)?;
+                    self.state.stack.push(field);
+                },
+                Op::SetField(name) => {
+                    let value = self.pop(span)?;
+                    let instance = self.pop(span)?;
+                    store_field(&instance, &chunk.names[name as usize], value, span)?;
+                },
+                Op::Unary(op) => {
+                    let operand = self.pop(span)?;
+                    let value = unary_op(op, operand, self.interpreter.arithmetic, span)?;
+                    self.state.stack.push(value);
+                },
+                Op::Binary(op) => {
+                    let stack = &mut self.state.stack;
+                    if let [.., Value::Int(l), Value::Int(r)] = stack[..] {
+                        if let Some(value) = int_binary(op, l, r) {
+                            stack.pop();
+                            let top = stack.len() - 1;
+                            stack[top] = value;
+                            continue;
+                        }
+                    }
+                    let right = self.pop(span)?;
+                    let left = self.pop(span)?;
+                    let value = self.interpreter.binary(op, left, right, span)?;
+                    self.state.stack.push(value);
+                },
+                Op::Truthy => {
+                    let value = self.pop(span)?;
+                    self.state.stack.push(Value::Bool(value.is_truthy()));
+                },
+                Op::Jump(target) => pc = target as usize,
+                Op::JumpIfFalse(target) => {
+                    if !self.pop(span)?.is_truthy() {
+                        pc = target as usize;
+                    }
+                },
+                Op::JumpIfTrue(target) => {
+                    if self.pop(span)?.is_truthy() {
+                        pc = target as usize;
+                    }
+                },
+                Op::Call { args, callee } | Op::TailCall { args, callee } => {
+                    let args = self.pop_n(args, span)?;
+                    let function = self.pop(span)?;
+                    if let Value::Function(function) = &function {
+                        if let Some(Bytecode::Stack(code)) = &function.code {
+                            let code = code.clone();
+                            match self.state.calls.last_mut().filter(|_| matches!(op, Op::TailCall { .. })) {
+                                Some(call) => {
+                                    self.interpreter.replace_frame(function, args, span)?;
+                                    self.state.stack.truncate(call.base);
+                                    call.function = code.clone();
+                                    call.call_site = span;
+                                },
+                                None => {
+                                    self.interpreter.push_frame(function, args, span)?;
+                                    self.state.calls.push(CallFrame {
+                                        function: code.clone(),
+                                        caller: current.take(),
+                                        return_to: pc,
+                                        base: self.state.stack.len(),
+                                        call_site: span,
+                                    });
+                                },
+                            }
+                            current = Some(code);
+                            pc = 0;
+                            continue;
+                        }
+                    }
+                    let callee = chunk.operand_spans[callee as usize];
+                    let value = self.interpreter.call_value(function, args, span, callee)?;
+                    self.state.stack.push(value);
+                },
+                Op::Return => {
+                    let value = self.pop(span)?;
+                    let Some(call) = self.state.calls.pop() else {
+                        return Ok(Some(value));
+                    };
+                    self.interpreter.pop_frame();
+                    self.state.stack.truncate(call.base);
+                    self.state.stack.push(value);
+                    current = call.caller;
+                    pc = call.return_to;
+                },
+                Op::Print(n) => {
+                    let values = self.pop_n(n, span)?;
+                    self.interpreter.print_values(&values, span)?;
+                },
+                Op::Import(i) => self.interpreter.import(&chunk.names[i as usize], span)?,
+                Op::Statement => {
+                    self.take_step();
+                    self.interpreter.enter_statement(span)?;
+                },
+                Op::Iteration => {
+                    self.take_step();
+                    self.interpreter.end_iteration(span)?;
+                },
+                Op::SetResult => result = self.pop(span)?,
+                Op::ClearResult => result = Value::Nil,
+                Op::Gosub { target, resume } => {
+                    self.interpreter.gosub(resume as usize, span)?;
+                    pc = target as usize;
+                },
+                Op::SubReturn => pc = self.interpreter.sub_return(span)?,
+                Op::UndefinedLine(i) => {
+                    let Value::Int(line) = chunk.constants[i as usize] else {
+                        unreachable!("line numbers are ints");
+                    };
+                    return Err(RuntimeError::UndefinedLine { line, span });
+                },
+            }
+        }
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod test {
+    use crate::ast::BinaryOp;
+    use crate::bytecode::{load, save};
+    use crate::compiler::{compile, compile_with, Backend, Chunk, CompileOptions, Op};
+    use crate::interpreter::{Interpreter, RuntimeError, SharedBuffer};
+    use crate::lexer::Span;
+    use crate::parser::{parse, parse_with_dialect, Dialect};
+    use crate::value::Value;
+
+    /// Runs `source` on the tree-walker and on the VM, checking both agree.
+    fn run_both(source: &str, dialect: Dialect) -> (Result<Value, RuntimeError>, String) {
+        let program = parse_with_dialect(source, dialect).unwrap();
+        let walker_output = SharedBuffer::new();
+        let walker = Interpreter::with_output(Box::new(walker_output.clone())).run(&program);
+        let chunk = compile(&program);
+        // What the compiler produces passes the checks of loading it
+        let mut bytes = Vec::new();
+        save(&chunk, &mut bytes).unwrap();
+        assert!(load(&bytes[..]).is_ok(), "{:?} doesn't load", source);
+        let vm_output = SharedBuffer::new();
+        let vm = Interpreter::with_output(Box::new(vm_output.clone())).run_compiled(&chunk);
+        assert_eq!(vm, walker, "results differ for {:?}", source);
+        assert_eq!(vm_output.contents(), walker_output.contents(), "output differs for {:?}", source);
+        (vm, vm_output.contents())
+    }
+
+    #[test]
+    fn test_matches_interpreter() {
+        let programs = [
+            "x = 1\nwhile x < 100 {\n  x = x * 2\n  if x == 16 { continue }\n  print x\n}\nx",
+            "i = 0\nwhile 1 {\n  i = i + 1\n  if i > 3 { break }\n}\ni",
+            "fn fib(n) {\n  if n < 2 { return n }\n  return fib(n - 1) + fib(n - 2)\n}\nprint fib(15)",
+            "fn counter() {\n  n = 0\n  return fn() {\n    global total\n    total = total + 1\n    return n\n  }\n}\ntotal = 0\nc = counter()\nc()\nc()\ntotal",
+            "x = 1\nfn f() {\n  global x\n  x = 2\n  let x = 3\n  return x\n}\n[f(), x]",
+            "struct P { x, y }\np = P(1, 2)\np.y = 5\nm = {\"a\": [p.x, p.y]}\nm[\"b\"] = 3\nm",
+            "a = [1, 2, 3]\na[1] = a[0] + a[2]\n[len(a), a[1], 0 && x, 1 || x, 0 || 2 > 1]",
+            "fn f(a) {\n  return a[5]\n}\nfn g() {\n  return f([1])\n}\ng()",
+            "print 1\nundefined_function(2)",
+            "x = {\"a\": 1}\nx[1]",
+            "3(1)",
+            "fn f() {}\nf(1)",
+            "1 + \"a\"",
+            "x = 9223372036854775807\n[x - 1, x * 1 == x, 7 / 2, x >= 3, 2 != 2]",
+            "x = 9223372036854775807\nx + 1",
+        ];
+        for source in programs {
+            let _ = run_both(source, Dialect::Modern);
+        }
+        let (result, output) = run_both("x = 1\nprint x\nx + 1", Dialect::Modern);
+        assert_eq!((result, output.as_str()), (Ok(Value::Int(2)), "1\n"));
+        let (result, _) = run_both("x = 1\nx + 1\ny = 2", Dialect::Modern);
+        assert_eq!(result, Ok(Value::Nil));
+    }
+
+    #[test]
+    fn test_classic() {
+        let source = "10 n = 0\n20 gosub 100\n30 if n < 3 { goto 20 }\n40 print n\n50 end = 1\n60 goto 200\n100 n = n + 1\n110 return";
+        let (result, output) = run_both(source, Dialect::Classic);
+        assert!(matches!(result, Err(RuntimeError::UndefinedLine { line: 200, .. })));
+        assert_eq!(output, "3\n");
+        assert_eq!(run_both("20 print 2\n10 print 1", Dialect::Classic).1, "1\n2\n");
+        assert_eq!(run_both("50 print 5\n45 return\n10 gosub 40\n20 goto 50\n40 print 4", Dialect::Classic).1, "4\n5\n");
+        let (result, _) = run_both("10 print 1\n20 return", Dialect::Classic);
+        assert!(matches!(result, Err(RuntimeError::ReturnWithoutGosub { .. })));
+    }
+
+    #[test]
+    fn test_invalid_bytecode() {
+        let chunk = Chunk { code: vec![Op::Nil, Op::Binary(BinaryOp::Add)], spans: vec![Span::default(); 2], ..Chunk::default() };
+        let error = Interpreter::with_output(Box::new(SharedBuffer::new())).run_compiled(&chunk).unwrap_err();
+        assert_eq!(error.message(), "invalid bytecode: operand stack underflow");
+    }
+
+    #[test]
+    fn test_limits() {
+        let program = parse("fn f(n) {\n  if n == 0 { return 0 }\n  return 1 + f(n - 1)\n}\nf(10000)").unwrap();
+        let chunk = compile(&program);
+        let mut interpreter = Interpreter::with_output(Box::new(SharedBuffer::new()));
+        assert!(matches!(interpreter.run_compiled(&chunk), Err(RuntimeError::Traced { .. })));
+        // VM calls don't use the host stack, so deep recursion is fine
+        interpreter.set_max_call_depth(20000);
+        assert_eq!(interpreter.run_compiled(&chunk), Ok(Value::Int(10000)));
+
+        let program = parse("i = 0\nwhile i < 10 {\n  i = i + 1\n}").unwrap();
+        for fuel in [15, 40] {
+            let mut walker = Interpreter::with_output(Box::new(SharedBuffer::new()));
+            walker.set_fuel(Some(fuel));
+            let mut vm = Interpreter::with_output(Box::new(SharedBuffer::new()));
+            vm.set_fuel(Some(fuel));
+            assert_eq!(vm.run_compiled(&compile(&program)), walker.run(&program));
+            assert_eq!(vm.fuel(), walker.fuel());
+        }
+    }
+
+    #[test]
+    fn test_mixed_engines() {
+        let mut interpreter = Interpreter::with_output(Box::new(SharedBuffer::new()));
+        interpreter.run_compiled(&compile(&parse("fn double(x) {\n  return x * 2\n}").unwrap())).unwrap();
+        let result = interpreter.run(&parse("fn add1(x) {\n  return x + 1\n}\ndouble(3)").unwrap());
+        assert_eq!(result, Ok(Value::Int(6)));
+        let result = interpreter.run_compiled(&compile(&parse("add1(double(4))").unwrap()));
+        assert_eq!(result, Ok(Value::Int(9)));
+        let error = interpreter.run(&parse("double(\"a\" - 1)").unwrap()).unwrap_err();
+        assert!(matches!(error, RuntimeError::TypeMismatch { .. }));
+        let error = interpreter.run(&parse("double([1])").unwrap()).unwrap_err();
+        assert_eq!(error.trace()[0].function, "double");
+    }
+
+    #[test]
+    fn test_tail_calls() {
+        let source = "\
+fn count(n, total) {
+  if n == 0 { return total }
+  return count(n - 1, total + n)
+}
+fn even(n) {
+  if n == 0 { return 1 }
+  let odd = fn(n) {
+    if n == 0 { return 0 }
+    return even(n - 1)
+  }
+  return odd(n - 1)
+}
+fn size(a) {
+  return len(a)
+}
+[count(10000, 0), even(5000), even(301), size([1, 2])]";
+        let program = parse(source).unwrap();
+        for backend in [Backend::Stack, Backend::Register] {
+            let options = CompileOptions { backend, tail_calls: true, ..CompileOptions::default() };
+            let mut interpreter = Interpreter::with_output(Box::new(SharedBuffer::new()));
+            let expected = Value::array(vec![Value::Int(50005000), Value::Int(1), Value::Int(0), Value::Int(2)]);
+            assert_eq!(compile_with(&program, &options).run(&mut interpreter), Ok(expected), "{:?}", backend);
+            let plain = CompileOptions { backend, ..CompileOptions::default() };
+            let error = compile_with(&program, &plain).run(&mut interpreter).unwrap_err();
+            assert!(matches!(error.root(), RuntimeError::StackOverflow { .. }), "{:?}", backend);
+
+            // The call that fails to start is the one reported, in the call
+            // making it
+            let program = parse("fn f(a) {\n  return a()\n}\nfn g() {\n  return f(fn(x) {})\n}\ng()").unwrap();
+            let error = compile_with(&program, &options).run(&mut interpreter).unwrap_err();
+            assert!(matches!(error.root(), RuntimeError::WrongArgumentCount { .. }), "{:?}", backend);
+            let trace: Vec<_> = error.trace().iter().map(|frame| (frame.function.as_str(), frame.call_site.line)).collect();
+            assert_eq!(trace, vec![("f", 5)], "{:?}", backend);
+        }
+    }
+}
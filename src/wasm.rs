@@ -0,0 +1,1160 @@
+use std::collections::{BTreeSet, HashMap};
+
+use indexmap::IndexMap;
+
+use crate::ast::{BinaryOp, Expr, ExprKind, Program, Stmt, StmtKind, UnaryOp};
+use crate::lexer::Span;
+use crate::transpile::{each_expr, each_subexpr, walk_blocks, Scopes, TranspileError};
+use crate::wasm_runtime::{self, Global, Rt, ARRAY, BOOL, BUILTIN, BUILTINS, INT, NIL, STR, UNSET};
+
+/// Compiles `program` into a WebAssembly module for WASI, which runs in
+/// any runtime implementing `wasi_snapshot_preview1` without the
+/// interpreter.
+///
+/// The module exports its memory and the program as `_start`. It prints
+/// to stdout through `fd_write`; a runtime error prints `error: ` and the
+/// error's location and message to stderr and exits with status 1, without
+/// the calls that led there. Modules only hold ints, bools, strings,
+/// arrays and functions that don't use the variables of the functions
+/// they are defined in: programs with floats, maps, structs, such closures
+/// or imports, and the builtins other than `len`, `push`, `pop`, `abs`,
+/// `min`, `max` and `exit`, are rejected.
+pub fn compile(program: &Program) -> Result<Vec<u8>, TranspileError> {
+    unsupported(&program.statements)?;
+    let scopes = Scopes::new(program, BUILTINS)?;
+    for scope in &scopes.functions {
+        if !scope.captured.is_empty() {
+            let message = format!("`{}` has variables used by functions defined inside it, which wasm can't hold", scope.decl.name);
+            return Err(TranspileError { message, span: scope.decl.span });
+        }
+    }
+    let mut data = Data::default();
+    let runtime = wasm_runtime::functions(&mut data);
+    let metas = scopes
+        .functions
+        .iter()
+        .enumerate()
+        .map(|(i, scope)| {
+            let name = data.string(&scope.decl.name);
+            data.words(&[name, scope.decl.params.len() as i32, i as i32])
+        })
+        .collect();
+    let mut generator = Generator {
+        scopes: &scopes,
+        data: &mut data,
+        metas,
+        globals: IndexMap::new(),
+        function: None,
+        code: Func::new(&[], &[]),
+        locals: HashMap::new(),
+        flags: HashMap::new(),
+        scratch: (0, 0),
+        temps: Vec::new(),
+        labels: Vec::new(),
+        cases: None,
+        lines: program.lines.clone(),
+    };
+    let mut functions: Vec<Func> = runtime.into_iter().map(|(_, function)| function).collect();
+    for i in 0..scopes.functions.len() {
+        functions.push(generator.function(i));
+    }
+    functions.push(generator.main(program));
+    let globals: Vec<String> = generator.globals.into_keys().collect();
+    Ok(module(functions, scopes.functions.len(), &globals, &data))
+}
+
+/// Fails on the values wasm modules don't hold.
+fn unsupported(statements: &[Stmt]) -> Result<(), TranspileError> {
+    for statement in statements {
+        match &statement.kind {
+            StmtKind::Struct(_) => {
+                let message = "structs can't be compiled to wasm".to_string();
+                return Err(TranspileError { message, span: statement.span });
+            },
+            StmtKind::Function(decl) => unsupported(&decl.body)?,
+            StmtKind::If { then_branch, else_branch, .. } => {
+                unsupported(then_branch)?;
+                unsupported(else_branch.as_deref().unwrap_or_default())?;
+            },
+            StmtKind::While { body, .. } => unsupported(body)?,
+            _ => {},
+        }
+        let mut result = Ok(());
+        each_expr(statement, &mut |expr| {
+            if result.is_ok() {
+                result = unsupported_expr(expr);
+            }
+        });
+        result?;
+    }
+    Ok(())
+}
+
+fn unsupported_expr(expr: &Expr) -> Result<(), TranspileError> {
+    let message = match &expr.kind {
+        ExprKind::Float(_) => "floats can't be compiled to wasm",
+        ExprKind::Map(_) => "maps can't be compiled to wasm",
+        ExprKind::Function(decl) => return unsupported(&decl.body),
+        _ => {
+            let mut result = Ok(());
+            each_subexpr(expr, &mut |expr| {
+                if result.is_ok() {
+                    result = unsupported_expr(expr);
+                }
+            });
+            return result;
+        },
+    };
+    Err(TranspileError { message: message.to_string(), span: expr.span })
+}
+
+/// What a `br` in the current function can reach.
+#[derive(Clone, Copy, PartialEq)]
+enum Label {
+    Other,
+    /// The end of a `while`.
+    Break,
+    /// The start of a `while`.
+    Continue,
+    /// The loop dispatching jumps of the classic dialect.
+    Dispatch,
+}
+
+struct Generator<'a> {
+    scopes: &'a Scopes,
+    data: &'a mut Data,
+    /// The address of the name, arity and table index of each function.
+    metas: Vec<i32>,
+    /// The globals the program reads or writes, each held in a tag and a
+    /// payload wasm global.
+    globals: IndexMap<String, u32>,
+    /// The function being generated, `None` for the top level.
+    function: Option<usize>,
+    code: Func,
+    /// The tag and payload locals of each variable of the function.
+    locals: HashMap<String, (u32, u32)>,
+    /// The locals telling whether the function declared a name global.
+    flags: HashMap<String, u32>,
+    /// Locals holding a value between two instructions.
+    scratch: (u32, u32),
+    /// Unused `i32` locals.
+    temps: Vec<u32>,
+    labels: Vec<Label>,
+    /// Classic dialect: the local holding the case to jump to, and the case
+    /// starting at each statement that is jumped to.
+    cases: Option<(u32, HashMap<usize, u32>)>,
+    lines: HashMap<i64, usize>,
+}
+
+impl Generator<'_> {
+    fn start(&mut self, code: Func) {
+        self.code = code;
+        self.locals.clear();
+        self.flags.clear();
+        self.temps.clear();
+        self.scratch = (self.code.local(ValType::I32), self.code.local(ValType::I64));
+    }
+
+    fn function(&mut self, i: usize) -> Func {
+        let scopes = self.scopes;
+        let scope = &scopes.functions[i];
+        self.function = Some(i);
+        self.start(Func::new(&[ValType::I32], &[ValType::I32, ValType::I64]));
+        for (n, name) in scope.locals.iter().enumerate() {
+            let local = (self.code.local(ValType::I32), self.code.local(ValType::I64));
+            if n < scope.decl.params.len() {
+                let offset = 16 * n as u32;
+                self.code.local_get(0).i32_load(offset).local_set(local.0);
+                self.code.local_get(0).i64_load(offset + 8).local_set(local.1);
+            }
+            self.locals.insert(name.clone(), local);
+        }
+        let mut globals: Vec<&String> = scope.globals.iter().collect();
+        globals.sort();
+        for name in globals {
+            let flag = self.code.local(ValType::I32);
+            self.flags.insert(name.clone(), flag);
+        }
+        self.block(&scope.decl.body, 0);
+        self.code.i32_const(NIL).i64_const(0);
+        std::mem::replace(&mut self.code, Func::new(&[], &[]))
+    }
+
+    fn main(&mut self, program: &Program) -> Func {
+        self.function = None;
+        self.start(Func::new(&[], &[]));
+        let mut jumps = false;
+        let mut starts = BTreeSet::from([0]);
+        for (i, statement) in program.statements.iter().enumerate() {
+            walk_blocks(statement, &mut |kind| match kind {
+                StmtKind::Goto(line) | StmtKind::Gosub(line) => {
+                    jumps = true;
+                    starts.extend(program.lines.get(line));
+                    if matches!(kind, StmtKind::Gosub(_)) {
+                        starts.insert(i + 1);
+                    }
+                },
+                StmtKind::SubReturn => jumps = true,
+                _ => {},
+            });
+        }
+        if !jumps {
+            self.block(&program.statements, 0);
+            return std::mem::replace(&mut self.code, Func::new(&[], &[]));
+        }
+        // Jumps go back to a `br_table` on the case to run, each case
+        // following the end of a block
+        let starts: Vec<usize> = starts.into_iter().collect();
+        let pc = self.code.local(ValType::I32);
+        let cases = starts.iter().enumerate().map(|(case, &start)| (start, case as u32)).collect();
+        self.cases = Some((pc, cases));
+        self.code.loop_();
+        self.labels.push(Label::Dispatch);
+        for _ in &starts {
+            self.code.block();
+            self.labels.push(Label::Other);
+        }
+        let targets: Vec<u32> = (0..starts.len() as u32).collect();
+        self.code.local_get(pc).br_table(&targets, targets.len() as u32 - 1);
+        for (case, &start) in starts.iter().enumerate() {
+            self.end();
+            let end = starts.get(case + 1).copied().unwrap_or(program.statements.len());
+            for (i, statement) in program.statements.iter().enumerate().take(end).skip(start) {
+                self.statement(statement, i + 1);
+            }
+        }
+        self.end();
+        self.cases = None;
+        std::mem::replace(&mut self.code, Func::new(&[], &[]))
+    }
+
+    fn if_(&mut self) {
+        self.code.if_();
+        self.labels.push(Label::Other);
+    }
+
+    fn end(&mut self) {
+        self.code.end();
+        self.labels.pop();
+    }
+
+    /// The depth of the innermost `label` for a `br`.
+    fn depth(&self, label: Label) -> u32 {
+        let i = self.labels.iter().rposition(|&l| l == label).unwrap();
+        (self.labels.len() - 1 - i) as u32
+    }
+
+    fn temp(&mut self) -> u32 {
+        self.temps.pop().unwrap_or_else(|| self.code.local(ValType::I32))
+    }
+
+    fn span(&mut self, span: Span) {
+        self.code.i32_const(span.line as i32).i32_const(span.column as i32);
+    }
+
+    /// A `gosub` nested in `statements` continues at `resume`, after the
+    /// top-level statement it is in.
+    fn block(&mut self, statements: &[Stmt], resume: usize) {
+        for statement in statements {
+            self.statement(statement, resume);
+        }
+    }
+
+    fn statement(&mut self, statement: &Stmt, resume: usize) {
+        match &statement.kind {
+            StmtKind::Assign { name, value, declaration, .. } => {
+                self.expr(value);
+                if *declaration {
+                    if let Some(&flag) = self.flags.get(name) {
+                        self.code.i32_const(0).local_set(flag);
+                    }
+                }
+                self.assign(name);
+            },
+            StmtKind::SetIndex { target, index, value } => {
+                self.expr(target);
+                self.expr(index);
+                self.expr(value);
+                self.span(target.span);
+                self.span(index.span);
+                self.code.call(Rt::SetIndex.index());
+            },
+            StmtKind::SetField { target, value, .. } => {
+                self.expr(target);
+                self.expr(value);
+                self.code.drop().drop().drop();
+                self.span(target.span);
+                self.code.call(Rt::NoFields.index()).unreachable();
+            },
+            StmtKind::If { condition, then_branch, else_branch } => {
+                self.expr(condition);
+                self.code.call(Rt::Truthy.index());
+                self.if_();
+                self.block(then_branch, resume);
+                if let Some(else_branch) = else_branch {
+                    self.code.else_();
+                    self.block(else_branch, resume);
+                }
+                self.end();
+            },
+            StmtKind::While { condition, body } => {
+                self.code.block();
+                self.labels.push(Label::Break);
+                self.code.loop_();
+                self.labels.push(Label::Continue);
+                self.expr(condition);
+                self.code.call(Rt::Truthy.index()).i32_eqz().br_if(1);
+                self.block(body, resume);
+                self.code.br(0);
+                self.end();
+                self.end();
+            },
+            StmtKind::Break => {
+                let depth = self.depth(Label::Break);
+                self.code.br(depth);
+            },
+            StmtKind::Continue => {
+                let depth = self.depth(Label::Continue);
+                self.code.br(depth);
+            },
+            StmtKind::Print(args) => {
+                let values = self.values(args);
+                self.code.local_get(values).i32_const(args.len() as i32).call(Rt::PrintValues.index());
+                self.pop_values(values);
+            },
+            StmtKind::Function(decl) => {
+                let meta = self.metas[self.scopes.of(decl)];
+                self.code.i32_const(meta).call(Rt::MakeFunction.index());
+                self.assign(&decl.name);
+            },
+            // Rejected by `unsupported` and `Scopes::new`
+            StmtKind::Struct(_) | StmtKind::Import(_) => {},
+            StmtKind::Return(value) => {
+                match value {
+                    Some(value) => self.expr(value),
+                    None => {
+                        self.code.i32_const(NIL).i64_const(0);
+                    },
+                }
+                if self.function.is_none() {
+                    self.code.drop().drop();
+                }
+                self.code.return_();
+            },
+            StmtKind::Global(names) => {
+                for name in names {
+                    if let Some(&flag) = self.flags.get(name) {
+                        self.code.i32_const(1).local_set(flag);
+                    }
+                }
+            },
+            StmtKind::Goto(line) => self.jump(*line, statement.span),
+            StmtKind::Gosub(line) => {
+                let case = self.case(resume);
+                self.code.i32_const(case as i32);
+                self.span(statement.span);
+                self.code.call(Rt::Gosub.index());
+                self.jump(*line, statement.span);
+            },
+            StmtKind::SubReturn => {
+                self.span(statement.span);
+                self.code.call(Rt::SubReturn.index());
+                self.dispatch();
+            },
+            StmtKind::Expr(expr) => {
+                self.expr(expr);
+                self.code.drop().drop();
+            },
+        }
+    }
+
+    fn case(&self, statement: usize) -> u32 {
+        self.cases.as_ref().unwrap().1[&statement]
+    }
+
+    /// Jumps to the case on the stack.
+    fn dispatch(&mut self) {
+        let pc = self.cases.as_ref().unwrap().0;
+        let depth = self.depth(Label::Dispatch);
+        self.code.local_set(pc).br(depth);
+    }
+
+    fn jump(&mut self, line: i64, span: Span) {
+        match self.lines.get(&line) {
+            Some(&statement) => {
+                let case = self.case(statement);
+                self.code.i32_const(case as i32);
+                self.dispatch();
+            },
+            None => {
+                self.code.i64_const(line);
+                self.span(span);
+                self.code.call(Rt::NoLine.index()).unreachable();
+            },
+        }
+    }
+
+    /// Evaluates `exprs` in order onto the argument stack, returning a
+    /// local holding their address.
+    fn values(&mut self, exprs: &[Expr]) -> u32 {
+        let base = self.temp();
+        self.code.i32_const(exprs.len() as i32).call(Rt::Reserve.index()).local_set(base);
+        for (i, expr) in exprs.iter().enumerate() {
+            self.expr(expr);
+            self.store(base, 16 * i as u32);
+        }
+        base
+    }
+
+    fn pop_values(&mut self, base: u32) {
+        self.code.local_get(base).global_set(Global::Sp as u32);
+        self.temps.push(base);
+    }
+
+    /// Stores the value on the stack at the address in `base` plus `offset`.
+    fn store(&mut self, base: u32, offset: u32) {
+        let (tag, payload) = self.scratch;
+        self.code.local_set(payload).local_set(tag);
+        self.code.local_get(base).local_get(tag).i32_store(offset);
+        self.code.local_get(base).local_get(payload).i64_store(offset + 8);
+    }
+
+    /// Emits the code pushing the tag and payload of `expr`.
+    fn expr(&mut self, expr: &Expr) {
+        match &expr.kind {
+            ExprKind::Number(n) => {
+                self.code.i32_const(INT).i64_const(*n);
+            },
+            ExprKind::Str(s) => {
+                let s = self.data.string(s);
+                self.code.i32_const(STR).i64_const(s as i64);
+            },
+            ExprKind::Variable(name) => self.read(name, expr.span, false),
+            ExprKind::Function(decl) => {
+                let meta = self.metas[self.scopes.of(decl)];
+                self.code.i32_const(meta).call(Rt::MakeFunction.index());
+            },
+            ExprKind::Array(items) => {
+                let array = self.temp();
+                self.code.i32_const(items.len() as i32).call(Rt::ArrayNew.index()).local_set(array);
+                let values = self.temp();
+                self.code.local_get(array).i32_load(8).local_set(values);
+                for (i, item) in items.iter().enumerate() {
+                    self.expr(item);
+                    self.store(values, 16 * i as u32);
+                }
+                self.code.i32_const(ARRAY).local_get(array).i64_extend_i32_u();
+                self.temps.extend([values, array]);
+            },
+            ExprKind::Index { target, index } => {
+                self.expr(target);
+                self.expr(index);
+                self.span(target.span);
+                self.span(index.span);
+                self.code.call(Rt::Index.index());
+            },
+            ExprKind::Field { target, .. } => {
+                self.expr(target);
+                self.code.drop();
+                self.span(target.span);
+                self.code.call(Rt::NoFields.index()).unreachable();
+            },
+            ExprKind::Unary { op: UnaryOp::Neg, operand } => {
+                self.expr(operand);
+                self.span(expr.span);
+                self.code.call(Rt::Neg.index());
+            },
+            ExprKind::Unary { op: UnaryOp::Not, operand } => {
+                self.code.i32_const(BOOL);
+                self.expr(operand);
+                self.code.call(Rt::Truthy.index()).i32_eqz().i64_extend_i32_u();
+            },
+            ExprKind::Binary { op: op @ (BinaryOp::And | BinaryOp::Or), left, right } => {
+                self.code.i32_const(BOOL);
+                self.expr(left);
+                self.code.call(Rt::Truthy.index());
+                self.code.if_i32();
+                self.labels.push(Label::Other);
+                if *op == BinaryOp::Or {
+                    self.code.i32_const(1).else_();
+                }
+                self.expr(right);
+                self.code.call(Rt::Truthy.index());
+                if *op == BinaryOp::And {
+                    self.code.else_().i32_const(0);
+                }
+                self.end();
+                self.code.i64_extend_i32_u();
+            },
+            ExprKind::Binary { op, left, right } => {
+                self.expr(left);
+                self.expr(right);
+                self.code.i32_const(operator(*op));
+                self.span(expr.span);
+                self.code.call(Rt::Binary.index());
+            },
+            ExprKind::Call { callee, args } => {
+                match &callee.kind {
+                    ExprKind::Variable(name) => self.read(name, callee.span, true),
+                    _ => self.expr(callee),
+                }
+                let values = self.values(args);
+                self.code.local_get(values).i32_const(args.len() as i32);
+                self.span(expr.span);
+                self.span(callee.span);
+                self.code.call(Rt::Call.index());
+                self.pop_values(values);
+            },
+            // Rejected by `unsupported`
+            ExprKind::Float(_) | ExprKind::Map(_) => {
+                self.code.unreachable();
+            },
+        }
+    }
+
+    /// Pushes the value of the variable `name`; `call` tells whether it is
+    /// being called, for the error if it isn't set.
+    fn read(&mut self, name: &str, span: Span, call: bool) {
+        let (tag, payload) = self.scratch;
+        match (self.locals.get(name).copied(), self.flags.get(name).copied()) {
+            (Some(local), Some(flag)) => {
+                self.code.local_get(flag);
+                self.if_();
+                self.read_global(name, span, call);
+                self.code.else_();
+                self.read_local(local, name, span, call);
+                self.end();
+            },
+            (Some(local), None) => self.read_local(local, name, span, call),
+            (None, _) => self.read_global(name, span, call),
+        }
+        self.code.local_get(tag).local_get(payload);
+    }
+
+    /// Copies a local into the scratch locals, or the global `name` if the
+    /// local isn't set.
+    fn read_local(&mut self, local: (u32, u32), name: &str, span: Span, call: bool) {
+        let (tag, payload) = self.scratch;
+        self.code.local_get(local.0).local_set(tag).local_get(local.1).local_set(payload);
+        self.code.local_get(tag).i32_eqz();
+        self.if_();
+        self.read_global(name, span, call);
+        self.end();
+    }
+
+    fn read_global(&mut self, name: &str, span: Span, call: bool) {
+        let (tag, payload) = self.scratch;
+        let global = self.global(name);
+        self.code.global_get(global).local_tee(tag).i32_eqz();
+        self.if_();
+        let name = self.data.string(name);
+        self.code.i32_const(name).i32_const(call as i32);
+        self.span(span);
+        self.code.call(Rt::Undefined.index()).unreachable();
+        self.end();
+        self.code.global_get(global + 1).local_set(payload);
+    }
+
+    /// Assigns the value on the stack to `name`.
+    fn assign(&mut self, name: &str) {
+        let global = self.global(name);
+        match (self.locals.get(name).copied(), self.flags.get(name).copied()) {
+            (Some(local), Some(flag)) => {
+                let (tag, payload) = self.scratch;
+                self.code.local_set(payload).local_set(tag).local_get(flag);
+                self.if_();
+                self.code.local_get(tag).global_set(global).local_get(payload).global_set(global + 1);
+                self.code.else_();
+                self.code.local_get(tag).local_set(local.0).local_get(payload).local_set(local.1);
+                self.end();
+            },
+            (Some(local), None) => {
+                self.code.local_set(local.1).local_set(local.0);
+            },
+            (None, _) => {
+                self.code.global_set(global + 1).global_set(global);
+            },
+        }
+    }
+
+    /// The tag global of `name`, followed by its payload global.
+    fn global(&mut self, name: &str) -> u32 {
+        let next = wasm_runtime::GLOBALS + 2 * self.globals.len() as u32;
+        *self.globals.entry(name.to_string()).or_insert(next)
+    }
+}
+
+fn operator(op: BinaryOp) -> i32 {
+    match op {
+        BinaryOp::Add => 0,
+        BinaryOp::Sub => 1,
+        BinaryOp::Mul => 2,
+        BinaryOp::Div => 3,
+        BinaryOp::Equals => 4,
+        BinaryOp::NotEquals => 5,
+        BinaryOp::SmallerThan => 6,
+        BinaryOp::GreaterThan => 7,
+        BinaryOp::SmallerEquals => 8,
+        BinaryOp::GreaterEquals => 9,
+        BinaryOp::And | BinaryOp::Or => unreachable!("`&&` and `||` short-circuit"),
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub(crate) enum ValType {
+    I32,
+    I64,
+}
+
+impl ValType {
+    fn code(self) -> u8 {
+        match self {
+            ValType::I32 => 0x7f,
+            ValType::I64 => 0x7e,
+        }
+    }
+}
+
+fn uleb(out: &mut Vec<u8>, mut n: u64) {
+    loop {
+        let byte = (n & 0x7f) as u8;
+        n >>= 7;
+        if n == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+fn sleb(out: &mut Vec<u8>, mut n: i64) {
+    loop {
+        let byte = (n & 0x7f) as u8;
+        n >>= 7;
+        if (n == 0 && byte & 0x40 == 0) || (n == -1 && byte & 0x40 != 0) {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+/// The code of a wasm function, written an instruction at a time.
+pub(crate) struct Func {
+    params: Vec<ValType>,
+    results: Vec<ValType>,
+    locals: Vec<ValType>,
+    code: Vec<u8>,
+}
+
+macro_rules! instructions {
+    ($($name:ident = $op:expr,)*) => {
+        impl Func {
+            $(
+                pub(crate) fn $name(&mut self) -> &mut Func {
+                    self.code.push($op);
+                    self
+                }
+            )*
+        }
+    };
+}
+
+instructions! {
+    unreachable = 0x00,
+    else_ = 0x05,
+    end = 0x0b,
+    return_ = 0x0f,
+    drop = 0x1a,
+    select = 0x1b,
+    i32_eqz = 0x45,
+    i32_eq = 0x46,
+    i32_ne = 0x47,
+    i32_lt_s = 0x48,
+    i32_lt_u = 0x49,
+    i32_gt_s = 0x4a,
+    i32_gt_u = 0x4b,
+    i32_le_s = 0x4c,
+    i32_ge_s = 0x4e,
+    i32_ge_u = 0x4f,
+    i64_eqz = 0x50,
+    i64_eq = 0x51,
+    i64_ne = 0x52,
+    i64_lt_s = 0x53,
+    i64_gt_s = 0x55,
+    i64_ge_u = 0x5a,
+    i32_add = 0x6a,
+    i32_sub = 0x6b,
+    i32_mul = 0x6c,
+    i32_and = 0x71,
+    i32_or = 0x72,
+    i32_shl = 0x74,
+    i32_shr_u = 0x76,
+    i64_add = 0x7c,
+    i64_sub = 0x7d,
+    i64_mul = 0x7e,
+    i64_div_s = 0x7f,
+    i64_div_u = 0x80,
+    i64_rem_u = 0x82,
+    i64_and = 0x83,
+    i64_xor = 0x85,
+    i32_wrap_i64 = 0xa7,
+    i64_extend_i32_u = 0xad,
+}
+
+impl Func {
+    pub(crate) fn new(params: &[ValType], results: &[ValType]) -> Func {
+        Func { params: params.to_vec(), results: results.to_vec(), locals: Vec::new(), code: Vec::new() }
+    }
+
+    /// Adds a local, returning its index.
+    pub(crate) fn local(&mut self, ty: ValType) -> u32 {
+        self.locals.push(ty);
+        (self.params.len() + self.locals.len() - 1) as u32
+    }
+
+    fn op(&mut self, op: u8, immediate: u32) -> &mut Func {
+        self.code.push(op);
+        uleb(&mut self.code, immediate as u64);
+        self
+    }
+
+    /// A memory access at `offset` from the address on the stack, aligned
+    /// to `align` as a power of two.
+    fn memory(&mut self, op: u8, align: u32, offset: u32) -> &mut Func {
+        self.op(op, align);
+        uleb(&mut self.code, offset as u64);
+        self
+    }
+
+    pub(crate) fn block(&mut self) -> &mut Func {
+        self.code.extend([0x02, 0x40]);
+        self
+    }
+
+    pub(crate) fn loop_(&mut self) -> &mut Func {
+        self.code.extend([0x03, 0x40]);
+        self
+    }
+
+    pub(crate) fn if_(&mut self) -> &mut Func {
+        self.code.extend([0x04, 0x40]);
+        self
+    }
+
+    /// An `if` leaving an `i32`.
+    pub(crate) fn if_i32(&mut self) -> &mut Func {
+        self.code.extend([0x04, ValType::I32.code()]);
+        self
+    }
+
+    pub(crate) fn br(&mut self, depth: u32) -> &mut Func {
+        self.op(0x0c, depth)
+    }
+
+    pub(crate) fn br_if(&mut self, depth: u32) -> &mut Func {
+        self.op(0x0d, depth)
+    }
+
+    pub(crate) fn br_table(&mut self, depths: &[u32], default: u32) -> &mut Func {
+        self.op(0x0e, depths.len() as u32);
+        for &depth in depths {
+            uleb(&mut self.code, depth as u64);
+        }
+        uleb(&mut self.code, default as u64);
+        self
+    }
+
+    pub(crate) fn call(&mut self, function: u32) -> &mut Func {
+        self.op(0x10, function)
+    }
+
+    /// Calls the function of table 0 at the index on the stack.
+    pub(crate) fn call_indirect(&mut self, ty: u32) -> &mut Func {
+        self.op(0x11, ty).code.push(0);
+        self
+    }
+
+    pub(crate) fn local_get(&mut self, local: u32) -> &mut Func {
+        self.op(0x20, local)
+    }
+
+    pub(crate) fn local_set(&mut self, local: u32) -> &mut Func {
+        self.op(0x21, local)
+    }
+
+    pub(crate) fn local_tee(&mut self, local: u32) -> &mut Func {
+        self.op(0x22, local)
+    }
+
+    pub(crate) fn global_get(&mut self, global: u32) -> &mut Func {
+        self.op(0x23, global)
+    }
+
+    pub(crate) fn global_set(&mut self, global: u32) -> &mut Func {
+        self.op(0x24, global)
+    }
+
+    pub(crate) fn i32_load(&mut self, offset: u32) -> &mut Func {
+        self.memory(0x28, 2, offset)
+    }
+
+    pub(crate) fn i64_load(&mut self, offset: u32) -> &mut Func {
+        self.memory(0x29, 3, offset)
+    }
+
+    pub(crate) fn i32_load8_u(&mut self, offset: u32) -> &mut Func {
+        self.memory(0x2d, 0, offset)
+    }
+
+    pub(crate) fn i32_store(&mut self, offset: u32) -> &mut Func {
+        self.memory(0x36, 2, offset)
+    }
+
+    pub(crate) fn i64_store(&mut self, offset: u32) -> &mut Func {
+        self.memory(0x37, 3, offset)
+    }
+
+    pub(crate) fn i32_store8(&mut self, offset: u32) -> &mut Func {
+        self.memory(0x3a, 0, offset)
+    }
+
+    /// The size of the memory in 64 KiB pages.
+    pub(crate) fn memory_size(&mut self) -> &mut Func {
+        self.op(0x3f, 0)
+    }
+
+    pub(crate) fn memory_grow(&mut self) -> &mut Func {
+        self.op(0x40, 0)
+    }
+
+    /// Copies a number of bytes from one address to another, both on the
+    /// stack under it.
+    pub(crate) fn memory_copy(&mut self) -> &mut Func {
+        self.code.extend([0xfc, 10, 0, 0]);
+        self
+    }
+
+    pub(crate) fn i32_const(&mut self, n: i32) -> &mut Func {
+        self.code.push(0x41);
+        sleb(&mut self.code, n as i64);
+        self
+    }
+
+    pub(crate) fn i64_const(&mut self, n: i64) -> &mut Func {
+        self.code.push(0x42);
+        sleb(&mut self.code, n);
+        self
+    }
+
+    /// The function's entry in the code section.
+    fn body(&self) -> Vec<u8> {
+        let mut runs: Vec<(u32, ValType)> = Vec::new();
+        for &ty in &self.locals {
+            match runs.last_mut() {
+                Some((count, last)) if *last == ty => *count += 1,
+                _ => runs.push((1, ty)),
+            }
+        }
+        let mut body = Vec::new();
+        uleb(&mut body, runs.len() as u64);
+        for (count, ty) in runs {
+            uleb(&mut body, count as u64);
+            body.push(ty.code());
+        }
+        body.extend(&self.code);
+        body.push(0x0b);
+        body
+    }
+}
+
+/// The constants in the data segment of a module, placed from
+/// `wasm_runtime::DATA` on.
+#[derive(Default)]
+pub(crate) struct Data {
+    bytes: Vec<u8>,
+    strings: HashMap<String, i32>,
+}
+
+impl Data {
+    fn align(&mut self) -> i32 {
+        while !self.bytes.len().is_multiple_of(4) {
+            self.bytes.push(0);
+        }
+        wasm_runtime::DATA + self.bytes.len() as i32
+    }
+
+    /// The address of the string `s`.
+    pub(crate) fn string(&mut self, s: &str) -> i32 {
+        if let Some(&address) = self.strings.get(s) {
+            return address;
+        }
+        let address = self.align();
+        self.bytes.extend((s.len() as u32).to_le_bytes());
+        self.bytes.extend(s.as_bytes());
+        self.strings.insert(s.to_string(), address);
+        address
+    }
+
+    /// The address of a new array of `i32`s.
+    pub(crate) fn words(&mut self, words: &[i32]) -> i32 {
+        let address = self.align();
+        for word in words {
+            self.bytes.extend(word.to_le_bytes());
+        }
+        address
+    }
+}
+
+fn section(module: &mut Vec<u8>, id: u8, count: usize, contents: &[u8]) {
+    let mut section = Vec::new();
+    uleb(&mut section, count as u64);
+    section.extend(contents);
+    module.push(id);
+    uleb(module, section.len() as u64);
+    module.extend(section);
+}
+
+fn name(out: &mut Vec<u8>, name: &str) {
+    uleb(out, name.len() as u64);
+    out.extend(name.as_bytes());
+}
+
+/// Encodes the module: the imports, then `functions`, ending with the
+/// runtime, the `compiled` functions of the program and its top level.
+fn module(functions: Vec<Func>, compiled: usize, globals: &[String], data: &Data) -> Vec<u8> {
+    let mut types: Vec<(Vec<ValType>, Vec<ValType>)> = vec![(vec![ValType::I32], vec![ValType::I32, ValType::I64])];
+    let mut type_of = |params: &[ValType], results: &[ValType]| {
+        let ty = (params.to_vec(), results.to_vec());
+        match types.iter().position(|t| *t == ty) {
+            Some(i) => i as u32,
+            None => {
+                types.push(ty);
+                types.len() as u32 - 1
+            },
+        }
+    };
+    let mut module = b"\0asm\x01\0\0\0".to_vec();
+    let mut imports = Vec::new();
+    for (field, params, results) in wasm_runtime::imports() {
+        name(&mut imports, "wasi_snapshot_preview1");
+        name(&mut imports, field);
+        imports.push(0x00);
+        uleb(&mut imports, type_of(&params, &results) as u64);
+    }
+    let mut declarations = Vec::new();
+    for function in &functions {
+        uleb(&mut declarations, type_of(&function.params, &function.results) as u64);
+    }
+    let mut encoded_types = Vec::new();
+    for (params, results) in &types {
+        encoded_types.push(0x60);
+        for list in [params, results] {
+            uleb(&mut encoded_types, list.len() as u64);
+            encoded_types.extend(list.iter().map(|ty| ty.code()));
+        }
+    }
+    section(&mut module, 1, types.len(), &encoded_types);
+    section(&mut module, 2, wasm_runtime::IMPORTS as usize, &imports);
+    section(&mut module, 3, functions.len(), &declarations);
+    let mut table = vec![0x70, 0x00];
+    uleb(&mut table, compiled as u64);
+    section(&mut module, 4, 1, &table);
+    // The data, then a page of heap
+    let heap = (wasm_runtime::DATA as usize + data.bytes.len() + 15) & !15;
+    let mut memory = vec![0x00];
+    uleb(&mut memory, (heap / 0x10000 + 2) as u64);
+    section(&mut module, 5, 1, &memory);
+    let mut encoded_globals = Vec::new();
+    let mut global = |ty: ValType, init: i64| {
+        encoded_globals.extend([ty.code(), 0x01, if ty == ValType::I32 { 0x41 } else { 0x42 }]);
+        sleb(&mut encoded_globals, init);
+        encoded_globals.push(0x0b);
+    };
+    let runtime_globals = [wasm_runtime::STACK as i64, heap as i64, 0, 0, 0, 0, 0];
+    for init in runtime_globals {
+        global(ValType::I32, init);
+    }
+    for name in globals {
+        match BUILTINS.iter().position(|builtin| builtin == name) {
+            Some(i) => {
+                global(ValType::I32, BUILTIN as i64);
+                global(ValType::I64, i as i64);
+            },
+            None => {
+                global(ValType::I32, UNSET as i64);
+                global(ValType::I64, 0);
+            },
+        }
+    }
+    section(&mut module, 6, runtime_globals.len() + 2 * globals.len(), &encoded_globals);
+    let first_compiled = wasm_runtime::IMPORTS as usize + functions.len() - compiled - 1;
+    let mut exports = Vec::new();
+    name(&mut exports, "memory");
+    exports.extend([0x02, 0x00]);
+    name(&mut exports, "_start");
+    exports.push(0x00);
+    uleb(&mut exports, (wasm_runtime::IMPORTS as usize + functions.len() - 1) as u64);
+    section(&mut module, 7, 2, &exports);
+    let mut elements = vec![0x00, 0x41, 0x00, 0x0b];
+    uleb(&mut elements, compiled as u64);
+    for i in 0..compiled {
+        uleb(&mut elements, (first_compiled + i) as u64);
+    }
+    section(&mut module, 9, 1, &elements);
+    let mut code = Vec::new();
+    for function in &functions {
+        let body = function.body();
+        uleb(&mut code, body.len() as u64);
+        code.extend(body);
+    }
+    section(&mut module, 10, functions.len(), &code);
+    let mut segment = vec![0x00, 0x41];
+    sleb(&mut segment, wasm_runtime::DATA as i64);
+    segment.push(0x0b);
+    uleb(&mut segment, data.bytes.len() as u64);
+    segment.extend(&data.bytes);
+    section(&mut module, 11, 1, &segment);
+    module
+}
+
+#[cfg(test)]
+mod test {
+    use std::process::Command;
+
+    use crate::interpreter::{Interpreter, SharedBuffer};
+    use crate::parser::{parse, parse_with_dialect, Dialect};
+    use crate::wasm::{compile, sleb, uleb};
+
+    /// Runs a module with the WASI of Node.js.
+    const RUNNER: &str = "\
+import { readFileSync } from \"node:fs\";
+import { WASI } from \"node:wasi\";
+const wasi = new WASI({ version: \"preview1\", returnOnExit: true });
+const module = await WebAssembly.compile(readFileSync(process.argv[2]));
+process.exitCode = wasi.start(await WebAssembly.instantiate(module, wasi.getImportObject()));
+";
+
+    /// Runs the module compiled from `source` on Node.js, returning its exit
+    /// status, stdout and stderr, or `None` without Node.js.
+    fn run_wasm(name: &str, source: &str, dialect: Dialect) -> Option<(i32, String, String)> {
+        let module = compile(&parse_with_dialect(source, dialect).unwrap()).unwrap();
+        let base = std::env::temp_dir().join(format!("tbasic-wasm-{}-{}", std::process::id(), name));
+        let (file, runner) = (base.with_extension("wasm"), base.with_extension("mjs"));
+        std::fs::write(&file, module).unwrap();
+        std::fs::write(&runner, RUNNER).unwrap();
+        let run = Command::new("node").arg("--no-warnings").arg(&runner).arg(&file).output();
+        std::fs::remove_file(&file).unwrap();
+        std::fs::remove_file(&runner).unwrap();
+        let run = run.ok()?;
+        let text = |bytes: Vec<u8>| String::from_utf8(bytes).unwrap();
+        Some((run.status.code().unwrap(), text(run.stdout), text(run.stderr)))
+    }
+
+    /// Checks that the module compiled from `source` prints what the
+    /// interpreter prints.
+    fn check(name: &str, source: &str, dialect: Dialect) {
+        let output = SharedBuffer::new();
+        let result = Interpreter::with_output(Box::new(output.clone())).run(&parse_with_dialect(source, dialect).unwrap());
+        assert!(result.is_ok(), "{:?}", result);
+        if let Some((status, stdout, stderr)) = run_wasm(name, source, dialect) {
+            assert_eq!((status, stdout.as_str(), stderr.as_str()), (0, output.contents().as_str(), ""), "{:?}", source);
+        }
+    }
+
+    #[test]
+    fn test_matches_interpreter() {
+        check(
+            "values",
+            "fn f() {}\nprint 1, -9223372036854775807 - 1, \"a\\tb\", f() == f(), f(), f == f, fn() {} == fn() {}\n\
+             print [1, \"x\\n\\\"\\\\\u{1}\r\u{7f}\", [2, []]], [] == [], [1, [2]] == [1, [2]], [1] == [\"1\"], 7 / 2, -7 / 2\n\
+             print \"ab\" + \"c\", \"b\" < \"ab\", \"a\" <= \"a\", 2 >= 3, 1 != 2, !0, !\"\", 1 && 0, 0 || \"x\", len, \"<π>\"",
+            Dialect::Modern,
+        );
+        check(
+            "functions",
+            "fn fib(n) {\n  if n < 2 { return n }\n  return fib(n - 1) + fib(n - 2)\n}\nprint fib(20)\n\
+             twice = fn(f, x) { return f(f(x)) }\nfn inc(n) { return n + 1 }\nprint twice(inc, 1), twice(fn(s) { return s + s }, \"ab\")\n\
+             fn outer() {\n  fn inner() { return 1 }\n  return inner\n}\nprint outer(), outer()()\n\
+             fn none() { return }\nprint none(), abs(-3), min(3, 1, 2), max(2, 4), len([1, 2]), len(\"héllo\")",
+            Dialect::Modern,
+        );
+        check(
+            "scopes",
+            "g = 1\nfn set() {\n  global g\n  g = 2\n  let g = 3\n  print g\n}\nset()\nprint g\n\
+             fn shadow() {\n  print g\n  g = 5\n  print g\n}\nshadow()\nprint g",
+            Dialect::Modern,
+        );
+        check(
+            "data",
+            "a = [3, 1, 2]\npush(a, 0)\nprint a, pop(a), a, len(a)\na[0] = [a[1]]\nprint a\n\
+             b = []\ni = 0\nwhile 1 {\n  i = i + 1\n  if i == 2 { continue }\n  if i > 40 { break }\n  push(b, i)\n}\n\
+             print len(b), b[38]",
+            Dialect::Modern,
+        );
+        check(
+            "classic",
+            "10 n = 0\n20 gosub 100\n30 if n < 3 { goto 20 }\n40 print \"done\", n\n45 if n > 0 { gosub 100 }\n\
+             50 goto 200\n100 n = n + 1\n110 print \"sub\", n\n120 return\n200 print \"end\"",
+            Dialect::Classic,
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        let programs = [
+            ("undefined", "x = 1\nprint y", "error: 2:7: undefined variable `y`"),
+            ("call", "f(1)", "error: 1:1: call to undefined function `f`"),
+            ("arity", "fn f(a) {}\nf()", "error: 2:1: `f` expects 1 argument, but 0 were given"),
+            ("overflow", "print 9223372036854775807 + 1", "error: 1:7: integer overflow"),
+            ("multiply", "print 3037000500 * -3037000500", "error: 1:7: integer overflow"),
+            ("zero", "print 1 / (1 - 1)", "error: 1:7: division by zero"),
+            ("mismatch", "print 1 + \"a\"", "error: 1:7: type mismatch: unsupported operands for Add: int and string"),
+            ("index", "a = [1]\nprint a[1]", "error: 2:9: index 1 is out of bounds for an array of length 1"),
+            ("callable", "x = 1\nx()", "error: 2:1: value of type int is not callable"),
+            ("builtin", "push(1, 2)", "error: 1:1: type mismatch: `push` expects an array, found int"),
+            ("recursion", "fn f() { return f() }\nf()", "error: 1:17: stack overflow in script: more than 100 nested calls"),
+        ];
+        for (name, source, message) in programs {
+            let Some((status, stdout, stderr)) = run_wasm(name, source, Dialect::Modern) else { return };
+            let expected = Interpreter::with_output(Box::new(SharedBuffer::new())).run(&parse(source).unwrap()).unwrap_err();
+            assert!(expected.to_string().starts_with(&message["error: ".len()..]), "{}", expected);
+            assert_eq!((status, stdout.as_str(), stderr.trim_end()), (1, "", message));
+        }
+        let Some((status, stdout, stderr)) = run_wasm("line", "10 print 1\n20 goto 5", Dialect::Classic) else { return };
+        assert_eq!((status, stdout.as_str(), stderr.trim_end()), (1, "1\n", "error: 2:4: there is no line 5"));
+        let Some((status, stdout, _)) = run_wasm("exit", "print 1\nexit(3)\nprint 2", Dialect::Modern) else { return };
+        assert_eq!((status, stdout.as_str()), (3, "1\n"));
+    }
+
+    #[test]
+    fn test_rejected() {
+        let error = |source: &str| compile(&parse(source).unwrap()).unwrap_err().to_string();
+        assert_eq!(error("x = 1\nprint 1.5"), "2:7: floats can't be compiled to wasm");
+        assert_eq!(error("fn f() { return {} }"), "1:17: maps can't be compiled to wasm");
+        assert_eq!(error("struct P { x }"), "1:1: structs can't be compiled to wasm");
+        assert_eq!(
+            error("fn f(a) { return fn() { return a } }"),
+            "1:1: `f` has variables used by functions defined inside it, which wasm can't hold"
+        );
+        assert_eq!(error("import m"), "1:1: modules can't be transpiled");
+        assert_eq!(error("print sqrt(4)"), "1:7: the builtin `sqrt` isn't available");
+    }
+
+    #[test]
+    fn test_leb128() {
+        let encode = |n: i64| {
+            let (mut signed, mut unsigned) = (Vec::new(), Vec::new());
+            sleb(&mut signed, n);
+            uleb(&mut unsigned, n as u64);
+            (signed, unsigned)
+        };
+        assert_eq!(encode(0), (vec![0], vec![0]));
+        assert_eq!(encode(63), (vec![63], vec![63]));
+        assert_eq!(encode(64), (vec![0xc0, 0], vec![64]));
+        assert_eq!(encode(300), (vec![0xac, 0x02], vec![0xac, 0x02]));
+        assert_eq!(encode(-1).0, [0x7f]);
+        assert_eq!(encode(-65).0, [0xbf, 0x7f]);
+        assert_eq!(encode(i64::MIN).0, [0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x7f]);
+    }
+}
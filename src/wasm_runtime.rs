@@ -0,0 +1,904 @@
+use crate::wasm::{Data, Func, ValType};
+
+use ValType::{I32, I64};
+
+// Values are a pair of an `i32` tag and an `i64` payload: the int itself,
+// 0 or 1 for bools, or a pointer for strings, arrays and functions.
+pub(crate) const UNSET: i32 = 0;
+pub(crate) const NIL: i32 = 1;
+pub(crate) const INT: i32 = 2;
+pub(crate) const BOOL: i32 = 3;
+pub(crate) const STR: i32 = 4;
+pub(crate) const ARRAY: i32 = 5;
+pub(crate) const FUNCTION: i32 = 6;
+/// The payload is the builtin's index in `BUILTINS`.
+pub(crate) const BUILTIN: i32 = 7;
+
+/// The builtins the runtime implements, each by the `Rt` function at the
+/// same offset from `Rt::Len`.
+pub(crate) const BUILTINS: &[&str] = &["len", "push", "pop", "abs", "min", "max", "exit"];
+
+// Memory layout: scratch space, then the stack of arguments being passed,
+// then the data segment and the heap. Strings are an `i32` length followed
+// by their bytes, arrays an `i32` length, capacity and pointer to 16-byte
+// items, functions a pointer to the `i32` name, arity and table index of
+// their code.
+const IOV: i32 = 0;
+const WRITTEN: i32 = 8;
+/// The end of the space `out_int` writes digits into.
+const DIGITS: i32 = 48;
+/// Where each pending `gosub` returns to.
+const RETURNS: i32 = 64;
+pub(crate) const STACK: i32 = 1024;
+pub(crate) const DATA: i32 = STACK + (1 << 20);
+
+pub(crate) const MAX_DEPTH: i32 = 100;
+
+// Imported functions
+const FD_WRITE: u32 = 0;
+const PROC_EXIT: u32 = 1;
+pub(crate) const IMPORTS: u32 = 2;
+
+/// The type of compiled functions, which take a pointer to their arguments.
+pub(crate) const FUNCTION_TYPE: u32 = 0;
+
+/// The runtime's globals, before those of the program.
+#[derive(Clone, Copy)]
+pub(crate) enum Global {
+    Sp,
+    Heap,
+    OutPtr,
+    OutLen,
+    OutCap,
+    Depth,
+    Returns,
+}
+
+pub(crate) const GLOBALS: u32 = 7;
+
+/// The runtime functions, in the order they are defined after the imports.
+#[derive(Clone, Copy)]
+pub(crate) enum Rt {
+    Alloc,
+    Out,
+    OutStr,
+    OutInt,
+    Flush,
+    OutQuoted,
+    OutValue,
+    FailBegin,
+    FailEnd,
+    TypeName,
+    Truthy,
+    Equal,
+    StrCompare,
+    Concat,
+    Overflow,
+    Binary,
+    Neg,
+    NoFields,
+    Undefined,
+    NoLine,
+    ArrayNew,
+    ArrayIndex,
+    Index,
+    SetIndex,
+    MakeFunction,
+    Reserve,
+    ExpectArgs,
+    ExpectType,
+    Call,
+    PrintValues,
+    Gosub,
+    SubReturn,
+    Len,
+    Push,
+    Pop,
+    Abs,
+    Min,
+    Max,
+    Exit,
+}
+
+impl Rt {
+    pub(crate) fn index(self) -> u32 {
+        IMPORTS + self as u32
+    }
+}
+
+/// The operators `Rt::Binary` takes, numbered as in its `op` parameter.
+pub(crate) const OPERATORS: &[&str] =
+    &["Add", "Sub", "Mul", "Div", "Equals", "NotEquals", "SmallerThan", "GreaterThan", "SmallerEquals", "GreaterEquals"];
+
+/// The types of the wasm imports, by index.
+pub(crate) fn imports() -> [(&'static str, Vec<ValType>, Vec<ValType>); IMPORTS as usize] {
+    [("fd_write", vec![I32; 4], vec![I32]), ("proc_exit", vec![I32], vec![])]
+}
+
+/// A part of an error message.
+enum Part<'a> {
+    Text(&'a str),
+    /// A string in a local.
+    Str(u32),
+    /// An `i64` local.
+    Int(u32),
+    /// An `i32` local.
+    Int32(u32),
+    /// The type name of the tag in a local.
+    TypeOf(u32),
+}
+
+struct Runtime<'a> {
+    data: &'a mut Data,
+    type_names: i32,
+    operator_names: i32,
+    builtin_names: i32,
+    hex: i32,
+}
+
+/// The runtime functions, in `Rt` order.
+pub(crate) fn functions(data: &mut Data) -> Vec<(Rt, Func)> {
+    let names = ["nil", "nil", "int", "bool", "string", "array", "function", "function"];
+    let names: Vec<i32> = names.iter().map(|name| data.string(name)).collect();
+    let type_names = data.words(&names);
+    let names: Vec<i32> = OPERATORS.iter().map(|name| data.string(name)).collect();
+    let operator_names = data.words(&names);
+    let names: Vec<i32> = BUILTINS.iter().map(|name| data.string(name)).collect();
+    let builtin_names = data.words(&names);
+    let hex = data.string("0123456789abcdef");
+    let mut rt = Runtime { data, type_names, operator_names, builtin_names, hex };
+    let functions = vec![
+        (Rt::Alloc, alloc()),
+        (Rt::Out, out()),
+        (Rt::OutStr, out_str()),
+        (Rt::OutInt, rt.out_int()),
+        (Rt::Flush, flush()),
+        (Rt::OutQuoted, rt.out_quoted()),
+        (Rt::OutValue, rt.out_value()),
+        (Rt::FailBegin, rt.fail_begin()),
+        (Rt::FailEnd, rt.fail_end()),
+        (Rt::TypeName, rt.type_name()),
+        (Rt::Truthy, truthy()),
+        (Rt::Equal, equal()),
+        (Rt::StrCompare, str_compare()),
+        (Rt::Concat, concat()),
+        (Rt::Overflow, rt.overflow()),
+        (Rt::Binary, rt.binary()),
+        (Rt::Neg, rt.neg()),
+        (Rt::NoFields, rt.no_fields()),
+        (Rt::Undefined, rt.undefined()),
+        (Rt::NoLine, rt.no_line()),
+        (Rt::ArrayNew, array_new()),
+        (Rt::ArrayIndex, rt.array_index()),
+        (Rt::Index, rt.index()),
+        (Rt::SetIndex, rt.set_index()),
+        (Rt::MakeFunction, make_function()),
+        (Rt::Reserve, reserve()),
+        (Rt::ExpectArgs, rt.expect_args()),
+        (Rt::ExpectType, rt.expect_type()),
+        (Rt::Call, rt.call()),
+        (Rt::PrintValues, rt.print_values()),
+        (Rt::Gosub, rt.gosub()),
+        (Rt::SubReturn, rt.sub_return()),
+        (Rt::Len, rt.len()),
+        (Rt::Push, rt.push()),
+        (Rt::Pop, rt.pop()),
+        (Rt::Abs, rt.abs()),
+        (Rt::Min, rt.extremum("min", true)),
+        (Rt::Max, rt.extremum("max", false)),
+        (Rt::Exit, rt.exit()),
+    ];
+    for (i, (rt, _)) in functions.iter().enumerate() {
+        debug_assert_eq!(*rt as usize, i);
+    }
+    functions
+}
+
+fn alloc() -> Func {
+    let mut f = Func::new(&[I32], &[I32]);
+    let (size, ptr, end) = (0, f.local(I32), f.local(I32));
+    f.global_get(Global::Heap as u32).i32_const(7).i32_add().i32_const(-8).i32_and().local_tee(ptr);
+    f.local_get(size).i32_add().local_tee(end);
+    f.memory_size().i32_const(16).i32_shl().i32_gt_u().if_();
+    f.local_get(end).memory_size().i32_const(16).i32_shl().i32_sub().i32_const(0xffff).i32_add();
+    f.i32_const(16).i32_shr_u().memory_grow().i32_const(-1).i32_eq().if_().unreachable().end();
+    f.end();
+    f.local_get(end).global_set(Global::Heap as u32).local_get(ptr);
+    f
+}
+
+/// Appends bytes to the output buffer.
+fn out() -> Func {
+    let mut f = Func::new(&[I32, I32], &[]);
+    let (ptr, len, need, cap, new) = (0, 1, f.local(I32), f.local(I32), f.local(I32));
+    let (out_ptr, out_len, out_cap) = (Global::OutPtr as u32, Global::OutLen as u32, Global::OutCap as u32);
+    f.global_get(out_len).local_get(len).i32_add().local_tee(need).global_get(out_cap).i32_gt_u().if_();
+    f.global_get(out_cap).i32_const(1).i32_shl().local_tee(cap).local_get(need).i32_lt_u();
+    f.if_().local_get(need).local_set(cap).end();
+    f.local_get(cap).i32_const(64).i32_lt_u().if_().i32_const(64).local_set(cap).end();
+    f.local_get(cap).call(Rt::Alloc.index()).local_tee(new);
+    f.global_get(out_ptr).global_get(out_len).memory_copy();
+    f.local_get(new).global_set(out_ptr).local_get(cap).global_set(out_cap);
+    f.end();
+    f.global_get(out_ptr).global_get(out_len).i32_add().local_get(ptr).local_get(len).memory_copy();
+    f.local_get(need).global_set(out_len);
+    f
+}
+
+fn out_str() -> Func {
+    let mut f = Func::new(&[I32], &[]);
+    f.local_get(0).i32_const(4).i32_add().local_get(0).i32_load(0).call(Rt::Out.index());
+    f
+}
+
+/// Writes the output buffer to the file descriptor `fd` and empties it.
+fn flush() -> Func {
+    let mut f = Func::new(&[I32], &[]);
+    let (fd, ptr, len, written) = (0, f.local(I32), f.local(I32), f.local(I32));
+    f.global_get(Global::OutPtr as u32).local_set(ptr).global_get(Global::OutLen as u32).local_set(len);
+    f.block().loop_();
+    f.local_get(len).i32_eqz().br_if(1);
+    f.i32_const(IOV).local_get(ptr).i32_store(0).i32_const(IOV).local_get(len).i32_store(4);
+    f.local_get(fd).i32_const(IOV).i32_const(1).i32_const(WRITTEN).call(FD_WRITE).br_if(1);
+    f.i32_const(WRITTEN).i32_load(0).local_set(written);
+    f.local_get(ptr).local_get(written).i32_add().local_set(ptr);
+    f.local_get(len).local_get(written).i32_sub().local_set(len);
+    f.br(0).end().end();
+    f.i32_const(0).global_set(Global::OutLen as u32);
+    f
+}
+
+fn truthy() -> Func {
+    let mut f = Func::new(&[I32, I64], &[I32]);
+    let (tag, payload) = (0, 1);
+    f.local_get(tag).i32_const(INT).i32_eq().local_get(tag).i32_const(BOOL).i32_eq().i32_or();
+    f.if_().local_get(payload).i64_eqz().i32_eqz().return_().end();
+    f.local_get(tag).i32_const(STR).i32_eq().local_get(tag).i32_const(ARRAY).i32_eq().i32_or();
+    f.if_().local_get(payload).i32_wrap_i64().i32_load(0).i32_eqz().i32_eqz().return_().end();
+    f.local_get(tag).i32_const(INT).i32_ge_u();
+    f
+}
+
+fn equal() -> Func {
+    let mut f = Func::new(&[I32, I64, I32, I64], &[I32]);
+    let (tag, a, other_tag, b) = (0, 1, 2, 3);
+    let (left, right, i, len) = (f.local(I32), f.local(I32), f.local(I32), f.local(I32));
+    f.local_get(tag).local_get(other_tag).i32_ne().if_().i32_const(0).return_().end();
+    f.local_get(tag).i32_const(STR).i32_eq().if_();
+    f.local_get(a).i32_wrap_i64().local_get(b).i32_wrap_i64().call(Rt::StrCompare.index()).i32_eqz().return_();
+    f.end();
+    f.local_get(tag).i32_const(ARRAY).i32_eq().if_();
+    f.local_get(a).i32_wrap_i64().local_tee(left).i32_load(0).local_tee(len);
+    f.local_get(b).i32_wrap_i64().local_tee(right).i32_load(0).i32_ne().if_().i32_const(0).return_().end();
+    f.local_get(left).i32_load(8).local_set(left).local_get(right).i32_load(8).local_set(right);
+    f.block().loop_();
+    f.local_get(i).local_get(len).i32_ge_u().br_if(1);
+    f.local_get(left).i32_load(0).local_get(left).i64_load(8);
+    f.local_get(right).i32_load(0).local_get(right).i64_load(8);
+    f.call(Rt::Equal.index()).i32_eqz().if_().i32_const(0).return_().end();
+    f.local_get(left).i32_const(16).i32_add().local_set(left);
+    f.local_get(right).i32_const(16).i32_add().local_set(right);
+    f.local_get(i).i32_const(1).i32_add().local_set(i);
+    f.br(0).end().end();
+    f.i32_const(1).return_();
+    f.end();
+    f.local_get(a).local_get(b).i64_eq();
+    f
+}
+
+/// Compares two strings byte by byte, returning -1, 0 or 1.
+fn str_compare() -> Func {
+    let mut f = Func::new(&[I32, I32], &[I32]);
+    let (a, b, i, n, x, y) = (0, 1, f.local(I32), f.local(I32), f.local(I32), f.local(I32));
+    f.local_get(a).i32_load(0).local_get(b).i32_load(0);
+    f.local_get(a).i32_load(0).local_get(b).i32_load(0).i32_lt_u().select().local_set(n);
+    f.block().loop_();
+    f.local_get(i).local_get(n).i32_ge_u().br_if(1);
+    f.local_get(a).local_get(i).i32_add().i32_load8_u(4).local_set(x);
+    f.local_get(b).local_get(i).i32_add().i32_load8_u(4).local_set(y);
+    f.local_get(x).local_get(y).i32_ne().if_();
+    f.i32_const(-1).i32_const(1).local_get(x).local_get(y).i32_lt_u().select().return_();
+    f.end();
+    f.local_get(i).i32_const(1).i32_add().local_set(i);
+    f.br(0).end().end();
+    f.local_get(a).i32_load(0).local_get(b).i32_load(0).i32_gt_u();
+    f.local_get(a).i32_load(0).local_get(b).i32_load(0).i32_lt_u().i32_sub();
+    f
+}
+
+fn concat() -> Func {
+    let mut f = Func::new(&[I32, I32], &[I32]);
+    let (a, b, r) = (0, 1, f.local(I32));
+    f.local_get(a).i32_load(0).local_get(b).i32_load(0).i32_add().i32_const(4).i32_add();
+    f.call(Rt::Alloc.index()).local_tee(r);
+    f.local_get(a).i32_load(0).local_get(b).i32_load(0).i32_add().i32_store(0);
+    f.local_get(r).i32_const(4).i32_add().local_get(a).i32_const(4).i32_add().local_get(a).i32_load(0).memory_copy();
+    f.local_get(r).i32_const(4).i32_add().local_get(a).i32_load(0).i32_add();
+    f.local_get(b).i32_const(4).i32_add().local_get(b).i32_load(0).memory_copy();
+    f.local_get(r);
+    f
+}
+
+fn array_new() -> Func {
+    let mut f = Func::new(&[I32], &[I32]);
+    let (n, a) = (0, f.local(I32));
+    f.i32_const(12).call(Rt::Alloc.index()).local_tee(a).local_get(n).i32_store(0);
+    f.local_get(a).local_get(n).i32_store(4);
+    f.local_get(a).local_get(n).i32_const(4).i32_shl().call(Rt::Alloc.index()).i32_store(8);
+    f.local_get(a);
+    f
+}
+
+/// A new value for the function described at `meta`.
+fn make_function() -> Func {
+    let mut f = Func::new(&[I32], &[I32, I64]);
+    let cell = f.local(I32);
+    f.i32_const(4).call(Rt::Alloc.index()).local_tee(cell).local_get(0).i32_store(0);
+    f.i32_const(FUNCTION).local_get(cell).i64_extend_i32_u();
+    f
+}
+
+/// Makes room for `n` values on the argument stack, returning where they
+/// go.
+fn reserve() -> Func {
+    let mut f = Func::new(&[I32], &[I32]);
+    let sp = Global::Sp as u32;
+    f.global_get(sp).global_get(sp).local_get(0).i32_const(4).i32_shl().i32_add().global_set(sp);
+    f.global_get(sp).i32_const(DATA).i32_gt_u().if_().unreachable().end();
+    f
+}
+
+impl Runtime<'_> {
+    fn text(&mut self, f: &mut Func, s: &str) {
+        let s = self.data.string(s);
+        f.i32_const(s).call(Rt::OutStr.index());
+    }
+
+    fn fail(&mut self, f: &mut Func, line: u32, column: u32, parts: &[Part]) {
+        f.local_get(line).local_get(column).call(Rt::FailBegin.index());
+        for part in parts {
+            match *part {
+                Part::Text(s) => self.text(f, s),
+                Part::Str(local) => {
+                    f.local_get(local).call(Rt::OutStr.index());
+                },
+                Part::Int(local) => {
+                    f.local_get(local).call(Rt::OutInt.index());
+                },
+                Part::Int32(local) => {
+                    f.local_get(local).i64_extend_i32_u().call(Rt::OutInt.index());
+                },
+                Part::TypeOf(local) => {
+                    f.local_get(local).call(Rt::TypeName.index()).call(Rt::OutStr.index());
+                },
+            }
+        }
+        f.call(Rt::FailEnd.index()).unreachable();
+    }
+
+    fn out_int(&mut self) -> Func {
+        let mut f = Func::new(&[I64], &[]);
+        let (n, pos) = (0, f.local(I32));
+        f.i32_const(DIGITS).local_set(pos);
+        f.local_get(n).i64_const(0).i64_lt_s().if_();
+        self.text(&mut f, "-");
+        // Wraps for the smallest int, whose digits are then printed unsigned
+        f.i64_const(0).local_get(n).i64_sub().local_set(n);
+        f.end();
+        f.loop_();
+        f.local_get(pos).i32_const(1).i32_sub().local_tee(pos);
+        f.local_get(n).i64_const(10).i64_rem_u().i32_wrap_i64().i32_const(b'0' as i32).i32_add().i32_store8(0);
+        f.local_get(n).i64_const(10).i64_div_u().local_tee(n).i64_eqz().i32_eqz().br_if(0);
+        f.end();
+        f.local_get(pos).i32_const(DIGITS).local_get(pos).i32_sub().call(Rt::Out.index());
+        f
+    }
+
+    /// A string in double quotes with escapes, as nested values print.
+    fn out_quoted(&mut self) -> Func {
+        let mut f = Func::new(&[I32], &[]);
+        let (s, i, c) = (0, f.local(I32), f.local(I32));
+        self.text(&mut f, "\"");
+        f.block().loop_();
+        f.local_get(i).local_get(s).i32_load(0).i32_ge_u().br_if(1);
+        f.local_get(s).local_get(i).i32_add().i32_load8_u(4).local_set(c);
+        f.block();
+        for (byte, escaped) in [(b'"', "\\\""), (b'\\', "\\\\"), (b'\n', "\\n"), (b'\r', "\\r"), (b'\t', "\\t"), (0, "\\0")] {
+            f.local_get(c).i32_const(byte as i32).i32_eq().if_();
+            self.text(&mut f, escaped);
+            f.br(1).end();
+        }
+        f.local_get(c).i32_const(0x20).i32_lt_u().local_get(c).i32_const(0x7f).i32_eq().i32_or().if_();
+        self.text(&mut f, "\\u{");
+        f.local_get(c).i32_const(16).i32_ge_u().if_();
+        f.i32_const(self.hex + 4).local_get(c).i32_const(4).i32_shr_u().i32_add().i32_const(1).call(Rt::Out.index());
+        f.end();
+        f.i32_const(self.hex + 4).local_get(c).i32_const(15).i32_and().i32_add().i32_const(1).call(Rt::Out.index());
+        self.text(&mut f, "}");
+        f.br(1).end();
+        f.local_get(s).i32_const(4).i32_add().local_get(i).i32_add().i32_const(1).call(Rt::Out.index());
+        f.end();
+        f.local_get(i).i32_const(1).i32_add().local_set(i);
+        f.br(0).end().end();
+        self.text(&mut f, "\"");
+        f
+    }
+
+    /// Writes a value the way `print` shows it; `nested` quotes strings.
+    fn out_value(&mut self) -> Func {
+        let mut f = Func::new(&[I32, I64, I32], &[]);
+        let (tag, payload, nested, item, end) = (0, 1, 2, f.local(I32), f.local(I32));
+        f.local_get(tag).i32_const(INT).i32_lt_u().if_();
+        self.text(&mut f, "nil");
+        f.end();
+        f.local_get(tag).i32_const(INT).i32_eq().if_().local_get(payload).call(Rt::OutInt.index()).end();
+        f.local_get(tag).i32_const(BOOL).i32_eq().if_();
+        let (yes, no) = (self.data.string("true"), self.data.string("false"));
+        f.i32_const(yes).i32_const(no).local_get(payload).i32_wrap_i64().select().call(Rt::OutStr.index());
+        f.end();
+        f.local_get(tag).i32_const(STR).i32_eq().if_();
+        f.local_get(nested).if_().local_get(payload).i32_wrap_i64().call(Rt::OutQuoted.index()).else_();
+        f.local_get(payload).i32_wrap_i64().call(Rt::OutStr.index()).end();
+        f.end();
+        f.local_get(tag).i32_const(ARRAY).i32_eq().if_();
+        self.text(&mut f, "[");
+        f.local_get(payload).i32_wrap_i64().local_tee(end).i32_load(8).local_set(item);
+        f.local_get(item).local_get(end).i32_load(0).i32_const(4).i32_shl().i32_add().local_set(end);
+        f.block().loop_();
+        f.local_get(item).local_get(end).i32_ge_u().br_if(1);
+        f.local_get(item).local_get(payload).i32_wrap_i64().i32_load(8).i32_ne().if_();
+        self.text(&mut f, ", ");
+        f.end();
+        f.local_get(item).i32_load(0).local_get(item).i64_load(8).i32_const(1).call(Rt::OutValue.index());
+        f.local_get(item).i32_const(16).i32_add().local_set(item);
+        f.br(0).end().end();
+        self.text(&mut f, "]");
+        f.end();
+        f.local_get(tag).i32_const(FUNCTION).i32_eq().if_();
+        self.text(&mut f, "<fn ");
+        f.local_get(payload).i32_wrap_i64().i32_load(0).i32_load(0).call(Rt::OutStr.index());
+        self.text(&mut f, ">");
+        f.end();
+        f.local_get(tag).i32_const(BUILTIN).i32_eq().if_();
+        self.text(&mut f, "<builtin ");
+        f.local_get(payload).i32_wrap_i64().i32_const(4).i32_mul().i32_load(self.builtin_names as u32);
+        f.call(Rt::OutStr.index());
+        self.text(&mut f, ">");
+        f.end();
+        f
+    }
+
+    /// Starts an error message at `line` and `column` in the output buffer.
+    fn fail_begin(&mut self) -> Func {
+        let mut f = Func::new(&[I32, I32], &[]);
+        f.i32_const(0).global_set(Global::OutLen as u32);
+        self.text(&mut f, "error: ");
+        f.local_get(0).i64_extend_i32_u().call(Rt::OutInt.index());
+        self.text(&mut f, ":");
+        f.local_get(1).i64_extend_i32_u().call(Rt::OutInt.index());
+        self.text(&mut f, ": ");
+        f
+    }
+
+    /// Prints the error message to stderr and exits with status 1.
+    fn fail_end(&mut self) -> Func {
+        let mut f = Func::new(&[], &[]);
+        self.text(&mut f, "\n");
+        f.i32_const(2).call(Rt::Flush.index()).i32_const(1).call(PROC_EXIT).unreachable();
+        f
+    }
+
+    fn type_name(&mut self) -> Func {
+        let mut f = Func::new(&[I32], &[I32]);
+        f.local_get(0).i32_const(4).i32_mul().i32_load(self.type_names as u32);
+        f
+    }
+
+    fn overflow(&mut self) -> Func {
+        let mut f = Func::new(&[I32, I32], &[]);
+        self.fail(&mut f, 0, 1, &[Part::Text("integer overflow")]);
+        f
+    }
+
+    fn binary(&mut self) -> Func {
+        let mut f = Func::new(&[I32, I64, I32, I64, I32, I32, I32], &[I32, I64]);
+        let (left_tag, left, right_tag, right, op, line, column) = (0, 1, 2, 3, 4, 5, 6);
+        let (result, order) = (f.local(I64), f.local(I32));
+        let overflow = |f: &mut Func| {
+            f.if_().local_get(line).local_get(column).call(Rt::Overflow.index()).end();
+        };
+        for (code, negated) in [(4, false), (5, true)] {
+            f.local_get(op).i32_const(code).i32_eq().if_().i32_const(BOOL);
+            f.local_get(left_tag).local_get(left).local_get(right_tag).local_get(right).call(Rt::Equal.index());
+            if negated {
+                f.i32_eqz();
+            }
+            f.i64_extend_i32_u().return_().end();
+        }
+        f.block().block();
+        f.local_get(left_tag).i32_const(INT).i32_eq().local_get(right_tag).i32_const(INT).i32_eq().i32_and().if_();
+        // Add and Sub overflow when the result's sign is impossible
+        f.local_get(op).i32_const(0).i32_eq().if_();
+        f.local_get(left).local_get(right).i64_add().local_set(result);
+        f.local_get(left).local_get(result).i64_xor().local_get(right).local_get(result).i64_xor().i64_and();
+        f.i64_const(0).i64_lt_s();
+        overflow(&mut f);
+        f.i32_const(INT).local_get(result).return_().end();
+        f.local_get(op).i32_const(1).i32_eq().if_();
+        f.local_get(left).local_get(right).i64_sub().local_set(result);
+        f.local_get(left).local_get(right).i64_xor().local_get(left).local_get(result).i64_xor().i64_and();
+        f.i64_const(0).i64_lt_s();
+        overflow(&mut f);
+        f.i32_const(INT).local_get(result).return_().end();
+        f.local_get(op).i32_const(2).i32_eq().if_();
+        f.local_get(left).i64_const(-1).i64_eq().local_get(right).i64_const(i64::MIN).i64_eq().i32_and();
+        overflow(&mut f);
+        f.local_get(left).local_get(right).i64_mul().local_set(result);
+        f.local_get(left).i64_const(0).i64_ne().local_get(left).i64_const(-1).i64_ne().i32_and().if_();
+        f.local_get(result).local_get(left).i64_div_s().local_get(right).i64_ne();
+        overflow(&mut f);
+        f.end();
+        f.i32_const(INT).local_get(result).return_().end();
+        f.local_get(op).i32_const(3).i32_eq().if_();
+        f.local_get(right).i64_eqz().if_();
+        self.fail(&mut f, line, column, &[Part::Text("division by zero")]);
+        f.end();
+        f.local_get(left).i64_const(i64::MIN).i64_eq().local_get(right).i64_const(-1).i64_eq().i32_and();
+        overflow(&mut f);
+        f.i32_const(INT).local_get(left).local_get(right).i64_div_s().return_().end();
+        f.local_get(left).local_get(right).i64_gt_s().local_get(left).local_get(right).i64_lt_s().i32_sub();
+        f.local_set(order).br(1);
+        f.end();
+        f.local_get(left_tag).i32_const(STR).i32_eq().local_get(right_tag).i32_const(STR).i32_eq().i32_and();
+        f.i32_eqz().br_if(1);
+        f.local_get(op).i32_eqz().if_();
+        f.i32_const(STR).local_get(left).i32_wrap_i64().local_get(right).i32_wrap_i64().call(Rt::Concat.index());
+        f.i64_extend_i32_u().return_().end();
+        f.local_get(op).i32_const(6).i32_lt_u().br_if(1);
+        f.local_get(left).i32_wrap_i64().local_get(right).i32_wrap_i64().call(Rt::StrCompare.index()).local_set(order);
+        f.end();
+        // SmallerThan to GreaterEquals, given the order of the operands
+        f.i32_const(BOOL);
+        f.local_get(order).i32_const(0).i32_lt_s();
+        f.local_get(order).i32_const(0).i32_gt_s();
+        f.local_get(op).i32_const(6).i32_eq().select();
+        f.local_get(order).i32_const(0).i32_le_s();
+        f.local_get(order).i32_const(0).i32_ge_s();
+        f.local_get(op).i32_const(8).i32_eq().select();
+        f.local_get(op).i32_const(8).i32_lt_u().select();
+        f.i64_extend_i32_u().return_();
+        f.end();
+        f.local_get(line).local_get(column).call(Rt::FailBegin.index());
+        self.text(&mut f, "type mismatch: unsupported operands for ");
+        f.local_get(op).i32_const(4).i32_mul().i32_load(self.operator_names as u32).call(Rt::OutStr.index());
+        self.text(&mut f, ": ");
+        f.local_get(left_tag).call(Rt::TypeName.index()).call(Rt::OutStr.index());
+        self.text(&mut f, " and ");
+        f.local_get(right_tag).call(Rt::TypeName.index()).call(Rt::OutStr.index());
+        f.call(Rt::FailEnd.index()).unreachable();
+        f
+    }
+
+    fn neg(&mut self) -> Func {
+        let mut f = Func::new(&[I32, I64, I32, I32], &[I32, I64]);
+        let (tag, n, line, column) = (0, 1, 2, 3);
+        f.local_get(tag).i32_const(INT).i32_eq().if_();
+        f.local_get(n).i64_const(i64::MIN).i64_eq().if_().local_get(line).local_get(column).call(Rt::Overflow.index()).end();
+        f.i32_const(INT).i64_const(0).local_get(n).i64_sub().return_();
+        f.end();
+        self.fail(&mut f, line, column, &[Part::Text("type mismatch: cannot negate "), Part::TypeOf(tag)]);
+        f
+    }
+
+    fn no_fields(&mut self) -> Func {
+        let mut f = Func::new(&[I32, I32, I32], &[]);
+        self.fail(&mut f, 1, 2, &[Part::Text("type mismatch: "), Part::TypeOf(0), Part::Text(" has no fields")]);
+        f
+    }
+
+    /// Fails on reading the unset variable `name`; `call` tells whether it
+    /// was being called.
+    fn undefined(&mut self) -> Func {
+        let mut f = Func::new(&[I32, I32, I32, I32], &[]);
+        let (name, call, line, column) = (0, 1, 2, 3);
+        f.local_get(line).local_get(column).call(Rt::FailBegin.index());
+        let (function, variable) = (self.data.string("call to undefined function `"), self.data.string("undefined variable `"));
+        f.i32_const(function).i32_const(variable).local_get(call).select().call(Rt::OutStr.index());
+        f.local_get(name).call(Rt::OutStr.index());
+        self.text(&mut f, "`");
+        f.call(Rt::FailEnd.index()).unreachable();
+        f
+    }
+
+    fn no_line(&mut self) -> Func {
+        let mut f = Func::new(&[I64, I32, I32], &[]);
+        self.fail(&mut f, 1, 2, &[Part::Text("there is no line "), Part::Int(0)]);
+        f
+    }
+
+    /// Checks an index into an array of length `len`.
+    fn array_index(&mut self) -> Func {
+        let mut f = Func::new(&[I32, I64, I32, I32, I32], &[I32]);
+        let (tag, index, len, line, column) = (0, 1, 2, 3, 4);
+        f.local_get(tag).i32_const(INT).i32_ne().if_();
+        self.fail(&mut f, line, column, &[Part::Text("type mismatch: array index must be an int, found "), Part::TypeOf(tag)]);
+        f.end();
+        f.local_get(index).local_get(len).i64_extend_i32_u().i64_ge_u().if_();
+        let parts = [
+            Part::Text("index "),
+            Part::Int(index),
+            Part::Text(" is out of bounds for an array of length "),
+            Part::Int32(len),
+        ];
+        self.fail(&mut f, line, column, &parts);
+        f.end();
+        f.local_get(index).i32_wrap_i64();
+        f
+    }
+
+    /// Leaves the address of the item `target[index]` in `item`.
+    fn item(&mut self, f: &mut Func, target: (u32, u32), index: (u32, u32), spans: [u32; 4], item: u32) {
+        let [target_line, target_column, line, column] = spans;
+        f.local_get(target.0).i32_const(ARRAY).i32_ne().if_();
+        self.fail(f, target_line, target_column, &[Part::Text("type mismatch: cannot index into "), Part::TypeOf(target.0)]);
+        f.end();
+        f.local_get(target.1).i32_wrap_i64().i32_load(8);
+        f.local_get(index.0).local_get(index.1).local_get(target.1).i32_wrap_i64().i32_load(0);
+        f.local_get(line).local_get(column).call(Rt::ArrayIndex.index());
+        f.i32_const(4).i32_shl().i32_add().local_set(item);
+    }
+
+    fn index(&mut self) -> Func {
+        let mut f = Func::new(&[I32, I64, I32, I64, I32, I32, I32, I32], &[I32, I64]);
+        let item = f.local(I32);
+        self.item(&mut f, (0, 1), (2, 3), [4, 5, 6, 7], item);
+        f.local_get(item).i32_load(0).local_get(item).i64_load(8);
+        f
+    }
+
+    fn set_index(&mut self) -> Func {
+        let mut f = Func::new(&[I32, I64, I32, I64, I32, I64, I32, I32, I32, I32], &[]);
+        let item = f.local(I32);
+        self.item(&mut f, (0, 1), (2, 3), [6, 7, 8, 9], item);
+        f.local_get(item).local_get(4).i32_store(0).local_get(item).local_get(5).i64_store(8);
+        f
+    }
+
+    fn expect_args(&mut self) -> Func {
+        let mut f = Func::new(&[I32, I32, I32, I32, I32], &[]);
+        let (name, expected, found, line, column) = (0, 1, 2, 3, 4);
+        f.local_get(expected).local_get(found).i32_eq().if_().return_().end();
+        f.local_get(line).local_get(column).call(Rt::FailBegin.index());
+        self.text(&mut f, "`");
+        f.local_get(name).call(Rt::OutStr.index());
+        self.text(&mut f, "` expects ");
+        f.local_get(expected).i64_extend_i32_u().call(Rt::OutInt.index());
+        let (one, many) = (self.data.string(" argument, but "), self.data.string(" arguments, but "));
+        f.i32_const(one).i32_const(many).local_get(expected).i32_const(1).i32_eq().select().call(Rt::OutStr.index());
+        f.local_get(found).i64_extend_i32_u().call(Rt::OutInt.index());
+        let (one, many) = (self.data.string(" was given"), self.data.string(" were given"));
+        f.i32_const(one).i32_const(many).local_get(found).i32_const(1).i32_eq().select().call(Rt::OutStr.index());
+        f.call(Rt::FailEnd.index()).unreachable();
+        f
+    }
+
+    /// Fails unless `tag` is `expected`; `what` is a string describing it.
+    fn expect_type(&mut self) -> Func {
+        let mut f = Func::new(&[I32, I32, I32, I32, I32, I32], &[]);
+        let (name, tag, expected, what, line, column) = (0, 1, 2, 3, 4, 5);
+        f.local_get(tag).local_get(expected).i32_eq().if_().return_().end();
+        let parts = [
+            Part::Text("type mismatch: `"),
+            Part::Str(name),
+            Part::Text("` expects "),
+            Part::Str(what),
+            Part::Text(", found "),
+            Part::TypeOf(tag),
+        ];
+        self.fail(&mut f, line, column, &parts);
+        f
+    }
+
+    fn call(&mut self) -> Func {
+        let mut f = Func::new(&[I32, I64, I32, I32, I32, I32, I32, I32], &[I32, I64]);
+        let (tag, callee, args, argc, line, column, callee_line, callee_column) = (0, 1, 2, 3, 4, 5, 6, 7);
+        let (meta, result_tag, result) = (f.local(I32), f.local(I32), f.local(I64));
+        let depth = Global::Depth as u32;
+        f.local_get(tag).i32_const(FUNCTION).i32_eq().if_();
+        f.local_get(callee).i32_wrap_i64().i32_load(0).local_tee(meta).i32_load(0).local_get(meta).i32_load(4);
+        f.local_get(argc).local_get(line).local_get(column).call(Rt::ExpectArgs.index());
+        f.global_get(depth).i32_const(MAX_DEPTH).i32_ge_u().if_();
+        let message = format!("stack overflow in script: more than {} nested calls", MAX_DEPTH);
+        self.fail(&mut f, line, column, &[Part::Text(&message)]);
+        f.end();
+        f.global_get(depth).i32_const(1).i32_add().global_set(depth);
+        f.local_get(args).local_get(meta).i32_load(8).call_indirect(FUNCTION_TYPE);
+        f.local_set(result).local_set(result_tag);
+        f.global_get(depth).i32_const(1).i32_sub().global_set(depth);
+        f.local_get(result_tag).local_get(result).return_();
+        f.end();
+        f.local_get(tag).i32_const(BUILTIN).i32_eq().if_();
+        for i in 0..BUILTINS.len() {
+            f.local_get(callee).i64_const(i as i64).i64_eq().if_();
+            f.local_get(args).local_get(argc).local_get(line).local_get(column).call(Rt::Len.index() + i as u32);
+            f.return_().end();
+        }
+        f.end();
+        let parts = [Part::Text("value of type "), Part::TypeOf(tag), Part::Text(" is not callable")];
+        self.fail(&mut f, callee_line, callee_column, &parts);
+        f
+    }
+
+    /// Prints the `n` values at `args` on a line.
+    fn print_values(&mut self) -> Func {
+        let mut f = Func::new(&[I32, I32], &[]);
+        let (args, n, end) = (0, 1, f.local(I32));
+        f.local_get(args).local_get(n).i32_const(4).i32_shl().i32_add().local_set(end);
+        f.block().loop_();
+        f.local_get(args).local_get(end).i32_ge_u().br_if(1);
+        f.local_get(args).i32_load(0).local_get(args).i64_load(8).i32_const(0).call(Rt::OutValue.index());
+        f.local_get(args).i32_const(16).i32_add().local_tee(args).local_get(end).i32_lt_u().if_();
+        self.text(&mut f, " ");
+        f.end();
+        f.br(0).end().end();
+        self.text(&mut f, "\n");
+        f.i32_const(1).call(Rt::Flush.index());
+        f
+    }
+
+    fn gosub(&mut self) -> Func {
+        let mut f = Func::new(&[I32, I32, I32], &[]);
+        let returns = Global::Returns as u32;
+        f.global_get(returns).i32_const(MAX_DEPTH).i32_ge_u().if_();
+        let message = format!("stack overflow in script: more than {} nested calls", MAX_DEPTH);
+        self.fail(&mut f, 1, 2, &[Part::Text(&message)]);
+        f.end();
+        f.global_get(returns).i32_const(4).i32_mul().local_get(0).i32_store(RETURNS as u32);
+        f.global_get(returns).i32_const(1).i32_add().global_set(returns);
+        f
+    }
+
+    /// Where the pending `gosub` continues.
+    fn sub_return(&mut self) -> Func {
+        let mut f = Func::new(&[I32, I32], &[I32]);
+        let returns = Global::Returns as u32;
+        f.global_get(returns).i32_eqz().if_();
+        self.fail(&mut f, 0, 1, &[Part::Text("`return` without a pending `gosub`")]);
+        f.end();
+        f.global_get(returns).i32_const(1).i32_sub().global_set(returns);
+        f.global_get(returns).i32_const(4).i32_mul().i32_load(RETURNS as u32);
+        f
+    }
+
+    /// A builtin taking the `argc` values at `args`.
+    fn builtin() -> Func {
+        Func::new(&[I32, I32, I32, I32], &[I32, I64])
+    }
+
+    fn name(&mut self, builtin: &str) -> i32 {
+        self.data.string(builtin)
+    }
+
+    /// Checks the type of argument `i`.
+    fn expect(&mut self, f: &mut Func, builtin: &str, i: u32, expected: i32, what: &str) {
+        let (name, what) = (self.name(builtin), self.data.string(what));
+        f.i32_const(name).local_get(0).i32_load(16 * i).i32_const(expected).i32_const(what);
+        f.local_get(2).local_get(3).call(Rt::ExpectType.index());
+    }
+
+    fn arity(&mut self, f: &mut Func, builtin: &str, expected: i32) {
+        let name = self.name(builtin);
+        f.i32_const(name).i32_const(expected).local_get(1).local_get(2).local_get(3).call(Rt::ExpectArgs.index());
+    }
+
+    fn len(&mut self) -> Func {
+        let mut f = Runtime::builtin();
+        let (args, s, i, n) = (0, f.local(I32), f.local(I32), f.local(I64));
+        self.arity(&mut f, "len", 1);
+        f.local_get(args).i32_load(0).i32_const(ARRAY).i32_eq().if_();
+        f.i32_const(INT).local_get(args).i64_load(8).i32_wrap_i64().i32_load(0).i64_extend_i32_u().return_();
+        f.end();
+        self.expect(&mut f, "len", 0, STR, "a string");
+        // Counts the bytes that start a character
+        f.local_get(args).i64_load(8).i32_wrap_i64().local_set(s);
+        f.block().loop_();
+        f.local_get(i).local_get(s).i32_load(0).i32_ge_u().br_if(1);
+        f.local_get(n).local_get(s).local_get(i).i32_add().i32_load8_u(4).i32_const(0xc0).i32_and();
+        f.i32_const(0x80).i32_ne().i64_extend_i32_u().i64_add().local_set(n);
+        f.local_get(i).i32_const(1).i32_add().local_set(i);
+        f.br(0).end().end();
+        f.i32_const(INT).local_get(n);
+        f
+    }
+
+    fn push(&mut self) -> Func {
+        let mut f = Runtime::builtin();
+        let (args, a, items, cap) = (0, f.local(I32), f.local(I32), f.local(I32));
+        self.arity(&mut f, "push", 2);
+        self.expect(&mut f, "push", 0, ARRAY, "an array");
+        f.local_get(args).i64_load(8).i32_wrap_i64().local_tee(a).i32_load(0).local_get(a).i32_load(4).i32_eq().if_();
+        f.local_get(a).i32_load(4).i32_const(1).i32_shl().local_tee(cap).i32_const(4).i32_lt_u();
+        f.if_().i32_const(4).local_set(cap).end();
+        f.local_get(cap).i32_const(4).i32_shl().call(Rt::Alloc.index()).local_tee(items);
+        f.local_get(a).i32_load(8).local_get(a).i32_load(0).i32_const(4).i32_shl().memory_copy();
+        f.local_get(a).local_get(items).i32_store(8).local_get(a).local_get(cap).i32_store(4);
+        f.end();
+        f.local_get(a).i32_load(8).local_get(a).i32_load(0).i32_const(4).i32_shl().i32_add().local_tee(items);
+        f.local_get(args).i32_load(16).i32_store(0).local_get(items).local_get(args).i64_load(24).i64_store(8);
+        f.local_get(a).local_get(a).i32_load(0).i32_const(1).i32_add().i32_store(0);
+        f.i32_const(NIL).i64_const(0);
+        f
+    }
+
+    fn pop(&mut self) -> Func {
+        let mut f = Runtime::builtin();
+        let (args, a, item) = (0, f.local(I32), f.local(I32));
+        self.arity(&mut f, "pop", 1);
+        self.expect(&mut f, "pop", 0, ARRAY, "an array");
+        f.local_get(args).i64_load(8).i32_wrap_i64().local_tee(a).i32_load(0).i32_eqz().if_();
+        self.fail(&mut f, 2, 3, &[Part::Text("invalid argument: cannot pop from an empty array")]);
+        f.end();
+        f.local_get(a).local_get(a).i32_load(0).i32_const(1).i32_sub().i32_store(0);
+        f.local_get(a).i32_load(8).local_get(a).i32_load(0).i32_const(4).i32_shl().i32_add().local_tee(item);
+        f.i32_load(0).local_get(item).i64_load(8);
+        f
+    }
+
+    fn abs(&mut self) -> Func {
+        let mut f = Runtime::builtin();
+        let (args, n) = (0, f.local(I64));
+        self.arity(&mut f, "abs", 1);
+        self.expect(&mut f, "abs", 0, INT, "a number");
+        f.local_get(args).i64_load(8).local_tee(n).i64_const(i64::MIN).i64_eq().if_();
+        f.local_get(2).local_get(3).call(Rt::Overflow.index());
+        f.end();
+        f.i32_const(INT).i64_const(0).local_get(n).i64_sub().local_get(n).local_get(n).i64_const(0).i64_lt_s().select();
+        f
+    }
+
+    fn extremum(&mut self, builtin: &str, smallest: bool) -> Func {
+        let mut f = Runtime::builtin();
+        let (args, argc, item, end, best) = (0, 1, f.local(I32), f.local(I32), f.local(I32));
+        let name = self.name(builtin);
+        f.local_get(argc).i32_const(2).i32_lt_u().if_();
+        f.i32_const(name).i32_const(2).local_get(argc).local_get(2).local_get(3).call(Rt::ExpectArgs.index());
+        f.end();
+        f.local_get(args).local_tee(item).local_set(best);
+        f.local_get(args).local_get(argc).i32_const(4).i32_shl().i32_add().local_set(end);
+        let what = self.data.string("a number");
+        f.block().loop_();
+        f.local_get(item).local_get(end).i32_ge_u().br_if(1);
+        f.i32_const(name).local_get(item).i32_load(0).i32_const(INT).i32_const(what);
+        f.local_get(2).local_get(3).call(Rt::ExpectType.index());
+        f.local_get(item).i64_load(8).local_get(best).i64_load(8);
+        if smallest {
+            f.i64_lt_s();
+        } else {
+            f.i64_gt_s();
+        }
+        f.if_().local_get(item).local_set(best).end();
+        f.local_get(item).i32_const(16).i32_add().local_set(item);
+        f.br(0).end().end();
+        f.local_get(best).i32_load(0).local_get(best).i64_load(8);
+        f
+    }
+
+    fn exit(&mut self) -> Func {
+        let mut f = Runtime::builtin();
+        let (args, argc, code) = (0, 1, f.local(I64));
+        f.local_get(argc).i32_const(1).i32_gt_u().if_();
+        self.arity(&mut f, "exit", 1);
+        f.end();
+        f.local_get(argc).if_();
+        self.expect(&mut f, "exit", 0, INT, "an int");
+        f.local_get(args).i64_load(8).local_set(code);
+        f.end();
+        f.local_get(code).i64_const(i32::MIN as i64).i64_lt_s().local_get(code).i64_const(i32::MAX as i64).i64_gt_s();
+        f.i32_or().if_();
+        let parts = [Part::Text("invalid argument: exit code "), Part::Int(code), Part::Text(" is out of range")];
+        self.fail(&mut f, 2, 3, &parts);
+        f.end();
+        f.local_get(code).i32_wrap_i64().call(PROC_EXIT).unreachable();
+        f
+    }
+}